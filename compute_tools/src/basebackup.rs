@@ -0,0 +1,156 @@
+//! Unpacking of the basebackup tarball, in a way which allows resuming the
+//! download from the last unpacked entry if it fails midway, rather than
+//! starting from scratch.
+//!
+//! The pageserver skips the first `--offset=N` bytes of the tarball, which is
+//! byte-identical every time it is taken at the same LSN, and with `--sha256`
+//! appends an entry with the checksum of the whole tarball, which we verify
+//! against the bytes received over all the attempts.
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+
+/// Name of the entry with the hex-encoded SHA-256 of the tarball.
+pub const CHECKSUM_FILE: &str = "basebackup.sha256";
+
+/// Progress of a basebackup download, kept across the attempts to resume it.
+#[derive(Default)]
+pub struct BasebackupProgress {
+    /// Bytes of the uncompressed tarball whose entries are unpacked, where the
+    /// next attempt resumes.
+    offset: u64,
+    /// SHA-256 of the first `offset` bytes.
+    hasher: Sha256,
+    /// Bytes read past `offset`, hashed once their entry is unpacked.
+    pending: Vec<u8>,
+    /// Whether the pageserver turned out not to support resuming, so that
+    /// every attempt downloads the whole basebackup.
+    unsupported: bool,
+}
+
+impl BasebackupProgress {
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn resumable(&self) -> bool {
+        !self.unsupported
+    }
+
+    /// Download the whole basebackup from now on, without a checksum, for a
+    /// pageserver which doesn't know the flags to resume it.
+    pub fn disable_resume(&mut self) {
+        *self = BasebackupProgress {
+            unsupported: true,
+            ..Default::default()
+        };
+    }
+
+    /// Start over at the beginning of the basebackup.
+    pub fn reset(&mut self) {
+        *self = BasebackupProgress {
+            unsupported: self.unsupported,
+            ..Default::default()
+        };
+    }
+
+    fn commit(&mut self, offset: u64) {
+        let n = (offset - self.offset) as usize;
+        self.hasher.update(&self.pending[..n]);
+        self.pending.drain(..n);
+        self.offset = offset;
+    }
+}
+
+/// Whether the pageserver rejected a basebackup request for one of its flags,
+/// which a pageserver from before resuming was supported does for `--sha256`.
+pub fn is_unknown_parameter_error(e: &postgres::Error) -> bool {
+    e.as_db_error()
+        .is_some_and(|e| e.message().starts_with("Parameter in position"))
+}
+
+struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a RefCell<BasebackupProgress>,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress
+            .borrow_mut()
+            .pending
+            .extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Unpack `tarball`, which starts at `progress.offset()` of the basebackup,
+/// into `pgdata`. If the tarball ends with a checksum, the bytes received so
+/// far are verified against it, and with `expect_checksum`, it must.
+///
+/// If this fails, `progress` tells where to resume from. If the checksum
+/// doesn't match, it is reset, so that the next attempt starts from scratch.
+pub fn unpack(
+    tarball: impl Read,
+    progress: &mut BasebackupProgress,
+    pgdata: &Path,
+    expect_checksum: bool,
+) -> Result<()> {
+    let cell = RefCell::new(std::mem::take(progress));
+    let result = unpack_entries(tarball, &cell, pgdata, expect_checksum);
+    *progress = cell.into_inner();
+    result
+}
+
+fn unpack_entries(
+    tarball: impl Read,
+    progress: &RefCell<BasebackupProgress>,
+    pgdata: &Path,
+    expect_checksum: bool,
+) -> Result<()> {
+    let start = {
+        let mut progress = progress.borrow_mut();
+        progress.pending.clear();
+        progress.offset
+    };
+
+    let mut archive = tar::Archive::new(ProgressReader {
+        inner: tarball,
+        progress,
+    });
+    // Set `ignore_zeros` so that we read all the Copy data and don't stop at
+    // an end-of-archive marker. Otherwise, if the server sends an Error after
+    // finishing the tarball, we will not notice it.
+    archive.set_ignore_zeros(true);
+
+    let mut verified = false;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        // everything before this entry belongs to the unpacked ones
+        progress
+            .borrow_mut()
+            .commit(start + entry.raw_header_position());
+
+        if entry.path()? == Path::new(CHECKSUM_FILE) {
+            let mut expected = String::new();
+            entry.read_to_string(&mut expected)?;
+            let actual = hex::encode(progress.borrow().hasher.clone().finalize());
+            if expected.trim() != actual {
+                progress.borrow_mut().reset();
+                bail!("basebackup checksum mismatch: expected {expected}, got {actual}");
+            }
+            verified = true;
+            continue;
+        }
+        entry.unpack_in(pgdata)?;
+    }
+
+    if expect_checksum && !verified {
+        bail!("basebackup doesn't end with a checksum");
+    }
+    Ok(())
+}
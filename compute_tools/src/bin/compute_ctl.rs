@@ -7,19 +7,34 @@
 //!   initialized again on each run.
 //! - If remote_extension_config is provided, it will be used to fetch extensions list
 //!  and download `shared_preload_libraries` from the remote storage.
+//!  With `--remote-ext-cache-dir`, the downloaded archives are kept there for
+//!  the next starts, see [`compute_tools::extension_cache`].
 //! - Next it will put configuration files into the `PGDATA` directory.
 //! - Sync safekeepers and get commit LSN.
 //! - Get `basebackup` from pageserver using the returned on the previous step LSN.
 //! - Try to start `postgres` and wait until it is ready to accept connections.
 //! - Check and alter/drop/create roles and databases.
-//! - Hang waiting on the `postmaster` process to exit.
+//! - If `--lfc-state-path` is provided, prewarm the local file cache in the
+//!   background with the pages saved there, and keep saving them periodically.
+//! - If `--pg-stats-remote-storage` is provided, restore the cumulative
+//!   statistics saved there before starting Postgres, and save them again
+//!   after a clean shutdown, see [`compute_tools::pg_stats`].
+//! - If `--pg-log-collector-url` is provided, ship the Postgres logs to it in
+//!   batches, with secrets redacted, see [`compute_tools::log_shipper`].
+//! - Hang waiting on the `postmaster` process to exit. If it was stopped by the
+//!   `/restart` API, start it again on the same `PGDATA` and keep waiting.
 //!
-//! Also `compute_ctl` spawns two separate service threads:
+//! Also `compute_ctl` spawns three separate service threads:
 //! - `compute-monitor` checks the last Postgres activity timestamp and saves it
 //!   into the shared `ComputeNode`;
+//! - `sql-metrics` runs the metric queries of the spec periodically, for the
+//!   `/metrics` endpoint;
 //! - `http-endpoint` runs a Hyper HTTP API server, which serves readiness and the
 //!   last activity requests.
 //!
+//! A static compute also renews the lease of its LSN on the pageservers in the
+//! `lsn-lease` thread, see [`compute_tools::lsn_lease`].
+//!
 //! If `AUTOSCALING` environment variable is set, `compute_ctl` will start the
 //! `vm-monitor` located in [`neon/libs/vm_monitor`]. For VM compute nodes,
 //! `vm-monitor` communicates with the VM autoscaling system. It coordinates
@@ -36,7 +51,7 @@
 //!
 use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::atomic::Ordering;
 use std::sync::{mpsc, Arc, Condvar, Mutex, RwLock};
@@ -44,28 +59,40 @@ use std::{thread, time::Duration};
 
 use anyhow::{Context, Result};
 use chrono::Utc;
-use clap::Arg;
+use clap::{Arg, ArgAction};
 use nix::sys::signal::{kill, Signal};
+use remote_storage::{GenericRemoteStorage, RemoteStorageConfig};
 use signal_hook::consts::{SIGQUIT, SIGTERM};
 use signal_hook::{consts::SIGINT, iterator::Signals};
-use tracing::{error, info};
+use toml_edit::Document;
+use tracing::{error, info, warn};
 use url::Url;
 
 use compute_api::responses::ComputeStatus;
 
 use compute_tools::compute::{ComputeNode, ComputeState, ParsedSpec, PG_PID, SYNC_SAFEKEEPERS_PID};
 use compute_tools::configurator::launch_configurator;
+use compute_tools::extension_cache::ExtensionCache;
 use compute_tools::extension_server::get_pg_version;
 use compute_tools::http::api::launch_http_server;
+use compute_tools::lfc_prewarm::launch_lfc_prewarm;
+use compute_tools::log_shipper::{LogShipper, Redactor};
 use compute_tools::logger::*;
+use compute_tools::lsn_lease::launch_lsn_lease_renewal;
 use compute_tools::monitor::launch_monitor;
 use compute_tools::params::*;
 use compute_tools::spec::*;
+use compute_tools::sql_metrics::launch_sql_metrics_collector;
 
 // this is an arbitrary build tag. Fine as a default / for testing purposes
 // in-case of not-set environment var
 const BUILD_TAG_DEFAULT: &str = "latest";
 
+/// How long to wait for the /terminate requests to be answered before exiting.
+const HTTP_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for the last batch of Postgres logs to be shipped.
+const LOG_SHIPPER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 fn main() -> Result<()> {
     init_tracing_and_logging(DEFAULT_LOG_LEVEL)?;
 
@@ -107,6 +134,34 @@ fn main() -> Result<()> {
     let connstr = matches
         .get_one::<String>("connstr")
         .expect("Postgres connection string is required");
+    let lfc_state_path = matches.get_one::<String>("lfc-state-path");
+    let pg_stats_storage = matches
+        .get_one::<RemoteStorageConfig>("pg-stats-remote-storage")
+        .map(GenericRemoteStorage::from_config)
+        .transpose()?;
+    let ext_cache = match matches.get_one::<String>("remote-ext-cache-dir") {
+        Some(dir) => {
+            let size_mb = *matches
+                .get_one::<u64>("remote-ext-cache-size-mb")
+                .expect("remote-ext-cache-size-mb has a default");
+            let cache = ExtensionCache::open(Path::new(dir), size_mb * 1024 * 1024)?;
+            Some(Arc::new(cache))
+        }
+        None => None,
+    };
+    let (log_shipper, log_shipper_shutdown) =
+        match matches.get_one::<String>("pg-log-collector-url") {
+            Some(url) => {
+                let rules: Vec<String> = matches
+                    .get_many::<String>("pg-log-redact")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                let (shipper, shutdown) = LogShipper::launch(url.clone(), Redactor::new(&rules)?);
+                (Some(shipper), Some(shutdown))
+            }
+            None => (None, None),
+        };
     let spec_json = matches.get_one::<String>("spec");
     let spec_path = matches.get_one::<String>("spec-path");
 
@@ -219,7 +274,11 @@ fn main() -> Result<()> {
         state_changed: Condvar::new(),
         ext_remote_storage: ext_remote_storage.map(|s| s.to_string()),
         ext_download_progress: RwLock::new(HashMap::new()),
+        ext_cache,
         build_tag,
+        lfc_state_path: lfc_state_path.map(PathBuf::from),
+        pg_stats_storage,
+        log_shipper,
     };
     let compute = Arc::new(compute_node);
 
@@ -233,7 +292,7 @@ fn main() -> Result<()> {
 
     // Launch http service first, so we were able to serve control-plane
     // requests, while configuration is still in progress.
-    let _http_handle =
+    let (_http_handle, http_shutdown) =
         launch_http_server(http_port, &compute).expect("cannot launch http endpoint thread");
 
     let extension_server_port: u16 = http_port;
@@ -279,12 +338,19 @@ fn main() -> Result<()> {
     // Launch remaining service threads
     let _monitor_handle = launch_monitor(&compute);
     let _configurator_handle = launch_configurator(&compute);
+    let _sql_metrics_handle = launch_sql_metrics_collector(&compute);
 
     // Start Postgres
     let mut delay_exit = false;
     let mut exit_code = None;
+    let mut _lfc_prewarm_handle = None;
+    let mut _lsn_lease_handle = None;
     let pg = match compute.start_compute(extension_server_port) {
-        Ok(pg) => Some(pg),
+        Ok(pg) => {
+            _lfc_prewarm_handle = launch_lfc_prewarm(&compute);
+            _lsn_lease_handle = launch_lsn_lease_renewal(&compute);
+            Some(pg)
+        }
         Err(err) => {
             error!("could not start the compute node: {:#}", err);
             let mut state = compute.state.lock().unwrap();
@@ -347,12 +413,15 @@ fn main() -> Result<()> {
     }
 
     // Wait for the child Postgres process forever. In this state Ctrl+C will
-    // propagate to Postgres and it will be shut down as well.
-    if let Some((mut pg, logs_handle)) = pg {
+    // propagate to Postgres and it will be shut down as well. If it was
+    // stopped by a /restart request, start it again and keep waiting.
+    let mut pg = pg;
+    if pg.is_some() {
         // Startup is finished, exit the startup tracing span
         drop(startup_context_guard);
-
-        let ecode = pg
+    }
+    while let Some((mut pg_process, logs_handle)) = pg.take() {
+        let ecode = pg_process
             .wait()
             .expect("failed to start waiting on Postgres process");
         PG_PID.store(0, Ordering::SeqCst);
@@ -362,8 +431,33 @@ fn main() -> Result<()> {
             .join()
             .map_err(|e| tracing::error!("log thread panicked: {:?}", e));
 
-        info!("Postgres exited with code {}, shutting down", ecode);
-        exit_code = ecode.code()
+        exit_code = ecode.code();
+        if compute.get_status() != ComputeStatus::RestartPending {
+            info!("Postgres exited with code {}, shutting down", ecode);
+            break;
+        }
+
+        // The statistics stay in PGDATA, no need to save them
+        info!("Postgres exited with code {}, restarting", ecode);
+        match compute.restart_postgres(extension_server_port) {
+            Ok(restarted) => pg = Some(restarted),
+            Err(err) => {
+                error!("could not restart the compute node: {:#}", err);
+                let mut state = compute.state.lock().unwrap();
+                state.error = Some(format!("{:?}", err));
+                state.status = ComputeStatus::Failed;
+                compute.state_changed.notify_all();
+                drop(state);
+                delay_exit = true;
+            }
+        }
+    }
+
+    // Postgres is gone for good, ship the logs it wrote since the last batch
+    if let Some(shutdown) = log_shipper_shutdown {
+        if !shutdown.shutdown(LOG_SHIPPER_SHUTDOWN_TIMEOUT) {
+            warn!("timed out shipping the last batch of Postgres logs");
+        }
     }
 
     // Terminate the vm_monitor so it releases the file watcher on
@@ -387,12 +481,27 @@ fn main() -> Result<()> {
     // Maybe sync safekeepers again, to speed up next startup
     let compute_state = compute.state.lock().unwrap().clone();
     let pspec = compute_state.pspec.as_ref().expect("spec must be set");
+
+    // Postgres only writes out the statistics on a clean shutdown
+    if exit_code == Some(0) {
+        compute.save_pg_stats();
+    }
+    let mut sync_result = Ok(None);
     if matches!(pspec.spec.mode, compute_api::spec::ComputeMode::Primary) {
         info!("syncing safekeepers on shutdown");
         let storage_auth_token = pspec.storage_auth_token.clone();
-        let lsn = compute.sync_safekeepers(storage_auth_token)?;
-        info!("synced safekeepers at lsn {lsn}");
+        sync_result = compute.sync_safekeepers(storage_auth_token).map(Some);
+        if let Ok(Some(lsn)) = &sync_result {
+            info!("synced safekeepers at lsn {lsn}");
+        }
+    }
+
+    // Report the final LSN, or the error, to the /terminate request if Postgres
+    // was stopped by one, and let the HTTP server answer it before we exit
+    if compute.finish_termination(&sync_result) && !http_shutdown.shutdown(HTTP_SHUTDOWN_TIMEOUT) {
+        warn!("timed out waiting for the HTTP requests in flight to be answered");
     }
+    sync_result?;
 
     if let Err(err) = compute.check_for_core_dumps() {
         error!("error while checking for core dumps: {err:?}");
@@ -493,6 +602,43 @@ fn cli() -> clap::Command {
                 .long("remote-ext-config")
                 .value_name("REMOTE_EXT_CONFIG"),
         )
+        .arg(
+            Arg::new("remote-ext-cache-dir")
+                .long("remote-ext-cache-dir")
+                .value_name("REMOTE_EXT_CACHE_DIR")
+                .help("Keep the downloaded extension archives in this directory across restarts"),
+        )
+        .arg(
+            Arg::new("remote-ext-cache-size-mb")
+                .long("remote-ext-cache-size-mb")
+                .value_name("MB")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1024"),
+        )
+        .arg(
+            Arg::new("lfc-state-path")
+                .long("lfc-state-path")
+                .value_name("LFC_STATE_PATH"),
+        )
+        .arg(
+            Arg::new("pg-stats-remote-storage")
+                .long("pg-stats-remote-storage")
+                .value_name("PG_STATS_REMOTE_STORAGE")
+                .value_parser(parse_remote_storage)
+                .help("Remote storage to save the statistics to, as an inline TOML table"),
+        )
+        .arg(
+            Arg::new("pg-log-collector-url")
+                .long("pg-log-collector-url")
+                .value_name("PG_LOG_COLLECTOR_URL"),
+        )
+        .arg(
+            Arg::new("pg-log-redact")
+                .long("pg-log-redact")
+                .value_name("REGEX")
+                .action(ArgAction::Append)
+                .help("Redact the matches of REGEX in the shipped Postgres logs, can be repeated"),
+        )
         // TODO(fprasx): we currently have default arguments because the cloud PR
         // to pass them in hasn't been merged yet. We should get rid of them once
         // the PR is merged.
@@ -518,6 +664,17 @@ fn cli() -> clap::Command {
         )
 }
 
+fn parse_remote_storage(storage_conf: &str) -> Result<RemoteStorageConfig> {
+    // toml doesn't consider a plain inline table a valid document, so wrap it in a key to parse
+    let storage_conf_toml = format!("remote_storage = {storage_conf}");
+    let parsed_toml = storage_conf_toml.parse::<Document>()?;
+    let (_, storage_conf_parsed_toml) = parsed_toml.iter().next().unwrap();
+    RemoteStorageConfig::from_toml(storage_conf_parsed_toml).and_then(|parsed_config| {
+        // Don't print the original toml here, there might be some sensitive data
+        parsed_config.context("Incorrectly parsed remote storage toml as no remote storage config")
+    })
+}
+
 /// When compute_ctl is killed, send also termination signal to sync-safekeepers
 /// to prevent leakage. TODO: it is better to convert compute_ctl to async and
 /// wait for termination which would be easy then.
@@ -0,0 +1,117 @@
+//! Introspection of the Postgres catalog for the `/catalog/*` APIs, so that the
+//! control plane and the CLI can show it without SQL connections of their own.
+use std::str::FromStr;
+
+use anyhow::Result;
+use postgres::config::Config;
+use postgres::{Client, NoTls};
+
+use compute_api::responses::{CatalogDatabase, CatalogExtension, CatalogRole, CatalogSchema};
+
+fn connect(connstr: &str, database: Option<&str>) -> Result<Client> {
+    let mut conf = Config::from_str(connstr)?;
+    if let Some(database) = database {
+        conf.dbname(database);
+    }
+    Ok(conf.connect(NoTls)?)
+}
+
+/// Databases users can connect to, i.e. not the templates.
+pub fn get_databases(connstr: &str) -> Result<Vec<CatalogDatabase>> {
+    let mut client = connect(connstr, None)?;
+    let rows = client.query(
+        "SELECT datname::text, pg_get_userbyid(datdba)::text, pg_database_size(oid)
+         FROM pg_database
+         WHERE NOT datistemplate AND datallowconn
+         ORDER BY 1",
+        &[],
+    )?;
+    Ok(rows
+        .iter()
+        .map(|row| CatalogDatabase {
+            name: row.get(0),
+            owner: row.get(1),
+            size_bytes: row.get::<_, i64>(2) as u64,
+        })
+        .collect())
+}
+
+pub fn database_exists(connstr: &str, database: &str) -> Result<bool> {
+    let mut client = connect(connstr, None)?;
+    let row = client.query_one(
+        "SELECT count(*) FROM pg_database WHERE datname = $1 AND datallowconn",
+        &[&database],
+    )?;
+    Ok(row.get::<_, i64>(0) > 0)
+}
+
+/// All roles but the predefined `pg_*` ones.
+pub fn get_roles(connstr: &str) -> Result<Vec<CatalogRole>> {
+    let mut client = connect(connstr, None)?;
+    let rows = client.query(
+        "SELECT r.rolname::text, r.rolcanlogin, r.rolcreatedb, r.rolcreaterole,
+                r.rolreplication, r.rolbypassrls,
+                array(SELECT g.rolname::text
+                      FROM pg_auth_members m JOIN pg_roles g ON g.oid = m.roleid
+                      WHERE m.member = r.oid
+                      ORDER BY 1)
+         FROM pg_roles r
+         WHERE r.rolname !~ '^pg_'
+         ORDER BY 1",
+        &[],
+    )?;
+    Ok(rows
+        .iter()
+        .map(|row| CatalogRole {
+            name: row.get(0),
+            can_login: row.get(1),
+            create_db: row.get(2),
+            create_role: row.get(3),
+            replication: row.get(4),
+            bypass_rls: row.get(5),
+            member_of: row.get(6),
+        })
+        .collect())
+}
+
+/// Schemas of `database`, without the system ones.
+pub fn get_schemas(connstr: &str, database: &str) -> Result<Vec<CatalogSchema>> {
+    let mut client = connect(connstr, Some(database))?;
+    let rows = client.query(
+        "SELECT n.nspname::text, pg_get_userbyid(n.nspowner)::text,
+                coalesce(sum(pg_total_relation_size(c.oid)), 0)::bigint
+         FROM pg_namespace n
+         LEFT JOIN pg_class c ON c.relnamespace = n.oid AND c.relkind IN ('r', 'm')
+         WHERE n.nspname !~ '^pg_' AND n.nspname <> 'information_schema'
+         GROUP BY n.nspname, n.nspowner
+         ORDER BY 1",
+        &[],
+    )?;
+    Ok(rows
+        .iter()
+        .map(|row| CatalogSchema {
+            name: row.get(0),
+            owner: row.get(1),
+            size_bytes: row.get::<_, i64>(2) as u64,
+        })
+        .collect())
+}
+
+/// Extensions installed in `database`.
+pub fn get_extensions(connstr: &str, database: &str) -> Result<Vec<CatalogExtension>> {
+    let mut client = connect(connstr, Some(database))?;
+    let rows = client.query(
+        "SELECT e.extname::text, e.extversion, n.nspname::text
+         FROM pg_extension e JOIN pg_namespace n ON n.oid = e.extnamespace
+         ORDER BY 1",
+        &[],
+    )?;
+    Ok(rows
+        .iter()
+        .map(|row| CatalogExtension {
+            name: row.get(0),
+            version: row.get(1),
+            schema: row.get(2),
+        })
+        .collect())
+}
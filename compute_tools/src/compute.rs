@@ -3,43 +3,61 @@ use std::env;
 use std::fs;
 use std::io::BufRead;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
-use std::sync::{Condvar, Mutex, RwLock};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use futures::future::join_all;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
 use postgres::{Client, NoTls};
 use tokio;
 use tokio_postgres;
-use tracing::{debug, error, info, instrument, warn};
+use tracing::{debug, error, field, info, instrument, warn, Span};
 use utils::id::{TenantId, TimelineId};
 use utils::lsn::Lsn;
 
-use compute_api::responses::{ComputeMetrics, ComputeStatus};
+use compute_api::responses::{
+    ComputeMetrics, ComputeStatus, LfcPrewarmProgress, ReconfigurationPath,
+};
 use compute_api::spec::{ComputeFeature, ComputeMode, ComputeSpec};
 use utils::measured_stream::MeasuredReader;
 
-use remote_storage::{DownloadError, RemotePath};
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
 
+use crate::basebackup::{self, BasebackupProgress};
 use crate::checker::create_availability_check_data;
+use crate::extension_cache::ExtensionCache;
+use crate::extension_server::DownloadClaim;
+use crate::log_shipper::LogShipper;
 use crate::logger::inlinify;
+use crate::metrics::SPEC_GENERATION;
+use crate::migration::handle_migrations;
 use crate::pg_helpers::*;
 use crate::spec::*;
+use crate::spec_diff::SpecChanges;
 use crate::sync_sk::{check_if_synced, ping_safekeeper};
-use crate::{config, extension_server};
+use crate::{config, extension_server, pg_stats};
 
 pub static SYNC_SAFEKEEPERS_PID: AtomicU32 = AtomicU32::new(0);
 pub static PG_PID: AtomicU32 = AtomicU32::new(0);
 
+/// How long to wait for Postgres to load a new pageserver connection string.
+const PAGESERVER_CONNSTRING_RELOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a replica being promoted may take to replay the WAL up to the
+/// requested LSN.
+const PROMOTE_REPLAY_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// Compute node info shared across several `compute_ctl` threads.
 pub struct ComputeNode {
     // Url type maintains proper escaping
@@ -70,7 +88,17 @@ pub struct ComputeNode {
     pub ext_remote_storage: Option<String>,
     // key: ext_archive_name, value: started download time, download_completed?
     pub ext_download_progress: RwLock<HashMap<String, (DateTime<Utc>, bool)>>,
+    /// Local cache of the downloaded extension archives, kept across restarts.
+    pub ext_cache: Option<Arc<ExtensionCache>>,
     pub build_tag: String,
+    /// Where to save the content of the local file cache, to prewarm it from
+    /// on the next start.
+    pub lfc_state_path: Option<PathBuf>,
+    /// Where to save the cumulative statistics of Postgres at shutdown, to
+    /// restore them from on the next start.
+    pub pg_stats_storage: Option<GenericRemoteStorage>,
+    /// Where Postgres logs are shipped to, in addition to stderr.
+    pub log_shipper: Option<LogShipper>,
 }
 
 // store some metrics about download size that might impact startup time
@@ -90,6 +118,15 @@ pub struct ComputeState {
     pub last_active: Option<DateTime<Utc>>,
     pub error: Option<String>,
     pub pspec: Option<ParsedSpec>,
+    /// The spec Postgres was last configured with, to compare new specs against.
+    pub running_spec: Option<ComputeSpec>,
+    /// How the last live reconfiguration was applied.
+    pub reconfiguration: Option<ReconfigurationPath>,
+    /// Progress of prewarming the local file cache, if there was a saved state.
+    pub lfc_prewarm: Option<LfcPrewarmProgress>,
+    /// The LSN the safekeepers were synced to after a termination requested
+    /// with the /terminate API.
+    pub terminate_flush_lsn: Option<Lsn>,
     pub metrics: ComputeMetrics,
 }
 
@@ -101,6 +138,10 @@ impl ComputeState {
             last_active: None,
             error: None,
             pspec: None,
+            running_spec: None,
+            reconfiguration: None,
+            lfc_prewarm: None,
+            terminate_flush_lsn: None,
             metrics: ComputeMetrics::default(),
         }
     }
@@ -205,6 +246,19 @@ fn maybe_cgexec(cmd: &str) -> Command {
     }
 }
 
+/// Where the statistics of the endpoint are saved in the remote storage, see
+/// [`pg_stats`]. They are kept per endpoint, so the spec must name it.
+fn pg_stats_path(pspec: &ParsedSpec) -> Option<RemotePath> {
+    let endpoint_id = pspec.spec.endpoint_id.as_ref()?;
+    match pg_stats::remote_pg_stats_path(pspec.tenant_id, endpoint_id) {
+        Ok(path) => Some(path),
+        Err(e) => {
+            warn!("cannot save statistics of endpoint {endpoint_id}: {e:#}");
+            None
+        }
+    }
+}
+
 /// Create special neon_superuser role, that's a slightly nerfed version of a real superuser
 /// that we give to customers
 #[instrument(skip_all)]
@@ -306,6 +360,281 @@ impl ComputeNode {
         self.state.lock().unwrap().status
     }
 
+    /// Checkpoint and start a fast shutdown of Postgres, for the /terminate API.
+    /// The checkpoint makes the shutdown checkpoint quick. Once Postgres has
+    /// exited, the main thread syncs the safekeepers and sets the `Terminated`
+    /// status.
+    pub fn terminate(&self) -> Result<()> {
+        let checkpoint = Client::connect(self.connstr.as_str(), NoTls)
+            .and_then(|mut client| client.simple_query("CHECKPOINT"));
+        if let Err(e) = checkpoint {
+            warn!("checkpoint before termination failed: {e}");
+        }
+        self.fast_shutdown()
+    }
+
+    /// Ask Postgres to shut down, aborting the open transactions.
+    pub fn fast_shutdown(&self) -> Result<()> {
+        let pid = PG_PID.load(Ordering::SeqCst);
+        if pid == 0 {
+            anyhow::bail!("Postgres is not running");
+        }
+        info!("requesting fast shutdown of Postgres");
+        kill(Pid::from_raw(pid as i32), Signal::SIGINT)?;
+        Ok(())
+    }
+
+    /// Report the outcome of syncing the safekeepers once Postgres has exited
+    /// to the /terminate requests waiting in [`Self::wait_terminated`]. Returns
+    /// whether the compute was being terminated.
+    pub fn finish_termination(&self, result: &Result<Option<Lsn>>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.status != ComputeStatus::TerminationPending {
+            return false;
+        }
+        match result {
+            Ok(lsn) => {
+                state.terminate_flush_lsn = *lsn;
+                state.status = ComputeStatus::Terminated;
+            }
+            Err(e) => {
+                state.error = Some(format!("{e:?}"));
+                state.status = ComputeStatus::Failed;
+            }
+        }
+        self.state_changed.notify_all();
+        true
+    }
+
+    /// Wait for the termination in progress to finish, and return the LSN the
+    /// safekeepers were synced to.
+    pub fn wait_terminated(&self) -> Result<Option<Lsn>> {
+        let mut state = self.state.lock().unwrap();
+        while state.status == ComputeStatus::TerminationPending {
+            state = self.state_changed.wait(state).unwrap();
+        }
+        match state.status {
+            ComputeStatus::Terminated => Ok(state.terminate_flush_lsn),
+            status => anyhow::bail!(
+                "termination failed with compute status {status:?}: {}",
+                state.error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+
+    /// Check that Postgres accepts the configuration generated from `spec`,
+    /// before stopping it for a restart, so that a bad setting fails the
+    /// restart instead of leaving the compute down. This also brings the
+    /// Postgres binary into the page cache.
+    ///
+    /// `postgres -C` checks the settings of Postgres itself, but doesn't load
+    /// shared_preload_libraries. Settings of the libraries which are loaded
+    /// already are checked by the running Postgres, as it has read the
+    /// configuration file on the last reload. Libraries must be installed or
+    /// available from the extension storage, but settings of the libraries
+    /// which are not loaded yet are only checked on start.
+    pub fn check_postgres_conf(&self, spec: &ComputeSpec) -> Result<()> {
+        let conf_path = Path::new(&self.pgdata).join("postgresql.conf.check");
+        // the extension server port doesn't matter for the check
+        config::write_postgres_conf(&conf_path, spec, None)?;
+
+        let output = Command::new(&self.pgbin)
+            .args(["-D", &self.pgdata])
+            .arg("-c")
+            .arg(format!("config_file={}", conf_path.display()))
+            .args(["-C", "shared_buffers"])
+            .output()
+            .context("failed to run postgres to check the configuration")?;
+        let _ = fs::remove_file(&conf_path);
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Postgres rejected the configuration: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        // Libraries available remotely are downloaded on start.
+        let remote_index = spec
+            .remote_extensions
+            .as_ref()
+            .filter(|_| self.ext_remote_storage.is_some())
+            .map(|remote_extensions| &remote_extensions.library_index);
+        let missing: Vec<String> = extension_server::missing_libraries(
+            &config::shared_preload_libraries(spec),
+            &self.pgbin,
+        )
+        .into_iter()
+        .filter(|lib| !remote_index.is_some_and(|index| index.contains_key(lib)))
+        .collect();
+        if !missing.is_empty() {
+            anyhow::bail!("shared preload libraries are not installed: {missing:?}");
+        }
+
+        // Values which can't be applied without a restart are reported as
+        // errors as well, but they are flagged as pending restart in
+        // pg_settings, unlike the invalid ones.
+        let mut client = Client::connect(self.connstr.as_str(), NoTls)?;
+        let invalid: Vec<String> = client
+            .query(
+                "SELECT f.name || ' = ' || f.setting AS setting
+                 FROM pg_file_settings f LEFT JOIN pg_settings s USING (name)
+                 WHERE f.error IS NOT NULL AND NOT coalesce(s.pending_restart, false)",
+                &[],
+            )?
+            .iter()
+            .map(|row| row.get("setting"))
+            .collect();
+        if !invalid.is_empty() {
+            anyhow::bail!("Postgres rejected the settings: {invalid:?}");
+        }
+        Ok(())
+    }
+
+    /// Reject new connections, wait up to `timeout` for the client connections
+    /// to close, then terminate the remaining ones, and checkpoint so that the
+    /// shutdown is quick. Returns the number of terminated connections. New
+    /// connections are accepted again if draining fails, or otherwise once
+    /// Postgres is restarted.
+    pub fn drain_connections(&self, timeout: Duration) -> Result<u64> {
+        let mut client = Client::connect(self.connstr.as_str(), NoTls)?;
+        let pgdata_path = Path::new(&self.pgdata);
+        reject_new_connections(pgdata_path)?;
+        self.pg_reload_conf()?;
+
+        let res = Self::wait_drained(&mut client, timeout);
+        if res.is_err() {
+            if let Err(e) = self.accept_connections() {
+                error!("failed to accept new connections again: {e:#}");
+            }
+        }
+        res
+    }
+
+    /// Accept new connections again after [`Self::drain_connections`], if
+    /// Postgres is not restarted.
+    pub fn accept_connections(&self) -> Result<()> {
+        accept_new_connections(Path::new(&self.pgdata))?;
+        self.pg_reload_conf()
+    }
+
+    fn wait_drained(client: &mut Client, timeout: Duration) -> Result<u64> {
+        let count_query = "SELECT count(*) FROM pg_stat_activity
+             WHERE backend_type = 'client backend' AND pid <> pg_backend_pid()";
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let open: i64 = client.query_one(count_query, &[])?.get(0);
+            if open == 0 {
+                info!("all client connections are closed");
+                client.simple_query("CHECKPOINT")?;
+                return Ok(0);
+            }
+            if Instant::now() >= deadline {
+                info!("{open} client connections are still open, terminating them");
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        let terminated: i64 = client
+            .query_one(
+                "SELECT count(*) FROM pg_stat_activity
+                 WHERE backend_type = 'client backend' AND pid <> pg_backend_pid()
+                   AND pg_terminate_backend(pid)",
+                &[],
+            )?
+            .get(0);
+        client.simple_query("CHECKPOINT")?;
+        Ok(terminated as u64)
+    }
+
+    /// Save the statistics Postgres wrote out at shutdown, to restore them on
+    /// the next start, see [`pg_stats`].
+    pub fn save_pg_stats(&self) {
+        let Some(storage) = &self.pg_stats_storage else {
+            return;
+        };
+        let (path, timeline_id) = {
+            let state = self.state.lock().unwrap();
+            let pspec = state.pspec.as_ref().expect("spec must be set");
+            (pg_stats_path(pspec), pspec.timeline_id)
+        };
+        let Some(path) = path else {
+            return;
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to create rt");
+        let pgdata = Path::new(&self.pgdata);
+        match rt.block_on(pg_stats::save_pg_stats(pgdata, storage, &path, timeline_id)) {
+            Ok(n) => info!("saved {n} statistics files to {path}"),
+            Err(e) => error!("failed to save statistics to {path}: {e:#}"),
+        }
+    }
+
+    /// Promote a hot standby replica to a primary, for the /promote API. If
+    /// `wait_lsn` is set, e.g. to the LSN the old primary was terminated at,
+    /// the WAL is replayed up to it first, so that no committed transaction
+    /// is lost. Once promoted, Postgres starts the walproposer and streams
+    /// WAL to the safekeepers. Returns the LSN the new primary starts at.
+    ///
+    /// Promotion switches Postgres to a new timeline, branching off at the
+    /// end of the replayed WAL: the walproposer only accepts it if that is
+    /// where the WAL on the safekeepers ends, i.e. if the old primary is
+    /// stopped and the replica has replayed all its WAL.
+    pub fn promote(&self, wait_lsn: Option<Lsn>) -> Result<Lsn> {
+        let mut client = Client::connect(self.connstr.as_str(), NoTls)?;
+
+        if let Some(wait_lsn) = wait_lsn {
+            let start = Instant::now();
+            loop {
+                let replayed: String = client
+                    .query_one(
+                        "SELECT coalesce(pg_last_wal_replay_lsn(), '0/0')::text",
+                        &[],
+                    )?
+                    .get(0);
+                let replayed = Lsn::from_str(&replayed)?;
+                if replayed >= wait_lsn {
+                    info!("replayed WAL up to {replayed} in {:?}", start.elapsed());
+                    break;
+                }
+                if start.elapsed() > PROMOTE_REPLAY_TIMEOUT {
+                    anyhow::bail!(
+                        "replica didn't replay WAL up to {wait_lsn} in {:?}, replayed up to {replayed}",
+                        PROMOTE_REPLAY_TIMEOUT
+                    );
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+
+        info!("promoting replica");
+        let promoted: bool = client
+            .query_one("SELECT pg_promote(wait => true)", &[])?
+            .get(0);
+        if !promoted {
+            anyhow::bail!("Postgres didn't finish the promotion in time");
+        }
+        let lsn: String = client
+            .query_one("SELECT pg_current_wal_flush_lsn()::text", &[])?
+            .get(0);
+        let lsn = Lsn::from_str(&lsn)?;
+
+        // further specs are compared against a primary
+        let mut state = self.state.lock().unwrap();
+        if let Some(pspec) = state.pspec.as_mut() {
+            pspec.spec.mode = ComputeMode::Primary;
+        }
+        if let Some(running_spec) = state.running_spec.as_mut() {
+            running_spec.mode = ComputeMode::Primary;
+        }
+        info!("promoted replica to primary at {lsn}");
+        Ok(lsn)
+    }
+
     // Remove `pgdata` directory and create it again with right permissions.
     fn create_pgdata(&self) -> Result<()> {
         // Ignore removal error, likely it is a 'No such file or directory (os error 2)'.
@@ -319,8 +648,14 @@ impl ComputeNode {
 
     // Get basebackup from the libpq connection to pageserver using `connstr` and
     // unarchive it to `pgdata` directory overriding all its previous content.
+    // Resumes from `progress` of the previous attempts, unless at Lsn(0).
     #[instrument(skip_all, fields(%lsn))]
-    fn try_get_basebackup(&self, compute_state: &ComputeState, lsn: Lsn) -> Result<()> {
+    fn try_get_basebackup(
+        &self,
+        compute_state: &ComputeState,
+        lsn: Lsn,
+        progress: &mut BasebackupProgress,
+    ) -> Result<()> {
         let spec = compute_state.pspec.as_ref().expect("spec must be set");
         let start_time = Instant::now();
 
@@ -339,16 +674,37 @@ impl ComputeNode {
         let mut client = config.connect(NoTls)?;
         let pageserver_connect_micros = start_time.elapsed().as_micros() as u64;
 
-        let basebackup_cmd = match lsn {
-            // HACK We don't use compression on first start (Lsn(0)) because there's no API for it
+        let plain_cmd = match lsn {
+            // HACK We don't use compression on first start (Lsn(0)) because there's no API for it.
+            // Neither can we resume, as the basebackup is taken at the LSN the timeline is at.
             Lsn(0) => format!("basebackup {} {}", spec.tenant_id, spec.timeline_id),
             _ => format!(
                 "basebackup {} {} {} --gzip",
                 spec.tenant_id, spec.timeline_id, lsn
             ),
         };
+        if lsn == Lsn(0) || !progress.resumable() {
+            progress.reset();
+        }
 
-        let copyreader = client.copy_out(basebackup_cmd.as_str())?;
+        let copyreader = if lsn != Lsn(0) && progress.resumable() {
+            if progress.offset() > 0 {
+                info!("resuming basebackup at offset {}", progress.offset());
+            }
+            let resume_cmd = format!("{plain_cmd} --sha256 --offset={}", progress.offset());
+            match client.copy_out(resume_cmd.as_str()) {
+                // An older pageserver rejects the flags it doesn't know: download
+                // the whole basebackup from it, as before they existed.
+                Err(e) if basebackup::is_unknown_parameter_error(&e) => {
+                    warn!("pageserver doesn't support resuming basebackups: {e}");
+                    progress.disable_resume();
+                    client.copy_out(plain_cmd.as_str())?
+                }
+                result => result?,
+            }
+        } else {
+            client.copy_out(plain_cmd.as_str())?
+        };
         let mut measured_reader = MeasuredReader::new(copyreader);
 
         // Check the magic number to see if it's a gzip or not. Even though
@@ -369,18 +725,13 @@ impl ComputeNode {
         };
 
         // Read the archive directly from the `CopyOutReader`
-        //
-        // Set `ignore_zeros` so that unpack() reads all the Copy data and
-        // doesn't stop at the end-of-archive marker. Otherwise, if the server
-        // sends an Error after finishing the tarball, we will not notice it.
+        let pgdata = Path::new(&self.pgdata);
+        let expect_checksum = lsn != Lsn(0) && progress.resumable();
         if gzip {
-            let mut ar = tar::Archive::new(flate2::read::GzDecoder::new(&mut bufreader));
-            ar.set_ignore_zeros(true);
-            ar.unpack(&self.pgdata)?;
+            let decoder = flate2::read::GzDecoder::new(&mut bufreader);
+            basebackup::unpack(decoder, progress, pgdata, expect_checksum)?;
         } else {
-            let mut ar = tar::Archive::new(&mut bufreader);
-            ar.set_ignore_zeros(true);
-            ar.unpack(&self.pgdata)?;
+            basebackup::unpack(&mut bufreader, progress, pgdata, expect_checksum)?;
         };
 
         // Report metrics
@@ -397,8 +748,9 @@ impl ComputeNode {
         let mut retry_period_ms = 500;
         let mut attempts = 0;
         let max_attempts = 5;
+        let mut progress = BasebackupProgress::default();
         loop {
-            let result = self.try_get_basebackup(compute_state, lsn);
+            let result = self.try_get_basebackup(compute_state, lsn, &mut progress);
             match result {
                 Ok(_) => {
                     return result;
@@ -496,6 +848,7 @@ impl ComputeNode {
 
     // Fast path for sync_safekeepers. If they're already synced we get the lsn
     // in one roundtrip. If not, we should do a full sync_safekeepers.
+    #[instrument(skip_all)]
     pub fn check_safekeepers_synced(&self, compute_state: &ComputeState) -> Result<Option<Lsn>> {
         let start_time = Utc::now();
 
@@ -542,7 +895,7 @@ impl ComputeNode {
             .stderr
             .take()
             .expect("stderr should be captured");
-        let logs_handle = handle_postgres_logs(stderr);
+        let logs_handle = handle_postgres_logs(stderr, None);
 
         let sync_output = sync_handle
             .wait_with_output()
@@ -634,6 +987,22 @@ impl ComputeNode {
         // Update pg_hba.conf received with basebackup.
         update_pg_hba(pgdata_path)?;
 
+        // Restore the statistics saved at the last shutdown. They are nice to
+        // have, so don't fail the start if they can't be restored.
+        if let (Some(storage), Some(path)) = (&self.pg_stats_storage, pg_stats_path(pspec)) {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("failed to create rt");
+            let restore =
+                pg_stats::restore_pg_stats(pgdata_path, storage, &path, pspec.timeline_id);
+            match rt.block_on(restore) {
+                Ok(0) => {}
+                Ok(n) => info!("restored {n} statistics files from {path}"),
+                Err(e) => warn!("failed to restore statistics from {path}: {e:#}"),
+            }
+        }
+
         match spec.mode {
             ComputeMode::Primary => {}
             ComputeMode::Replica | ComputeMode::Static(..) => {
@@ -713,13 +1082,60 @@ impl ComputeNode {
 
         // Start a thread to collect logs from stderr.
         let stderr = pg.stderr.take().expect("stderr should be captured");
-        let logs_handle = handle_postgres_logs(stderr);
+        let logs_handle = handle_postgres_logs(stderr, self.log_shipper.clone());
 
         wait_for_postgres(&mut pg, pgdata_path)?;
 
         Ok((pg, logs_handle))
     }
 
+    /// Start Postgres again on the existing PGDATA once it was stopped for the
+    /// /restart API. Unlike [`Self::start_compute`], there is no basebackup
+    /// or safekeepers sync: Postgres was shut down cleanly and only needs the
+    /// configuration from the current spec.
+    #[instrument(skip_all)]
+    pub fn restart_postgres(
+        &self,
+        extension_server_port: u16,
+    ) -> Result<(std::process::Child, std::thread::JoinHandle<()>)> {
+        let compute_state = self.state.lock().unwrap().clone();
+        let pspec = compute_state.pspec.as_ref().expect("spec must be set");
+        let pgdata_path = Path::new(&self.pgdata);
+
+        if pspec.spec.remote_extensions.is_some() {
+            self.prepare_preload_libraries(&pspec.spec)?;
+        }
+        config::write_postgres_conf(
+            &pgdata_path.join("postgresql.conf"),
+            &pspec.spec,
+            Some(extension_server_port),
+        )?;
+        accept_new_connections(pgdata_path)?;
+
+        let start_time = Utc::now();
+        let pg_process = self.start_postgres(pspec.storage_auth_token.clone())?;
+
+        let startup_end_time = Utc::now();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.metrics.start_postgres_ms = startup_end_time
+                .signed_duration_since(start_time)
+                .to_std()
+                .unwrap()
+                .as_millis() as u64;
+            state.metrics.config_ms = 0;
+            state.metrics.total_startup_ms = startup_end_time
+                .signed_duration_since(compute_state.start_time)
+                .to_std()
+                .unwrap()
+                .as_millis() as u64;
+        }
+        self.set_status(ComputeStatus::Running);
+        info!("restarted Postgres");
+
+        Ok(pg_process)
+    }
+
     /// Do initial configuration of the already started Postgres.
     #[instrument(skip_all)]
     pub fn apply_config(&self, compute_state: &ComputeState) -> Result<()> {
@@ -779,9 +1195,10 @@ impl ComputeNode {
         drop(client);
 
         // Run migrations separately to not hold up cold starts
+        let migration_settings = spec.migrations.clone();
         thread::spawn(move || {
             let mut client = Client::connect(connstr.as_str(), NoTls)?;
-            handle_migrations(&mut client)
+            handle_migrations(&mut client, &migration_settings)
         });
         Ok(())
     }
@@ -801,11 +1218,31 @@ impl ComputeNode {
 
     /// Similar to `apply_config()`, but does a bit different sequence of operations,
     /// as it's used to reconfigure a previously started and configured Postgres node.
+    ///
+    /// Only the parts of the spec which changed compared to the running one are
+    /// applied. Settings which need a restart are written to the config, but only
+    /// take effect when the compute is restarted, which is left to the control plane.
     #[instrument(skip_all)]
-    pub fn reconfigure(&self) -> Result<()> {
-        let spec = self.state.lock().unwrap().pspec.clone().unwrap().spec;
+    pub fn reconfigure(&self) -> Result<ReconfigurationPath> {
+        let (spec, pageserver_connstr, running_spec) = {
+            let state = self.state.lock().unwrap();
+            let pspec = state.pspec.clone().unwrap();
+            (
+                pspec.spec,
+                pspec.pageserver_connstr,
+                state.running_spec.clone().unwrap_or_default(),
+            )
+        };
+
+        let changes = SpecChanges::new(&running_spec, &spec);
+        if changes.is_empty() {
+            info!("spec didn't change, nothing to reconfigure");
+            self.state.lock().unwrap().running_spec = Some(spec);
+            return Ok(ReconfigurationPath::Unchanged);
+        }
+        info!(?changes, "reconfiguring compute node");
 
-        if let Some(ref pgbouncer_settings) = spec.pgbouncer_settings {
+        if let (true, Some(pgbouncer_settings)) = (changes.pgbouncer, &spec.pgbouncer_settings) {
             info!("tuning pgbouncer");
 
             let rt = tokio::runtime::Builder::new_current_thread()
@@ -824,21 +1261,41 @@ impl ComputeNode {
             });
         }
 
-        // Write new config
+        if let (true, Some(remote_extensions)) =
+            (changes.remote_extensions, &spec.remote_extensions)
+        {
+            extension_server::create_control_files(remote_extensions, &self.pgbin);
+        }
+
         let pgdata_path = Path::new(&self.pgdata);
-        let postgresql_conf_path = pgdata_path.join("postgresql.conf");
-        config::write_postgres_conf(&postgresql_conf_path, &spec, None)?;
-        // temporarily reset max_cluster_size in config
-        // to avoid the possibility of hitting the limit, while we are reconfiguring:
-        // creating new extensions, roles, etc...
-        config::compute_ctl_temp_override_create(pgdata_path, "neon.max_cluster_size=-1")?;
-        self.pg_reload_conf()?;
+        if changes.config() {
+            // Write new config
+            let postgresql_conf_path = pgdata_path.join("postgresql.conf");
+            config::write_postgres_conf(&postgresql_conf_path, &spec, None)?;
+        }
+        if changes.catalog {
+            // temporarily reset max_cluster_size in config
+            // to avoid the possibility of hitting the limit, while we are reconfiguring:
+            // creating new extensions, roles, etc...
+            config::compute_ctl_temp_override_create(pgdata_path, "neon.max_cluster_size=-1")?;
+        }
+        if changes.config() || changes.catalog {
+            self.pg_reload_conf()?;
+        }
 
         let mut client = Client::connect(self.connstr.as_str(), NoTls)?;
 
+        if changes
+            .settings
+            .iter()
+            .any(|name| name == "neon.pageserver_connstring")
+        {
+            self.wait_for_pageserver_connstring(&mut client, &pageserver_connstr)?;
+        }
+
         // Proceed with post-startup configuration. Note, that order of operations is important.
         // Disable DDL forwarding because control plane already knows about these roles/databases.
-        if spec.mode == ComputeMode::Primary {
+        if changes.catalog && spec.mode == ComputeMode::Primary {
             client.simple_query("SET neon.forward_ddl = false")?;
             cleanup_instance(&mut client)?;
             handle_roles(&spec, &mut client)?;
@@ -850,6 +1307,9 @@ impl ComputeNode {
                 self.connstr.as_str(),
                 self.has_feature(ComputeFeature::AnonExtension),
             )?;
+        }
+        // creates the extensions of `shared_preload_libraries`, so it depends on the settings too
+        if (changes.catalog || changes.config()) && spec.mode == ComputeMode::Primary {
             handle_extensions(&spec, &mut client)?;
             handle_extension_neon(&mut client)?;
             // We can skip handle_migrations here because a new migration can only appear
@@ -858,30 +1318,103 @@ impl ComputeNode {
             // instead of reconfigure.
         }
 
+        // Settings which can only be set at server start
+        let mut pending_restart: Vec<String> = client
+            .query(
+                "SELECT name FROM pg_settings WHERE context = 'postmaster' AND name = ANY($1)",
+                &[&changes.settings],
+            )?
+            .iter()
+            .map(|row| row.get("name"))
+            .collect();
+        pending_restart.extend(changes.restart.iter().map(|name| name.to_string()));
+
         // 'Close' connection
         drop(client);
 
-        // reset max_cluster_size in config back to original value and reload config
-        config::compute_ctl_temp_override_remove(pgdata_path)?;
-        self.pg_reload_conf()?;
+        if changes.catalog {
+            // reset max_cluster_size in config back to original value and reload config
+            config::compute_ctl_temp_override_remove(pgdata_path)?;
+            self.pg_reload_conf()?;
+        }
+
+        let path = if pending_restart.is_empty() {
+            ReconfigurationPath::Online
+        } else {
+            warn!("changes waiting for a restart: {pending_restart:?}");
+            ReconfigurationPath::RestartRequired {
+                pending_changes: pending_restart,
+            }
+        };
+        self.state.lock().unwrap().running_spec = Some(spec.clone());
+        SPEC_GENERATION.inc();
 
         let unknown_op = "unknown".to_string();
         let op_id = spec.operation_uuid.as_ref().unwrap_or(&unknown_op);
         info!(
-            "finished reconfiguration of compute node for operation {}",
-            op_id
+            ?path,
+            "finished reconfiguration of compute node for operation {}", op_id
         );
 
-        Ok(())
+        Ok(path)
     }
 
-    #[instrument(skip_all)]
+    /// Wait until Postgres has loaded the new pageserver connection string
+    /// after a config reload, e.g. when the tenant was split into more shards
+    /// or moved to other pageservers. The neon extension then switches to the
+    /// new pageservers without interrupting the running queries: requests in
+    /// flight are retried with the new shard map.
+    fn wait_for_pageserver_connstring(&self, client: &mut Client, connstr: &str) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            // the session reloads the config between queries, after the postmaster did
+            let current: String = client
+                .query_one("SELECT current_setting('neon.pageserver_connstring')", &[])?
+                .get(0);
+            if current == connstr {
+                info!(
+                    "switched to the new pageservers in {:?}: {connstr}",
+                    start.elapsed()
+                );
+                return Ok(());
+            }
+            if start.elapsed() > PAGESERVER_CONNSTRING_RELOAD_TIMEOUT {
+                anyhow::bail!(
+                    "Postgres didn't load the new pageserver connection string in {:?}",
+                    PAGESERVER_CONNSTRING_RELOAD_TIMEOUT
+                );
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// The root span of the startup phases, which are exported as its child
+    /// spans: syncing safekeepers, getting the basebackup, configuring and
+    /// starting Postgres, and applying the spec.
+    #[instrument(
+        skip_all,
+        fields(
+            endpoint_id = field::Empty,
+            operation_uuid = field::Empty,
+            tenant_id = field::Empty,
+            timeline_id = field::Empty,
+        )
+    )]
     pub fn start_compute(
         &self,
         extension_server_port: u16,
     ) -> Result<(std::process::Child, std::thread::JoinHandle<()>)> {
         let compute_state = self.state.lock().unwrap().clone();
         let pspec = compute_state.pspec.as_ref().expect("spec must be set");
+        let span = Span::current();
+        if let Some(endpoint_id) = &pspec.spec.endpoint_id {
+            span.record("endpoint_id", endpoint_id.as_str());
+        }
+        if let Some(operation_uuid) = &pspec.spec.operation_uuid {
+            span.record("operation_uuid", operation_uuid.as_str());
+        }
+        span.record("tenant_id", field::display(pspec.tenant_id));
+        span.record("timeline_id", field::display(pspec.timeline_id));
         info!(
             "starting compute for project {}, operation {}, tenant {}, timeline {}",
             pspec.spec.cluster.cluster_id.as_deref().unwrap_or("None"),
@@ -941,6 +1474,17 @@ impl ComputeNode {
             info!("{:?}", remote_ext_metrics);
         }
 
+        // fail with a clear error rather than have Postgres fail to start
+        if !pspec.spec.preload_libraries.is_empty() {
+            let missing = extension_server::missing_libraries(
+                &config::shared_preload_libraries(&pspec.spec),
+                &self.pgbin,
+            );
+            if !missing.is_empty() {
+                anyhow::bail!("shared preload libraries are not installed: {missing:?}");
+            }
+        }
+
         self.prepare_pgdata(&compute_state, extension_server_port)?;
 
         let start_time = Utc::now();
@@ -979,7 +1523,9 @@ impl ComputeNode {
                 .to_std()
                 .unwrap()
                 .as_millis() as u64;
+            state.running_spec = Some(pspec.spec.clone());
         }
+        SPEC_GENERATION.inc();
         self.set_status(ComputeStatus::Running);
 
         info!(
@@ -1123,55 +1669,35 @@ LIMIT 100",
                     "Remote extensions storage is not configured",
                 )))?;
 
-        let ext_archive_name = ext_path.object_name().expect("bad path");
-
-        let mut first_try = false;
-        if !self
-            .ext_download_progress
-            .read()
-            .expect("lock err")
-            .contains_key(ext_archive_name)
-        {
-            self.ext_download_progress
-                .write()
-                .expect("lock err")
-                .insert(ext_archive_name.to_string(), (Utc::now(), false));
-            first_try = true;
-        }
-        let (download_start, download_completed) =
-            self.ext_download_progress.read().expect("lock err")[ext_archive_name];
-        let start_time_delta = Utc::now()
-            .signed_duration_since(download_start)
-            .to_std()
-            .unwrap()
-            .as_millis() as u64;
-
-        // how long to wait for extension download if it was started by another process
-        const HANG_TIMEOUT: u64 = 3000; // milliseconds
-
-        if download_completed {
-            info!("extension already downloaded, skipping re-download");
-            return Ok(0);
-        } else if start_time_delta < HANG_TIMEOUT && !first_try {
-            info!("download {ext_archive_name} already started by another process, hanging untill completion or timeout");
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(500));
-            loop {
-                info!("waiting for download");
-                interval.tick().await;
-                let (_, download_completed_now) =
-                    self.ext_download_progress.read().expect("lock")[ext_archive_name];
-                if download_completed_now {
-                    info!("download finished by whoever else downloaded it");
+        let ext_archive_name = ext_path.object_name().expect("bad path").to_string();
+
+        // how long to wait for extension download if it was started by another request
+        let hang_timeout = chrono::Duration::minutes(5);
+
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(500));
+        let started = loop {
+            let claim = {
+                let mut progress = self.ext_download_progress.write().expect("lock err");
+                extension_server::claim_download(
+                    &mut progress,
+                    &ext_archive_name,
+                    Utc::now(),
+                    hang_timeout,
+                )
+            };
+            match claim {
+                DownloadClaim::Downloaded => {
+                    info!("extension already downloaded, skipping re-download");
                     return Ok(0);
                 }
+                DownloadClaim::InProgress => {
+                    info!("download {ext_archive_name} already started by another request, waiting for it to complete");
+                    interval.tick().await;
+                }
+                DownloadClaim::Claimed(started) => break started,
             }
-            // NOTE: the above loop will get terminated
-            // based on the timeout of the download function
-        }
+        };
 
-        // if extension hasn't been downloaded before or the previous
-        // attempt to download was at least HANG_TIMEOUT ms ago
-        // then we try to download it here
         info!("downloading new extension {ext_archive_name}");
 
         let download_size = extension_server::download_extension(
@@ -1179,18 +1705,23 @@ LIMIT 100",
             &ext_path,
             ext_remote_storage,
             &self.pgbin,
+            self.ext_cache.clone(),
         )
         .await
         .map_err(DownloadError::Other);
 
-        self.ext_download_progress
-            .write()
-            .expect("bad lock")
-            .insert(ext_archive_name.to_string(), (download_start, true));
+        let mut progress = self.ext_download_progress.write().expect("bad lock");
+        extension_server::finish_download(
+            &mut progress,
+            &ext_archive_name,
+            started,
+            download_size.is_ok(),
+        );
 
         download_size
     }
 
+    #[instrument(skip_all)]
     #[tokio::main]
     pub async fn prepare_preload_libraries(
         &self,
@@ -1208,36 +1739,10 @@ LIMIT 100",
             .as_ref()
             .ok_or(anyhow::anyhow!("Remote extensions are not configured"))?;
 
-        info!("parse shared_preload_libraries from spec.cluster.settings");
-        let mut libs_vec = Vec::new();
-        if let Some(libs) = spec.cluster.settings.find("shared_preload_libraries") {
-            libs_vec = libs
-                .split(&[',', '\'', ' '])
-                .filter(|s| *s != "neon" && !s.is_empty())
-                .map(str::to_string)
-                .collect();
-        }
-        info!("parse shared_preload_libraries from provided postgresql.conf");
-
-        // that is used in neon_local and python tests
-        if let Some(conf) = &spec.cluster.postgresql_conf {
-            let conf_lines = conf.split('\n').collect::<Vec<&str>>();
-            let mut shared_preload_libraries_line = "";
-            for line in conf_lines {
-                if line.starts_with("shared_preload_libraries") {
-                    shared_preload_libraries_line = line;
-                }
-            }
-            let mut preload_libs_vec = Vec::new();
-            if let Some(libs) = shared_preload_libraries_line.split("='").nth(1) {
-                preload_libs_vec = libs
-                    .split(&[',', '\'', ' '])
-                    .filter(|s| *s != "neon" && !s.is_empty())
-                    .map(str::to_string)
-                    .collect();
-            }
-            libs_vec.extend(preload_libs_vec);
-        }
+        let mut libs_vec: Vec<String> = config::shared_preload_libraries(spec)
+            .into_iter()
+            .filter(|lib| lib != "neon")
+            .collect();
 
         // Don't try to download libraries that are not in the index.
         // Assume that they are already present locally.
@@ -6,7 +6,8 @@ use std::path::Path;
 use anyhow::Result;
 
 use crate::pg_helpers::escape_conf_value;
-use crate::pg_helpers::PgOptionsSerialize;
+use crate::pg_helpers::{GenericOptionsSearch, PgOptionsSerialize};
+use crate::spec_diff::parse_conf;
 use compute_api::spec::{ComputeMode, ComputeSpec};
 
 /// Check that `line` is inside a text file and put it there if it is not.
@@ -79,6 +80,15 @@ pub fn write_postgres_conf(
         ComputeMode::Replica => {
             // hot_standby is 'on' by default, but let's be explicit
             writeln!(file, "hot_standby=on")?;
+            if let (Some(conninfo), Some(timeline_id)) =
+                (replica_primary_conninfo(spec), &spec.timeline_id)
+            {
+                writeln!(file, "primary_conninfo={}", escape_conf_value(&conninfo))?;
+                writeln!(file, "primary_slot_name='repl_{timeline_id}_'")?;
+                // keep the primary from vacuuming away rows the queries on the
+                // replica still see, can be turned off in the spec settings
+                writeln!(file, "hot_standby_feedback=on")?;
+            }
         }
     }
 
@@ -93,6 +103,16 @@ pub fn write_postgres_conf(
         writeln!(file, "neon.extension_server_port={}", port)?;
     }
 
+    // Computes which declare their preload libraries get exactly those, on
+    // top of the ones from the settings above
+    if !spec.preload_libraries.is_empty() {
+        writeln!(
+            file,
+            "shared_preload_libraries={}",
+            escape_conf_value(&shared_preload_libraries(spec).join(","))
+        )?;
+    }
+
     // This is essential to keep this line at the end of the file,
     // because it is intended to override any settings above.
     writeln!(file, "include_if_exists = 'compute_ctl_temp_override.conf'")?;
@@ -100,6 +120,80 @@ pub fn write_postgres_conf(
     Ok(())
 }
 
+fn parse_libraries(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(&[',', '\'', '"', ' '])
+        .filter(|lib| !lib.is_empty())
+        .map(library_name)
+}
+
+/// Name of a library in `shared_preload_libraries` without the `$libdir/`
+/// prefix and the `.so` suffix, which Postgres adds when looking it up, so
+/// that the different spellings of a library compare equal. Paths are kept.
+pub fn library_name(lib: &str) -> &str {
+    let name = lib.strip_prefix("$libdir/").unwrap_or(lib);
+    if name.contains('/') {
+        return lib;
+    }
+    name.strip_suffix(".so").unwrap_or(name)
+}
+
+/// The final `shared_preload_libraries` of the compute: `neon`, then the ones
+/// set in `postgresql.conf` and the settings of the spec, which is how older
+/// control planes pass them, then the ones the spec declares.
+pub fn shared_preload_libraries(spec: &ComputeSpec) -> Vec<String> {
+    let from_conf = spec
+        .cluster
+        .postgresql_conf
+        .as_deref()
+        .and_then(|conf| parse_conf(conf).remove("shared_preload_libraries"));
+    let from_settings = spec.cluster.settings.find("shared_preload_libraries");
+
+    let mut libs = vec!["neon".to_string()];
+    let declared = spec.preload_libraries.iter().map(|lib| library_name(lib));
+    for lib in parse_libraries(from_conf.as_deref().unwrap_or_default())
+        .chain(parse_libraries(
+            from_settings.as_deref().unwrap_or_default(),
+        ))
+        .chain(declared)
+    {
+        if !libs.iter().any(|l| l == lib) {
+            libs.push(lib.to_string());
+        }
+    }
+    libs
+}
+
+/// Connection string for a replica to stream WAL from the safekeepers, it
+/// connects to any of them which is available. `None` if the spec doesn't
+/// have the safekeepers, the tenant or the timeline.
+pub fn replica_primary_conninfo(spec: &ComputeSpec) -> Option<String> {
+    let (Some(tenant_id), Some(timeline_id)) = (&spec.tenant_id, &spec.timeline_id) else {
+        return None;
+    };
+    if spec.safekeeper_connstrings.is_empty() {
+        return None;
+    }
+
+    let mut hosts = Vec::new();
+    let mut ports = Vec::new();
+    for sk in &spec.safekeeper_connstrings {
+        let (host, port) = sk.rsplit_once(':')?;
+        hosts.push(host);
+        ports.push(port);
+    }
+    let mut conninfo = format!(
+        "host={} port={} options='-c timeline_id={timeline_id} tenant_id={tenant_id}' application_name=replica replication=true",
+        hosts.join(","),
+        ports.join(","),
+    );
+    if let Some(token) = &spec.storage_auth_token {
+        let token = token.replace('\\', "\\\\").replace('\'', "\\'");
+        conninfo.push_str(&format!(" password='{token}'"));
+    }
+    Some(conninfo)
+}
+
 /// create file compute_ctl_temp_override.conf in pgdata_dir
 /// add provided options to this file
 pub fn compute_ctl_temp_override_create(pgdata_path: &Path, options: &str) -> Result<()> {
@@ -21,11 +21,13 @@ fn configurator_main_loop(compute: &Arc<ComputeNode>) {
             drop(state);
 
             let mut new_status = ComputeStatus::Failed;
-            if let Err(e) = compute.reconfigure() {
-                error!("could not configure compute node: {}", e);
-            } else {
-                new_status = ComputeStatus::Running;
-                info!("compute node configured");
+            match compute.reconfigure() {
+                Ok(path) => {
+                    new_status = ComputeStatus::Running;
+                    info!(?path, "compute node configured");
+                    compute.state.lock().unwrap().reconfiguration = Some(path);
+                }
+                Err(e) => error!("could not configure compute node: {}", e),
             }
 
             // XXX: used to test that API is blocking
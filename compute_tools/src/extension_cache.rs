@@ -0,0 +1,209 @@
+//! Cache of the extension archives downloaded from the extension storage, on
+//! the local disk of the compute, so that computes which are suspended often
+//! don't download the same, possibly large, extensions on every start.
+//!
+//! The archives are kept next to an index file, which records their size,
+//! SHA-256 and when they were last used. The least recently used archives are
+//! evicted to keep the cache within its size limit, and an archive which
+//! doesn't match its index entry anymore is discarded instead of being used.
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheIndex {
+    /// Keyed by the path of the archive in the extension storage, which
+    /// includes the build tag, so that archives of different builds don't
+    /// clash.
+    pub entries: HashMap<String, CacheEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// Name of the archive file in the cache directory.
+    pub file: String,
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the archive.
+    pub sha256: String,
+    pub last_used: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct ExtensionCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<CacheIndex>,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// The archive file of `key`. The storage paths contain slashes, so they are
+/// hashed rather than used as they are.
+fn file_name(key: &str) -> String {
+    format!("{}.tar.zst", sha256_hex(key.as_bytes()))
+}
+
+impl ExtensionCache {
+    /// Open the cache in `dir`, creating it if needed. Archives which aren't
+    /// in the index, e.g. because `compute_ctl` died while writing them, are
+    /// removed, and so are the least recently used ones if the cache is larger
+    /// than `max_size`, which may have been lowered since the last start.
+    pub fn open(dir: &Path, max_size: u64) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create extension cache directory {dir:?}"))?;
+
+        let index_path = dir.join(INDEX_FILE);
+        let mut index = match fs::read(&index_path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                warn!("ignoring corrupted extension cache index {index_path:?}: {e}");
+                CacheIndex::default()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => CacheIndex::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {index_path:?}"));
+            }
+        };
+
+        index
+            .entries
+            .retain(|_, entry| dir.join(&entry.file).exists());
+        for file in fs::read_dir(dir)? {
+            let name = file?.file_name();
+            let name = name.to_string_lossy();
+            if name != INDEX_FILE && index.entries.values().all(|entry| entry.file != name) {
+                info!("removing stray file {name} from the extension cache");
+                // it's left in the cache directory, but not used
+                if let Err(e) = fs::remove_file(dir.join(&*name)) {
+                    warn!("failed to remove stray file {name} from the extension cache: {e}");
+                }
+            }
+        }
+
+        let cache = ExtensionCache {
+            dir: dir.to_path_buf(),
+            max_size,
+            index: Mutex::new(CacheIndex::default()),
+        };
+        cache.evict(&mut index, 0);
+        cache.save_index(&index)?;
+        *cache.index.lock().unwrap() = index;
+
+        Ok(cache)
+    }
+
+    /// Total size of the cached archives.
+    pub fn size(&self) -> u64 {
+        let index = self.index.lock().unwrap();
+        index.entries.values().map(|entry| entry.size).sum()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.index.lock().unwrap().entries.contains_key(key)
+    }
+
+    /// The cached archive of `key`, if there is one and it is intact.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let mut index = self.index.lock().unwrap();
+        let entry = index.entries.get_mut(key)?;
+
+        match fs::read(self.dir.join(&entry.file)) {
+            Ok(data) if data.len() as u64 == entry.size && sha256_hex(&data) == entry.sha256 => {
+                entry.last_used = Utc::now();
+                if let Err(e) = self.save_index(&index) {
+                    warn!("failed to save extension cache index: {e:#}");
+                }
+                Some(data)
+            }
+            Ok(_) => {
+                warn!("cached archive of {key} doesn't match its checksum, discarding it");
+                self.remove(&mut index, key);
+                None
+            }
+            Err(e) => {
+                warn!("failed to read cached archive of {key}, discarding it: {e}");
+                self.remove(&mut index, key);
+                None
+            }
+        }
+    }
+
+    /// Add the archive of `key` to the cache, evicting the least recently used
+    /// archives to make room for it. Archives larger than the whole cache are
+    /// not cached.
+    pub fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let size = data.len() as u64;
+        if size > self.max_size {
+            info!("archive of {key} is larger than the extension cache, not caching it");
+            return Ok(());
+        }
+
+        let mut index = self.index.lock().unwrap();
+        self.remove(&mut index, key);
+        self.evict(&mut index, size);
+
+        let file = file_name(key);
+        let tmp_path = self.dir.join(format!("{file}.tmp"));
+        fs::write(&tmp_path, data).with_context(|| format!("failed to write {tmp_path:?}"))?;
+        fs::rename(&tmp_path, self.dir.join(&file))?;
+
+        index.entries.insert(
+            key.to_string(),
+            CacheEntry {
+                file,
+                size,
+                sha256: sha256_hex(data),
+                last_used: Utc::now(),
+            },
+        );
+        self.save_index(&index)
+    }
+
+    fn remove(&self, index: &mut CacheIndex, key: &str) {
+        if let Some(entry) = index.entries.remove(key) {
+            if let Err(e) = fs::remove_file(self.dir.join(&entry.file)) {
+                warn!("failed to remove cached archive of {key}: {e}");
+            }
+        }
+    }
+
+    /// Evict the least recently used archives until `needed` more bytes fit.
+    fn evict(&self, index: &mut CacheIndex, needed: u64) {
+        loop {
+            let total: u64 = index.entries.values().map(|entry| entry.size).sum();
+            if total + needed <= self.max_size {
+                return;
+            }
+            let Some(key) = index
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                return;
+            };
+            info!("evicting archive of {key} from the extension cache");
+            self.remove(index, &key);
+        }
+    }
+
+    fn save_index(&self, index: &CacheIndex) -> Result<()> {
+        let path = self.dir.join(INDEX_FILE);
+        let tmp_path = self.dir.join(format!("{INDEX_FILE}.tmp"));
+        fs::write(&tmp_path, serde_json::to_vec(index)?)
+            .with_context(|| format!("failed to write {tmp_path:?}"))?;
+        fs::rename(&tmp_path, &path).with_context(|| format!("failed to write {path:?}"))?;
+        Ok(())
+    }
+}
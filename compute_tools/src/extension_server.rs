@@ -71,20 +71,33 @@ More specifically, here is an example ext_index.json
     }
 }
 */
+use anyhow::Context;
 use anyhow::{self, Result};
-use anyhow::{bail, Context};
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use compute_api::spec::RemoteExtSpec;
 use regex::Regex;
 use remote_storage::*;
 use reqwest::StatusCode;
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::Arc;
+use std::time::Duration;
 use tar::Archive;
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 use tracing::log::warn;
+use utils::backoff;
 use zstd::stream::read::Decoder;
 
+use crate::extension_cache::ExtensionCache;
+
+/// Timeout of a single attempt to download an extension archive.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// How many times to retry a failed download, unless the archive doesn't exist.
+const DOWNLOAD_RETRIES: u32 = 3;
+
 fn get_pg_config(argument: &str, pgbin: &str) -> String {
     // gives the result of `pg_config [argument]`
     // where argument is a flag like `--version` or `--sharedir`
@@ -103,6 +116,24 @@ fn get_pg_config(argument: &str, pgbin: &str) -> String {
         .to_string()
 }
 
+/// Libraries of `libs`, as returned by [`crate::config::shared_preload_libraries`],
+/// which are not installed: names missing from the library directory of
+/// Postgres, or paths which don't exist.
+pub fn missing_libraries(libs: &[String], pgbin: &str) -> Vec<String> {
+    let libdir = PathBuf::from(get_pg_config("--pkglibdir", pgbin));
+    libs.iter()
+        .filter(|lib| {
+            let path = if lib.contains('/') {
+                PathBuf::from(lib)
+            } else {
+                libdir.join(lib)
+            };
+            !path.exists() && !path.with_extension("so").exists()
+        })
+        .cloned()
+        .collect()
+}
+
 pub fn get_pg_version(pgbin: &str) -> String {
     // pg_config --version returns a (platform specific) human readable string
     // such as "PostgreSQL 15.4". We parse this to v14/v15/v16 etc.
@@ -131,34 +162,87 @@ fn parse_pg_version(human_version: &str) -> &str {
     panic!("Unsuported postgres version {human_version}");
 }
 
-// download the archive for a given extension,
-// unzip it, and place files in the appropriate locations (share/lib)
+// download the archive for a given extension, unless it is in the cache,
+// unzip it, and place files in the appropriate locations (share/lib).
+// Returns the number of bytes downloaded, 0 if the archive was cached.
 pub async fn download_extension(
     ext_name: &str,
     ext_path: &RemotePath,
     ext_remote_storage: &str,
     pgbin: &str,
+    cache: Option<Arc<ExtensionCache>>,
 ) -> Result<u64> {
+    // reading the cache, checking the archives and unpacking them is blocking
+    // file I/O, so it's done off the async runtime threads
+    let cache_key = ext_path.to_string();
+    let cached = match &cache {
+        Some(cache) => {
+            let (cache, cache_key) = (Arc::clone(cache), cache_key.clone());
+            tokio::task::spawn_blocking(move || cache.get(&cache_key)).await?
+        }
+        None => None,
+    };
+    if let Some(archive) = cached {
+        info!("Using cached archive of extension {:?}", ext_name);
+        let (ext_name, ext_path, pgbin) = (ext_name.to_owned(), ext_path.clone(), pgbin.to_owned());
+        tokio::task::spawn_blocking(move || {
+            unpack_extension(&ext_name, &ext_path, &archive, &pgbin)
+        })
+        .await??;
+        return Ok(0);
+    }
+
     info!("Download extension {:?} from {:?}", ext_name, ext_path);
 
-    // TODO add retry logic
-    let download_buffer =
-        match download_extension_tar(ext_remote_storage, &ext_path.to_string()).await {
-            Ok(buffer) => buffer,
-            Err(error_message) => {
-                return Err(anyhow::anyhow!(
-                    "error downloading extension {:?}: {:?}",
-                    ext_name,
-                    error_message
-                ));
-            }
-        };
+    let download_buffer = match backoff::retry(
+        || download_extension_tar(ext_remote_storage, &ext_path.to_string()),
+        |e| matches!(e, DownloadError::NotFound | DownloadError::BadInput(_)),
+        1,
+        DOWNLOAD_RETRIES,
+        "downloading extension",
+        // never cancelled, the download is bounded by the retries
+        &CancellationToken::new(),
+    )
+    .await
+    .unwrap_or(Err(DownloadError::Cancelled))
+    {
+        Ok(buffer) => buffer,
+        Err(error_message) => {
+            return Err(anyhow::anyhow!(
+                "error downloading extension {:?}: {:?}",
+                ext_name,
+                error_message
+            ));
+        }
+    };
 
     let download_size = download_buffer.len() as u64;
     info!("Download size {:?}", download_size);
+    let (ext_name, ext_path, pgbin) = (ext_name.to_owned(), ext_path.clone(), pgbin.to_owned());
+    tokio::task::spawn_blocking(move || {
+        unpack_extension(&ext_name, &ext_path, &download_buffer, &pgbin)?;
+
+        // cache the archive only once it turned out to be valid
+        if let Some(cache) = cache {
+            if let Err(e) = cache.put(&cache_key, &download_buffer) {
+                warn!("failed to cache archive of extension {ext_name}: {e:#}");
+            }
+        }
+        anyhow::Ok(())
+    })
+    .await??;
+    Ok(download_size)
+}
+
+fn unpack_extension(
+    ext_name: &str,
+    ext_path: &RemotePath,
+    archive: &[u8],
+    pgbin: &str,
+) -> Result<()> {
     // it's unclear whether it is more performant to decompress into memory or not
     // TODO: decompressing into memory can be avoided
-    let decoder = Decoder::new(download_buffer.as_ref())?;
+    let decoder = Decoder::new(archive)?;
     let mut archive = Archive::new(decoder);
 
     let unzip_dest = pgbin
@@ -197,7 +281,66 @@ pub async fn download_extension(
         }
     }
     info!("done moving extension {ext_name}");
-    Ok(download_size)
+    Ok(())
+}
+
+/// The state of the download of an extension archive, see [`claim_download`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum DownloadClaim {
+    /// The archive was downloaded already.
+    Downloaded,
+    /// Another request is downloading the archive.
+    InProgress,
+    /// The caller is to download the archive, and to call [`finish_download`]
+    /// with this start time.
+    Claimed(DateTime<Utc>),
+}
+
+/// Claim the download of an archive in `progress`, which maps the archive names
+/// to the start of their download and whether it completed, unless another
+/// request has been downloading it for less than `hang_timeout`.
+pub fn claim_download(
+    progress: &mut HashMap<String, (DateTime<Utc>, bool)>,
+    ext_archive_name: &str,
+    now: DateTime<Utc>,
+    hang_timeout: chrono::Duration,
+) -> DownloadClaim {
+    match progress.get(ext_archive_name) {
+        Some((_, true)) => DownloadClaim::Downloaded,
+        Some((download_start, false))
+            if now.signed_duration_since(*download_start) < hang_timeout =>
+        {
+            DownloadClaim::InProgress
+        }
+        // not downloaded yet, the previous attempt failed or hung
+        _ => {
+            progress.insert(ext_archive_name.to_owned(), (now, false));
+            DownloadClaim::Claimed(now)
+        }
+    }
+}
+
+/// Record the outcome of a download claimed at `started`. The claim may have
+/// been taken over by another request meanwhile, if this one took longer than
+/// the hang timeout: a failure then leaves the other request's claim alone.
+pub fn finish_download(
+    progress: &mut HashMap<String, (DateTime<Utc>, bool)>,
+    ext_archive_name: &str,
+    started: DateTime<Utc>,
+    succeeded: bool,
+) {
+    if succeeded {
+        if let Some((_, downloaded)) = progress.get_mut(ext_archive_name) {
+            *downloaded = true;
+        } else {
+            progress.insert(ext_archive_name.to_owned(), (started, true));
+        }
+    } else if let Some((download_start, false)) = progress.get(ext_archive_name) {
+        // let the next request try again
+        if *download_start == started {
+            progress.remove(ext_archive_name);
+        }
+    }
 }
 
 // Create extension control files from spec
@@ -233,12 +376,23 @@ pub fn create_control_files(remote_extensions: &RemoteExtSpec, pgbin: &str) {
 // using HHTP GET
 // and return the response body as bytes
 //
-async fn download_extension_tar(ext_remote_storage: &str, ext_path: &str) -> Result<Bytes> {
+async fn download_extension_tar(
+    ext_remote_storage: &str,
+    ext_path: &str,
+) -> Result<Bytes, DownloadError> {
     let uri = format!("{}/{}", ext_remote_storage, ext_path);
 
     info!("Download extension {:?} from uri {:?}", ext_path, uri);
 
-    let resp = reqwest::get(uri).await?;
+    let client = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()
+        .map_err(|e| DownloadError::Other(e.into()))?;
+    let resp = client
+        .get(uri)
+        .send()
+        .await
+        .map_err(|e| DownloadError::Other(e.into()))?;
 
     match resp.status() {
         StatusCode::OK => match resp.bytes().await {
@@ -246,13 +400,23 @@ async fn download_extension_tar(ext_remote_storage: &str, ext_path: &str) -> Res
                 info!("Download extension {:?} completed successfully", ext_path);
                 Ok(resp)
             }
-            Err(e) => bail!("could not deserialize remote extension response: {}", e),
+            Err(e) => Err(DownloadError::Other(anyhow::anyhow!(
+                "could not deserialize remote extension response: {}",
+                e
+            ))),
         },
-        StatusCode::SERVICE_UNAVAILABLE => bail!("remote extension is temporarily unavailable"),
-        _ => bail!(
+        StatusCode::NOT_FOUND => Err(DownloadError::NotFound),
+        StatusCode::SERVICE_UNAVAILABLE => Err(DownloadError::Other(anyhow::anyhow!(
+            "remote extension is temporarily unavailable"
+        ))),
+        status if status.is_client_error() => Err(DownloadError::BadInput(anyhow::anyhow!(
+            "unexpected remote extension response status code: {}",
+            status
+        ))),
+        status => Err(DownloadError::Other(anyhow::anyhow!(
             "unexpected remote extension response status code: {}",
-            resp.status()
-        ),
+            status
+        ))),
     }
 }
 
@@ -2,20 +2,32 @@ use std::convert::Infallible;
 use std::net::IpAddr;
 use std::net::Ipv6Addr;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::catalog;
 use crate::compute::{ComputeNode, ComputeState, ParsedSpec};
-use compute_api::requests::ConfigurationRequest;
-use compute_api::responses::{ComputeStatus, ComputeStatusResponse, GenericAPIError};
+use compute_api::requests::{ConfigurationRequest, LfcSizeRequest, PromoteRequest, RestartRequest};
+use compute_api::responses::{
+    ComputeStatus, ComputeStatusResponse, GenericAPIError, LfcSizeResponse, PromoteResponse,
+    RestartResponse, TerminateResponse,
+};
+use compute_api::spec::ComputeMode;
+use utils::lsn::Lsn;
+use vm_monitor::filecache::{self, FileCacheConfig, FileCacheState};
 
 use anyhow::Result;
+use chrono::Utc;
+use hyper::header::CONTENT_TYPE;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use num_cpus;
 use serde_json;
+use tokio::sync::oneshot;
 use tokio::task;
-use tracing::{error, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 use tracing_utils::http::OtelName;
 
 fn status_response_from_state(state: &ComputeState) -> ComputeStatusResponse {
@@ -32,6 +44,8 @@ fn status_response_from_state(state: &ComputeState) -> ComputeStatusResponse {
         status: state.status,
         last_active: state.last_active,
         error: state.error.clone(),
+        reconfiguration: state.reconfiguration.clone(),
+        lfc_prewarm: state.lfc_prewarm.clone(),
     }
 }
 
@@ -51,8 +65,23 @@ async fn routes(req: Request<Body>, compute: &Arc<ComputeNode>) -> Response<Body
             Response::new(Body::from(serde_json::to_string(&status_response).unwrap()))
         }
 
-        // Startup metrics in JSON format. Keep /metrics reserved for a possible
-        // future use for Prometheus metrics format.
+        // Prometheus metrics
+        (&Method::GET, "/metrics") => {
+            debug!("serving /metrics GET request");
+            let state = compute.state.lock().unwrap().clone();
+            match crate::metrics::render(&state) {
+                Ok((content_type, body)) => Response::builder()
+                    .header(CONTENT_TYPE, content_type)
+                    .body(Body::from(body))
+                    .unwrap(),
+                Err(e) => {
+                    error!("failed to render metrics: {e:#}");
+                    render_json_error(&format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        }
+
+        // Startup metrics in JSON format.
         (&Method::GET, "/metrics.json") => {
             info!("serving /metrics.json GET request");
             let metrics = compute.state.lock().unwrap().metrics.clone();
@@ -106,6 +135,127 @@ async fn routes(req: Request<Body>, compute: &Arc<ComputeNode>) -> Response<Body
             ))
         }
 
+        // Checkpoint and stop Postgres, and return the LSN the safekeepers were
+        // synced to, so that the control plane can record a clean suspend point.
+        (&Method::POST, "/terminate") => {
+            info!("serving /terminate POST request");
+            match handle_terminate_request(compute).await {
+                Ok(lsn) => Response::new(Body::from(
+                    serde_json::to_string(&TerminateResponse { lsn }).unwrap(),
+                )),
+                Err((msg, code)) => {
+                    error!("error handling /terminate request: {msg}");
+                    render_json_error(&msg, code)
+                }
+            }
+        }
+
+        // Restart Postgres with the configuration from the current spec, e.g.
+        // to apply settings which need a restart, with minimal downtime.
+        (&Method::POST, "/restart") => {
+            info!("serving /restart POST request");
+            match handle_restart_request(req, compute).await {
+                Ok(response) => {
+                    Response::new(Body::from(serde_json::to_string(&response).unwrap()))
+                }
+                Err((msg, code)) => {
+                    error!("error handling /restart request: {msg}");
+                    render_json_error(&msg, code)
+                }
+            }
+        }
+
+        // Databases, roles, and the schemas and extensions of a database given
+        // with the `database` query parameter
+        (
+            &Method::GET,
+            "/catalog/databases" | "/catalog/roles" | "/catalog/schemas" | "/catalog/extensions",
+        ) => {
+            let route = req.uri().path().to_string();
+            info!("serving {route} GET request");
+            match handle_catalog_request(req, compute).await {
+                Ok(json) => Response::new(Body::from(json)),
+                Err((msg, code)) => {
+                    error!("error handling {route} request: {msg}");
+                    render_json_error(&msg, code)
+                }
+            }
+        }
+
+        // Promote a hot standby replica to a primary
+        (&Method::POST, "/promote") => {
+            info!("serving /promote POST request");
+            match handle_promote_request(req, compute).await {
+                Ok(lsn) => Response::new(Body::from(
+                    serde_json::to_string(&PromoteResponse { lsn }).unwrap(),
+                )),
+                Err((msg, code)) => {
+                    error!("error handling /promote request: {msg}");
+                    render_json_error(&msg, code)
+                }
+            }
+        }
+
+        // Get the size of the local file cache of the running compute
+        (&Method::GET, "/lfc_size") => {
+            info!("serving /lfc_size GET request");
+            match handle_lfc_size_get_request(compute).await {
+                Ok(size_bytes) => Response::new(Body::from(
+                    serde_json::to_string(&LfcSizeResponse { size_bytes }).unwrap(),
+                )),
+                Err((msg, code)) => {
+                    error!("error handling /lfc_size request: {msg}");
+                    render_json_error(&msg, code)
+                }
+            }
+        }
+
+        // Resize the local file cache of the running compute
+        (&Method::POST, "/lfc_size") => {
+            info!("serving /lfc_size POST request");
+            match handle_lfc_size_request(req, compute).await {
+                Ok(size_bytes) => Response::new(Body::from(
+                    serde_json::to_string(&LfcSizeResponse { size_bytes }).unwrap(),
+                )),
+                Err((msg, code)) => {
+                    error!("error handling /lfc_size request: {msg}");
+                    render_json_error(&msg, code)
+                }
+            }
+        }
+
+        // Save the content of the local file cache, e.g. before suspending the
+        // compute, so that it can be prewarmed on the next start.
+        (&Method::POST, "/lfc_state") => {
+            info!("serving /lfc_state POST request");
+            let Some(path) = compute.lfc_state_path.clone() else {
+                return render_json_error(
+                    "local file cache state path is not configured",
+                    StatusCode::PRECONDITION_FAILED,
+                );
+            };
+            let status = compute.get_status();
+            if status != ComputeStatus::Running {
+                let msg = format!("compute is not running, current status: {:?}", status);
+                error!(msg);
+                return render_json_error(&msg, StatusCode::PRECONDITION_FAILED);
+            }
+
+            let c = compute.clone();
+            match task::spawn_blocking(move || crate::lfc_prewarm::save_lfc_state(&c, &path))
+                .await
+                .unwrap()
+            {
+                Ok(total_blocks) => Response::new(Body::from(
+                    serde_json::json!({ "total_blocks": total_blocks }).to_string(),
+                )),
+                Err(e) => {
+                    error!("failed to save local file cache state: {e:#}");
+                    render_json_error(&format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        }
+
         // Accept spec in JSON format and request compute configuration. If
         // anything goes wrong after we set the compute status to `ConfigurationPending`
         // and update compute state with new spec, we basically leave compute
@@ -209,6 +359,291 @@ async fn routes(req: Request<Body>, compute: &Arc<ComputeNode>) -> Response<Body
     }
 }
 
+/// Connect to the running compute to manage its local file cache.
+async fn connect_file_cache(
+    compute: &Arc<ComputeNode>,
+) -> Result<FileCacheState, (String, StatusCode)> {
+    let status = compute.get_status();
+    if status != ComputeStatus::Running {
+        let msg = format!("invalid compute status for lfc_size request: {status:?}");
+        return Err((msg, StatusCode::PRECONDITION_FAILED));
+    }
+
+    FileCacheState::new(
+        compute.connstr.as_str(),
+        FileCacheConfig::default(),
+        CancellationToken::new(),
+    )
+    .await
+    .map_err(|e| (format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+async fn handle_lfc_size_get_request(
+    compute: &Arc<ComputeNode>,
+) -> Result<u64, (String, StatusCode)> {
+    let mut file_cache = connect_file_cache(compute).await?;
+    file_cache
+        .get_file_cache_size()
+        .await
+        .map_err(|e| (format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+async fn handle_lfc_size_request(
+    req: Request<Body>,
+    compute: &Arc<ComputeNode>,
+) -> Result<u64, (String, StatusCode)> {
+    let body_bytes = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request = serde_json::from_slice::<LfcSizeRequest>(&body_bytes)
+        .map_err(|e| (e.to_string(), StatusCode::BAD_REQUEST))?;
+
+    let mut file_cache = connect_file_cache(compute).await?;
+
+    // the vm-monitor won't grow the cache beyond this size on upscaling
+    filecache::set_size_cap(request.size_bytes);
+
+    let internal_error = |e: anyhow::Error| (format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR);
+    match request.size_bytes {
+        Some(size_bytes) => {
+            let size_bytes = file_cache
+                .set_file_cache_size(size_bytes)
+                .await
+                .map_err(internal_error)?;
+            // respond once the pages above the new size are evicted
+            file_cache
+                .wait_for_file_cache_size(size_bytes)
+                .await
+                .map_err(internal_error)?;
+            Ok(size_bytes)
+        }
+        // only the cap is removed, the size stays until the next scaling
+        None => file_cache
+            .get_file_cache_size()
+            .await
+            .map_err(internal_error),
+    }
+}
+
+async fn handle_catalog_request(
+    req: Request<Body>,
+    compute: &Arc<ComputeNode>,
+) -> Result<String, (String, StatusCode)> {
+    let status = compute.get_status();
+    if status != ComputeStatus::Running {
+        let msg = format!("invalid compute status for catalog request: {status:?}");
+        return Err((msg, StatusCode::PRECONDITION_FAILED));
+    }
+
+    let route = req.uri().path().to_string();
+    let database = req.uri().query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == "database")
+            .map(|(_, value)| value.into_owned())
+    });
+    let connstr = compute.connstr.to_string();
+
+    task::spawn_blocking(move || {
+        let internal_error =
+            |e: anyhow::Error| (format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR);
+        let json = match route.as_str() {
+            "/catalog/databases" => {
+                serde_json::to_string(&catalog::get_databases(&connstr).map_err(internal_error)?)
+            }
+            "/catalog/roles" => {
+                serde_json::to_string(&catalog::get_roles(&connstr).map_err(internal_error)?)
+            }
+            _ => {
+                let Some(database) = database else {
+                    return Err((
+                        "database parameter is required".to_string(),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                };
+                if !catalog::database_exists(&connstr, &database).map_err(internal_error)? {
+                    return Err((
+                        format!("database {database} does not exist"),
+                        StatusCode::NOT_FOUND,
+                    ));
+                }
+                if route == "/catalog/schemas" {
+                    serde_json::to_string(
+                        &catalog::get_schemas(&connstr, &database).map_err(internal_error)?,
+                    )
+                } else {
+                    serde_json::to_string(
+                        &catalog::get_extensions(&connstr, &database).map_err(internal_error)?,
+                    )
+                }
+            }
+        };
+        Ok(json.unwrap())
+    })
+    .await
+    .unwrap()
+}
+
+async fn handle_promote_request(
+    req: Request<Body>,
+    compute: &Arc<ComputeNode>,
+) -> Result<Lsn, (String, StatusCode)> {
+    let body_bytes = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request = if body_bytes.is_empty() {
+        PromoteRequest::default()
+    } else {
+        serde_json::from_slice::<PromoteRequest>(&body_bytes)
+            .map_err(|e| (e.to_string(), StatusCode::BAD_REQUEST))?
+    };
+
+    {
+        let mut state = compute.state.lock().unwrap();
+        if state.status != ComputeStatus::Running {
+            let msg = format!(
+                "invalid compute status for promote request: {:?}",
+                state.status
+            );
+            return Err((msg, StatusCode::PRECONDITION_FAILED));
+        }
+        let mode = state.pspec.as_ref().map(|pspec| pspec.spec.mode);
+        if mode != Some(ComputeMode::Replica) {
+            let msg = format!("only a replica can be promoted, compute mode is {mode:?}");
+            return Err((msg, StatusCode::PRECONDITION_FAILED));
+        }
+        // don't let a reconfiguration run concurrently
+        state.status = ComputeStatus::Configuration;
+        compute.state_changed.notify_all();
+    }
+
+    let c = compute.clone();
+    task::spawn_blocking(move || {
+        let res = c.promote(request.wait_lsn);
+        c.set_status(ComputeStatus::Running);
+        res.map_err(|e| (format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR))
+    })
+    .await
+    .unwrap()
+}
+
+async fn handle_terminate_request(
+    compute: &Arc<ComputeNode>,
+) -> Result<Option<Lsn>, (String, StatusCode)> {
+    let start = {
+        let mut state = compute.state.lock().unwrap();
+        match state.status {
+            ComputeStatus::Running => {
+                state.status = ComputeStatus::TerminationPending;
+                compute.state_changed.notify_all();
+                true
+            }
+            // a retried request waits for the termination in progress
+            ComputeStatus::TerminationPending | ComputeStatus::Terminated => false,
+            status => {
+                let msg = format!("invalid compute status for termination request: {status:?}");
+                return Err((msg, StatusCode::PRECONDITION_FAILED));
+            }
+        }
+    };
+
+    let c = compute.clone();
+    task::spawn_blocking(move || {
+        if start {
+            if let Err(e) = c.terminate() {
+                c.set_status(ComputeStatus::Running);
+                return Err((format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR));
+            }
+        }
+
+        c.wait_terminated()
+            .map_err(|e| (format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR))
+    })
+    .await
+    .unwrap()
+}
+
+/// How long the /restart API waits for the client connections to close by
+/// default.
+const RESTART_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn handle_restart_request(
+    req: Request<Body>,
+    compute: &Arc<ComputeNode>,
+) -> Result<RestartResponse, (String, StatusCode)> {
+    let body_bytes = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request = if body_bytes.is_empty() {
+        RestartRequest::default()
+    } else {
+        serde_json::from_slice::<RestartRequest>(&body_bytes)
+            .map_err(|e| (e.to_string(), StatusCode::BAD_REQUEST))?
+    };
+    let drain_timeout = request
+        .drain_timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(RESTART_DRAIN_TIMEOUT);
+
+    let spec = {
+        let mut state = compute.state.lock().unwrap();
+        if state.status != ComputeStatus::Running {
+            let msg = format!(
+                "invalid compute status for restart request: {:?}",
+                state.status
+            );
+            return Err((msg, StatusCode::PRECONDITION_FAILED));
+        }
+        // don't let a reconfiguration run concurrently
+        state.status = ComputeStatus::Configuration;
+        compute.state_changed.notify_all();
+        state.pspec.as_ref().expect("spec must be set").spec.clone()
+    };
+
+    let c = compute.clone();
+    task::spawn_blocking(move || {
+        // Nothing is stopped until the configuration is known to be good
+        let prepared = c
+            .check_postgres_conf(&spec)
+            .and_then(|()| c.drain_connections(drain_timeout));
+        let terminated_connections = match prepared {
+            Ok(terminated) => terminated,
+            Err(e) => {
+                c.set_status(ComputeStatus::Running);
+                return Err((format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR));
+            }
+        };
+
+        // The main thread starts Postgres again once it has exited
+        let stopped_at = Instant::now();
+        {
+            let mut state = c.state.lock().unwrap();
+            // measure the startup metrics from here, like for a fresh start
+            state.start_time = Utc::now();
+            state.status = ComputeStatus::RestartPending;
+            c.state_changed.notify_all();
+        }
+        if let Err(e) = c.fast_shutdown() {
+            if let Err(e) = c.accept_connections() {
+                error!("failed to accept new connections again: {e:#}");
+            }
+            c.set_status(ComputeStatus::Running);
+            return Err((format!("{e:#}"), StatusCode::INTERNAL_SERVER_ERROR));
+        }
+
+        let mut state = c.state.lock().unwrap();
+        while state.status == ComputeStatus::RestartPending {
+            state = c.state_changed.wait(state).unwrap();
+        }
+        if state.status != ComputeStatus::Running {
+            let msg = format!(
+                "compute did not restart: {}",
+                state.error.as_deref().unwrap_or("unknown error")
+            );
+            return Err((msg, StatusCode::INTERNAL_SERVER_ERROR));
+        }
+        Ok(RestartResponse {
+            terminated_connections,
+            downtime_ms: stopped_at.elapsed().as_millis() as u64,
+        })
+    })
+    .await
+    .unwrap()
+}
+
 async fn handle_configure_request(
     req: Request<Body>,
     compute: &Arc<ComputeNode>,
@@ -297,9 +732,10 @@ fn render_json_error(e: &str, status: StatusCode) -> Response<Body> {
         .unwrap()
 }
 
-// Main Hyper HTTP server function that runs it and blocks waiting on it forever.
+// Main Hyper HTTP server function that runs it and blocks waiting on it until
+// it is shut down.
 #[tokio::main]
-async fn serve(port: u16, state: Arc<ComputeNode>) {
+async fn serve(port: u16, state: Arc<ComputeNode>, shutdown: ShutdownRequest) {
     // this usually binds to both IPv4 and IPv6 on linux
     // see e.g. https://github.com/rust-lang/rust/pull/34440
     let addr = SocketAddr::new(IpAddr::from(Ipv6Addr::UNSPECIFIED), port);
@@ -328,19 +764,67 @@ async fn serve(port: u16, state: Arc<ComputeNode>) {
 
     info!("starting HTTP server on {}", addr);
 
-    let server = Server::bind(&addr).serve(make_service);
-
-    // Run this server forever
+    // Run this server until compute_ctl asks for a shutdown, if ever, then
+    // answer the requests in flight before returning
+    let ShutdownRequest { request, done } = shutdown;
+    let server = Server::bind(&addr)
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            if request.await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+            info!("shutting down HTTP server");
+        });
     if let Err(e) = server.await {
         error!("server error: {}", e);
     }
+    let _ = done.send(());
+}
+
+/// The server's end of [`HttpServerShutdown`].
+struct ShutdownRequest {
+    request: oneshot::Receiver<()>,
+    done: mpsc::Sender<()>,
+}
+
+/// Handle to shut down the HTTP server, e.g. to answer the /terminate requests
+/// before compute_ctl exits. The server runs forever if it is dropped instead.
+pub struct HttpServerShutdown {
+    request: oneshot::Sender<()>,
+    done: mpsc::Receiver<()>,
 }
 
-/// Launch a separate Hyper HTTP API server thread and return its `JoinHandle`.
-pub fn launch_http_server(port: u16, state: &Arc<ComputeNode>) -> Result<thread::JoinHandle<()>> {
+impl HttpServerShutdown {
+    /// Stop accepting connections and wait for the requests in flight to be
+    /// answered, for up to `timeout`. Returns whether they all were.
+    pub fn shutdown(self, timeout: Duration) -> bool {
+        let _ = self.request.send(());
+        self.done.recv_timeout(timeout).is_ok()
+    }
+}
+
+/// Launch a separate Hyper HTTP API server thread and return its `JoinHandle`,
+/// with the handle to shut it down.
+pub fn launch_http_server(
+    port: u16,
+    state: &Arc<ComputeNode>,
+) -> Result<(thread::JoinHandle<()>, HttpServerShutdown)> {
     let state = Arc::clone(state);
+    let (request_tx, request_rx) = oneshot::channel();
+    let (done_tx, done_rx) = mpsc::channel();
+    let shutdown = ShutdownRequest {
+        request: request_rx,
+        done: done_tx,
+    };
 
-    Ok(thread::Builder::new()
+    let handle = thread::Builder::new()
         .name("http-endpoint".into())
-        .spawn(move || serve(port, state))?)
+        .spawn(move || serve(port, state, shutdown))?;
+    Ok((
+        handle,
+        HttpServerShutdown {
+            request: request_tx,
+            done: done_rx,
+        },
+    ))
 }
@@ -0,0 +1,233 @@
+//! Saving the list of pages in the local file cache, and prewarming the cache
+//! with them when the compute starts again, so that a compute which was
+//! suspended doesn't have to fetch its working set from the pageserver one
+//! query at a time.
+//!
+//! The state is a list of block ranges, saved as JSON to the file passed with
+//! `--lfc-state-path` periodically and on request to the `/lfc_state` API.
+//! The control plane is expected to keep the file across restarts of the
+//! compute.
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use postgres::{Client, NoTls};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use utils::id::TimelineId;
+
+use compute_api::responses::{LfcPrewarmProgress, LfcPrewarmStatus};
+
+use crate::compute::ComputeNode;
+
+/// How often the cache state is saved.
+const LFC_STATE_SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Serializes the writers of the state file.
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Pages of the local file cache, saved to be prewarmed after a restart.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LfcState {
+    /// The cached pages are only meaningful for the same timeline.
+    pub timeline_id: Option<TimelineId>,
+    pub ranges: Vec<BlockRange>,
+}
+
+/// Consecutive blocks of a relation fork.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRange {
+    pub spc_oid: u32,
+    pub db_oid: u32,
+    pub rel_number: u32,
+    pub fork: i16,
+    pub first_block: i64,
+    pub nblocks: i64,
+}
+
+impl LfcState {
+    pub fn total_blocks(&self) -> u64 {
+        self.ranges.iter().map(|r| r.nblocks as u64).sum()
+    }
+}
+
+/// Merge single cached blocks, sorted by relation, fork and block number,
+/// into ranges of consecutive blocks.
+pub fn merge_blocks(blocks: impl IntoIterator<Item = BlockRange>) -> Vec<BlockRange> {
+    let mut ranges: Vec<BlockRange> = Vec::new();
+    for block in blocks {
+        if let Some(last) = ranges.last_mut() {
+            if (last.spc_oid, last.db_oid, last.rel_number, last.fork)
+                == (block.spc_oid, block.db_oid, block.rel_number, block.fork)
+                && last.first_block + last.nblocks == block.first_block
+            {
+                last.nblocks += block.nblocks;
+                continue;
+            }
+        }
+        ranges.push(block);
+    }
+    ranges
+}
+
+fn read_lfc_state(client: &mut Client, timeline_id: Option<TimelineId>) -> Result<LfcState> {
+    let rows = client.query(
+        "SELECT reltablespace, reldatabase, relfilenode, relforknumber, relblocknumber
+         FROM neon.local_cache
+         ORDER BY 1, 2, 3, 4, 5",
+        &[],
+    )?;
+    let ranges = merge_blocks(rows.iter().map(|row| BlockRange {
+        spc_oid: row.get(0),
+        db_oid: row.get(1),
+        rel_number: row.get(2),
+        fork: row.get(3),
+        first_block: row.get(4),
+        nblocks: 1,
+    }));
+    Ok(LfcState {
+        timeline_id,
+        ranges,
+    })
+}
+
+/// Save the current content of the local file cache to `path`.
+pub fn save_lfc_state(compute: &ComputeNode, path: &Path) -> Result<u64> {
+    let timeline_id = compute
+        .state
+        .lock()
+        .unwrap()
+        .pspec
+        .as_ref()
+        .map(|pspec| pspec.timeline_id);
+
+    let mut client = Client::connect(compute.connstr.as_str(), NoTls)?;
+    let state = read_lfc_state(&mut client, timeline_id)?;
+
+    let _guard = SAVE_LOCK.lock().unwrap();
+    // write the file atomically, so that a restart in the middle doesn't
+    // leave a truncated state behind
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec(&state)?)
+        .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)?;
+
+    let total_blocks = state.total_blocks();
+    info!(
+        "saved local file cache state with {total_blocks} blocks in {} ranges",
+        state.ranges.len()
+    );
+    Ok(total_blocks)
+}
+
+fn update_progress(compute: &ComputeNode, f: impl FnOnce(&mut LfcPrewarmProgress)) {
+    let mut state = compute.state.lock().unwrap();
+    if let Some(progress) = state.lfc_prewarm.as_mut() {
+        f(progress);
+    }
+}
+
+/// Fetch the pages of the saved state into the local file cache.
+fn prewarm(compute: &ComputeNode, path: &Path) -> Result<()> {
+    let state: LfcState = match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .with_context(|| format!("failed to parse {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("no saved local file cache state, not prewarming");
+            return Ok(());
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+
+    let timeline_id = compute
+        .state
+        .lock()
+        .unwrap()
+        .pspec
+        .as_ref()
+        .map(|pspec| pspec.timeline_id);
+    if state.timeline_id != timeline_id {
+        warn!(
+            "saved local file cache state is for timeline {:?}, not prewarming",
+            state.timeline_id
+        );
+        return Ok(());
+    }
+
+    let total_blocks = state.total_blocks();
+    info!(
+        "prewarming local file cache with {total_blocks} blocks in {} ranges",
+        state.ranges.len()
+    );
+    compute.state.lock().unwrap().lfc_prewarm = Some(LfcPrewarmProgress {
+        status: LfcPrewarmStatus::Running,
+        total_blocks,
+        prewarmed_blocks: 0,
+        fetched_blocks: 0,
+        error: None,
+    });
+
+    let mut client = Client::connect(compute.connstr.as_str(), NoTls)?;
+    for range in &state.ranges {
+        let row = client.query_one(
+            "SELECT neon.prewarm_local_cache($1, $2, $3, $4, $5, $6)",
+            &[
+                &range.spc_oid,
+                &range.db_oid,
+                &range.rel_number,
+                &range.fork,
+                &range.first_block,
+                &range.nblocks,
+            ],
+        )?;
+        let fetched: i64 = row.get(0);
+        update_progress(compute, |progress| {
+            progress.prewarmed_blocks += range.nblocks as u64;
+            progress.fetched_blocks += fetched as u64;
+        });
+    }
+
+    update_progress(compute, |progress| {
+        progress.status = LfcPrewarmStatus::Completed;
+        info!(
+            "prewarmed local file cache, fetched {} of {} blocks",
+            progress.fetched_blocks, progress.total_blocks
+        );
+    });
+    Ok(())
+}
+
+fn lfc_state_loop(compute: &ComputeNode, path: &Path) {
+    if let Err(e) = prewarm(compute, path) {
+        error!("failed to prewarm local file cache: {e:#}");
+        update_progress(compute, |progress| {
+            progress.status = LfcPrewarmStatus::Failed;
+            progress.error = Some(format!("{e:#}"));
+        });
+    }
+
+    loop {
+        thread::sleep(LFC_STATE_SAVE_INTERVAL);
+        if let Err(e) = save_lfc_state(compute, path) {
+            warn!("failed to save local file cache state: {e:#}");
+        }
+    }
+}
+
+/// Launch a separate thread, which prewarms the local file cache from the saved
+/// state in the background and then keeps saving the state periodically.
+/// Should be called once Postgres is running.
+pub fn launch_lfc_prewarm(compute: &Arc<ComputeNode>) -> Option<thread::JoinHandle<()>> {
+    let path = compute.lfc_state_path.clone()?;
+    let compute = Arc::clone(compute);
+
+    Some(
+        thread::Builder::new()
+            .name("lfc-state".into())
+            .spawn(move || lfc_state_loop(&compute, &path))
+            .expect("cannot launch local file cache state thread"),
+    )
+}
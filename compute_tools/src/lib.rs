@@ -2,6 +2,8 @@
 //! configuration.
 #![deny(unsafe_code)]
 #![deny(clippy::undocumented_unsafe_blocks)]
+pub mod basebackup;
+pub mod catalog;
 pub mod checker;
 pub mod config;
 pub mod configurator;
@@ -9,9 +11,18 @@ pub mod http;
 #[macro_use]
 pub mod logger;
 pub mod compute;
+pub mod extension_cache;
 pub mod extension_server;
+pub mod lfc_prewarm;
+pub mod log_shipper;
+pub mod lsn_lease;
+pub mod metrics;
+pub mod migration;
 pub mod monitor;
 pub mod params;
 pub mod pg_helpers;
+pub mod pg_stats;
 pub mod spec;
+pub mod spec_diff;
+pub mod sql_metrics;
 pub mod sync_sk;
@@ -0,0 +1,269 @@
+//! Shipping of the Postgres logs to an HTTP collector, so that the logs are
+//! delivered to the user even after the compute is gone.
+//!
+//! Log messages are redacted and collected into batches, which are uploaded
+//! once they are large enough or old enough. The queue of messages is bounded:
+//! if the collector can't keep up, new messages are dropped rather than
+//! blocking Postgres on its stderr, and the number of dropped messages is
+//! reported with the next batch. The last, partial batch is uploaded on
+//! [`LogShipperShutdown::shutdown`].
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc as std_mpsc, Arc};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// How many log messages can wait for an upload.
+const QUEUE_CAPACITY: usize = 10_000;
+/// Upload a batch once it's this large...
+const MAX_BATCH_BYTES: usize = 1024 * 1024;
+/// ... or this old.
+const MAX_BATCH_AGE: Duration = Duration::from_secs(5);
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+const UPLOAD_RETRIES: u32 = 5;
+
+/// What's replaced by the redaction rules.
+const REDACTED: &str = "<redacted>";
+
+/// Built-in redaction rules, for the secrets Postgres logs may contain: the
+/// passwords of roles in logged statements and connection strings.
+const DEFAULT_REDACTION_RULES: &[&str] = &[
+    r"(?i)(\bpassword\s+)'(?:[^']|'')*'",
+    r"(?i)(\bpassword\s*=\s*)(?:'(?:[^']|'')*'|\S+)",
+    r"(?i)(\bpostgres(?:ql)?://[^:/@\s]*:)[^@\s]*(@)",
+];
+
+pub struct Redactor {
+    rules: Vec<Regex>,
+}
+
+impl Redactor {
+    /// The built-in rules, plus extra ones. The matches of a rule are replaced,
+    /// except for the text matched by its first two capture groups, if any.
+    pub fn new(extra_rules: &[String]) -> Result<Self> {
+        let mut rules = DEFAULT_REDACTION_RULES
+            .iter()
+            .map(|rule| Regex::new(rule).expect("regex is valid"))
+            .collect::<Vec<_>>();
+        for rule in extra_rules {
+            rules.push(Regex::new(rule).with_context(|| format!("invalid redaction rule {rule}"))?);
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn redact(&self, message: &str) -> String {
+        let mut message = message.to_owned();
+        for rule in &self.rules {
+            // keep the parts matched by the groups, e.g. the `password=` prefix;
+            // the whole match counts as a group too
+            message = match rule.captures_len() {
+                1 => rule.replace_all(&message, REDACTED),
+                2 => rule.replace_all(&message, format!("${{1}}{REDACTED}")),
+                _ => rule.replace_all(&message, format!("${{1}}{REDACTED}${{2}}")),
+            }
+            .into_owned();
+        }
+        message
+    }
+}
+
+/// A batch of log messages, as uploaded to the collector.
+#[derive(Serialize)]
+struct LogBatch<'a> {
+    /// Sequence number of the batch since `compute_ctl` started, to let the
+    /// collector order the batches and detect retried uploads.
+    seq: u64,
+    /// Messages dropped since the previous batch because the queue was full.
+    dropped: u64,
+    messages: &'a [String],
+}
+
+/// Handle for sending Postgres log messages to the shipper.
+#[derive(Clone)]
+pub struct LogShipper {
+    tx: mpsc::Sender<String>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl LogShipper {
+    /// Start shipping logs to `url` in a separate thread, and return the handle
+    /// to ship the last batch when compute_ctl exits.
+    pub fn launch(url: String, redactor: Redactor) -> (Self, LogShipperShutdown) {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (request_tx, request_rx) = oneshot::channel();
+        let (done_tx, done_rx) = std_mpsc::channel();
+
+        let uploader = Uploader {
+            client: reqwest::Client::new(),
+            url,
+            redactor,
+            dropped: dropped.clone(),
+            seq: 0,
+        };
+        std::thread::Builder::new()
+            .name("pg-log-shipper".into())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("failed to build tokio runtime");
+                runtime.block_on(uploader.run(rx, request_rx));
+                let _ = done_tx.send(());
+            })
+            .expect("cannot launch log shipper thread");
+
+        (
+            Self { tx, dropped },
+            LogShipperShutdown {
+                request: request_tx,
+                done: done_rx,
+            },
+        )
+    }
+
+    /// Queue a log message for upload, never blocks.
+    pub fn send(&self, message: String) {
+        if self.tx.try_send(message).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Handle to ship the last batch of log messages before compute_ctl exits. The
+/// senders are kept by the compute, so the queue is never closed and the
+/// messages of the last batch are lost if this is dropped instead.
+pub struct LogShipperShutdown {
+    request: oneshot::Sender<()>,
+    done: std_mpsc::Receiver<()>,
+}
+
+impl LogShipperShutdown {
+    /// Upload the messages queued so far and stop, waiting for up to
+    /// `timeout`. Returns whether the shipper stopped in time.
+    pub fn shutdown(self, timeout: Duration) -> bool {
+        let _ = self.request.send(());
+        self.done.recv_timeout(timeout).is_ok()
+    }
+}
+
+struct Uploader {
+    client: reqwest::Client,
+    url: String,
+    redactor: Redactor,
+    dropped: Arc<AtomicU64>,
+    seq: u64,
+}
+
+impl Uploader {
+    async fn run(mut self, mut rx: mpsc::Receiver<String>, mut shutdown: oneshot::Receiver<()>) {
+        info!("shipping Postgres logs to {}", self.url);
+
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0;
+        let mut deadline = Instant::now() + MAX_BATCH_AGE;
+        let mut shutdown_dropped = false;
+        loop {
+            let closed = tokio::select! {
+                message = rx.recv() => match message {
+                    Some(message) => {
+                        batch_bytes += self.add(&mut batch, &message);
+                        false
+                    }
+                    None => true,
+                },
+                _ = tokio::time::sleep_until(deadline) => false,
+                res = &mut shutdown, if !shutdown_dropped => match res {
+                    Ok(()) => {
+                        info!("shipping the last batch of Postgres logs");
+                        while let Ok(message) = rx.try_recv() {
+                            batch_bytes += self.add(&mut batch, &message);
+                        }
+                        true
+                    }
+                    // nobody will ask, ship until the queue is closed
+                    Err(_) => {
+                        shutdown_dropped = true;
+                        false
+                    }
+                },
+            };
+
+            if closed || batch_bytes >= MAX_BATCH_BYTES || Instant::now() >= deadline {
+                // report the dropped messages even if there are no new ones
+                if !batch.is_empty() || self.dropped.load(Ordering::Relaxed) > 0 {
+                    self.upload(&batch).await;
+                }
+                batch.clear();
+                batch_bytes = 0;
+                deadline = Instant::now() + MAX_BATCH_AGE;
+            }
+            if closed {
+                break;
+            }
+        }
+    }
+
+    /// Add a redacted message to the batch, returns its size.
+    fn add(&self, batch: &mut Vec<String>, message: &str) -> usize {
+        let message = self.redactor.redact(message);
+        let len = message.len();
+        batch.push(message);
+        len
+    }
+
+    async fn upload(&mut self, messages: &[String]) {
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        let body = serde_json::to_vec(&LogBatch {
+            seq: self.seq,
+            dropped,
+            messages,
+        })
+        .expect("serialization can't fail");
+        self.seq += 1;
+
+        let res = utils::backoff::retry(
+            || async {
+                self.client
+                    .post(&self.url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .timeout(UPLOAD_TIMEOUT)
+                    .body(body.clone())
+                    .send()
+                    .await?
+                    .error_for_status()
+            },
+            |e: &reqwest::Error| e.status().is_some_and(|s| s.is_client_error()),
+            1,
+            UPLOAD_RETRIES,
+            "uploading Postgres logs",
+            &CancellationToken::new(),
+        )
+        .await;
+        match res {
+            Some(Ok(_)) => {
+                if dropped > 0 {
+                    warn!(
+                        "dropped {dropped} Postgres log messages, the log collector can't keep up"
+                    );
+                }
+            }
+            Some(Err(e)) => {
+                error!(
+                    "failed to upload {} Postgres log messages: {e}",
+                    messages.len()
+                );
+                // count them in the next batch
+                self.dropped
+                    .fetch_add(dropped + messages.len() as u64, Ordering::Relaxed);
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Renewing the lease of the LSN of a static compute on the pageservers, so
+//! that GC keeps the data at that LSN for as long as the compute runs.
+//!
+//! The pageservers keep the leases in memory only, and they expire, so the
+//! lease is renewed periodically rather than once at start. That also covers
+//! pageserver restarts and tenant migrations, as the pageservers are taken
+//! from the current spec on each renewal.
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use compute_api::spec::ComputeMode;
+use postgres::{NoTls, SimpleQueryMessage};
+use tracing::{info, warn};
+use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+use crate::compute::ComputeNode;
+
+/// The longest we wait between renewals, so that a lease lost by a restarted
+/// pageserver is taken again soon.
+pub const MAX_RENEWAL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait before trying again after a failed renewal.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Launch a thread renewing the LSN lease if the compute is a static one.
+pub fn launch_lsn_lease_renewal(compute: &Arc<ComputeNode>) -> Option<thread::JoinHandle<()>> {
+    let (tenant_id, timeline_id, lsn) = {
+        let state = compute.state.lock().unwrap();
+        let pspec = state.pspec.as_ref().expect("spec must be set");
+        match pspec.spec.mode {
+            ComputeMode::Static(lsn) => (pspec.tenant_id, pspec.timeline_id, lsn),
+            ComputeMode::Primary | ComputeMode::Replica => return None,
+        }
+    };
+    let compute = Arc::clone(compute);
+
+    Some(
+        thread::Builder::new()
+            .name("lsn-lease".into())
+            .spawn(move || lsn_lease_loop(&compute, tenant_id, timeline_id, lsn))
+            .expect("cannot launch LSN lease renewal thread"),
+    )
+}
+
+fn lsn_lease_loop(compute: &ComputeNode, tenant_id: TenantId, timeline_id: TimelineId, lsn: Lsn) {
+    info!("renewing the lease of LSN {lsn} periodically");
+    loop {
+        let interval = match renew_lsn_lease(compute, tenant_id, timeline_id, lsn) {
+            Ok(valid_until) => next_renewal_in(valid_until, SystemTime::now()),
+            Err(e) => {
+                warn!("failed to renew the lease of LSN {lsn}: {e:#}");
+                RETRY_INTERVAL
+            }
+        };
+        thread::sleep(interval);
+    }
+}
+
+/// How long to wait before renewing a lease valid until `valid_until`: half of
+/// the time it's still valid for, so that a failed renewal can be retried.
+pub fn next_renewal_in(valid_until: SystemTime, now: SystemTime) -> Duration {
+    let remaining = valid_until.duration_since(now).unwrap_or_default();
+    (remaining / 2).min(MAX_RENEWAL_INTERVAL)
+}
+
+/// Lease the LSN on the pageservers of all the shards, and return until when
+/// all the leases are valid.
+fn renew_lsn_lease(
+    compute: &ComputeNode,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    lsn: Lsn,
+) -> Result<SystemTime> {
+    let (pageserver_connstr, storage_auth_token) = {
+        let state = compute.state.lock().unwrap();
+        let pspec = state.pspec.as_ref().expect("spec must be set");
+        (
+            pspec.pageserver_connstr.clone(),
+            pspec.storage_auth_token.clone(),
+        )
+    };
+
+    let mut valid_until: Option<SystemTime> = None;
+    for connstr in pageserver_connstr.split(',') {
+        let mut config = postgres::Config::from_str(connstr)?;
+        if let Some(storage_auth_token) = &storage_auth_token {
+            config.password(storage_auth_token);
+        }
+        let mut client = config.connect(NoTls)?;
+
+        let query = format!("lease lsn {tenant_id} {timeline_id} {lsn}");
+        let lease = client
+            .simple_query(&query)?
+            .into_iter()
+            .find_map(|message| match message {
+                SimpleQueryMessage::Row(row) => row.get(0).map(str::to_owned),
+                _ => None,
+            })
+            .with_context(|| format!("no lease returned by {connstr}"))?;
+        let lease = UNIX_EPOCH + Duration::from_millis(lease.parse()?);
+        valid_until = Some(valid_until.map_or(lease, |v| v.min(lease)));
+    }
+    valid_until.context("no pageservers to lease the LSN on")
+}
@@ -0,0 +1,110 @@
+//! Prometheus metrics of `compute_ctl`, served at `/metrics`.
+//!
+//! Most of them mirror the compute state and the startup metrics of
+//! `/metrics.json`, and are only updated from the state when scraped. The
+//! metrics collected with SQL queries are appended to them, see
+//! [`crate::sql_metrics`].
+use metrics::{
+    register_int_gauge, register_int_gauge_vec, Encoder, IntGauge, IntGaugeVec, TextEncoder,
+};
+use once_cell::sync::Lazy;
+
+use compute_api::responses::ComputeStatus;
+
+use crate::compute::ComputeState;
+use crate::sql_metrics;
+
+static STARTUP_PHASE_DURATION: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "compute_ctl_startup_phase_duration_ms",
+        "Time spent in each phase of the last compute startup",
+        &["phase"]
+    )
+    .expect("failed to define a metric")
+});
+
+static BASEBACKUP_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "compute_ctl_basebackup_bytes",
+        "Compressed size of the basebackup received at startup"
+    )
+    .expect("failed to define a metric")
+});
+
+static STATUS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "compute_ctl_status",
+        "Current status of the compute, 1 for the current status and 0 for the others",
+        &["status"]
+    )
+    .expect("failed to define a metric")
+});
+
+static LAST_ACTIVE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "compute_ctl_last_active_timestamp_seconds",
+        "Unix timestamp of the last Postgres activity, 0 if there was none since start"
+    )
+    .expect("failed to define a metric")
+});
+
+/// Number of specs the compute was configured with since start, including the
+/// one it started with.
+pub static SPEC_GENERATION: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "compute_ctl_spec_generation",
+        "Number of specs applied since compute_ctl started"
+    )
+    .expect("failed to define a metric")
+});
+
+const ALL_STATUSES: [(ComputeStatus, &str); 9] = [
+    (ComputeStatus::Empty, "empty"),
+    (ComputeStatus::ConfigurationPending, "configuration_pending"),
+    (ComputeStatus::Init, "init"),
+    (ComputeStatus::Running, "running"),
+    (ComputeStatus::Configuration, "configuration"),
+    (ComputeStatus::Failed, "failed"),
+    (ComputeStatus::TerminationPending, "termination_pending"),
+    (ComputeStatus::RestartPending, "restart_pending"),
+    (ComputeStatus::Terminated, "terminated"),
+];
+
+fn update_from_state(state: &ComputeState) {
+    let m = &state.metrics;
+    for (phase, ms) in [
+        ("wait_for_spec", m.wait_for_spec_ms),
+        ("sync_sk_check", m.sync_sk_check_ms),
+        ("sync_safekeepers", m.sync_safekeepers_ms),
+        ("basebackup", m.basebackup_ms),
+        ("load_extensions", m.load_ext_ms),
+        ("start_postgres", m.start_postgres_ms),
+        ("config", m.config_ms),
+        ("total", m.total_startup_ms),
+    ] {
+        STARTUP_PHASE_DURATION
+            .with_label_values(&[phase])
+            .set(ms as i64);
+    }
+    BASEBACKUP_BYTES.set(m.basebackup_bytes as i64);
+
+    for (status, label) in ALL_STATUSES {
+        STATUS
+            .with_label_values(&[label])
+            .set((state.status == status) as i64);
+    }
+    LAST_ACTIVE.set(state.last_active.map_or(0, |t| t.timestamp()));
+    // make sure it's reported before the first spec is applied
+    Lazy::force(&SPEC_GENERATION);
+}
+
+/// Render the metrics in the Prometheus text format.
+pub fn render(state: &ComputeState) -> anyhow::Result<(String, Vec<u8>)> {
+    update_from_state(state);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&metrics::gather(), &mut buffer)?;
+    buffer.extend_from_slice(sql_metrics::render_collected().as_bytes());
+    Ok((encoder.format_type().to_string(), buffer))
+}
@@ -0,0 +1,156 @@
+//! Migrations of the SQL objects managed by neon: grants to `neon_superuser`,
+//! internal schemas and the like.
+//!
+//! Every migration has a sequential ID and is applied once, in its own
+//! transaction. The ID of the last applied migration is kept in
+//! `neon_migration.migration_id`, and every run of a migration is recorded in
+//! `neon_migration.migration_history`.
+use anyhow::{Context, Result};
+use postgres::Client;
+use tracing::{info, instrument, warn};
+
+use compute_api::spec::MigrationSettings;
+
+pub struct Migration {
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+macro_rules! migration {
+    ($name:literal) => {
+        Migration {
+            name: $name,
+            sql: include_str!(concat!("./migrations/", $name, ".sql")),
+        }
+    };
+}
+
+// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
+// !BE SURE TO ONLY ADD MIGRATIONS TO THE END OF THIS ARRAY. IF YOU DO NOT, VERY VERY BAD THINGS MAY HAPPEN!
+// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
+/// All the migrations, the ID of a migration is its index plus one.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!("0001-neon_superuser_bypass_rls"),
+    migration!("0002-alter_roles"),
+    migration!("0003-grant_pg_create_subscription_to_neon_superuser"),
+    migration!("0004-grant_pg_monitor_to_neon_superuser"),
+];
+
+/// IDs of the migrations to run, given the ID of the last applied one, and
+/// whether each of them is forced.
+pub fn plan(applied: i64, count: usize, settings: &MigrationSettings) -> Vec<(i64, bool)> {
+    if settings.skip {
+        return Vec::new();
+    }
+    let count = count as i64;
+
+    let mut forced: Vec<i64> = settings
+        .force
+        .iter()
+        .copied()
+        .filter(|id| (1..=applied.min(count)).contains(id))
+        .collect();
+    forced.sort_unstable();
+    forced.dedup();
+
+    forced
+        .into_iter()
+        .map(|id| (id, true))
+        .chain((applied + 1..=count).map(|id| (id, false)))
+        .collect()
+}
+
+pub struct MigrationRunner<'m> {
+    client: &'m mut Client,
+    migrations: &'m [Migration],
+}
+
+impl<'m> MigrationRunner<'m> {
+    pub fn new(client: &'m mut Client, migrations: &'m [Migration]) -> Self {
+        Self { client, migrations }
+    }
+
+    fn prepare(&mut self) -> Result<()> {
+        self.client.batch_execute(
+            "CREATE SCHEMA IF NOT EXISTS neon_migration;
+             CREATE TABLE IF NOT EXISTS neon_migration.migration_id (key INT NOT NULL PRIMARY KEY, id bigint NOT NULL DEFAULT 0);
+             INSERT INTO neon_migration.migration_id VALUES (0, 0) ON CONFLICT DO NOTHING;
+             CREATE TABLE IF NOT EXISTS neon_migration.migration_history (
+                 id bigint NOT NULL,
+                 name text NOT NULL,
+                 forced bool NOT NULL,
+                 applied_at timestamptz NOT NULL DEFAULT now()
+             );
+             ALTER SCHEMA neon_migration OWNER TO cloud_admin;
+             REVOKE ALL ON SCHEMA neon_migration FROM PUBLIC;",
+        )?;
+        Ok(())
+    }
+
+    fn applied_id(&mut self) -> Result<i64> {
+        let row = self
+            .client
+            .query_one("SELECT id FROM neon_migration.migration_id", &[])?;
+        Ok(row.get::<&str, i64>("id"))
+    }
+
+    fn run_migration(&mut self, id: i64, forced: bool) -> Result<()> {
+        let migration = &self.migrations[id as usize - 1];
+        info!(
+            "running migration {id} {}:\n{}",
+            migration.name, migration.sql
+        );
+
+        let mut tx = self.client.transaction()?;
+        tx.batch_execute(migration.sql)?;
+        tx.execute(
+            "UPDATE neon_migration.migration_id SET id = GREATEST(id, $1)",
+            &[&id],
+        )?;
+        tx.execute(
+            "INSERT INTO neon_migration.migration_history (id, name, forced) VALUES ($1, $2, $3)",
+            &[&id, &migration.name, &forced],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn run(&mut self, settings: &MigrationSettings) -> Result<usize> {
+        if settings.skip {
+            warn!("skipping migrations as requested by the spec");
+            return Ok(0);
+        }
+
+        self.prepare()?;
+        let applied = self.applied_id()?;
+        if applied > self.migrations.len() as i64 {
+            warn!(
+                "migration {applied} was applied, but this compute_ctl only knows about {}",
+                self.migrations.len()
+            );
+        }
+        for id in &settings.force {
+            if *id < 1 || *id > self.migrations.len() as i64 {
+                warn!("can't force migration {id}, it doesn't exist");
+            }
+        }
+
+        let plan = plan(applied, self.migrations.len(), settings);
+        for (id, forced) in &plan {
+            self.run_migration(*id, *forced)
+                .with_context(|| format!("failed to run migration {id}"))?;
+        }
+        Ok(plan.len())
+    }
+}
+
+/// Apply the pending migrations, and the ones forced by the spec.
+#[instrument(skip_all)]
+pub fn handle_migrations(client: &mut Client, settings: &MigrationSettings) -> Result<()> {
+    info!("handle migrations");
+
+    let count = MigrationRunner::new(client, MIGRATIONS).run(settings)?;
+
+    info!("Ran {} migrations", count);
+    Ok(())
+}
@@ -0,0 +1 @@
+ALTER ROLE neon_superuser BYPASSRLS;
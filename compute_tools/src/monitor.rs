@@ -199,7 +199,7 @@ fn watch_compute_activity(compute: &ComputeNode) {
 }
 
 // Hang on condition variable waiting until the compute status is `Running`.
-fn wait_for_postgres_start(compute: &ComputeNode) {
+pub(crate) fn wait_for_postgres_start(compute: &ComputeNode) {
     let mut state = compute.state.lock().unwrap();
     while state.status != ComputeStatus::Running {
         info!("compute is not running, waiting before monitoring activity");
@@ -20,6 +20,8 @@ use tracing::{debug, error, info, instrument};
 
 use compute_api::spec::{Database, GenericOption, GenericOptions, PgIdent, Role};
 
+use crate::log_shipper::LogShipper;
+
 const POSTGRES_WAIT_TIMEOUT: Duration = Duration::from_millis(60 * 1000); // milliseconds
 
 /// Escape a string for including it in a SQL literal. Wrapping the result
@@ -467,9 +469,12 @@ pub async fn tune_pgbouncer(pgbouncer_config: HashMap<String, String>) -> Result
 }
 
 /// Spawn a thread that will read Postgres logs from `stderr`, join multiline logs
-/// and send them to the logger. In the future we may also want to add context to
-/// these logs.
-pub fn handle_postgres_logs(stderr: std::process::ChildStderr) -> JoinHandle<()> {
+/// and send them to the logger, and to `shipper` if any. In the future we may also
+/// want to add context to these logs.
+pub fn handle_postgres_logs(
+    stderr: std::process::ChildStderr,
+    shipper: Option<LogShipper>,
+) -> JoinHandle<()> {
     std::thread::spawn(move || {
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_all()
@@ -478,7 +483,7 @@ pub fn handle_postgres_logs(stderr: std::process::ChildStderr) -> JoinHandle<()>
 
         let res = runtime.block_on(async move {
             let stderr = tokio::process::ChildStderr::from_std(stderr)?;
-            handle_postgres_logs_async(stderr).await
+            handle_postgres_logs_async(stderr, shipper).await
         });
         if let Err(e) = res {
             tracing::error!("error while processing postgres logs: {}", e);
@@ -490,7 +495,10 @@ pub fn handle_postgres_logs(stderr: std::process::ChildStderr) -> JoinHandle<()>
 /// - next line starts with timestamp
 /// - EOF
 /// - no new lines were written for the last second
-async fn handle_postgres_logs_async(stderr: tokio::process::ChildStderr) -> Result<()> {
+async fn handle_postgres_logs_async(
+    stderr: tokio::process::ChildStderr,
+    shipper: Option<LogShipper>,
+) -> Result<()> {
     let mut lines = tokio::io::BufReader::new(stderr).lines();
     let timeout_duration = Duration::from_millis(100);
     let ts_regex =
@@ -512,6 +520,9 @@ async fn handle_postgres_logs_async(stderr: tokio::process::ChildStderr) -> Resu
             // join multiline message into a single line, separated by unicode Zero Width Space.
             // "PG:" suffix is used to distinguish postgres logs from other logs.
             let combined = format!("PG:{}\n", buf.join("\u{200B}"));
+            if let Some(shipper) = &shipper {
+                shipper.send(buf.join("\n"));
+            }
             buf.clear();
 
             // sync write to stderr to avoid interleaving with other logs
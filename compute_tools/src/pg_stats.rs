@@ -0,0 +1,140 @@
+//! Saving the cumulative statistics of Postgres when the compute is shut down
+//! gracefully, and restoring them on the next start of the endpoint, so that
+//! query statistics and the autovacuum counters survive suspensions.
+//!
+//! On a clean shutdown, Postgres writes the statistics to `pg_stat/` in PGDATA
+//! (`pgstat.stat`, and `pg_stat_statements.stat` if the extension is loaded),
+//! and reads them back at startup. PGDATA is recreated from a basebackup on
+//! every start, possibly on another VM, so we upload these files in a tarball
+//! to the remote storage passed with `--pg-stats-remote-storage`, at
+//! `pg_stats/<tenant_id>/<endpoint_id>.tar`, and download it on the next start.
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use futures::StreamExt;
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utils::id::{TenantId, TimelineId};
+
+/// The first entry of the tarball, describing where the statistics come from.
+const MANIFEST_FILE: &str = "pg_stats.json";
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PgStatsManifest {
+    /// The statistics describe the relations of this timeline only.
+    pub timeline_id: TimelineId,
+}
+
+/// Where the statistics of an endpoint are saved in the remote storage.
+pub fn remote_pg_stats_path(tenant_id: TenantId, endpoint_id: &str) -> Result<RemotePath> {
+    RemotePath::from_string(&format!("pg_stats/{tenant_id}/{endpoint_id}.tar"))
+}
+
+/// Save the statistics files written by Postgres to `pgdata/pg_stat` at
+/// shutdown to `path` in the remote storage. Returns the number of files
+/// saved.
+pub async fn save_pg_stats(
+    pgdata: &Path,
+    storage: &GenericRemoteStorage,
+    path: &RemotePath,
+    timeline_id: TimelineId,
+) -> Result<usize> {
+    let (archive, files) = pack_pg_stats(&pgdata.join("pg_stat"), timeline_id)?;
+    let size = archive.len();
+    let stream = futures::stream::once(futures::future::ready(Ok(Bytes::from(archive))));
+    storage
+        .upload(stream, size, path, None)
+        .await
+        .with_context(|| format!("failed to upload statistics to {path}"))?;
+    Ok(files)
+}
+
+/// Restore the statistics saved to `path` in the remote storage into
+/// `pgdata/pg_stat`, for Postgres to read at startup, if they were saved on
+/// the same timeline. Returns the number of files restored.
+///
+/// The saved statistics are removed once restored, so that they are not
+/// restored again after a crash, after which Postgres discards its
+/// statistics too.
+pub async fn restore_pg_stats(
+    pgdata: &Path,
+    storage: &GenericRemoteStorage,
+    path: &RemotePath,
+    timeline_id: TimelineId,
+) -> Result<usize> {
+    let download = match storage.download(path).await {
+        Ok(download) => download,
+        Err(DownloadError::NotFound) => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("failed to download {path}")),
+    };
+    let mut stream = download.download_stream;
+    let mut archive = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        archive.extend_from_slice(&chunk.with_context(|| format!("failed to download {path}"))?);
+    }
+
+    if let Err(e) = storage.delete(path).await {
+        warn!("failed to remove saved statistics {path}: {e:#}");
+    }
+    unpack_pg_stats(&archive, &pgdata.join("pg_stat"), timeline_id)
+}
+
+/// Build the tarball of the statistics files in `stat_dir`, returning it
+/// with the number of files in it.
+pub fn pack_pg_stats(stat_dir: &Path, timeline_id: TimelineId) -> Result<(Vec<u8>, usize)> {
+    let dir = fs::read_dir(stat_dir).with_context(|| format!("failed to read {stat_dir:?}"))?;
+    let mut files = Vec::new();
+    for entry in dir {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.file_name());
+        }
+    }
+    files.sort();
+
+    let manifest = serde_json::to_vec(&PgStatsManifest { timeline_id })?;
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o600);
+    builder.append_data(&mut header, MANIFEST_FILE, manifest.as_slice())?;
+    for file in &files {
+        builder.append_path_with_name(stat_dir.join(file), file)?;
+    }
+    Ok((builder.into_inner()?, files.len()))
+}
+
+/// Unpack a tarball built by [`pack_pg_stats`] into `stat_dir`, unless it was
+/// built on another timeline. Returns the number of files unpacked.
+pub fn unpack_pg_stats(archive: &[u8], stat_dir: &Path, timeline_id: TimelineId) -> Result<usize> {
+    let mut archive = tar::Archive::new(Cursor::new(archive));
+    let mut entries = archive.entries()?;
+
+    let Some(manifest) = entries.next() else {
+        bail!("saved statistics are empty");
+    };
+    let mut manifest = manifest?;
+    if manifest.path()? != Path::new(MANIFEST_FILE) {
+        bail!("saved statistics don't start with {MANIFEST_FILE}");
+    }
+    let manifest: PgStatsManifest = serde_json::from_reader(&mut manifest)?;
+    if manifest.timeline_id != timeline_id {
+        info!(
+            "not restoring statistics saved on timeline {}",
+            manifest.timeline_id
+        );
+        return Ok(0);
+    }
+
+    fs::create_dir_all(stat_dir)?;
+    let mut restored = 0;
+    for entry in entries {
+        entry?.unpack_in(stat_dir)?;
+        restored += 1;
+    }
+    Ok(restored)
+}
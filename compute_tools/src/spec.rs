@@ -8,7 +8,7 @@ use postgres::{Client, NoTls};
 use reqwest::StatusCode;
 use tracing::{error, info, info_span, instrument, span_enabled, warn, Level};
 
-use crate::config;
+use crate::config::{self, shared_preload_libraries};
 use crate::logger::inlinify;
 use crate::params::PG_HBA_ALL_MD5;
 use crate::pg_helpers::*;
@@ -65,6 +65,7 @@ fn do_control_plane_request(
 
 /// Request spec from the control-plane by compute_id. If `NEON_CONTROL_PLANE_TOKEN`
 /// env variable is set, it will be used for authorization.
+#[instrument(skip_all, fields(%compute_id))]
 pub fn get_spec_from_control_plane(
     base_uri: &str,
     compute_id: &str,
@@ -134,6 +135,35 @@ pub fn update_pg_hba(pgdata_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Rules on top of pg_hba.conf rejecting all new connections, while the
+/// connections are drained for a restart.
+const PG_HBA_REJECT_ALL: &str = "local all all reject # compute_ctl: draining connections
+host all all all reject # compute_ctl: draining connections
+";
+
+/// Reject new connections once the configuration is reloaded. The open ones
+/// are not affected.
+pub fn reject_new_connections(pgdata_path: &Path) -> Result<()> {
+    let pghba_path = pgdata_path.join("pg_hba.conf");
+    let content = std::fs::read_to_string(&pghba_path)?;
+    if !content.starts_with(PG_HBA_REJECT_ALL) {
+        info!("rejecting new connections");
+        std::fs::write(&pghba_path, format!("{PG_HBA_REJECT_ALL}{content}"))?;
+    }
+    Ok(())
+}
+
+/// Undo [`reject_new_connections`].
+pub fn accept_new_connections(pgdata_path: &Path) -> Result<()> {
+    let pghba_path = pgdata_path.join("pg_hba.conf");
+    let content = std::fs::read_to_string(&pghba_path)?;
+    if let Some(original) = content.strip_prefix(PG_HBA_REJECT_ALL) {
+        info!("accepting new connections");
+        std::fs::write(&pghba_path, original)?;
+    }
+    Ok(())
+}
+
 /// Create a standby.signal file
 pub fn add_standby_signal(pgdata_path: &Path) -> Result<()> {
     // XXX: consider making it a part of spec.json
@@ -696,13 +726,14 @@ pub fn handle_grants(
 /// Create required system extensions
 #[instrument(skip_all)]
 pub fn handle_extensions(spec: &ComputeSpec, client: &mut Client) -> Result<()> {
-    if let Some(libs) = spec.cluster.settings.find("shared_preload_libraries") {
-        if libs.contains("pg_stat_statements") {
-            // Create extension only if this compute really needs it
-            let query = "CREATE EXTENSION IF NOT EXISTS pg_stat_statements";
-            info!("creating system extensions with query: {}", query);
-            client.simple_query(query)?;
-        }
+    if shared_preload_libraries(spec)
+        .iter()
+        .any(|lib| lib == "pg_stat_statements")
+    {
+        // Create extension only if this compute really needs it
+        let query = "CREATE EXTENSION IF NOT EXISTS pg_stat_statements";
+        info!("creating system extensions with query: {}", query);
+        client.simple_query(query)?;
     }
 
     Ok(())
@@ -738,92 +769,6 @@ pub fn handle_extension_neon(client: &mut Client) -> Result<()> {
     Ok(())
 }
 
-#[instrument(skip_all)]
-pub fn handle_migrations(client: &mut Client) -> Result<()> {
-    info!("handle migrations");
-
-    // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-    // !BE SURE TO ONLY ADD MIGRATIONS TO THE END OF THIS ARRAY. IF YOU DO NOT, VERY VERY BAD THINGS MAY HAPPEN!
-    // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-
-    let migrations = [
-        "ALTER ROLE neon_superuser BYPASSRLS",
-        r#"
-DO $$
-DECLARE
-    role_name text;
-BEGIN
-    FOR role_name IN SELECT rolname FROM pg_roles WHERE pg_has_role(rolname, 'neon_superuser', 'member')
-    LOOP
-        RAISE NOTICE 'EXECUTING ALTER ROLE % INHERIT', quote_ident(role_name);
-        EXECUTE 'ALTER ROLE ' || quote_ident(role_name) || ' INHERIT';
-    END LOOP;
-
-    FOR role_name IN SELECT rolname FROM pg_roles
-        WHERE
-            NOT pg_has_role(rolname, 'neon_superuser', 'member') AND NOT starts_with(rolname, 'pg_')
-    LOOP
-        RAISE NOTICE 'EXECUTING ALTER ROLE % NOBYPASSRLS', quote_ident(role_name);
-        EXECUTE 'ALTER ROLE ' || quote_ident(role_name) || ' NOBYPASSRLS';
-    END LOOP;
-END $$;
-"#,
-        r#"
-DO $$
-BEGIN
-    IF (SELECT setting::numeric >= 160000 FROM pg_settings WHERE name = 'server_version_num') THEN
-        EXECUTE 'GRANT pg_create_subscription TO neon_superuser';
-    END IF;
-END
-$$;"#,
-        "GRANT pg_monitor TO neon_superuser WITH ADMIN OPTION",
-    ];
-
-    let mut query = "CREATE SCHEMA IF NOT EXISTS neon_migration";
-    client.simple_query(query)?;
-
-    query = "CREATE TABLE IF NOT EXISTS neon_migration.migration_id (key INT NOT NULL PRIMARY KEY, id bigint NOT NULL DEFAULT 0)";
-    client.simple_query(query)?;
-
-    query = "INSERT INTO neon_migration.migration_id VALUES (0, 0) ON CONFLICT DO NOTHING";
-    client.simple_query(query)?;
-
-    query = "ALTER SCHEMA neon_migration OWNER TO cloud_admin";
-    client.simple_query(query)?;
-
-    query = "REVOKE ALL ON SCHEMA neon_migration FROM PUBLIC";
-    client.simple_query(query)?;
-
-    query = "SELECT id FROM neon_migration.migration_id";
-    let row = client.query_one(query, &[])?;
-    let mut current_migration: usize = row.get::<&str, i64>("id") as usize;
-    let starting_migration_id = current_migration;
-
-    query = "BEGIN";
-    client.simple_query(query)?;
-
-    while current_migration < migrations.len() {
-        info!("Running migration:\n{}\n", migrations[current_migration]);
-        client.simple_query(migrations[current_migration])?;
-        current_migration += 1;
-    }
-    let setval = format!(
-        "UPDATE neon_migration.migration_id SET id={}",
-        migrations.len()
-    );
-    client.simple_query(&setval)?;
-
-    query = "COMMIT";
-    client.simple_query(query)?;
-
-    info!(
-        "Ran {} migrations",
-        (migrations.len() - starting_migration_id)
-    );
-
-    Ok(())
-}
-
 /// Connect to the database as superuser and pre-create anon extension
 /// if it is present in shared_preload_libraries
 #[instrument(skip_all)]
@@ -835,109 +780,110 @@ pub fn handle_extension_anon(
 ) -> Result<()> {
     info!("handle extension anon");
 
-    if let Some(libs) = spec.cluster.settings.find("shared_preload_libraries") {
-        if libs.contains("anon") {
-            if !grants_only {
-                // check if extension is already initialized using anon.is_initialized()
-                let query = "SELECT anon.is_initialized()";
-                match db_client.query(query, &[]) {
-                    Ok(rows) => {
-                        if !rows.is_empty() {
-                            let is_initialized: bool = rows[0].get(0);
-                            if is_initialized {
-                                info!("anon extension is already initialized");
-                                return Ok(());
-                            }
+    if shared_preload_libraries(spec)
+        .iter()
+        .any(|lib| lib == "anon")
+    {
+        if !grants_only {
+            // check if extension is already initialized using anon.is_initialized()
+            let query = "SELECT anon.is_initialized()";
+            match db_client.query(query, &[]) {
+                Ok(rows) => {
+                    if !rows.is_empty() {
+                        let is_initialized: bool = rows[0].get(0);
+                        if is_initialized {
+                            info!("anon extension is already initialized");
+                            return Ok(());
                         }
                     }
-                    Err(e) => {
-                        warn!(
-                            "anon extension is_installed check failed with expected error: {}",
-                            e
-                        );
-                    }
-                };
-
-                // Create anon extension if this compute needs it
-                // Users cannot create it themselves, because superuser is required.
-                let mut query = "CREATE EXTENSION IF NOT EXISTS anon CASCADE";
-                info!("creating anon extension with query: {}", query);
-                match db_client.query(query, &[]) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("anon extension creation failed with error: {}", e);
-                        return Ok(());
-                    }
                 }
+                Err(e) => {
+                    warn!(
+                        "anon extension is_installed check failed with expected error: {}",
+                        e
+                    );
+                }
+            };
 
-                // check that extension is installed
-                query = "SELECT extname FROM pg_extension WHERE extname = 'anon'";
-                let rows = db_client.query(query, &[])?;
-                if rows.is_empty() {
-                    error!("anon extension is not installed");
+            // Create anon extension if this compute needs it
+            // Users cannot create it themselves, because superuser is required.
+            let mut query = "CREATE EXTENSION IF NOT EXISTS anon CASCADE";
+            info!("creating anon extension with query: {}", query);
+            match db_client.query(query, &[]) {
+                Ok(_) => {}
+                Err(e) => {
+                    error!("anon extension creation failed with error: {}", e);
                     return Ok(());
                 }
+            }
 
-                // Initialize anon extension
-                // This also requires superuser privileges, so users cannot do it themselves.
-                query = "SELECT anon.init()";
-                match db_client.query(query, &[]) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("anon.init() failed with error: {}", e);
-                        return Ok(());
-                    }
-                }
+            // check that extension is installed
+            query = "SELECT extname FROM pg_extension WHERE extname = 'anon'";
+            let rows = db_client.query(query, &[])?;
+            if rows.is_empty() {
+                error!("anon extension is not installed");
+                return Ok(());
             }
 
-            // check that extension is installed, if not bail early
-            let query = "SELECT extname FROM pg_extension WHERE extname = 'anon'";
+            // Initialize anon extension
+            // This also requires superuser privileges, so users cannot do it themselves.
+            query = "SELECT anon.init()";
             match db_client.query(query, &[]) {
-                Ok(rows) => {
-                    if rows.is_empty() {
-                        error!("anon extension is not installed");
-                        return Ok(());
-                    }
-                }
+                Ok(_) => {}
                 Err(e) => {
-                    error!("anon extension check failed with error: {}", e);
+                    error!("anon.init() failed with error: {}", e);
                     return Ok(());
                 }
-            };
-
-            let query = format!("GRANT ALL ON SCHEMA anon TO {}", db_owner);
-            info!("granting anon extension permissions with query: {}", query);
-            db_client.simple_query(&query)?;
-
-            // Grant permissions to db_owner to use anon extension functions
-            let query = format!("GRANT ALL ON ALL FUNCTIONS IN SCHEMA anon TO {}", db_owner);
-            info!("granting anon extension permissions with query: {}", query);
-            db_client.simple_query(&query)?;
-
-            // This is needed, because some functions are defined as SECURITY DEFINER.
-            // In Postgres SECURITY DEFINER functions are executed with the privileges
-            // of the owner.
-            // In anon extension this it is needed to access some GUCs, which are only accessible to
-            // superuser. But we've patched postgres to allow db_owner to access them as well.
-            // So we need to change owner of these functions to db_owner.
-            let query = format!("
-                SELECT 'ALTER FUNCTION '||nsp.nspname||'.'||p.proname||'('||pg_get_function_identity_arguments(p.oid)||') OWNER TO {};'
-                from pg_proc p
-                join pg_namespace nsp ON p.pronamespace = nsp.oid
-                where nsp.nspname = 'anon';", db_owner);
-
-            info!("change anon extension functions owner to db owner");
-            db_client.simple_query(&query)?;
-
-            //  affects views as well
-            let query = format!("GRANT ALL ON ALL TABLES IN SCHEMA anon TO {}", db_owner);
-            info!("granting anon extension permissions with query: {}", query);
-            db_client.simple_query(&query)?;
-
-            let query = format!("GRANT ALL ON ALL SEQUENCES IN SCHEMA anon TO {}", db_owner);
-            info!("granting anon extension permissions with query: {}", query);
-            db_client.simple_query(&query)?;
+            }
         }
+
+        // check that extension is installed, if not bail early
+        let query = "SELECT extname FROM pg_extension WHERE extname = 'anon'";
+        match db_client.query(query, &[]) {
+            Ok(rows) => {
+                if rows.is_empty() {
+                    error!("anon extension is not installed");
+                    return Ok(());
+                }
+            }
+            Err(e) => {
+                error!("anon extension check failed with error: {}", e);
+                return Ok(());
+            }
+        };
+
+        let query = format!("GRANT ALL ON SCHEMA anon TO {}", db_owner);
+        info!("granting anon extension permissions with query: {}", query);
+        db_client.simple_query(&query)?;
+
+        // Grant permissions to db_owner to use anon extension functions
+        let query = format!("GRANT ALL ON ALL FUNCTIONS IN SCHEMA anon TO {}", db_owner);
+        info!("granting anon extension permissions with query: {}", query);
+        db_client.simple_query(&query)?;
+
+        // This is needed, because some functions are defined as SECURITY DEFINER.
+        // In Postgres SECURITY DEFINER functions are executed with the privileges
+        // of the owner.
+        // In anon extension this it is needed to access some GUCs, which are only accessible to
+        // superuser. But we've patched postgres to allow db_owner to access them as well.
+        // So we need to change owner of these functions to db_owner.
+        let query = format!("
+            SELECT 'ALTER FUNCTION '||nsp.nspname||'.'||p.proname||'('||pg_get_function_identity_arguments(p.oid)||') OWNER TO {};'
+            from pg_proc p
+            join pg_namespace nsp ON p.pronamespace = nsp.oid
+            where nsp.nspname = 'anon';", db_owner);
+
+        info!("change anon extension functions owner to db owner");
+        db_client.simple_query(&query)?;
+
+        //  affects views as well
+        let query = format!("GRANT ALL ON ALL TABLES IN SCHEMA anon TO {}", db_owner);
+        info!("granting anon extension permissions with query: {}", query);
+        db_client.simple_query(&query)?;
+
+        let query = format!("GRANT ALL ON ALL SEQUENCES IN SCHEMA anon TO {}", db_owner);
+        info!("granting anon extension permissions with query: {}", query);
+        db_client.simple_query(&query)?;
     }
 
     Ok(())
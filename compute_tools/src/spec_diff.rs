@@ -0,0 +1,146 @@
+//! Comparison of a new compute spec with the spec the compute is running with,
+//! so that a live reconfiguration only does the work that the changes need.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use compute_api::spec::ComputeSpec;
+
+use crate::config::shared_preload_libraries;
+use crate::pg_helpers::escape_conf_value;
+
+/// What changed between the running spec and the new one.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SpecChanges {
+    /// Names of the Postgres settings whose values changed, including the
+    /// `neon.*` settings which `compute_ctl` derives from the spec.
+    pub settings: Vec<String>,
+    /// Changes which the running Postgres can't pick up at all, e.g. the mode
+    /// of the compute.
+    pub restart: Vec<&'static str>,
+    /// Roles, databases or delta operations changed.
+    pub catalog: bool,
+    /// The set of remote extensions available to the compute changed.
+    pub remote_extensions: bool,
+    /// pgbouncer settings changed.
+    pub pgbouncer: bool,
+}
+
+impl SpecChanges {
+    pub fn new(running: &ComputeSpec, new: &ComputeSpec) -> Self {
+        let mut settings: BTreeSet<String> = BTreeSet::new();
+        let running_settings = spec_settings(running);
+        let new_settings = spec_settings(new);
+        for (name, value) in &running_settings {
+            if new_settings.get(name) != Some(value) {
+                settings.insert(name.clone());
+            }
+        }
+        for name in new_settings.keys() {
+            if !running_settings.contains_key(name) {
+                settings.insert(name.clone());
+            }
+        }
+
+        let mut restart = Vec::new();
+        if running.mode != new.mode {
+            restart.push("mode");
+        }
+        // the token is passed to Postgres in the environment
+        if running.storage_auth_token != new.storage_auth_token {
+            restart.push("storage_auth_token");
+        }
+
+        Self {
+            settings: settings.into_iter().collect(),
+            restart,
+            catalog: !same(&running.cluster.roles, &new.cluster.roles)
+                || !same(&running.cluster.databases, &new.cluster.databases)
+                || !same(&running.delta_operations, &new.delta_operations)
+                || running.features != new.features,
+            remote_extensions: !same(&running.remote_extensions, &new.remote_extensions),
+            pgbouncer: running.pgbouncer_settings != new.pgbouncer_settings,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether `postgresql.conf` has to be rewritten.
+    pub fn config(&self) -> bool {
+        !self.settings.is_empty() || !self.restart.is_empty()
+    }
+}
+
+fn same<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Settings which end up in `postgresql.conf` for the spec, see [`crate::config::write_postgres_conf`].
+fn spec_settings(spec: &ComputeSpec) -> HashMap<String, String> {
+    let mut settings = spec
+        .cluster
+        .postgresql_conf
+        .as_deref()
+        .map(parse_conf)
+        .unwrap_or_default();
+
+    let mut set = |name: &str, value: String| settings.insert(name.to_owned(), value);
+    if let Some(s) = &spec.pageserver_connstring {
+        set("neon.pageserver_connstring", escape_conf_value(s));
+    }
+    if !spec.safekeeper_connstrings.is_empty() {
+        set(
+            "neon.safekeepers",
+            escape_conf_value(&spec.safekeeper_connstrings.join(",")),
+        );
+    }
+    if let Some(s) = &spec.tenant_id {
+        set("neon.tenant_id", escape_conf_value(&s.to_string()));
+    }
+    if let Some(s) = &spec.timeline_id {
+        set("neon.timeline_id", escape_conf_value(&s.to_string()));
+    }
+    for option in spec.cluster.settings.iter().flatten() {
+        let value = option.value.clone().unwrap_or_default();
+        settings.insert(option.name.to_lowercase(), value);
+    }
+    // compare the libraries rather than how they are spelled, including the
+    // declared ones
+    settings.insert(
+        "shared_preload_libraries".to_owned(),
+        shared_preload_libraries(spec).join(","),
+    );
+    settings
+}
+
+/// Parse `name = value` lines of a config file, the last value of a setting wins.
+pub fn parse_conf(conf: &str) -> HashMap<String, String> {
+    let mut settings = HashMap::new();
+    for line in conf.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => line.split_once(char::is_whitespace).unwrap_or((line, "")),
+        };
+        settings.insert(name.trim().to_lowercase(), value.trim().to_owned());
+    }
+    settings
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
@@ -0,0 +1,306 @@
+//! Metrics collected with SQL queries, in the manner of `sql_exporter`.
+//!
+//! The queries are defined in the compute spec, plus a few built-in ones for
+//! the signals the autoscaling relies on, like the local file cache hit rate.
+//! They are run periodically and the last collected samples are served at
+//! `/metrics`, together with the other metrics of `compute_ctl`.
+//!
+//! Note that with the experimental activity monitor, queries in databases
+//! other than `postgres` count as user activity.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use postgres::{Client, Config, NoTls, SimpleQueryMessage};
+use tracing::{info, warn};
+
+use compute_api::spec::SqlMetric;
+
+use crate::compute::ComputeNode;
+use crate::monitor::wait_for_postgres_start;
+
+const COLLECTION_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A query running longer than this is cancelled, so that a slow one doesn't
+/// hold up the collection of the others.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prefix of the metrics of `compute_ctl`, reserved for the built-in ones
+/// here, so that they don't collide with the metrics `sql_exporter` collects
+/// with the same queries.
+const RESERVED_PREFIX: &str = "compute_ctl_";
+
+/// The database the queries run in if they don't specify one.
+const DEFAULT_DATABASE: &str = "postgres";
+
+/// Samples of a metric from the last collection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedMetric {
+    pub name: String,
+    pub help: String,
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+static COLLECTED: Mutex<Vec<CollectedMetric>> = Mutex::new(Vec::new());
+
+fn lfc_metric(name: &str, key: &str, help: &str) -> SqlMetric {
+    SqlMetric {
+        metric_name: format!("{RESERVED_PREFIX}{name}"),
+        help: help.to_string(),
+        database: None,
+        query: format!("SELECT lfc_value FROM neon.neon_lfc_stats WHERE lfc_key = '{key}'"),
+        key_labels: Vec::new(),
+        value_column: "lfc_value".to_string(),
+    }
+}
+
+fn builtin_metrics() -> Vec<SqlMetric> {
+    vec![
+        lfc_metric(
+            "lfc_hits",
+            "file_cache_hits",
+            "Number of reads served from the local file cache",
+        ),
+        lfc_metric(
+            "lfc_misses",
+            "file_cache_misses",
+            "Number of reads which missed the local file cache",
+        ),
+        lfc_metric(
+            "lfc_used",
+            "file_cache_used",
+            "Number of chunks of the local file cache in use",
+        ),
+        lfc_metric(
+            "lfc_writes",
+            "file_cache_writes",
+            "Number of writes to the local file cache",
+        ),
+    ]
+}
+
+/// Whether `name` is a valid Prometheus metric or label name.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Check the names of a metric before collecting it.
+pub fn validate(metric: &SqlMetric) -> Result<()> {
+    if !is_valid_name(&metric.metric_name) {
+        bail!("invalid metric name");
+    }
+    if metric.metric_name.starts_with(RESERVED_PREFIX)
+        && builtin_metrics()
+            .iter()
+            .all(|b| b.metric_name != metric.metric_name)
+    {
+        bail!("metric name prefix {RESERVED_PREFIX} is reserved");
+    }
+    if let Some(label) = metric.key_labels.iter().find(|l| !is_valid_name(l)) {
+        bail!("invalid label name {label}");
+    }
+    Ok(())
+}
+
+/// The metrics to collect: the built-in ones, unless overridden by the spec,
+/// and the ones from the spec.
+pub fn metrics_to_collect(spec_metrics: &[SqlMetric]) -> Vec<SqlMetric> {
+    let mut metrics: Vec<SqlMetric> = builtin_metrics()
+        .into_iter()
+        .filter(|b| spec_metrics.iter().all(|m| m.metric_name != b.metric_name))
+        .collect();
+    metrics.extend(spec_metrics.iter().cloned());
+    metrics
+}
+
+fn run_query(client: &mut Client, metric: &SqlMetric) -> Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for message in client.simple_query(&metric.query)? {
+        let SimpleQueryMessage::Row(row) = message else {
+            continue;
+        };
+        let Some(value) = row.try_get(metric.value_column.as_str())? else {
+            continue;
+        };
+        let value =
+            f64::from_str(value).with_context(|| format!("value {value:?} is not a number"))?;
+
+        let mut labels = Vec::with_capacity(metric.key_labels.len());
+        for label in &metric.key_labels {
+            let label_value = row.try_get(label.as_str())?.unwrap_or_default();
+            labels.push((label.clone(), label_value.to_string()));
+        }
+        samples.push(Sample { labels, value });
+    }
+    Ok(samples)
+}
+
+struct Collector {
+    connstr: String,
+    /// Connections to the databases the queries run in, kept between rounds.
+    clients: HashMap<String, Client>,
+    /// Metrics whose last collection failed, so that a failure is logged once
+    /// rather than every round.
+    failing: HashSet<String>,
+}
+
+impl Collector {
+    fn client(&mut self, database: &str) -> Result<&mut Client> {
+        if self.clients.get(database).map_or(true, |c| c.is_closed()) {
+            let mut conf = Config::from_str(&self.connstr)?;
+            conf.dbname(database);
+            conf.application_name("compute_ctl:sql_metrics");
+            conf.options(&format!(
+                "-c statement_timeout={}",
+                QUERY_TIMEOUT.as_millis()
+            ));
+            self.clients
+                .insert(database.to_string(), conf.connect(NoTls)?);
+        }
+        Ok(self.clients.get_mut(database).unwrap())
+    }
+
+    fn collect_one(&mut self, metric: &SqlMetric) -> Result<Vec<Sample>> {
+        validate(metric)?;
+        let database = metric.database.as_deref().unwrap_or(DEFAULT_DATABASE);
+        let client = self.client(database)?;
+        run_query(client, metric)
+    }
+
+    fn collect(&mut self, metrics: &[SqlMetric]) -> Vec<CollectedMetric> {
+        // group the queries by database, to use one connection at a time
+        let mut by_database: BTreeMap<&str, Vec<&SqlMetric>> = BTreeMap::new();
+        for metric in metrics {
+            let database = metric.database.as_deref().unwrap_or(DEFAULT_DATABASE);
+            by_database.entry(database).or_default().push(metric);
+        }
+        // don't keep connections to databases which aren't queried anymore
+        self.clients
+            .retain(|database, _| by_database.contains_key(database.as_str()));
+
+        let mut collected = Vec::new();
+        for metric in by_database.into_values().flatten() {
+            match self.collect_one(metric) {
+                Ok(samples) => {
+                    if self.failing.remove(&metric.metric_name) {
+                        info!("collected SQL metric {} again", metric.metric_name);
+                    }
+                    // the same metric may be collected in several databases
+                    match collected
+                        .iter_mut()
+                        .find(|c: &&mut CollectedMetric| c.name == metric.metric_name)
+                    {
+                        Some(c) => c.samples.extend(samples),
+                        None => collected.push(CollectedMetric {
+                            name: metric.metric_name.clone(),
+                            help: metric.help.clone(),
+                            samples,
+                        }),
+                    }
+                }
+                Err(e) => {
+                    if self.failing.insert(metric.metric_name.clone()) {
+                        warn!("failed to collect SQL metric {}: {e:#}", metric.metric_name);
+                    }
+                }
+            }
+        }
+        collected
+    }
+}
+
+fn collect_loop(compute: &ComputeNode) {
+    wait_for_postgres_start(compute);
+    info!("starting SQL metrics collection");
+
+    let mut collector = Collector {
+        connstr: compute.connstr.to_string(),
+        clients: HashMap::new(),
+        failing: HashSet::new(),
+    };
+    loop {
+        let spec_metrics = compute
+            .state
+            .lock()
+            .unwrap()
+            .pspec
+            .as_ref()
+            .map(|pspec| pspec.spec.sql_metrics.clone())
+            .unwrap_or_default();
+
+        let collected = collector.collect(&metrics_to_collect(&spec_metrics));
+        *COLLECTED.lock().unwrap() = collected;
+
+        thread::sleep(COLLECTION_INTERVAL);
+    }
+}
+
+/// Launch a separate thread, which collects the SQL metrics periodically once
+/// Postgres is running.
+pub fn launch_sql_metrics_collector(compute: &Arc<ComputeNode>) -> thread::JoinHandle<()> {
+    let compute = Arc::clone(compute);
+
+    thread::Builder::new()
+        .name("sql-metrics".into())
+        .spawn(move || collect_loop(&compute))
+        .expect("cannot launch SQL metrics collector thread")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+fn format_value(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Render the metrics in the Prometheus text format.
+pub fn render(metrics: &[CollectedMetric]) -> String {
+    let mut out = String::new();
+    for metric in metrics {
+        if !metric.help.is_empty() {
+            let help = metric.help.replace('\\', r"\\").replace('\n', r"\n");
+            writeln!(out, "# HELP {} {help}", metric.name).unwrap();
+        }
+        writeln!(out, "# TYPE {} gauge", metric.name).unwrap();
+        for sample in &metric.samples {
+            out.push_str(&metric.name);
+            if !sample.labels.is_empty() {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(name, value)| format!("{name}=\"{}\"", escape_label_value(value)))
+                    .collect();
+                write!(out, "{{{}}}", labels.join(",")).unwrap();
+            }
+            writeln!(out, " {}", format_value(sample.value)).unwrap();
+        }
+    }
+    out
+}
+
+/// Render the metrics from the last collection.
+pub fn render_collected() -> String {
+    render(&COLLECTED.lock().unwrap())
+}
@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod basebackup_tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use sha2::{Digest, Sha256};
+
+    use compute_tools::basebackup::*;
+
+    fn pgdata(name: &str) -> PathBuf {
+        let dir = Path::new("./tests/tmp/basebackup").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(path: &str, content: &[u8]) -> Vec<u8> {
+        let mut header = tar::Header::new_gnu();
+        header.set_path(path).unwrap();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o600);
+        header.set_cksum();
+
+        let mut entry = header.as_bytes().to_vec();
+        entry.extend_from_slice(content);
+        entry.resize(entry.len().next_multiple_of(512), 0);
+        entry
+    }
+
+    /// A tarball like the pageserver sends, and the offset of its second entry.
+    fn make_tarball(checksum: Option<&str>) -> (Vec<u8>, usize) {
+        let mut tarball = entry("PG_VERSION", b"16\n");
+        let second = tarball.len();
+        tarball.extend(entry("global/pg_control", &[7; 1000]));
+        tarball.extend(entry("pg_xact/0000", &[1; 2000]));
+
+        let checksum = match checksum {
+            Some(checksum) => checksum.to_string(),
+            None => hex::encode(Sha256::digest(&tarball)),
+        };
+        tarball.extend(entry(CHECKSUM_FILE, checksum.as_bytes()));
+        (tarball, second)
+    }
+
+    #[test]
+    fn unpack_verified() {
+        let pgdata = pgdata("unpack_verified");
+        let (tarball, _) = make_tarball(None);
+
+        let mut progress = BasebackupProgress::default();
+        unpack(tarball.as_slice(), &mut progress, &pgdata, true).unwrap();
+
+        assert_eq!(fs::read(pgdata.join("PG_VERSION")).unwrap(), b"16\n");
+        assert_eq!(fs::read(pgdata.join("pg_xact/0000")).unwrap(), [1; 2000]);
+        assert!(!pgdata.join(CHECKSUM_FILE).exists());
+    }
+
+    #[test]
+    fn resume() {
+        let pgdata = pgdata("resume");
+        let (tarball, second) = make_tarball(None);
+
+        // the download fails in the middle of the second entry
+        let mut progress = BasebackupProgress::default();
+        let interrupted = &tarball[..second + 700];
+        assert!(unpack(interrupted, &mut progress, &pgdata, true).is_err());
+        assert_eq!(progress.offset(), second as u64);
+
+        // the next attempt starts at the second entry
+        let rest = &tarball[progress.offset() as usize..];
+        unpack(rest, &mut progress, &pgdata, true).unwrap();
+        assert_eq!(
+            fs::read(pgdata.join("global/pg_control")).unwrap(),
+            [7; 1000]
+        );
+
+        // a tarball without the checksum is incomplete
+        let mut progress = BasebackupProgress::default();
+        let without_checksum = &tarball[..tarball.len() - 1024];
+        assert!(unpack(without_checksum, &mut progress, &pgdata, true).is_err());
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let pgdata = pgdata("checksum_mismatch");
+        let (tarball, _) = make_tarball(Some(&hex::encode(Sha256::digest(b"something else"))));
+
+        let mut progress = BasebackupProgress::default();
+        assert!(unpack(tarball.as_slice(), &mut progress, &pgdata, true).is_err());
+        // start from scratch next time
+        assert_eq!(progress.offset(), 0);
+    }
+}
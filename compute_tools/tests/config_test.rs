@@ -5,6 +5,7 @@ mod config_tests {
     use std::io::{Read, Write};
     use std::path::Path;
 
+    use compute_api::spec::ComputeSpec;
     use compute_tools::config::*;
 
     fn write_test_file(path: &Path, content: &str) {
@@ -45,4 +46,53 @@ mod config_tests {
 
         remove_file(path).unwrap();
     }
+
+    #[test]
+    fn test_replica_primary_conninfo() {
+        let mut spec = ComputeSpec {
+            tenant_id: Some("3d1f7595b468230304e0b73cecbcb081".parse().unwrap()),
+            timeline_id: Some("7f2aff2a1042b93a2617f44851638422".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(replica_primary_conninfo(&spec), None);
+
+        spec.safekeeper_connstrings = vec!["sk-1:5454".to_string(), "sk-2:5454".to_string()];
+        assert_eq!(
+            replica_primary_conninfo(&spec).unwrap(),
+            "host=sk-1,sk-2 port=5454,5454 \
+             options='-c timeline_id=7f2aff2a1042b93a2617f44851638422 tenant_id=3d1f7595b468230304e0b73cecbcb081' \
+             application_name=replica replication=true"
+        );
+
+        spec.storage_auth_token = Some("it's".to_string());
+        assert!(replica_primary_conninfo(&spec)
+            .unwrap()
+            .ends_with(" password='it\\'s'"));
+    }
+
+    #[test]
+    fn test_shared_preload_libraries() {
+        let mut spec = ComputeSpec::default();
+        assert_eq!(shared_preload_libraries(&spec), ["neon"]);
+
+        spec.cluster.postgresql_conf =
+            Some("shared_preload_libraries='neon,pg_stat_statements'\n".to_string());
+        spec.preload_libraries = vec!["timescaledb".to_string(), "pg_stat_statements".to_string()];
+        assert_eq!(
+            shared_preload_libraries(&spec),
+            ["neon", "pg_stat_statements", "timescaledb"]
+        );
+
+        spec.cluster.postgresql_conf = Some(
+            "shared_preload_libraries='$libdir/neon,$libdir/pg_stat_statements.so'\n".to_string(),
+        );
+        spec.preload_libraries = vec![
+            "pg_stat_statements".to_string(),
+            "/opt/lib/timescaledb.so".to_string(),
+        ];
+        assert_eq!(
+            shared_preload_libraries(&spec),
+            ["neon", "pg_stat_statements", "/opt/lib/timescaledb.so"]
+        );
+    }
 }
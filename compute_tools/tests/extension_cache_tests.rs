@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod extension_cache_tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::time::Duration;
+
+    use compute_tools::extension_cache::*;
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir = Path::new("./tests/tmp/extension_cache").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn put_get() {
+        let dir = cache_dir("put_get");
+        let cache = ExtensionCache::open(&dir, 1024).unwrap();
+
+        assert_eq!(cache.get("5615610098/v15/extensions/anon.tar.zst"), None);
+        cache
+            .put("5615610098/v15/extensions/anon.tar.zst", b"anon archive")
+            .unwrap();
+        assert_eq!(
+            cache.get("5615610098/v15/extensions/anon.tar.zst").unwrap(),
+            b"anon archive"
+        );
+
+        // archives larger than the whole cache are not cached
+        cache
+            .put("5615610098/v15/extensions/postgis.tar.zst", &[0; 2048])
+            .unwrap();
+        assert!(!cache.contains("5615610098/v15/extensions/postgis.tar.zst"));
+
+        // the cache survives restarts
+        drop(cache);
+        let cache = ExtensionCache::open(&dir, 1024).unwrap();
+        assert_eq!(
+            cache.get("5615610098/v15/extensions/anon.tar.zst").unwrap(),
+            b"anon archive"
+        );
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let dir = cache_dir("lru_eviction");
+        let cache = ExtensionCache::open(&dir, 300).unwrap();
+
+        cache.put("v15/extensions/a.tar.zst", &[1; 100]).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        cache.put("v15/extensions/b.tar.zst", &[2; 100]).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        cache.put("v15/extensions/c.tar.zst", &[3; 100]).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        // a is now used more recently than b
+        cache.get("v15/extensions/a.tar.zst").unwrap();
+        thread::sleep(Duration::from_millis(5));
+
+        cache.put("v15/extensions/d.tar.zst", &[4; 150]).unwrap();
+        assert!(cache.contains("v15/extensions/a.tar.zst"));
+        assert!(!cache.contains("v15/extensions/b.tar.zst"));
+        assert!(!cache.contains("v15/extensions/c.tar.zst"));
+        assert!(cache.contains("v15/extensions/d.tar.zst"));
+        assert_eq!(cache.size(), 250);
+
+        // lowering the limit evicts on the next start
+        drop(cache);
+        let cache = ExtensionCache::open(&dir, 200).unwrap();
+        assert!(!cache.contains("v15/extensions/a.tar.zst"));
+        assert!(cache.contains("v15/extensions/d.tar.zst"));
+        // and only the archives in the index remain on disk
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn corrupted_archive() {
+        let dir = cache_dir("corrupted_archive");
+        let cache = ExtensionCache::open(&dir, 1024).unwrap();
+        cache
+            .put("v15/extensions/anon.tar.zst", b"anon archive")
+            .unwrap();
+
+        for file in fs::read_dir(&dir).unwrap() {
+            let path = file.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "zst") {
+                fs::write(path, b"anon archivf").unwrap();
+            }
+        }
+
+        assert_eq!(cache.get("v15/extensions/anon.tar.zst"), None);
+        assert!(!cache.contains("v15/extensions/anon.tar.zst"));
+        assert_eq!(cache.size(), 0);
+    }
+}
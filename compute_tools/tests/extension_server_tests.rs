@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod extension_server_tests {
+    use std::collections::HashMap;
+
+    use chrono::{DateTime, Duration, Utc};
+
+    use compute_tools::extension_server::*;
+
+    const ARCHIVE: &str = "anon.tar.zst";
+
+    fn hang_timeout() -> Duration {
+        Duration::minutes(5)
+    }
+
+    #[test]
+    fn download_once() {
+        let mut progress = HashMap::new();
+        let now = Utc::now();
+
+        let DownloadClaim::Claimed(started) =
+            claim_download(&mut progress, ARCHIVE, now, hang_timeout())
+        else {
+            panic!("download is not claimed");
+        };
+        // concurrent requests wait for the download
+        assert_eq!(
+            claim_download(&mut progress, ARCHIVE, now, hang_timeout()),
+            DownloadClaim::InProgress
+        );
+
+        finish_download(&mut progress, ARCHIVE, started, true);
+        assert_eq!(
+            claim_download(&mut progress, ARCHIVE, now, hang_timeout()),
+            DownloadClaim::Downloaded
+        );
+    }
+
+    #[test]
+    fn retry_after_failure() {
+        let mut progress = HashMap::new();
+        let now = Utc::now();
+
+        let DownloadClaim::Claimed(started) =
+            claim_download(&mut progress, ARCHIVE, now, hang_timeout())
+        else {
+            panic!("download is not claimed");
+        };
+        finish_download(&mut progress, ARCHIVE, started, false);
+        assert!(matches!(
+            claim_download(&mut progress, ARCHIVE, now, hang_timeout()),
+            DownloadClaim::Claimed(_)
+        ));
+    }
+
+    #[test]
+    fn hung_download_taken_over() {
+        let mut progress: HashMap<String, (DateTime<Utc>, bool)> = HashMap::new();
+        let first = Utc::now();
+
+        let DownloadClaim::Claimed(hung) =
+            claim_download(&mut progress, ARCHIVE, first, hang_timeout())
+        else {
+            panic!("download is not claimed");
+        };
+        let later = first + hang_timeout() + Duration::seconds(1);
+        let DownloadClaim::Claimed(second) =
+            claim_download(&mut progress, ARCHIVE, later, hang_timeout())
+        else {
+            panic!("hung download is not taken over");
+        };
+
+        // the hung request failing doesn't drop the claim of the second one
+        finish_download(&mut progress, ARCHIVE, hung, false);
+        assert_eq!(
+            claim_download(&mut progress, ARCHIVE, later, hang_timeout()),
+            DownloadClaim::InProgress
+        );
+
+        finish_download(&mut progress, ARCHIVE, second, true);
+        assert_eq!(
+            claim_download(&mut progress, ARCHIVE, later, hang_timeout()),
+            DownloadClaim::Downloaded
+        );
+    }
+
+    #[test]
+    fn success_without_claim() {
+        // the claim might have been dropped by a failed request meanwhile, the
+        // successful download is recorded anyway rather than panicking
+        let mut progress = HashMap::new();
+        finish_download(&mut progress, ARCHIVE, Utc::now(), true);
+        assert_eq!(
+            claim_download(&mut progress, ARCHIVE, Utc::now(), hang_timeout()),
+            DownloadClaim::Downloaded
+        );
+    }
+}
@@ -0,0 +1,46 @@
+#[cfg(test)]
+mod lfc_prewarm_tests {
+    use compute_tools::lfc_prewarm::*;
+
+    fn block(rel_number: u32, fork: i16, blkno: i64) -> BlockRange {
+        BlockRange {
+            spc_oid: 1663,
+            db_oid: 5,
+            rel_number,
+            fork,
+            first_block: blkno,
+            nblocks: 1,
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let ranges = merge_blocks([
+            block(16384, 0, 0),
+            block(16384, 0, 1),
+            block(16384, 0, 2),
+            block(16384, 0, 5),
+            block(16384, 1, 6),
+            block(16390, 1, 7),
+        ]);
+        assert_eq!(
+            ranges,
+            [
+                BlockRange {
+                    nblocks: 3,
+                    ..block(16384, 0, 0)
+                },
+                block(16384, 0, 5),
+                block(16384, 1, 6),
+                block(16390, 1, 7),
+            ]
+        );
+
+        let state = LfcState {
+            timeline_id: None,
+            ranges,
+        };
+        assert_eq!(state.total_blocks(), 6);
+        assert!(merge_blocks([]).is_empty());
+    }
+}
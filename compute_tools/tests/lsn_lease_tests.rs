@@ -0,0 +1,23 @@
+#[cfg(test)]
+mod lsn_lease_tests {
+    use std::time::{Duration, SystemTime};
+
+    use compute_tools::lsn_lease::*;
+
+    #[test]
+    fn renewal_interval() {
+        let now = SystemTime::now();
+
+        // halfway through a short lease
+        let valid_until = now + Duration::from_secs(20);
+        assert_eq!(next_renewal_in(valid_until, now), Duration::from_secs(10));
+
+        // but not less often than every MAX_RENEWAL_INTERVAL
+        let valid_until = now + Duration::from_secs(10 * 60);
+        assert_eq!(next_renewal_in(valid_until, now), MAX_RENEWAL_INTERVAL);
+
+        // an expired lease is renewed right away
+        let valid_until = now - Duration::from_secs(1);
+        assert_eq!(next_renewal_in(valid_until, now), Duration::ZERO);
+    }
+}
@@ -0,0 +1,42 @@
+#[cfg(test)]
+mod migration_tests {
+    use compute_api::spec::MigrationSettings;
+    use compute_tools::migration::*;
+
+    #[test]
+    fn plan_pending() {
+        let settings = MigrationSettings::default();
+        assert_eq!(plan(0, 3, &settings), [(1, false), (2, false), (3, false)]);
+        assert_eq!(plan(2, 3, &settings), [(3, false)]);
+        assert!(plan(3, 3, &settings).is_empty());
+        // applied by a newer compute_ctl
+        assert!(plan(5, 3, &settings).is_empty());
+    }
+
+    #[test]
+    fn plan_forced() {
+        let settings = MigrationSettings {
+            skip: false,
+            force: vec![2, 1, 2, 4, 0],
+        };
+        // 4 is not applied yet, so it runs as a regular migration
+        assert_eq!(plan(3, 4, &settings), [(1, true), (2, true), (4, false)]);
+    }
+
+    #[test]
+    fn plan_skip() {
+        let settings = MigrationSettings {
+            skip: true,
+            force: vec![1],
+        };
+        assert!(plan(0, 3, &settings).is_empty());
+    }
+
+    #[test]
+    fn migrations_are_named_by_id() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert!(migration.name.starts_with(&format!("{:04}-", i + 1)));
+            assert!(!migration.sql.trim().is_empty());
+        }
+    }
+}
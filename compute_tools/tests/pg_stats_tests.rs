@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod pg_stats_tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    use remote_storage::{GenericRemoteStorage, RemoteStorageConfig};
+    use toml_edit::Document;
+    use utils::id::{TenantId, TimelineId};
+
+    use compute_tools::pg_stats::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = Path::new("./tests/tmp/pg_stats").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("pgdata/pg_stat")).unwrap();
+        dir
+    }
+
+    fn local_storage(dir: &Path) -> GenericRemoteStorage {
+        let toml = format!("local_path = '{}'", dir.join("remote").display());
+        let toml = toml.parse::<Document>().unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .unwrap();
+        GenericRemoteStorage::from_config(&config).unwrap()
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    fn timeline(id: &str) -> TimelineId {
+        TimelineId::from_str(id).unwrap()
+    }
+
+    fn tenant() -> TenantId {
+        TenantId::from_str("3aa8fcc61f6d357410b7de754b1d9001").unwrap()
+    }
+
+    #[test]
+    fn save_restore() {
+        let dir = test_dir("save_restore");
+        let pgdata = dir.join("pgdata");
+        let storage = local_storage(&dir);
+        let path = remote_pg_stats_path(tenant(), "ep-cool-darkness-123456").unwrap();
+        let timeline_id = timeline("de200bd42b49cc1814412c7e592dd6e9");
+        let rt = runtime();
+
+        fs::write(pgdata.join("pg_stat/pgstat.stat"), [1; 3000]).unwrap();
+        fs::write(pgdata.join("pg_stat/pg_stat_statements.stat"), [2; 100]).unwrap();
+        let saved = rt.block_on(save_pg_stats(&pgdata, &storage, &path, timeline_id));
+        assert_eq!(saved.unwrap(), 2);
+
+        // the next start gets a fresh PGDATA, possibly on another VM
+        fs::remove_dir_all(&pgdata).unwrap();
+        fs::create_dir_all(&pgdata).unwrap();
+        let restored = rt.block_on(restore_pg_stats(&pgdata, &storage, &path, timeline_id));
+        assert_eq!(restored.unwrap(), 2);
+        assert_eq!(
+            fs::read(pgdata.join("pg_stat/pgstat.stat")).unwrap(),
+            [1; 3000]
+        );
+        assert_eq!(
+            fs::read(pgdata.join("pg_stat/pg_stat_statements.stat")).unwrap(),
+            [2; 100]
+        );
+
+        // they are only restored once
+        let restored = rt.block_on(restore_pg_stats(&pgdata, &storage, &path, timeline_id));
+        assert_eq!(restored.unwrap(), 0);
+    }
+
+    #[test]
+    fn other_timeline() {
+        let dir = test_dir("other_timeline");
+        let pgdata = dir.join("pgdata");
+        let storage = local_storage(&dir);
+        let path = remote_pg_stats_path(tenant(), "ep-cool-darkness-123456").unwrap();
+        let rt = runtime();
+
+        fs::write(pgdata.join("pg_stat/pgstat.stat"), [1; 3000]).unwrap();
+        let saved_on = timeline("de200bd42b49cc1814412c7e592dd6e9");
+        rt.block_on(save_pg_stats(&pgdata, &storage, &path, saved_on))
+            .unwrap();
+
+        fs::remove_dir_all(&pgdata).unwrap();
+        fs::create_dir_all(&pgdata).unwrap();
+        let branch = timeline("f5a3b2e8c1d04f6a9b7c8d9e0f1a2b3c");
+        let restored = rt.block_on(restore_pg_stats(&pgdata, &storage, &path, branch));
+        assert_eq!(restored.unwrap(), 0);
+        assert!(!pgdata.join("pg_stat/pgstat.stat").exists());
+    }
+}
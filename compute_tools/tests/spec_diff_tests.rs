@@ -0,0 +1,122 @@
+#[cfg(test)]
+mod spec_diff_tests {
+    use std::fs::File;
+
+    use compute_api::spec::{ComputeMode, ComputeSpec, DeltaOp, GenericOption};
+    use compute_tools::spec_diff::*;
+
+    fn spec() -> ComputeSpec {
+        let file = File::open("../libs/compute_api/tests/cluster_spec.json").unwrap();
+        serde_json::from_reader(file).unwrap()
+    }
+
+    fn set(spec: &mut ComputeSpec, name: &str, value: &str) {
+        let settings = spec.cluster.settings.get_or_insert_with(Vec::new);
+        settings.retain(|option| option.name != name);
+        settings.push(GenericOption {
+            name: name.to_owned(),
+            value: Some(value.to_owned()),
+            vartype: "string".to_owned(),
+        });
+    }
+
+    #[test]
+    fn unchanged() {
+        assert!(SpecChanges::new(&spec(), &spec()).is_empty());
+    }
+
+    #[test]
+    fn settings() {
+        let mut new = spec();
+        set(&mut new, "shared_buffers", "65536");
+        set(&mut new, "Work_Mem", "64MB");
+        new.cluster.postgresql_conf = Some("max_connections = 200 # more\n".to_owned());
+
+        let changes = SpecChanges::new(&spec(), &new);
+        assert_eq!(
+            changes.settings,
+            ["max_connections", "shared_buffers", "work_mem"]
+        );
+        assert!(changes.config());
+        assert!(!changes.catalog);
+        assert!(changes.restart.is_empty());
+    }
+
+    #[test]
+    fn catalog() {
+        let mut new = spec();
+        new.cluster.roles.pop();
+        assert!(SpecChanges::new(&spec(), &new).catalog);
+
+        let mut new = spec();
+        new.delta_operations = Some(vec![DeltaOp {
+            action: "delete_db".to_owned(),
+            name: "zen".to_owned(),
+            new_name: None,
+        }]);
+        let changes = SpecChanges::new(&spec(), &new);
+        assert!(changes.catalog);
+        assert!(!changes.config());
+    }
+
+    #[test]
+    fn pageservers() {
+        let mut new = spec();
+        set(
+            &mut new,
+            "neon.pageserver_connstring",
+            "postgresql://ps1:6400,postgresql://ps2:6400",
+        );
+
+        // applied online, the neon extension reconnects to the new pageservers
+        let changes = SpecChanges::new(&spec(), &new);
+        assert_eq!(changes.settings, ["neon.pageserver_connstring"]);
+        assert!(changes.restart.is_empty());
+    }
+
+    #[test]
+    fn restart() {
+        let mut new = spec();
+        new.mode = ComputeMode::Replica;
+        new.storage_auth_token = Some("token".to_owned());
+
+        let changes = SpecChanges::new(&spec(), &new);
+        assert_eq!(changes.restart, ["mode", "storage_auth_token"]);
+        assert!(changes.config());
+    }
+
+    #[test]
+    fn preload_libraries() {
+        let mut new = spec();
+        new.preload_libraries = vec!["pg_stat_statements".to_owned()];
+        let changes = SpecChanges::new(&spec(), &new);
+        assert_eq!(changes.settings, ["shared_preload_libraries"]);
+        // the restart is detected from the context of the setting
+        assert!(changes.restart.is_empty());
+
+        // declaring the libraries the settings already have changes nothing,
+        // however they are spelled
+        let mut running = spec();
+        set(
+            &mut running,
+            "shared_preload_libraries",
+            "neon,$libdir/pg_stat_statements.so",
+        );
+        assert!(SpecChanges::new(&running, &new).is_empty());
+    }
+
+    #[test]
+    fn conf_parsing() {
+        let conf = parse_conf(
+            "# comment\n\
+             shared_buffers = 128MB   # min 128kB\n\
+             search_path = '\"$user\", public # not a comment'\n\
+             listen_addresses '*'\n\
+             shared_buffers = 1GB\n",
+        );
+        assert_eq!(conf.len(), 3);
+        assert_eq!(conf["shared_buffers"], "1GB");
+        assert_eq!(conf["search_path"], "'\"$user\", public # not a comment'");
+        assert_eq!(conf["listen_addresses"], "'*'");
+    }
+}
@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod sql_metrics_tests {
+    use compute_api::spec::SqlMetric;
+    use compute_tools::sql_metrics::*;
+
+    #[test]
+    fn valid_names() {
+        assert!(is_valid_name("lfc_hits"));
+        assert!(is_valid_name("_pg:connections_total"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("1st"));
+        assert!(!is_valid_name("lfc-hits"));
+    }
+
+    #[test]
+    fn reserved_prefix() {
+        let mut metric = SqlMetric {
+            metric_name: "compute_ctl_status".to_string(),
+            help: String::new(),
+            database: None,
+            query: "SELECT 1 AS value".to_string(),
+            key_labels: Vec::new(),
+            value_column: "value".to_string(),
+        };
+        assert!(validate(&metric).is_err());
+
+        // unless it overrides a built-in metric
+        metric.metric_name = "compute_ctl_lfc_hits".to_string();
+        assert!(validate(&metric).is_ok());
+        metric.metric_name = "lfc_hits".to_string();
+        assert!(validate(&metric).is_ok());
+    }
+
+    #[test]
+    fn spec_overrides_builtin() {
+        let metric = SqlMetric {
+            metric_name: "compute_ctl_lfc_hits".to_string(),
+            help: String::new(),
+            database: None,
+            query: "SELECT 1 AS value".to_string(),
+            key_labels: Vec::new(),
+            value_column: "value".to_string(),
+        };
+        let metrics = metrics_to_collect(&[metric.clone()]);
+        let hits: Vec<_> = metrics
+            .iter()
+            .filter(|m| m.metric_name == "compute_ctl_lfc_hits")
+            .collect();
+        assert_eq!(hits, [&metric]);
+        assert!(metrics
+            .iter()
+            .any(|m| m.metric_name == "compute_ctl_lfc_misses"));
+    }
+
+    #[test]
+    fn render_text_format() {
+        let metrics = [
+            CollectedMetric {
+                name: "lfc_hits".to_string(),
+                help: "Number of hits".to_string(),
+                samples: vec![Sample {
+                    labels: Vec::new(),
+                    value: 42.0,
+                }],
+            },
+            CollectedMetric {
+                name: "db_size_bytes".to_string(),
+                help: String::new(),
+                samples: vec![
+                    Sample {
+                        labels: vec![("datname".to_string(), "my \"db\"".to_string())],
+                        value: 1.5,
+                    },
+                    Sample {
+                        labels: vec![("datname".to_string(), "postgres".to_string())],
+                        value: f64::INFINITY,
+                    },
+                ],
+            },
+        ];
+        assert_eq!(
+            render(&metrics),
+            "# HELP lfc_hits Number of hits\n\
+             # TYPE lfc_hits gauge\n\
+             lfc_hits 42\n\
+             # TYPE db_size_bytes gauge\n\
+             db_size_bytes{datname=\"my \\\"db\\\"\"} 1.5\n\
+             db_size_bytes{datname=\"postgres\"} +Inf\n"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod terminate_tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Condvar, Mutex, RwLock};
+    use std::thread;
+
+    use compute_api::responses::ComputeStatus;
+    use utils::lsn::Lsn;
+
+    use compute_tools::compute::*;
+
+    fn compute(status: ComputeStatus) -> Arc<ComputeNode> {
+        let mut state = ComputeState::new();
+        state.status = status;
+        Arc::new(ComputeNode {
+            connstr: url::Url::parse("postgres://cloud_admin@localhost:5432/postgres").unwrap(),
+            pgdata: String::new(),
+            pgbin: String::new(),
+            pgversion: String::new(),
+            live_config_allowed: false,
+            state: Mutex::new(state),
+            state_changed: Condvar::new(),
+            ext_remote_storage: None,
+            ext_download_progress: RwLock::new(HashMap::new()),
+            ext_cache: None,
+            build_tag: String::new(),
+            lfc_state_path: None,
+            pg_stats_storage: None,
+            log_shipper: None,
+        })
+    }
+
+    #[test]
+    fn waiter_gets_the_lsn() {
+        let compute = compute(ComputeStatus::TerminationPending);
+        let waiter = {
+            let compute = Arc::clone(&compute);
+            thread::spawn(move || compute.wait_terminated())
+        };
+
+        assert!(compute.finish_termination(&Ok(Some(Lsn(0x16B9188)))));
+        assert_eq!(waiter.join().unwrap().unwrap(), Some(Lsn(0x16B9188)));
+        assert_eq!(compute.get_status(), ComputeStatus::Terminated);
+
+        // a retried request gets the same LSN
+        assert_eq!(compute.wait_terminated().unwrap(), Some(Lsn(0x16B9188)));
+    }
+
+    #[test]
+    fn waiter_gets_the_sync_error() {
+        let compute = compute(ComputeStatus::TerminationPending);
+        let waiter = {
+            let compute = Arc::clone(&compute);
+            thread::spawn(move || compute.wait_terminated())
+        };
+
+        let sync_result = Err(anyhow::anyhow!("sync-safekeepers exited with 1"));
+        assert!(compute.finish_termination(&sync_result));
+        let err = waiter.join().unwrap().unwrap_err();
+        assert!(format!("{err:#}").contains("sync-safekeepers exited with 1"));
+        assert_eq!(compute.get_status(), ComputeStatus::Failed);
+    }
+
+    #[test]
+    fn not_terminating() {
+        // Postgres exited on its own, there is no one to report to
+        let compute = compute(ComputeStatus::Running);
+        assert!(!compute.finish_termination(&Ok(Some(Lsn(0x16B9188)))));
+        assert_eq!(compute.get_status(), ComputeStatus::Running);
+    }
+}
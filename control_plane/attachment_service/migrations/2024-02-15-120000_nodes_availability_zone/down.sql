@@ -0,0 +1 @@
+ALTER TABLE nodes DROP COLUMN availability_zone_id;
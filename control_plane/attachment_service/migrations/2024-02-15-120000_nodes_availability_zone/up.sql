@@ -0,0 +1 @@
+ALTER TABLE nodes ADD COLUMN availability_zone_id VARCHAR;
@@ -12,7 +12,7 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use utils::auth::SwappableJwtAuth;
 use utils::http::endpoint::{auth_middleware, request_span};
-use utils::http::request::parse_request_param;
+use utils::http::request::{parse_query_param, parse_request_param};
 use utils::id::{TenantId, TimelineId};
 
 use utils::{
@@ -28,8 +28,8 @@ use utils::{
 use pageserver_api::control_api::{ReAttachRequest, ValidateRequest};
 
 use control_plane::attachment_service::{
-    AttachHookRequest, InspectRequest, NodeConfigureRequest, NodeRegisterRequest,
-    TenantShardMigrateRequest,
+    AttachHookRequest, InspectRequest, NodeConfigureRequest, NodeDecommissionMode,
+    NodeRegisterRequest, TenantShardMigrateRequest,
 };
 
 /// State available to HTTP request handlers
@@ -296,7 +296,49 @@ async fn handle_node_configure(mut req: Request<Body>) -> Result<Response<Body>,
     }
     let state = get_state(&req);
 
-    json_response(StatusCode::OK, state.service.node_configure(config_req)?)
+    json_response(
+        StatusCode::OK,
+        state.service.node_configure(config_req).await?,
+    )
+}
+
+async fn handle_node_drain(
+    service: Arc<Service>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let node_id: NodeId = parse_request_param(&req, "node_id")?;
+    json_response(StatusCode::OK, service.node_drain(node_id).await?)
+}
+
+async fn handle_node_fill(
+    service: Arc<Service>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let node_id: NodeId = parse_request_param(&req, "node_id")?;
+    json_response(StatusCode::OK, service.node_fill(node_id).await?)
+}
+
+async fn handle_node_decommission(
+    service: Arc<Service>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let node_id: NodeId = parse_request_param(&req, "node_id")?;
+    let mode = parse_query_param(&req, "mode")?.unwrap_or(NodeDecommissionMode::Graceful);
+    json_response(
+        StatusCode::OK,
+        service.node_decommission(node_id, mode).await?,
+    )
+}
+
+async fn handle_service_metrics(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let state = get_state(&req);
+    json_response(StatusCode::OK, state.service.service_metrics())
+}
+
+async fn handle_node_operation(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let node_id: NodeId = parse_request_param(&req, "node_id")?;
+    let state = get_state(&req);
+    json_response(StatusCode::OK, state.service.node_operation(node_id)?)
 }
 
 async fn handle_tenant_shard_split(
@@ -318,14 +360,45 @@ async fn handle_tenant_shard_migrate(
 ) -> Result<Response<Body>, ApiError> {
     let tenant_shard_id: TenantShardId = parse_request_param(&req, "tenant_shard_id")?;
     let migrate_req = json_request::<TenantShardMigrateRequest>(&mut req).await?;
+    match migrate_req.warm_threshold {
+        Some(warm_threshold) => json_response(
+            StatusCode::OK,
+            service
+                .tenant_shard_migrate_warm(tenant_shard_id, migrate_req.node_id, warm_threshold)
+                .await?,
+        ),
+        None => json_response(
+            StatusCode::OK,
+            service
+                .tenant_shard_migrate(tenant_shard_id, migrate_req)
+                .await?,
+        ),
+    }
+}
+
+async fn handle_tenant_shard_migration(
+    service: Arc<Service>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&req, "tenant_shard_id")?;
     json_response(
         StatusCode::OK,
-        service
-            .tenant_shard_migrate(tenant_shard_id, migrate_req)
-            .await?,
+        service.tenant_shard_migration(tenant_shard_id)?,
     )
 }
 
+async fn handle_optimizer_plan(
+    service: Arc<Service>,
+    _req: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    json_response(StatusCode::OK, service.optimizer_plan(false).await?)
+}
+
+async fn handle_failover_events(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let state = get_state(&req);
+    json_response(StatusCode::OK, state.service.failover_events())
+}
+
 async fn handle_tenant_drop(req: Request<Body>) -> Result<Response<Body>, ApiError> {
     let tenant_id: TenantId = parse_request_param(&req, "tenant_id")?;
     let state = get_state(&req);
@@ -429,16 +502,44 @@ pub fn make_router(
             request_span(r, handle_node_register)
         })
         .get("/control/v1/node", |r| request_span(r, handle_node_list))
+        .get("/control/v1/failover_events", |r| {
+            request_span(r, handle_failover_events)
+        })
         .put("/control/v1/node/:node_id/config", |r| {
             request_span(r, handle_node_configure)
         })
+        // Drain a node ahead of its restart, and fill it back afterwards
+        .put("/control/v1/node/:node_id/drain", |r| {
+            tenant_service_handler(r, handle_node_drain)
+        })
+        .put("/control/v1/node/:node_id/fill", |r| {
+            tenant_service_handler(r, handle_node_fill)
+        })
+        // Remove a node for good, after migrating its shards elsewhere unless it is gone already
+        .delete("/control/v1/node/:node_id", |r| {
+            tenant_service_handler(r, handle_node_decommission)
+        })
+        .get("/control/v1/node/:node_id/operation", |r| {
+            request_span(r, handle_node_operation)
+        })
         // Tenant Shard operations
         .put("/control/v1/tenant/:tenant_shard_id/migrate", |r| {
             tenant_service_handler(r, handle_tenant_shard_migrate)
         })
+        .get("/control/v1/tenant/:tenant_shard_id/migrate", |r| {
+            tenant_service_handler(r, handle_tenant_shard_migration)
+        })
         .put("/control/v1/tenant/:tenant_id/shard_split", |r| {
             tenant_service_handler(r, handle_tenant_shard_split)
         })
+        // The metrics of the scheduling and reconciliation, as JSON
+        .get("/control/v1/metrics", |r| {
+            request_span(r, handle_service_metrics)
+        })
+        // Dry run of the optimizer: the migrations it would start now
+        .get("/control/v1/optimizer/plan", |r| {
+            tenant_service_handler(r, handle_optimizer_plan)
+        })
         // Tenant operations
         // The ^/v1/ endpoints act as a "Virtual Pageserver", enabling shard-naive clients to call into
         // this service to manage tenants that actually consist of many tenant shards, as if they are a single entity.
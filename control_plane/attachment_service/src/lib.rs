@@ -3,7 +3,9 @@ use utils::seqwait::MonotonicCounter;
 
 mod compute_hook;
 pub mod http;
+mod metrics;
 mod node;
+mod optimizer;
 pub mod persistence;
 mod reconciler;
 mod scheduler;
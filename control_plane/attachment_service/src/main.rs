@@ -58,6 +58,29 @@ struct Cli {
     /// URL to connect to postgres, like postgresql://localhost:1234/attachment_service
     #[arg(long)]
     database_url: Option<String>,
+
+    /// How often to migrate shards from the most loaded pageservers to the least loaded
+    /// ones, like `5m`. If not set, shards are only migrated on request.
+    #[arg(long)]
+    optimizer_interval: Option<humantime::Duration>,
+
+    /// Maximum number of shards migrated by the optimizer in each interval
+    #[arg(long, default_value_t = 1)]
+    optimizer_max_migrations: usize,
+
+    /// Imbalance between the loads of pageservers, as a fraction of the load of a pageserver
+    /// if all were even, that the optimizer tolerates
+    #[arg(long, default_value_t = 0.2)]
+    optimizer_imbalance_threshold: f64,
+
+    /// How often to heartbeat the pageservers
+    #[arg(long, default_value = "5s")]
+    heartbeat_interval: humantime::Duration,
+
+    /// Number of heartbeats a pageserver may miss in a row before it is marked offline and its
+    /// shards are attached elsewhere.  Zero disables this.
+    #[arg(long, default_value_t = 6)]
+    heartbeat_failure_threshold: u32,
 }
 
 /// Secrets may either be provided on the command line (for testing), or loaded from AWS SecretManager: this
@@ -220,6 +243,11 @@ async fn async_main() -> anyhow::Result<()> {
         jwt_token: secrets.jwt_token,
         control_plane_jwt_token: secrets.control_plane_jwt_token,
         compute_hook_url: args.compute_hook_url,
+        optimizer_interval: args.optimizer_interval.map(Into::into),
+        optimizer_max_migrations: args.optimizer_max_migrations,
+        optimizer_imbalance_threshold: args.optimizer_imbalance_threshold,
+        heartbeat_interval: args.heartbeat_interval.into(),
+        heartbeat_failure_threshold: args.heartbeat_failure_threshold,
     };
 
     // After loading secrets & config, but before starting anything else, apply database migrations
@@ -0,0 +1,131 @@
+use control_plane::attachment_service::NodeOperationKind;
+use metrics::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use once_cell::sync::Lazy;
+
+pub(crate) static SCHEDULING_VIOLATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "storage_controller_schedule_violations_total",
+        "Number of shard locations scheduled without meeting an anti-affinity constraint, by constraint",
+        &["constraint"]
+    )
+    .expect("Failed to register storage_controller_schedule_violations_total counter")
+});
+
+pub(crate) static SCHEDULE_DECISIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "storage_controller_schedule_decisions_total",
+        "Number of shard locations placed on a node, by reason",
+        &["reason"]
+    )
+    .expect("Failed to register storage_controller_schedule_decisions_total counter")
+});
+
+pub(crate) static RECONCILES_IN_PROGRESS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "storage_controller_reconciles_in_progress",
+        "Number of reconcile tasks spawned and not finished yet, including those waiting for the previous reconcile of their shard"
+    )
+    .expect("Failed to register storage_controller_reconciles_in_progress gauge")
+});
+
+pub(crate) static RECONCILE_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "storage_controller_reconcile_seconds",
+        "Time spent reconciling a shard, by result",
+        &["result"],
+        vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0],
+    )
+    .expect("Failed to register storage_controller_reconcile_seconds histogram")
+});
+
+pub(crate) static NODE_AVAILABLE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "storage_controller_node_available",
+        "Whether a pageserver is considered available, from its heartbeats",
+        &["node_id"]
+    )
+    .expect("Failed to register storage_controller_node_available gauge")
+});
+
+pub(crate) static NODE_MISSED_HEARTBEATS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "storage_controller_node_missed_heartbeats",
+        "Number of heartbeats a pageserver missed in a row",
+        &["node_id"]
+    )
+    .expect("Failed to register storage_controller_node_missed_heartbeats gauge")
+});
+
+pub(crate) static PENDING_MIGRATIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "storage_controller_pending_migrations",
+        "Number of shard migrations planned and not done yet, by the operation which planned them",
+        &["operation"]
+    )
+    .expect("Failed to register storage_controller_pending_migrations gauge")
+});
+
+/// Why a location of a shard was placed on a node.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ScheduleReason {
+    /// The scheduler picked the attached location, e.g. of a new shard or after a failover.
+    Attach,
+    /// The scheduler picked a secondary location.
+    Secondary,
+    /// A migration requested through the API.
+    Api,
+    Optimizer,
+    Drain,
+    Fill,
+    Decommission,
+    WarmMigration,
+}
+
+impl ScheduleReason {
+    pub(crate) const ALL: [ScheduleReason; 8] = [
+        Self::Attach,
+        Self::Secondary,
+        Self::Api,
+        Self::Optimizer,
+        Self::Drain,
+        Self::Fill,
+        Self::Decommission,
+        Self::WarmMigration,
+    ];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Attach => "attach",
+            Self::Secondary => "secondary",
+            Self::Api => "api",
+            Self::Optimizer => "optimizer",
+            Self::Drain => "drain",
+            Self::Fill => "fill",
+            Self::Decommission => "decommission",
+            Self::WarmMigration => "warm_migration",
+        }
+    }
+
+    pub(crate) fn record(&self) {
+        SCHEDULE_DECISIONS.with_label_values(&[self.as_str()]).inc();
+    }
+}
+
+impl From<NodeOperationKind> for ScheduleReason {
+    fn from(kind: NodeOperationKind) -> Self {
+        match kind {
+            NodeOperationKind::Drain => Self::Drain,
+            NodeOperationKind::Fill => Self::Fill,
+            NodeOperationKind::Decommission => Self::Decommission,
+        }
+    }
+}
+
+/// Forget the heartbeat status of a node which was removed.
+pub(crate) fn remove_node_metrics(node_id: &str) {
+    let _ = NODE_AVAILABLE.remove_label_values(&[node_id]);
+    let _ = NODE_MISSED_HEARTBEATS.remove_label_values(&[node_id]);
+}
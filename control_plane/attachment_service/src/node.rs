@@ -15,6 +15,8 @@ pub(crate) struct Node {
 
     pub(crate) listen_pg_addr: String,
     pub(crate) listen_pg_port: u16,
+
+    pub(crate) availability_zone_id: Option<String>,
 }
 
 impl Node {
@@ -45,6 +47,7 @@ impl Node {
             listen_http_port: self.listen_http_port as i32,
             listen_pg_addr: self.listen_pg_addr.clone(),
             listen_pg_port: self.listen_pg_port as i32,
+            availability_zone_id: self.availability_zone_id.clone(),
         }
     }
 }
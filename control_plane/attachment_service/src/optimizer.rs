@@ -0,0 +1,223 @@
+//! Balancing of the shards between pageservers.
+//!
+//! The load of a pageserver is the mean of its shares of the shards, of the
+//! resident layers, and of the GetPage requests of all the pageservers. The
+//! optimizer plans migrations of shards from the most loaded pageserver to the
+//! least loaded one, picking the shard which evens them out best, until their
+//! loads are within a threshold of each other.
+use std::collections::{HashMap, HashSet};
+
+use control_plane::attachment_service::{NodeLoad, PlannedMigration};
+use pageserver_api::shard::TenantShardId;
+use utils::id::NodeId;
+
+pub(crate) struct NodeUtilization {
+    pub(crate) node_id: NodeId,
+    /// Whether shards may be migrated from and to the node.
+    pub(crate) may_schedule: bool,
+    pub(crate) shards: Vec<ShardLoad>,
+}
+
+pub(crate) struct ShardLoad {
+    pub(crate) tenant_shard_id: TenantShardId,
+    pub(crate) resident_size: u64,
+    /// GetPage requests per second.
+    pub(crate) getpage_rate: f64,
+    /// Whether the shard may be migrated: it is attached where we intend it
+    /// to be, and not being reconciled.
+    pub(crate) movable: bool,
+}
+
+/// Plan at most `max_migrations` migrations, while the difference between
+/// the loads of the most and least loaded nodes is over `imbalance_threshold`
+/// times the load of a node if all were even.
+pub(crate) fn plan(
+    nodes: &[NodeUtilization],
+    imbalance_threshold: f64,
+    max_migrations: usize,
+) -> (Vec<NodeLoad>, Vec<PlannedMigration>) {
+    let all_shards = || nodes.iter().flat_map(|node| &node.shards);
+    let total_shards = all_shards().count() as f64;
+    let total_resident_size = all_shards().map(|s| s.resident_size).sum::<u64>() as f64;
+    let total_getpage_rate = all_shards().map(|s| s.getpage_rate).sum::<f64>();
+
+    // The terms of the load with a total of zero, like the GetPage rate on
+    // the first sample, are left out.
+    let shard_load = |shard: &ShardLoad| {
+        let mut shares = vec![1.0 / total_shards];
+        if total_resident_size > 0.0 {
+            shares.push(shard.resident_size as f64 / total_resident_size);
+        }
+        if total_getpage_rate > 0.0 {
+            shares.push(shard.getpage_rate / total_getpage_rate);
+        }
+        shares.iter().sum::<f64>() / shares.len() as f64
+    };
+
+    let mut loads: HashMap<NodeId, f64> = nodes
+        .iter()
+        .map(|node| (node.node_id, node.shards.iter().map(shard_load).sum()))
+        .collect();
+    let node_loads = nodes
+        .iter()
+        .map(|node| NodeLoad {
+            node_id: node.node_id,
+            shard_count: node.shards.len(),
+            resident_size: node.shards.iter().map(|s| s.resident_size).sum(),
+            getpage_rate: node.shards.iter().map(|s| s.getpage_rate).sum(),
+            load: loads[&node.node_id],
+        })
+        .collect();
+
+    let schedulable: Vec<&NodeUtilization> =
+        nodes.iter().filter(|node| node.may_schedule).collect();
+    if schedulable.len() < 2 {
+        return (node_loads, Vec::new());
+    }
+    let balanced_gap = imbalance_threshold / schedulable.len() as f64;
+
+    // Shards move at most once per plan.
+    let mut moved = HashSet::new();
+    let mut migrations = Vec::new();
+    while migrations.len() < max_migrations {
+        let by_load = |a: &&&NodeUtilization, b: &&&NodeUtilization| {
+            loads[&a.node_id].total_cmp(&loads[&b.node_id])
+        };
+        let hot = schedulable.iter().max_by(by_load).unwrap();
+        let cold = schedulable.iter().min_by(by_load).unwrap();
+        let gap = loads[&hot.node_id] - loads[&cold.node_id];
+        if gap <= balanced_gap {
+            break;
+        }
+
+        // Moving a shard of load `l` changes the gap to `|gap - 2l|`: the best
+        // one is the closest to half the gap, among those which reduce it.
+        let Some((shard, load)) = hot
+            .shards
+            .iter()
+            .filter(|shard| shard.movable && !moved.contains(&shard.tenant_shard_id))
+            .map(|shard| (shard, shard_load(shard)))
+            .filter(|(_, load)| *load < gap)
+            .min_by(|(_, a), (_, b)| (a - gap / 2.0).abs().total_cmp(&(b - gap / 2.0).abs()))
+        else {
+            break;
+        };
+
+        *loads.get_mut(&hot.node_id).unwrap() -= load;
+        *loads.get_mut(&cold.node_id).unwrap() += load;
+        moved.insert(shard.tenant_shard_id);
+        migrations.push(PlannedMigration {
+            tenant_shard_id: shard.tenant_shard_id,
+            from_node_id: hot.node_id,
+            to_node_id: cold.node_id,
+        });
+    }
+
+    (node_loads, migrations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::id::TenantId;
+
+    fn shard(resident_size: u64, getpage_rate: f64) -> ShardLoad {
+        ShardLoad {
+            tenant_shard_id: TenantShardId::unsharded(TenantId::generate()),
+            resident_size,
+            getpage_rate,
+            movable: true,
+        }
+    }
+
+    fn node(node_id: u64, shards: Vec<ShardLoad>) -> NodeUtilization {
+        NodeUtilization {
+            node_id: NodeId(node_id),
+            may_schedule: true,
+            shards,
+        }
+    }
+
+    #[test]
+    fn balanced_nodes_plan_nothing() {
+        let nodes = vec![
+            node(1, vec![shard(100, 10.0), shard(100, 10.0)]),
+            node(2, vec![shard(100, 10.0), shard(100, 10.0)]),
+        ];
+        let (loads, migrations) = plan(&nodes, 0.1, 10);
+        assert!(migrations.is_empty());
+        assert_eq!(loads.len(), 2);
+        for load in loads {
+            assert_eq!(load.shard_count, 2);
+            assert!((load.load - 0.5).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn moves_shards_to_the_empty_node() {
+        let nodes = vec![
+            node(1, (0..4).map(|_| shard(100, 0.0)).collect()),
+            node(2, Vec::new()),
+        ];
+        let (_, migrations) = plan(&nodes, 0.1, 10);
+        assert_eq!(migrations.len(), 2);
+        for migration in &migrations {
+            assert_eq!(migration.from_node_id, NodeId(1));
+            assert_eq!(migration.to_node_id, NodeId(2));
+        }
+        assert_ne!(migrations[0].tenant_shard_id, migrations[1].tenant_shard_id);
+    }
+
+    #[test]
+    fn respects_max_migrations() {
+        let nodes = vec![
+            node(1, (0..4).map(|_| shard(100, 0.0)).collect()),
+            node(2, Vec::new()),
+        ];
+        let (_, migrations) = plan(&nodes, 0.1, 1);
+        assert_eq!(migrations.len(), 1);
+    }
+
+    #[test]
+    fn leaves_unmovable_shards() {
+        let mut shards: Vec<ShardLoad> = (0..4).map(|_| shard(100, 0.0)).collect();
+        for shard in &mut shards[1..] {
+            shard.movable = false;
+        }
+        let movable = shards[0].tenant_shard_id;
+        let nodes = vec![node(1, shards), node(2, Vec::new())];
+        let (_, migrations) = plan(&nodes, 0.1, 10);
+        assert_eq!(migrations.len(), 1);
+        assert_eq!(migrations[0].tenant_shard_id, movable);
+    }
+
+    #[test]
+    fn skips_unschedulable_nodes() {
+        let mut unschedulable = node(2, Vec::new());
+        unschedulable.may_schedule = false;
+        let nodes = vec![
+            node(1, (0..4).map(|_| shard(100, 0.0)).collect()),
+            unschedulable,
+        ];
+        let (loads, migrations) = plan(&nodes, 0.1, 10);
+        assert!(migrations.is_empty());
+        // Unschedulable nodes are still reported
+        assert_eq!(loads.len(), 2);
+    }
+
+    #[test]
+    fn does_not_move_a_shard_which_would_swap_the_hot_node() {
+        // The hot shard alone makes up more than the gap between the nodes: moving it would
+        // only make node 2 the hot one, so the optimizer moves a small shard instead.
+        let hot = shard(1000, 100.0);
+        let hot_id = hot.tenant_shard_id;
+        let nodes = vec![
+            node(1, vec![hot, shard(10, 1.0)]),
+            node(2, vec![shard(10, 1.0), shard(10, 1.0)]),
+        ];
+        let (_, migrations) = plan(&nodes, 0.1, 10);
+        assert_eq!(migrations.len(), 1);
+        assert_ne!(migrations[0].tenant_shard_id, hot_id);
+        assert_eq!(migrations[0].from_node_id, NodeId(1));
+    }
+}
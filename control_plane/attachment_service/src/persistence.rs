@@ -28,6 +28,9 @@ use crate::PlacementPolicy;
 /// - Tenant's PlacementPolicy and TenantConfig, as the source of truth for these is something external.
 /// - Node's scheduling policies, as the source of truth for these is something external.
 ///
+/// Changes to these are persisted _before_ they are applied in memory, so that a restart of the
+/// service only ever loses changes which were not acknowledged to the caller.
+///
 /// Other things we store durably as an implementation detail:
 /// - Node's host/port: this could be avoided it we made nodes emit a self-registering heartbeat,
 ///   but it is operationally simpler to make this service the authority for which nodes
@@ -146,6 +149,7 @@ impl Persistence {
                         listen_http_port: n.listen_http_port as u16,
                         listen_pg_addr: n.listen_pg_addr,
                         listen_pg_port: n.listen_pg_port as u16,
+                        availability_zone_id: n.availability_zone_id,
                     })
                     .collect::<Vec<Node>>())
             })
@@ -284,6 +288,53 @@ impl Persistence {
         .await
     }
 
+    /// Ordering: call this _before_ applying the new scheduling policy in memory, so that a restart
+    /// can never forget that a node was e.g. draining.
+    pub(crate) async fn update_node_scheduling(
+        &self,
+        input_node_id: NodeId,
+        input_scheduling: NodeSchedulingPolicy,
+    ) -> DatabaseResult<()> {
+        use crate::schema::nodes::dsl::*;
+        let updated = self
+            .with_conn(move |conn| -> DatabaseResult<usize> {
+                Ok(diesel::update(nodes)
+                    .filter(node_id.eq(input_node_id.0 as i64))
+                    .set(scheduling_policy.eq(String::from(input_scheduling)))
+                    .execute(conn)?)
+            })
+            .await?;
+
+        if updated != 1 {
+            return Err(DatabaseError::Logical(format!(
+                "Node {input_node_id:?} not found in database"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Ordering: call this _before_ applying the new placement policy to the tenant's shards in
+    /// memory.  Updates all the shards of the tenant in one statement, so that they never disagree.
+    pub(crate) async fn update_tenant_placement_policy(
+        &self,
+        input_tenant_id: TenantId,
+        input_placement_policy: &PlacementPolicy,
+    ) -> DatabaseResult<()> {
+        use crate::schema::tenant_shards::dsl::*;
+        let input_placement_policy = serde_json::to_string(input_placement_policy)
+            .map_err(|e| DatabaseError::Logical(format!("Serialization error: {e}")))?;
+        self.with_conn(move |conn| -> DatabaseResult<()> {
+            diesel::update(tenant_shards)
+                .filter(tenant_id.eq(input_tenant_id.to_string()))
+                .set(placement_policy.eq(input_placement_policy.clone()))
+                .execute(conn)?;
+
+            Ok(())
+        })
+        .await
+    }
+
     /// When a tenant invokes the /re-attach API, this function is responsible for doing an efficient
     /// batched increment of the generations of all tenants whose generation_pageserver is equal to
     /// the node that called /re-attach.
@@ -295,19 +346,23 @@ impl Persistence {
         use crate::schema::tenant_shards::dsl::*;
         let updated = self
             .with_conn(move |conn| {
-                let rows_updated = diesel::update(tenant_shards)
-                    .filter(generation_pageserver.eq(node_id.0 as i64))
-                    .set(generation.eq(generation + 1))
-                    .execute(conn)?;
+                // In one transaction, so that we return the generations we incremented even
+                // if e.g. a shard is attached elsewhere concurrently.
+                let updated = conn.transaction(|conn| -> QueryResult<_> {
+                    let rows_updated = diesel::update(tenant_shards)
+                        .filter(generation_pageserver.eq(node_id.0 as i64))
+                        .set(generation.eq(generation + 1))
+                        .execute(conn)?;
 
-                tracing::info!("Incremented {} tenants' generations", rows_updated);
+                    tracing::info!("Incremented {} tenants' generations", rows_updated);
 
-                // TODO: UPDATE+SELECT in one query
+                    // TODO: UPDATE+SELECT in one query
 
-                let updated = tenant_shards
-                    .filter(generation_pageserver.eq(node_id.0 as i64))
-                    .select(TenantShardPersistence::as_select())
-                    .load(conn)?;
+                    tenant_shards
+                        .filter(generation_pageserver.eq(node_id.0 as i64))
+                        .select(TenantShardPersistence::as_select())
+                        .load(conn)
+                })?;
                 Ok(updated)
             })
             .await?;
@@ -478,6 +533,55 @@ impl Persistence {
         })
         .await
     }
+
+    /// When a shard split fails, we must atomically drop the child shards and clear the
+    /// splitting marker on the parent shards.  If the parents are already gone, the split
+    /// was completed and may not be rolled back.
+    pub(crate) async fn abort_shard_split(
+        &self,
+        split_tenant_id: TenantId,
+        new_shard_count: ShardCount,
+    ) -> DatabaseResult<AbortShardSplitStatus> {
+        use crate::schema::tenant_shards::dsl::*;
+        self.with_conn(move |conn| -> DatabaseResult<AbortShardSplitStatus> {
+            let status = conn.transaction(|conn| -> QueryResult<AbortShardSplitStatus> {
+                let parents = tenant_shards
+                    .filter(tenant_id.eq(split_tenant_id.to_string()))
+                    .filter(shard_count.ne(new_shard_count.0 as i32))
+                    .count()
+                    .get_result::<i64>(conn)?;
+                if parents == 0 {
+                    return Ok(AbortShardSplitStatus::Complete);
+                }
+
+                // Drop child shards
+                diesel::delete(tenant_shards)
+                    .filter(tenant_id.eq(split_tenant_id.to_string()))
+                    .filter(shard_count.eq(new_shard_count.0 as i32))
+                    .execute(conn)?;
+
+                // Clear sharding flag
+                diesel::update(tenant_shards)
+                    .filter(tenant_id.eq(split_tenant_id.to_string()))
+                    .set((splitting.eq(0),))
+                    .execute(conn)?;
+
+                Ok(AbortShardSplitStatus::Aborted)
+            })?;
+
+            Ok(status)
+        })
+        .await
+    }
+}
+
+/// Outcome of [`Persistence::abort_shard_split`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AbortShardSplitStatus {
+    /// The child shards were dropped: the parent shards are back to their state before the split.
+    Aborted,
+    /// The split was already completed, there is nothing to roll back.
+    Complete,
 }
 
 /// Parts of [`crate::tenant_state::TenantState`] that are stored durably
@@ -519,4 +623,5 @@ pub(crate) struct NodePersistence {
     pub(crate) listen_http_port: i32,
     pub(crate) listen_pg_addr: String,
     pub(crate) listen_pg_port: i32,
+    pub(crate) availability_zone_id: Option<String>,
 }
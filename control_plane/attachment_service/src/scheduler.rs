@@ -1,8 +1,11 @@
 use pageserver_api::shard::TenantShardId;
-use std::collections::{BTreeMap, HashMap};
-use utils::{http::error::ApiError, id::NodeId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use utils::{
+    http::error::ApiError,
+    id::{NodeId, TenantId},
+};
 
-use crate::{node::Node, tenant_state::TenantState};
+use crate::{metrics::SCHEDULING_VIOLATIONS, node::Node, tenant_state::TenantState};
 
 /// Scenarios in which we cannot find a suitable location for a tenant shard
 #[derive(thiserror::Error, Debug)]
@@ -21,6 +24,13 @@ impl From<ScheduleError> for ApiError {
 
 pub(crate) struct Scheduler {
     tenant_counts: HashMap<NodeId, usize>,
+
+    /// How many locations of each tenant are on each node, to avoid placing locations of the
+    /// same tenant together.
+    tenant_locations: HashMap<TenantId, HashMap<NodeId, usize>>,
+
+    /// Availability zones of the nodes which have one.
+    node_azs: HashMap<NodeId, String>,
 }
 
 impl Scheduler {
@@ -33,11 +43,19 @@ impl Scheduler {
             tenant_counts.insert(*node_id, 0);
         }
 
+        let mut tenant_locations: HashMap<TenantId, HashMap<NodeId, usize>> = HashMap::new();
         for tenant in tenants.values() {
             if let Some(ps) = tenant.intent.attached {
                 let entry = tenant_counts.entry(ps).or_insert(0);
                 *entry += 1;
             }
+
+            let locations = tenant_locations
+                .entry(tenant.tenant_shard_id.tenant_id)
+                .or_default();
+            for ps in tenant.intent.all_pageservers() {
+                *locations.entry(ps).or_insert(0) += 1;
+            }
         }
 
         for (node_id, node) in nodes {
@@ -46,44 +64,132 @@ impl Scheduler {
             }
         }
 
-        Self { tenant_counts }
+        let node_azs = nodes
+            .values()
+            .filter_map(|node| Some((node.id, node.availability_zone_id.clone()?)))
+            .collect();
+
+        Self {
+            tenant_counts,
+            tenant_locations,
+            node_azs,
+        }
+    }
+
+    /// Pick the node with the fewest attached shards among `secondaries`, the secondary
+    /// locations of a shard, to attach it there.  Returns None if none of them may be
+    /// scheduled on.
+    pub(crate) fn promote_secondary(&mut self, secondaries: &[NodeId]) -> Option<NodeId> {
+        let node_id = secondaries
+            .iter()
+            .filter_map(|node_id| Some((*self.tenant_counts.get(node_id)?, *node_id)))
+            .min()?
+            .1;
+        tracing::info!("scheduler selected secondary node {node_id}");
+        *self.tenant_counts.get_mut(&node_id).unwrap() += 1;
+        Some(node_id)
     }
 
+    /// Pick a node for a new location of a shard, which may not be one of `hard_exclude`: the
+    /// existing locations of the shard.
+    ///
+    /// Preferably, the node is in a different availability zone than the existing locations of
+    /// the shard, then has no locations of other shards of the same tenant, then is in the
+    /// availability zone with the fewest locations of the tenant.  Among those, the node with
+    /// the fewest attached shards is picked.
     pub(crate) fn schedule_shard(
         &mut self,
+        tenant_shard_id: TenantShardId,
         hard_exclude: &[NodeId],
     ) -> Result<NodeId, ScheduleError> {
         if self.tenant_counts.is_empty() {
             return Err(ScheduleError::NoPageservers);
         }
 
-        let mut tenant_counts: Vec<(NodeId, usize)> = self
+        let shard_azs: HashSet<&String> = hard_exclude
+            .iter()
+            .filter_map(|node_id| self.node_azs.get(node_id))
+            .collect();
+        let no_locations = HashMap::new();
+        let tenant_locations = self
+            .tenant_locations
+            .get(&tenant_shard_id.tenant_id)
+            .unwrap_or(&no_locations);
+        let mut tenant_az_locations: HashMap<&String, usize> = HashMap::new();
+        for (node_id, count) in tenant_locations {
+            if let Some(az) = self.node_azs.get(node_id) {
+                *tenant_az_locations.entry(az).or_insert(0) += count;
+            }
+        }
+
+        let mut candidates: Vec<ScheduleCandidate> = self
             .tenant_counts
             .iter()
-            .filter_map(|(k, v)| {
-                if hard_exclude.contains(k) {
-                    None
-                } else {
-                    Some((*k, *v))
+            .filter(|(node_id, _)| !hard_exclude.contains(node_id))
+            .map(|(node_id, tenant_count)| {
+                let az = self.node_azs.get(node_id);
+                ScheduleCandidate {
+                    same_az_as_shard: az.is_some_and(|az| shard_azs.contains(az)),
+                    tenant_node_locations: tenant_locations.get(node_id).copied().unwrap_or(0),
+                    tenant_az_locations: az
+                        .and_then(|az| tenant_az_locations.get(az))
+                        .copied()
+                        .unwrap_or(0),
+                    tenant_count: *tenant_count,
+                    node_id: *node_id,
                 }
             })
             .collect();
 
-        // Sort by tenant count.  Nodes with the same tenant count are sorted by ID.
-        tenant_counts.sort_by_key(|i| (i.1, i.0));
+        // Sort by the constraints, then by tenant count.  Nodes with the same tenant count are
+        // sorted by ID.
+        candidates.sort_by_key(|c| {
+            (
+                c.same_az_as_shard,
+                c.tenant_node_locations,
+                c.tenant_az_locations,
+                c.tenant_count,
+                c.node_id,
+            )
+        });
 
-        if tenant_counts.is_empty() {
+        let Some(selected) = candidates.first() else {
             // After applying constraints, no pageservers were left
             return Err(ScheduleError::ImpossibleConstraint);
-        }
+        };
 
-        for (node_id, count) in &tenant_counts {
-            tracing::info!("tenant_counts[{node_id}]={count}");
+        for c in &candidates {
+            tracing::info!("tenant_counts[{}]={}", c.node_id, c.tenant_count);
         }
 
-        let node_id = tenant_counts.first().unwrap().0;
+        let node_id = selected.node_id;
         tracing::info!("scheduler selected node {node_id}");
+        if selected.same_az_as_shard {
+            tracing::warn!(%tenant_shard_id, "Scheduled a location on node {node_id}, in the same availability zone as another location of the shard");
+            SCHEDULING_VIOLATIONS.with_label_values(&["shard_az"]).inc();
+        }
+        if selected.tenant_node_locations > 0 {
+            tracing::warn!(%tenant_shard_id, "Scheduled a location on node {node_id}, which has locations of other shards of the tenant");
+            SCHEDULING_VIOLATIONS
+                .with_label_values(&["tenant_node"])
+                .inc();
+        }
+
         *self.tenant_counts.get_mut(&node_id).unwrap() += 1;
+        *self
+            .tenant_locations
+            .entry(tenant_shard_id.tenant_id)
+            .or_default()
+            .entry(node_id)
+            .or_insert(0) += 1;
         Ok(node_id)
     }
 }
+
+struct ScheduleCandidate {
+    same_az_as_shard: bool,
+    tenant_node_locations: usize,
+    tenant_az_locations: usize,
+    tenant_count: usize,
+    node_id: NodeId,
+}
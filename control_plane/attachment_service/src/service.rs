@@ -1,16 +1,19 @@
 use std::{
     cmp::Ordering,
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     str::FromStr,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
 use control_plane::attachment_service::{
-    AttachHookRequest, AttachHookResponse, InspectRequest, InspectResponse, NodeAvailability,
-    NodeConfigureRequest, NodeRegisterRequest, NodeSchedulingPolicy, TenantCreateResponse,
-    TenantCreateResponseShard, TenantLocateResponse, TenantLocateResponseShard,
-    TenantShardMigrateRequest, TenantShardMigrateResponse,
+    AttachHookRequest, AttachHookResponse, FailoverEvent, FailoverEventKind, InspectRequest,
+    InspectResponse, NodeAvailability, NodeConfigureRequest, NodeDecommissionMode,
+    NodeHeartbeatStatus, NodeOperationKind, NodeOperationState, NodeOperationStatus,
+    NodeRegisterRequest, NodeSchedulingPolicy, OptimizerPlanResponse, PlannedMigration,
+    ServiceMetricsResponse, TenantCreateResponse, TenantCreateResponseShard, TenantLocateResponse,
+    TenantLocateResponseShard, TenantShardMigrateRequest, TenantShardMigrateResponse,
+    TenantShardMigrationState, TenantShardMigrationStatus,
 };
 use diesel::result::DatabaseErrorKind;
 use futures::StreamExt;
@@ -41,10 +44,15 @@ use utils::{
 
 use crate::{
     compute_hook::{self, ComputeHook},
+    metrics::{
+        self, ScheduleReason, NODE_AVAILABLE, NODE_MISSED_HEARTBEATS, PENDING_MIGRATIONS,
+        RECONCILES_IN_PROGRESS, RECONCILE_SECONDS,
+    },
     node::Node,
+    optimizer::{self, NodeUtilization, ShardLoad},
     persistence::{
-        split_state::SplitState, DatabaseError, NodePersistence, Persistence,
-        TenantShardPersistence,
+        split_state::SplitState, AbortShardSplitStatus, DatabaseError, NodePersistence,
+        Persistence, TenantShardPersistence,
     },
     reconciler::attached_location_conf,
     scheduler::Scheduler,
@@ -57,10 +65,35 @@ use crate::{
 
 const RECONCILE_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// How many shards a drain or fill of a node migrates at the same time.
+const NODE_OPERATION_CONCURRENCY: usize = 4;
+
+/// How long a pageserver may take to answer a heartbeat.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many of the latest failover events are kept.
+const MAX_FAILOVER_EVENTS: usize = 1000;
+
+/// How often a warm migration reports the progress of the secondary location it warms up.
+const MIGRATION_WARMUP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a warm migration waits for the secondary location to reach its threshold.
+const MIGRATION_WARMUP_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long the child shards of a split may take to activate on their pageserver.
+const SHARD_SPLIT_ACTIVATE_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// How long [`Service::startup_reconcile`] is allowed to take before it should give
 /// up on unresponsive pageservers and proceed.
 pub(crate) const STARTUP_RECONCILE_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// A parent shard which will be split
+struct SplitTarget {
+    parent_id: TenantShardId,
+    node: Node,
+    child_ids: Vec<TenantShardId>,
+}
+
 // Top level state available to all HTTP handlers
 struct ServiceState {
     tenants: BTreeMap<TenantShardId, TenantState>,
@@ -102,6 +135,25 @@ pub struct Config {
     /// (this URL points to the control plane in prod). If this is None, the compute hook will
     /// assume it is running in a test environment and try to update neon_local.
     pub compute_hook_url: Option<String>,
+
+    /// How often the optimizer migrates shards from the most loaded pageservers to the
+    /// least loaded ones. If this is None, shards are only migrated on request.
+    pub optimizer_interval: Option<Duration>,
+
+    /// How many migrations the optimizer starts at most in each interval.
+    pub optimizer_max_migrations: usize,
+
+    /// Difference between the loads of the most and least loaded pageservers, as a fraction
+    /// of the load of a pageserver if all were even, under which the optimizer doesn't
+    /// migrate shards.
+    pub optimizer_imbalance_threshold: f64,
+
+    /// How often to heartbeat the pageservers.
+    pub heartbeat_interval: Duration,
+
+    /// After how many heartbeats missed in a row a pageserver is marked offline, and its shards
+    /// attached elsewhere.  If this is zero, pageservers are only marked offline on request.
+    pub heartbeat_failure_threshold: u32,
 }
 
 impl From<DatabaseError> for ApiError {
@@ -124,11 +176,83 @@ pub struct Service {
     config: Config,
     persistence: Arc<Persistence>,
 
+    /// The GetPage request counts of the shards of each node at the last time the optimizer
+    /// looked at them, to compute the rates from.
+    getpage_samples: std::sync::Mutex<HashMap<NodeId, (Instant, HashMap<TenantShardId, u64>)>>,
+
+    /// The latest drain, fill or decommission of each node, which may still be in progress.
+    node_operations: std::sync::Mutex<HashMap<NodeId, NodeOperation>>,
+
+    /// The latest warm migration of each shard, which may still be in progress.
+    migrations: std::sync::Mutex<HashMap<TenantShardId, TenantShardMigration>>,
+
+    /// The latest decisions taken after the availability of a node changed, oldest first.
+    failover_events: std::sync::Mutex<VecDeque<FailoverEvent>>,
+
+    /// Serializes the changes to the nodes' registration and scheduling policies, so that they
+    /// are applied to the database and to memory in the same order.  This only orders the API
+    /// calls to this process: a single instance of the service is assumed to run at a time.
+    node_op_lock: tokio::sync::Mutex<()>,
+
+    /// Like [`Self::node_op_lock`], for the operations which change the placement of the shards
+    /// of a tenant.
+    tenant_op_locks: std::sync::Mutex<HashMap<TenantId, Arc<tokio::sync::Mutex<()>>>>,
+
     /// This waits for initial reconciliation with pageservers to complete.  Until this barrier
     /// passes, it isn't safe to do any actions that mutate tenants.
     pub(crate) startup_complete: Barrier,
 }
 
+struct TenantShardMigration {
+    status: TenantShardMigrationStatus,
+    /// Fired when another warm migration of the shard starts.
+    cancel: CancellationToken,
+}
+
+struct NodeOperation {
+    status: NodeOperationStatus,
+    /// Fired when another drain or fill of the node starts.
+    cancel: CancellationToken,
+}
+
+/// Export the migrations left to the node operations in progress: call this after changing
+/// them, while holding [`Service::node_operations`].
+fn update_node_operation_metrics(operations: &HashMap<NodeId, NodeOperation>) {
+    for kind in [
+        NodeOperationKind::Drain,
+        NodeOperationKind::Fill,
+        NodeOperationKind::Decommission,
+    ] {
+        let pending: usize = operations
+            .values()
+            .filter(|op| {
+                op.status.kind == kind && op.status.state == NodeOperationState::InProgress
+            })
+            .map(|op| op.status.pending.len())
+            .sum();
+        PENDING_MIGRATIONS
+            .with_label_values(&[ScheduleReason::from(kind).as_str()])
+            .set(pending as i64);
+    }
+}
+
+/// Export the warm migrations in progress: call this after changing them, while holding
+/// [`Service::migrations`].
+fn update_warm_migration_metrics(migrations: &HashMap<TenantShardId, TenantShardMigration>) {
+    let pending = migrations
+        .values()
+        .filter(|migration| {
+            matches!(
+                migration.status.state,
+                TenantShardMigrationState::WarmingUp | TenantShardMigrationState::CuttingOver
+            )
+        })
+        .count();
+    PENDING_MIGRATIONS
+        .with_label_values(&[ScheduleReason::WarmMigration.as_str()])
+        .set(pending as i64);
+}
+
 impl From<ReconcileWaitError> for ApiError {
     fn from(value: ReconcileWaitError) -> Self {
         match value {
@@ -377,7 +501,29 @@ impl Service {
         tracing::info!("Loaded {} nodes from database.", nodes.len());
 
         tracing::info!("Loading shards from database...");
-        let tenant_shard_persistence = persistence.list_tenant_shards().await?;
+        let mut tenant_shard_persistence = persistence.list_tenant_shards().await?;
+
+        // Roll back the splits which were in progress when we stopped: the child shards they may
+        // have left on pageservers are detached in [`Self::startup_reconcile`], as unknown shards.
+        let mut splitting: HashMap<TenantId, ShardCount> = HashMap::new();
+        for tsp in &tenant_shard_persistence {
+            if tsp.splitting == SplitState::Splitting {
+                let shard_count = ShardCount(tsp.shard_count as u8);
+                let entry = splitting
+                    .entry(TenantId::from_str(tsp.tenant_id.as_str())?)
+                    .or_insert(shard_count);
+                *entry = std::cmp::max(*entry, shard_count);
+            }
+        }
+        if !splitting.is_empty() {
+            for (tenant_id, new_shard_count) in splitting {
+                tracing::info!("Rolling back interrupted split of {tenant_id}");
+                persistence
+                    .abort_shard_split(tenant_id, new_shard_count)
+                    .await?;
+            }
+            tenant_shard_persistence = persistence.list_tenant_shards().await?;
+        }
         tracing::info!(
             "Loaded {} shards from database.",
             tenant_shard_persistence.len()
@@ -422,6 +568,7 @@ impl Service {
                 error_waiter: Arc::new(SeqWait::new(Sequence::initial())),
                 last_error: Arc::default(),
                 pending_compute_notification: false,
+                splitting: SplitState::Idle,
             };
 
             tenants.insert(tenant_shard_id, new_tenant);
@@ -438,6 +585,12 @@ impl Service {
             ))),
             config,
             persistence,
+            getpage_samples: Default::default(),
+            node_operations: Default::default(),
+            migrations: Default::default(),
+            failover_events: Default::default(),
+            node_op_lock: Default::default(),
+            tenant_op_locks: Default::default(),
             startup_complete: startup_complete.clone(),
         });
 
@@ -508,9 +661,343 @@ impl Service {
             startup_reconcile_this.startup_reconcile().await
         });
 
+        let heartbeat_this = this.clone();
+        tokio::task::spawn(async move { heartbeat_this.heartbeat_loop().await });
+
+        if let Some(interval) = this.config.optimizer_interval {
+            let optimizer_this = this.clone();
+            tokio::task::spawn(async move { optimizer_this.optimizer_loop(interval).await });
+        }
+
         Ok(this)
     }
 
+    /// Periodically check that the pageservers are up: mark those which miss too many heartbeats
+    /// offline, which attaches their shards elsewhere, and those which answer again active.
+    async fn heartbeat_loop(&self) {
+        self.startup_complete.clone().wait().await;
+
+        let http_client = reqwest::ClientBuilder::new()
+            .timeout(HEARTBEAT_TIMEOUT)
+            .build()
+            .expect("Failed to construct HTTP client");
+        let mut missed_heartbeats: HashMap<NodeId, u32> = HashMap::new();
+        // Nodes which this loop marked offline.  Only these are marked active again when they
+        // answer: a node an operator marked offline stays offline until they mark it active.
+        // Nodes which did not answer during startup are offline for the same reason as those
+        // which miss heartbeats.
+        let mut marked_offline: HashSet<NodeId> = self
+            .inner
+            .read()
+            .unwrap()
+            .nodes
+            .values()
+            .filter(|node| matches!(node.availability, NodeAvailability::Offline))
+            .map(|node| node.id)
+            .collect();
+        let mut ticks = tokio::time::interval(self.config.heartbeat_interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+
+            let nodes = self.inner.read().unwrap().nodes.clone();
+            marked_offline.retain(|node_id| {
+                nodes
+                    .get(node_id)
+                    .map(|node| matches!(node.availability, NodeAvailability::Offline))
+                    .unwrap_or(false)
+            });
+            let heartbeats = nodes.values().map(|node| {
+                let client = mgmt_api::Client::from_client(
+                    http_client.clone(),
+                    node.base_url(),
+                    self.config.jwt_token.as_deref(),
+                );
+                async move { (node, client.status().await) }
+            });
+            for (node, result) in futures::future::join_all(heartbeats).await {
+                let availability = match result {
+                    Ok(()) => {
+                        missed_heartbeats.remove(&node.id);
+                        if !marked_offline.contains(&node.id) {
+                            continue;
+                        }
+                        self.record_failover_event(node.id, FailoverEventKind::NodeActive);
+                        NodeAvailability::Active
+                    }
+                    Err(e) => {
+                        let missed = missed_heartbeats.entry(node.id).or_insert(0);
+                        *missed += 1;
+                        tracing::warn!(
+                            "Node {} missed {} heartbeats in a row: {e}",
+                            node.id,
+                            *missed
+                        );
+                        if self.config.heartbeat_failure_threshold == 0
+                            || *missed < self.config.heartbeat_failure_threshold
+                            || !matches!(node.availability, NodeAvailability::Active)
+                        {
+                            continue;
+                        }
+                        self.record_failover_event(
+                            node.id,
+                            FailoverEventKind::NodeOffline {
+                                missed_heartbeats: *missed,
+                            },
+                        );
+                        NodeAvailability::Offline
+                    }
+                };
+
+                let config_req = NodeConfigureRequest {
+                    node_id: node.id,
+                    availability: Some(availability),
+                    scheduling: None,
+                };
+                match self.node_configure(config_req).await {
+                    Ok(()) => match availability {
+                        NodeAvailability::Active => {
+                            marked_offline.remove(&node.id);
+                        }
+                        NodeAvailability::Offline => {
+                            marked_offline.insert(node.id);
+                        }
+                    },
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to update the availability of node {}: {e}",
+                            node.id
+                        );
+                    }
+                }
+            }
+
+            let nodes = self.inner.read().unwrap().nodes.clone();
+            for node in nodes.values() {
+                let node_id = node.id.to_string();
+                NODE_AVAILABLE
+                    .with_label_values(&[&node_id])
+                    .set(matches!(node.availability, NodeAvailability::Active) as i64);
+                NODE_MISSED_HEARTBEATS
+                    .with_label_values(&[&node_id])
+                    .set(missed_heartbeats.get(&node.id).copied().unwrap_or(0) as i64);
+            }
+        }
+    }
+
+    fn record_failover_event(&self, node_id: NodeId, kind: FailoverEventKind) {
+        tracing::info!("Failover event on node {node_id}: {kind:?}");
+        let mut events = self.failover_events.lock().unwrap();
+        if events.len() == MAX_FAILOVER_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(FailoverEvent {
+            time: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            node_id,
+            kind,
+        });
+    }
+
+    pub(crate) fn failover_events(&self) -> Vec<FailoverEvent> {
+        self.failover_events
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// The signals exported to Prometheus, gathered for the debug API.
+    pub(crate) fn service_metrics(&self) -> ServiceMetricsResponse {
+        let reconciles_ok = RECONCILE_SECONDS.with_label_values(&["ok"]);
+        let reconciles_error = RECONCILE_SECONDS.with_label_values(&["error"]);
+        let reconciles = reconciles_ok.get_sample_count() + reconciles_error.get_sample_count();
+        let reconcile_mean_seconds = if reconciles > 0 {
+            (reconciles_ok.get_sample_sum() + reconciles_error.get_sample_sum()) / reconciles as f64
+        } else {
+            0.0
+        };
+
+        let schedule_decisions = ScheduleReason::ALL
+            .iter()
+            .map(|reason| {
+                let count = metrics::SCHEDULE_DECISIONS
+                    .with_label_values(&[reason.as_str()])
+                    .get();
+                (reason.as_str().to_string(), count)
+            })
+            .collect();
+        let pending_migrations = [
+            ScheduleReason::Drain,
+            ScheduleReason::Fill,
+            ScheduleReason::Decommission,
+            ScheduleReason::WarmMigration,
+        ]
+        .iter()
+        .map(|reason| {
+            let count = PENDING_MIGRATIONS
+                .with_label_values(&[reason.as_str()])
+                .get();
+            (reason.as_str().to_string(), count)
+        })
+        .collect();
+
+        let nodes = self.inner.read().unwrap().nodes.clone();
+        let mut nodes: Vec<NodeHeartbeatStatus> = nodes
+            .values()
+            .map(|node| NodeHeartbeatStatus {
+                node_id: node.id,
+                availability: node.availability,
+                missed_heartbeats: NODE_MISSED_HEARTBEATS
+                    .with_label_values(&[&node.id.to_string()])
+                    .get(),
+            })
+            .collect();
+        nodes.sort_by_key(|node| node.node_id);
+
+        ServiceMetricsResponse {
+            reconciles_in_progress: RECONCILES_IN_PROGRESS.get(),
+            reconciles_succeeded: reconciles_ok.get_sample_count(),
+            reconciles_failed: reconciles_error.get_sample_count(),
+            reconcile_mean_seconds,
+            schedule_decisions,
+            pending_migrations,
+            nodes,
+        }
+    }
+
+    /// Periodically migrate shards from the most loaded pageservers to the least loaded ones.
+    async fn optimizer_loop(&self, interval: Duration) {
+        self.startup_complete.clone().wait().await;
+
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let plan = match self.optimizer_plan(true).await {
+                Ok(plan) => plan,
+                Err(e) => {
+                    tracing::warn!("Optimizer failed to plan migrations: {e}");
+                    continue;
+                }
+            };
+            for migration in plan.migrations {
+                tracing::info!(
+                    "Optimizer migrating {} from node {} to node {}",
+                    migration.tenant_shard_id,
+                    migration.from_node_id,
+                    migration.to_node_id
+                );
+                let migrate_req = TenantShardMigrateRequest {
+                    tenant_shard_id: migration.tenant_shard_id,
+                    node_id: migration.to_node_id,
+                    warm_threshold: None,
+                };
+                if let Err(e) = self
+                    .migrate_shard(
+                        migration.tenant_shard_id,
+                        migrate_req,
+                        ScheduleReason::Optimizer,
+                    )
+                    .await
+                {
+                    tracing::warn!(
+                        "Optimizer failed to migrate {}: {e}",
+                        migration.tenant_shard_id
+                    );
+                }
+            }
+        }
+    }
+
+    /// Plan the migrations that the optimizer would start now, from the utilization
+    /// reported by the pageservers.  GetPage rates are measured since the previous
+    /// sample: only the optimizer loop `update_samples`, so that a dry run does not
+    /// shorten the interval the next plan measures over.
+    pub(crate) async fn optimizer_plan(
+        &self,
+        update_samples: bool,
+    ) -> Result<OptimizerPlanResponse, ApiError> {
+        const UTILIZATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+        let nodes = self.inner.read().unwrap().nodes.clone();
+        let mut utilizations = Vec::new();
+        for node in nodes.values() {
+            if !matches!(node.availability, NodeAvailability::Active) {
+                continue;
+            }
+            let client = mgmt_api::Client::new(node.base_url(), self.config.jwt_token.as_deref());
+            match tokio::time::timeout(UTILIZATION_TIMEOUT, client.utilization()).await {
+                Ok(Ok(utilization)) => utilizations.push((node, Instant::now(), utilization)),
+                Ok(Err(e)) => tracing::warn!("Failed to get utilization of node {}: {e}", node.id),
+                Err(_) => tracing::warn!("Timed out getting utilization of node {}", node.id),
+            }
+        }
+
+        let locked = self.inner.read().unwrap();
+        let mut samples = self.getpage_samples.lock().unwrap();
+        let mut node_utilizations = Vec::new();
+        for (node, sampled_at, utilization) in utilizations {
+            let previous = samples.get(&node.id);
+            let shards = utilization
+                .shards
+                .iter()
+                .map(|shard| {
+                    // Shards attached since the previous sample have no rate yet.
+                    let getpage_rate = previous
+                        .and_then(|(previous_at, counts)| {
+                            let count = *counts.get(&shard.tenant_shard_id)?;
+                            let secs = sampled_at.duration_since(*previous_at).as_secs_f64();
+                            (secs > 0.0)
+                                .then(|| shard.getpage_requests.saturating_sub(count) as f64 / secs)
+                        })
+                        .unwrap_or(0.0);
+                    let movable =
+                        locked
+                            .tenants
+                            .get(&shard.tenant_shard_id)
+                            .is_some_and(|tenant| {
+                                tenant.intent.attached == Some(node.id)
+                                    && !matches!(tenant.policy, PlacementPolicy::Detached)
+                                    && tenant.waiter.load() == tenant.sequence
+                            });
+                    ShardLoad {
+                        tenant_shard_id: shard.tenant_shard_id,
+                        resident_size: shard.resident_size,
+                        getpage_rate,
+                        movable,
+                    }
+                })
+                .collect();
+            if update_samples {
+                samples.insert(
+                    node.id,
+                    (
+                        sampled_at,
+                        utilization
+                            .shards
+                            .iter()
+                            .map(|shard| (shard.tenant_shard_id, shard.getpage_requests))
+                            .collect(),
+                    ),
+                );
+            }
+            node_utilizations.push(NodeUtilization {
+                node_id: node.id,
+                may_schedule: node.may_schedule(),
+                shards,
+            });
+        }
+        node_utilizations.sort_by_key(|node| node.node_id);
+
+        let (nodes, migrations) = optimizer::plan(
+            &node_utilizations,
+            self.config.optimizer_imbalance_threshold,
+            self.config.optimizer_max_migrations,
+        );
+        Ok(OptimizerPlanResponse { nodes, migrations })
+    }
+
     pub(crate) async fn attach_hook(
         &self,
         attach_req: AttachHookRequest,
@@ -903,12 +1390,24 @@ impl Service {
         Ok(())
     }
 
+    /// Wait for any other operation changing the placement of the tenant's shards to complete, and
+    /// hold off the next ones until the returned guard is dropped.
+    async fn tenant_op_lock(&self, tenant_id: TenantId) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = self
+            .tenant_op_locks
+            .lock()
+            .unwrap()
+            .entry(tenant_id)
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+
     /// This API is used by the cloud control plane to do coarse-grained control of tenants:
     /// - Call with mode Attached* to upsert the tenant.
     /// - Call with mode Detached to switch to PolicyMode::Detached
-    ///
-    /// In future, calling with mode Secondary may switch to a detach-lite mode in which a tenant only has
-    /// secondary locations.
+    /// - Calling with mode Secondary is refused with 400 Bad Request: there is no placement
+    ///   policy for a tenant which only has secondary locations, use Detached instead.
     pub(crate) async fn tenant_location_config(
         &self,
         tenant_id: TenantId,
@@ -920,6 +1419,36 @@ impl Service {
             )));
         }
 
+        let _tenant_lock = self.tenant_op_lock(tenant_id).await;
+
+        // Use location config mode as an indicator of policy: if they ask for
+        // attached we go to default HA attached mode.  If they ask for detached we detach.
+        let policy = match req.config.mode {
+            LocationConfigMode::Detached => PlacementPolicy::Detached,
+            LocationConfigMode::Secondary => {
+                return Err(ApiError::BadRequest(anyhow::anyhow!(
+                    "Secondary mode is not supported, use Detached to detach the tenant"
+                )));
+            }
+            LocationConfigMode::AttachedMulti
+            | LocationConfigMode::AttachedSingle
+            | LocationConfigMode::AttachedStale => {
+                if self.inner.read().unwrap().nodes.len() > 1 {
+                    PlacementPolicy::Double(1)
+                } else {
+                    // Convenience for dev/test: if we just have one pageserver, import
+                    // tenants into Single mode so that scheduling will succeed.
+                    PlacementPolicy::Single
+                }
+            }
+        };
+
+        // The policy of existing shards is persisted before it is applied below: this is a no-op
+        // if the tenant doesn't exist yet, in which case it is persisted on creation.
+        self.persistence
+            .update_tenant_placement_policy(tenant_id, &policy)
+            .await?;
+
         let mut waiters = Vec::new();
         let mut result = TenantLocationConfigResponse { shards: Vec::new() };
         let maybe_create = {
@@ -944,30 +1473,7 @@ impl Service {
                 // callers' generations may be ignored.  This represents a one-way migration of tenants from the outer
                 // cloud control plane into this service.
 
-                // Use location config mode as an indicator of policy: if they ask for
-                // attached we go to default HA attached mode.  If they ask for secondary
-                // we go to secondary-only mode.  If they ask for detached we detach.
-                match req.config.mode {
-                    LocationConfigMode::Detached => {
-                        shard.policy = PlacementPolicy::Detached;
-                    }
-                    LocationConfigMode::Secondary => {
-                        // TODO: implement secondary-only mode.
-                        todo!();
-                    }
-                    LocationConfigMode::AttachedMulti
-                    | LocationConfigMode::AttachedSingle
-                    | LocationConfigMode::AttachedStale => {
-                        // TODO: persistence for changes in policy
-                        if pageservers.len() > 1 {
-                            shard.policy = PlacementPolicy::Double(1)
-                        } else {
-                            // Convenience for dev/test: if we just have one pageserver, import
-                            // tenants into Single mode so that scheduling will succeed.
-                            shard.policy = PlacementPolicy::Single
-                        }
-                    }
-                }
+                shard.policy = policy.clone();
 
                 shard.schedule(&mut scheduler)?;
 
@@ -1066,6 +1572,8 @@ impl Service {
     }
 
     pub(crate) async fn tenant_delete(&self, tenant_id: TenantId) -> Result<StatusCode, ApiError> {
+        let _tenant_lock = self.tenant_op_lock(tenant_id).await;
+
         // TODO: refactor into helper
         let targets = {
             let locked = self.inner.read().unwrap();
@@ -1136,6 +1644,7 @@ impl Service {
             locked
                 .tenants
                 .retain(|tenant_shard_id, _shard| tenant_shard_id.tenant_id != tenant_id);
+            self.tenant_op_locks.lock().unwrap().remove(&tenant_id);
             tracing::info!(
                 "Deleted tenant {tenant_id}, now have {} tenants",
                 locked.tenants.len()
@@ -1421,19 +1930,14 @@ impl Service {
         tenant_id: TenantId,
         split_req: TenantShardSplitRequest,
     ) -> Result<TenantShardSplitResponse, ApiError> {
+        let _tenant_lock = self.tenant_op_lock(tenant_id).await;
+
         let mut policy = None;
         let mut shard_ident = None;
 
         // TODO: put a cancellation token on Service for clean shutdown
         let cancel = CancellationToken::new();
 
-        // A parent shard which will be split
-        struct SplitTarget {
-            parent_id: TenantShardId,
-            node: Node,
-            child_ids: Vec<TenantShardId>,
-        }
-
         // Validate input, and calculate which shards we will create
         let (old_shard_count, targets, compute_hook) = {
             let locked = self.inner.read().unwrap();
@@ -1598,65 +2102,49 @@ impl Service {
             }
         }
 
-        // FIXME: we have now committed the shard split state to the database, so any subsequent
-        // failure needs to roll it back.  We will later wrap this function in logic to roll back
-        // the split if it fails.
-        // (https://github.com/neondatabase/neon/issues/6676)
-
-        // TODO: issue split calls concurrently (this only matters once we're splitting
-        // N>1 shards into M shards -- initially we're usually splitting 1 shard into N).
+        // The parent shards now belong to the split: until it completes or is rolled back, they
+        // may not be rescheduled or reconciled.
+        {
+            let mut locked = self.inner.write().unwrap();
+            for target in &targets {
+                if let Some(parent) = locked.tenants.get_mut(&target.parent_id) {
+                    parent.splitting = SplitState::Splitting;
+                }
+            }
+        }
 
-        for target in &targets {
-            let SplitTarget {
-                parent_id,
-                node,
-                child_ids,
-            } = target;
-            let client = mgmt_api::Client::new(node.base_url(), self.config.jwt_token.as_deref());
-            let response = client
-                .tenant_shard_split(
-                    *parent_id,
-                    TenantShardSplitRequest {
-                        new_shard_count: split_req.new_shard_count,
-                    },
-                )
+        let new_shard_count = ShardCount(split_req.new_shard_count);
+        if let Err(e) = self
+            .do_tenant_shard_split(tenant_id, old_shard_count, new_shard_count, &targets)
+            .await
+        {
+            tracing::warn!("Failed to split {tenant_id}, rolling back: {e}");
+            match self
+                .abort_tenant_shard_split(tenant_id, new_shard_count, &targets)
                 .await
-                .map_err(|e| ApiError::Conflict(format!("Failed to split {}: {}", parent_id, e)))?;
-
-            tracing::info!(
-                "Split {} into {}",
-                parent_id,
-                response
-                    .new_shards
-                    .iter()
-                    .map(|s| format!("{:?}", s))
-                    .collect::<Vec<_>>()
-                    .join(",")
-            );
-
-            if &response.new_shards != child_ids {
-                // This should never happen: the pageserver should agree with us on how shard splits work.
-                return Err(ApiError::InternalServerError(anyhow::anyhow!(
-                    "Splitting shard {} resulted in unexpected IDs: {:?} (expected {:?})",
-                    parent_id,
-                    response.new_shards,
-                    child_ids
-                )));
+            {
+                Ok(AbortShardSplitStatus::Aborted) => return Err(e),
+                Ok(AbortShardSplitStatus::Complete) => {
+                    // We failed after persisting the completion, e.g. on a database error
+                    // reporting: the children are in place, so carry on.
+                    tracing::info!("Split of {tenant_id} was already complete");
+                }
+                Err(abort_e) => {
+                    // The tenant stays marked as splitting in the database: the next attempt to
+                    // split it fails until we restart and roll it back.  The parents are still
+                    // the shards we serve until then, so let them be reconciled again.
+                    tracing::error!("Failed to roll back split of {tenant_id}: {abort_e}");
+                    let waiters = self.release_split_parents(&targets);
+                    if let Err(wait_e) = self.await_waiters(waiters).await {
+                        tracing::warn!(
+                            "Failed to attach parent shards of {tenant_id} again: {wait_e}"
+                        );
+                    }
+                    return Err(e);
+                }
             }
         }
 
-        // TODO: if the pageserver restarted concurrently with our split API call,
-        // the actual generation of the child shard might differ from the generation
-        // we expect it to have.  In order for our in-database generation to end up
-        // correct, we should carry the child generation back in the response and apply it here
-        // in complete_shard_split (and apply the correct generation in memory)
-        // (or, we can carry generation in the request and reject the request if
-        //  it doesn't match, but that requires more retry logic on this side)
-
-        self.persistence
-            .complete_shard_split(tenant_id, old_shard_count)
-            .await?;
-
         // Replace all the shards we just split with their children
         let mut response = TenantShardSplitResponse {
             new_shards: Vec::new(),
@@ -1736,23 +2224,214 @@ impl Service {
         Ok(response)
     }
 
-    pub(crate) async fn tenant_shard_migrate(
+    /// Split the parent shards on their pageservers, wait for the children to activate, and
+    /// persist the completion of the split.  On failure, the split must be rolled back with
+    /// [`Self::abort_tenant_shard_split`].
+    async fn do_tenant_shard_split(
         &self,
-        tenant_shard_id: TenantShardId,
-        migrate_req: TenantShardMigrateRequest,
-    ) -> Result<TenantShardMigrateResponse, ApiError> {
-        let waiter = {
-            let mut locked = self.inner.write().unwrap();
-
-            let result_tx = locked.result_tx.clone();
-            let pageservers = locked.nodes.clone();
-            let compute_hook = locked.compute_hook.clone();
+        tenant_id: TenantId,
+        old_shard_count: ShardCount,
+        new_shard_count: ShardCount,
+        targets: &[SplitTarget],
+    ) -> Result<(), ApiError> {
+        // TODO: issue split calls concurrently (this only matters once we're splitting
+        // N>1 shards into M shards -- initially we're usually splitting 1 shard into N).
 
-            let Some(shard) = locked.tenants.get_mut(&tenant_shard_id) else {
-                return Err(ApiError::NotFound(
-                    anyhow::anyhow!("Tenant shard not found").into(),
-                ));
-            };
+        for target in targets {
+            let SplitTarget {
+                parent_id,
+                node,
+                child_ids,
+            } = target;
+            let client = mgmt_api::Client::new(node.base_url(), self.config.jwt_token.as_deref());
+            let response = client
+                .tenant_shard_split(
+                    *parent_id,
+                    TenantShardSplitRequest {
+                        new_shard_count: new_shard_count.0,
+                    },
+                )
+                .await
+                .map_err(|e| ApiError::Conflict(format!("Failed to split {}: {}", parent_id, e)))?;
+
+            tracing::info!(
+                "Split {} into {}",
+                parent_id,
+                response
+                    .new_shards
+                    .iter()
+                    .map(|s| format!("{:?}", s))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            );
+
+            if &response.new_shards != child_ids {
+                // This should never happen: the pageserver should agree with us on how shard splits work.
+                return Err(ApiError::InternalServerError(anyhow::anyhow!(
+                    "Splitting shard {} resulted in unexpected IDs: {:?} (expected {:?})",
+                    parent_id,
+                    response.new_shards,
+                    child_ids
+                )));
+            }
+        }
+
+        // The pageserver spawns the children in the background: don't let go of the parents
+        // until all the children can serve.
+        let deadline = Instant::now() + SHARD_SPLIT_ACTIVATE_TIMEOUT;
+        for target in targets {
+            let client =
+                mgmt_api::Client::new(target.node.base_url(), self.config.jwt_token.as_deref());
+            for child_id in &target.child_ids {
+                loop {
+                    match client.tenant_details(*child_id).await {
+                        Ok(details) => match details.tenant_info.state {
+                            models::TenantState::Active => break,
+                            models::TenantState::Broken { reason, .. } => {
+                                return Err(ApiError::InternalServerError(anyhow::anyhow!(
+                                    "Child shard {child_id} is broken: {reason}"
+                                )));
+                            }
+                            state => {
+                                tracing::info!("Waiting for child shard {child_id} ({state:?})")
+                            }
+                        },
+                        Err(e) => tracing::info!("Waiting for child shard {child_id} ({e})"),
+                    }
+                    if Instant::now() > deadline {
+                        return Err(ApiError::Timeout(
+                            format!("Child shard {child_id} did not activate").into(),
+                        ));
+                    }
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+
+        // TODO: if the pageserver restarted concurrently with our split API call,
+        // the actual generation of the child shard might differ from the generation
+        // we expect it to have.  In order for our in-database generation to end up
+        // correct, we should carry the child generation back in the response and apply it here
+        // in complete_shard_split (and apply the correct generation in memory)
+        // (or, we can carry generation in the request and reject the request if
+        //  it doesn't match, but that requires more retry logic on this side)
+
+        self.persistence
+            .complete_shard_split(tenant_id, old_shard_count)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Undo a failed split: drop the child shards from the database and their pageservers, and
+    /// attach the parent shards again.
+    async fn abort_tenant_shard_split(
+        &self,
+        tenant_id: TenantId,
+        new_shard_count: ShardCount,
+        targets: &[SplitTarget],
+    ) -> Result<AbortShardSplitStatus, ApiError> {
+        let status = self
+            .persistence
+            .abort_shard_split(tenant_id, new_shard_count)
+            .await?;
+        if status == AbortShardSplitStatus::Complete {
+            return Ok(status);
+        }
+
+        for target in targets {
+            let client =
+                mgmt_api::Client::new(target.node.base_url(), self.config.jwt_token.as_deref());
+            for child_id in &target.child_ids {
+                let detach = LocationConfig {
+                    mode: LocationConfigMode::Detached,
+                    generation: None,
+                    secondary_conf: None,
+                    shard_number: child_id.shard_number.0,
+                    shard_count: child_id.shard_count.0,
+                    shard_stripe_size: 0,
+                    tenant_conf: models::TenantConfig::default(),
+                };
+                if let Err(e) = client.location_config(*child_id, detach, None).await {
+                    // Non-fatal: the child is not in our database any more, so it is detached as an
+                    // unknown shard the next time we start up.
+                    tracing::warn!(
+                        "Failed to detach child shard {child_id} from pageserver {}: {e}",
+                        target.node.id
+                    );
+                }
+            }
+        }
+
+        let waiters = self.release_split_parents(targets);
+        if let Err(e) = self.await_waiters(waiters).await {
+            tracing::warn!("Failed to attach parent shards of {tenant_id} again: {e}");
+        }
+
+        Ok(status)
+    }
+
+    /// Clear the splitting flag of the parent shards of a split which did not complete, and
+    /// reconcile them.  The pageserver may have shut the parents down while splitting them:
+    /// forget what we know of their locations, so that reconciling attaches them again.
+    fn release_split_parents(&self, targets: &[SplitTarget]) -> Vec<ReconcilerWaiter> {
+        let mut locked = self.inner.write().unwrap();
+        let result_tx = locked.result_tx.clone();
+        let compute_hook = locked.compute_hook.clone();
+        let pageservers = locked.nodes.clone();
+        let mut waiters = Vec::new();
+        for target in targets {
+            let Some(parent) = locked.tenants.get_mut(&target.parent_id) else {
+                continue;
+            };
+            parent.splitting = SplitState::Idle;
+            parent
+                .observed
+                .locations
+                .insert(target.node.id, ObservedStateLocation { conf: None });
+            parent.sequence = parent.sequence.next();
+            if let Some(waiter) = parent.maybe_reconcile(
+                result_tx.clone(),
+                &pageservers,
+                &compute_hook,
+                &self.config,
+                &self.persistence,
+            ) {
+                waiters.push(waiter);
+            }
+        }
+        waiters
+    }
+
+    pub(crate) async fn tenant_shard_migrate(
+        &self,
+        tenant_shard_id: TenantShardId,
+        migrate_req: TenantShardMigrateRequest,
+    ) -> Result<TenantShardMigrateResponse, ApiError> {
+        self.migrate_shard(tenant_shard_id, migrate_req, ScheduleReason::Api)
+            .await
+    }
+
+    /// Attach a shard to the node in the request: `reason` is what asked for it, for
+    /// [`metrics::SCHEDULE_DECISIONS`].
+    async fn migrate_shard(
+        &self,
+        tenant_shard_id: TenantShardId,
+        migrate_req: TenantShardMigrateRequest,
+        reason: ScheduleReason,
+    ) -> Result<TenantShardMigrateResponse, ApiError> {
+        let waiter = {
+            let mut locked = self.inner.write().unwrap();
+
+            let result_tx = locked.result_tx.clone();
+            let pageservers = locked.nodes.clone();
+            let compute_hook = locked.compute_hook.clone();
+
+            let Some(shard) = locked.tenants.get_mut(&tenant_shard_id) else {
+                return Err(ApiError::NotFound(
+                    anyhow::anyhow!("Tenant shard not found").into(),
+                ));
+            };
 
             if shard.intent.attached == Some(migrate_req.node_id) {
                 // No-op case: we will still proceed to wait for reconciliation in case it is
@@ -1781,6 +2460,7 @@ impl Service {
                     }
                 }
                 shard.intent.attached = Some(migrate_req.node_id);
+                reason.record();
 
                 tracing::info!("Migrating: new intent {:?}", shard.intent);
                 shard.sequence = shard.sequence.next();
@@ -1804,6 +2484,340 @@ impl Service {
         Ok(TenantShardMigrateResponse {})
     }
 
+    /// Migrate a shard to a node once a secondary location there is warm, so that the shard
+    /// doesn't serve its reads from remote storage after the cutover.  The secondary location is
+    /// created if the node doesn't hold one already, and removed again if the migration doesn't
+    /// cut over to it.
+    ///
+    /// The migration runs in the background: this returns its initial status, and its progress
+    /// is reported by [`Self::tenant_shard_migration`].  It holds the [`Self::tenant_op_lock`]
+    /// of the tenant until it is over.
+    pub(crate) async fn tenant_shard_migrate_warm(
+        self: &Arc<Self>,
+        tenant_shard_id: TenantShardId,
+        node_id: NodeId,
+        warm_threshold: f64,
+    ) -> Result<TenantShardMigrationStatus, ApiError> {
+        if !(0.0..=1.0).contains(&warm_threshold) {
+            return Err(ApiError::BadRequest(anyhow::anyhow!(
+                "warm_threshold must be between 0 and 1"
+            )));
+        }
+        Self::check_warm_migration(&self.inner.read().unwrap(), tenant_shard_id, node_id)?;
+
+        // Register the migration before waiting for the tenant: this cancels an earlier migration
+        // of the shard, which then releases the tenant.
+        let status = TenantShardMigrationStatus {
+            tenant_shard_id,
+            node_id,
+            state: TenantShardMigrationState::WarmingUp,
+            progress: None,
+            error: None,
+        };
+        let cancel = CancellationToken::new();
+        let migration = TenantShardMigration {
+            status: status.clone(),
+            cancel: cancel.clone(),
+        };
+        let mut migrations = self.migrations.lock().unwrap();
+        if let Some(previous) = migrations.insert(tenant_shard_id, migration) {
+            previous.cancel.cancel();
+        }
+        update_warm_migration_metrics(&migrations);
+        drop(migrations);
+
+        let tenant_lock = self.tenant_op_lock(tenant_shard_id.tenant_id).await;
+        if cancel.is_cancelled() {
+            // A newer migration of the shard replaced this one while we waited.
+            return Ok(status);
+        }
+
+        let prepared = {
+            let mut locked = self.inner.write().unwrap();
+
+            let result_tx = locked.result_tx.clone();
+            let pageservers = locked.nodes.clone();
+            let compute_hook = locked.compute_hook.clone();
+
+            // The shard may have changed while we waited for the tenant, e.g. been split.
+            Self::check_warm_migration(&locked, tenant_shard_id, node_id).map(|()| {
+                let shard = locked
+                    .tenants
+                    .get_mut(&tenant_shard_id)
+                    .expect("checked above");
+
+                let origin = shard.intent.attached;
+                let added_secondary =
+                    origin != Some(node_id) && !shard.intent.secondary.contains(&node_id);
+                if added_secondary {
+                    shard.intent.secondary.push(node_id);
+                    tracing::info!("Warm migration: new intent {:?}", shard.intent);
+                    shard.sequence = shard.sequence.next();
+                }
+
+                let waiter = shard.maybe_reconcile(
+                    result_tx,
+                    &pageservers,
+                    &compute_hook,
+                    &self.config,
+                    &self.persistence,
+                );
+                (origin, added_secondary, waiter)
+            })
+        };
+        let (origin, added_secondary, waiter) = match prepared {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                self.update_migration(tenant_shard_id, &cancel, |status| {
+                    status.state = TenantShardMigrationState::Failed;
+                    status.error = Some(format!("{e}"));
+                });
+                return Err(e);
+            }
+        };
+
+        let this = self.clone();
+        tokio::task::spawn(async move {
+            let _tenant_lock = tenant_lock;
+            let result = this
+                .warm_migrate(
+                    tenant_shard_id,
+                    node_id,
+                    origin,
+                    warm_threshold,
+                    waiter,
+                    &cancel,
+                )
+                .await;
+            this.warm_migration_cleanup(tenant_shard_id, node_id, origin, added_secondary)
+                .await;
+            this.update_migration(tenant_shard_id, &cancel, |status| match result {
+                Ok(()) => {
+                    tracing::info!(
+                        "Warm migration of {tenant_shard_id} to node {node_id} is complete"
+                    );
+                    status.state = TenantShardMigrationState::Complete;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Warm migration of {tenant_shard_id} to node {node_id} failed: {e:#}"
+                    );
+                    status.state = TenantShardMigrationState::Failed;
+                    status.error = Some(format!("{e:#}"));
+                }
+            });
+        });
+
+        Ok(status)
+    }
+
+    fn check_warm_migration(
+        locked: &ServiceState,
+        tenant_shard_id: TenantShardId,
+        node_id: NodeId,
+    ) -> Result<(), ApiError> {
+        if !locked.nodes.contains_key(&node_id) {
+            return Err(ApiError::BadRequest(anyhow::anyhow!(
+                "Node {node_id} not found"
+            )));
+        }
+        let Some(shard) = locked.tenants.get(&tenant_shard_id) else {
+            return Err(ApiError::NotFound(
+                anyhow::anyhow!("Tenant shard not found").into(),
+            ));
+        };
+        if matches!(shard.policy, PlacementPolicy::Detached) {
+            return Err(ApiError::BadRequest(anyhow::anyhow!(
+                "Cannot migrate a tenant that is PlacementPolicy::Detached: configure it to an attached policy first"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Bring the secondary locations of a shard back in line with its policy once a warm
+    /// migration is over.  If the migration didn't cut over, the secondary location it added on
+    /// the node is removed.  If it did, the origin was demoted next to the secondary locations
+    /// the shard already had, and is removed first if there are too many.
+    async fn warm_migration_cleanup(
+        &self,
+        tenant_shard_id: TenantShardId,
+        node_id: NodeId,
+        origin: Option<NodeId>,
+        added_secondary: bool,
+    ) {
+        let waiter = {
+            let mut locked = self.inner.write().unwrap();
+
+            let result_tx = locked.result_tx.clone();
+            let pageservers = locked.nodes.clone();
+            let compute_hook = locked.compute_hook.clone();
+
+            let Some(shard) = locked.tenants.get_mut(&tenant_shard_id) else {
+                return;
+            };
+
+            let secondary = shard.intent.secondary.clone();
+            if shard.intent.attached == Some(node_id) {
+                if let PlacementPolicy::Double(n) = shard.policy {
+                    if shard.intent.secondary.len() > n {
+                        if let Some(origin) = origin {
+                            shard.intent.secondary.retain(|s| *s != origin);
+                        }
+                        shard.intent.secondary.truncate(n);
+                    }
+                }
+            } else if added_secondary {
+                shard.intent.secondary.retain(|s| *s != node_id);
+            }
+            if shard.intent.secondary == secondary {
+                return;
+            }
+
+            tracing::info!("Warm migration: new intent {:?}", shard.intent);
+            shard.sequence = shard.sequence.next();
+            shard.maybe_reconcile(
+                result_tx,
+                &pageservers,
+                &compute_hook,
+                &self.config,
+                &self.persistence,
+            )
+        };
+
+        if let Some(waiter) = waiter {
+            if let Err(e) = waiter.wait_timeout(RECONCILE_TIMEOUT).await {
+                tracing::warn!(
+                    "Failed to reconcile {tenant_shard_id} after its warm migration: {e}"
+                );
+            }
+        }
+    }
+
+    /// The progress of the latest warm migration of a shard.
+    pub(crate) fn tenant_shard_migration(
+        &self,
+        tenant_shard_id: TenantShardId,
+    ) -> Result<TenantShardMigrationStatus, ApiError> {
+        match self.migrations.lock().unwrap().get(&tenant_shard_id) {
+            Some(migration) => Ok(migration.status.clone()),
+            None => Err(ApiError::NotFound(
+                anyhow::anyhow!("Shard {tenant_shard_id} was never migrated warm").into(),
+            )),
+        }
+    }
+
+    async fn warm_migrate(
+        &self,
+        tenant_shard_id: TenantShardId,
+        node_id: NodeId,
+        origin: Option<NodeId>,
+        warm_threshold: f64,
+        waiter: Option<ReconcilerWaiter>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        // Wait for the secondary location to be created.
+        if let Some(waiter) = waiter {
+            waiter.wait_timeout(RECONCILE_TIMEOUT).await?;
+        }
+
+        if origin != Some(node_id) {
+            let client = |node_id| {
+                let locked = self.inner.read().unwrap();
+                let node = locked
+                    .nodes
+                    .get(&node_id)
+                    .ok_or_else(|| anyhow::anyhow!("Node {node_id} not found"))?;
+                anyhow::Ok(mgmt_api::Client::new(
+                    node.base_url(),
+                    self.config.jwt_token.as_deref(),
+                ))
+            };
+
+            // The secondary location follows the heatmap of the attached one: make it fresh.
+            if let Some(origin) = origin {
+                client(origin)?
+                    .tenant_heatmap_upload(tenant_shard_id)
+                    .await?;
+            }
+
+            let client = client(node_id)?;
+            let deadline = Instant::now() + MIGRATION_WARMUP_TIMEOUT;
+            loop {
+                // Downloads may take longer than the poll interval: we stop waiting for them to
+                // report the progress meanwhile, and the next request joins the running download.
+                let downloaded = match tokio::time::timeout(
+                    MIGRATION_WARMUP_POLL_INTERVAL,
+                    client.tenant_secondary_download(tenant_shard_id),
+                )
+                .await
+                {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        tracing::info!("Secondary download on node {node_id} failed: {e}");
+                        tokio::time::sleep(MIGRATION_WARMUP_POLL_INTERVAL).await;
+                        false
+                    }
+                    Err(_) => false,
+                };
+                if cancel.is_cancelled() {
+                    return Ok(());
+                }
+
+                let progress = client.tenant_secondary_status(tenant_shard_id).await?;
+                // Before the first download, the progress doesn't cover anything yet.
+                let warm = downloaded
+                    || (progress.bytes_total > 0
+                        && progress.bytes_downloaded as f64 / progress.bytes_total as f64
+                            >= warm_threshold);
+                self.update_migration(tenant_shard_id, cancel, |status| {
+                    status.progress = Some(progress)
+                });
+                if warm {
+                    break;
+                }
+                if Instant::now() > deadline {
+                    anyhow::bail!(
+                        "Secondary location on node {node_id} did not warm up within {MIGRATION_WARMUP_TIMEOUT:?}"
+                    );
+                }
+            }
+        }
+
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+        self.update_migration(tenant_shard_id, cancel, |status| {
+            status.state = TenantShardMigrationState::CuttingOver
+        });
+        let migrate_req = TenantShardMigrateRequest {
+            tenant_shard_id,
+            node_id,
+            warm_threshold: None,
+        };
+        self.migrate_shard(tenant_shard_id, migrate_req, ScheduleReason::WarmMigration)
+            .await?;
+
+        Ok(())
+    }
+
+    fn update_migration(
+        &self,
+        tenant_shard_id: TenantShardId,
+        cancel: &CancellationToken,
+        update: impl FnOnce(&mut TenantShardMigrationStatus),
+    ) {
+        // A cancelled migration was replaced by the one of the shard in the map.  Migrations are
+        // cancelled while holding the lock, so checking under it is enough.
+        let mut migrations = self.migrations.lock().unwrap();
+        if cancel.is_cancelled() {
+            return;
+        }
+        if let Some(migration) = migrations.get_mut(&tenant_shard_id) {
+            update(&mut migration.status);
+        }
+        update_warm_migration_metrics(&migrations);
+    }
+
     /// This is for debug/support only: we simply drop all state for a tenant, without
     /// detaching or deleting it on pageservers.
     pub(crate) async fn tenant_drop(&self, tenant_id: TenantId) -> Result<(), ApiError> {
@@ -1828,6 +2842,13 @@ impl Service {
     ///
     /// TODO: proper node deletion API that unhooks things more gracefully
     pub(crate) async fn node_drop(&self, node_id: NodeId) -> Result<(), ApiError> {
+        let _node_lock = self.node_op_lock.lock().await;
+        self.remove_node(node_id).await
+    }
+
+    /// Ordering: call this while holding [`Self::node_op_lock`].  The node is removed from the
+    /// database first: if we then crash, we will drop the in-memory state.
+    async fn remove_node(&self, node_id: NodeId) -> Result<(), ApiError> {
         self.persistence.delete_node(node_id).await?;
 
         let mut locked = self.inner.write().unwrap();
@@ -1839,6 +2860,7 @@ impl Service {
         let mut nodes = (*locked.nodes).clone();
         nodes.remove(&node_id);
         locked.nodes = Arc::new(nodes);
+        metrics::remove_node_metrics(&node_id.to_string());
 
         Ok(())
     }
@@ -1861,6 +2883,9 @@ impl Service {
         &self,
         register_req: NodeRegisterRequest,
     ) -> Result<(), ApiError> {
+        // Holding this makes the pre-check authoritative: the node can't be inserted concurrently.
+        let _node_lock = self.node_op_lock.lock().await;
+
         // Pre-check for an already-existing node
         {
             let locked = self.inner.read().unwrap();
@@ -1871,6 +2896,7 @@ impl Service {
                     && node.listen_http_port == register_req.listen_http_port
                     && node.listen_pg_addr == register_req.listen_pg_addr
                     && node.listen_pg_port == register_req.listen_pg_port
+                    && node.availability_zone_id == register_req.availability_zone_id
                 {
                     tracing::info!(
                         "Node {} re-registered with matching address",
@@ -1882,11 +2908,12 @@ impl Service {
                     // the node.  Safest/simplest thing is to refuse it, and usually we deploy with
                     // a fixed address through the lifetime of a node.
                     tracing::warn!(
-                        "Node {} tried to register with different address",
+                        "Node {} tried to register with different address or availability zone",
                         register_req.node_id
                     );
                     return Err(ApiError::Conflict(
-                        "Node is already registered with different address".to_string(),
+                        "Node is already registered with different address or availability zone"
+                            .to_string(),
                     ));
                 }
             }
@@ -1901,11 +2928,11 @@ impl Service {
             listen_http_port: register_req.listen_http_port,
             listen_pg_addr: register_req.listen_pg_addr,
             listen_pg_port: register_req.listen_pg_port,
+            availability_zone_id: register_req.availability_zone_id,
             scheduling: NodeSchedulingPolicy::Filling,
             // TODO: we shouldn't really call this Active until we've heartbeated it.
             availability: NodeAvailability::Active,
         };
-        // TODO: idempotency if the node already exists in the database
         self.persistence.insert_node(&new_node).await?;
 
         let mut locked = self.inner.write().unwrap();
@@ -1923,7 +2950,22 @@ impl Service {
         Ok(())
     }
 
-    pub(crate) fn node_configure(&self, config_req: NodeConfigureRequest) -> Result<(), ApiError> {
+    pub(crate) async fn node_configure(
+        &self,
+        config_req: NodeConfigureRequest,
+    ) -> Result<(), ApiError> {
+        // Availability is not persisted, as nodes are considered offline at startup until proven
+        // otherwise: only changes of the scheduling policy go via the database.
+        let _node_lock = match config_req.scheduling {
+            Some(scheduling) => {
+                let node_lock = self.node_op_lock.lock().await;
+                self.persist_node_scheduling(config_req.node_id, scheduling)
+                    .await?;
+                Some(node_lock)
+            }
+            None => None,
+        };
+
         let mut locked = self.inner.write().unwrap();
         let result_tx = locked.result_tx.clone();
         let compute_hook = locked.compute_hook.clone();
@@ -1979,14 +3021,32 @@ impl Service {
 
                 if tenant_state.intent.notify_offline(config_req.node_id) {
                     tenant_state.sequence = tenant_state.sequence.next();
+                    let secondaries = tenant_state.intent.secondary.clone();
                     match tenant_state.schedule(&mut scheduler) {
                         Err(e) => {
                             // It is possible that some tenants will become unschedulable when too many pageservers
                             // go offline: in this case there isn't much we can do other than make the issue observable.
                             // TODO: give TenantState a scheduling error attribute to be queried later.
                             tracing::warn!(%tenant_shard_id, "Scheduling error when marking pageserver {} offline: {e}", config_req.node_id);
+                            self.record_failover_event(
+                                config_req.node_id,
+                                FailoverEventKind::ShardFailoverFailed {
+                                    tenant_shard_id: *tenant_shard_id,
+                                    error: e.to_string(),
+                                },
+                            );
                         }
                         Ok(()) => {
+                            if let Some(to_node_id) = tenant_state.intent.attached {
+                                self.record_failover_event(
+                                    config_req.node_id,
+                                    FailoverEventKind::ShardFailover {
+                                        tenant_shard_id: *tenant_shard_id,
+                                        to_node_id,
+                                        to_secondary: secondaries.contains(&to_node_id),
+                                    },
+                                );
+                            }
                             tenant_state.maybe_reconcile(
                                 result_tx.clone(),
                                 &new_nodes,
@@ -2027,6 +3087,586 @@ impl Service {
         Ok(())
     }
 
+    /// Migrate the shards attached to a node elsewhere, ahead of restarting it: to one of their
+    /// secondary locations if they have one, which is already warm, or else to the least loaded
+    /// node.  The node is not scheduled on until it is filled.
+    ///
+    /// The migrations run in the background: this returns their plan, and their progress is
+    /// reported by [`Self::node_operation`].
+    pub(crate) async fn node_drain(
+        self: &Arc<Self>,
+        node_id: NodeId,
+    ) -> Result<NodeOperationStatus, ApiError> {
+        let _node_lock = self.node_op_lock.lock().await;
+        self.persist_node_scheduling(node_id, NodeSchedulingPolicy::Draining)
+            .await?;
+
+        let mut locked = self.inner.write().unwrap();
+        self.set_node_scheduling(&mut locked, node_id, NodeSchedulingPolicy::Draining)?;
+
+        let (migrations, errors) = Self::plan_node_drain(&locked, node_id);
+        Ok(self.start_node_operation(node_id, NodeOperationKind::Drain, migrations, errors))
+    }
+
+    /// Remove a node, once all its shards are moved to other nodes.
+    ///
+    /// In [`NodeDecommissionMode::Graceful`] mode, the node is first drained in the background
+    /// like by [`Self::node_drain`], then its secondary locations are moved, and it is checked to
+    /// hold no locations before it is removed.  In [`NodeDecommissionMode::Forced`] mode, the node
+    /// is removed right away, and its shards are rescheduled as if it was offline.
+    pub(crate) async fn node_decommission(
+        self: &Arc<Self>,
+        node_id: NodeId,
+        mode: NodeDecommissionMode,
+    ) -> Result<NodeOperationStatus, ApiError> {
+        let _node_lock = self.node_op_lock.lock().await;
+        match mode {
+            NodeDecommissionMode::Graceful => {
+                if let Some(node) = self.inner.read().unwrap().nodes.get(&node_id) {
+                    if matches!(node.availability, NodeAvailability::Offline) {
+                        return Err(ApiError::PreconditionFailed(
+                            format!("Node {node_id} is offline: decommission it with mode=forced")
+                                .into(),
+                        ));
+                    }
+                }
+                self.persist_node_scheduling(node_id, NodeSchedulingPolicy::Draining)
+                    .await?;
+
+                let mut locked = self.inner.write().unwrap();
+                self.set_node_scheduling(&mut locked, node_id, NodeSchedulingPolicy::Draining)?;
+
+                let (migrations, errors) = Self::plan_node_drain(&locked, node_id);
+                Ok(self.start_node_operation(
+                    node_id,
+                    NodeOperationKind::Decommission,
+                    migrations,
+                    errors,
+                ))
+            }
+            NodeDecommissionMode::Forced => {
+                if !self.inner.read().unwrap().nodes.contains_key(&node_id) {
+                    return Err(ApiError::NotFound(
+                        anyhow::anyhow!("Node not registered").into(),
+                    ));
+                }
+                Ok(self.node_decommission_forced(node_id).await?)
+            }
+        }
+    }
+
+    async fn node_decommission_forced(
+        &self,
+        node_id: NodeId,
+    ) -> Result<NodeOperationStatus, ApiError> {
+        tracing::info!("Forcibly decommissioning node {node_id}");
+        // The shards with a location on the node, and whether they are attached there.
+        let affected: HashMap<TenantShardId, bool> = self
+            .inner
+            .read()
+            .unwrap()
+            .tenants
+            .iter()
+            .filter(|(_, shard)| shard.intent.all_pageservers().contains(&node_id))
+            .map(|(tenant_shard_id, shard)| {
+                (*tenant_shard_id, shard.intent.attached == Some(node_id))
+            })
+            .collect();
+        self.remove_node(node_id).await?;
+
+        // The shards of the node were dereferenced with it: reschedule them elsewhere.
+        let mut migrated = Vec::new();
+        let mut errors = Vec::new();
+        let mut locked = self.inner.write().unwrap();
+        let result_tx = locked.result_tx.clone();
+        let compute_hook = locked.compute_hook.clone();
+        let pageservers = locked.nodes.clone();
+        let mut scheduler = Scheduler::new(&locked.tenants, &locked.nodes);
+        for (tenant_shard_id, shard) in &mut locked.tenants {
+            let Some(was_attached) = affected.get(tenant_shard_id) else {
+                continue;
+            };
+            match shard.schedule(&mut scheduler) {
+                Ok(()) => {
+                    if let Some(to_node_id) = shard.intent.attached {
+                        if *was_attached {
+                            migrated.push(PlannedMigration {
+                                tenant_shard_id: *tenant_shard_id,
+                                from_node_id: node_id,
+                                to_node_id,
+                            });
+                        }
+                    }
+                }
+                Err(e) => errors.push(format!("{tenant_shard_id}: {e}")),
+            }
+            shard.maybe_reconcile(
+                result_tx.clone(),
+                &pageservers,
+                &compute_hook,
+                &self.config,
+                &self.persistence,
+            );
+        }
+
+        let status = NodeOperationStatus {
+            node_id,
+            kind: NodeOperationKind::Decommission,
+            state: if errors.is_empty() {
+                NodeOperationState::Complete
+            } else {
+                NodeOperationState::Failed
+            },
+            pending: Vec::new(),
+            migrated,
+            errors,
+        };
+        let operation = NodeOperation {
+            status: status.clone(),
+            cancel: CancellationToken::new(),
+        };
+        let mut operations = self.node_operations.lock().unwrap();
+        if let Some(previous) = operations.insert(node_id, operation) {
+            previous.cancel.cancel();
+        }
+        update_node_operation_metrics(&operations);
+        Ok(status)
+    }
+
+    /// Plan the migrations of the shards attached to a node: to one of their secondary locations
+    /// if they have one on a schedulable node, or else to the node picked by the scheduler.
+    fn plan_node_drain(
+        locked: &ServiceState,
+        node_id: NodeId,
+    ) -> (Vec<PlannedMigration>, Vec<String>) {
+        let mut migrations = Vec::new();
+        let mut errors = Vec::new();
+        let mut scheduler = Scheduler::new(&locked.tenants, &locked.nodes);
+        for (tenant_shard_id, shard) in &locked.tenants {
+            if shard.intent.attached != Some(node_id)
+                || matches!(shard.policy, PlacementPolicy::Detached)
+            {
+                continue;
+            }
+
+            let warm_secondary = shard.intent.secondary.iter().find(|secondary| {
+                locked
+                    .nodes
+                    .get(secondary)
+                    .is_some_and(|node| node.may_schedule())
+            });
+            let to_node_id = match warm_secondary {
+                Some(secondary) => *secondary,
+                None => match scheduler
+                    .schedule_shard(*tenant_shard_id, &shard.intent.all_pageservers())
+                {
+                    Ok(to_node_id) => to_node_id,
+                    Err(e) => {
+                        errors.push(format!("{tenant_shard_id}: {e}"));
+                        continue;
+                    }
+                },
+            };
+            migrations.push(PlannedMigration {
+                tenant_shard_id: *tenant_shard_id,
+                from_node_id: node_id,
+                to_node_id,
+            });
+        }
+
+        (migrations, errors)
+    }
+
+    /// Migrate shards back to a node once it is restarted after a drain, until it has as many
+    /// attached shards as the average node.  The shards that were drained from it come back
+    /// first, then those with a secondary location on it.
+    ///
+    /// Like [`Self::node_drain`], the migrations run in the background.
+    pub(crate) async fn node_fill(
+        self: &Arc<Self>,
+        node_id: NodeId,
+    ) -> Result<NodeOperationStatus, ApiError> {
+        let _node_lock = self.node_op_lock.lock().await;
+        if let Some(node) = self.inner.read().unwrap().nodes.get(&node_id) {
+            if matches!(node.availability, NodeAvailability::Offline) {
+                return Err(ApiError::PreconditionFailed(
+                    format!("Node {node_id} is offline").into(),
+                ));
+            }
+        }
+        self.persist_node_scheduling(node_id, NodeSchedulingPolicy::Filling)
+            .await?;
+
+        let mut locked = self.inner.write().unwrap();
+        self.set_node_scheduling(&mut locked, node_id, NodeSchedulingPolicy::Filling)?;
+
+        let mut attached_counts: HashMap<NodeId, usize> = locked
+            .nodes
+            .values()
+            .filter(|node| node.may_schedule())
+            .map(|node| (node.id, 0))
+            .collect();
+        for shard in locked.tenants.values() {
+            if let Some(count) = shard
+                .intent
+                .attached
+                .and_then(|attached| attached_counts.get_mut(&attached))
+            {
+                *count += 1;
+            }
+        }
+        let fair_share = attached_counts.values().sum::<usize>() / attached_counts.len();
+
+        let drained = match self.node_operations.lock().unwrap().get(&node_id) {
+            Some(op) if op.status.kind == NodeOperationKind::Drain => op
+                .status
+                .migrated
+                .iter()
+                .map(|migration| migration.tenant_shard_id)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let with_secondary = locked
+            .tenants
+            .values()
+            .filter(|shard| shard.intent.secondary.contains(&node_id))
+            .map(|shard| shard.tenant_shard_id);
+        let mut seen = HashSet::new();
+        let candidates = drained
+            .into_iter()
+            .chain(with_secondary)
+            .chain(locked.tenants.keys().copied())
+            .filter(|tenant_shard_id| seen.insert(*tenant_shard_id));
+
+        let mut migrations = Vec::new();
+        for tenant_shard_id in candidates {
+            if attached_counts[&node_id] >= fair_share {
+                break;
+            }
+            let Some(shard) = locked.tenants.get(&tenant_shard_id) else {
+                continue;
+            };
+            let Some(from_node_id) = shard.intent.attached else {
+                continue;
+            };
+            if from_node_id == node_id || matches!(shard.policy, PlacementPolicy::Detached) {
+                continue;
+            }
+            // Only take shards from nodes which would still have their fair share.
+            match attached_counts.get_mut(&from_node_id) {
+                Some(count) if *count > fair_share => *count -= 1,
+                _ => continue,
+            }
+            *attached_counts.get_mut(&node_id).unwrap() += 1;
+            migrations.push(PlannedMigration {
+                tenant_shard_id,
+                from_node_id,
+                to_node_id: node_id,
+            });
+        }
+
+        Ok(self.start_node_operation(node_id, NodeOperationKind::Fill, migrations, Vec::new()))
+    }
+
+    /// The progress of the latest drain or fill of a node.
+    pub(crate) fn node_operation(&self, node_id: NodeId) -> Result<NodeOperationStatus, ApiError> {
+        match self.node_operations.lock().unwrap().get(&node_id) {
+            Some(op) => Ok(op.status.clone()),
+            None => Err(ApiError::NotFound(
+                anyhow::anyhow!("Node {node_id} was never drained, filled or decommissioned")
+                    .into(),
+            )),
+        }
+    }
+
+    /// Ordering: call this while holding [`Self::node_op_lock`], and before applying the policy
+    /// in memory with [`Self::set_node_scheduling`].
+    async fn persist_node_scheduling(
+        &self,
+        node_id: NodeId,
+        scheduling: NodeSchedulingPolicy,
+    ) -> Result<(), ApiError> {
+        if !self.inner.read().unwrap().nodes.contains_key(&node_id) {
+            return Err(ApiError::NotFound(
+                anyhow::anyhow!("Node not registered").into(),
+            ));
+        }
+        self.persistence
+            .update_node_scheduling(node_id, scheduling)
+            .await?;
+        Ok(())
+    }
+
+    fn set_node_scheduling(
+        &self,
+        locked: &mut ServiceState,
+        node_id: NodeId,
+        scheduling: NodeSchedulingPolicy,
+    ) -> Result<(), ApiError> {
+        let mut new_nodes = (*locked.nodes).clone();
+        let Some(node) = new_nodes.get_mut(&node_id) else {
+            return Err(ApiError::NotFound(
+                anyhow::anyhow!("Node not registered").into(),
+            ));
+        };
+        node.scheduling = scheduling;
+        locked.nodes = Arc::new(new_nodes);
+        Ok(())
+    }
+
+    /// Record a new drain, fill or decommission of a node, replacing the previous one, and run its migrations
+    /// in the background.  The migrations of the previous one which are not started yet are
+    /// cancelled.
+    fn start_node_operation(
+        self: &Arc<Self>,
+        node_id: NodeId,
+        kind: NodeOperationKind,
+        migrations: Vec<PlannedMigration>,
+        errors: Vec<String>,
+    ) -> NodeOperationStatus {
+        tracing::info!(
+            "Starting {kind:?} of node {node_id}: {} migrations",
+            migrations.len()
+        );
+        let status = NodeOperationStatus {
+            node_id,
+            kind,
+            state: NodeOperationState::InProgress,
+            pending: migrations.clone(),
+            migrated: Vec::new(),
+            errors,
+        };
+        let cancel = CancellationToken::new();
+        let mut operations = self.node_operations.lock().unwrap();
+        let operation = NodeOperation {
+            status: status.clone(),
+            cancel: cancel.clone(),
+        };
+        if let Some(previous) = operations.insert(node_id, operation) {
+            previous.cancel.cancel();
+        }
+        update_node_operation_metrics(&operations);
+        drop(operations);
+
+        let this = self.clone();
+        tokio::task::spawn(async move {
+            futures::stream::iter(migrations)
+                .for_each_concurrent(NODE_OPERATION_CONCURRENCY, |migration| {
+                    this.node_operation_migrate(node_id, kind, migration, &cancel)
+                })
+                .await;
+            if kind == NodeOperationKind::Decommission {
+                this.node_decommission_secondaries(node_id, &cancel).await;
+            }
+            this.complete_node_operation(node_id, kind, &cancel).await;
+        });
+
+        status
+    }
+
+    async fn node_operation_migrate(
+        &self,
+        node_id: NodeId,
+        kind: NodeOperationKind,
+        migration: PlannedMigration,
+        cancel: &CancellationToken,
+    ) {
+        if cancel.is_cancelled() {
+            return;
+        }
+
+        // The shard may have been migrated or deleted since the migration was planned.
+        let attached = self
+            .inner
+            .read()
+            .unwrap()
+            .tenants
+            .get(&migration.tenant_shard_id)
+            .and_then(|shard| shard.intent.attached);
+        let result = if attached == Some(migration.from_node_id) {
+            let migrate_req = TenantShardMigrateRequest {
+                tenant_shard_id: migration.tenant_shard_id,
+                node_id: migration.to_node_id,
+                warm_threshold: None,
+            };
+            self.migrate_shard(migration.tenant_shard_id, migrate_req, kind.into())
+                .await
+                .map(|_| true)
+        } else {
+            Ok(false)
+        };
+
+        // A cancelled operation was replaced by the one of the node in the map.  Operations are
+        // cancelled while holding the lock, so checking under it is enough.
+        let mut operations = self.node_operations.lock().unwrap();
+        if cancel.is_cancelled() {
+            return;
+        }
+        let Some(op) = operations.get_mut(&node_id) else {
+            return;
+        };
+        op.status
+            .pending
+            .retain(|pending| pending.tenant_shard_id != migration.tenant_shard_id);
+        match result {
+            Ok(true) => op.status.migrated.push(migration),
+            Ok(false) => {}
+            Err(e) => {
+                tracing::warn!("Failed to migrate {}: {e}", migration.tenant_shard_id);
+                op.status
+                    .errors
+                    .push(format!("{}: {e}", migration.tenant_shard_id));
+            }
+        }
+        update_node_operation_metrics(&operations);
+    }
+
+    /// Once the attached shards of a node being decommissioned are migrated, move its secondary
+    /// locations to other nodes, and check with the node itself that it holds no location.
+    async fn node_decommission_secondaries(&self, node_id: NodeId, cancel: &CancellationToken) {
+        if cancel.is_cancelled() {
+            return;
+        }
+
+        let mut errors = Vec::new();
+        let waiters = {
+            let mut locked = self.inner.write().unwrap();
+            let result_tx = locked.result_tx.clone();
+            let compute_hook = locked.compute_hook.clone();
+            let pageservers = locked.nodes.clone();
+            let mut scheduler = Scheduler::new(&locked.tenants, &locked.nodes);
+            let mut waiters = Vec::new();
+            for (tenant_shard_id, shard) in &mut locked.tenants {
+                if shard.intent.secondary.contains(&node_id) {
+                    shard.intent.secondary.retain(|n| *n != node_id);
+                    shard.sequence = shard.sequence.next();
+                    if let Err(e) = shard.schedule(&mut scheduler) {
+                        errors.push(format!("{tenant_shard_id}: {e}"));
+                    }
+                }
+                // Forget what we know of a location left on the node, such as the origin of a
+                // migration of a shard with a single location: reconciling then detaches it.
+                if !shard.intent.all_pageservers().contains(&node_id) {
+                    if let Some(observed_loc) = shard.observed.locations.get_mut(&node_id) {
+                        observed_loc.conf = None;
+                    }
+                }
+                if let Some(waiter) = shard.maybe_reconcile(
+                    result_tx.clone(),
+                    &pageservers,
+                    &compute_hook,
+                    &self.config,
+                    &self.persistence,
+                ) {
+                    waiters.push(waiter);
+                }
+            }
+            waiters
+        };
+        if let Err(e) = self.await_waiters(waiters).await {
+            errors.push(format!("Failed to move secondary locations: {e}"));
+        }
+
+        // Our observed state only covers the shards we know of.
+        if errors.is_empty() {
+            let node = self.inner.read().unwrap().nodes.get(&node_id).cloned();
+            match node {
+                Some(node) => {
+                    let client =
+                        mgmt_api::Client::new(node.base_url(), self.config.jwt_token.as_deref());
+                    match client.list_location_config().await {
+                        Ok(response) if response.tenant_shards.is_empty() => {}
+                        Ok(response) => errors.push(format!(
+                            "Node {node_id} still holds {} locations",
+                            response.tenant_shards.len()
+                        )),
+                        Err(e) => errors.push(format!(
+                            "Failed to list the locations on node {node_id}: {e}"
+                        )),
+                    }
+                }
+                None => errors.push(format!("Node {node_id} was removed")),
+            }
+        }
+
+        let mut operations = self.node_operations.lock().unwrap();
+        if cancel.is_cancelled() {
+            return;
+        }
+        if let Some(op) = operations.get_mut(&node_id) {
+            op.status.errors.extend(errors);
+        }
+    }
+
+    async fn complete_node_operation(
+        &self,
+        node_id: NodeId,
+        kind: NodeOperationKind,
+        cancel: &CancellationToken,
+    ) {
+        // Operations are replaced while holding this lock, so a fill or decommission which is not
+        // cancelled here may activate or remove the node without racing with the next operation.
+        let _node_lock = self.node_op_lock.lock().await;
+        if cancel.is_cancelled() {
+            return;
+        }
+
+        let finish = match kind {
+            NodeOperationKind::Drain => Ok(()),
+            // A drained node stays unschedulable until it is filled.
+            NodeOperationKind::Fill => self
+                .persist_node_scheduling(node_id, NodeSchedulingPolicy::Active)
+                .await
+                .map_err(|e| format!("Failed to activate node {node_id} after filling it: {e}")),
+            // A node which still holds locations stays registered, so that the decommission
+            // may be retried.
+            NodeOperationKind::Decommission => {
+                let clean = self
+                    .node_operations
+                    .lock()
+                    .unwrap()
+                    .get(&node_id)
+                    .is_some_and(|op| op.status.errors.is_empty());
+                if clean {
+                    self.remove_node(node_id)
+                        .await
+                        .map_err(|e| format!("Failed to remove node {node_id}: {e}"))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+
+        // Lock order: the service state before the node operations.
+        let mut locked = self.inner.write().unwrap();
+        let mut operations = self.node_operations.lock().unwrap();
+        if cancel.is_cancelled() {
+            return;
+        }
+        let Some(op) = operations.get_mut(&node_id) else {
+            return;
+        };
+        if let Err(e) = &finish {
+            tracing::warn!("{e}");
+            op.status.errors.push(e.clone());
+        }
+        op.status.state = if op.status.errors.is_empty() {
+            NodeOperationState::Complete
+        } else {
+            NodeOperationState::Failed
+        };
+        tracing::info!("{kind:?} of node {node_id} is {:?}", op.status.state);
+        update_node_operation_metrics(&operations);
+
+        if kind == NodeOperationKind::Fill && finish.is_ok() {
+            if let Err(e) =
+                self.set_node_scheduling(&mut locked, node_id, NodeSchedulingPolicy::Active)
+            {
+                tracing::warn!("Failed to activate node {node_id} after filling it: {e}");
+            }
+        }
+    }
+
     /// Helper for methods that will try and call pageserver APIs for
     /// a tenant, such as timeline CRUD: they cannot proceed unless the tenant
     /// is attached somewhere.
@@ -15,8 +15,9 @@ use utils::{
 
 use crate::{
     compute_hook::ComputeHook,
+    metrics::{ScheduleReason, RECONCILES_IN_PROGRESS, RECONCILE_SECONDS},
     node::Node,
-    persistence::Persistence,
+    persistence::{split_state::SplitState, Persistence},
     reconciler::{attached_location_conf, secondary_location_conf, ReconcileError, Reconciler},
     scheduler::{ScheduleError, Scheduler},
     service, PlacementPolicy, Sequence,
@@ -77,6 +78,11 @@ pub(crate) struct TenantState {
     /// sending it.  This is the mechanism by which compute notifications are included in the scope
     /// of state that we publish externally in an eventually consistent way.
     pub(crate) pending_compute_notification: bool,
+
+    /// Set while this shard is the parent of a shard split in progress: the location of the
+    /// parent on its pageserver is being replaced by its children, so it must not be rescheduled
+    /// or reconciled until the split either completes or is rolled back.
+    pub(crate) splitting: SplitState,
 }
 
 #[derive(Default, Clone, Debug)]
@@ -243,6 +249,7 @@ impl TenantState {
             error_waiter: Arc::new(SeqWait::new(Sequence(0))),
             last_error: Arc::default(),
             pending_compute_notification: false,
+            splitting: SplitState::Idle,
         }
     }
 
@@ -293,8 +300,10 @@ impl TenantState {
         // self.intent refers to pageservers that are offline, and pick other
         // pageservers if so.
 
-        // TODO: respect the splitting bit on tenants: if they are currently splitting then we may not
-        // change their attach location.
+        // A splitting shard may not change its attach location.
+        if self.splitting == SplitState::Splitting {
+            return Ok(());
+        }
 
         // Build the set of pageservers already in use by this tenant, to avoid scheduling
         // more work on the same pageservers we're already using.
@@ -306,9 +315,11 @@ impl TenantState {
             Single => {
                 // Should have exactly one attached, and zero secondaries
                 if self.intent.attached.is_none() {
-                    let node_id = scheduler.schedule_shard(&used_pageservers)?;
+                    let node_id =
+                        scheduler.schedule_shard(self.tenant_shard_id, &used_pageservers)?;
                     self.intent.attached = Some(node_id);
                     used_pageservers.push(node_id);
+                    ScheduleReason::Attach.record();
                     modified = true;
                 }
                 if !self.intent.secondary.is_empty() {
@@ -319,16 +330,26 @@ impl TenantState {
             Double(secondary_count) => {
                 // Should have exactly one attached, and N secondaries
                 if self.intent.attached.is_none() {
-                    let node_id = scheduler.schedule_shard(&used_pageservers)?;
-                    self.intent.attached = Some(node_id);
-                    used_pageservers.push(node_id);
+                    // Prefer attaching to a secondary location, which is already warm.
+                    if let Some(node_id) = scheduler.promote_secondary(&self.intent.secondary) {
+                        self.intent.secondary.retain(|s| *s != node_id);
+                        self.intent.attached = Some(node_id);
+                    } else {
+                        let node_id =
+                            scheduler.schedule_shard(self.tenant_shard_id, &used_pageservers)?;
+                        self.intent.attached = Some(node_id);
+                        used_pageservers.push(node_id);
+                    }
+                    ScheduleReason::Attach.record();
                     modified = true;
                 }
 
                 while self.intent.secondary.len() < secondary_count {
-                    let node_id = scheduler.schedule_shard(&used_pageservers)?;
+                    let node_id =
+                        scheduler.schedule_shard(self.tenant_shard_id, &used_pageservers)?;
                     self.intent.secondary.push(node_id);
                     used_pageservers.push(node_id);
+                    ScheduleReason::Secondary.record();
                     modified = true;
                 }
             }
@@ -423,6 +444,12 @@ impl TenantState {
         service_config: &service::Config,
         persistence: &Arc<Persistence>,
     ) -> Option<ReconcilerWaiter> {
+        // The split owns the location of the parent shard until it is done.
+        if self.splitting == SplitState::Splitting {
+            tracing::info!("Splitting, not reconciling");
+            return None;
+        }
+
         // If there are any ambiguous observed states, and the nodes they refer to are available,
         // we should reconcile to clean them up.
         let mut dirty_observed = false;
@@ -480,7 +507,11 @@ impl TenantState {
 
         tracing::info!("Spawning Reconciler for sequence {}", self.sequence);
         let must_notify = self.pending_compute_notification;
+        RECONCILES_IN_PROGRESS.inc();
         let join_handle = tokio::task::spawn(async move {
+            // Decrement on every exit, including a panic of the reconciler.
+            let _in_progress = scopeguard::guard((), |()| RECONCILES_IN_PROGRESS.dec());
+
             // Wait for any previous reconcile task to complete before we start
             if let Some(old_handle) = old_handle {
                 old_handle.cancel.cancel();
@@ -499,7 +530,11 @@ impl TenantState {
             }
 
             // Attempt to make observed state match intent state
+            let started_at = std::time::Instant::now();
             let result = reconciler.reconcile().await;
+            RECONCILE_SECONDS
+                .with_label_values(&[if result.is_ok() { "ok" } else { "error" }])
+                .observe(started_at.elapsed().as_secs_f64());
 
             // If we know we had a pending compute notification from some previous action, send a notification irrespective
             // of whether the above reconcile() did any work
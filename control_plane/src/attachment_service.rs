@@ -3,21 +3,22 @@ use camino::{Utf8Path, Utf8PathBuf};
 use hyper::Method;
 use pageserver_api::{
     models::{
-        ShardParameters, TenantCreateRequest, TenantShardSplitRequest, TenantShardSplitResponse,
-        TimelineCreateRequest, TimelineInfo,
+        SecondaryProgress, ShardParameters, TenantCreateRequest, TenantShardSplitRequest,
+        TenantShardSplitResponse, TimelineCreateRequest, TimelineInfo,
     },
     shard::TenantShardId,
 };
 use pageserver_client::mgmt_api::ResponseErrorMessageExt;
 use postgres_backend::AuthType;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use tokio::process::Command;
 use tracing::instrument;
 use url::Url;
 use utils::{
     auth::{Claims, Scope},
-    id::{NodeId, TenantId},
+    id::{NodeId, TenantId, TimelineId},
 };
 
 pub struct AttachmentService {
@@ -76,6 +77,11 @@ pub struct NodeRegisterRequest {
 
     pub listen_http_addr: String,
     pub listen_http_port: u16,
+
+    /// The scheduler avoids placing locations of the same tenant in the same
+    /// availability zone.
+    #[serde(default)]
+    pub availability_zone_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -111,9 +117,35 @@ pub struct TenantLocateResponse {
 pub struct TenantShardMigrateRequest {
     pub tenant_shard_id: TenantShardId,
     pub node_id: NodeId,
+    /// Warm up a secondary location on the node before cutting over to it: the shard is attached
+    /// there once this fraction of the bytes in its heatmap is downloaded.  The migration then
+    /// runs in the background, and the response is its [`TenantShardMigrationStatus`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warm_threshold: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TenantShardMigrationState {
+    /// Downloading the shard's layers to a secondary location on the node.
+    WarmingUp,
+    /// Attaching the shard to the node, and notifying the compute.
+    CuttingOver,
+    Complete,
+    Failed,
+}
+
+/// Progress of the latest warm migration of a shard.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TenantShardMigrationStatus {
+    pub tenant_shard_id: TenantShardId,
+    pub node_id: NodeId,
+    pub state: TenantShardMigrationState,
+    /// The progress of the secondary location on the node, as of the latest poll.
+    pub progress: Option<SecondaryProgress>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 pub enum NodeAvailability {
     // Normal, happy state
     Active,
@@ -175,6 +207,143 @@ impl From<NodeSchedulingPolicy> for String {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TenantShardMigrateResponse {}
 
+/// The migrations that the optimizer would start now, from the most loaded
+/// pageservers to the least loaded ones.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OptimizerPlanResponse {
+    pub nodes: Vec<NodeLoad>,
+    pub migrations: Vec<PlannedMigration>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeLoad {
+    pub node_id: NodeId,
+    pub shard_count: usize,
+    pub resident_size: u64,
+    /// GetPage requests per second, since the previous sample of the
+    /// utilization of the pageserver.
+    pub getpage_rate: f64,
+    /// Share of the load of all the pageservers, from 0 to 1.
+    pub load: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlannedMigration {
+    pub tenant_shard_id: TenantShardId,
+    pub from_node_id: NodeId,
+    pub to_node_id: NodeId,
+}
+
+/// A decision of the attachment service after the availability of a node
+/// changed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FailoverEvent {
+    /// RFC 3339 timestamp.
+    pub time: String,
+    pub node_id: NodeId,
+    #[serde(flatten)]
+    pub kind: FailoverEventKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind")]
+pub enum FailoverEventKind {
+    /// The node missed too many heartbeats in a row, and is marked offline.
+    NodeOffline { missed_heartbeats: u32 },
+    /// The node answered a heartbeat while offline, and is marked active.
+    NodeActive,
+    /// A shard attached to the offline node is attached to another node.
+    ShardFailover {
+        tenant_shard_id: TenantShardId,
+        to_node_id: NodeId,
+        /// Whether the other node had a secondary location of the shard.
+        to_secondary: bool,
+    },
+    /// A shard attached to the offline node has no other node to attach to.
+    ShardFailoverFailed {
+        tenant_shard_id: TenantShardId,
+        error: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeOperationKind {
+    /// Migrate the attached shards off the node, ahead of its restart.
+    Drain,
+    /// Migrate shards back to the node once it is restarted.
+    Fill,
+    /// Migrate all the shards off the node, including secondary locations, and then remove it.
+    Decommission,
+}
+
+/// How to remove a node with `DELETE /control/v1/node/:node_id?mode=...`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeDecommissionMode {
+    /// Live migrate the shards off the node, and check that it holds no locations before
+    /// removing it.
+    Graceful,
+    /// Remove the node right away, failing its shards over to other nodes: for nodes which
+    /// are never coming back.
+    Forced,
+}
+
+impl FromStr for NodeDecommissionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "graceful" => Ok(Self::Graceful),
+            "forced" => Ok(Self::Forced),
+            _ => Err(anyhow::anyhow!("Unknown decommission mode '{s}'")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeOperationState {
+    InProgress,
+    Complete,
+    /// Completed, but some shards could not be migrated.
+    Failed,
+}
+
+/// Progress of the latest drain, fill or decommission of a node.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NodeOperationStatus {
+    pub node_id: NodeId,
+    pub kind: NodeOperationKind,
+    pub state: NodeOperationState,
+    pub pending: Vec<PlannedMigration>,
+    pub migrated: Vec<PlannedMigration>,
+    /// Shards which could not be migrated, or for which there was no other
+    /// node to migrate to.
+    pub errors: Vec<String>,
+}
+
+/// The signals exported to Prometheus on `/metrics`, for humans.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServiceMetricsResponse {
+    /// Reconciles spawned and not finished yet.
+    pub reconciles_in_progress: i64,
+    pub reconciles_succeeded: u64,
+    pub reconciles_failed: u64,
+    /// Mean duration of the finished reconciles, in seconds.
+    pub reconcile_mean_seconds: f64,
+    /// Shard locations placed on a node since startup, by reason.
+    pub schedule_decisions: BTreeMap<String, u64>,
+    /// Migrations planned and not done yet, by the operation which planned them.
+    pub pending_migrations: BTreeMap<String, i64>,
+    pub nodes: Vec<NodeHeartbeatStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeHeartbeatStatus {
+    pub node_id: NodeId,
+    pub availability: NodeAvailability,
+    /// Heartbeats missed in a row.
+    pub missed_heartbeats: i64,
+}
+
 impl AttachmentService {
     pub fn from_env(env: &LocalEnv) -> Self {
         let path = Utf8PathBuf::from_path_buf(env.base_data_dir.clone())
@@ -379,6 +548,8 @@ impl AttachmentService {
             db_start_args,
             [],
             background_process::InitialPidFile::Create(self.postgres_pid_file()),
+            // pg_ctl exits once postgres is started, which is not supervised.
+            None,
             || self.pg_isready(&pg_bin_dir),
         )
         .await?;
@@ -411,6 +582,10 @@ impl AttachmentService {
             ));
         }
 
+        if let Some(heartbeat_interval) = &self.env.pageserver_heartbeat_interval {
+            args.push(format!("--heartbeat-interval={heartbeat_interval}"));
+        }
+
         background_process::start_process(
             COMMAND,
             &self.env.base_data_dir,
@@ -421,6 +596,8 @@ impl AttachmentService {
                 self.env.base_data_dir.to_string_lossy().to_string(),
             )],
             background_process::InitialPidFile::Create(self.pid_file()),
+            self.env
+                .supervision(Some(format!("http://{}/status", self.listen))),
             || async {
                 match self.status().await {
                     Ok(_) => Ok(true),
@@ -433,6 +610,10 @@ impl AttachmentService {
         Ok(())
     }
 
+    pub fn process_status(&self) -> anyhow::Result<background_process::ProcessStatus> {
+        background_process::process_status(&self.pid_file())
+    }
+
     pub async fn stop(&self, immediate: bool) -> anyhow::Result<()> {
         background_process::stop_process(immediate, COMMAND, &self.pid_file())?;
 
@@ -581,11 +762,44 @@ impl AttachmentService {
             Some(TenantShardMigrateRequest {
                 tenant_shard_id,
                 node_id,
+                warm_threshold: None,
+            }),
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn tenant_migrate_warm(
+        &self,
+        tenant_shard_id: TenantShardId,
+        node_id: NodeId,
+        warm_threshold: f64,
+    ) -> anyhow::Result<TenantShardMigrationStatus> {
+        self.dispatch(
+            Method::PUT,
+            format!("control/v1/tenant/{tenant_shard_id}/migrate"),
+            Some(TenantShardMigrateRequest {
+                tenant_shard_id,
+                node_id,
+                warm_threshold: Some(warm_threshold),
             }),
         )
         .await
     }
 
+    #[instrument(skip(self))]
+    pub async fn tenant_migration(
+        &self,
+        tenant_shard_id: TenantShardId,
+    ) -> anyhow::Result<TenantShardMigrationStatus> {
+        self.dispatch::<(), _>(
+            Method::GET,
+            format!("control/v1/tenant/{tenant_shard_id}/migrate"),
+            None,
+        )
+        .await
+    }
+
     #[instrument(skip(self), fields(%tenant_id, %new_shard_count))]
     pub async fn tenant_split(
         &self,
@@ -616,6 +830,32 @@ impl AttachmentService {
         .await
     }
 
+    #[instrument(skip(self))]
+    pub async fn node_drain(&self, node_id: NodeId) -> anyhow::Result<NodeOperationStatus> {
+        self.dispatch::<(), _>(
+            Method::PUT,
+            format!("control/v1/node/{node_id}/drain"),
+            None,
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn node_fill(&self, node_id: NodeId) -> anyhow::Result<NodeOperationStatus> {
+        self.dispatch::<(), _>(Method::PUT, format!("control/v1/node/{node_id}/fill"), None)
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn node_operation(&self, node_id: NodeId) -> anyhow::Result<NodeOperationStatus> {
+        self.dispatch::<(), _>(
+            Method::GET,
+            format!("control/v1/node/{node_id}/operation"),
+            None,
+        )
+        .await
+    }
+
     #[instrument(skip(self))]
     pub async fn status(&self) -> anyhow::Result<()> {
         self.dispatch::<(), ()>(Method::GET, "status".to_string(), None)
@@ -635,4 +875,18 @@ impl AttachmentService {
         )
         .await
     }
+
+    #[instrument(skip(self))]
+    pub async fn tenant_timeline_delete(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> anyhow::Result<()> {
+        self.dispatch::<(), ()>(
+            Method::DELETE,
+            format!("v1/tenant/{tenant_id}/timeline/{timeline_id}"),
+            None,
+        )
+        .await
+    }
 }
@@ -11,14 +11,26 @@
 //! The pid stored in the file is later used to stop the service.
 //!
 //! See the [`lock_file`](utils::lock_file) module for more info.
+//!
+//! Processes can optionally be supervised: rather than spawning the process
+//! directly, we spawn a `neon_local supervise` process, which spawns it as
+//! its child and restarts it, with backoff, when it exits without being
+//! stopped, or stops responding at its liveness URL. The supervisor has its
+//! own pid file, `${process_name}.supervisor.pid`, next to the one of the
+//! process, and records the crashes in `${process_name}.supervisor-status.json`.
+//!
+//! Processes can be paused with SIGSTOP, to look hung to their peers. A paused
+//! process has a `${process_name}.paused` file next to its pid file, so that
+//! its supervisor doesn't restart it for not responding.
 
 use std::ffi::OsStr;
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::prelude::AsRawFd;
 use std::os::unix::process::CommandExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use std::{fs, io, thread};
 
 use anyhow::Context;
@@ -27,6 +39,8 @@ use nix::errno::Errno;
 use nix::fcntl::{FcntlArg, FdFlag};
 use nix::sys::signal::{kill, Signal};
 use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
 use utils::pid_file::{self, PidFileRead};
 
 // These constants control the loop used to poll for process start / stop.
@@ -42,6 +56,19 @@ const RETRY_INTERVAL_MILLIS: u64 = 100;
 const DOT_EVERY_RETRIES: u64 = 10;
 const NOTICE_AFTER_RETRIES: u64 = 50;
 
+// These constants control the restarts of supervised processes.
+//
+// The delay before a restart doubles after each crash, up to the maximum, and
+// is reset once the process has run for a while. A process which doesn't
+// respond to a number of liveness checks in a row, once it has responded to
+// one, is killed and restarted.
+const RESTART_BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+const RESTART_BACKOFF_RESET_AFTER: Duration = Duration::from_secs(60);
+const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const LIVENESS_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const LIVENESS_CHECK_FAILURES: u32 = 3;
+
 /// Argument to `start_process`, to indicate whether it should create pidfile or if the process creates
 /// it itself.
 pub enum InitialPidFile {
@@ -51,7 +78,46 @@ pub enum InitialPidFile {
     Expect(Utf8PathBuf),
 }
 
+/// Argument to `start_process`, to have the process supervised.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Supervision {
+    /// URL that the process must respond to, with any status, to be considered
+    /// alive. Without it, only crashes are detected.
+    pub liveness_url: Option<String>,
+}
+
+/// What the supervisor needs to know to (re)start the process.
+#[derive(Serialize, Deserialize)]
+struct SupervisorSpec {
+    process_name: String,
+    datadir: PathBuf,
+    command: PathBuf,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    /// The pid file to create for the process, if it doesn't create one itself.
+    create_pid_file: Option<Utf8PathBuf>,
+    /// The process is paused while this file exists.
+    paused_file: Utf8PathBuf,
+    supervision: Supervision,
+}
+
+/// Crashes of a supervised process, as recorded by its supervisor.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SupervisorStatus {
+    /// Restarts since the supervisor was started.
+    pub restarts: u32,
+    pub last_crash: Option<Crash>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Crash {
+    /// RFC 3339 timestamp.
+    pub at: String,
+    pub reason: String,
+}
+
 /// Start a background child process using the parameters given.
+#[allow(clippy::too_many_arguments)]
 pub async fn start_process<F, Fut, AI, A, EI>(
     process_name: &str,
     datadir: &Path,
@@ -59,6 +125,7 @@ pub async fn start_process<F, Fut, AI, A, EI>(
     args: AI,
     envs: EI,
     initial_pid_file: InitialPidFile,
+    supervision: Option<Supervision>,
     process_status_check: F,
 ) -> anyhow::Result<()>
 where
@@ -69,32 +136,62 @@ where
     // Not generic AsRef<OsStr>, otherwise empty `envs` prevents type inference
     EI: IntoIterator<Item = (String, String)>,
 {
-    let log_path = datadir.join(format!("{process_name}.log"));
-    let process_log_file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_path)
-        .with_context(|| {
-            format!("Could not open {process_name} log file {log_path:?} for writing")
-        })?;
-    let same_file_for_stderr = process_log_file.try_clone().with_context(|| {
-        format!("Could not reuse {process_name} log file {log_path:?} for writing stderr")
-    })?;
+    let pid_file_to_check = match &initial_pid_file {
+        InitialPidFile::Create(path) | InitialPidFile::Expect(path) => path.clone(),
+    };
 
-    let mut command = Command::new(command);
-    let background_command = command
-        .stdout(process_log_file)
-        .stderr(same_file_for_stderr)
-        .args(args);
-    let filled_cmd = fill_remote_storage_secrets_vars(fill_rust_env_vars(background_command));
-    filled_cmd.envs(envs);
+    let supervised = supervision.is_some();
+    let mut filled_cmd = match supervision {
+        None => {
+            let mut filled_cmd = process_command(process_name, datadir, command, args, envs)?;
+            if let InitialPidFile::Create(path) = &initial_pid_file {
+                pre_exec_create_pidfile(&mut filled_cmd, path);
+            }
+            filled_cmd
+        }
+        Some(supervision) => {
+            let spec = SupervisorSpec {
+                process_name: process_name.to_string(),
+                datadir: datadir.to_owned(),
+                command: command.to_owned(),
+                args: args
+                    .into_iter()
+                    .map(|arg| arg.as_ref().to_string_lossy().into_owned())
+                    .collect(),
+                envs: envs.into_iter().collect(),
+                create_pid_file: match initial_pid_file {
+                    InitialPidFile::Create(path) => Some(path),
+                    InitialPidFile::Expect(_) => None,
+                },
+                paused_file: supervisor_file(&pid_file_to_check, "paused"),
+                supervision,
+            };
+            let spec = serde_json::to_vec(&spec)?;
+            let spec_path = supervisor_file(&pid_file_to_check, "supervisor.json");
+            // The environment variables may contain secrets.
+            fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&spec_path)
+                .and_then(|mut file| file.write_all(&spec))
+                .with_context(|| format!("Could not write supervisor spec {spec_path:?}"))?;
 
-    let pid_file_to_check = match &initial_pid_file {
-        InitialPidFile::Create(path) => {
-            pre_exec_create_pidfile(filled_cmd, path);
-            path
+            let neon_local = std::env::current_exe().context("Could not find neon_local")?;
+            let mut filled_cmd = process_command(
+                &format!("{process_name}.supervisor"),
+                datadir,
+                &neon_local,
+                [OsStr::new("supervise"), spec_path.as_os_str()],
+                [],
+            )?;
+            pre_exec_create_pidfile(
+                &mut filled_cmd,
+                supervisor_file(&pid_file_to_check, "supervisor.pid"),
+            );
+            filled_cmd
         }
-        InitialPidFile::Expect(path) => path,
     };
 
     let spawned_process = filled_cmd.spawn().with_context(|| {
@@ -109,8 +206,13 @@ where
     let spawned_process = scopeguard::guard(spawned_process, |mut spawned_process| {
         println!("SIGKILL & wait the started process");
         (|| {
-            // TODO: use another signal that can be caught by the child so it can clean up any children it spawned (e..g, walredo).
-            spawned_process.kill().context("SIGKILL child")?;
+            if supervised {
+                // The supervisor kills the process on SIGQUIT before exiting.
+                kill(pid, Signal::SIGQUIT).context("SIGQUIT supervisor")?;
+            } else {
+                // TODO: use another signal that can be caught by the child so it can clean up any children it spawned (e..g, walredo).
+                spawned_process.kill().context("SIGKILL child")?;
+            }
             spawned_process.wait().context("wait() for child process")?;
             anyhow::Ok(())
         })()
@@ -118,10 +220,13 @@ where
         .unwrap();
     });
 
+    // The pid of a supervised process is the one of a child of the supervisor.
+    let expected_pid = (!supervised).then_some(pid);
     for retries in 0..RETRIES {
-        match process_started(pid, pid_file_to_check, &process_status_check).await {
+        match process_started(expected_pid, &pid_file_to_check, &process_status_check).await {
             Ok(true) => {
-                println!("\n{process_name} started and passed status check, pid: {pid}");
+                let pid_kind = if supervised { "supervisor pid" } else { "pid" };
+                println!("\n{process_name} started and passed status check, {pid_kind}: {pid}");
                 // leak the child process, it'll outlive this neon_local invocation
                 drop(scopeguard::ScopeGuard::into_inner(spawned_process));
                 return Ok(());
@@ -151,11 +256,22 @@ where
 }
 
 /// Stops the process, using the pid file given. Returns Ok also if the process is already not running.
+///
+/// A supervised process is stopped through its supervisor, so that it is not restarted.
 pub fn stop_process(
     immediate: bool,
     process_name: &str,
     pid_file: &Utf8Path,
 ) -> anyhow::Result<()> {
+    remove_paused_file(pid_file)?;
+
+    let supervisor_pid_file = supervisor_file(pid_file, "supervisor.pid");
+    if let PidFileRead::LockedByOtherProcess(pid) = pid_file::read(&supervisor_pid_file)
+        .with_context(|| format!("read pid_file {supervisor_pid_file:?}"))?
+    {
+        return signal_and_wait(immediate, &format!("{process_name} supervisor"), pid);
+    }
+
     let pid = match pid_file::read(pid_file)
         .with_context(|| format!("read pid_file {pid_file:?}"))?
     {
@@ -174,6 +290,10 @@ pub fn stop_process(
         }
         PidFileRead::LockedByOtherProcess(pid) => pid,
     };
+    signal_and_wait(immediate, process_name, pid)
+}
+
+fn signal_and_wait(immediate: bool, process_name: &str, pid: Pid) -> anyhow::Result<()> {
     // XXX the pid could become invalid (and recycled) at any time before the kill() below.
 
     // send signal
@@ -190,12 +310,14 @@ pub fn stop_process(
         Err(Errno::ESRCH) => {
             // Again, don't delete the pid file. The unlink can race with a new pid file being created.
             println!(
-                "{process_name} with pid {pid} does not exist, but a pid file {pid_file:?} was found. Likely the pid got recycled. Lucky we didn't harm anyone."
+                "{process_name} with pid {pid} does not exist, but a pid file was found. Likely the pid got recycled. Lucky we didn't harm anyone."
             );
             return Ok(());
         }
         Err(e) => anyhow::bail!("Failed to send signal to {process_name} with pid {pid}: {e}"),
     }
+    // A paused process only handles the signal once resumed.
+    let _ = kill(pid, Signal::SIGCONT);
 
     // Wait until process is gone
     wait_until_stopped(process_name, pid)?;
@@ -231,6 +353,255 @@ pub fn wait_until_stopped(process_name: &str, pid: Pid) -> anyhow::Result<()> {
     anyhow::bail!("{process_name} with pid {pid} did not stop in {RETRY_UNTIL_SECS} seconds");
 }
 
+/// Suspend the process with SIGSTOP, until [`resume_process`]. Unlike a
+/// stopped process, a paused one keeps its connections open and the ports it
+/// listens on bound, without responding, like a hung or partitioned node.
+pub fn pause_process(process_name: &str, pid_file: &Utf8Path) -> anyhow::Result<()> {
+    let pid = running_pid(process_name, pid_file)?;
+    // Before the process stops responding, for its supervisor to know.
+    fs::write(supervisor_file(pid_file, "paused"), pid.to_string()).context("write paused file")?;
+    kill(pid, Signal::SIGSTOP)
+        .with_context(|| format!("Failed to pause {process_name} with pid {pid}"))?;
+    println!("{process_name} with pid {pid} paused");
+    Ok(())
+}
+
+pub fn resume_process(process_name: &str, pid_file: &Utf8Path) -> anyhow::Result<()> {
+    let pid = running_pid(process_name, pid_file)?;
+    kill(pid, Signal::SIGCONT)
+        .with_context(|| format!("Failed to resume {process_name} with pid {pid}"))?;
+    remove_paused_file(pid_file)?;
+    println!("{process_name} with pid {pid} resumed");
+    Ok(())
+}
+
+fn running_pid(process_name: &str, pid_file: &Utf8Path) -> anyhow::Result<Pid> {
+    match pid_file::read(pid_file).with_context(|| format!("read pid_file {pid_file:?}"))? {
+        PidFileRead::LockedByOtherProcess(pid) => Ok(pid),
+        PidFileRead::NotExist | PidFileRead::NotHeldByAnyProcess(_) => {
+            anyhow::bail!("{process_name} is not running")
+        }
+    }
+}
+
+fn remove_paused_file(pid_file: &Utf8Path) -> anyhow::Result<()> {
+    let paused_file = supervisor_file(pid_file, "paused");
+    match fs::remove_file(&paused_file) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("remove {paused_file:?}")),
+    }
+}
+
+/// The status of a background process, as far as its pid files tell.
+#[derive(Debug)]
+pub struct ProcessStatus {
+    /// Pid of the running process.
+    pub pid: Option<Pid>,
+    /// The running process is paused.
+    pub paused: bool,
+    /// The crashes of the process if it is supervised, or was last time.
+    pub supervisor: Option<SupervisorStatus>,
+}
+
+pub fn process_status(pid_file: &Utf8Path) -> anyhow::Result<ProcessStatus> {
+    let pid =
+        match pid_file::read(pid_file).with_context(|| format!("read pid_file {pid_file:?}"))? {
+            PidFileRead::LockedByOtherProcess(pid) => Some(pid),
+            PidFileRead::NotExist | PidFileRead::NotHeldByAnyProcess(_) => None,
+        };
+    let status_path = supervisor_file(pid_file, "supervisor-status.json");
+    let supervisor = match fs::read(&status_path) {
+        Ok(status) => Some(
+            serde_json::from_slice(&status)
+                .with_context(|| format!("invalid supervisor status in {status_path:?}"))?,
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("read {status_path:?}")),
+    };
+    let paused = pid.is_some() && supervisor_file(pid_file, "paused").exists();
+    Ok(ProcessStatus {
+        pid,
+        paused,
+        supervisor,
+    })
+}
+
+/// Run the supervisor of a process, described by the spec at `spec_path`, until
+/// it is stopped with SIGTERM, SIGINT or SIGQUIT, which it passes to the process.
+pub async fn supervise(spec_path: &Path) -> anyhow::Result<()> {
+    let spec: SupervisorSpec = serde_json::from_slice(
+        &fs::read(spec_path).with_context(|| format!("read supervisor spec {spec_path:?}"))?,
+    )?;
+    let process_name = &spec.process_name;
+    let status_path = spec_path.with_file_name(format!(
+        "{}-status.json",
+        spec_path.file_stem().unwrap().to_string_lossy()
+    ));
+    let mut status: SupervisorStatus = fs::read(&status_path)
+        .ok()
+        .and_then(|status| serde_json::from_slice(&status).ok())
+        .unwrap_or_default();
+    status.restarts = 0;
+    write_supervisor_status(&status_path, &status)?;
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigquit = signal(SignalKind::quit())?;
+    let client = reqwest::Client::builder()
+        .timeout(LIVENESS_CHECK_TIMEOUT)
+        .build()?;
+
+    let mut backoff = RESTART_BACKOFF_INITIAL;
+    loop {
+        let mut cmd = process_command(
+            process_name,
+            &spec.datadir,
+            &spec.command,
+            &spec.args,
+            spec.envs.iter().cloned(),
+        )?;
+        if let Some(path) = &spec.create_pid_file {
+            pre_exec_create_pidfile(&mut cmd, path);
+        }
+        let mut child = tokio::process::Command::from(cmd)
+            .spawn()
+            .with_context(|| format!("Could not spawn {process_name}"))?;
+        let pid = Pid::from_raw(child.id().context("child exited already")? as i32);
+        println!("started {process_name}, pid: {pid}");
+        let started_at = Instant::now();
+
+        let mut liveness_checks = tokio::time::interval(LIVENESS_CHECK_INTERVAL);
+        let mut responded = false;
+        let mut failed_checks = 0;
+        let reason = loop {
+            tokio::select! {
+                exit_status = child.wait() => break exit_status?.to_string(),
+                _ = sigterm.recv() => return stop_child(&mut child, pid, Signal::SIGTERM).await,
+                _ = sigint.recv() => return stop_child(&mut child, pid, Signal::SIGTERM).await,
+                _ = sigquit.recv() => return stop_child(&mut child, pid, Signal::SIGQUIT).await,
+                _ = liveness_checks.tick(), if spec.supervision.liveness_url.is_some() => {
+                    let url = spec.supervision.liveness_url.as_ref().unwrap();
+                    if spec.paused_file.exists() {
+                        failed_checks = 0;
+                    } else if client.get(url).send().await.is_ok() {
+                        responded = true;
+                        failed_checks = 0;
+                    } else if responded {
+                        failed_checks += 1;
+                        if failed_checks == LIVENESS_CHECK_FAILURES {
+                            kill(pid, Signal::SIGKILL)?;
+                            child.wait().await?;
+                            break format!("killed after not responding at {url}");
+                        }
+                    }
+                }
+            }
+        };
+
+        let log_path = spec.datadir.join(format!("{process_name}.log"));
+        let reason = match last_error_line(&log_path) {
+            Some(line) => format!("{reason}; last error: {line}"),
+            None => reason,
+        };
+        println!("{process_name} crashed: {reason}");
+        status.restarts += 1;
+        status.last_crash = Some(Crash {
+            at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            reason,
+        });
+        write_supervisor_status(&status_path, &status)?;
+
+        if started_at.elapsed() > RESTART_BACKOFF_RESET_AFTER {
+            backoff = RESTART_BACKOFF_INITIAL;
+        }
+        println!("restarting {process_name} in {backoff:?}");
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = sigterm.recv() => return Ok(()),
+            _ = sigint.recv() => return Ok(()),
+            _ = sigquit.recv() => return Ok(()),
+        }
+        backoff = std::cmp::min(backoff * 2, RESTART_BACKOFF_MAX);
+    }
+}
+
+async fn stop_child(
+    child: &mut tokio::process::Child,
+    pid: Pid,
+    signal: Signal,
+) -> anyhow::Result<()> {
+    match kill(pid, signal) {
+        Ok(()) | Err(Errno::ESRCH) => {}
+        Err(e) => anyhow::bail!("Failed to send signal to process with pid {pid}: {e}"),
+    }
+    let _ = kill(pid, Signal::SIGCONT);
+    child.wait().await?;
+    Ok(())
+}
+
+fn write_supervisor_status(path: &Path, status: &SupervisorStatus) -> anyhow::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec(status)?)?;
+    fs::rename(&tmp_path, path).with_context(|| format!("write {path:?}"))
+}
+
+/// The last line of the log that looks like it explains a crash.
+fn last_error_line(log_path: &Path) -> Option<String> {
+    const TAIL_BYTES: u64 = 64 * 1024;
+
+    let mut file = fs::File::open(log_path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))
+        .ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+    String::from_utf8_lossy(&tail)
+        .lines()
+        .rev()
+        .find(|line| line.contains("panicked") || line.contains("ERROR") || line.contains("FATAL"))
+        .map(|line| line.trim().to_string())
+}
+
+fn supervisor_file(pid_file: &Utf8Path, extension: &str) -> Utf8PathBuf {
+    pid_file.with_extension(extension)
+}
+
+/// The command for a background process, with its output going to `${process_name}.log`.
+fn process_command<AI, A, EI>(
+    process_name: &str,
+    datadir: &Path,
+    command: &Path,
+    args: AI,
+    envs: EI,
+) -> anyhow::Result<Command>
+where
+    AI: IntoIterator<Item = A>,
+    A: AsRef<OsStr>,
+    EI: IntoIterator<Item = (String, String)>,
+{
+    let log_path = datadir.join(format!("{process_name}.log"));
+    let process_log_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| {
+            format!("Could not open {process_name} log file {log_path:?} for writing")
+        })?;
+    let same_file_for_stderr = process_log_file.try_clone().with_context(|| {
+        format!("Could not reuse {process_name} log file {log_path:?} for writing stderr")
+    })?;
+
+    let mut command = Command::new(command);
+    command
+        .stdout(process_log_file)
+        .stderr(same_file_for_stderr)
+        .args(args);
+    fill_remote_storage_secrets_vars(fill_rust_env_vars(&mut command));
+    command.envs(envs);
+    Ok(command)
+}
+
 fn fill_rust_env_vars(cmd: &mut Command) -> &mut Command {
     // If RUST_BACKTRACE is set, pass it through. But if it's not set, default
     // to RUST_BACKTRACE=1.
@@ -332,7 +703,7 @@ where
 }
 
 async fn process_started<F, Fut>(
-    pid: Pid,
+    expected_pid: Option<Pid>,
     pid_file_to_check: &Utf8Path,
     status_check: &F,
 ) -> anyhow::Result<bool>
@@ -343,7 +714,9 @@ where
     match status_check().await {
         Ok(true) => match pid_file::read(pid_file_to_check)? {
             PidFileRead::NotExist => Ok(false),
-            PidFileRead::LockedByOtherProcess(pid_in_file) => Ok(pid_in_file == pid),
+            PidFileRead::LockedByOtherProcess(pid_in_file) => {
+                Ok(expected_pid.map_or(true, |pid| pid_in_file == pid))
+            }
             PidFileRead::NotHeldByAnyProcess(_) => Ok(false),
         },
         Ok(false) => Ok(false),
@@ -9,15 +9,22 @@ use anyhow::{anyhow, bail, Context, Result};
 use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueEnum};
 use compute_api::spec::ComputeMode;
 use control_plane::attachment_service::{
-    AttachmentService, NodeAvailability, NodeConfigureRequest, NodeSchedulingPolicy,
+    AttachmentService, NodeAvailability, NodeConfigureRequest, NodeOperationState,
+    NodeOperationStatus, NodeSchedulingPolicy, TenantShardMigrationState,
+    TenantShardMigrationStatus,
 };
-use control_plane::endpoint::ComputeControlPlane;
-use control_plane::local_env::{InitForceMode, LocalEnv};
+use control_plane::background_process::{self, ProcessStatus};
+use control_plane::endpoint::{ComputeControlPlane, Endpoint, EndpointStatus};
+use control_plane::local_env::{ConfigProfile, InitForceMode, LocalEnv};
 use control_plane::pageserver::{PageServerNode, PAGESERVER_REMOTE_STORAGE_DIR};
+use control_plane::proxy::{DEFAULT_PROXY_HTTP_PORT, DEFAULT_PROXY_PG_PORT};
 use control_plane::safekeeper::SafekeeperNode;
-use control_plane::{broker, local_env};
+use control_plane::workload::{self, WorkloadConfig, WorkloadPattern};
+use control_plane::{broker, local_env, proxy, snapshot};
 use pageserver_api::models::{
-    ShardParameters, TenantCreateRequest, TimelineCreateRequest, TimelineInfo,
+    LocationConfig, LocationConfigMode, LsnLeaseRequest, ShardParameters, TenantConfig,
+    TenantCreateRequest, TenantState, TimelineCreateRequest, TimelineGcInfo, TimelineGcRequest,
+    TimelineInfo,
 };
 use pageserver_api::shard::{ShardCount, ShardStripeSize, TenantShardId};
 use pageserver_api::{
@@ -31,13 +38,16 @@ use safekeeper_api::{
     DEFAULT_PG_LISTEN_PORT as DEFAULT_SAFEKEEPER_PG_PORT,
 };
 use std::collections::{BTreeSet, HashMap};
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
 use storage_broker::DEFAULT_LISTEN_ADDR as DEFAULT_BROKER_ADDR;
 use url::Host;
 use utils::{
     auth::{Claims, Scope},
+    failpoint_support::FailpointConfig,
     id::{NodeId, TenantId, TenantTimelineId, TimelineId},
     lsn::Lsn,
     project_git_version,
@@ -101,6 +111,8 @@ struct TimelineTreeEl {
     pub name: Option<String>,
     /// Holds all direct children of this timeline referenced using `timeline_id`.
     pub children: BTreeSet<TimelineId>,
+    /// The head and retained size of the timeline, printed by `timeline tree`.
+    pub details: Option<String>,
 }
 
 // Main entry point for the 'neon_local' CLI utility
@@ -121,6 +133,9 @@ fn main() -> Result<()> {
     // Check for 'neon init' command first.
     let subcommand_result = if sub_name == "init" {
         handle_init(sub_args).map(Some)
+    } else if sub_name == "supervise" {
+        // Runs in the background, without a config: see background_process.
+        handle_supervise(sub_args).map(|()| None)
     } else {
         // all other commands need an existing config
         let mut env = LocalEnv::load_config().context("Error loading config")?;
@@ -140,6 +155,10 @@ fn main() -> Result<()> {
             "attachment_service" => rt.block_on(handle_attachment_service(sub_args, &env)),
             "safekeeper" => rt.block_on(handle_safekeeper(sub_args, &env)),
             "endpoint" => rt.block_on(handle_endpoint(sub_args, &env)),
+            "proxy" => rt.block_on(handle_proxy(sub_args, &env)),
+            "snapshot" => rt.block_on(handle_snapshot(sub_args, &env)),
+            "workload" => rt.block_on(handle_workload(sub_args, &env)),
+            "status" => rt.block_on(handle_status(&env)),
             "mappings" => handle_mappings(sub_args, &mut env),
             "pg" => bail!("'pg' subcommand has been renamed to 'endpoint'"),
             _ => bail!("unexpected subcommand {sub_name}"),
@@ -164,15 +183,33 @@ fn main() -> Result<()> {
 }
 
 ///
-/// Prints timelines list as a tree-like structure.
+/// Prints timelines list as a tree-like structure. With `gc_infos`, the head
+/// of each timeline and the size of the layers GC retains for it are printed
+/// too, or '?' for the timelines missing there.
 ///
 fn print_timelines_tree(
     timelines: Vec<TimelineInfo>,
     mut timeline_name_mappings: HashMap<TenantTimelineId, String>,
+    gc_infos: Option<HashMap<TimelineId, TimelineGcInfo>>,
 ) -> Result<()> {
     let mut timelines_hash = timelines
         .iter()
         .map(|t| {
+            let details = gc_infos.as_ref().map(|gc_infos| {
+                let retained = match gc_infos.get(&t.timeline_id) {
+                    Some(gc_info) => {
+                        let r = gc_info.retained;
+                        let bytes = r.horizon_bytes
+                            + r.pitr_bytes
+                            + r.leased_bytes
+                            + r.branches_bytes
+                            + r.latest_bytes;
+                        format!("{} MiB", bytes / (1024 * 1024))
+                    }
+                    None => "?".to_string(),
+                };
+                format!("head {}, retained {retained}", t.last_record_lsn)
+            });
             (
                 t.timeline_id,
                 TimelineTreeEl {
@@ -180,6 +217,7 @@ fn print_timelines_tree(
                     children: BTreeSet::new(),
                     name: timeline_name_mappings
                         .remove(&TenantTimelineId::new(t.tenant_id.tenant_id, t.timeline_id)),
+                    details,
                 },
             )
         })
@@ -244,11 +282,15 @@ fn print_timeline(
     }
 
     // Finally print a timeline id and name with new line
-    println!(
+    print!(
         "{} [{}]",
         timeline.name.as_deref().unwrap_or("_no_name_"),
         timeline.info.timeline_id
     );
+    match &timeline.details {
+        Some(details) => println!(" {details}"),
+        None => println!(),
+    }
 
     let len = timeline.children.len();
     let mut i: usize = 0;
@@ -341,6 +383,23 @@ fn parse_timeline_id(sub_match: &ArgMatches) -> anyhow::Result<Option<TimelineId
         .context("Failed to parse timeline id from the argument string")
 }
 
+// Helper function to parse --timeline-id option, or resolve --branch-name, 'main' by default
+fn get_timeline_id(
+    sub_match: &ArgMatches,
+    env: &local_env::LocalEnv,
+    tenant_id: TenantId,
+) -> anyhow::Result<TimelineId> {
+    if let Some(timeline_id) = parse_timeline_id(sub_match)? {
+        return Ok(timeline_id);
+    }
+    let branch_name = sub_match
+        .get_one::<String>("branch-name")
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_BRANCH_NAME);
+    env.get_branch_timeline_id(branch_name, tenant_id)
+        .ok_or_else(|| anyhow!("Found no timeline id for branch name '{branch_name}'"))
+}
+
 fn handle_init(init_match: &ArgMatches) -> anyhow::Result<LocalEnv> {
     let num_pageservers = init_match
         .get_one::<u16>("num-pageservers")
@@ -366,6 +425,12 @@ fn handle_init(init_match: &ArgMatches) -> anyhow::Result<LocalEnv> {
 
     let mut env =
         LocalEnv::parse_config(&toml_file).context("Failed to create neon configuration")?;
+    let profile = init_match.get_one::<ConfigProfile>("profile").copied();
+    if let Some(profile) = profile {
+        for sk_conf in &mut env.safekeepers {
+            profile.apply_to_safekeeper(sk_conf);
+        }
+    }
     let force = init_match.get_one("force").expect("we set a default value");
     env.init(pg_version, force)
         .context("Failed to initialize neon repository")?;
@@ -374,9 +439,13 @@ fn handle_init(init_match: &ArgMatches) -> anyhow::Result<LocalEnv> {
     std::fs::create_dir_all(env.base_data_dir.join(PAGESERVER_REMOTE_STORAGE_DIR))?;
 
     // Initialize pageserver, create initial tenant and timeline.
+    let mut config_overrides = profile
+        .map(|profile| profile.pageserver_overrides().to_vec())
+        .unwrap_or_default();
+    config_overrides.extend(pageserver_config_overrides(init_match));
     for ps_conf in &env.pageservers {
         PageServerNode::from_env(&env, ps_conf)
-            .initialize(&pageserver_config_overrides(init_match))
+            .initialize(&config_overrides)
             .unwrap_or_else(|e| {
                 eprintln!("pageserver init failed: {e:?}");
                 exit(1);
@@ -413,7 +482,8 @@ async fn handle_tenant(
 ) -> anyhow::Result<()> {
     let pageserver = get_default_pageserver(env);
     match tenant_match.subcommand() {
-        Some(("list", _)) => {
+        Some(("list", list_match)) => {
+            let pageserver = get_target_pageserver(env, list_match)?.unwrap_or(pageserver);
             for t in pageserver.tenant_list().await? {
                 println!("{} {:?}", t.id, t.state);
             }
@@ -460,6 +530,19 @@ async fn handle_tenant(
                 .await?;
             println!("tenant {tenant_id} successfully created on the pageserver");
 
+            // Place all the shards on the requested pageserver, the attachment
+            // service otherwise picks one for each.
+            if let Some(target) = get_target_pageserver(env, create_match)? {
+                for shard in attachment_service.tenant_locate(tenant_id).await?.shards {
+                    if shard.node_id != target.conf.id {
+                        attachment_service
+                            .tenant_migrate(shard.shard_id, target.conf.id)
+                            .await?;
+                    }
+                }
+                println!("tenant {tenant_id} placed on pageserver {}", target.conf.id);
+            }
+
             // Create an initial timeline for the new tenant
             let new_timeline_id =
                 parse_timeline_id(create_match)?.unwrap_or(TimelineId::generate());
@@ -497,6 +580,94 @@ async fn handle_tenant(
                 env.default_tenant_id = Some(tenant_id);
             }
         }
+        Some(("attach", attach_match)) => {
+            let tenant_id = parse_tenant_id(attach_match)?.context("No tenant id specified")?;
+            let remote_storage = attach_match
+                .get_one::<String>("remote-storage")
+                .context("No remote storage specified")?;
+            let generation = *attach_match
+                .get_one::<u32>("generation")
+                .context("No generation specified")?;
+            let pageserver =
+                get_target_pageserver(env, attach_match)?.context("No pageserver specified")?;
+            let node_id = pageserver.conf.id;
+
+            // The pageserver is switched to the other remote storage, where
+            // the data of the tenants it already holds isn't.
+            pageserver
+                .check_status()
+                .await
+                .with_context(|| format!("pageserver {node_id} is not running"))?;
+            if pageserver.conf.remote_storage.as_ref() != Some(remote_storage) {
+                let tenants = pageserver.tenant_list().await?;
+                if !tenants.is_empty() {
+                    bail!(
+                        "pageserver {node_id} holds {} tenants, attach to one which holds none",
+                        tenants.len()
+                    );
+                }
+
+                // Keep the attachment service from placing other tenants on it.
+                AttachmentService::from_env(env)
+                    .node_configure(NodeConfigureRequest {
+                        node_id,
+                        availability: None,
+                        scheduling: Some(NodeSchedulingPolicy::Pause),
+                    })
+                    .await?;
+
+                for ps_conf in env.pageservers.iter_mut().filter(|c| c.id == node_id) {
+                    ps_conf.remote_storage = Some(remote_storage.clone());
+                }
+                pageserver.stop(false)?;
+                PageServerNode::from_env(env, env.get_pageserver_conf(node_id)?)
+                    .start(&[], true)
+                    .await?;
+                println!("Remote storage of pageserver {node_id} set to {remote_storage}");
+            }
+
+            // Attach stale, with GC and compaction off, so that nothing is
+            // deleted from the remote storage: it may still be in use.
+            let tenant_shard_id = TenantShardId::unsharded(tenant_id);
+            pageserver
+                .location_config(
+                    tenant_shard_id,
+                    LocationConfig {
+                        mode: LocationConfigMode::AttachedStale,
+                        generation: Some(generation),
+                        secondary_conf: None,
+                        shard_number: 0,
+                        shard_count: 0,
+                        shard_stripe_size: 0,
+                        tenant_conf: TenantConfig {
+                            gc_period: Some("0s".to_string()),
+                            compaction_period: Some("0s".to_string()),
+                            ..Default::default()
+                        },
+                    },
+                    None,
+                )
+                .await?;
+            wait_until_tenant_active(&pageserver, tenant_shard_id).await?;
+            println!(
+                "tenant {tenant_id} attached read-only to pageserver {node_id} in generation {generation}"
+            );
+            println!("Only start static endpoints on it, with --pageserver-id {node_id} and --lsn");
+
+            // Name the branches after their timelines, except the root one.
+            for timeline in pageserver.timeline_list(&tenant_shard_id).await? {
+                let branch_name = if timeline.ancestor_timeline_id.is_none() {
+                    DEFAULT_BRANCH_NAME.to_string()
+                } else {
+                    timeline.timeline_id.to_string()
+                };
+                env.register_branch_mapping(branch_name.clone(), tenant_id, timeline.timeline_id)?;
+                println!(
+                    "timeline {} mapped to branch '{branch_name}'",
+                    timeline.timeline_id
+                );
+            }
+        }
         Some(("set-default", set_default_match)) => {
             let tenant_id =
                 parse_tenant_id(set_default_match)?.context("No tenant id specified")?;
@@ -505,6 +676,7 @@ async fn handle_tenant(
         }
         Some(("config", create_match)) => {
             let tenant_id = get_tenant_id(create_match, env)?;
+            let pageserver = get_target_pageserver(env, create_match)?.unwrap_or(pageserver);
             let tenant_conf: HashMap<_, _> = create_match
                 .get_many::<String>("config")
                 .map(|vals| vals.flat_map(|c| c.split_once(':')).collect())
@@ -518,15 +690,44 @@ async fn handle_tenant(
         }
         Some(("migrate", matches)) => {
             let tenant_shard_id = get_tenant_shard_id(matches, env)?;
-            let new_pageserver = get_pageserver(env, matches)?;
-            let new_pageserver_id = new_pageserver.conf.id;
+            let new_pageserver_id = match matches.get_one::<String>("to") {
+                Some(id_str) => NodeId(id_str.parse().context("while parsing pageserver id")?),
+                None => get_pageserver(env, matches)?.conf.id,
+            };
+            env.get_pageserver_conf(new_pageserver_id)?;
+            let warm_threshold = matches.get_one::<f64>("warm-threshold").copied();
 
+            // Given a tenant ID rather than a shard's, migrate all the shards.
+            let tenant_id = tenant_shard_id.tenant_id;
             let attachment_service = AttachmentService::from_env(env);
-            attachment_service
-                .tenant_migrate(tenant_shard_id, new_pageserver_id)
-                .await?;
+            for shard in attachment_service.tenant_locate(tenant_id).await?.shards {
+                if !tenant_shard_id.is_unsharded() && shard.shard_id != tenant_shard_id {
+                    continue;
+                }
+                if shard.node_id == new_pageserver_id {
+                    println!("shard {} is on {new_pageserver_id} already", shard.shard_id);
+                    continue;
+                }
+                match warm_threshold {
+                    Some(warm_threshold) => {
+                        let status = attachment_service
+                            .tenant_migrate_warm(shard.shard_id, new_pageserver_id, warm_threshold)
+                            .await?;
+                        wait_for_migration(&attachment_service, status).await?;
+                    }
+                    None => {
+                        attachment_service
+                            .tenant_migrate(shard.shard_id, new_pageserver_id)
+                            .await?;
+                    }
+                }
+                println!(
+                    "shard {} migrated from {} to {new_pageserver_id}",
+                    shard.shard_id, shard.node_id
+                );
+            }
 
-            println!("tenant {tenant_shard_id} migrated to {}", new_pageserver_id);
+            reconfigure_running_endpoints(env, tenant_id).await?;
         }
         Some(("status", matches)) => {
             let tenant_id = get_tenant_id(matches, env)?;
@@ -593,6 +794,18 @@ async fn handle_tenant(
                     .collect::<Vec<_>>()
                     .join(",")
             );
+
+            let mut shard_table = comfy_table::Table::new();
+            shard_table.set_header(["Shard", "Pageserver"]);
+            for shard in attachment_service.tenant_locate(tenant_id).await?.shards {
+                shard_table.add_row([
+                    format!("{}", shard.shard_id.shard_slug()),
+                    format!("{}", shard.node_id.0),
+                ]);
+            }
+            println!("{shard_table}");
+
+            reconfigure_running_endpoints(env, tenant_id).await?;
         }
 
         Some((sub_name, _)) => bail!("Unexpected tenant subcommand '{}'", sub_name),
@@ -601,6 +814,60 @@ async fn handle_tenant(
     Ok(())
 }
 
+async fn wait_until_tenant_active(
+    pageserver: &PageServerNode,
+    tenant_shard_id: TenantShardId,
+) -> anyhow::Result<()> {
+    // Attaching downloads the index of every timeline, which can take a
+    // while for a large tenant.
+    const ATTACH_TIMEOUT: Duration = Duration::from_secs(300);
+
+    let started_at = Instant::now();
+    loop {
+        let details = pageserver.http_client.tenant_details(tenant_shard_id).await;
+        match details.map(|details| details.tenant_info.state) {
+            Ok(TenantState::Active) => return Ok(()),
+            Ok(TenantState::Broken { reason, .. }) => {
+                bail!("tenant {tenant_shard_id} is broken: {reason}")
+            }
+            // Not visible yet, or still attaching.
+            Ok(_) | Err(_) => {}
+        }
+        if started_at.elapsed() > ATTACH_TIMEOUT {
+            bail!("tenant {tenant_shard_id} did not become active in {ATTACH_TIMEOUT:?}");
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// Await `job`, printing a dot every second until it is done, and how long it took.
+async fn with_progress<T>(
+    label: &str,
+    job: impl std::future::Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    print!("{label}");
+    io::stdout().flush()?;
+    let started_at = Instant::now();
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    // The first tick completes immediately.
+    ticks.tick().await;
+    tokio::pin!(job);
+    let result = loop {
+        tokio::select! {
+            result = &mut job => break result,
+            _ = ticks.tick() => {
+                print!(".");
+                io::stdout().flush()?;
+            }
+        }
+    };
+    match &result {
+        Ok(_) => println!(" done in {:.1?}", started_at.elapsed()),
+        Err(_) => println!(" failed after {:.1?}", started_at.elapsed()),
+    }
+    result
+}
+
 async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::LocalEnv) -> Result<()> {
     let pageserver = get_default_pageserver(env);
 
@@ -609,8 +876,32 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
             // TODO(sharding): this command shouldn't have to specify a shard ID: we should ask the attachment service
             // where shard 0 is attached, and query there.
             let tenant_shard_id = get_tenant_shard_id(list_match, env)?;
+            let pageserver = get_target_pageserver(env, list_match)?.unwrap_or(pageserver);
             let timelines = pageserver.timeline_list(&tenant_shard_id).await?;
-            print_timelines_tree(timelines, env.timeline_name_mappings())?;
+            print_timelines_tree(timelines, env.timeline_name_mappings(), None)?;
+        }
+        Some(("tree", tree_match)) => {
+            let tenant_shard_id = get_tenant_shard_id(tree_match, env)?;
+            let pageserver = get_target_pageserver(env, tree_match)?.unwrap_or(pageserver);
+            let timelines = pageserver.timeline_list(&tenant_shard_id).await?;
+
+            let mut gc_infos = HashMap::new();
+            for timeline in &timelines {
+                match pageserver
+                    .http_client
+                    .timeline_gc_info(tenant_shard_id, timeline.timeline_id)
+                    .await
+                {
+                    Ok(gc_info) => {
+                        gc_infos.insert(timeline.timeline_id, gc_info);
+                    }
+                    Err(e) => eprintln!(
+                        "Failed to get GC info of timeline {}: {e}",
+                        timeline.timeline_id
+                    ),
+                }
+            }
+            print_timelines_tree(timelines, env.timeline_name_mappings(), Some(gc_infos))?;
         }
         Some(("create", create_match)) => {
             let tenant_id = get_tenant_id(create_match, env)?;
@@ -626,7 +917,6 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
             let new_timeline_id_opt = parse_timeline_id(create_match)?;
             let new_timeline_id = new_timeline_id_opt.unwrap_or(TimelineId::generate());
 
-            let attachment_service = AttachmentService::from_env(env);
             let create_req = TimelineCreateRequest {
                 new_timeline_id,
                 ancestor_timeline_id: None,
@@ -634,9 +924,7 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
                 ancestor_start_lsn: None,
                 pg_version: Some(pg_version),
             };
-            let timeline_info = attachment_service
-                .tenant_timeline_create(tenant_id, create_req)
-                .await?;
+            let timeline_info = timeline_create(env, create_match, tenant_id, create_req).await?;
 
             let last_record_lsn = timeline_info.last_record_lsn;
             env.register_branch_mapping(new_branch_name.to_string(), tenant_id, new_timeline_id)?;
@@ -666,11 +954,25 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
             let base = (base_lsn, base_tarfile);
 
             // Parse pg_wal inputs
-            let wal_tarfile = import_match.get_one::<PathBuf>("wal-tarfile").cloned();
             let end_lsn = import_match
                 .get_one::<String>("end-lsn")
-                .map(|s| Lsn::from_str(s).unwrap());
-            // TODO validate both or none are provided
+                .map(|s| Lsn::from_str(s))
+                .transpose()
+                .context("Failed to parse end Lsn from the argument string")?;
+            let wal_tarfile = match import_match.get_one::<PathBuf>("wal-dir") {
+                Some(wal_dir) => {
+                    let end_lsn = end_lsn.context("--wal-dir requires --end-lsn")?;
+                    let tarfile = env
+                        .base_data_dir
+                        .join(format!("import_wal_{timeline_id}.tar"));
+                    tar_wal_segments(wal_dir, base_lsn, end_lsn, &tarfile)?;
+                    Some(tarfile)
+                }
+                None => import_match.get_one::<PathBuf>("wal-tarfile").cloned(),
+            };
+            if end_lsn.is_some() != wal_tarfile.is_some() {
+                bail!("--end-lsn and the WAL to import must be given together");
+            }
             let pg_wal = end_lsn.zip(wal_tarfile);
 
             let pg_version = import_match
@@ -680,13 +982,19 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
 
             let mut cplane = ComputeControlPlane::load(env.clone())?;
             println!("Importing timeline into pageserver ...");
-            pageserver
-                .timeline_import(tenant_id, timeline_id, base, pg_wal, pg_version)
-                .await?;
+            let imported = pageserver
+                .timeline_import(tenant_id, timeline_id, base, pg_wal.clone(), pg_version)
+                .await;
+            if import_match.contains_id("wal-dir") {
+                if let Some((_, tarfile)) = &pg_wal {
+                    let _ = std::fs::remove_file(tarfile);
+                }
+            }
+            imported?;
             env.register_branch_mapping(name.to_string(), tenant_id, timeline_id)?;
 
             println!("Creating endpoint for imported timeline ...");
-            cplane.new_endpoint(
+            let endpoint = cplane.new_endpoint(
                 name,
                 tenant_id,
                 timeline_id,
@@ -695,8 +1003,58 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
                 pg_version,
                 ComputeMode::Primary,
             )?;
+
+            if import_match.get_flag("validate") {
+                println!("Starting endpoint {name} to validate the imported data ...");
+                validate_imported_timeline(env, &pageserver, &endpoint).await?;
+            }
             println!("Done");
         }
+        Some(("compact", compact_match)) => {
+            let tenant_id = get_tenant_id(compact_match, env)?;
+            let timeline_id = get_timeline_id(compact_match, env, tenant_id)?;
+            let wait = compact_match.get_flag("wait");
+            let attachment_service = AttachmentService::from_env(env);
+            for shard in attachment_service.tenant_locate(tenant_id).await?.shards {
+                let pageserver =
+                    PageServerNode::from_env(env, env.get_pageserver_conf(shard.node_id)?);
+                let job =
+                    pageserver
+                        .http_client
+                        .timeline_compact(shard.shard_id, timeline_id, wait);
+                with_progress(
+                    &format!("Compacting {}/{timeline_id}", shard.shard_id),
+                    async { Ok(job.await?) },
+                )
+                .await?;
+            }
+        }
+        Some(("gc", gc_match)) => {
+            let tenant_id = get_tenant_id(gc_match, env)?;
+            let timeline_id = get_timeline_id(gc_match, env, tenant_id)?;
+            let wait = gc_match.get_flag("wait");
+            let req = TimelineGcRequest {
+                gc_horizon: gc_match.get_one::<u64>("gc-horizon").copied(),
+            };
+            let attachment_service = AttachmentService::from_env(env);
+            for shard in attachment_service.tenant_locate(tenant_id).await?.shards {
+                let pageserver =
+                    PageServerNode::from_env(env, env.get_pageserver_conf(shard.node_id)?);
+                let job =
+                    pageserver
+                        .http_client
+                        .timeline_gc(shard.shard_id, timeline_id, &req, wait);
+                let result = with_progress(
+                    &format!("Garbage collecting {}/{timeline_id}", shard.shard_id),
+                    async { Ok(job.await?) },
+                )
+                .await?;
+                println!(
+                    "Removed {} of {} layers",
+                    result.layers_removed, result.layers_total
+                );
+            }
+        }
         Some(("branch", branch_match)) => {
             let tenant_id = get_tenant_id(branch_match, env)?;
             let new_branch_name = branch_match
@@ -718,7 +1076,6 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
                 .transpose()
                 .context("Failed to parse ancestor start Lsn from the request")?;
             let new_timeline_id = TimelineId::generate();
-            let attachment_service = AttachmentService::from_env(env);
             let create_req = TimelineCreateRequest {
                 new_timeline_id,
                 ancestor_timeline_id: Some(ancestor_timeline_id),
@@ -726,9 +1083,7 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
                 ancestor_start_lsn: start_lsn,
                 pg_version: None,
             };
-            let timeline_info = attachment_service
-                .tenant_timeline_create(tenant_id, create_req)
-                .await?;
+            let timeline_info = timeline_create(env, branch_match, tenant_id, create_req).await?;
 
             let last_record_lsn = timeline_info.last_record_lsn;
 
@@ -746,6 +1101,179 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
     Ok(())
 }
 
+/// Create a timeline on the pageserver given with `--pageserver-id`, which
+/// must have the tenant attached and unsharded, or through the attachment
+/// service on all the shards of the tenant otherwise.
+async fn timeline_create(
+    env: &local_env::LocalEnv,
+    args: &ArgMatches,
+    tenant_id: TenantId,
+    req: TimelineCreateRequest,
+) -> Result<TimelineInfo> {
+    match get_target_pageserver(env, args)? {
+        Some(pageserver) => {
+            pageserver
+                .timeline_create(
+                    TenantShardId::unsharded(tenant_id),
+                    req.new_timeline_id,
+                    req.ancestor_start_lsn,
+                    req.ancestor_timeline_id,
+                    req.pg_version,
+                    req.existing_initdb_timeline_id,
+                )
+                .await
+        }
+        None => {
+            let attachment_service = AttachmentService::from_env(env);
+            attachment_service
+                .tenant_timeline_create(tenant_id, req)
+                .await
+        }
+    }
+}
+
+/// Point the running endpoints of the tenant at the pageservers its shards are
+/// attached to now. The attachment service notifies the endpoints too, but only
+/// if it can load the neon_local config, so don't rely on it.
+async fn reconfigure_running_endpoints(
+    env: &local_env::LocalEnv,
+    tenant_id: TenantId,
+) -> Result<()> {
+    let cplane = ComputeControlPlane::load(env.clone())?;
+    for (endpoint_id, endpoint) in &cplane.endpoints {
+        if endpoint.tenant_id == tenant_id && endpoint.status() == EndpointStatus::Running {
+            endpoint.reconfigure(Vec::new()).await?;
+            println!("endpoint {endpoint_id} reconfigured");
+        }
+    }
+    Ok(())
+}
+
+/// Lease `lsn` on the pageserver given with `--pageserver-id`, or on the
+/// pageservers of all the shards of the tenant otherwise, so that a static
+/// endpoint can read at it. Returns until when all the leases are valid.
+async fn lease_lsn(
+    env: &local_env::LocalEnv,
+    args: &ArgMatches,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    lsn: Lsn,
+) -> Result<SystemTime> {
+    let shards = match get_target_pageserver(env, args)? {
+        Some(pageserver) => vec![(TenantShardId::unsharded(tenant_id), pageserver)],
+        None => {
+            let attachment_service = AttachmentService::from_env(env);
+            let mut shards = Vec::new();
+            for shard in attachment_service.tenant_locate(tenant_id).await?.shards {
+                let conf = env.get_pageserver_conf(shard.node_id)?;
+                shards.push((shard.shard_id, PageServerNode::from_env(env, conf)));
+            }
+            shards
+        }
+    };
+
+    let req = LsnLeaseRequest { lsn, length: None };
+    let mut valid_until: Option<SystemTime> = None;
+    for (tenant_shard_id, pageserver) in shards {
+        let lease = pageserver
+            .http_client
+            .timeline_lsn_lease(tenant_shard_id, timeline_id, &req)
+            .await
+            .with_context(|| format!("failed to lease LSN {lsn} on shard {tenant_shard_id}"))?;
+        valid_until = Some(valid_until.map_or(lease.valid_until, |v| v.min(lease.valid_until)));
+    }
+    valid_until.context("tenant has no shards")
+}
+
+/// Put the WAL segments of `wal_dir` from the one containing `start_lsn` up to
+/// the one containing `end_lsn` into `tarfile`, in order, as the pageserver's
+/// WAL import expects them.
+fn tar_wal_segments(wal_dir: &Path, start_lsn: Lsn, end_lsn: Lsn, tarfile: &Path) -> Result<()> {
+    const WAL_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+    const SEGMENTS_PER_XLOGID: u64 = 0x1_0000_0000 / WAL_SEGMENT_SIZE;
+    // Only timeline 1 is supported by the import, like in the pageserver
+    let segment_name = |lsn: Lsn| {
+        let segno = lsn.0 / WAL_SEGMENT_SIZE;
+        format!(
+            "{:08X}{:08X}{:08X}",
+            1,
+            segno / SEGMENTS_PER_XLOGID,
+            segno % SEGMENTS_PER_XLOGID
+        )
+    };
+    let (first, last) = (segment_name(start_lsn), segment_name(end_lsn));
+
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir(wal_dir).with_context(|| format!("reading {wal_dir:?}"))? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        let is_segment = name.len() == 24 && name.chars().all(|c| c.is_ascii_hexdigit());
+        if is_segment && name >= first && name <= last {
+            segments.push(name);
+        }
+    }
+    segments.sort();
+    if segments.first() != Some(&first) || segments.last() != Some(&last) {
+        bail!("{wal_dir:?} doesn't contain the WAL segments from {first} to {last}");
+    }
+
+    let mut builder = tar::Builder::new(std::fs::File::create(tarfile)?);
+    for segment in &segments {
+        builder.append_path_with_name(wal_dir.join(segment), segment)?;
+    }
+    builder.into_inner()?;
+    Ok(())
+}
+
+/// Start `endpoint` on a freshly imported timeline, list its databases and
+/// stop it again, to check that the import produced a working timeline.
+async fn validate_imported_timeline(
+    env: &local_env::LocalEnv,
+    pageserver: &PageServerNode,
+    endpoint: &Endpoint,
+) -> Result<()> {
+    // The timeline was imported directly into this pageserver
+    let (host, port) = parse_host_port(&pageserver.conf.listen_pg_addr).expect("Bad config");
+    let auth_token = if matches!(pageserver.conf.pg_auth_type, AuthType::NeonJWT) {
+        let claims = Claims::new(Some(endpoint.tenant_id), Scope::Tenant);
+        Some(env.generate_auth_token(&claims)?)
+    } else {
+        None
+    };
+    let safekeepers = env.safekeepers.iter().map(|sk| sk.id).collect();
+    endpoint
+        .start(
+            &auth_token,
+            safekeepers,
+            vec![(host, port.unwrap_or(5432))],
+            None,
+            ShardParameters::DEFAULT_STRIPE_SIZE.0 as usize,
+            false,
+        )
+        .await?;
+
+    let validated = async {
+        let (client, conn) = tokio_postgres::connect(&endpoint.connstr(), tokio_postgres::NoTls)
+            .await
+            .context("connecting to the endpoint")?;
+        tokio::spawn(conn);
+        let rows = client
+            .query(
+                "SELECT datname, pg_database_size(oid) FROM pg_database WHERE datallowconn",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            let (name, size): (String, i64) = (row.get(0), row.get(1));
+            println!("  database {name}: {} MiB", size / (1024 * 1024));
+        }
+        anyhow::Ok(())
+    }
+    .await;
+
+    endpoint.stop("fast", false)?;
+    validated
+}
+
 async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
     let (sub_name, sub_args) = match ep_match.subcommand() {
         Some(ep_subcommand_data) => ep_subcommand_data,
@@ -852,6 +1380,7 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 .get_one::<bool>("hot-standby")
                 .copied()
                 .unwrap_or(false);
+            let read_only = sub_args.get_flag("read-only");
 
             let mode = match (lsn, hot_standby) {
                 (Some(lsn), false) => ComputeMode::Static(lsn),
@@ -872,6 +1401,16 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
 
             cplane.check_conflicting_endpoints(mode, tenant_id, timeline_id)?;
 
+            // Check that the LSN is still readable before creating the
+            // endpoint, and keep it so while the endpoint is used.
+            if let (true, ComputeMode::Static(lsn)) = (read_only, mode) {
+                let valid_until = lease_lsn(env, sub_args, tenant_id, timeline_id, lsn).await?;
+                println!(
+                    "Leased LSN {lsn} until {}",
+                    humantime::format_rfc3339_seconds(valid_until)
+                );
+            }
+
             cplane.new_endpoint(
                 &endpoint_id,
                 tenant_id,
@@ -897,6 +1436,7 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 };
 
             let remote_ext_config = sub_args.get_one::<String>("remote-ext-config");
+            let lfc_state = sub_args.get_flag("lfc-state");
 
             // If --safekeepers argument is given, use only the listed safekeeper nodes.
             let safekeepers =
@@ -924,6 +1464,15 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 endpoint.timeline_id,
             )?;
 
+            // Renew the lease of a static endpoint. If the LSN has been
+            // garbage collected meanwhile, Postgres fails to start.
+            if let ComputeMode::Static(lsn) = endpoint.mode {
+                let (tenant_id, timeline_id) = (endpoint.tenant_id, endpoint.timeline_id);
+                if let Err(e) = lease_lsn(env, sub_args, tenant_id, timeline_id, lsn).await {
+                    eprintln!("Failed to renew the lease of LSN {lsn}: {e:#}");
+                }
+            }
+
             let (pageservers, stripe_size) = if let Some(pageserver_id) = pageserver_id {
                 let conf = env.get_pageserver_conf(pageserver_id).unwrap();
                 let parsed = parse_host_port(&conf.listen_pg_addr).expect("Bad config");
@@ -972,6 +1521,7 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                     pageservers,
                     remote_ext_config,
                     stripe_size.0 as usize,
+                    lfc_state,
                 )
                 .await?;
         }
@@ -1020,7 +1570,36 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 .endpoints
                 .get(endpoint_id.as_str())
                 .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+
+            // Only a primary writes WAL, and an immediate shutdown may lose the WAL
+            // that the safekeepers haven't acknowledged yet, legitimately.
+            let check_durability = sub_args.get_flag("check-durability")
+                && mode != "immediate"
+                && endpoint.mode == ComputeMode::Primary
+                && endpoint.status() == EndpointStatus::Running;
+            let flush_lsn = if check_durability {
+                Some(endpoint.flush_lsn().await.context(
+                    "Failed to get the flush LSN of the endpoint, stop it without --check-durability",
+                )?)
+            } else {
+                None
+            };
+
             endpoint.stop(mode, destroy)?;
+
+            if let Some(flush_lsn) = flush_lsn {
+                let timeout: Duration = (*sub_args
+                    .get_one::<humantime::Duration>("durability-timeout")
+                    .expect("has a default"))
+                .into();
+                endpoint
+                    .wait_for_durable_wal(flush_lsn, timeout)
+                    .await
+                    .context("WAL of the stopped endpoint is not durable")?;
+                println!(
+                    "WAL up to {flush_lsn} is durable on the safekeepers and in remote storage"
+                );
+            }
         }
 
         _ => bail!("Unexpected endpoint subcommand '{sub_name}'"),
@@ -1074,6 +1653,22 @@ fn get_pageserver(env: &local_env::LocalEnv, args: &ArgMatches) -> Result<PageSe
     ))
 }
 
+/// The pageserver given with `--pageserver-id` to a command which otherwise
+/// uses the default pageserver or goes through the attachment service.
+fn get_target_pageserver(
+    env: &local_env::LocalEnv,
+    args: &ArgMatches,
+) -> Result<Option<PageServerNode>> {
+    let Some(id_str) = args.get_one::<String>("endpoint-pageserver-id") else {
+        return Ok(None);
+    };
+    let node_id = NodeId(id_str.parse().context("while parsing pageserver id")?);
+    Ok(Some(PageServerNode::from_env(
+        env,
+        env.get_pageserver_conf(node_id)?,
+    )))
+}
+
 async fn handle_pageserver(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
     match sub_match.subcommand() {
         Some(("start", subcommand_args)) => {
@@ -1101,6 +1696,13 @@ async fn handle_pageserver(sub_match: &ArgMatches, env: &local_env::LocalEnv) ->
 
         Some(("restart", subcommand_args)) => {
             let pageserver = get_pageserver(env, subcommand_args)?;
+            let drain = subcommand_args.get_flag("drain");
+            let attachment_service = AttachmentService::from_env(env);
+            if drain {
+                let status = attachment_service.node_drain(pageserver.conf.id).await?;
+                wait_for_node_operation(&attachment_service, status).await?;
+            }
+
             //TODO what shutdown strategy should we use here?
             if let Err(e) = pageserver.stop(false) {
                 eprintln!("pageserver stop failed: {}", e);
@@ -1114,6 +1716,25 @@ async fn handle_pageserver(sub_match: &ArgMatches, env: &local_env::LocalEnv) ->
                 eprintln!("pageserver start failed: {e}");
                 exit(1);
             }
+
+            if drain {
+                let status = attachment_service.node_fill(pageserver.conf.id).await?;
+                wait_for_node_operation(&attachment_service, status).await?;
+            }
+        }
+
+        Some(("drain", subcommand_args)) => {
+            let pageserver = get_pageserver(env, subcommand_args)?;
+            let attachment_service = AttachmentService::from_env(env);
+            let status = attachment_service.node_drain(pageserver.conf.id).await?;
+            wait_for_node_operation(&attachment_service, status).await?;
+        }
+
+        Some(("fill", subcommand_args)) => {
+            let pageserver = get_pageserver(env, subcommand_args)?;
+            let attachment_service = AttachmentService::from_env(env);
+            let status = attachment_service.node_fill(pageserver.conf.id).await?;
+            wait_for_node_operation(&attachment_service, status).await?;
         }
 
         Some(("set-state", subcommand_args)) => {
@@ -1147,6 +1768,84 @@ async fn handle_pageserver(sub_match: &ArgMatches, env: &local_env::LocalEnv) ->
     Ok(())
 }
 
+/// Wait for a drain or fill of a pageserver to complete, printing its progress.
+async fn wait_for_node_operation(
+    attachment_service: &AttachmentService,
+    mut status: NodeOperationStatus,
+) -> Result<()> {
+    let total = status.pending.len() + status.migrated.len();
+    let mut reported = None;
+    while status.state == NodeOperationState::InProgress {
+        if reported != Some(status.pending.len()) {
+            println!(
+                "{:?} of pageserver {}: {}/{total} shards migrated",
+                status.kind,
+                status.node_id,
+                total - status.pending.len()
+            );
+            reported = Some(status.pending.len());
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        status = attachment_service.node_operation(status.node_id).await?;
+    }
+
+    println!(
+        "{:?} of pageserver {} is {:?}: {} shards migrated",
+        status.kind,
+        status.node_id,
+        status.state,
+        status.migrated.len()
+    );
+    if !status.errors.is_empty() {
+        bail!(
+            "{:?} of pageserver {} failed:\n{}",
+            status.kind,
+            status.node_id,
+            status.errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+async fn wait_for_migration(
+    attachment_service: &AttachmentService,
+    mut status: TenantShardMigrationStatus,
+) -> Result<()> {
+    let mut reported = None;
+    while matches!(
+        status.state,
+        TenantShardMigrationState::WarmingUp | TenantShardMigrationState::CuttingOver
+    ) {
+        let downloaded = status
+            .progress
+            .as_ref()
+            .map(|p| (p.bytes_downloaded, p.bytes_total));
+        if let Some((bytes_downloaded, bytes_total)) = downloaded {
+            if reported != downloaded {
+                println!(
+                    "shard {} warming up on pageserver {}: {bytes_downloaded}/{bytes_total} bytes downloaded",
+                    status.tenant_shard_id, status.node_id
+                );
+                reported = downloaded;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        status = attachment_service
+            .tenant_migration(status.tenant_shard_id)
+            .await?;
+    }
+
+    if status.state == TenantShardMigrationState::Failed {
+        bail!(
+            "Migration of shard {} to pageserver {} failed: {}",
+            status.tenant_shard_id,
+            status.node_id,
+            status.error.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
 async fn handle_attachment_service(
     sub_match: &ArgMatches,
     env: &local_env::LocalEnv,
@@ -1158,6 +1857,19 @@ async fn handle_attachment_service(
                 eprintln!("start failed: {e}");
                 exit(1);
             }
+
+            // The pageservers register themselves when they start, so this is
+            // only needed for those started before the attachment service.
+            for ps_conf in &env.pageservers {
+                let pageserver = PageServerNode::from_env(env, ps_conf);
+                if pageserver.check_status().await.is_err() {
+                    continue;
+                }
+                if let Err(e) = pageserver.register().await {
+                    eprintln!("pageserver {} registration failed: {e:#}", ps_conf.id);
+                    exit(1);
+                }
+            }
         }
 
         Some(("stop", stop_match)) => {
@@ -1177,6 +1889,252 @@ async fn handle_attachment_service(
     Ok(())
 }
 
+async fn handle_proxy(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
+    match sub_match.subcommand() {
+        Some(("start", start_match)) => {
+            let endpoint_id = start_match
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided to route to"))?;
+            let cplane = ComputeControlPlane::load(env.clone())?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .ok_or_else(|| anyhow!("endpoint {endpoint_id} not found"))?;
+            if endpoint.status() != EndpointStatus::Running {
+                bail!("endpoint {endpoint_id} is not running");
+            }
+
+            let pg_port = start_match
+                .get_one::<u16>("pg-port")
+                .copied()
+                .unwrap_or(DEFAULT_PROXY_PG_PORT);
+            let http_port = start_match
+                .get_one::<u16>("http-port")
+                .copied()
+                .unwrap_or(DEFAULT_PROXY_HTTP_PORT);
+            if let Err(e) = proxy::start_proxy_process(env, endpoint, pg_port, http_port).await {
+                eprintln!("proxy start failed: {e:#}");
+                exit(1);
+            }
+            println!(
+                "Connect with e.g. psql 'postgres://<role>:<password>@{endpoint_id}.localtest.me:{pg_port}/postgres'"
+            );
+        }
+
+        Some(("stop", stop_match)) => {
+            let immediate = stop_match
+                .get_one::<String>("stop-mode")
+                .map(|s| s.as_str())
+                == Some("immediate");
+
+            if let Err(e) = proxy::stop_proxy_process(env, immediate) {
+                eprintln!("proxy stop failed: {e:#}");
+                exit(1);
+            }
+        }
+        Some((sub_name, _)) => bail!("Unexpected proxy subcommand '{}'", sub_name),
+        None => bail!("no proxy subcommand provided"),
+    }
+    Ok(())
+}
+
+async fn handle_workload(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
+    match sub_match.subcommand() {
+        Some(("run", run_match)) => {
+            let endpoint_id = run_match.get_one::<String>("endpoint").ok_or_else(|| {
+                anyhow!("No endpoint ID was provided to run the workload against")
+            })?;
+            let cplane = ComputeControlPlane::load(env.clone())?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .ok_or_else(|| anyhow!("endpoint {endpoint_id} not found"))?;
+            if endpoint.status() != EndpointStatus::Running {
+                bail!("endpoint {endpoint_id} is not running");
+            }
+
+            // Run each pattern once, as the runs of a pattern would share its tables
+            let mut patterns = Vec::new();
+            for pattern in run_match
+                .get_many::<WorkloadPattern>("pattern")
+                .into_iter()
+                .flatten()
+            {
+                if !patterns.contains(pattern) {
+                    patterns.push(*pattern);
+                }
+            }
+            if patterns.is_empty() {
+                patterns.push(WorkloadPattern::Pgbench);
+            }
+            let config = WorkloadConfig {
+                patterns,
+                scale: *run_match.get_one::<u32>("scale").unwrap(),
+                duration: (*run_match
+                    .get_one::<humantime::Duration>("duration")
+                    .unwrap())
+                .into(),
+                clients: *run_match.get_one::<u32>("clients").unwrap(),
+                seed: *run_match.get_one::<u64>("seed").unwrap(),
+            };
+            println!(
+                "Running workload {:?} against endpoint {endpoint_id} for {}",
+                config.patterns,
+                humantime::format_duration(config.duration)
+            );
+            workload::run_workload(endpoint, &config).await?;
+        }
+        Some((sub_name, _)) => bail!("Unexpected workload subcommand '{}'", sub_name),
+        None => bail!("no workload subcommand provided"),
+    }
+    Ok(())
+}
+
+fn handle_supervise(sub_match: &ArgMatches) -> Result<()> {
+    let spec_path = sub_match
+        .get_one::<String>("spec")
+        .expect("spec is required");
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(background_process::supervise(Path::new(spec_path)))
+}
+
+async fn handle_status(env: &local_env::LocalEnv) -> Result<()> {
+    let mut table = comfy_table::Table::new();
+    table.load_preset(comfy_table::presets::NOTHING);
+    table.set_header(["SERVICE", "HEALTH", "PID", "RESTARTS", "LAST CRASH"]);
+    let mut add_row = |service: String, status: ProcessStatus, responds: Option<bool>| {
+        let health = match (status.pid, responds) {
+            (None, _) => "stopped",
+            (Some(_), _) if status.paused => "paused",
+            (Some(_), None) => "running",
+            (Some(_), Some(true)) => "healthy",
+            (Some(_), Some(false)) => "unresponsive",
+        };
+        let (restarts, last_crash) = match status.supervisor {
+            Some(supervisor) => (
+                supervisor.restarts.to_string(),
+                supervisor
+                    .last_crash
+                    .map(|crash| format!("{}: {}", crash.at, crash.reason))
+                    .unwrap_or_default(),
+            ),
+            None => ("-".to_string(), String::new()),
+        };
+        table.add_row([
+            service,
+            health.to_string(),
+            status.pid.map(|pid| pid.to_string()).unwrap_or_default(),
+            restarts,
+            last_crash,
+        ]);
+    };
+
+    let broker_status_url = env.broker.client_url().join("status")?;
+    let broker_responds = reqwest::get(broker_status_url).await.is_ok();
+    add_row(
+        "storage_broker".to_string(),
+        broker::broker_process_status(env)?,
+        Some(broker_responds),
+    );
+
+    if env.control_plane_api.is_some() {
+        let attachment_service = AttachmentService::from_env(env);
+        add_row(
+            "attachment_service".to_string(),
+            attachment_service.process_status()?,
+            Some(attachment_service.status().await.is_ok()),
+        );
+    }
+
+    for ps_conf in &env.pageservers {
+        let pageserver = PageServerNode::from_env(env, ps_conf);
+        add_row(
+            format!("pageserver {}", ps_conf.id),
+            pageserver.process_status()?,
+            Some(pageserver.check_status().await.is_ok()),
+        );
+    }
+
+    for sk_conf in &env.safekeepers {
+        let safekeeper = SafekeeperNode::from_env(env, sk_conf);
+        let status = safekeeper.process_status()?;
+        // A paused safekeeper accepts connections, but never responds.
+        let responds = if status.paused {
+            None
+        } else {
+            Some(safekeeper.check_status().await.is_ok())
+        };
+        add_row(format!("safekeeper {}", sk_conf.id), status, responds);
+    }
+
+    // The proxy's HTTP port is picked by the OS, so it can't be checked.
+    add_row("proxy".to_string(), proxy::proxy_process_status(env)?, None);
+
+    println!("{table}");
+    Ok(())
+}
+
+async fn handle_snapshot(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
+    match sub_match.subcommand() {
+        Some(("create", create_match)) => {
+            let name = create_match
+                .get_one::<String>("name")
+                .expect("name is required");
+            // Stop cleanly, for the services to find their data consistent
+            // when started from the snapshot.
+            if !try_stop_all(env, false).await {
+                bail!("not all services could be stopped, not taking the snapshot");
+            }
+            let path = snapshot::create_snapshot(env, name, GIT_VERSION)?;
+            println!("Snapshot {name} created at {}", path.display());
+        }
+        Some(("restore", restore_match)) => {
+            let name = restore_match
+                .get_one::<String>("name")
+                .expect("name is required");
+            if !try_stop_all(env, false).await {
+                bail!("not all services could be stopped, not restoring the snapshot");
+            }
+            let manifest = snapshot::restore_snapshot(env, name)?;
+            if manifest.neon_local_version != GIT_VERSION {
+                eprintln!(
+                    "Warning: snapshot {name} was created by neon_local {}, this is {GIT_VERSION}",
+                    manifest.neon_local_version
+                );
+            }
+            println!(
+                "Snapshot {name} from {} restored, start the services with 'neon_local start'",
+                manifest.created_at
+            );
+        }
+        Some(("list", _)) => {
+            let mut table = comfy_table::Table::new();
+            table.load_preset(comfy_table::presets::NOTHING);
+            table.set_header(["NAME", "CREATED", "PAGESERVERS", "SAFEKEEPERS"]);
+            for manifest in snapshot::list_snapshots(env)? {
+                let ids = |ids: &[NodeId]| {
+                    ids.iter()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                };
+                table.add_row([
+                    manifest.name.clone(),
+                    manifest.created_at.clone(),
+                    ids(&manifest.pageservers),
+                    ids(&manifest.safekeepers),
+                ]);
+            }
+            println!("{table}");
+        }
+        Some((sub_name, _)) => bail!("Unexpected snapshot subcommand '{}'", sub_name),
+        None => bail!("no snapshot subcommand provided"),
+    }
+    Ok(())
+}
+
 fn get_safekeeper(env: &local_env::LocalEnv, id: NodeId) -> Result<SafekeeperNode> {
     if let Some(node) = env.safekeepers.iter().find(|node| node.id == id) {
         Ok(SafekeeperNode::from_env(env, node))
@@ -1200,6 +2158,9 @@ async fn handle_safekeeper(sub_match: &ArgMatches, env: &local_env::LocalEnv) ->
         Some(safekeeper_command_data) => safekeeper_command_data,
         None => bail!("no safekeeper subcommand provided"),
     };
+    if sub_name == "fault" {
+        return handle_safekeeper_fault(sub_args, env).await;
+    }
 
     // All the commands take an optional safekeeper name argument
     let sk_id = if let Some(id_str) = sub_args.get_one::<String>("id") {
@@ -1252,6 +2213,51 @@ async fn handle_safekeeper(sub_match: &ArgMatches, env: &local_env::LocalEnv) ->
     Ok(())
 }
 
+async fn handle_safekeeper_fault(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
+    let (sub_name, sub_args) = match sub_match.subcommand() {
+        Some(fault_command_data) => fault_command_data,
+        None => bail!("no safekeeper fault subcommand provided"),
+    };
+
+    let sk_id = if let Some(id_str) = sub_args.get_one::<String>("id") {
+        NodeId(id_str.parse().context("while parsing safekeeper id")?)
+    } else {
+        DEFAULT_SAFEKEEPER_ID
+    };
+    let safekeeper = get_safekeeper(env, sk_id)?;
+
+    let failpoint = |name: &str, actions: &str| FailpointConfig {
+        name: name.to_string(),
+        actions: actions.to_string(),
+    };
+    match sub_name {
+        "pause" => safekeeper.pause()?,
+        "resume" => safekeeper.resume()?,
+        "partition" => {
+            safekeeper
+                .configure_failpoints(vec![failpoint("sk-partition", "return")])
+                .await?;
+            println!("safekeeper {sk_id} partitioned");
+        }
+        "heal" => {
+            safekeeper
+                .configure_failpoints(vec![failpoint("sk-partition", "off")])
+                .await?;
+            println!("safekeeper {sk_id} healed");
+        }
+        "failpoint" => {
+            let name = sub_args.get_one::<String>("name").unwrap();
+            let actions = sub_args.get_one::<String>("actions").unwrap();
+            safekeeper
+                .configure_failpoints(vec![failpoint(name, actions)])
+                .await?;
+            println!("safekeeper {sk_id} failpoint {name} set to '{actions}'");
+        }
+        _ => bail!("Unexpected safekeeper fault subcommand '{}'", sub_name),
+    }
+    Ok(())
+}
+
 async fn handle_start_all(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> anyhow::Result<()> {
     // Endpoints are not started automatically
 
@@ -1299,18 +2305,32 @@ async fn handle_stop_all(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> R
     Ok(())
 }
 
-async fn try_stop_all(env: &local_env::LocalEnv, immediate: bool) {
+/// Stop all the services, carrying on if some fail to stop. Returns whether
+/// all of them were stopped.
+async fn try_stop_all(env: &local_env::LocalEnv, immediate: bool) -> bool {
+    let mut stopped = true;
+
+    if let Err(e) = proxy::stop_proxy_process(env, immediate) {
+        eprintln!("proxy stop failed: {e:#}");
+        stopped = false;
+    }
+
     // Stop all endpoints
     match ComputeControlPlane::load(env.clone()) {
         Ok(cplane) => {
             for (_k, node) in cplane.endpoints {
+                if node.status() == EndpointStatus::Stopped {
+                    continue;
+                }
                 if let Err(e) = node.stop(if immediate { "immediate" } else { "fast " }, false) {
                     eprintln!("postgres stop failed: {e:#}");
+                    stopped = false;
                 }
             }
         }
         Err(e) => {
-            eprintln!("postgres stop failed, could not restore control plane data from env: {e:#}")
+            eprintln!("postgres stop failed, could not restore control plane data from env: {e:#}");
+            stopped = false;
         }
     }
 
@@ -1318,6 +2338,7 @@ async fn try_stop_all(env: &local_env::LocalEnv, immediate: bool) {
         let pageserver = PageServerNode::from_env(env, ps_conf);
         if let Err(e) = pageserver.stop(immediate) {
             eprintln!("pageserver {} stop failed: {:#}", ps_conf.id, e);
+            stopped = false;
         }
     }
 
@@ -1325,22 +2346,32 @@ async fn try_stop_all(env: &local_env::LocalEnv, immediate: bool) {
         let safekeeper = SafekeeperNode::from_env(env, node);
         if let Err(e) = safekeeper.stop(immediate) {
             eprintln!("safekeeper {} stop failed: {:#}", safekeeper.id, e);
+            stopped = false;
         }
     }
 
     if let Err(e) = broker::stop_broker_process(env) {
         eprintln!("neon broker stop failed: {e:#}");
+        stopped = false;
     }
 
     if env.control_plane_api.is_some() {
         let attachment_service = AttachmentService::from_env(env);
         if let Err(e) = attachment_service.stop(immediate).await {
             eprintln!("attachment service stop failed: {e:#}");
+            stopped = false;
         }
     }
+
+    stopped
 }
 
 fn cli() -> Command {
+    let wait_arg = Arg::new("wait")
+        .long("wait")
+        .action(ArgAction::SetTrue)
+        .help("Also wait for the resulting layer changes to be uploaded to remote storage");
+
     let branch_name_arg = Arg::new("branch-name")
         .long("branch-name")
         .help("Name of the branch to be created or used as an alias for other services")
@@ -1362,6 +2393,11 @@ fn cli() -> Command {
     let endpoint_pageserver_id_arg = Arg::new("endpoint-pageserver-id")
         .long("pageserver-id")
         .required(false);
+    // --pageserver-id for tenant and timeline commands, which otherwise use the
+    // default pageserver or the attachment service
+    let target_pageserver_id_arg = endpoint_pageserver_id_arg
+        .clone()
+        .help("Use this pageserver instead of the default one or the attachment service");
 
     let safekeeper_extra_opt_arg = Arg::new("safekeeper-extra-opt")
         .short('e')
@@ -1474,17 +2510,44 @@ fn cli() -> Command {
                 )
                 .arg(pg_version_arg.clone())
                 .arg(force_arg)
+                .arg(
+                    Arg::new("profile")
+                        .long("profile")
+                        .value_parser(value_parser!(ConfigProfile))
+                        .required(false)
+                        .help("Apply curated settings to the pageserver and safekeeper configs: 'perf' for benchmarks, 'tiny' to exercise compaction and GC with little data"),
+                )
         )
         .subcommand(
             Command::new("timeline")
             .about("Manage timelines")
             .subcommand(Command::new("list")
                 .about("List all timelines, available to this pageserver")
-                .arg(tenant_id_arg.clone()))
+                .arg(tenant_id_arg.clone())
+                .arg(target_pageserver_id_arg.clone()))
+            .subcommand(Command::new("tree")
+                .about("Print the branches of a tenant with their branch points, heads and the size GC retains for them")
+                .arg(tenant_id_arg.clone())
+                .arg(target_pageserver_id_arg.clone()))
+            .subcommand(Command::new("compact")
+                .about("Compact a timeline on all the shards of its tenant, which needs pageservers built with the testing feature")
+                .arg(tenant_id_arg.clone())
+                .arg(timeline_id_arg.clone())
+                .arg(branch_name_arg.clone().help("Branch of the timeline, if --timeline-id is not given, main by default"))
+                .arg(wait_arg.clone()))
+            .subcommand(Command::new("gc")
+                .about("Garbage collect a timeline on all the shards of its tenant")
+                .arg(tenant_id_arg.clone())
+                .arg(timeline_id_arg.clone())
+                .arg(branch_name_arg.clone().help("Branch of the timeline, if --timeline-id is not given, main by default"))
+                .arg(Arg::new("gc-horizon").long("gc-horizon").value_parser(value_parser!(u64))
+                    .help("Override the gc_horizon of the tenant, in bytes of WAL"))
+                .arg(wait_arg.clone()))
             .subcommand(Command::new("branch")
                 .about("Create a new timeline, using another timeline as a base, copying its data")
                 .arg(tenant_id_arg.clone())
                 .arg(branch_name_arg.clone())
+                .arg(target_pageserver_id_arg.clone())
                 .arg(Arg::new("ancestor-branch-name").long("ancestor-branch-name")
                     .help("Use last Lsn of another timeline (and its data) as base when creating the new timeline. The timeline gets resolved by its branch name.").required(false))
                 .arg(Arg::new("ancestor-start-lsn").long("ancestor-start-lsn")
@@ -1495,6 +2558,7 @@ fn cli() -> Command {
                 .arg(timeline_id_arg.clone())
                 .arg(branch_name_arg.clone())
                 .arg(pg_version_arg.clone())
+                .arg(target_pageserver_id_arg.clone())
             )
             .subcommand(Command::new("import")
                 .about("Import timeline from basebackup directory")
@@ -1504,6 +2568,7 @@ fn cli() -> Command {
                     .help("Name to assign to the imported timeline"))
                 .arg(Arg::new("base-tarfile")
                     .long("base-tarfile")
+                    .alias("base")
                     .value_parser(value_parser!(PathBuf))
                     .help("Basebackup tarfile to import")
                 )
@@ -1514,33 +2579,57 @@ fn cli() -> Command {
                     .value_parser(value_parser!(PathBuf))
                     .help("Wal to add after base")
                 )
+                .arg(Arg::new("wal-dir")
+                    .long("wal-dir")
+                    .alias("wal")
+                    .value_parser(value_parser!(PathBuf))
+                    .conflicts_with("wal-tarfile")
+                    .help("pg_wal directory with the WAL to add after base, up to --end-lsn")
+                )
                 .arg(Arg::new("end-lsn").long("end-lsn")
                     .help("Lsn the basebackup ends at"))
                 .arg(pg_version_arg.clone())
+                .arg(Arg::new("validate").long("validate").action(ArgAction::SetTrue).required(false)
+                    .help("Start the endpoint of the imported timeline and list its databases, then stop it"))
             )
         ).subcommand(
             Command::new("tenant")
             .arg_required_else_help(true)
             .about("Manage tenants")
-            .subcommand(Command::new("list"))
+            .subcommand(Command::new("list")
+                .arg(target_pageserver_id_arg.clone()))
             .subcommand(Command::new("create")
                 .arg(tenant_id_arg.clone())
+                .arg(target_pageserver_id_arg.clone().help("Place all the shards of the tenant on this pageserver"))
                 .arg(timeline_id_arg.clone().help("Use a specific timeline id when creating a tenant and its initial timeline"))
                 .arg(Arg::new("config").short('c').num_args(1).action(ArgAction::Append).required(false))
                 .arg(pg_version_arg.clone())
                 .arg(Arg::new("set-default").long("set-default").action(ArgAction::SetTrue).required(false)
                     .help("Use this tenant in future CLI commands where tenant_id is needed, but not specified"))
                 .arg(Arg::new("shard-count").value_parser(value_parser!(u8)).long("shard-count").action(ArgAction::Set).help("Number of shards in the new tenant (default 1)"))
-                .arg(Arg::new("shard-stripe-size").value_parser(value_parser!(u32)).long("shard-stripe-size").action(ArgAction::Set).help("Sharding stripe size in pages"))
+                .arg(Arg::new("shard-stripe-size").value_parser(value_parser!(u32)).long("shard-stripe-size").alias("stripe-size").action(ArgAction::Set).help("Sharding stripe size in pages"))
+                )
+            .subcommand(Command::new("attach")
+                .about("Attach an existing tenant from remote storage, read-only, to a pageserver holding no other tenant. The pageserver is switched to that remote storage, and no longer scheduled to by the attachment service")
+                .arg(tenant_id_arg.clone().required(true))
+                .arg(target_pageserver_id_arg.clone().required(true).help("Dedicated pageserver to attach the tenant to"))
+                .arg(Arg::new("remote-storage").long("remote-storage").required(true)
+                    .help("Remote storage config as a TOML inline table, e.g. \"{local_path='/abs/path'}\" or \"{bucket_name='b', bucket_region='r', prefix_in_bucket='pageserver/v1'}\""))
+                .arg(Arg::new("generation").long("generation").value_parser(value_parser!(u32)).required(true)
+                    .help("Generation to attach in, which must be at least the one the tenant was last attached in"))
                 )
             .subcommand(Command::new("set-default").arg(tenant_id_arg.clone().required(true))
                 .about("Set a particular tenant as default in future CLI commands where tenant_id is needed, but not specified"))
             .subcommand(Command::new("config")
                 .arg(tenant_id_arg.clone())
+                .arg(target_pageserver_id_arg.clone())
                 .arg(Arg::new("config").short('c').num_args(1).action(ArgAction::Append).required(false)))
             .subcommand(Command::new("migrate")
-                .about("Migrate a tenant from one pageserver to another")
+                .about("Migrate a tenant, or one of its shards, to another pageserver through the attachment service")
                 .arg(tenant_id_arg.clone())
+                .arg(Arg::new("to").long("to").help("Id of the pageserver to migrate to").required_unless_present("pageserver-id"))
+                .arg(Arg::new("warm-threshold").long("warm-threshold").value_parser(value_parser!(f64))
+                    .help("Warm up a secondary location on the pageserver before migrating, until this fraction of the shard's layers is downloaded"))
                 .arg(pageserver_id_arg.clone()))
             .subcommand(Command::new("status")
                 .about("Human readable summary of the tenant's shards and attachment locations")
@@ -1548,7 +2637,7 @@ fn cli() -> Command {
             .subcommand(Command::new("shard-split")
                 .about("Increase the number of shards in the tenant")
                 .arg(tenant_id_arg.clone())
-                .arg(Arg::new("shard-count").value_parser(value_parser!(u8)).long("shard-count").action(ArgAction::Set).help("Number of shards in the new tenant (default 1)"))
+                .arg(Arg::new("shard-count").value_parser(value_parser!(u8)).long("shard-count").action(ArgAction::Set).required(true).help("Number of shards after the split"))
                 )
         )
         .subcommand(
@@ -1572,6 +2661,16 @@ fn cli() -> Command {
                 .subcommand(Command::new("restart")
                     .about("Restart local pageserver")
                     .arg(pageserver_config_args.clone())
+                    .arg(Arg::new("drain")
+                        .long("drain")
+                        .action(ArgAction::SetTrue)
+                        .help("Migrate the attached shards off the pageserver before restarting it, and back afterwards"))
+                )
+                .subcommand(Command::new("drain")
+                    .about("Migrate the attached shards off the pageserver, ahead of a restart")
+                )
+                .subcommand(Command::new("fill")
+                    .about("Migrate shards back to the pageserver after a drain")
                 )
                 .subcommand(Command::new("set-state")
                     .arg(Arg::new("availability").value_parser(value_parser!(NodeAvailability)).long("availability").action(ArgAction::Set).help("Availability state: offline,active"))
@@ -1582,12 +2681,67 @@ fn cli() -> Command {
         )
         .subcommand(
             Command::new("attachment_service")
+                .alias("storage_controller")
                 .arg_required_else_help(true)
                 .about("Manage attachment_service")
                 .subcommand(Command::new("start").about("Start local pageserver").arg(pageserver_config_args.clone()))
                 .subcommand(Command::new("stop").about("Stop local pageserver")
                             .arg(stop_mode_arg.clone()))
         )
+        .subcommand(
+            Command::new("status")
+                .about("Show the health of the services, and their last crash if they are supervised")
+        )
+        .subcommand(
+            Command::new("supervise")
+                .hide(true)
+                .about("Run a background process and restart it when it crashes, used by the other commands")
+                .arg(Arg::new("spec").required(true))
+        )
+        .subcommand(
+            Command::new("snapshot")
+                .arg_required_else_help(true)
+                .about("Manage snapshots of the whole environment, stopping all the services to take or restore one")
+                .subcommand(Command::new("create")
+                            .about("Archive the environment to a snapshot")
+                            .arg(Arg::new("name").required(true).help("Name of the snapshot")))
+                .subcommand(Command::new("restore")
+                            .about("Replace the environment with a snapshot")
+                            .arg(Arg::new("name").required(true).help("Name of the snapshot")))
+                .subcommand(Command::new("list")
+                            .about("List the snapshots"))
+        )
+        .subcommand(
+            Command::new("proxy")
+                .arg_required_else_help(true)
+                .about("Manage the proxy, which routes all the connections to one endpoint")
+                .subcommand(Command::new("start")
+                            .about("Start the proxy in front of a running endpoint, with a self-signed TLS certificate")
+                            .arg(endpoint_id_arg.clone().required(true))
+                            .arg(pg_port_arg.clone().help("Port for Postgres connections, 4432 by default"))
+                            .arg(http_port_arg.clone().help("Port for WebSocket and SQL-over-HTTP connections, 4444 by default")))
+                .subcommand(Command::new("stop")
+                            .about("Stop the proxy")
+                            .arg(stop_mode_arg.clone()))
+        )
+        .subcommand(
+            Command::new("workload")
+                .arg_required_else_help(true)
+                .about("Generate a reproducible load, for performance investigations")
+                .subcommand(Command::new("run")
+                            .about("Run a mix of workload patterns against a running endpoint, concurrently")
+                            .arg(Arg::new("endpoint").long("endpoint").required(true).help("Endpoint to run the workload against"))
+                            .arg(Arg::new("scale").long("scale").value_parser(value_parser!(u32)).default_value("1")
+                                .help("Size of the data sets: pgbench scale factor, 100k rows per COPY and 100 relations per round for each unit"))
+                            .arg(Arg::new("duration").long("duration").value_parser(value_parser!(humantime::Duration)).default_value("60s")
+                                .help("How long to run the workload for"))
+                            .arg(Arg::new("clients").long("clients").value_parser(value_parser!(u32)).default_value("4")
+                                .help("Number of concurrent pgbench clients"))
+                            .arg(Arg::new("seed").long("seed").value_parser(value_parser!(u64)).default_value("0")
+                                .help("Seed of the generated data, so that runs are reproducible"))
+                            .arg(Arg::new("pattern").long("pattern").value_parser(value_parser!(WorkloadPattern)).action(ArgAction::Append)
+                                .help("Workload pattern to run, can be repeated. pgbench by default")))
+        )
         .subcommand(
             Command::new("safekeeper")
                 .arg_required_else_help(true)
@@ -1604,10 +2758,30 @@ fn cli() -> Command {
                 )
                 .subcommand(Command::new("restart")
                             .about("Restart local safekeeper")
-                            .arg(safekeeper_id_arg)
+                            .arg(safekeeper_id_arg.clone())
                             .arg(stop_mode_arg.clone())
                             .arg(safekeeper_extra_opt_arg)
                 )
+                .subcommand(Command::new("fault")
+                            .arg_required_else_help(true)
+                            .about("Inject faults into a local safekeeper, to reproduce quorum loss and recovery. Partitions and failpoints need a safekeeper built with the testing feature")
+                            .subcommand(Command::new("pause")
+                                        .about("Suspend the safekeeper with SIGSTOP, keeping its connections open")
+                                        .arg(safekeeper_id_arg.clone()))
+                            .subcommand(Command::new("resume")
+                                        .about("Resume a paused safekeeper")
+                                        .arg(safekeeper_id_arg.clone()))
+                            .subcommand(Command::new("partition")
+                                        .about("Cut the safekeeper off: close its WAL service connections, refuse new ones, and stop publishing to the broker")
+                                        .arg(safekeeper_id_arg.clone()))
+                            .subcommand(Command::new("heal")
+                                        .about("End the partition of the safekeeper")
+                                        .arg(safekeeper_id_arg.clone()))
+                            .subcommand(Command::new("failpoint")
+                                        .about("Configure a failpoint of the safekeeper")
+                                        .arg(Arg::new("name").required(true).help("Name of the failpoint, e.g. sk-write-zeroes"))
+                                        .arg(Arg::new("actions").required(true).help("Actions of the failpoint, in the format of the fail crate, e.g. 'return', 'off', '50%return'"))
+                                        .arg(Arg::new("id").long("id").help("safekeeper id"))))
         )
         .subcommand(
             Command::new("endpoint")
@@ -1630,6 +2804,14 @@ fn cli() -> Command {
                             .required(false))
                     .arg(pg_version_arg.clone())
                     .arg(hot_standby_arg.clone())
+                    .arg(
+                        Arg::new("read-only")
+                            .long("read-only")
+                            .action(ArgAction::SetTrue)
+                            .requires("lsn")
+                            .conflicts_with("hot-standby")
+                            .help("Create a static endpoint at --lsn, leasing the LSN on the pageserver so that GC keeps it readable")
+                            .required(false))
                 )
                 .subcommand(Command::new("start")
                     .about("Start postgres.\n If the endpoint doesn't exist yet, it is created.")
@@ -1637,6 +2819,12 @@ fn cli() -> Command {
                     .arg(endpoint_pageserver_id_arg.clone())
                     .arg(safekeepers_arg)
                     .arg(remote_ext_config_args)
+                    .arg(
+                        Arg::new("lfc-state")
+                            .long("lfc-state")
+                            .action(ArgAction::SetTrue)
+                            .help("Save the state of the local file cache in the endpoint directory, and prewarm the cache from it on start")
+                            .required(false))
                 )
                 .subcommand(Command::new("reconfigure")
                             .about("Reconfigure the endpoint")
@@ -1663,6 +2851,20 @@ fn cli() -> Command {
                             .value_parser(["smart", "fast", "immediate"])
                             .default_value("fast")
                     )
+                    .arg(
+                        Arg::new("check-durability")
+                            .help("Check that the WAL flushed by a primary before a smart or fast shutdown is on a quorum of its safekeepers and in remote storage once stopped. Requires pageservers built with the testing feature")
+                            .long("check-durability")
+                            .action(ArgAction::SetTrue)
+                            .required(false)
+                    )
+                    .arg(
+                        Arg::new("durability-timeout")
+                            .help("How long to wait for the WAL of the stopped endpoint to be durable")
+                            .long("durability-timeout")
+                            .value_parser(value_parser!(humantime::Duration))
+                            .default_value("30s")
+                    )
                 )
 
         )
@@ -27,6 +27,7 @@ pub async fn start_broker_process(env: &local_env::LocalEnv) -> anyhow::Result<(
         args,
         [],
         background_process::InitialPidFile::Create(storage_broker_pid_file_path(env)),
+        env.supervision(Some(format!("{}status", broker.client_url()))),
         || async {
             let url = broker.client_url();
             let status_url = url.join("status").with_context(|| {
@@ -51,6 +52,12 @@ pub fn stop_broker_process(env: &local_env::LocalEnv) -> anyhow::Result<()> {
     background_process::stop_process(true, "storage_broker", &storage_broker_pid_file_path(env))
 }
 
+pub fn broker_process_status(
+    env: &local_env::LocalEnv,
+) -> anyhow::Result<background_process::ProcessStatus> {
+    background_process::process_status(&storage_broker_pid_file_path(env))
+}
+
 fn storage_broker_pid_file_path(env: &local_env::LocalEnv) -> Utf8PathBuf {
     Utf8PathBuf::from_path_buf(env.base_data_dir.join("storage_broker.pid"))
         .expect("non-Unicode path")
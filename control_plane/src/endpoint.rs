@@ -41,8 +41,9 @@ use std::net::SocketAddr;
 use std::net::TcpStream;
 use std::path::PathBuf;
 use std::process::Command;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
 use compute_api::spec::RemoteExtSpec;
@@ -51,10 +52,13 @@ use nix::sys::signal::Signal;
 use serde::{Deserialize, Serialize};
 use url::Host;
 use utils::id::{NodeId, TenantId, TimelineId};
+use utils::lsn::Lsn;
 
 use crate::attachment_service::AttachmentService;
 use crate::local_env::LocalEnv;
+use crate::pageserver::PageServerNode;
 use crate::postgresql_conf::PostgresConf;
+use crate::safekeeper::SafekeeperNode;
 
 use compute_api::responses::{ComputeState, ComputeStatus};
 use compute_api::spec::{Cluster, ComputeFeature, ComputeMode, ComputeSpec};
@@ -210,7 +214,7 @@ pub struct Endpoint {
     pub http_address: SocketAddr,
 
     // postgres major version in the format: 14, 15, etc.
-    pg_version: u32,
+    pub pg_version: u32,
 
     // These are not part of the endpoint as such, but the environment
     // the endpoint runs in.
@@ -355,31 +359,7 @@ impl Endpoint {
                 conf.append("recovery_target_lsn", &lsn.to_string());
             }
             ComputeMode::Replica => {
-                assert!(!self.env.safekeepers.is_empty());
-
-                // TODO: use future host field from safekeeper spec
-                // Pass the list of safekeepers to the replica so that it can connect to any of them,
-                // whichever is available.
-                let sk_ports = self
-                    .env
-                    .safekeepers
-                    .iter()
-                    .map(|x| x.get_compute_port().to_string())
-                    .collect::<Vec<_>>()
-                    .join(",");
-                let sk_hosts = vec!["localhost"; self.env.safekeepers.len()].join(",");
-
-                let connstr = format!(
-                    "host={} port={} options='-c timeline_id={} tenant_id={}' application_name=replica replication=true",
-                    sk_hosts,
-                    sk_ports,
-                    &self.timeline_id.to_string(),
-                    &self.tenant_id.to_string(),
-                );
-
-                let slot_name = format!("repl_{}_", self.timeline_id);
-                conf.append("primary_conninfo", connstr.as_str());
-                conf.append("primary_slot_name", slot_name.as_str());
+                // compute_ctl points primary_conninfo at the safekeepers
                 conf.append("hot_standby", "on");
                 // prefetching of blocks referenced in WAL doesn't make sense for us
                 // Neon hot standby ignores pages that are not in the shared_buffers
@@ -500,6 +480,7 @@ impl Endpoint {
         pageservers: Vec<(Host, u16)>,
         remote_ext_config: Option<&String>,
         shard_stripe_size: usize,
+        lfc_state: bool,
     ) -> Result<()> {
         if self.status() == EndpointStatus::Running {
             anyhow::bail!("The endpoint is already running");
@@ -517,7 +498,8 @@ impl Endpoint {
         assert!(!pageserver_connstring.is_empty());
 
         let mut safekeeper_connstrings = Vec::new();
-        if self.mode == ComputeMode::Primary {
+        // a replica streams WAL from the safekeepers, and writes to them once promoted
+        if matches!(self.mode, ComputeMode::Primary | ComputeMode::Replica) {
             for sk_id in safekeepers {
                 let sk = self
                     .env
@@ -544,8 +526,10 @@ impl Endpoint {
         // Create spec file
         let spec = ComputeSpec {
             skip_pg_catalog_updates: self.skip_pg_catalog_updates,
+            migrations: Default::default(),
             format_version: 1.0,
             operation_uuid: None,
+            endpoint_id: Some(self.endpoint_id.clone()),
             features: self.features.clone(),
             cluster: Cluster {
                 cluster_id: None, // project ID: not used
@@ -566,6 +550,8 @@ impl Endpoint {
             remote_extensions,
             pgbouncer_settings: None,
             shard_stripe_size: Some(shard_stripe_size),
+            sql_metrics: Vec::new(),
+            preload_libraries: Vec::new(),
         };
         let spec_path = self.endpoint_path().join("spec.json");
         std::fs::write(spec_path, serde_json::to_string_pretty(&spec)?)?;
@@ -602,6 +588,18 @@ impl Endpoint {
             cmd.args(["--remote-ext-config", remote_ext_config]);
         }
 
+        // Keep the state of the local file cache in the endpoint directory, which
+        // survives restarts of the endpoint, unlike the data directory.
+        if lfc_state {
+            cmd.args([
+                "--lfc-state-path",
+                self.endpoint_path()
+                    .join("lfc_state.json")
+                    .to_str()
+                    .unwrap(),
+            ]);
+        }
+
         let child = cmd.spawn()?;
         // set up a scopeguard to kill & wait for the child in case we panic or bail below
         let child = scopeguard::guard(child, |mut child| {
@@ -652,7 +650,10 @@ impl Endpoint {
                         }
                         ComputeStatus::Empty
                         | ComputeStatus::ConfigurationPending
-                        | ComputeStatus::Configuration => {
+                        | ComputeStatus::Configuration
+                        | ComputeStatus::TerminationPending
+                        | ComputeStatus::RestartPending
+                        | ComputeStatus::Terminated => {
                             bail!("unexpected compute status: {:?}", state.status)
                         }
                     }
@@ -717,6 +718,7 @@ impl Endpoint {
         if pageservers.is_empty() {
             let attachment_service = AttachmentService::from_env(&self.env);
             let locate_result = attachment_service.tenant_locate(self.tenant_id).await?;
+            spec.shard_stripe_size = Some(locate_result.shard_params.stripe_size.0 as usize);
             pageservers = locate_result
                 .shards
                 .into_iter()
@@ -783,6 +785,114 @@ impl Endpoint {
         Ok(())
     }
 
+    /// The LSN up to which the endpoint has flushed WAL, all of which must be
+    /// durable once it is stopped.
+    pub async fn flush_lsn(&self) -> Result<Lsn> {
+        let (client, conn) = tokio_postgres::connect(&self.connstr(), tokio_postgres::NoTls)
+            .await
+            .context("Failed to connect to the endpoint")?;
+        tokio::spawn(conn);
+        let row = client
+            .query_one("SELECT pg_current_wal_flush_lsn()::text", &[])
+            .await?;
+        Lsn::from_str(row.get(0)).context("Invalid flush LSN")
+    }
+
+    /// Wait until the WAL of the endpoint up to `lsn` is on a quorum of the
+    /// safekeepers it was started with, and in remote storage for all the
+    /// shards of the tenant. The pageservers are asked to flush their in-memory
+    /// layers once they have received the WAL, which requires them to be built
+    /// with the `testing` feature.
+    pub async fn wait_for_durable_wal(&self, lsn: Lsn, timeout: Duration) -> Result<()> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(500);
+        let deadline = Instant::now() + timeout;
+
+        let spec: ComputeSpec = {
+            let spec_path = self.endpoint_path().join("spec.json");
+            let file = std::fs::File::open(spec_path)?;
+            serde_json::from_reader(file)?
+        };
+        let safekeepers = self
+            .env
+            .safekeepers
+            .iter()
+            .filter(|conf| {
+                let connstring = format!("127.0.0.1:{}", conf.get_compute_port());
+                spec.safekeeper_connstrings.contains(&connstring)
+            })
+            .map(|conf| SafekeeperNode::from_env(&self.env, conf))
+            .collect::<Vec<_>>();
+        let quorum = safekeepers.len() / 2 + 1;
+        while !safekeepers.is_empty() {
+            let mut flushed = 0;
+            for safekeeper in &safekeepers {
+                // An unavailable safekeeper just doesn't count for the quorum.
+                if let Ok(status) = safekeeper
+                    .timeline_status(self.tenant_id, self.timeline_id)
+                    .await
+                {
+                    if status.flush_lsn >= lsn {
+                        flushed += 1;
+                    }
+                }
+            }
+            if flushed >= quorum {
+                break;
+            }
+            if Instant::now() > deadline {
+                bail!(
+                    "WAL up to {lsn} reached {flushed} safekeepers out of {} within {}, a quorum is {quorum}",
+                    safekeepers.len(),
+                    humantime::format_duration(timeout)
+                );
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+
+        let attachment_service = AttachmentService::from_env(&self.env);
+        for shard in attachment_service
+            .tenant_locate(self.tenant_id)
+            .await?
+            .shards
+        {
+            let pageserver =
+                PageServerNode::from_env(&self.env, self.env.get_pageserver_conf(shard.node_id)?);
+            let mut flushed = false;
+            loop {
+                let timeline = pageserver
+                    .http_client
+                    .timeline_list(&shard.shard_id)
+                    .await?
+                    .into_iter()
+                    .find(|timeline| timeline.timeline_id == self.timeline_id)
+                    .with_context(|| format!("timeline not found on shard {}", shard.shard_id))?;
+                if timeline.remote_consistent_lsn >= lsn {
+                    break;
+                }
+                if timeline.last_record_lsn >= lsn && !flushed {
+                    pageserver
+                        .http_client
+                        .timeline_checkpoint(shard.shard_id, self.timeline_id, true)
+                        .await
+                        .with_context(|| format!("failed to flush shard {}", shard.shard_id))?;
+                    flushed = true;
+                    continue;
+                }
+                if Instant::now() > deadline {
+                    bail!(
+                        "WAL up to {lsn} not in remote storage for shard {} within {}: last_record_lsn {}, remote_consistent_lsn {}",
+                        shard.shard_id,
+                        humantime::format_duration(timeout),
+                        timeline.last_record_lsn,
+                        timeline.remote_consistent_lsn
+                    );
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+        Ok(())
+    }
+
     pub fn connstr(&self) -> String {
         format!(
             "postgresql://{}@{}:{}/{}",
@@ -7,10 +7,13 @@
 #![deny(clippy::undocumented_unsafe_blocks)]
 
 pub mod attachment_service;
-mod background_process;
+pub mod background_process;
 pub mod broker;
 pub mod endpoint;
 pub mod local_env;
 pub mod pageserver;
 pub mod postgresql_conf;
+pub mod proxy;
 pub mod safekeeper;
+pub mod snapshot;
+pub mod workload;
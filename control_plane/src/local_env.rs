@@ -22,6 +22,7 @@ use utils::{
     id::{NodeId, TenantId, TenantTimelineId, TimelineId},
 };
 
+use crate::background_process::Supervision;
 use crate::safekeeper::SafekeeperNode;
 
 pub const DEFAULT_PG_VERSION: u32 = 15;
@@ -64,6 +65,11 @@ pub struct LocalEnv {
     #[serde(default)]
     pub private_key_path: PathBuf,
 
+    /// Supervise the storage services, the broker and the proxy, restarting
+    /// them with backoff when they crash or stop responding.
+    #[serde(default)]
+    pub restart_on_crash: bool,
+
     pub broker: NeonBroker,
 
     /// This Vec must always contain at least one pageserver
@@ -82,6 +88,11 @@ pub struct LocalEnv {
     #[serde(default)]
     pub control_plane_compute_hook_api: Option<Url>,
 
+    /// How often the attachment service heartbeats the pageservers, as a human
+    /// readable duration; attachment service default if not set.
+    #[serde(default)]
+    pub pageserver_heartbeat_interval: Option<String>,
+
     /// Keep human-readable aliases in memory (and persist them to config), to hide ZId hex strings from the user.
     #[serde(default)]
     // A `HashMap<String, HashMap<TenantId, TimelineId>>` would be more appropriate here,
@@ -126,6 +137,14 @@ pub struct PageServerConf {
     // auth type used for the PG and HTTP ports
     pub pg_auth_type: AuthType,
     pub http_auth_type: AuthType,
+
+    /// Remote storage config, as a TOML inline table, to use instead of the
+    /// local file system directory shared by all the pageservers.
+    pub remote_storage: Option<String>,
+
+    /// Availability zone reported to the attachment service on registration,
+    /// for it to spread the locations of tenants across zones.
+    pub availability_zone: Option<String>,
 }
 
 impl Default for PageServerConf {
@@ -136,6 +155,8 @@ impl Default for PageServerConf {
             listen_http_addr: String::new(),
             pg_auth_type: AuthType::Trust,
             http_auth_type: AuthType::Trust,
+            remote_storage: None,
+            availability_zone: None,
         }
     }
 }
@@ -147,7 +168,13 @@ pub struct SafekeeperConf {
     pub pg_port: u16,
     pub pg_tenant_only_port: Option<u16>,
     pub http_port: u16,
+    pub http_tenant_only_port: Option<u16>,
+    /// fsync WAL and control files. Disabling it is only for tests and
+    /// benchmarks.
     pub sync: bool,
+    /// Max time to wait for more WAL before fsyncing it, as a human readable
+    /// duration; safekeeper default if not set.
+    pub wal_flush_batch_delay: Option<String>,
     pub remote_storage: Option<String>,
     pub backup_threads: Option<u32>,
     pub auth_enabled: bool,
@@ -160,7 +187,9 @@ impl Default for SafekeeperConf {
             pg_port: 0,
             pg_tenant_only_port: None,
             http_port: 0,
+            http_tenant_only_port: None,
             sync: true,
+            wal_flush_batch_delay: None,
             remote_storage: None,
             backup_threads: None,
             auth_enabled: false,
@@ -193,6 +222,63 @@ impl ValueEnum for InitForceMode {
     }
 }
 
+/// Curated settings for the services, which `neon_local init --profile` applies
+/// to the configs it generates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigProfile {
+    /// Settings close to production, with large caches, for benchmarks.
+    Perf,
+    /// Small caches and layers, and no fsync, so that compaction and GC kick
+    /// in after writing little data, and tests run fast.
+    Tiny,
+}
+
+impl ValueEnum for ConfigProfile {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Perf, Self::Tiny]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(match self {
+            ConfigProfile::Perf => "perf",
+            ConfigProfile::Tiny => "tiny",
+        }))
+    }
+}
+
+impl ConfigProfile {
+    /// Pageserver config overrides. The overrides given on the command line
+    /// go after these, and so replace the keys they share, e.g. `tenant_config`
+    /// as a whole.
+    pub fn pageserver_overrides(&self) -> &'static [&'static str] {
+        match self {
+            ConfigProfile::Perf => &[
+                // 1 GiB of 8 KiB pages
+                "page_cache_size=131072",
+                "max_file_descriptors=10000",
+            ],
+            ConfigProfile::Tiny => &[
+                // 8 MiB of 8 KiB pages
+                "page_cache_size=1024",
+                "max_file_descriptors=100",
+                "tenant_config={checkpoint_distance=4194304, compaction_target_size=4194304, \
+                 compaction_threshold=3, image_creation_threshold=2, gc_period='1 m'}",
+            ],
+        }
+    }
+
+    pub fn apply_to_safekeeper(&self, conf: &mut SafekeeperConf) {
+        match self {
+            ConfigProfile::Perf => {
+                conf.sync = true;
+            }
+            ConfigProfile::Tiny => {
+                conf.sync = false;
+            }
+        }
+    }
+}
+
 impl SafekeeperConf {
     /// Compute is served by port on which only tenant scoped tokens allowed, if
     /// it is configured.
@@ -243,6 +329,16 @@ impl LocalEnv {
         self.neon_distrib_dir.join("storage_broker")
     }
 
+    pub fn proxy_bin(&self) -> PathBuf {
+        self.neon_distrib_dir.join("proxy")
+    }
+
+    /// How to supervise a background process, per `restart_on_crash`.
+    pub fn supervision(&self, liveness_url: Option<String>) -> Option<Supervision> {
+        self.restart_on_crash
+            .then_some(Supervision { liveness_url })
+    }
+
     pub fn endpoints_path(&self) -> PathBuf {
         self.base_data_dir.join("endpoints")
     }
@@ -125,9 +125,12 @@ impl PageServerNode {
             .iter()
             .any(|c| c.starts_with("remote_storage"))
         {
-            overrides.push(format!(
-                "remote_storage={{local_path='../{PAGESERVER_REMOTE_STORAGE_DIR}'}}"
-            ));
+            overrides.push(match &self.conf.remote_storage {
+                Some(remote_storage) => format!("remote_storage={remote_storage}"),
+                None => {
+                    format!("remote_storage={{local_path='../{PAGESERVER_REMOTE_STORAGE_DIR}'}}")
+                }
+            });
         }
 
         if self.conf.http_auth_type != AuthType::Trust || self.conf.pg_auth_type != AuthType::Trust
@@ -237,6 +240,10 @@ impl PageServerNode {
             args.iter().map(Cow::as_ref),
             self.pageserver_env_variables()?,
             background_process::InitialPidFile::Expect(self.pid_file()),
+            self.env.supervision(Some(format!(
+                "http://{}/v1/status",
+                self.conf.listen_http_addr
+            ))),
             || async {
                 let st = self.check_status().await;
                 match st {
@@ -249,25 +256,32 @@ impl PageServerNode {
         .await?;
 
         if register {
-            let attachment_service = AttachmentService::from_env(&self.env);
-            let (pg_host, pg_port) =
-                parse_host_port(&self.conf.listen_pg_addr).expect("Unable to parse listen_pg_addr");
-            let (http_host, http_port) = parse_host_port(&self.conf.listen_http_addr)
-                .expect("Unable to parse listen_http_addr");
-            attachment_service
-                .node_register(NodeRegisterRequest {
-                    node_id: self.conf.id,
-                    listen_pg_addr: pg_host.to_string(),
-                    listen_pg_port: pg_port.unwrap_or(5432),
-                    listen_http_addr: http_host.to_string(),
-                    listen_http_port: http_port.unwrap_or(80),
-                })
-                .await?;
+            self.register().await?;
         }
 
         Ok(())
     }
 
+    /// Register the pageserver with the attachment service, which does nothing
+    /// if it is registered already.
+    pub async fn register(&self) -> anyhow::Result<()> {
+        let attachment_service = AttachmentService::from_env(&self.env);
+        let (pg_host, pg_port) =
+            parse_host_port(&self.conf.listen_pg_addr).expect("Unable to parse listen_pg_addr");
+        let (http_host, http_port) =
+            parse_host_port(&self.conf.listen_http_addr).expect("Unable to parse listen_http_addr");
+        attachment_service
+            .node_register(NodeRegisterRequest {
+                node_id: self.conf.id,
+                listen_pg_addr: pg_host.to_string(),
+                listen_pg_port: pg_port.unwrap_or(5432),
+                listen_http_addr: http_host.to_string(),
+                listen_http_port: http_port.unwrap_or(80),
+                availability_zone_id: self.conf.availability_zone.clone(),
+            })
+            .await
+    }
+
     fn pageserver_basic_args<'a>(
         &self,
         config_overrides: &'a [&'a str],
@@ -311,6 +325,10 @@ impl PageServerNode {
         background_process::stop_process(immediate, "pageserver", &self.pid_file())
     }
 
+    pub fn process_status(&self) -> anyhow::Result<background_process::ProcessStatus> {
+        background_process::process_status(&self.pid_file())
+    }
+
     pub async fn page_server_psql_client(
         &self,
     ) -> anyhow::Result<(
@@ -0,0 +1,135 @@
+//! Code to manage the proxy
+//!
+//! neon_local runs the proxy with the `postgres` auth backend, which looks up
+//! the role secrets in, and routes all the connections to, a single endpoint:
+//! the one given to `neon_local proxy start`. The roles need a password to log
+//! in through the proxy. The data of the proxy, including its self-signed TLS
+//! certificate, is stored in
+//!
+//! ```text
+//!   .neon/proxy
+//! ```
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context};
+use camino::Utf8PathBuf;
+use tokio::net::TcpStream;
+
+use crate::endpoint::Endpoint;
+use crate::{background_process, local_env};
+
+pub const DEFAULT_PROXY_PG_PORT: u16 = 4432;
+/// Port for WebSocket and SQL-over-HTTP connections.
+pub const DEFAULT_PROXY_HTTP_PORT: u16 = 4444;
+
+pub async fn start_proxy_process(
+    env: &local_env::LocalEnv,
+    endpoint: &Endpoint,
+    pg_port: u16,
+    http_port: u16,
+) -> anyhow::Result<()> {
+    let proxy_dir = proxy_dir(env);
+    std::fs::create_dir_all(&proxy_dir)
+        .with_context(|| format!("Failed to create proxy directory {proxy_dir:?}"))?;
+
+    prepare_endpoint(endpoint).await?;
+
+    let cert_path = proxy_dir.join("proxy.crt");
+    let key_path = proxy_dir.join("proxy.key");
+    if !key_path.exists() {
+        generate_tls_cert(&cert_path, &key_path)?;
+    }
+
+    println!(
+        "Starting proxy at 127.0.0.1:{pg_port}, routing to endpoint at {}",
+        endpoint.pg_address
+    );
+
+    let args = [
+        format!("--proxy=127.0.0.1:{pg_port}"),
+        format!("--wss=127.0.0.1:{http_port}"),
+        // Not used locally, so let the OS pick ports that don't conflict
+        // with other proxies.
+        "--mgmt=127.0.0.1:0".to_string(),
+        "--http=127.0.0.1:0".to_string(),
+        "--auth-backend=postgres".to_string(),
+        format!("--auth-endpoint={}", endpoint.connstr()),
+        format!("--tls-cert={}", cert_path.display()),
+        format!("--tls-key={}", key_path.display()),
+    ];
+
+    background_process::start_process(
+        "proxy",
+        &proxy_dir,
+        &env.proxy_bin(),
+        args,
+        [],
+        background_process::InitialPidFile::Create(proxy_pid_file_path(env)),
+        // The HTTP port of the proxy is picked by the OS, so only crashes are
+        // detected.
+        env.supervision(None),
+        || async { Ok(TcpStream::connect(("127.0.0.1", pg_port)).await.is_ok()) },
+    )
+    .await
+    .context("Failed to spawn proxy subprocess")?;
+    Ok(())
+}
+
+pub fn stop_proxy_process(env: &local_env::LocalEnv, immediate: bool) -> anyhow::Result<()> {
+    background_process::stop_process(immediate, "proxy", &proxy_pid_file_path(env))
+}
+
+pub fn proxy_process_status(
+    env: &local_env::LocalEnv,
+) -> anyhow::Result<background_process::ProcessStatus> {
+    background_process::process_status(&proxy_pid_file_path(env))
+}
+
+/// Create the table the `postgres` auth backend reads the IP allowlists from,
+/// which is empty, so that any IP address is allowed.
+async fn prepare_endpoint(endpoint: &Endpoint) -> anyhow::Result<()> {
+    let (client, conn) = tokio_postgres::connect(&endpoint.connstr(), tokio_postgres::NoTls)
+        .await
+        .context("Failed to connect to the endpoint, is it running?")?;
+    tokio::spawn(conn);
+    client
+        .batch_execute(
+            "CREATE SCHEMA IF NOT EXISTS neon_control_plane;
+             CREATE TABLE IF NOT EXISTS neon_control_plane.endpoints (
+                 endpoint_id VARCHAR(255) PRIMARY KEY,
+                 allowed_ips VARCHAR(255)
+             );",
+        )
+        .await
+        .context("Failed to create the neon_control_plane.endpoints table")?;
+    Ok(())
+}
+
+fn generate_tls_cert(cert_path: &Path, key_path: &Path) -> anyhow::Result<()> {
+    // localtest.me and its subdomains resolve to 127.0.0.1, and the proxy
+    // takes the endpoint ID from the first label of the server name.
+    let output = Command::new("openssl")
+        .args(["req", "-new", "-x509", "-days", "365", "-nodes", "-text"])
+        .args(["-out", cert_path.to_str().unwrap()])
+        .args(["-keyout", key_path.to_str().unwrap()])
+        .args(["-subj", "/CN=*.localtest.me"])
+        .args(["-addext", "subjectAltName = DNS:*.localtest.me"])
+        .output()
+        .context("failed to generate the proxy TLS certificate")?;
+    if !output.status.success() {
+        bail!(
+            "openssl failed: '{}'",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+fn proxy_dir(env: &local_env::LocalEnv) -> PathBuf {
+    env.base_data_dir.join("proxy")
+}
+
+fn proxy_pid_file_path(env: &local_env::LocalEnv) -> Utf8PathBuf {
+    Utf8PathBuf::from_path_buf(proxy_dir(env).join("proxy.pid")).expect("non-Unicode path")
+}
@@ -13,8 +13,13 @@ use anyhow::Context;
 use camino::Utf8PathBuf;
 use postgres_connection::PgConnectionConfig;
 use reqwest::{IntoUrl, Method};
+use serde::Deserialize;
 use thiserror::Error;
-use utils::{http::error::HttpErrorBody, id::NodeId};
+use utils::auth::{Claims, Scope};
+use utils::failpoint_support::ConfigureFailpointsRequest;
+use utils::http::error::HttpErrorBody;
+use utils::id::{NodeId, TenantId, TimelineId};
+use utils::lsn::Lsn;
 
 use crate::{
     background_process,
@@ -56,6 +61,13 @@ impl ResponseErrorMessageExt for reqwest::Response {
     }
 }
 
+/// The part of the status of a timeline on a safekeeper that we use.
+#[derive(Debug, Deserialize)]
+pub struct TimelineStatus {
+    pub flush_lsn: Lsn,
+    pub commit_lsn: Lsn,
+}
+
 //
 // Control routines for safekeeper.
 //
@@ -142,9 +154,27 @@ impl SafekeeperNode {
             let listen_pg_tenant_only = format!("127.0.0.1:{}", pg_tenant_only_port);
             args.extend(["--listen-pg-tenant-only".to_owned(), listen_pg_tenant_only]);
         }
+        // safekeeper refuses to serve tenant only http without auth
+        let http_tenant_only_port = self
+            .conf
+            .http_tenant_only_port
+            .filter(|_| self.conf.auth_enabled);
+        if let Some(http_tenant_only_port) = http_tenant_only_port {
+            let listen_http_tenant_only = format!("127.0.0.1:{}", http_tenant_only_port);
+            args.extend([
+                "--listen-http-tenant-only".to_owned(),
+                listen_http_tenant_only,
+            ]);
+        }
         if !self.conf.sync {
             args.push("--no-sync".to_owned());
         }
+        if let Some(wal_flush_batch_delay) = &self.conf.wal_flush_batch_delay {
+            args.extend([
+                "--wal-flush-batch-delay".to_owned(),
+                wal_flush_batch_delay.clone(),
+            ]);
+        }
 
         let broker_endpoint = format!("{}", self.env.broker.client_url());
         args.extend(["--broker-endpoint".to_owned(), broker_endpoint]);
@@ -181,6 +211,10 @@ impl SafekeeperNode {
                 "--http-auth-public-key-path".to_owned(),
                 key_path_string.clone(),
             ]);
+            args.extend([
+                "--http-tenant-only-auth-public-key-path".to_owned(),
+                key_path_string.clone(),
+            ]);
         }
 
         args.extend(extra_opts);
@@ -190,8 +224,10 @@ impl SafekeeperNode {
             &datadir,
             &self.env.safekeeper_bin(),
             &args,
-            [],
+            self.safekeeper_env_variables()?,
             background_process::InitialPidFile::Expect(self.pid_file()),
+            self.env
+                .supervision(Some(format!("{}/status", self.http_base_url))),
             || async {
                 match self.check_status().await {
                     Ok(()) => Ok(true),
@@ -203,6 +239,18 @@ impl SafekeeperNode {
         .await
     }
 
+    fn safekeeper_env_variables(&self) -> anyhow::Result<Vec<(String, String)>> {
+        // Generate a token to connect from safekeeper to peers
+        Ok(if self.conf.auth_enabled {
+            let token = self
+                .env
+                .generate_auth_token(&Claims::new(None, Scope::SafekeeperData))?;
+            vec![("SAFEKEEPER_AUTH_TOKEN".to_owned(), token)]
+        } else {
+            Vec::new()
+        })
+    }
+
     ///
     /// Stop the server.
     ///
@@ -219,6 +267,18 @@ impl SafekeeperNode {
         )
     }
 
+    pub fn process_status(&self) -> anyhow::Result<background_process::ProcessStatus> {
+        background_process::process_status(&self.pid_file())
+    }
+
+    pub fn pause(&self) -> anyhow::Result<()> {
+        background_process::pause_process(&format!("safekeeper {}", self.id), &self.pid_file())
+    }
+
+    pub fn resume(&self) -> anyhow::Result<()> {
+        background_process::resume_process(&format!("safekeeper {}", self.id), &self.pid_file())
+    }
+
     fn http_request<U: IntoUrl>(&self, method: Method, url: U) -> reqwest::RequestBuilder {
         // TODO: authentication
         //if self.env.auth_type == AuthType::NeonJWT {
@@ -227,6 +287,39 @@ impl SafekeeperNode {
         self.http_client.request(method, url)
     }
 
+    pub async fn timeline_status(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> Result<TimelineStatus> {
+        Ok(self
+            .http_request(
+                Method::GET,
+                format!(
+                    "{}/tenant/{tenant_id}/timeline/{timeline_id}",
+                    self.http_base_url
+                ),
+            )
+            .send()
+            .await?
+            .error_from_body()
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Configure failpoints, which requires the safekeeper to be built with
+    /// the `testing` feature.
+    pub async fn configure_failpoints(&self, failpoints: ConfigureFailpointsRequest) -> Result<()> {
+        self.http_request(Method::PUT, format!("{}/failpoints", self.http_base_url))
+            .json(&failpoints)
+            .send()
+            .await?
+            .error_from_body()
+            .await?;
+        Ok(())
+    }
+
     pub async fn check_status(&self) -> Result<()> {
         self.http_request(Method::GET, format!("{}/{}", self.http_base_url, "status"))
             .send()
@@ -0,0 +1,191 @@
+//! Snapshots of the whole local environment, for reproducible test fixtures
+//! and bug reports.
+//!
+//! A snapshot is a tarball of the repository directory, taken while all the
+//! services are stopped, which starts with a manifest describing it. They are
+//! stored in
+//!
+//! ```text
+//!   .neon/snapshots/<name>.tar
+//! ```
+use std::fs;
+use std::io::{self, Seek};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use utils::id::NodeId;
+
+use crate::local_env::LocalEnv;
+
+/// The first entry of the tarball.
+const MANIFEST_FILE: &str = "snapshot.json";
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub name: String,
+    /// RFC 3339 timestamp.
+    pub created_at: String,
+    /// Version of the neon_local which took the snapshot. The data of the
+    /// services may not be readable by other versions.
+    pub neon_local_version: String,
+    pub pageservers: Vec<NodeId>,
+    pub safekeepers: Vec<NodeId>,
+}
+
+/// Archive the repository directory, except the snapshots, to a snapshot
+/// called `name`. The services must be stopped.
+pub fn create_snapshot(
+    env: &LocalEnv,
+    name: &str,
+    neon_local_version: &str,
+) -> anyhow::Result<PathBuf> {
+    let path = snapshot_path(env, name)?;
+    if path.exists() {
+        bail!("snapshot {name} already exists at {path:?}");
+    }
+    fs::create_dir_all(snapshots_dir(env))?;
+
+    let manifest = SnapshotManifest {
+        name: name.to_string(),
+        created_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        neon_local_version: neon_local_version.to_string(),
+        pageservers: env.pageservers.iter().map(|ps| ps.id).collect(),
+        safekeepers: env.safekeepers.iter().map(|sk| sk.id).collect(),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+
+    let tmp_path = path.with_extension("tar.tmp");
+    let file =
+        fs::File::create(&tmp_path).with_context(|| format!("failed to create {tmp_path:?}"))?;
+    let mut builder = tar::Builder::new(file);
+    builder.follow_symlinks(false);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, MANIFEST_FILE, manifest.as_slice())?;
+
+    let mut entries = fs::read_dir(&env.base_data_dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let file_name = entry.file_name();
+        if file_name == SNAPSHOTS_DIR {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            builder.append_dir_all(&file_name, entry.path())?;
+        } else {
+            builder.append_path_with_name(entry.path(), &file_name)?;
+        }
+    }
+    builder.into_inner()?.sync_all()?;
+    fs::rename(&tmp_path, &path).with_context(|| format!("failed to write {path:?}"))?;
+
+    Ok(path)
+}
+
+/// Replace the contents of the repository directory, except the snapshots,
+/// with the snapshot called `name`. The services must be stopped.
+pub fn restore_snapshot(env: &LocalEnv, name: &str) -> anyhow::Result<SnapshotManifest> {
+    let path = snapshot_path(env, name)?;
+    // Check the whole archive before removing anything.
+    let manifest = read_manifest(&path)?;
+    validate_archive(&path)?;
+
+    for entry in fs::read_dir(&env.base_data_dir)? {
+        let entry = entry?;
+        if entry.file_name() == SNAPSHOTS_DIR {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    let mut archive = tar::Archive::new(fs::File::open(&path)?);
+    archive.set_preserve_permissions(true);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()? == Path::new(MANIFEST_FILE) {
+            continue;
+        }
+        entry.unpack_in(&env.base_data_dir)?;
+    }
+
+    Ok(manifest)
+}
+
+/// The manifests of all the snapshots, oldest first.
+pub fn list_snapshots(env: &LocalEnv) -> anyhow::Result<Vec<SnapshotManifest>> {
+    let dir = snapshots_dir(env);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut manifests = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "tar") {
+            manifests.push(read_manifest(&path)?);
+        }
+    }
+    // RFC 3339 timestamps sort chronologically
+    manifests.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    Ok(manifests)
+}
+
+/// Read the whole archive, to check that it is complete and that it only has
+/// files within the repository directory.
+fn validate_archive(path: &Path) -> anyhow::Result<()> {
+    let file = fs::File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+    let mut archive = tar::Archive::new(file);
+    for entry in archive.entries()? {
+        let mut entry = entry.with_context(|| format!("snapshot {path:?} is corrupted"))?;
+        let entry_path = entry.path()?.into_owned();
+        if !entry_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            bail!("snapshot {path:?} has a file outside of the repository: {entry_path:?}");
+        }
+        let size = entry.size();
+        if io::copy(&mut entry, &mut io::sink())? != size {
+            bail!("snapshot {path:?} is truncated at {entry_path:?}");
+        }
+    }
+    // The entries end at the zero blocks which end the archive, rather than at
+    // the end of the file if it was truncated between two entries.
+    let mut file = archive.into_inner();
+    if file.stream_position()? >= file.metadata()?.len() {
+        bail!("snapshot {path:?} is truncated");
+    }
+    Ok(())
+}
+
+fn read_manifest(path: &Path) -> anyhow::Result<SnapshotManifest> {
+    let file = fs::File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+    let mut archive = tar::Archive::new(file);
+    let Some(entry) = archive.entries()?.next() else {
+        bail!("snapshot {path:?} is empty");
+    };
+    let mut entry = entry?;
+    if entry.path()? != Path::new(MANIFEST_FILE) {
+        bail!("snapshot {path:?} doesn't start with {MANIFEST_FILE}");
+    }
+    serde_json::from_reader(&mut entry).with_context(|| format!("invalid manifest in {path:?}"))
+}
+
+fn snapshots_dir(env: &LocalEnv) -> PathBuf {
+    env.base_data_dir.join(SNAPSHOTS_DIR)
+}
+
+fn snapshot_path(env: &LocalEnv, name: &str) -> anyhow::Result<PathBuf> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        bail!("invalid snapshot name '{name}'");
+    }
+    Ok(snapshots_dir(env).join(format!("{name}.tar")))
+}
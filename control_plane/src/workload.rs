@@ -0,0 +1,270 @@
+//! A workload generator, for performance investigations to start from a
+//! common, reproducible load.
+//!
+//! A workload runs a mix of patterns against an endpoint, concurrently, for a
+//! given duration. Next to pgbench's TPC-B-like load, the patterns exercise
+//! what is specific to Neon: WAL ingestion with large COPYs, catalog and
+//! relation size churn with many relations, and timeline creation and deletion
+//! with branch churn. The data is generated from a seed, so that two runs with
+//! the same settings write the same data.
+use std::io::Cursor;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use futures::future::BoxFuture;
+use futures::{pin_mut, FutureExt, SinkExt};
+use pageserver_api::models::TimelineCreateRequest;
+use tokio::process::Command;
+use utils::id::TimelineId;
+
+use crate::attachment_service::AttachmentService;
+use crate::endpoint::Endpoint;
+
+/// Rows written by each COPY, per unit of scale.
+const COPY_ROWS_PER_SCALE: u64 = 100_000;
+/// Relations created in each round, per unit of scale.
+const RELATIONS_PER_SCALE: u32 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorkloadPattern {
+    /// pgbench's TPC-B-like transactions, on `scale` times 100k accounts.
+    Pgbench,
+    /// COPY of `scale` times 100k rows into a table, truncated between rounds.
+    Copy,
+    /// Creation of `scale` times 100 small tables with an index, dropped in
+    /// the next round.
+    Relations,
+    /// Creation and deletion of branches of the endpoint's timeline.
+    BranchChurn,
+}
+
+impl ValueEnum for WorkloadPattern {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Pgbench,
+            Self::Copy,
+            Self::Relations,
+            Self::BranchChurn,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(match self {
+            WorkloadPattern::Pgbench => "pgbench",
+            WorkloadPattern::Copy => "copy",
+            WorkloadPattern::Relations => "relations",
+            WorkloadPattern::BranchChurn => "branch-churn",
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct WorkloadConfig {
+    pub patterns: Vec<WorkloadPattern>,
+    pub scale: u32,
+    pub duration: Duration,
+    /// Concurrent pgbench clients.
+    pub clients: u32,
+    pub seed: u64,
+}
+
+/// Run the workload against a running endpoint, printing a summary of each
+/// pattern once the duration has elapsed.
+pub async fn run_workload(endpoint: &Endpoint, config: &WorkloadConfig) -> anyhow::Result<()> {
+    if config.patterns.is_empty() {
+        bail!("no workload patterns given");
+    }
+    let deadline = Instant::now() + config.duration;
+
+    let runs: Vec<BoxFuture<anyhow::Result<String>>> = config
+        .patterns
+        .iter()
+        .map(|pattern| match pattern {
+            WorkloadPattern::Pgbench => run_pgbench(endpoint, config).boxed(),
+            WorkloadPattern::Copy => run_copy(endpoint, config, deadline).boxed(),
+            WorkloadPattern::Relations => run_relations(endpoint, config, deadline).boxed(),
+            WorkloadPattern::BranchChurn => run_branch_churn(endpoint, deadline).boxed(),
+        })
+        .collect();
+    let summaries = futures::future::try_join_all(runs).await?;
+
+    for (pattern, summary) in config.patterns.iter().zip(summaries) {
+        let name = pattern.to_possible_value().unwrap();
+        println!("{}: {summary}", name.get_name());
+    }
+    Ok(())
+}
+
+async fn connect(endpoint: &Endpoint) -> anyhow::Result<tokio_postgres::Client> {
+    let (client, conn) = tokio_postgres::connect(&endpoint.connstr(), tokio_postgres::NoTls)
+        .await
+        .context("Failed to connect to the endpoint, is it running?")?;
+    tokio::spawn(conn);
+    Ok(client)
+}
+
+async fn run_pgbench(endpoint: &Endpoint, config: &WorkloadConfig) -> anyhow::Result<String> {
+    let pg_bin_dir = endpoint.env.pg_bin_dir(endpoint.pg_version)?;
+    let pg_lib_dir = endpoint.env.pg_lib_dir(endpoint.pg_version)?;
+    let pgbench = |args: &[String]| {
+        let mut cmd = Command::new(pg_bin_dir.join("pgbench"));
+        cmd.args(args)
+            .arg(endpoint.connstr())
+            .env("LD_LIBRARY_PATH", &pg_lib_dir)
+            .env("DYLD_LIBRARY_PATH", &pg_lib_dir)
+            .stdin(Stdio::null());
+        cmd
+    };
+
+    let status = pgbench(&[
+        "-i".to_string(),
+        "-q".to_string(),
+        format!("-s{}", config.scale),
+    ])
+    .status()
+    .await
+    .context("Failed to run pgbench")?;
+    if !status.success() {
+        bail!("pgbench initialization failed: {status}");
+    }
+
+    // The output is passed through, for the progress reports.
+    let output = pgbench(&[
+        format!("-c{}", config.clients),
+        format!("-j{}", config.clients),
+        format!("-T{}", config.duration.as_secs().max(1)),
+        format!("--random-seed={}", config.seed),
+        "-P10".to_string(),
+    ])
+    .stderr(Stdio::inherit())
+    .output()
+    .await
+    .context("Failed to run pgbench")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    print!("{stdout}");
+    if !output.status.success() {
+        bail!("pgbench failed: {}", output.status);
+    }
+    Ok(stdout
+        .lines()
+        .find(|line| line.starts_with("tps = "))
+        .unwrap_or("no tps reported")
+        .to_string())
+}
+
+async fn run_copy(
+    endpoint: &Endpoint,
+    config: &WorkloadConfig,
+    deadline: Instant,
+) -> anyhow::Result<String> {
+    let client = connect(endpoint).await?;
+    client
+        .batch_execute(
+            "DROP TABLE IF EXISTS workload_copy;
+             CREATE TABLE workload_copy (id bigint, payload text);",
+        )
+        .await?;
+
+    let rows_per_copy = COPY_ROWS_PER_SCALE * config.scale as u64;
+    let started_at = Instant::now();
+    let mut rows = 0;
+    let mut bytes = 0;
+    while Instant::now() < deadline {
+        let mut data = Vec::new();
+        for id in rows..rows + rows_per_copy {
+            let word = splitmix64(config.seed ^ id);
+            // About 100 bytes per row.
+            data.extend(format!("{id}\t{word:016x}").as_bytes());
+            for _ in 0..5 {
+                data.extend(format!("{word:016x}").as_bytes());
+            }
+            data.push(b'\n');
+        }
+        bytes += data.len();
+
+        client.batch_execute("TRUNCATE workload_copy").await?;
+        let sink = client.copy_in("COPY workload_copy FROM STDIN").await?;
+        pin_mut!(sink);
+        sink.send(Cursor::new(data)).await?;
+        sink.finish().await?;
+        rows += rows_per_copy;
+    }
+
+    let secs = started_at.elapsed().as_secs_f64();
+    Ok(format!(
+        "copied {rows} rows, {:.1} MiB/s",
+        bytes as f64 / (1024.0 * 1024.0) / secs
+    ))
+}
+
+async fn run_relations(
+    endpoint: &Endpoint,
+    config: &WorkloadConfig,
+    deadline: Instant,
+) -> anyhow::Result<String> {
+    let client = connect(endpoint).await?;
+    let relations_per_round = RELATIONS_PER_SCALE * config.scale;
+    let drop_round = |round: u32| {
+        (0..relations_per_round)
+            .map(|i| format!("DROP TABLE IF EXISTS workload_rel_{round}_{i};"))
+            .collect::<String>()
+    };
+
+    let mut created = 0;
+    let mut round = 0;
+    while Instant::now() < deadline {
+        let mut sql = String::new();
+        for i in 0..relations_per_round {
+            sql.push_str(&format!(
+                "CREATE TABLE workload_rel_{round}_{i} (id int PRIMARY KEY, v text);
+                 INSERT INTO workload_rel_{round}_{i} SELECT g, md5(g::text) FROM generate_series(1, 10) g;"
+            ));
+        }
+        client.batch_execute(&sql).await?;
+        created += relations_per_round;
+        if round > 0 {
+            client.batch_execute(&drop_round(round - 1)).await?;
+        }
+        round += 1;
+    }
+    if round > 0 {
+        client.batch_execute(&drop_round(round - 1)).await?;
+    }
+
+    Ok(format!("created and dropped {created} relations"))
+}
+
+async fn run_branch_churn(endpoint: &Endpoint, deadline: Instant) -> anyhow::Result<String> {
+    let attachment_service = AttachmentService::from_env(&endpoint.env);
+    let mut branches = 0;
+    while Instant::now() < deadline {
+        let new_timeline_id = TimelineId::generate();
+        attachment_service
+            .tenant_timeline_create(
+                endpoint.tenant_id,
+                TimelineCreateRequest {
+                    new_timeline_id,
+                    ancestor_timeline_id: Some(endpoint.timeline_id),
+                    ancestor_start_lsn: None,
+                    existing_initdb_timeline_id: None,
+                    pg_version: None,
+                },
+            )
+            .await?;
+        attachment_service
+            .tenant_timeline_delete(endpoint.tenant_id, new_timeline_id)
+            .await?;
+        branches += 1;
+    }
+    Ok(format!("created and deleted {branches} branches"))
+}
+
+/// A fast, seedable hash, for reproducible data.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
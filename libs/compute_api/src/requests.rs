@@ -2,6 +2,7 @@
 
 use crate::spec::ComputeSpec;
 use serde::Deserialize;
+use utils::lsn::Lsn;
 
 /// Request of the /configure API
 ///
@@ -12,3 +13,30 @@ use serde::Deserialize;
 pub struct ConfigurationRequest {
     pub spec: ComputeSpec,
 }
+
+/// Request of the /lfc_size API
+#[derive(Deserialize, Debug)]
+pub struct LfcSizeRequest {
+    /// New size of the local file cache in bytes, which it also can't grow
+    /// beyond when the VM is upscaled. `None` lets the vm-monitor size the
+    /// cache by the memory of the VM again, starting with the next scaling.
+    pub size_bytes: Option<u64>,
+}
+
+/// Request of the /promote API
+#[derive(Deserialize, Debug, Default)]
+pub struct PromoteRequest {
+    /// LSN to replay the WAL up to before promoting, usually the one the old
+    /// primary was terminated at.
+    #[serde(default)]
+    pub wait_lsn: Option<Lsn>,
+}
+
+/// Request of the /restart API
+#[derive(Deserialize, Debug, Default)]
+pub struct RestartRequest {
+    /// How long to wait for the client connections to close before they are
+    /// terminated. compute_ctl picks a default if not set.
+    #[serde(default)]
+    pub drain_timeout_ms: Option<u64>,
+}
@@ -3,6 +3,8 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize, Serializer};
 
+use utils::lsn::Lsn;
+
 use crate::spec::ComputeSpec;
 
 #[derive(Serialize, Debug, Deserialize)]
@@ -21,6 +23,52 @@ pub struct ComputeStatusResponse {
     #[serde(serialize_with = "rfc3339_serialize")]
     pub last_active: Option<DateTime<Utc>>,
     pub error: Option<String>,
+    /// How the last live configuration request was applied, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reconfiguration: Option<ReconfigurationPath>,
+    /// Progress of prewarming the local file cache, if the compute started
+    /// with a saved cache state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lfc_prewarm: Option<LfcPrewarmProgress>,
+}
+
+/// How `compute_ctl` applied a new spec to the running compute.
+#[derive(Serialize, Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "path")]
+pub enum ReconfigurationPath {
+    /// Nothing changed compared to the running spec.
+    Unchanged,
+    /// All the changes were applied to the running Postgres.
+    Online,
+    /// The changes were written to the config, but some of them only take
+    /// effect after a restart of the compute: settings which can only be set
+    /// at server start, or parts of the spec like the compute mode.
+    RestartRequired { pending_changes: Vec<String> },
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LfcPrewarmStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Progress of prewarming the local file cache with the pages which were
+/// cached when the compute last saved its cache state.
+#[derive(Serialize, Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct LfcPrewarmProgress {
+    pub status: LfcPrewarmStatus,
+    /// Blocks in the saved cache state.
+    pub total_blocks: u64,
+    /// Blocks processed so far, both the ones which had to be fetched from
+    /// the pageserver and the ones which were already cached or don't exist
+    /// anymore.
+    pub prewarmed_blocks: u64,
+    /// Blocks fetched from the pageserver.
+    pub fetched_blocks: u64,
+    pub error: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -52,6 +100,14 @@ pub enum ComputeStatus {
     // compute will exit soon or is waiting for
     // control-plane to terminate it.
     Failed,
+    // Termination was requested, Postgres is shutting down.
+    TerminationPending,
+    // Restart was requested, Postgres is shutting down to be
+    // started again.
+    RestartPending,
+    // Postgres is stopped and the safekeepers are synced,
+    // compute_ctl is about to exit.
+    Terminated,
 }
 
 fn rfc3339_serialize<S>(x: &Option<DateTime<Utc>>, s: S) -> Result<S::Ok, S::Error>
@@ -65,6 +121,79 @@ where
     }
 }
 
+/// Response of the /terminate API
+#[derive(Serialize, Debug, Deserialize)]
+pub struct TerminateResponse {
+    /// The LSN the safekeepers were synced to after Postgres was stopped,
+    /// i.e. the point the compute was cleanly suspended at. Not set for
+    /// read-only computes.
+    pub lsn: Option<Lsn>,
+}
+
+/// Response of the /restart API
+#[derive(Serialize, Debug, Deserialize)]
+pub struct RestartResponse {
+    /// Client connections which were still open when the drain deadline
+    /// passed, and were terminated.
+    pub terminated_connections: u64,
+    /// Time from stopping Postgres until it accepted connections again.
+    pub downtime_ms: u64,
+}
+
+/// Response of the /promote API
+#[derive(Serialize, Debug, Deserialize)]
+pub struct PromoteResponse {
+    /// The LSN the promoted compute started to write WAL at.
+    pub lsn: Lsn,
+}
+
+/// Response of the /lfc_size API
+#[derive(Serialize, Debug, Deserialize)]
+pub struct LfcSizeResponse {
+    /// Current size limit of the local file cache in bytes.
+    pub size_bytes: u64,
+}
+
+/// Item of the /catalog/databases API response
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct CatalogDatabase {
+    pub name: String,
+    pub owner: String,
+    /// Size of the database as reported by `pg_database_size()`.
+    pub size_bytes: u64,
+}
+
+/// Item of the /catalog/roles API response, without the password
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct CatalogRole {
+    pub name: String,
+    pub can_login: bool,
+    pub create_db: bool,
+    pub create_role: bool,
+    pub replication: bool,
+    pub bypass_rls: bool,
+    /// Roles this role is a direct member of.
+    pub member_of: Vec<String>,
+}
+
+/// Item of the /catalog/schemas API response
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct CatalogSchema {
+    pub name: String,
+    pub owner: String,
+    /// Total size of the tables and materialized views in the schema,
+    /// including their indexes and TOAST.
+    pub size_bytes: u64,
+}
+
+/// Item of the /catalog/extensions API response
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct CatalogExtension {
+    pub name: String,
+    pub version: String,
+    pub schema: String,
+}
+
 /// Response of the /metrics.json API
 #[derive(Clone, Debug, Default, Serialize)]
 pub struct ComputeMetrics {
@@ -27,6 +27,10 @@ pub struct ComputeSpec {
     // deserializing it.
     pub operation_uuid: Option<String>,
 
+    /// ID of the endpoint the compute runs for, to tag its logs and traces.
+    #[serde(default)]
+    pub endpoint_id: Option<String>,
+
     /// Compute features to enable. These feature flags are provided, when we
     /// know all the details about client's compute, so they cannot be used
     /// to change `Empty` compute behavior.
@@ -43,6 +47,11 @@ pub struct ComputeSpec {
     #[serde(default)] // Default false
     pub skip_pg_catalog_updates: bool,
 
+    /// Controls of the migrations of neon-managed SQL objects, which
+    /// `compute_ctl` runs on start together with the pg catalog updates.
+    #[serde(default)]
+    pub migrations: MigrationSettings,
+
     // Information needed to connect to the storage layer.
     //
     // `tenant_id`, `timeline_id` and `pageserver_connstring` are always needed.
@@ -76,9 +85,48 @@ pub struct ComputeSpec {
 
     pub pgbouncer_settings: Option<HashMap<String, String>>,
 
+    /// Libraries the endpoint needs in `shared_preload_libraries`, besides
+    /// `neon`. `compute_ctl` downloads the remote ones and makes sure they are
+    /// all installed before starting Postgres.
+    #[serde(default)]
+    pub preload_libraries: Vec<String>,
+
     // Stripe size for pageserver sharding, in pages
     #[serde(default)]
     pub shard_stripe_size: Option<usize>,
+
+    /// Metrics which `compute_ctl` collects with SQL queries periodically, and
+    /// serves together with its own metrics.
+    #[serde(default)]
+    pub sql_metrics: Vec<SqlMetric>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MigrationSettings {
+    /// Don't run any migrations, e.g. to get a compute started while one of
+    /// them is broken.
+    #[serde(default)]
+    pub skip: bool,
+    /// IDs of the migrations to run again, even though they were applied
+    /// already. Migrations are idempotent, so this is safe to do.
+    #[serde(default)]
+    pub force: Vec<i64>,
+}
+
+/// A gauge collected with a SQL query, every row of the result is a sample.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SqlMetric {
+    pub metric_name: String,
+    #[serde(default)]
+    pub help: String,
+    /// Database to run the query in, `postgres` if not set.
+    pub database: Option<PgIdent>,
+    pub query: String,
+    /// Columns whose values become the labels of the samples.
+    #[serde(default)]
+    pub key_labels: Vec<String>,
+    /// Column with the value of the samples, rows where it's NULL are skipped.
+    pub value_column: String,
 }
 
 /// Feature flag to signal `compute_ctl` to enable certain experimental functionality.
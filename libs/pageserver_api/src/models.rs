@@ -446,6 +446,34 @@ pub enum TenantAttachmentStatus {
     Failed { reason: String },
 }
 
+/// Load of a pageserver, for the storage controller to balance the shards
+/// between pageservers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PageserverUtilization {
+    /// The active attached shards.
+    pub shards: Vec<ShardUtilization>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShardUtilization {
+    pub tenant_shard_id: TenantShardId,
+    /// Size of the layer files on local disk.
+    pub resident_size: u64,
+    /// GetPage requests served since the shard was attached, to compute a rate
+    /// from successive samples.
+    pub getpage_requests: u64,
+}
+
+/// How much of the heatmap of the attached location a secondary location holds,
+/// as of its latest download.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SecondaryProgress {
+    pub layers_downloaded: usize,
+    pub layers_total: usize,
+    pub bytes_downloaded: u64,
+    pub bytes_total: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct TenantInfo {
     pub id: TenantShardId,
@@ -512,6 +540,55 @@ pub struct TimelineInfo {
     pub walreceiver_status: String,
 }
 
+/// Breakdown of the layer file bytes that GC keeps for a timeline, by the reason
+/// they are kept.  Each layer is counted under the first reason that applies, in
+/// the order GC checks them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcRetainedSize {
+    /// Layers newer than the `gc_horizon` cutoff.
+    pub horizon_bytes: u64,
+    /// Layers within the `pitr_interval` window.
+    pub pitr_bytes: u64,
+    /// Layers newer than the lowest leased LSN, which read-only computes may read.
+    #[serde(default)]
+    pub leased_bytes: u64,
+    /// Layers that child branches forked off this timeline still depend on.
+    pub branches_bytes: u64,
+    /// Layers past all cutoffs that have no newer image layer covering them yet.
+    pub latest_bytes: u64,
+    /// Layers that the next GC iteration is free to remove.
+    pub collectable_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineGcInfo {
+    pub horizon_cutoff: Lsn,
+    pub pitr_cutoff: Lsn,
+    pub latest_gc_cutoff_lsn: Lsn,
+    /// LSNs of the child branch points
+    pub retain_lsns: Vec<Lsn>,
+    /// LSNs with an unexpired lease, which GC keeps readable
+    #[serde(default)]
+    pub leased_lsns: Vec<Lsn>,
+    pub retained: GcRetainedSize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LsnLeaseRequest {
+    pub lsn: Lsn,
+    /// How long to keep the LSN readable for, the pageserver's default if unset.
+    #[serde(default, with = "humantime_serde")]
+    pub length: Option<Duration>,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LsnLease {
+    /// Until when GC keeps the LSN readable, unless the lease is renewed.
+    #[serde_as(as = "serde_with::TimestampMilliSeconds")]
+    pub valid_until: SystemTime,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct LayerMapInfo {
     pub in_memory_layers: Vec<InMemoryLayerInfo>,
@@ -627,6 +704,31 @@ pub enum HistoricLayerInfo {
     },
 }
 
+/// How a layer came into existence, as told by its key and LSN ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayerCreationReason {
+    /// L0 delta layer written by flushing an in-memory layer.
+    Flush,
+    /// L1 delta layer written by compacting L0 layers.
+    Compaction,
+    /// Image layer.
+    Image,
+}
+
+/// Residency and access statistics of a single layer, for capacity planning and
+/// eviction policy debugging.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayerResidencyInfo {
+    pub layer_file_name: String,
+    pub layer_file_size: u64,
+    pub residence: LayerResidenceStatus,
+    pub creation_reason: LayerCreationReason,
+    /// Time of the latest access since the layer was loaded, if any.
+    pub last_access_millis_since_epoch: Option<u64>,
+    /// Number of accesses since the layer was loaded.
+    pub access_count: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DownloadRemoteLayersTaskSpawnRequest {
     pub max_concurrent_downloads: NonZeroUsize,
@@ -653,6 +755,15 @@ pub struct TimelineGcRequest {
     pub gc_horizon: Option<u64>,
 }
 
+/// The part of the result of a manual GC run that clients look at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineGcResponse {
+    pub layers_total: u64,
+    pub layers_removed: u64,
+    /// In milliseconds.
+    pub elapsed: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WalRedoManagerStatus {
     pub last_redo_at: Option<chrono::DateTime<chrono::Utc>>,
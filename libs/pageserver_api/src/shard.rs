@@ -1,7 +1,11 @@
-use std::{ops::RangeInclusive, str::FromStr};
+use std::{
+    ops::{Range, RangeInclusive},
+    str::FromStr,
+};
 
 use crate::{
     key::{is_rel_block_key, Key},
+    keyspace::KeySpace,
     models::ShardParameters,
 };
 use hex::FromHex;
@@ -481,6 +485,72 @@ impl ShardIdentity {
         }
     }
 
+    /// Return the subset of `keyspace` that this shard stores, i.e. with all the
+    /// stripes of relation blocks that belong to other shards cut out.  Keys that
+    /// are not distributed across shards are always retained.
+    ///
+    /// Compaction and GC use this to avoid reading or materializing pages that
+    /// this shard would only discard afterwards.  Filtering is best effort: ranges
+    /// can span the whole keyspace, so after [`Self::FILTER_KEYSPACE_MAX_STEPS`]
+    /// chunks the rest of a range is retained as it is.
+    pub fn filter_keyspace(&self, keyspace: &KeySpace) -> KeySpace {
+        if self.count < ShardCount(2) {
+            return keyspace.clone();
+        }
+
+        let mut ranges: Vec<Range<Key>> = Vec::with_capacity(keyspace.ranges.len());
+        for range in &keyspace.ranges {
+            let mut start = range.start;
+            let mut steps = 0;
+            while start < range.end {
+                let exhausted = steps == Self::FILTER_KEYSPACE_MAX_STEPS;
+                let chunk_end = if exhausted {
+                    range.end
+                } else if is_rel_block_key(&start) {
+                    // All blocks up to the end of the stripe share the same owner
+                    let stripe = start.field6 / self.stripe_size.0;
+                    let stripe_end = (stripe as u64 + 1) * self.stripe_size.0 as u64;
+                    Key {
+                        // Clamped to the relation size key, which is not a block key
+                        field6: std::cmp::min(stripe_end, 0xffffffff) as u32,
+                        ..start
+                    }
+                } else if start.field1 == 0x00 && start.field4 == 0 {
+                    // Database-level keys, followed by the blocks of the first relation
+                    Key {
+                        field4: 1,
+                        field5: 0,
+                        field6: 0,
+                        ..start
+                    }
+                } else if start.field1 == 0x00 {
+                    // A relation size key, followed by the blocks of the next fork
+                    start.next()
+                } else {
+                    // Nothing after this point is a relation block
+                    range.end
+                };
+                let chunk_end = std::cmp::min(chunk_end, range.end);
+
+                if exhausted || !self.is_key_disposable(&start) {
+                    match ranges.last_mut() {
+                        Some(last) if last.end == start => last.end = chunk_end,
+                        _ => ranges.push(start..chunk_end),
+                    }
+                }
+                start = chunk_end;
+                steps += 1;
+            }
+        }
+
+        KeySpace { ranges }
+    }
+
+    /// How many chunks of a range [`Self::filter_keyspace`] inspects before it retains
+    /// the rest of the range: enough for the blocks of a 4TiB relation with the default
+    /// stripe size.
+    const FILTER_KEYSPACE_MAX_STEPS: usize = 16384;
+
     pub fn shard_slug(&self) -> String {
         if self.count > ShardCount(0) {
             format!("-{:02x}{:02x}", self.number.0, self.count.0)
@@ -818,6 +888,123 @@ mod tests {
         assert_eq!(shard, ShardNumber(8));
     }
 
+    #[test]
+    fn filter_keyspace() {
+        let rel_key = |blkno: u32| Key {
+            field1: 0x00,
+            field2: 0x67f,
+            field3: 0x5,
+            field4: 0x400c,
+            field5: 0x00,
+            field6: blkno,
+        };
+        let stripe_size = ShardStripeSize(8);
+        let nblocks = stripe_size.0 * 16;
+        let keyspace = KeySpace {
+            ranges: vec![
+                Key::from_i128(0x10)..Key::from_i128(0x20),
+                rel_key(0)..rel_key(nblocks),
+                rel_key(0xffffffff)..rel_key(0xffffffff).next(),
+                Key::from_i128(0x1 << 120)..Key::from_i128((0x1 << 120) + 0x10),
+            ],
+        };
+
+        let shard_count = ShardCount(4);
+        let mut local_blocks = 0;
+        for number in 0..shard_count.0 {
+            let shard = ShardIdentity::new(ShardNumber(number), shard_count, stripe_size).unwrap();
+            let filtered = shard.filter_keyspace(&keyspace);
+
+            // Ranges remain sorted and non-overlapping
+            for pair in filtered.ranges.windows(2) {
+                assert!(pair[0].end < pair[1].start);
+            }
+
+            // Keys outside relation blocks are kept on every shard, and exactly the
+            // local blocks are kept.
+            let contains = |key: &Key| filtered.ranges.iter().any(|r| r.contains(key));
+            assert!(contains(&Key::from_i128(0x10)));
+            assert!(contains(&rel_key(0xffffffff)));
+            assert!(contains(&Key::from_i128((0x1 << 120) + 0x8)));
+            for blkno in 0..nblocks {
+                let key = rel_key(blkno);
+                assert_eq!(contains(&key), shard.is_key_local(&key));
+                if shard.is_key_local(&key) {
+                    local_blocks += 1;
+                }
+            }
+        }
+        assert_eq!(local_blocks, nblocks);
+
+        // Unsharded tenants keep everything
+        assert_eq!(
+            ShardIdentity::unsharded().filter_keyspace(&keyspace),
+            keyspace
+        );
+    }
+
+    #[test]
+    fn filter_keyspace_wide_ranges() {
+        let stripe_size = ShardStripeSize(8);
+        let shard_count = ShardCount(4);
+        let keyspace = KeySpace {
+            ranges: vec![
+                // Database-level keys only: 2^40 keys without a relation block
+                Key {
+                    field1: 0x00,
+                    field2: 0x67f,
+                    field3: 0x5,
+                    field4: 0,
+                    field5: 0,
+                    field6: 0,
+                }..Key {
+                    field1: 0x00,
+                    field2: 0x67f,
+                    field3: 0x5,
+                    field4: 0,
+                    field5: 0xff,
+                    field6: 0xffffffff,
+                },
+                // Everything from the first relation block to the end of the keyspace
+                Key {
+                    field1: 0x00,
+                    field2: 0x67f,
+                    field3: 0x6,
+                    field4: 0x400c,
+                    field5: 0x00,
+                    field6: 0,
+                }..Key::MAX,
+            ],
+        };
+
+        for number in 0..shard_count.0 {
+            let shard = ShardIdentity::new(ShardNumber(number), shard_count, stripe_size).unwrap();
+            let filtered = shard.filter_keyspace(&keyspace);
+
+            for pair in filtered.ranges.windows(2) {
+                assert!(pair[0].end < pair[1].start);
+            }
+            let contains = |key: &Key| filtered.ranges.iter().any(|r| r.contains(key));
+
+            // The database-level keys are kept in one piece
+            assert_eq!(filtered.ranges[0], keyspace.ranges[0]);
+
+            // The first stripes are filtered, and everything is retained once the
+            // filtering gives up.
+            let rel_key = |blkno: u32| Key {
+                field6: blkno,
+                ..keyspace.ranges[1].start
+            };
+            for blkno in 0..stripe_size.0 * 16 {
+                let key = rel_key(blkno);
+                assert_eq!(contains(&key), shard.is_key_local(&key));
+            }
+            assert!(contains(&rel_key(0xffffffff)));
+            assert!(contains(&Key::from_i128(0x1 << 120)));
+            assert_eq!(filtered.ranges.last().unwrap().end, Key::MAX);
+        }
+    }
+
     #[test]
     fn shard_id_split() {
         let tenant_id = TenantId::generate();
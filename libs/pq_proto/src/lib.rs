@@ -673,6 +673,8 @@ pub fn read_cstr(buf: &mut Bytes) -> Result<Bytes, ProtocolError> {
 pub const SQLSTATE_INTERNAL_ERROR: &[u8; 5] = b"XX000";
 pub const SQLSTATE_ADMIN_SHUTDOWN: &[u8; 5] = b"57P01";
 pub const SQLSTATE_SUCCESSFUL_COMPLETION: &[u8; 5] = b"00000";
+pub const SQLSTATE_INVALID_AUTHORIZATION_SPECIFICATION: &[u8; 5] = b"28000";
+pub const SQLSTATE_TOO_MANY_CONNECTIONS: &[u8; 5] = b"53300";
 
 impl<'a> BeMessage<'a> {
     /// Serialize `message` to the given `buf`.
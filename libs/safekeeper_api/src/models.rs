@@ -45,6 +45,9 @@ pub struct SkTimelineInfo {
     pub peer_horizon_lsn: Lsn,
     #[serde(default = "lsn_invalid")]
     pub local_start_lsn: Lsn,
+    /// Last LSN received by the pageserver.
+    #[serde(default = "lsn_invalid")]
+    pub ps_last_received_lsn: Lsn,
     /// A connection string to use for WAL receiving.
     #[serde(default)]
     pub safekeeper_connstr: Option<String>,
@@ -52,6 +55,28 @@ pub struct SkTimelineInfo {
     pub http_connstr: Option<String>,
 }
 
+/// Timeline state which safekeeper publishes in the broker, mirrors
+/// SafekeeperTimelineInfo in broker.proto. Served over HTTP to peers and
+/// pageservers which poll static list of safekeepers instead of the broker.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BrokerTimelineInfo {
+    pub safekeeper_id: NodeId,
+    pub tenant_id: TenantId,
+    pub timeline_id: TimelineId,
+    pub term: u64,
+    pub last_log_term: u64,
+    pub flush_lsn: Lsn,
+    pub commit_lsn: Lsn,
+    pub backup_lsn: Lsn,
+    pub remote_consistent_lsn: Lsn,
+    pub peer_horizon_lsn: Lsn,
+    pub local_start_lsn: Lsn,
+    pub ps_last_received_lsn: Lsn,
+    pub safekeeper_connstr: String,
+    pub http_connstr: String,
+    pub availability_zone: Option<String>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TimelineCopyRequest {
     pub target_timeline_id: TimelineId,
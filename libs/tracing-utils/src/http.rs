@@ -78,8 +78,8 @@ where
     response
 }
 
-// Extract remote tracing context from the HTTP headers
-fn extract_remote_context(headers: &HeaderMap) -> opentelemetry::Context {
+/// Extract remote tracing context from the HTTP headers
+pub fn extract_remote_context(headers: &HeaderMap) -> opentelemetry::Context {
     struct HeaderExtractor<'a>(&'a HeaderMap);
 
     impl<'a> opentelemetry::propagation::Extractor for HeaderExtractor<'a> {
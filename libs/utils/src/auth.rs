@@ -29,6 +29,8 @@ pub enum Scope {
     // Should only be used e.g. for status check.
     // Currently also used for connection from any pageserver to any safekeeper.
     SafekeeperData,
+    // Provides access to the admin API of the proxy, e.g. to change its connection pool limits.
+    ProxyApi,
 }
 
 /// JWT payload. See docs/authentication.md for the format
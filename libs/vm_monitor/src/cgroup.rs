@@ -4,8 +4,8 @@ use std::time::{Duration, Instant};
 use anyhow::{anyhow, Context};
 use cgroups_rs::{
     hierarchies::{self, is_cgroup2_unified_mode},
-    memory::MemController,
-    Subsystem,
+    memory::{MemController, SetMemory},
+    Controller, MaxValue, Subsystem,
 };
 use tokio::sync::watch;
 use tracing::{info, warn};
@@ -87,11 +87,33 @@ impl CgroupWatcher {
         let history_log_len = self.config.memory_history_log_interval;
         let mut history_log_buf = vec![MemoryStatus::zeroed(); history_log_len];
 
+        // the last count we read, which we keep reporting while reading memory.events fails
+        let mut memory_high_events = None;
+        let mut memory_high_events_failing = false;
+
         for t in 0_u64.. {
             ticker.tick().await;
 
             let now = Instant::now();
             let mem = Self::memory_usage(mem_controller);
+            match Self::memory_high_events(mem_controller) {
+                Ok(events) => {
+                    if memory_high_events_failing {
+                        info!("reading cgroup memory.events succeeded again");
+                        memory_high_events_failing = false;
+                    }
+                    memory_high_events = Some(events);
+                }
+                // Only log the first failure, we retry every poll interval.
+                Err(e) if !memory_high_events_failing => {
+                    warn!(
+                        error = format!("{e:#}"),
+                        "failed to read cgroup memory.events"
+                    );
+                    memory_high_events_failing = true;
+                }
+                Err(_) => {}
+            }
 
             let i = t as usize % history_log_len;
             history_log_buf[i] = mem;
@@ -109,6 +131,7 @@ impl CgroupWatcher {
                     / samples_count as u64,
                 samples_count,
                 samples_span: self.config.memory_poll_interval * (samples_count - 1) as u32,
+                memory_high_events,
             };
 
             // Log the current history if it's time to do so. Because `history_log_buf` has length
@@ -143,6 +166,29 @@ impl CgroupWatcher {
             .ok_or_else(|| anyhow!("could not find memory subsystem"))
     }
 
+    /// Set the cgroup's memory.high, above which the kernel throttles the processes in it and
+    /// reclaims their memory.
+    pub fn set_memory_high_bytes(&self, bytes: u64) -> anyhow::Result<()> {
+        let bytes = i64::try_from(bytes).context("memory.high value does not fit into i64")?;
+        self.memory()?
+            .set_mem(SetMemory {
+                low: None,
+                high: Some(MaxValue::Value(bytes)),
+                min: None,
+                max: None,
+            })
+            .context("failed to set memory.high")
+    }
+
+    /// The number of times the cgroup's memory usage went over memory.high, i.e. was throttled,
+    /// since the cgroup was created.
+    fn memory_high_events(mem_controller: &MemController) -> anyhow::Result<u64> {
+        let path = mem_controller.path().join("memory.events");
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        parse_memory_high_events(&contents)
+    }
+
     /// Given a handle on the memory subsystem, returns the current memory information
     fn memory_usage(mem_controller: &MemController) -> MemoryStatus {
         let stat = mem_controller.memory_stat().stat;
@@ -152,6 +198,25 @@ impl CgroupWatcher {
     }
 }
 
+/// Extract the `high` counter from the contents of a memory.events file, e.g.:
+///
+/// ```text
+/// low 0
+/// high 12
+/// max 0
+/// oom 0
+/// oom_kill 0
+/// ```
+fn parse_memory_high_events(contents: &str) -> anyhow::Result<u64> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("high "))
+        .ok_or_else(|| anyhow!("no high counter in memory.events"))?
+        .trim()
+        .parse()
+        .context("failed to parse memory.events high counter")
+}
+
 // Helper function for `CgroupWatcher::watch`
 fn ring_buf_recent_values_iter<T>(
     buf: &[T],
@@ -183,6 +248,10 @@ pub struct MemoryHistory {
     pub samples_count: usize,
     /// Total timespan between the first and last sample used for this summary
     pub samples_span: Duration,
+
+    /// Number of times the cgroup was throttled at memory.high so far, from memory.events, or
+    /// None if it couldn't be read yet
+    pub memory_high_events: Option<u64>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -236,6 +305,15 @@ impl MemoryStatus {
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn memory_high_events() {
+        let contents = "low 0\nhigh 12\nmax 3\noom 0\noom_kill 0\n";
+        assert_eq!(super::parse_memory_high_events(contents).unwrap(), 12);
+
+        assert!(super::parse_memory_high_events("low 0\nmax 3\n").is_err());
+        assert!(super::parse_memory_high_events("high lots\n").is_err());
+    }
+
     #[test]
     fn ring_buf_iter() {
         let buf = vec![0_i32, 1, 2, 3, 4, 5, 6, 7, 8, 9];
@@ -1,6 +1,8 @@
 //! Logic for configuring and scaling the Postgres file cache.
 
 use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use crate::MiB;
 use anyhow::{anyhow, Context};
@@ -8,6 +10,23 @@ use tokio_postgres::{types::ToSql, Client, NoTls, Row};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
 
+/// Upper bound of the file cache size in bytes, set at runtime by `compute_ctl`.
+/// `u64::MAX` if there is none.
+static SIZE_CAP: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// How long to wait for Postgres to load a new file cache size.
+const SIZE_CHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of a Postgres page, which the working set is estimated in.
+const PAGE_SIZE: u64 = 8192;
+
+/// Set the upper bound of the file cache size, which every following
+/// [`FileCacheState::set_file_cache_size`] respects, so that the cache doesn't
+/// grow beyond it when the VM is upscaled. `None` removes the bound.
+pub fn set_size_cap(cap: Option<u64>) {
+    SIZE_CAP.store(cap.unwrap_or(u64::MAX), Ordering::Relaxed);
+}
+
 /// Manages Postgres' file cache by keeping a connection open.
 #[derive(Debug)]
 pub struct FileCacheState {
@@ -242,6 +261,45 @@ impl FileCacheState {
         .context("failed to extract file cache size from query result")
     }
 
+    /// Get the estimated size of the working set, i.e. of the distinct pages read
+    /// through the file cache in the last `window`, in bytes.
+    ///
+    /// Returns `None` if the neon extension doesn't provide the estimate yet, or
+    /// if the file cache is disabled.
+    #[tracing::instrument(skip_all, fields(?window))]
+    pub async fn get_working_set_size(&mut self, window: Duration) -> anyhow::Result<Option<u64>> {
+        // the function only exists since version 1.3 of the extension, which
+        // computes created before it haven't been updated to
+        let exists = self
+            .query_with_retry(
+                "SELECT to_regprocedure('neon.approximate_working_set_size_seconds(integer)') IS NOT NULL;",
+                &[],
+            )
+            .await
+            .context("failed to query pg for the working set estimate function")?
+            .first()
+            .ok_or_else(|| anyhow!("working set estimate function query returned no rows"))?
+            .try_get::<_, bool>(0)
+            .context("failed to extract working set estimate function from query result")?;
+        if !exists {
+            return Ok(None);
+        }
+
+        let seconds = i32::try_from(window.as_secs()).unwrap_or(i32::MAX);
+        self.query_with_retry(
+            "SELECT neon.approximate_working_set_size_seconds($1);",
+            &[&seconds],
+        )
+        .await
+        .context("failed to query pg for working set size")?
+        .first()
+        .ok_or_else(|| anyhow!("working set size query returned no rows"))?
+        .try_get::<_, Option<i32>>(0)
+        // The estimate is a number of pages, which is never negative.
+        .map(|pages| pages.map(|pages| pages as u64 * PAGE_SIZE))
+        .context("failed to extract working set size from query result")
+    }
+
     /// Attempt to set the file cache size, returning the size it was actually
     /// set to.
     #[tracing::instrument(skip_all, fields(%num_bytes))]
@@ -261,11 +319,14 @@ impl FileCacheState {
             .map(|bytes| bytes as u64)
             .context("failed to extract max file cache size from query result")?;
 
+        let size_cap = SIZE_CAP.load(Ordering::Relaxed);
         let max_mb = max_bytes / MiB;
-        let num_mb = u64::min(num_bytes, max_bytes) / MiB;
+        let num_mb = num_bytes.min(max_bytes).min(size_cap) / MiB;
 
         let capped = if num_bytes > max_bytes {
             " (capped by maximum size)"
+        } else if num_bytes > size_cap {
+            " (capped by the size set through compute_ctl)"
         } else {
             ""
         };
@@ -297,4 +358,26 @@ impl FileCacheState {
 
         Ok(num_mb * MiB)
     }
+
+    /// Wait until our session has loaded the file cache size set by
+    /// [`Self::set_file_cache_size`]. Loading a smaller size evicts the pages
+    /// above it from the cache, so once this returns, the memory can be taken
+    /// away, e.g. by a downscaling.
+    pub async fn wait_for_file_cache_size(&mut self, num_bytes: u64) -> anyhow::Result<()> {
+        let start = Instant::now();
+        loop {
+            // the session reloads the config between queries, after the postmaster did
+            let size = self.get_file_cache_size().await?;
+            // the setting is in MiB, compare with that precision
+            if size / MiB == num_bytes / MiB {
+                return Ok(());
+            }
+            anyhow::ensure!(
+                start.elapsed() < SIZE_CHANGE_TIMEOUT,
+                "Postgres didn't load the new file cache size in {SIZE_CHANGE_TIMEOUT:?}, \
+                 it's still {size} bytes"
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
 }
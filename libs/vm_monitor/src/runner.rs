@@ -4,7 +4,9 @@
 //! This is the "Monitor" part of the monitor binary and is the main entrypoint for
 //! all functionality.
 
+use std::collections::VecDeque;
 use std::fmt::Debug;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context};
@@ -45,10 +47,46 @@ pub struct Runner {
 
 #[derive(Debug)]
 struct CgroupState {
+    manager: Arc<CgroupWatcher>,
     watcher: watch::Receiver<(Instant, cgroup::MemoryHistory)>,
     /// If [`cgroup::MemoryHistory::avg_non_reclaimable`] exceeds `threshold`, we send upscale
     /// requests.
     threshold: u64,
+    /// The memory.high we set for the cgroup, above which the kernel throttles it.
+    memory_high: u64,
+    /// The number of vCPUs we were last granted, possibly fractional.
+    cpu: f64,
+    /// Recent [`cgroup::MemoryHistory::memory_high_events`], to tell when the cgroup is being
+    /// throttled at memory.high.
+    memory_high_events: MemoryHighEvents,
+    /// When the cgroup was last throttled at memory.high.
+    last_memory_high_at: Option<Instant>,
+}
+
+/// Recent counts of the times the cgroup was throttled at memory.high. The kernel also does that
+/// to reclaim page cache, which is harmless, so the cgroup only counts as throttled if the count
+/// grows at least by some amount over a time window.
+#[derive(Debug, Default)]
+struct MemoryHighEvents {
+    /// Counts not older than the window, oldest first.
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl MemoryHighEvents {
+    /// Record the count at `now`, returning whether it grew by at least `min_events` within the
+    /// last `window`.
+    fn record(&mut self, now: Instant, events: u64, window: Duration, min_events: u64) -> bool {
+        while let Some((t, _)) = self.samples.front() {
+            if now.duration_since(*t) <= window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, events));
+
+        let (_, oldest) = self.samples.front().expect("just pushed a sample");
+        events.saturating_sub(*oldest) >= min_events
+    }
 }
 
 /// Configuration for a `Runner`
@@ -84,6 +122,32 @@ pub struct Config {
     cgroup_min_overhead_fraction: f64,
 
     cgroup_downscale_threshold_buffer_bytes: u64,
+
+    /// Fraction of total memory left above the cgroup's memory.high with one or more vCPUs.
+    ///
+    /// With a fraction of a vCPU, the kernel reclaims memory proportionally slower once
+    /// memory.high is exceeded, so the headroom is scaled up accordingly (e.g. 4x for 0.25 vCPU),
+    /// but it's never more than `cgroup_min_overhead_fraction`, so that memory.high stays above
+    /// the threshold at which we request upscaling.
+    memory_high_overhead_fraction: f64,
+
+    /// How long after the cgroup was last throttled at memory.high we deny downscaling. This
+    /// keeps us from downscaling right after an upscale that the throttling caused, only to be
+    /// throttled and upscaled again.
+    memory_high_cooldown: Duration,
+
+    /// The cgroup counts as throttled at memory.high, so that we request upscaling, once it was
+    /// throttled at least `memory_high_min_events` times within `memory_high_window`.
+    memory_high_window: Duration,
+    memory_high_min_events: u64,
+
+    /// The window over which the file cache working set is estimated, i.e. pages read less
+    /// recently than this don't count towards it.
+    working_set_window: Duration,
+
+    /// How often to check whether the file cache working set outgrew the file cache, in which
+    /// case we request upscaling.
+    working_set_check_interval: Duration,
 }
 
 impl Default for Config {
@@ -92,6 +156,12 @@ impl Default for Config {
             sys_buffer_bytes: 100 * MiB,
             cgroup_min_overhead_fraction: 0.15,
             cgroup_downscale_threshold_buffer_bytes: 100 * MiB,
+            memory_high_overhead_fraction: 0.05,
+            memory_high_cooldown: Duration::from_secs(30),
+            memory_high_window: Duration::from_secs(5),
+            memory_high_min_events: 50,
+            working_set_window: Duration::from_secs(5 * 60),
+            working_set_check_interval: Duration::from_secs(20),
         }
     }
 }
@@ -116,6 +186,20 @@ impl Config {
 
         memory_remaining_for_cgroup.min(max_threshold)
     }
+
+    fn memory_high(&self, total_mem: u64, cpu: f64) -> u64 {
+        // Guard against a zero or nonsensical CPU amount, which would otherwise make the
+        // overhead infinite.
+        let cpu = if cpu.is_finite() {
+            cpu.clamp(0.25, 1.0)
+        } else {
+            1.0
+        };
+        let overhead_fraction =
+            (self.memory_high_overhead_fraction / cpu).min(self.cgroup_min_overhead_fraction);
+
+        (total_mem as f64 * (1.0 - overhead_fraction)) as u64
+    }
 }
 
 impl Runner {
@@ -193,26 +277,43 @@ impl Runner {
             // now, and then set limits later.
             info!("initializing cgroup");
 
-            let cgroup =
-                CgroupWatcher::new(name.clone()).context("failed to create cgroup manager")?;
+            let cgroup = Arc::new(
+                CgroupWatcher::new(name.clone()).context("failed to create cgroup manager")?,
+            );
 
             let init_value = cgroup::MemoryHistory {
                 avg_non_reclaimable: 0,
                 samples_count: 0,
                 samples_span: Duration::ZERO,
+                memory_high_events: None,
             };
             let (hist_tx, hist_rx) = watch::channel((Instant::now(), init_value));
 
+            let watcher = Arc::clone(&cgroup);
             spawn_with_cancel(token, |_| error!("cgroup watcher terminated"), async move {
-                cgroup.watch(hist_tx).await
+                watcher.watch(hist_tx).await
             });
 
             let threshold = state.config.cgroup_threshold(mem, file_cache_disk_size);
             info!(threshold, "set initial cgroup threshold",);
 
+            // We don't know how many vCPUs we have until the agent tells us, so assume the
+            // ones we can see.
+            let cpu = std::thread::available_parallelism().map_or(1.0, |n| n.get() as f64);
+            let memory_high = state.config.memory_high(mem, cpu);
+            cgroup
+                .set_memory_high_bytes(memory_high)
+                .context("failed to set initial cgroup memory.high")?;
+            info!(memory_high, cpu, "set initial cgroup memory.high");
+
             state.cgroup = Some(CgroupState {
+                manager: cgroup,
                 watcher: hist_rx,
                 threshold,
+                memory_high,
+                cpu,
+                memory_high_events: MemoryHighEvents::default(),
+                last_memory_high_at: None,
             });
         }
 
@@ -238,6 +339,31 @@ impl Runner {
             .as_ref()
             .map(|file_cache| file_cache.config.calculate_cache_size(usable_system_memory))
             .unwrap_or(0);
+
+        // Even if the memory usage allows for it, don't downscale if the pages that were read
+        // recently wouldn't fit into the smaller file cache anymore: they would have to be
+        // fetched from the pageserver again.
+        if let Some(file_cache) = &mut self.filecache {
+            match file_cache
+                .get_working_set_size(self.config.working_set_window)
+                .await
+            {
+                Ok(Some(working_set)) if working_set > expected_file_cache_size => {
+                    let status = format!(
+                        "{}: {} MiB (working set) > {} MiB (new file cache size)",
+                        "file cache working set too large",
+                        bytes_to_mebibytes(working_set),
+                        bytes_to_mebibytes(expected_file_cache_size),
+                    );
+                    info!(status, "discontinuing downscale");
+                    return Ok((false, status));
+                }
+                Ok(_) => {}
+                // the estimate is advisory, don't fail the downscale because of it
+                Err(e) => warn!(error = format!("{e:#}"), "failed to get working set size"),
+            }
+        }
+
         if let Some(cgroup) = &self.cgroup {
             let (last_time, last_history) = *cgroup.watcher.borrow();
 
@@ -259,6 +385,18 @@ impl Runner {
                 return Ok((false, status.to_owned()));
             }
 
+            if let Some(t) = cgroup.last_memory_high_at {
+                if t.elapsed() < self.config.memory_high_cooldown {
+                    let status = format!(
+                        "cgroup was throttled at memory.high {:?} ago, less than the {:?} cooldown",
+                        t.elapsed(),
+                        self.config.memory_high_cooldown,
+                    );
+                    info!(status, "discontinuing downscale");
+                    return Ok((false, status));
+                }
+            }
+
             let new_threshold = self
                 .config
                 .cgroup_threshold(usable_system_memory, expected_file_cache_size);
@@ -311,6 +449,22 @@ impl Runner {
             cgroup.threshold = new_threshold;
             info!("downscale: {message}");
             status.push(message);
+
+            let new_memory_high = self.config.memory_high(usable_system_memory, target.cpu);
+            cgroup
+                .manager
+                .set_memory_high_bytes(new_memory_high)
+                .context("failed to set cgroup memory.high")?;
+            let message = format!(
+                "set cgroup memory.high from {} MiB to {} MiB, with {} vCPU",
+                bytes_to_mebibytes(cgroup.memory_high),
+                bytes_to_mebibytes(new_memory_high),
+                target.cpu,
+            );
+            cgroup.memory_high = new_memory_high;
+            cgroup.cpu = target.cpu;
+            info!("downscale: {message}");
+            status.push(message);
         }
 
         // TODO: make this status thing less jank
@@ -365,6 +519,20 @@ impl Runner {
                 bytes_to_mebibytes(usable_system_memory)
             );
             cgroup.threshold = new_threshold;
+
+            let new_memory_high = self.config.memory_high(usable_system_memory, resources.cpu);
+            cgroup
+                .manager
+                .set_memory_high_bytes(new_memory_high)
+                .context("failed to set cgroup memory.high")?;
+            info!(
+                "set cgroup memory.high from {} MiB to {} MiB, with {} vCPU",
+                bytes_to_mebibytes(cgroup.memory_high),
+                bytes_to_mebibytes(new_memory_high),
+                resources.cpu,
+            );
+            cgroup.memory_high = new_memory_high;
+            cgroup.cpu = resources.cpu;
         }
 
         Ok(())
@@ -413,10 +581,78 @@ impl Runner {
         }
     }
 
+    /// Request upscaling from the agent, unless we already did less than a second ago, to avoid
+    /// spamming it. Returns whether the request was sent.
+    async fn request_upscale(&mut self) -> anyhow::Result<bool> {
+        if let Some(t) = self.last_upscale_request_at {
+            if t.elapsed() < Duration::from_secs(1) {
+                // *Ideally* we'd like to log here that we're ignoring the fact the
+                // memory stats are too high, but in practice this can result in
+                // spamming the logs with repetitive messages about ignoring the signal
+                //
+                // See https://github.com/neondatabase/neon/issues/5865 for more.
+                return Ok(false);
+            }
+        }
+
+        self.last_upscale_request_at = Some(Instant::now());
+
+        self.counter += 2; // Increment, preserving parity (i.e. keep the
+                           // counter odd). See the field comment for more.
+        self.dispatcher
+            .send(OutboundMsg::new(
+                OutboundMsgKind::UpscaleRequest {},
+                self.counter,
+            ))
+            .await
+            .context("failed to send message")?;
+        Ok(true)
+    }
+
+    /// Check whether the working set outgrew the file cache, and request upscaling if so. The
+    /// memory stats of the cgroup don't show this, as the file cache is reclaimable page cache,
+    /// so without it we would keep evicting the pages we're about to read again.
+    async fn check_working_set(&mut self) -> anyhow::Result<()> {
+        let Some(file_cache) = &mut self.filecache else {
+            return Ok(());
+        };
+
+        let working_set = match file_cache
+            .get_working_set_size(self.config.working_set_window)
+            .await
+        {
+            Ok(Some(working_set)) => working_set,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                warn!(error = format!("{e:#}"), "failed to get working set size");
+                return Ok(());
+            }
+        };
+        let file_cache_size = file_cache
+            .get_file_cache_size()
+            .await
+            .context("failed to get file cache size")?;
+
+        if working_set <= file_cache_size {
+            return Ok(());
+        }
+
+        if self.request_upscale().await? {
+            info!(
+                working_set = bytes_to_mebibytes(working_set),
+                file_cache_size = bytes_to_mebibytes(file_cache_size),
+                "file cache working set exceeds the file cache size, requested upscale",
+            );
+        }
+        Ok(())
+    }
+
     // TODO: don't propagate errors, probably just warn!?
     #[tracing::instrument(skip_all)]
     pub async fn run(&mut self) -> anyhow::Result<()> {
         info!("starting dispatcher");
+        let mut working_set_check = tokio::time::interval(self.config.working_set_check_interval);
+        working_set_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
         loop {
             tokio::select! {
                 signal = self.kill.recv() => {
@@ -431,44 +667,53 @@ impl Runner {
                 result = self.cgroup.as_mut().unwrap().watcher.changed(), if self.cgroup.is_some() => {
                     result.context("failed to receive from cgroup memory stats watcher")?;
 
-                    let cgroup = self.cgroup.as_ref().unwrap();
-
-                    let (_time, cgroup_mem_stat) = *cgroup.watcher.borrow();
+                    let cgroup = self.cgroup.as_mut().unwrap();
+
+                    let (time, cgroup_mem_stat) = *cgroup.watcher.borrow();
+
+                    // Getting throttled at memory.high means that we're already short on memory,
+                    // regardless of the threshold, so we want upscaling.
+                    let throttled = cgroup_mem_stat.memory_high_events.is_some_and(|events| {
+                        cgroup.memory_high_events.record(
+                            time,
+                            events,
+                            self.config.memory_high_window,
+                            self.config.memory_high_min_events,
+                        )
+                    });
+                    if throttled {
+                        cgroup.last_memory_high_at = Some(Instant::now());
+                        let memory_high = cgroup.memory_high;
+                        if self.request_upscale().await? {
+                            info!(
+                                memory_high = bytes_to_mebibytes(memory_high),
+                                "cgroup was throttled at memory.high, requested upscale",
+                            );
+                        }
+                        continue;
+                    }
 
                     // If we haven't exceeded the threshold, then we're all ok
                     if cgroup_mem_stat.avg_non_reclaimable < cgroup.threshold {
                         continue;
                     }
 
-                    // Otherwise, we generally want upscaling. But, if it's been less than 1 second
-                    // since the last time we requested upscaling, ignore the event, to avoid
-                    // spamming the agent.
-                    if let Some(t) = self.last_upscale_request_at {
-                        let elapsed = t.elapsed();
-                        if elapsed < Duration::from_secs(1) {
-                            // *Ideally* we'd like to log here that we're ignoring the fact the
-                            // memory stats are too high, but in practice this can result in
-                            // spamming the logs with repetitive messages about ignoring the signal
-                            //
-                            // See https://github.com/neondatabase/neon/issues/5865 for more.
-                            continue;
-                        }
+                    // Otherwise, we generally want upscaling, unless we requested it very
+                    // recently.
+                    let avg_non_reclaimable = cgroup_mem_stat.avg_non_reclaimable;
+                    let threshold = cgroup.threshold;
+                    if self.request_upscale().await? {
+                        info!(
+                            avg_non_reclaimable = bytes_to_mebibytes(avg_non_reclaimable),
+                            threshold = bytes_to_mebibytes(threshold),
+                            "cgroup memory stats are high enough to upscale, requested upscale",
+                        );
                     }
+                },
 
-                    self.last_upscale_request_at = Some(Instant::now());
-
-                    info!(
-                        avg_non_reclaimable = bytes_to_mebibytes(cgroup_mem_stat.avg_non_reclaimable),
-                        threshold = bytes_to_mebibytes(cgroup.threshold),
-                        "cgroup memory stats are high enough to upscale, requesting upscale",
-                    );
-
-                    self.counter += 2; // Increment, preserving parity (i.e. keep the
-                                       // counter odd). See the field comment for more.
-                    self.dispatcher
-                        .send(OutboundMsg::new(OutboundMsgKind::UpscaleRequest {}, self.counter))
-                        .await
-                        .context("failed to send message")?;
+                // The file cache working set *may* have outgrown the file cache
+                _ = working_set_check.tick(), if self.filecache.is_some() => {
+                    self.check_working_set().await?;
                 },
 
                 // there is a message from the agent
@@ -527,3 +772,38 @@ impl Runner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::MemoryHighEvents;
+
+    #[test]
+    fn memory_high_events() {
+        let window = Duration::from_secs(5);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut events = MemoryHighEvents::default();
+
+        // The first count is only the baseline, even if the cgroup was throttled before.
+        assert!(!events.record(at(0), 1000, window, 10));
+
+        // Page cache reclaim now and then doesn't count, however long it goes on.
+        for i in 1..100 {
+            assert!(!events.record(at(i * 1000), 1000 + i, window, 10));
+        }
+
+        // Persistent throttling does.
+        assert!(!events.record(at(100_100), 1104, window, 10));
+        assert!(events.record(at(100_200), 1110, window, 10));
+        assert!(events.record(at(100_300), 1200, window, 10));
+
+        // Until it stops for longer than the window.
+        assert!(events.record(at(105_000), 1200, window, 10));
+        assert!(!events.record(at(105_400), 1200, window, 10));
+
+        // A counter reset, e.g. because the cgroup was recreated, doesn't count either.
+        assert!(!events.record(at(106_000), 0, window, 10));
+    }
+}
@@ -175,6 +175,8 @@ pub struct Config {
     /// walproposer mode, finish when all safekeepers are synced or subscribe
     /// to WAL streaming
     pub sync_safekeepers: bool,
+    /// Generation of safekeepers membership configuration
+    pub safekeepers_generation: u32,
 }
 
 /// WalProposer main struct. C methods are reexported as Rust functions.
@@ -210,6 +212,7 @@ impl Wrapper {
             syncSafekeepers: config.sync_safekeepers,
             systemId: 0,
             pgTimeline: 1,
+            safekeepers_generation: config.safekeepers_generation,
             callback_data,
         };
         let c_config = Box::into_raw(Box::new(c_config));
@@ -514,6 +517,7 @@ mod tests {
             safekeeper_reconnect_timeout: 1000,
             safekeeper_connection_timeout: 10000,
             sync_safekeepers: true,
+            safekeepers_generation: 0,
         };
 
         let wp = Wrapper::new(my_impl, config);
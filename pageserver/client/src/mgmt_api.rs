@@ -189,6 +189,15 @@ impl Client {
         Ok(())
     }
 
+    pub async fn utilization(&self) -> Result<PageserverUtilization> {
+        let uri = format!("{}/v1/utilization", self.mgmt_api_endpoint);
+        self.get(&uri)
+            .await?
+            .json()
+            .await
+            .map_err(Error::ReceiveBody)
+    }
+
     pub async fn tenant_create(&self, req: &TenantCreateRequest) -> Result<TenantId> {
         let uri = format!("{}/v1/tenant", self.mgmt_api_endpoint);
         self.request(Method::POST, &uri, req)
@@ -232,6 +241,30 @@ impl Client {
         Ok(())
     }
 
+    pub async fn tenant_secondary_status(
+        &self,
+        tenant_shard_id: TenantShardId,
+    ) -> Result<SecondaryProgress> {
+        let uri = format!(
+            "{}/v1/tenant/{}/secondary/status",
+            self.mgmt_api_endpoint, tenant_shard_id
+        );
+        self.get(&uri)
+            .await?
+            .json()
+            .await
+            .map_err(Error::ReceiveBody)
+    }
+
+    pub async fn tenant_heatmap_upload(&self, tenant_shard_id: TenantShardId) -> Result<()> {
+        let uri = format!(
+            "{}/v1/tenant/{}/heatmap_upload",
+            self.mgmt_api_endpoint, tenant_shard_id
+        );
+        self.request(Method::POST, &uri, ()).await?;
+        Ok(())
+    }
+
     pub async fn location_config(
         &self,
         tenant_shard_id: TenantShardId,
@@ -280,6 +313,85 @@ impl Client {
             .map_err(Error::ReceiveBody)
     }
 
+    pub async fn timeline_gc_info(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+    ) -> Result<TimelineGcInfo> {
+        let uri = format!(
+            "{}/v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/gc_info",
+            self.mgmt_api_endpoint
+        );
+        self.get(&uri)
+            .await?
+            .json()
+            .await
+            .map_err(Error::ReceiveBody)
+    }
+
+    pub async fn timeline_compact(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        wait_until_uploaded: bool,
+    ) -> Result<()> {
+        let uri = format!(
+            "{}/v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/compact?wait_until_uploaded={wait_until_uploaded}",
+            self.mgmt_api_endpoint
+        );
+        self.request(Method::PUT, &uri, ()).await.map(|_| ())
+    }
+
+    /// Flush the in-memory layers and compact, with a pageserver built with
+    /// the `testing` feature.
+    pub async fn timeline_checkpoint(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        wait_until_uploaded: bool,
+    ) -> Result<()> {
+        let uri = format!(
+            "{}/v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/checkpoint?wait_until_uploaded={wait_until_uploaded}",
+            self.mgmt_api_endpoint
+        );
+        self.request(Method::PUT, &uri, ()).await.map(|_| ())
+    }
+
+    pub async fn timeline_gc(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        req: &TimelineGcRequest,
+        wait_until_uploaded: bool,
+    ) -> Result<TimelineGcResponse> {
+        let uri = format!(
+            "{}/v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/do_gc?wait_until_uploaded={wait_until_uploaded}",
+            self.mgmt_api_endpoint
+        );
+        self.request(Method::PUT, &uri, req)
+            .await?
+            .json()
+            .await
+            .map_err(Error::ReceiveBody)
+    }
+
+    pub async fn timeline_lsn_lease(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        req: &LsnLeaseRequest,
+    ) -> Result<LsnLease> {
+        let uri = format!(
+            "{}/v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/lsn_lease",
+            self.mgmt_api_endpoint
+        );
+        self.request(Method::POST, &uri, req)
+            .await?
+            .json()
+            .await
+            .map_err(Error::ReceiveBody)
+    }
+
     /// The timeline deletion API can return 201 if deletion is incomplete, or
     /// 403 if it is complete.  Callers are responsible for checking the status
     /// code and retrying.  Error codes other than 403 will return Err().
@@ -17,5 +17,8 @@ pub fn check_permission(claims: &Claims, tenant_id: Option<TenantId>) -> Result<
         (Scope::SafekeeperData, _) => Err(AuthError(
             "SafekeeperData scope makes no sense for Pageserver".into(),
         )),
+        (Scope::ProxyApi, _) => Err(AuthError(
+            "ProxyApi scope makes no sense for Pageserver".into(),
+        )),
     }
 }
@@ -15,10 +15,13 @@ use bytes::{BufMut, Bytes, BytesMut};
 use fail::fail_point;
 use pageserver_api::key::{key_to_slru_block, Key};
 use postgres_ffi::pg_constants;
+use sha2::{Digest, Sha256};
 use std::fmt::Write as FmtWrite;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
 use std::time::SystemTime;
 use tokio::io;
-use tokio::io::AsyncWrite;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tracing::*;
 
 use tokio_tar::{Builder, EntryType, Header};
@@ -38,6 +41,10 @@ use postgres_ffi::PG_TLI;
 use postgres_ffi::{BLCKSZ, RELSEG_SIZE, WAL_SEGMENT_SIZE};
 use utils::lsn::Lsn;
 
+/// Name of the entry with the SHA-256 of the tarball, which
+/// [`send_basebackup_tarball_from`] appends if asked to.
+pub const CHECKSUM_FILE: &str = "basebackup.sha256";
+
 /// Create basebackup with non-rel data in it.
 /// Only include relational data if 'full_backup' is true.
 ///
@@ -54,6 +61,71 @@ pub async fn send_basebackup_tarball<'a, W>(
     full_backup: bool,
     ctx: &'a RequestContext,
 ) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Send + Sync + Unpin,
+{
+    // use current time as last modified time
+    let mtime = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    build_tarball(write, timeline, req_lsn, prev_lsn, full_backup, mtime, ctx).await
+}
+
+/// Send the non-full basebackup at `req_lsn` without its first `offset` bytes,
+/// which the client received before an earlier request got interrupted, so
+/// that it can resume the download from the last entry it unpacked.
+///
+/// To make that possible, the entries have a fixed modification time, so that
+/// the tarball is byte-identical every time it is taken at the same LSN. If
+/// `with_checksum` is set, the tarball is followed by an entry named
+/// [`CHECKSUM_FILE`], with the hex-encoded SHA-256 of the whole tarball,
+/// regardless of `offset`.
+pub async fn send_basebackup_tarball_from<'a, W>(
+    write: &'a mut W,
+    timeline: &'a Timeline,
+    req_lsn: Lsn,
+    offset: u64,
+    with_checksum: bool,
+    ctx: &'a RequestContext,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Send + Sync + Unpin,
+{
+    let mut writer = HashingWriter {
+        inner: SkipWriter {
+            inner: write,
+            skip: offset,
+        },
+        hasher: Sha256::new(),
+    };
+    build_tarball(&mut writer, timeline, Some(req_lsn), None, false, 0, ctx).await?;
+    ensure!(
+        writer.inner.skip == 0,
+        "offset {offset} is beyond the end of the basebackup"
+    );
+
+    if with_checksum {
+        let checksum = hex::encode(writer.hasher.finalize());
+        let header = new_tar_header(CHECKSUM_FILE, checksum.len() as u64, 0)?;
+        let mut entry = header.as_bytes().to_vec();
+        entry.extend_from_slice(checksum.as_bytes());
+        // pad the content to a full block, like every tar entry
+        entry.resize(entry.len().next_multiple_of(512), 0);
+        writer.inner.inner.write_all(&entry).await?;
+    }
+    Ok(())
+}
+
+async fn build_tarball<'a, W>(
+    write: &'a mut W,
+    timeline: &'a Timeline,
+    req_lsn: Option<Lsn>,
+    prev_lsn: Option<Lsn>,
+    full_backup: bool,
+    mtime: u64,
+    ctx: &'a RequestContext,
+) -> anyhow::Result<()>
 where
     W: AsyncWrite + Send + Sync + Unpin,
 {
@@ -111,6 +183,7 @@ where
         lsn: backup_lsn,
         prev_record_lsn: prev_lsn,
         full_backup,
+        mtime,
         ctx,
     };
     basebackup
@@ -131,6 +204,8 @@ where
     lsn: Lsn,
     prev_record_lsn: Lsn,
     full_backup: bool,
+    /// Modification time of the entries.
+    mtime: u64,
     ctx: &'a RequestContext,
 }
 
@@ -143,17 +218,19 @@ where
     ar: &'a mut Builder<&'b mut W>,
     buf: Vec<u8>,
     current_segment: Option<(SlruKind, u32)>,
+    mtime: u64,
 }
 
 impl<'a, 'b, W> SlruSegmentsBuilder<'a, 'b, W>
 where
     W: AsyncWrite + Send + Sync + Unpin,
 {
-    fn new(ar: &'a mut Builder<&'b mut W>) -> Self {
+    fn new(ar: &'a mut Builder<&'b mut W>, mtime: u64) -> Self {
         Self {
             ar,
             buf: Vec::new(),
             current_segment: None,
+            mtime,
         }
     }
 
@@ -196,7 +273,7 @@ where
         let nblocks = self.buf.len() / BLCKSZ as usize;
         let (kind, segno) = self.current_segment.take().unwrap();
         let segname = format!("{}/{:>04X}", kind.to_str(), segno);
-        let header = new_tar_header(&segname, self.buf.len() as u64)?;
+        let header = new_tar_header(&segname, self.buf.len() as u64, self.mtime)?;
         self.ar.append(&header, self.buf.as_slice()).await?;
 
         trace!("Added to basebackup slru {} relsize {}", segname, nblocks);
@@ -220,13 +297,11 @@ where
     W: AsyncWrite + Send + Sync + Unpin,
 {
     async fn send_tarball(mut self) -> anyhow::Result<()> {
-        // TODO include checksum
-
         let lazy_slru_download = self.timeline.get_lazy_slru_download() && !self.full_backup;
 
         // Create pgdata subdirs structure
         for dir in PGDATA_SUBDIRS.iter() {
-            let header = new_tar_header_dir(dir)?;
+            let header = new_tar_header_dir(dir, self.mtime)?;
             self.ar
                 .append(&header, &mut io::empty())
                 .await
@@ -237,13 +312,13 @@ where
         for filepath in PGDATA_SPECIAL_FILES.iter() {
             if *filepath == "pg_hba.conf" {
                 let data = PG_HBA.as_bytes();
-                let header = new_tar_header(filepath, data.len() as u64)?;
+                let header = new_tar_header(filepath, data.len() as u64, self.mtime)?;
                 self.ar
                     .append(&header, data)
                     .await
                     .context("could not add config file to basebackup tarball")?;
             } else {
-                let header = new_tar_header(filepath, 0)?;
+                let header = new_tar_header(filepath, 0, self.mtime)?;
                 self.ar
                     .append(&header, &mut io::empty())
                     .await
@@ -258,7 +333,7 @@ where
                 .await?
                 .partition(Timeline::MAX_GET_VECTORED_KEYS * BLCKSZ as u64);
 
-            let mut slru_builder = SlruSegmentsBuilder::new(&mut self.ar);
+            let mut slru_builder = SlruSegmentsBuilder::new(&mut self.ar, self.mtime);
 
             for part in slru_partitions.parts {
                 let blocks = self
@@ -273,11 +348,13 @@ where
             slru_builder.finish().await?;
         }
 
+        // The listings below come from hash maps: sort them, so that the
+        // tarball is byte-identical every time it is taken at the same LSN.
         let mut min_restart_lsn: Lsn = Lsn::MAX;
+        let mut dbdirs = Vec::from_iter(self.timeline.list_dbdirs(self.lsn, self.ctx).await?);
+        dbdirs.sort_unstable();
         // Create tablespace directories
-        for ((spcnode, dbnode), has_relmap_file) in
-            self.timeline.list_dbdirs(self.lsn, self.ctx).await?
-        {
+        for ((spcnode, dbnode), has_relmap_file) in dbdirs {
             self.add_dbdir(spcnode, dbnode, has_relmap_file).await?;
 
             // If full backup is requested, include all relation files.
@@ -286,7 +363,9 @@ where
                 .timeline
                 .list_rels(spcnode, dbnode, Version::Lsn(self.lsn), self.ctx)
                 .await?;
-            for &rel in rels.iter() {
+            let mut sorted_rels = Vec::from_iter(rels.iter().copied());
+            sorted_rels.sort_unstable();
+            for rel in sorted_rels {
                 // Send init fork as main fork to provide well formed empty
                 // contents of UNLOGGED relations. Postgres copies it in
                 // `reinit.c` during recovery.
@@ -308,7 +387,10 @@ where
                 }
             }
 
-            for (path, content) in self.timeline.list_aux_files(self.lsn, self.ctx).await? {
+            let mut aux_files =
+                Vec::from_iter(self.timeline.list_aux_files(self.lsn, self.ctx).await?);
+            aux_files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            for (path, content) in aux_files {
                 if path.starts_with("pg_replslot") {
                     let offs = pg_constants::REPL_SLOT_ON_DISK_OFFSETOF_RESTART_LSN;
                     let restart_lsn = Lsn(u64::from_le_bytes(
@@ -317,7 +399,7 @@ where
                     info!("Replication slot {} restart LSN={}", path, restart_lsn);
                     min_restart_lsn = Lsn::min(min_restart_lsn, restart_lsn);
                 }
-                let header = new_tar_header(&path, content.len() as u64)?;
+                let header = new_tar_header(&path, content.len() as u64, self.mtime)?;
                 self.ar
                     .append(&header, &*content)
                     .await
@@ -330,17 +412,19 @@ where
                 min_restart_lsn
             );
             let data = min_restart_lsn.0.to_le_bytes();
-            let header = new_tar_header("restart.lsn", data.len() as u64)?;
+            let header = new_tar_header("restart.lsn", data.len() as u64, self.mtime)?;
             self.ar
                 .append(&header, &data[..])
                 .await
                 .context("could not add restart.lsn file to basebackup tarball")?;
         }
-        for xid in self
-            .timeline
-            .list_twophase_files(self.lsn, self.ctx)
-            .await?
-        {
+        let mut twophase_xids = Vec::from_iter(
+            self.timeline
+                .list_twophase_files(self.lsn, self.ctx)
+                .await?,
+        );
+        twophase_xids.sort_unstable();
+        for xid in twophase_xids {
             self.add_twophase_file(xid).await?;
         }
 
@@ -365,7 +449,7 @@ where
         // If the relation is empty, create an empty file
         if nblocks == 0 {
             let file_name = dst.to_segfile_name(0);
-            let header = new_tar_header(&file_name, 0)?;
+            let header = new_tar_header(&file_name, 0, self.mtime)?;
             self.ar.append(&header, &mut io::empty()).await?;
             return Ok(());
         }
@@ -386,7 +470,7 @@ where
             }
 
             let file_name = dst.to_segfile_name(seg as u32);
-            let header = new_tar_header(&file_name, segment_data.len() as u64)?;
+            let header = new_tar_header(&file_name, segment_data.len() as u64, self.mtime)?;
             self.ar.append(&header, segment_data.as_slice()).await?;
 
             seg += 1;
@@ -432,14 +516,15 @@ where
                 14 | 15 => self.timeline.pg_version.to_string(),
                 ver => format!("{ver}\x0A"),
             };
-            let header = new_tar_header("PG_VERSION", pg_version_str.len() as u64)?;
+            let header = new_tar_header("PG_VERSION", pg_version_str.len() as u64, self.mtime)?;
             self.ar.append(&header, pg_version_str.as_bytes()).await?;
 
             info!("timeline.pg_version {}", self.timeline.pg_version);
 
             if let Some(img) = relmap_img {
                 // filenode map for global tablespace
-                let header = new_tar_header("global/pg_filenode.map", img.len() as u64)?;
+                let header =
+                    new_tar_header("global/pg_filenode.map", img.len() as u64, self.mtime)?;
                 self.ar.append(&header, &img[..]).await?;
             } else {
                 warn!("global/pg_filenode.map is missing");
@@ -469,7 +554,7 @@ where
 
             // Append dir path for each database
             let path = format!("base/{}", dbnode);
-            let header = new_tar_header_dir(&path)?;
+            let header = new_tar_header_dir(&path, self.mtime)?;
             self.ar.append(&header, &mut io::empty()).await?;
 
             if let Some(img) = relmap_img {
@@ -479,11 +564,11 @@ where
                     14 | 15 => self.timeline.pg_version.to_string(),
                     ver => format!("{ver}\x0A"),
                 };
-                let header = new_tar_header(&dst_path, pg_version_str.len() as u64)?;
+                let header = new_tar_header(&dst_path, pg_version_str.len() as u64, self.mtime)?;
                 self.ar.append(&header, pg_version_str.as_bytes()).await?;
 
                 let relmap_path = format!("base/{}/pg_filenode.map", dbnode);
-                let header = new_tar_header(&relmap_path, img.len() as u64)?;
+                let header = new_tar_header(&relmap_path, img.len() as u64, self.mtime)?;
                 self.ar.append(&header, &img[..]).await?;
             }
         };
@@ -504,7 +589,7 @@ where
         let crc = crc32c::crc32c(&img[..]);
         buf.put_u32_le(crc);
         let path = format!("pg_twophase/{:>08X}", xid);
-        let header = new_tar_header(&path, buf.len() as u64)?;
+        let header = new_tar_header(&path, buf.len() as u64, self.mtime)?;
         self.ar.append(&header, &buf[..]).await?;
 
         Ok(())
@@ -528,7 +613,7 @@ where
         }
         self.ar
             .append(
-                &new_tar_header("zenith.signal", zenith_signal.len() as u64)?,
+                &new_tar_header("zenith.signal", zenith_signal.len() as u64, self.mtime)?,
                 zenith_signal.as_bytes(),
             )
             .await?;
@@ -552,14 +637,18 @@ where
         )?;
 
         //send pg_control
-        let header = new_tar_header("global/pg_control", pg_control_bytes.len() as u64)?;
+        let header = new_tar_header(
+            "global/pg_control",
+            pg_control_bytes.len() as u64,
+            self.mtime,
+        )?;
         self.ar.append(&header, &pg_control_bytes[..]).await?;
 
         //send wal segment
         let segno = self.lsn.segment_number(WAL_SEGMENT_SIZE);
         let wal_file_name = XLogFileName(PG_TLI, segno, WAL_SEGMENT_SIZE);
         let wal_file_path = format!("pg_wal/{}", wal_file_name);
-        let header = new_tar_header(&wal_file_path, WAL_SEGMENT_SIZE as u64)?;
+        let header = new_tar_header(&wal_file_path, WAL_SEGMENT_SIZE as u64, self.mtime)?;
 
         let wal_seg = postgres_ffi::generate_wal_segment(
             segno,
@@ -577,35 +666,82 @@ where
 //
 // Create new tarball entry header
 //
-fn new_tar_header(path: &str, size: u64) -> anyhow::Result<Header> {
+fn new_tar_header(path: &str, size: u64, mtime: u64) -> anyhow::Result<Header> {
     let mut header = Header::new_gnu();
     header.set_size(size);
     header.set_path(path)?;
     header.set_mode(0b110000000); // -rw-------
-    header.set_mtime(
-        // use currenttime as last modified time
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
+    header.set_mtime(mtime);
     header.set_cksum();
     Ok(header)
 }
 
-fn new_tar_header_dir(path: &str) -> anyhow::Result<Header> {
+fn new_tar_header_dir(path: &str, mtime: u64) -> anyhow::Result<Header> {
     let mut header = Header::new_gnu();
     header.set_size(0);
     header.set_path(path)?;
     header.set_mode(0o755); // -rw-------
     header.set_entry_type(EntryType::dir());
-    header.set_mtime(
-        // use currenttime as last modified time
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
+    header.set_mtime(mtime);
     header.set_cksum();
     Ok(header)
 }
+
+/// Discards the first `skip` bytes written to it.
+struct SkipWriter<W> {
+    inner: W,
+    skip: u64,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for SkipWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.skip > 0 {
+            let n = this.skip.min(buf.len() as u64);
+            this.skip -= n;
+            return Poll::Ready(Ok(n as usize));
+        }
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Computes the SHA-256 of everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.hasher.update(&buf[..n]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -121,6 +121,10 @@ pub mod defaults {
 
 #virtual_file_io_engine = '{DEFAULT_VIRTUAL_FILE_IO_ENGINE}'
 
+#verify_layer_checksums = true
+
+#static_safekeepers = []
+
 [tenant_config]
 #checkpoint_distance = {DEFAULT_CHECKPOINT_DISTANCE} # in bytes
 #checkpoint_timeout = {DEFAULT_CHECKPOINT_TIMEOUT}
@@ -256,6 +260,14 @@ pub struct PageServerConf {
     pub ingest_batch_size: u64,
 
     pub virtual_file_io_engine: virtual_file::IoEngineKind,
+
+    /// Verify the per-block checksums of layer files when reading blocks from disk.
+    /// Layer files written by older versions have no checksums and are never verified.
+    pub verify_layer_checksums: bool,
+
+    /// HTTP endpoints of safekeepers to poll for timeline state instead of
+    /// subscribing to the storage broker. If set, the broker is not used.
+    pub static_safekeepers: Vec<String>,
 }
 
 /// We do not want to store this in a PageServerConf because the latter may be logged
@@ -342,6 +354,10 @@ struct PageServerConfigBuilder {
     ingest_batch_size: BuilderValue<u64>,
 
     virtual_file_io_engine: BuilderValue<virtual_file::IoEngineKind>,
+
+    verify_layer_checksums: BuilderValue<bool>,
+
+    static_safekeepers: BuilderValue<Vec<String>>,
 }
 
 impl Default for PageServerConfigBuilder {
@@ -419,6 +435,10 @@ impl Default for PageServerConfigBuilder {
             ingest_batch_size: Set(DEFAULT_INGEST_BATCH_SIZE),
 
             virtual_file_io_engine: Set(DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap()),
+
+            verify_layer_checksums: Set(true),
+
+            static_safekeepers: Set(Vec::new()),
         }
     }
 }
@@ -579,6 +599,14 @@ impl PageServerConfigBuilder {
         self.virtual_file_io_engine = BuilderValue::Set(value);
     }
 
+    pub fn verify_layer_checksums(&mut self, value: bool) {
+        self.verify_layer_checksums = BuilderValue::Set(value);
+    }
+
+    pub fn static_safekeepers(&mut self, value: Vec<String>) {
+        self.static_safekeepers = BuilderValue::Set(value);
+    }
+
     pub fn build(self) -> anyhow::Result<PageServerConf> {
         let concurrent_tenant_warmup = self
             .concurrent_tenant_warmup
@@ -689,6 +717,12 @@ impl PageServerConfigBuilder {
             virtual_file_io_engine: self
                 .virtual_file_io_engine
                 .ok_or(anyhow!("missing virtual_file_io_engine"))?,
+            verify_layer_checksums: self
+                .verify_layer_checksums
+                .ok_or(anyhow!("missing verify_layer_checksums"))?,
+            static_safekeepers: self
+                .static_safekeepers
+                .ok_or(anyhow!("missing static_safekeepers"))?,
         })
     }
 }
@@ -943,6 +977,8 @@ impl PageServerConf {
                 "virtual_file_io_engine" => {
                     builder.virtual_file_io_engine(parse_toml_from_str("virtual_file_io_engine", item)?)
                 }
+                "verify_layer_checksums" => builder.verify_layer_checksums(parse_toml_bool(key, item)?),
+                "static_safekeepers" => builder.static_safekeepers(deserialize_from_item("static_safekeepers", item)?),
                 _ => bail!("unrecognized pageserver option '{key}'"),
             }
         }
@@ -1017,6 +1053,8 @@ impl PageServerConf {
             secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
             ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
             virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
+            verify_layer_checksums: true,
+            static_safekeepers: Vec::new(),
         }
     }
 }
@@ -1250,6 +1288,8 @@ background_task_maximum_delay = '334 s'
                 secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
                 ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
                 virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
+                verify_layer_checksums: true,
+                static_safekeepers: Vec::new(),
             },
             "Correct defaults should be used when no config values are provided"
         );
@@ -1314,6 +1354,8 @@ background_task_maximum_delay = '334 s'
                 secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
                 ingest_batch_size: 100,
                 virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
+                verify_layer_checksums: true,
+                static_safekeepers: Vec::new(),
             },
             "Should be able to parse all basic config values correctly"
         );
@@ -1544,6 +1586,7 @@ threshold = "20m"
                 #[cfg(feature = "testing")]
                 mock_statvfs: None,
                 eviction_order: crate::disk_usage_eviction_task::EvictionOrder::AbsoluteAccessed,
+                reserve: crate::disk_usage_eviction_task::EvictionReserve::default(),
             })
         );
         match &conf.default_tenant_conf.eviction_policy {
@@ -36,6 +36,14 @@
 //! during page reconstruction.
 //! An alternative default for all tenants can be specified in the `tenant_config` section of the config.
 //! Lastly, each tenant can have an override in their respective tenant config (`min_resident_size_override`).
+//!
+//! Secondary locations only keep warm copies of layers, so they are trimmed first: their
+//! layers are evicted, least recently accessed according to the heatmap first, before any
+//! layer of an attached tenant. The `reserve` setting protects a percentage of each location's
+//! resident bytes from this first pass, separately for attached and secondary locations.
+//! For attached tenants the reservation extends `tenant_min_resident_size`, for secondary
+//! locations it keeps the most recently accessed layers on par with attached tenants' layers
+//! outside their reservation.
 
 // Implementation notes:
 // - The `#[allow(dead_code)]` above various structs are to suppress warnings about only the Debug impl
@@ -80,6 +88,41 @@ pub struct DiskUsageEvictionTaskConfig {
     /// Select sorting for evicted layers
     #[serde(default)]
     pub eviction_order: EvictionOrder,
+    /// Share of each location's resident layers protected from eviction, per location mode
+    #[serde(default)]
+    pub reserve: EvictionReserve,
+}
+
+/// Percentage of a location's resident layer bytes, most recently accessed first, which are
+/// only evicted after all unreserved layers in the same mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EvictionReserve {
+    /// For attached tenants, applied when larger than the tenant's `min_resident_size`.
+    #[serde(default = "default_reserve_pct")]
+    pub attached_pct: Percent,
+    /// For secondary locations; the rest of their layers are evicted before any attached
+    /// tenant's layers.
+    #[serde(default = "default_reserve_pct")]
+    pub secondary_pct: Percent,
+}
+
+fn default_reserve_pct() -> Percent {
+    Percent::new(0).unwrap()
+}
+
+impl Default for EvictionReserve {
+    fn default() -> Self {
+        Self {
+            attached_pct: default_reserve_pct(),
+            secondary_pct: default_reserve_pct(),
+        }
+    }
+}
+
+impl EvictionReserve {
+    fn reserved_bytes(pct: Percent, resident_bytes: u64) -> u64 {
+        (resident_bytes as u128 * pct.get() as u128 / 100) as u64
+    }
 }
 
 /// Selects the sort order for eviction candidates *after* per tenant `min_resident_size`
@@ -304,6 +347,7 @@ async fn disk_usage_eviction_task_iteration(
         usage_pre,
         tenant_manager,
         task_config.eviction_order,
+        task_config.reserve,
         cancel,
     )
     .await;
@@ -394,6 +438,7 @@ pub(crate) async fn disk_usage_eviction_task_iteration_impl<U: Usage>(
     usage_pre: U,
     tenant_manager: &Arc<TenantManager>,
     eviction_order: EvictionOrder,
+    reserve: EvictionReserve,
     cancel: &CancellationToken,
 ) -> anyhow::Result<IterationOutcome<U>> {
     // use tokio's mutex to get a Sync guard (instead of std::sync::Mutex)
@@ -414,7 +459,7 @@ pub(crate) async fn disk_usage_eviction_task_iteration_impl<U: Usage>(
     );
 
     let candidates =
-        match collect_eviction_candidates(tenant_manager, eviction_order, cancel).await? {
+        match collect_eviction_candidates(tenant_manager, eviction_order, reserve, cancel).await? {
             EvictionCandidates::Cancelled => {
                 return Ok(IterationOutcome::Cancelled);
             }
@@ -652,6 +697,8 @@ impl std::fmt::Debug for EvictionCandidate {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 enum MinResidentSizePartition {
+    /// Secondary location layers outside the secondary reserve
+    Secondary,
     Above,
     Below,
 }
@@ -766,6 +813,7 @@ enum EvictionCandidates {
 async fn collect_eviction_candidates(
     tenant_manager: &Arc<TenantManager>,
     eviction_order: EvictionOrder,
+    reserve: EvictionReserve,
     cancel: &CancellationToken,
 ) -> anyhow::Result<EvictionCandidates> {
     // get a snapshot of the list of tenants
@@ -846,6 +894,16 @@ async fn collect_eviction_candidates(
             );
             max_layer_size
         };
+        let min_resident_size = {
+            let resident_bytes = tenant_candidates
+                .iter()
+                .map(|c| c.layer.get_file_size())
+                .sum();
+            min_resident_size.max(EvictionReserve::reserved_bytes(
+                reserve.attached_pct,
+                resident_bytes,
+            ))
+        };
 
         // Sort layers most-recently-used first, then partition by
         // cumsum above/below min_resident_size.
@@ -906,6 +964,16 @@ async fn collect_eviction_candidates(
             .resident_layers
             .sort_unstable_by_key(|layer_info| std::cmp::Reverse(layer_info.last_activity_ts));
 
+        let reserved_size = EvictionReserve::reserved_bytes(
+            reserve.secondary_pct,
+            layer_info
+                .resident_layers
+                .iter()
+                .map(|c| c.layer.get_file_size())
+                .sum(),
+        );
+        let mut cumsum: u64 = 0;
+
         let tenant_candidates =
             layer_info
                 .resident_layers
@@ -914,13 +982,19 @@ async fn collect_eviction_candidates(
                 .map(|(i, mut candidate)| {
                     candidate.relative_last_activity =
                         eviction_order.relative_last_activity(total_layers, i);
-                    (
-                        // Secondary locations' layers are always considered above the min resident size,
-                        // i.e. secondary locations are permitted to be trimmed to zero layers if all
-                        // the layers have sufficiently old access times.
-                        MinResidentSizePartition::Above,
-                        candidate,
-                    )
+
+                    // Secondary locations' layers are never considered below the min resident size,
+                    // i.e. secondary locations are permitted to be trimmed to zero layers if all
+                    // the layers have sufficiently old access times.  Layers outside of the reserve
+                    // go before any attached tenant's layers.
+                    let partition = if cumsum >= reserved_size {
+                        MinResidentSizePartition::Secondary
+                    } else {
+                        MinResidentSizePartition::Above
+                    };
+                    cumsum += candidate.layer.get_file_size();
+
+                    (partition, candidate)
                 });
 
         candidates.extend(tenant_candidates);
@@ -930,6 +1004,10 @@ async fn collect_eviction_candidates(
 
     debug_assert!(MinResidentSizePartition::Above < MinResidentSizePartition::Below,
         "as explained in the function's doc comment, layers that aren't in the tenant's min_resident_size are evicted first");
+    debug_assert!(
+        MinResidentSizePartition::Secondary < MinResidentSizePartition::Above,
+        "secondary locations' unreserved layers are evicted before attached tenants' layers"
+    );
 
     eviction_order.sort(&mut candidates);
 
@@ -1178,6 +1256,7 @@ mod filesystem_level_usage {
     #[test]
     fn max_usage_pct_pressure() {
         use super::EvictionOrder;
+        use super::EvictionReserve;
         use super::Usage as _;
         use std::time::Duration;
         use utils::serde_percent::Percent;
@@ -1190,6 +1269,7 @@ mod filesystem_level_usage {
                 #[cfg(feature = "testing")]
                 mock_statvfs: None,
                 eviction_order: EvictionOrder::default(),
+                reserve: EvictionReserve::default(),
             },
             total_bytes: 100_000,
             avail_bytes: 0,
@@ -15,7 +15,9 @@ use hyper::StatusCode;
 use hyper::{Body, Request, Response, Uri};
 use metrics::launch_timestamp::LaunchTimestamp;
 use pageserver_api::models::LocationConfigListResponse;
+use pageserver_api::models::PageserverUtilization;
 use pageserver_api::models::ShardParameters;
+use pageserver_api::models::ShardUtilization;
 use pageserver_api::models::TenantDetails;
 use pageserver_api::models::TenantLocationConfigResponse;
 use pageserver_api::models::TenantShardLocation;
@@ -41,7 +43,7 @@ use utils::http::request::{get_request_param, must_get_query_param, parse_query_
 
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::deletion_queue::DeletionQueueClient;
-use crate::metrics::{StorageTimeOperation, STORAGE_TIME_GLOBAL};
+use crate::metrics::{SmgrQueryType, StorageTimeOperation, STORAGE_TIME_GLOBAL};
 use crate::pgdatadir_mapping::LsnForTimestamp;
 use crate::task_mgr::TaskKind;
 use crate::tenant::config::{LocationConf, TenantConfOpt};
@@ -57,13 +59,14 @@ use crate::tenant::size::ModelInputs;
 use crate::tenant::storage_layer::LayerAccessStatsReset;
 use crate::tenant::timeline::CompactFlags;
 use crate::tenant::timeline::Timeline;
+use crate::tenant::timeline::DEFAULT_LSN_LEASE_LENGTH;
 use crate::tenant::SpawnMode;
 use crate::tenant::{LogicalSizeCalculationCause, PageReconstructError};
 use crate::{config::PageServerConf, tenant::mgr};
 use crate::{disk_usage_eviction_task, tenant};
 use pageserver_api::models::{
-    StatusResponse, TenantConfigRequest, TenantCreateRequest, TenantCreateResponse, TenantInfo,
-    TimelineCreateRequest, TimelineGcRequest, TimelineInfo,
+    LsnLease, LsnLeaseRequest, StatusResponse, TenantConfigRequest, TenantCreateRequest,
+    TenantCreateResponse, TenantInfo, TimelineCreateRequest, TimelineGcRequest, TimelineInfo,
 };
 use utils::{
     auth::SwappableJwtAuth,
@@ -447,6 +450,38 @@ async fn status_handler(
     json_response(StatusCode::OK, StatusResponse { id: config.id })
 }
 
+async fn get_utilization(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let state = get_state(&request);
+    let shards = state
+        .tenant_manager
+        .get_attached_active_tenant_shards()
+        .into_iter()
+        .map(|tenant| {
+            let timelines = tenant.list_timelines();
+            ShardUtilization {
+                tenant_shard_id: tenant.tenant_shard_id(),
+                resident_size: timelines
+                    .iter()
+                    .map(|timeline| timeline.resident_physical_size())
+                    .sum(),
+                getpage_requests: timelines
+                    .iter()
+                    .map(|timeline| {
+                        timeline
+                            .query_metrics
+                            .get_sample_count(SmgrQueryType::GetPageAtLsn)
+                    })
+                    .sum(),
+            }
+        })
+        .collect();
+    json_response(StatusCode::OK, PageserverUtilization { shards })
+}
+
 async fn reload_auth_validation_keys_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -1159,6 +1194,87 @@ async fn layer_map_info_handler(
     json_response(StatusCode::OK, layer_map_info)
 }
 
+async fn timeline_layers_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let format: Option<String> = parse_query_param(&request, "format")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
+    let layers = timeline.layer_residency_info().await;
+
+    match format.as_deref() {
+        None | Some("json") => json_response(StatusCode::OK, layers),
+        Some("csv") => {
+            use std::fmt::Write;
+
+            let mut csv = String::from(
+                "layer_file_name,layer_file_size,residence,creation_reason,last_access_millis_since_epoch,access_count\n",
+            );
+            for layer in layers {
+                writeln!(
+                    csv,
+                    "{},{},{:?},{:?},{},{}",
+                    layer.layer_file_name,
+                    layer.layer_file_size,
+                    layer.residence,
+                    layer.creation_reason,
+                    layer
+                        .last_access_millis_since_epoch
+                        .map(|ts| ts.to_string())
+                        .unwrap_or_default(),
+                    layer.access_count,
+                )
+                .unwrap();
+            }
+
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "text/csv")
+                .body(Body::from(csv))
+                .map_err(|e| ApiError::InternalServerError(e.into()))
+        }
+        Some(other) => Err(ApiError::BadRequest(anyhow!(
+            "unsupported format '{other}', expected 'json' or 'csv'"
+        ))),
+    }
+}
+
+async fn timeline_gc_info_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
+
+    json_response(StatusCode::OK, timeline.gc_info_summary())
+}
+
+async fn timeline_lsn_lease_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let request_data: LsnLeaseRequest = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
+
+    let length = request_data.length.unwrap_or(DEFAULT_LSN_LEASE_LENGTH);
+    let valid_until = timeline
+        .make_lsn_lease(request_data.lsn, length)
+        .map_err(|e| ApiError::PreconditionFailed(e.to_string().into_boxed_str()))?;
+
+    json_response(StatusCode::OK, LsnLease { valid_until })
+}
+
 async fn layer_download_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -1580,6 +1696,8 @@ async fn timeline_gc_handler(
     let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
     check_permission(&request, Some(tenant_shard_id.tenant_id))?;
 
+    let wait_until_uploaded =
+        parse_query_param::<_, bool>(&request, "wait_until_uploaded")?.unwrap_or(false);
     let gc_req: TimelineGcRequest = json_request(&mut request).await?;
 
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
@@ -1591,6 +1709,11 @@ async fn timeline_gc_handler(
         .map_err(ApiError::InternalServerError)?
         .map_err(ApiError::InternalServerError)?;
 
+    if wait_until_uploaded {
+        let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
+        wait_for_uploads(&timeline).await?;
+    }
+
     json_response(StatusCode::OK, gc_result)
 }
 
@@ -1607,6 +1730,8 @@ async fn timeline_compact_handler(
     if Some(true) == parse_query_param::<_, bool>(&request, "force_repartition")? {
         flags |= CompactFlags::ForceRepartition;
     }
+    let wait_until_uploaded =
+        parse_query_param::<_, bool>(&request, "wait_until_uploaded")?.unwrap_or(false);
     async {
         let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
         let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
@@ -1614,12 +1739,27 @@ async fn timeline_compact_handler(
             .compact(&cancel, flags, &ctx)
             .await
             .map_err(|e| ApiError::InternalServerError(e.into()))?;
+        if wait_until_uploaded {
+            wait_for_uploads(&timeline).await?;
+        }
         json_response(StatusCode::OK, ())
     }
     .instrument(info_span!("manual_compaction", tenant_id = %tenant_shard_id.tenant_id, shard_id = %tenant_shard_id.shard_slug(), %timeline_id))
     .await
 }
 
+/// Wait for the layer files and index changes scheduled so far to be uploaded,
+/// for callers that need a deterministic state in remote storage.
+async fn wait_for_uploads(timeline: &Timeline) -> Result<(), ApiError> {
+    if let Some(remote_client) = &timeline.remote_client {
+        remote_client
+            .wait_completion()
+            .await
+            .map_err(ApiError::InternalServerError)?;
+    }
+    Ok(())
+}
+
 // Run checkpoint immediately on given timeline.
 async fn timeline_checkpoint_handler(
     request: Request<Body>,
@@ -1633,6 +1773,8 @@ async fn timeline_checkpoint_handler(
     if Some(true) == parse_query_param::<_, bool>(&request, "force_repartition")? {
         flags |= CompactFlags::ForceRepartition;
     }
+    let wait_until_uploaded =
+        parse_query_param::<_, bool>(&request, "wait_until_uploaded")?.unwrap_or(false);
     async {
         let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
         let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
@@ -1644,6 +1786,9 @@ async fn timeline_checkpoint_handler(
             .compact(&cancel, flags, &ctx)
             .await
             .map_err(|e| ApiError::InternalServerError(e.into()))?;
+        if wait_until_uploaded {
+            wait_for_uploads(&timeline).await?;
+        }
 
         json_response(StatusCode::OK, ())
     }
@@ -1822,6 +1967,9 @@ async fn disk_usage_eviction_run(
 
         #[serde(default)]
         eviction_order: crate::disk_usage_eviction_task::EvictionOrder,
+
+        #[serde(default)]
+        reserve: crate::disk_usage_eviction_task::EvictionReserve,
     }
 
     #[derive(Debug, Clone, Copy, serde::Serialize)]
@@ -1865,6 +2013,7 @@ async fn disk_usage_eviction_run(
         usage,
         &state.tenant_manager,
         config.eviction_order,
+        config.reserve,
         &cancel,
     )
     .await;
@@ -1906,6 +2055,29 @@ async fn secondary_download_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn secondary_status_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let state = get_state(&request);
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    fail::fail_point!("secondary-status", |_| {
+        Err(ApiError::InternalServerError(anyhow::anyhow!(
+            "failpoint secondary-status"
+        )))
+    });
+    let Some(secondary_tenant) = state
+        .tenant_manager
+        .get_secondary_tenant_shard(tenant_shard_id)
+    else {
+        return Err(ApiError::NotFound(
+            anyhow::anyhow!("Shard {tenant_shard_id} not found or not in secondary mode").into(),
+        ));
+    };
+
+    json_response(StatusCode::OK, secondary_tenant.progress())
+}
+
 async fn handler_404(_: Request<Body>) -> Result<Response<Body>, ApiError> {
     json_response(
         StatusCode::NOT_FOUND,
@@ -2081,6 +2253,7 @@ pub fn make_router(
     Ok(router
         .data(state)
         .get("/v1/status", |r| api_handler(r, status_handler))
+        .get("/v1/utilization", |r| api_handler(r, get_utilization))
         .put("/v1/failpoints", |r| {
             testing_api_handler("manage failpoints", r, failpoints_handler)
         })
@@ -2153,6 +2326,14 @@ pub fn make_router(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/get_timestamp_of_lsn",
             |r| api_handler(r, get_timestamp_of_lsn_handler),
         )
+        .get(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/gc_info",
+            |r| api_handler(r, timeline_gc_info_handler),
+        )
+        .post(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/lsn_lease",
+            |r| api_handler(r, timeline_lsn_lease_handler),
+        )
         .put(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/do_gc",
             |r| api_handler(r, timeline_gc_handler),
@@ -2180,6 +2361,10 @@ pub fn make_router(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/layer",
             |r| api_handler(r, layer_map_info_handler),
         )
+        .get(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/layers",
+            |r| api_handler(r, timeline_layers_handler),
+        )
         .get(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/layer/:layer_file_name",
             |r| api_handler(r, layer_download_handler),
@@ -2200,6 +2385,9 @@ pub fn make_router(
         .post("/v1/tenant/:tenant_shard_id/secondary/download", |r| {
             api_handler(r, secondary_download_handler)
         })
+        .get("/v1/tenant/:tenant_shard_id/secondary/status", |r| {
+            api_handler(r, secondary_status_handler)
+        })
         .put("/v1/tenant/:tenant_shard_id/break", |r| {
             testing_api_handler("set tenant state to broken", r, handle_tenant_break)
         })
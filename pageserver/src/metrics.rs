@@ -466,6 +466,22 @@ pub(crate) static REMOTE_ONDEMAND_DOWNLOADED_BYTES: Lazy<IntCounter> = Lazy::new
     .unwrap()
 });
 
+pub(crate) static LAYER_CHECKSUM_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_layer_checksum_failures_total",
+        "Number of layer file blocks read from disk whose checksum did not match",
+    )
+    .unwrap()
+});
+
+pub(crate) static LAYER_DOWNLOAD_VERIFICATION_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_layer_download_verification_failures_total",
+        "Number of downloaded layer files which failed verification and were quarantined",
+    )
+    .unwrap()
+});
+
 static CURRENT_LOGICAL_SIZE: Lazy<UIntGaugeVec> = Lazy::new(|| {
     register_uint_gauge_vec!(
         "pageserver_current_logical_size",
@@ -1141,6 +1157,12 @@ impl SmgrQueryTimePerTimeline {
         });
         Self { metrics }
     }
+    /// Number of queries of the timeline, since it was loaded.
+    pub(crate) fn get_sample_count(&self, op: SmgrQueryType) -> u64 {
+        self.metrics[op as usize]
+            .per_tenant_timeline
+            .get_sample_count()
+    }
     pub(crate) fn start_timer(&self, op: SmgrQueryType) -> impl Drop + '_ {
         let metric = &self.metrics[op as usize];
         GlobalAndPerTimelineHistogramTimer {
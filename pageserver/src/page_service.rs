@@ -39,7 +39,7 @@ use std::pin::pin;
 use std::str;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io::AsyncWriteExt;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::io::StreamReader;
@@ -72,6 +72,7 @@ use crate::tenant::mgr::get_active_tenant_with_timeout;
 use crate::tenant::mgr::GetActiveTenantError;
 use crate::tenant::mgr::ShardSelector;
 use crate::tenant::timeline::WaitLsnError;
+use crate::tenant::timeline::DEFAULT_LSN_LEASE_LENGTH;
 use crate::tenant::GetTimelineError;
 use crate::tenant::PageReconstructError;
 use crate::tenant::Timeline;
@@ -1190,7 +1191,7 @@ impl PageServerHandler {
     }
 
     #[allow(clippy::too_many_arguments)]
-    #[instrument(skip_all, fields(shard_id, ?lsn, ?prev_lsn, %full_backup))]
+    #[instrument(skip_all, fields(shard_id, ?lsn, ?prev_lsn, %full_backup, %offset))]
     async fn handle_basebackup_request<IO>(
         &mut self,
         pgb: &mut PostgresBackend<IO>,
@@ -1200,6 +1201,8 @@ impl PageServerHandler {
         prev_lsn: Option<Lsn>,
         full_backup: bool,
         gzip: bool,
+        offset: u64,
+        with_checksum: bool,
         ctx: RequestContext,
     ) -> Result<(), QueryError>
     where
@@ -1240,6 +1243,33 @@ impl PageServerHandler {
                 &ctx,
             )
             .await?;
+        } else if offset > 0 || with_checksum {
+            let lsn = lsn.context("resuming a basebackup requires an LSN")?;
+            let mut writer = pgb.copyout_writer();
+            if gzip {
+                let mut encoder =
+                    GzipEncoder::with_quality(writer, async_compression::Level::Fastest);
+                basebackup::send_basebackup_tarball_from(
+                    &mut encoder,
+                    &timeline,
+                    lsn,
+                    offset,
+                    with_checksum,
+                    &ctx,
+                )
+                .await?;
+                encoder.shutdown().await?;
+            } else {
+                basebackup::send_basebackup_tarball_from(
+                    &mut writer,
+                    &timeline,
+                    lsn,
+                    offset,
+                    with_checksum,
+                    &ctx,
+                )
+                .await?;
+            }
         } else {
             let mut writer = pgb.copyout_writer();
             if gzip {
@@ -1437,18 +1467,29 @@ where
                 None
             };
 
-            let gzip = if params.len() >= 4 {
-                if params[3] == "--gzip" {
-                    true
+            // Flags after the LSN:
+            // --gzip: compress the tarball
+            // --offset=N: skip the first N bytes of the uncompressed tarball, to
+            //   resume an interrupted download
+            // --sha256: append the checksum of the tarball to it
+            let mut gzip = false;
+            let mut offset = 0;
+            let mut with_checksum = false;
+            for (i, param) in params.iter().enumerate().skip(3) {
+                if *param == "--gzip" {
+                    gzip = true;
+                } else if *param == "--sha256" {
+                    with_checksum = true;
+                } else if let Some(value) = param.strip_prefix("--offset=") {
+                    offset = value
+                        .parse()
+                        .with_context(|| format!("Failed to parse offset from {value}"))?;
                 } else {
                     return Err(QueryError::Other(anyhow::anyhow!(
-                        "Parameter in position 3 unknown {}",
-                        params[3],
+                        "Parameter in position {i} unknown {param}",
                     )));
                 }
-            } else {
-                false
-            };
+            }
 
             ::metrics::metric_vec_duration::observe_async_block_duration_by_result(
                 &*metrics::BASEBACKUP_QUERY_TIME,
@@ -1461,6 +1502,8 @@ where
                         None,
                         false,
                         gzip,
+                        offset,
+                        with_checksum,
                         ctx,
                     )
                     .await?;
@@ -1515,6 +1558,56 @@ where
             ))
             .await?;
         }
+        // keep an LSN readable for a static compute, on all the shards of the tenant here,
+        // and return until when, in milliseconds since the epoch
+        else if query_string.starts_with("lease lsn ") {
+            let (_, params_raw) = query_string.split_at("lease lsn ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+
+            if params.len() != 3 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for lease lsn command"
+                )));
+            }
+
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let lsn = Lsn::from_str(params[2])
+                .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?;
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            self.check_permission(Some(tenant_id))?;
+            async {
+                let mut valid_until: Option<SystemTime> = None;
+                for (tenant_shard_id, _, _) in mgr::list_tenants().await? {
+                    if tenant_shard_id.tenant_id != tenant_id {
+                        continue;
+                    }
+                    let timeline =
+                        mgr::get_tenant(tenant_shard_id, true)?.get_timeline(timeline_id, true)?;
+                    let lease = timeline.make_lsn_lease(lsn, DEFAULT_LSN_LEASE_LENGTH)?;
+                    valid_until = Some(valid_until.map_or(lease, |v| v.min(lease)));
+                }
+                let valid_until = valid_until.context("tenant is not attached here")?;
+                let valid_until_millis = valid_until.duration_since(UNIX_EPOCH)?.as_millis();
+
+                pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::text_col(
+                    b"valid_until",
+                )]))?
+                .write_message_noflush(&BeMessage::DataRow(&[Some(
+                    valid_until_millis.to_string().as_bytes(),
+                )]))?
+                .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+                anyhow::Ok(())
+            }
+            .instrument(info_span!("handle_lease_lsn", %lsn))
+            .await?;
+        }
         // same as basebackup, but result includes relational data as well
         else if query_string.starts_with("fullbackup ") {
             let (_, params_raw) = query_string.split_at("fullbackup ".len());
@@ -1564,6 +1657,8 @@ where
                 prev_lsn,
                 true,
                 false,
+                0,
+                false,
                 ctx,
             )
             .await?;
@@ -16,7 +16,7 @@ use tokio_epoll_uring::{BoundedBuf, Slice};
 
 use crate::context::RequestContext;
 use crate::page_cache::PAGE_SZ;
-use crate::tenant::block_io::BlockCursor;
+use crate::tenant::block_io::{BlockChecksums, BlockCursor};
 use crate::virtual_file::VirtualFile;
 use std::cmp::min;
 use std::io::{Error, ErrorKind};
@@ -105,15 +105,19 @@ pub struct BlobWriter<const BUFFERED: bool> {
     buf: Vec<u8>,
     /// We do tiny writes for the length headers; they need to be in an owned buffer;
     io_buf: Option<BytesMut>,
+    /// Checksums of the blocks written so far, starting from the block at `start_offset`
+    checksums: BlockChecksums,
 }
 
 impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
     pub fn new(inner: VirtualFile, start_offset: u64) -> Self {
+        debug_assert_eq!(start_offset % PAGE_SZ as u64, 0);
         Self {
             inner,
             offset: start_offset,
             buf: Vec::with_capacity(Self::CAPACITY),
             io_buf: Some(BytesMut::new()),
+            checksums: BlockChecksums::default(),
         }
     }
 
@@ -121,6 +125,12 @@ impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
         self.offset
     }
 
+    /// Take the checksums of all the blocks written so far. The last block may be
+    /// partially filled; see [`BlockChecksums::pad_to_block_boundary`].
+    pub fn take_checksums(&mut self) -> BlockChecksums {
+        std::mem::take(&mut self.checksums)
+    }
+
     const CAPACITY: usize = if BUFFERED { PAGE_SZ } else { 0 };
 
     /// Writes the given buffer directly to the underlying `VirtualFile`.
@@ -135,6 +145,9 @@ impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
         let (src_buf, res) = if src_buf_len > 0 {
             let src_buf = src_buf.slice(0..src_buf_len);
             let res = self.inner.write_all(&src_buf).await;
+            if res.is_ok() {
+                self.checksums.update(&src_buf);
+            }
             let src_buf = Slice::into_inner(src_buf);
             (src_buf, res)
         } else {
@@ -161,6 +174,7 @@ impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
         let remaining = Self::CAPACITY - self.buf.len();
         let to_copy = src_buf.len().min(remaining);
         self.buf.extend_from_slice(&src_buf[..to_copy]);
+        self.checksums.update(&src_buf[..to_copy]);
         self.offset += to_copy as u64;
         to_copy
     }
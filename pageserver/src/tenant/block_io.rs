@@ -9,6 +9,7 @@ use crate::page_cache::{self, PageReadGuard, PageWriteGuard, ReadBufResult, PAGE
 use crate::virtual_file::VirtualFile;
 use bytes::Bytes;
 use std::ops::Deref;
+use std::sync::Arc;
 
 /// This is implemented by anything that can read 8 kB (PAGE_SZ)
 /// blocks, using the page cache
@@ -165,13 +166,76 @@ pub struct FileBlockReader {
 
     /// Unique ID of this file, used as key in the page cache.
     file_id: page_cache::FileId,
+
+    /// Expected CRC32C of blocks `1..=checksums.len()`, if the file has them and
+    /// verification is enabled. Blocks are verified when they are read from disk,
+    /// page cache hits are not re-verified.
+    checksums: Option<Arc<[u32]>>,
 }
 
 impl FileBlockReader {
     pub fn new(file: VirtualFile) -> Self {
         let file_id = page_cache::next_file_id();
 
-        FileBlockReader { file_id, file }
+        FileBlockReader {
+            file_id,
+            file,
+            checksums: None,
+        }
+    }
+
+    /// Load the checksum table written by [`BlockChecksums::write_table`], and verify
+    /// every block covered by it from now on.
+    ///
+    /// `checksums_start_blk` is the block where the table begins; the table covers
+    /// all blocks between the summary block and itself.
+    pub(crate) async fn load_checksums(
+        &mut self,
+        checksums_start_blk: u32,
+        ctx: &RequestContext,
+    ) -> Result<(), std::io::Error> {
+        let count = checksums_start_blk.saturating_sub(1) as usize;
+        let mut checksums = Vec::with_capacity(count);
+        let mut blknum = checksums_start_blk;
+        while checksums.len() < count {
+            let blk = self.read_blk(blknum, ctx).await?;
+            for chunk in blk.chunks_exact(4) {
+                if checksums.len() == count {
+                    break;
+                }
+                checksums.push(u32::from_be_bytes(chunk.try_into().unwrap()));
+            }
+            blknum += 1;
+        }
+        self.checksums = Some(checksums.into());
+        Ok(())
+    }
+
+    fn verify_checksum(&self, blknum: u32, buf: &[u8]) -> Result<(), std::io::Error> {
+        let Some(checksums) = self.checksums.as_ref() else {
+            return Ok(());
+        };
+        let Some(expected) = (blknum as usize)
+            .checked_sub(1)
+            .and_then(|idx| checksums.get(idx))
+        else {
+            // the summary block and the checksum table itself are not covered
+            return Ok(());
+        };
+        let actual = crc32c::crc32c(buf);
+        if actual != *expected {
+            crate::metrics::LAYER_CHECKSUM_FAILURES.inc();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                BlockChecksumMismatch {
+                    path: self.file.path.to_string(),
+                    blknum,
+                    expected: *expected,
+                    actual,
+                },
+            ));
+        }
+        Ok(())
     }
 
     /// Read a page from the underlying file into given buffer.
@@ -209,6 +273,9 @@ impl FileBlockReader {
             ReadBufResult::NotFound(write_guard) => {
                 // Read the page from disk into the buffer
                 let write_guard = self.fill_buffer(write_guard, blknum).await?;
+                // On mismatch the buffer is dropped without being marked valid, so
+                // the corrupted contents never become visible in the page cache.
+                self.verify_checksum(blknum, &write_guard[..])?;
                 Ok(write_guard.mark_valid().into())
             }
         }
@@ -221,6 +288,90 @@ impl BlockReader for FileBlockReader {
     }
 }
 
+/// Error returned (wrapped in an [`std::io::Error`] of kind `InvalidData`) when a block
+/// read from a layer file does not match the checksum recorded when it was written.
+#[derive(Debug, thiserror::Error)]
+#[error("checksum mismatch in block {blknum} of {path}: expected {expected:#010x}, actual {actual:#010x}")]
+pub(crate) struct BlockChecksumMismatch {
+    path: String,
+    blknum: u32,
+    expected: u32,
+    actual: u32,
+}
+
+/// Returns true if any error in the chain is a [`BlockChecksumMismatch`].
+pub(crate) fn is_checksum_mismatch(e: &anyhow::Error) -> bool {
+    e.chain().any(|e| {
+        e.is::<BlockChecksumMismatch>()
+            || e.downcast_ref::<std::io::Error>()
+                .and_then(|e| e.get_ref())
+                .is_some_and(|inner| inner.is::<BlockChecksumMismatch>())
+    })
+}
+
+///
+/// Computes CRC32C checksums of consecutive PAGE_SZ blocks of a file, as the
+/// contents are being written.
+///
+/// Layer writers feed every byte they write after the summary block through
+/// [`Self::update`], and finally append the checksums to the end of the file
+/// with [`Self::write_table`].
+///
+#[derive(Default)]
+pub struct BlockChecksums {
+    checksums: Vec<u32>,
+    current: u32,
+    /// Bytes of the current block fed so far
+    filled: usize,
+}
+
+impl BlockChecksums {
+    pub fn update(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let n = std::cmp::min(PAGE_SZ - self.filled, buf.len());
+            self.current = crc32c::crc32c_append(self.current, &buf[..n]);
+            self.filled += n;
+            buf = &buf[n..];
+            if self.filled == PAGE_SZ {
+                self.checksums.push(self.current);
+                self.current = 0;
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Account for zeros up to the next block boundary, for when the writer
+    /// seeks over a partially filled block.
+    pub fn pad_to_block_boundary(&mut self) {
+        if self.filled > 0 {
+            const ZEROS: [u8; PAGE_SZ] = [0u8; PAGE_SZ];
+            self.update(&ZEROS[..PAGE_SZ - self.filled]);
+        }
+    }
+
+    /// Number of complete blocks checksummed so far.
+    pub fn len(&self) -> usize {
+        self.checksums.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checksums.is_empty()
+    }
+
+    /// Serialize the checksums into whole blocks, ready to be written after the
+    /// last checksummed block.
+    pub fn write_table(mut self) -> Vec<u8> {
+        self.pad_to_block_boundary();
+        let mut buf = Vec::with_capacity(self.checksums.len() * 4 + PAGE_SZ);
+        for checksum in self.checksums {
+            buf.extend_from_slice(&checksum.to_be_bytes());
+        }
+        let padded_len = (buf.len() + PAGE_SZ - 1) / PAGE_SZ * PAGE_SZ;
+        buf.resize(padded_len, 0);
+        buf
+    }
+}
+
 ///
 /// Trait for block-oriented output
 ///
@@ -263,3 +414,35 @@ impl Default for BlockBuf {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_checksums_split_writes() {
+        let data: Vec<u8> = (0..(PAGE_SZ * 3 + 100)).map(|i| (i % 251) as u8).collect();
+
+        let mut whole = BlockChecksums::default();
+        whole.update(&data);
+
+        let mut split = BlockChecksums::default();
+        for chunk in data.chunks(1000) {
+            split.update(chunk);
+        }
+        assert_eq!(whole.len(), 3);
+        assert_eq!(whole.checksums, split.checksums);
+
+        for (blk, checksum) in data.chunks(PAGE_SZ).zip(whole.checksums.iter()) {
+            assert_eq!(crc32c::crc32c(blk), *checksum);
+        }
+
+        // the partial last block is checksummed as if padded with zeros
+        let mut padded = data[PAGE_SZ * 3..].to_vec();
+        padded.resize(PAGE_SZ, 0);
+        let table = whole.write_table();
+        assert_eq!(table.len(), PAGE_SZ);
+        assert_eq!(table[12..16], crc32c::crc32c(&padded).to_be_bytes());
+        assert!(table[16..].iter().all(|b| *b == 0));
+    }
+}
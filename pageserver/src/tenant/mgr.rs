@@ -1477,6 +1477,10 @@ impl TenantManager {
             .await?;
         }
 
+        fail::fail_point!("shard-split-post-child", |_| {
+            anyhow::bail!("failpoint: shard-split-post-child")
+        });
+
         // Phase 4: wait for child chards WAL ingest to catch up to target LSN
         for child_shard_id in &child_shards {
             let child_shard = {
@@ -10,22 +10,24 @@ use anyhow::{anyhow, Context};
 use camino::{Utf8Path, Utf8PathBuf};
 use pageserver_api::shard::TenantShardId;
 use tokio::fs::{self, File, OpenOptions};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio_util::sync::CancellationToken;
 use tracing::warn;
 use utils::timeout::timeout_cancellable;
 use utils::{backoff, crashsafe};
 
 use crate::config::PageServerConf;
+use crate::page_cache::PAGE_SZ;
 use crate::span::debug_assert_current_span_has_tenant_and_timeline_id;
 use crate::tenant::remote_timeline_client::{
     download_cancellable, remote_layer_path, remote_timelines_path, DOWNLOAD_TIMEOUT,
 };
-use crate::tenant::storage_layer::LayerFileName;
+use crate::tenant::storage_layer::{delta_layer, image_layer, LayerFileName};
 use crate::tenant::Generation;
 use crate::virtual_file::on_fatal_io_error;
-use crate::TEMP_FILE_SUFFIX;
+use crate::{DELTA_FILE_MAGIC, IMAGE_FILE_MAGIC, STORAGE_FORMAT_VERSION, TEMP_FILE_SUFFIX};
 use remote_storage::{DownloadError, GenericRemoteStorage, ListingMode};
+use utils::bin_ser::BeSer;
 use utils::crashsafe::path_with_suffix_extension;
 use utils::id::TimelineId;
 
@@ -37,8 +39,9 @@ use super::{
 };
 
 ///
-/// If 'metadata' is given, we will validate that the downloaded file's size matches that
-/// in the metadata. (In the future, we might do more cross-checks, like CRC validation)
+/// We validate that the downloaded file's size matches that in the metadata, and that its
+/// summary header and block checksums are consistent with the layer it is supposed to be.
+/// Files failing validation are moved aside with a [`QUARANTINE_EXTENSION`] suffix.
 ///
 /// Returns the size of the downloaded file.
 pub async fn download_layer_file<'a>(
@@ -172,6 +175,32 @@ pub async fn download_layer_file<'a>(
         .map_err(DownloadError::Other)?;
     drop(destination_file);
 
+    if let Err(e) = verify_downloaded_layer(
+        &temp_file_path,
+        layer_file_name,
+        tenant_shard_id,
+        timeline_id,
+        expected,
+        conf.verify_layer_checksums,
+    )
+    .await
+    {
+        crate::metrics::LAYER_DOWNLOAD_VERIFICATION_FAILURES.inc();
+        let quarantine_path = path_with_suffix_extension(&local_path, QUARANTINE_EXTENSION);
+        tracing::error!(
+            "downloaded layer file failed verification, quarantining it as {quarantine_path}: {e:#}"
+        );
+        if let Err(rename_err) = fs::rename(&temp_file_path, &quarantine_path).await {
+            warn!("failed to quarantine {temp_file_path}: {rename_err}");
+            if let Err(e) = fs::remove_file(&temp_file_path).await {
+                on_fatal_io_error(&e, &format!("Removing temporary file {temp_file_path}"));
+            }
+        }
+        return Err(DownloadError::Other(
+            e.context(format!("verify downloaded layer file {local_path}")),
+        ));
+    }
+
     fail::fail_point!("remote-storage-download-pre-rename", |_| {
         Err(DownloadError::Other(anyhow!(
             "remote-storage-download-pre-rename failpoint triggered"
@@ -195,6 +224,140 @@ pub async fn download_layer_file<'a>(
 
 const TEMP_DOWNLOAD_EXTENSION: &str = "temp_download";
 
+/// Suffix for downloaded layer files which failed verification. These are kept around
+/// for investigation and never loaded.
+pub(crate) const QUARANTINE_EXTENSION: &str = "corrupt";
+
+pub fn is_quarantined_layer_file(path: &Utf8Path) -> bool {
+    path.extension() == Some(QUARANTINE_EXTENSION)
+}
+
+/// Sanity checks for a freshly downloaded layer file, done before it is renamed into
+/// place so that corruption is detected at download time instead of on first read.
+///
+/// Checks the file size, that the summary header describes the expected layer, and,
+/// if `verify_checksums` is set and the file has them, every block checksum.
+async fn verify_downloaded_layer(
+    path: &Utf8Path,
+    layer_file_name: &LayerFileName,
+    tenant_shard_id: TenantShardId,
+    timeline_id: TimelineId,
+    expected_size: u64,
+    verify_checksums: bool,
+) -> anyhow::Result<()> {
+    let mut file = File::open(path).await.context("open downloaded file")?;
+    let actual_size = file.metadata().await.context("stat downloaded file")?.len();
+    anyhow::ensure!(
+        actual_size == expected_size,
+        "file size {actual_size} does not match expected size {expected_size}"
+    );
+    anyhow::ensure!(
+        actual_size >= PAGE_SZ as u64 && actual_size % PAGE_SZ as u64 == 0,
+        "file size {actual_size} is not a positive multiple of the block size"
+    );
+    let file_blocks = actual_size / PAGE_SZ as u64;
+
+    let mut buf = vec![0u8; PAGE_SZ];
+    file.read_exact(&mut buf)
+        .await
+        .context("read summary block")?;
+
+    let (index_start_blk, checksums_start_blk) = match layer_file_name {
+        LayerFileName::Delta(name) => {
+            let summary =
+                delta_layer::Summary::des_prefix(&buf).context("deserialize delta summary")?;
+            anyhow::ensure!(
+                summary.magic == DELTA_FILE_MAGIC,
+                "bad magic {:#x}",
+                summary.magic
+            );
+            anyhow::ensure!(
+                summary.format_version <= STORAGE_FORMAT_VERSION,
+                "unsupported format version {}",
+                summary.format_version
+            );
+            anyhow::ensure!(
+                summary.tenant_id == tenant_shard_id.tenant_id
+                    && summary.timeline_id == timeline_id
+                    && summary.key_range == name.key_range
+                    && summary.lsn_range == name.lsn_range,
+                "summary does not match the layer file name: {summary:?}"
+            );
+            (summary.index_start_blk, summary.checksums_start_blk)
+        }
+        LayerFileName::Image(name) => {
+            let summary =
+                image_layer::Summary::des_prefix(&buf).context("deserialize image summary")?;
+            anyhow::ensure!(
+                summary.magic == IMAGE_FILE_MAGIC,
+                "bad magic {:#x}",
+                summary.magic
+            );
+            anyhow::ensure!(
+                summary.format_version <= STORAGE_FORMAT_VERSION,
+                "unsupported format version {}",
+                summary.format_version
+            );
+            anyhow::ensure!(
+                summary.tenant_id == tenant_shard_id.tenant_id
+                    && summary.timeline_id == timeline_id
+                    && summary.key_range == name.key_range
+                    && summary.lsn == name.lsn,
+                "summary does not match the layer file name: {summary:?}"
+            );
+            (summary.index_start_blk, summary.checksums_start_blk)
+        }
+    };
+
+    anyhow::ensure!(
+        index_start_blk >= 1 && (index_start_blk as u64) < file_blocks,
+        "index start block {index_start_blk} is outside of the file ({file_blocks} blocks)"
+    );
+
+    if checksums_start_blk == 0 || !verify_checksums {
+        return Ok(());
+    }
+
+    // The table holds one u32 for each block from 1 up to the table itself.
+    let checksummed_blocks = checksums_start_blk as u64 - 1;
+    let table_blocks = (checksummed_blocks * 4 + PAGE_SZ as u64 - 1) / PAGE_SZ as u64;
+    anyhow::ensure!(
+        checksums_start_blk > index_start_blk
+            && checksums_start_blk as u64 + table_blocks <= file_blocks,
+        "checksum table at block {checksums_start_blk} is outside of the file ({file_blocks} blocks)"
+    );
+
+    file.seek(std::io::SeekFrom::Start(
+        checksums_start_blk as u64 * PAGE_SZ as u64,
+    ))
+    .await?;
+    let mut table = vec![0u8; (table_blocks as usize) * PAGE_SZ];
+    file.read_exact(&mut table)
+        .await
+        .context("read checksum table")?;
+
+    file.seek(std::io::SeekFrom::Start(PAGE_SZ as u64)).await?;
+    let mut file = tokio::io::BufReader::with_capacity(super::BUFFER_SIZE, file);
+    for (i, expected) in table
+        .chunks_exact(4)
+        .take(checksummed_blocks as usize)
+        .enumerate()
+    {
+        let expected = u32::from_be_bytes(expected.try_into().unwrap());
+        file.read_exact(&mut buf)
+            .await
+            .with_context(|| format!("read block {}", i + 1))?;
+        let actual = crc32c::crc32c(&buf);
+        anyhow::ensure!(
+            actual == expected,
+            "checksum mismatch in block {}: expected {expected:#010x}, actual {actual:#010x}",
+            i + 1
+        );
+    }
+
+    Ok(())
+}
+
 pub fn is_temp_download_file(path: &Utf8Path) -> bool {
     let extension = path.extension();
     match extension {
@@ -96,6 +96,10 @@ pub(crate) struct SecondaryTenant {
     tenant_conf: std::sync::Mutex<TenantConfOpt>,
 
     detail: std::sync::Mutex<SecondaryDetail>,
+
+    /// Updated by the downloader as it goes through the layers of the heatmap, for the
+    /// storage controller to tell when the location is warm enough to attach.
+    progress: std::sync::Mutex<models::SecondaryProgress>,
 }
 
 impl SecondaryTenant {
@@ -118,6 +122,7 @@ impl SecondaryTenant {
             tenant_conf: std::sync::Mutex::new(tenant_conf),
 
             detail: std::sync::Mutex::new(SecondaryDetail::new(config.clone())),
+            progress: std::sync::Mutex::default(),
         })
     }
 
@@ -160,6 +165,10 @@ impl SecondaryTenant {
         &self.tenant_shard_id
     }
 
+    pub(crate) fn progress(&self) -> models::SecondaryProgress {
+        self.progress.lock().unwrap().clone()
+    }
+
     pub(crate) fn get_layers_for_eviction(self: &Arc<Self>) -> (DiskUsageEvictionInfo, usize) {
         self.detail.lock().unwrap().get_layers_for_eviction(self)
     }
@@ -39,7 +39,7 @@ use crate::tenant::{
 
 use chrono::format::{DelayedFormat, StrftimeItems};
 use futures::Future;
-use pageserver_api::shard::TenantShardId;
+use pageserver_api::{models::SecondaryProgress, shard::TenantShardId};
 use rand::Rng;
 use remote_storage::{DownloadError, GenericRemoteStorage};
 
@@ -495,6 +495,15 @@ impl<'a> TenantDownloader<'a> {
 
         tracing::debug!("Wrote local heatmap to {}", heatmap_path);
 
+        // Start counting the layers we hold from scratch: the heatmap may have changed
+        // since the previous download.
+        let layers = || heatmap.timelines.iter().flat_map(|t| &t.layers);
+        *self.secondary_state.progress.lock().unwrap() = SecondaryProgress {
+            layers_total: layers().count(),
+            bytes_total: layers().map(|l| l.metadata.file_size).sum(),
+            ..Default::default()
+        };
+
         // Download the layers in the heatmap
         for timeline in heatmap.timelines {
             if self.secondary_state.cancel.is_cancelled() {
@@ -515,6 +524,12 @@ impl<'a> TenantDownloader<'a> {
         Ok(())
     }
 
+    fn layer_downloaded(&self, size: u64) {
+        let mut progress = self.secondary_state.progress.lock().unwrap();
+        progress.layers_downloaded += 1;
+        progress.bytes_downloaded += size;
+    }
+
     async fn download_heatmap(&self) -> Result<Vec<u8>, UpdateError> {
         debug_assert_current_span_has_tenant_id();
         let tenant_shard_id = self.secondary_state.get_tenant_shard_id();
@@ -620,6 +635,7 @@ impl<'a> TenantDownloader<'a> {
             // Existing on-disk layers: just update their access time.
             if let Some(on_disk) = timeline_state.on_disk_layers.get(&layer.name) {
                 tracing::debug!("Layer {} is already on disk", layer.name);
+                self.layer_downloaded(layer.metadata.file_size);
                 if on_disk.metadata != LayerFileMetadata::from(&layer.metadata)
                     || on_disk.access_time != layer.access_time
                 {
@@ -700,6 +716,8 @@ impl<'a> TenantDownloader<'a> {
                 tokio::fs::remove_file(&local_path)
                     .await
                     .or_else(fs_ext::ignore_not_found)?;
+            } else {
+                self.layer_downloaded(downloaded_bytes);
             }
 
             SECONDARY_MODE.download_layer.inc();
@@ -257,6 +257,15 @@ impl LayerAccessStats {
         ret
     }
 
+    /// Get the total number of accesses and the latest access timestamp, not counting
+    /// residence events. Unlike [`Self::as_api_model`], these are never reset.
+    pub(crate) fn access_summary(&self) -> (u64, Option<SystemTime>) {
+        let locked = self.0.lock().unwrap();
+        let inner = &locked.for_eviction_policy;
+        let count = inner.count_by_access_kind.values().sum();
+        (count, inner.last_accesses.recent().map(|a| a.when))
+    }
+
     /// Get the latest access timestamp, falling back to latest residence event, further falling
     /// back to `SystemTime::now` for a usable timestamp for eviction.
     pub(crate) fn latest_activity_or_now(&self) -> SystemTime {
@@ -27,6 +27,9 @@
 //! "values" part.  The actual page images and WAL records are stored in the
 //! "values" part.
 //!
+//! The index is followed by a table of CRC32C checksums of every "values" and
+//! "index" block, see [`Summary::checksums_start_blk`].
+//!
 use crate::config::PageServerConf;
 use crate::context::{PageContentKind, RequestContext, RequestContextBuilder};
 use crate::page_cache::PAGE_SZ;
@@ -82,6 +85,13 @@ pub struct Summary {
     pub index_start_blk: u32,
     /// Block within the 'index', where the B-tree root page is stored
     pub index_root_blk: u32,
+    /// Block number where the table of per-block CRC32C checksums begins, or 0 if
+    /// the file was written without checksums. The table covers all blocks from 1
+    /// up to (not including) this one.
+    ///
+    /// Files written before this field existed have zeros here, because the rest
+    /// of the summary block is zero-filled.
+    pub checksums_start_blk: u32,
 }
 
 impl From<&DeltaLayer> for Summary {
@@ -113,6 +123,7 @@ impl Summary {
 
             index_start_blk: 0,
             index_root_blk: 0,
+            checksums_start_blk: 0,
         }
     }
 }
@@ -291,7 +302,7 @@ impl DeltaLayer {
     async fn load_inner(&self, ctx: &RequestContext) -> Result<Arc<DeltaLayerInner>> {
         let path = self.path();
 
-        let loaded = DeltaLayerInner::load(&path, None, ctx)
+        let loaded = DeltaLayerInner::load(&path, None, true, ctx)
             .await
             .and_then(|res| res)?;
 
@@ -450,10 +461,19 @@ impl DeltaLayerWriterInner {
     ///
     /// Finish writing the delta layer.
     ///
-    async fn finish(self, key_end: Key, timeline: &Arc<Timeline>) -> anyhow::Result<ResidentLayer> {
+    async fn finish(
+        mut self,
+        key_end: Key,
+        timeline: &Arc<Timeline>,
+    ) -> anyhow::Result<ResidentLayer> {
         let index_start_blk =
             ((self.blob_writer.size() + PAGE_SZ as u64 - 1) / PAGE_SZ as u64) as u32;
 
+        // The gap between the last value and the index reads back as zeros.
+        let mut checksums = self.blob_writer.take_checksums();
+        checksums.pad_to_block_boundary();
+        debug_assert_eq!(checksums.len() + 1, index_start_blk as usize);
+
         let mut file = self.blob_writer.into_inner().await?;
 
         // Write out the index
@@ -461,8 +481,13 @@ impl DeltaLayerWriterInner {
         file.seek(SeekFrom::Start(index_start_blk as u64 * PAGE_SZ as u64))
             .await?;
         for buf in block_buf.blocks {
+            checksums.update(buf.as_ref());
             file.write_all(buf.as_ref()).await?;
         }
+
+        // Write out the checksums of all the blocks above, right after the index
+        let checksums_start_blk = checksums.len() as u32 + 1;
+        file.write_all(&checksums.write_table()).await?;
         assert!(self.lsn_range.start < self.lsn_range.end);
         // Fill in the summary on blk 0
         let summary = Summary {
@@ -474,6 +499,7 @@ impl DeltaLayerWriterInner {
             lsn_range: self.lsn_range.clone(),
             index_start_blk,
             index_root_blk,
+            checksums_start_blk,
         };
 
         let mut buf = smallvec::SmallVec::<[u8; PAGE_SZ]>::new();
@@ -702,13 +728,14 @@ impl DeltaLayerInner {
     pub(super) async fn load(
         path: &Utf8Path,
         summary: Option<Summary>,
+        verify_checksums: bool,
         ctx: &RequestContext,
     ) -> Result<Result<Self, anyhow::Error>, anyhow::Error> {
         let file = match VirtualFile::open(path).await {
             Ok(file) => file,
             Err(e) => return Ok(Err(anyhow::Error::new(e).context("open layer file"))),
         };
-        let file = FileBlockReader::new(file);
+        let mut file = FileBlockReader::new(file);
 
         let summary_blk = match file.read_blk(0, ctx).await {
             Ok(blk) => blk,
@@ -723,6 +750,7 @@ impl DeltaLayerInner {
             // production code path
             expected_summary.index_start_blk = actual_summary.index_start_blk;
             expected_summary.index_root_blk = actual_summary.index_root_blk;
+            expected_summary.checksums_start_blk = actual_summary.checksums_start_blk;
             if actual_summary != expected_summary {
                 bail!(
                     "in-file summary does not match expected summary. actual = {:?} expected = {:?}",
@@ -732,6 +760,16 @@ impl DeltaLayerInner {
             }
         }
 
+        drop(summary_blk);
+        if verify_checksums && actual_summary.checksums_start_blk != 0 {
+            if let Err(e) = file
+                .load_checksums(actual_summary.checksums_start_blk, ctx)
+                .await
+            {
+                return Ok(Err(anyhow::Error::new(e).context("read checksums")));
+            }
+        }
+
         Ok(Ok(DeltaLayerInner {
             file,
             index_start_blk: actual_summary.index_start_blk,
@@ -23,6 +23,9 @@
 //! layer, and offsets to the other parts. The "index" is a B-tree,
 //! mapping from Key to an offset in the "values" part.  The
 //! actual page images are stored in the "values" part.
+//!
+//! The index is followed by a table of CRC32C checksums of every "values" and
+//! "index" block, see [`Summary::checksums_start_blk`].
 use crate::config::PageServerConf;
 use crate::context::{PageContentKind, RequestContext, RequestContextBuilder};
 use crate::page_cache::PAGE_SZ;
@@ -82,6 +85,13 @@ pub struct Summary {
     pub index_start_blk: u32,
     /// Block within the 'index', where the B-tree root page is stored
     pub index_root_blk: u32,
+    /// Block number where the table of per-block CRC32C checksums begins, or 0 if
+    /// the file was written without checksums. The table covers all blocks from 1
+    /// up to (not including) this one.
+    ///
+    /// Files written before this field existed have zeros here, because the rest
+    /// of the summary block is zero-filled.
+    pub checksums_start_blk: u32,
     // the 'values' part starts after the summary header, on block 1.
 }
 
@@ -113,6 +123,7 @@ impl Summary {
 
             index_start_blk: 0,
             index_root_blk: 0,
+            checksums_start_blk: 0,
         }
     }
 }
@@ -250,7 +261,7 @@ impl ImageLayer {
     async fn load_inner(&self, ctx: &RequestContext) -> Result<ImageLayerInner> {
         let path = self.path();
 
-        let loaded = ImageLayerInner::load(&path, self.desc.image_layer_lsn(), None, ctx)
+        let loaded = ImageLayerInner::load(&path, self.desc.image_layer_lsn(), None, true, ctx)
             .await
             .and_then(|res| res)?;
 
@@ -365,13 +376,14 @@ impl ImageLayerInner {
         path: &Utf8Path,
         lsn: Lsn,
         summary: Option<Summary>,
+        verify_checksums: bool,
         ctx: &RequestContext,
     ) -> Result<Result<Self, anyhow::Error>, anyhow::Error> {
         let file = match VirtualFile::open(path).await {
             Ok(file) => file,
             Err(e) => return Ok(Err(anyhow::Error::new(e).context("open layer file"))),
         };
-        let file = FileBlockReader::new(file);
+        let mut file = FileBlockReader::new(file);
         let summary_blk = match file.read_blk(0, ctx).await {
             Ok(blk) => blk,
             Err(e) => return Ok(Err(anyhow::Error::new(e).context("read first block"))),
@@ -388,6 +400,7 @@ impl ImageLayerInner {
             // production code path
             expected_summary.index_start_blk = actual_summary.index_start_blk;
             expected_summary.index_root_blk = actual_summary.index_root_blk;
+            expected_summary.checksums_start_blk = actual_summary.checksums_start_blk;
 
             if actual_summary != expected_summary {
                 bail!(
@@ -398,6 +411,16 @@ impl ImageLayerInner {
             }
         }
 
+        drop(summary_blk);
+        if verify_checksums && actual_summary.checksums_start_blk != 0 {
+            if let Err(e) = file
+                .load_checksums(actual_summary.checksums_start_blk, ctx)
+                .await
+            {
+                return Ok(Err(anyhow::Error::new(e).context("read checksums")));
+            }
+        }
+
         Ok(Ok(ImageLayerInner {
             index_start_blk: actual_summary.index_start_blk,
             index_root_blk: actual_summary.index_root_blk,
@@ -544,10 +567,15 @@ impl ImageLayerWriterInner {
     ///
     /// Finish writing the image layer.
     ///
-    async fn finish(self, timeline: &Arc<Timeline>) -> anyhow::Result<ResidentLayer> {
+    async fn finish(mut self, timeline: &Arc<Timeline>) -> anyhow::Result<ResidentLayer> {
         let index_start_blk =
             ((self.blob_writer.size() + PAGE_SZ as u64 - 1) / PAGE_SZ as u64) as u32;
 
+        // The gap between the last value and the index reads back as zeros.
+        let mut checksums = self.blob_writer.take_checksums();
+        checksums.pad_to_block_boundary();
+        debug_assert_eq!(checksums.len() + 1, index_start_blk as usize);
+
         let mut file = self.blob_writer.into_inner();
 
         // Write out the index
@@ -555,9 +583,14 @@ impl ImageLayerWriterInner {
             .await?;
         let (index_root_blk, block_buf) = self.tree.finish()?;
         for buf in block_buf.blocks {
+            checksums.update(buf.as_ref());
             file.write_all(buf.as_ref()).await?;
         }
 
+        // Write out the checksums of all the blocks above, right after the index
+        let checksums_start_blk = checksums.len() as u32 + 1;
+        file.write_all(&checksums.write_table()).await?;
+
         // Fill in the summary on blk 0
         let summary = Summary {
             magic: IMAGE_FILE_MAGIC,
@@ -568,6 +601,7 @@ impl ImageLayerWriterInner {
             lsn: self.lsn,
             index_start_blk,
             index_root_blk,
+            checksums_start_blk,
         };
 
         let mut buf = smallvec::SmallVec::<[u8; PAGE_SZ]>::new();
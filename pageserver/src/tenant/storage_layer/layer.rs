@@ -1,7 +1,8 @@
 use anyhow::Context;
 use camino::{Utf8Path, Utf8PathBuf};
 use pageserver_api::models::{
-    HistoricLayerInfo, LayerAccessKind, LayerResidenceEventReason, LayerResidenceStatus,
+    HistoricLayerInfo, LayerAccessKind, LayerCreationReason, LayerResidenceEventReason,
+    LayerResidenceStatus, LayerResidencyInfo,
 };
 use pageserver_api::shard::ShardIndex;
 use std::ops::Range;
@@ -16,7 +17,7 @@ use crate::config::PageServerConf;
 use crate::context::RequestContext;
 use crate::repository::Key;
 use crate::span::debug_assert_current_span_has_tenant_and_timeline_id;
-use crate::tenant::{remote_timeline_client::LayerFileMetadata, Timeline};
+use crate::tenant::{layer_map::LayerMap, remote_timeline_client::LayerFileMetadata, Timeline};
 
 use super::delta_layer::{self, DeltaEntry};
 use super::image_layer;
@@ -255,11 +256,19 @@ impl Layer {
             ensure!(lsn_range.end >= self.layer_desc().image_layer_lsn());
         }
 
-        layer
+        let res = layer
             .get_value_reconstruct_data(key, lsn_range, reconstruct_data, &self.0, ctx)
             .instrument(tracing::debug_span!("get_value_reconstruct_data", layer=%self))
-            .await
-            .with_context(|| format!("get_value_reconstruct_data for layer {self}"))
+            .await;
+
+        if let Err(e) = &res {
+            if crate::tenant::block_io::is_checksum_mismatch(e) {
+                drop(layer);
+                self.0.evict_corrupted();
+            }
+        }
+
+        res.with_context(|| format!("get_value_reconstruct_data for layer {self}"))
     }
 
     /// Download the layer if evicted.
@@ -308,6 +317,10 @@ impl Layer {
         &self.0.access_stats
     }
 
+    pub(crate) fn residency_info(&self) -> LayerResidencyInfo {
+        self.0.residency_info()
+    }
+
     pub(crate) fn local_path(&self) -> &Utf8Path {
         &self.0.path
     }
@@ -649,6 +662,32 @@ impl LayerInner {
         }
     }
 
+    /// Called after reading the local file failed a checksum: evict it once the current
+    /// readers are done, so that the next access downloads a fresh copy from remote storage.
+    ///
+    /// Does not wait for the eviction, and a later access can still cancel it; in that case
+    /// the corruption will be hit again and we end up here again.
+    fn evict_corrupted(&self) {
+        if !self.have_remote_client {
+            tracing::error!(layer=%self, "layer file is corrupted, but there is no remote storage to download it again from");
+            return;
+        }
+
+        let strong = match self.inner.get() {
+            Some(mut either) => {
+                self.wanted_evicted.store(true, Ordering::Relaxed);
+                either.downgrade()
+            }
+            None => None,
+        };
+
+        if strong.is_some() {
+            tracing::warn!(layer=%self, "evicting layer with corrupted local file, it will be downloaded again on next access");
+            drop(strong);
+            LAYER_IMPL_METRICS.inc_started_evictions();
+        }
+    }
+
     /// Cancellation safe.
     async fn get_or_maybe_download(
         self: &Arc<Self>,
@@ -1022,6 +1061,36 @@ impl LayerInner {
         }
     }
 
+    fn residency_info(&self) -> LayerResidencyInfo {
+        // same inaccuracy as in `info`: a download or eviction could be in progress
+        let residence = if self.inner.get().is_none() {
+            LayerResidenceStatus::Evicted
+        } else {
+            LayerResidenceStatus::Resident
+        };
+
+        let creation_reason = if !self.desc.is_delta {
+            LayerCreationReason::Image
+        } else if LayerMap::is_l0(&self.desc) {
+            LayerCreationReason::Flush
+        } else {
+            LayerCreationReason::Compaction
+        };
+
+        let (access_count, last_access) = self.access_stats.access_summary();
+
+        LayerResidencyInfo {
+            layer_file_name: self.desc.filename().file_name(),
+            layer_file_size: self.desc.file_size,
+            residence,
+            creation_reason,
+            last_access_millis_since_epoch: last_access
+                .as_ref()
+                .map(super::system_time_to_millis_since_epoch),
+            access_count,
+        }
+    }
+
     /// `DownloadedLayer` is being dropped, so it calls this method.
     fn on_downloaded_layer_drop(self: Arc<LayerInner>, version: usize) {
         let delete = self.wanted_deleted.load(Ordering::Acquire);
@@ -1274,9 +1343,14 @@ impl DownloadedLayer {
                     owner.desc.key_range.clone(),
                     owner.desc.lsn_range.clone(),
                 ));
-                delta_layer::DeltaLayerInner::load(&owner.path, summary, ctx)
-                    .await
-                    .map(|res| res.map(LayerKind::Delta))
+                delta_layer::DeltaLayerInner::load(
+                    &owner.path,
+                    summary,
+                    owner.conf.verify_layer_checksums,
+                    ctx,
+                )
+                .await
+                .map(|res| res.map(LayerKind::Delta))
             } else {
                 let lsn = owner.desc.image_layer_lsn();
                 let summary = Some(image_layer::Summary::expected(
@@ -1285,9 +1359,15 @@ impl DownloadedLayer {
                     owner.desc.key_range.clone(),
                     lsn,
                 ));
-                image_layer::ImageLayerInner::load(&owner.path, lsn, summary, ctx)
-                    .await
-                    .map(|res| res.map(LayerKind::Image))
+                image_layer::ImageLayerInner::load(
+                    &owner.path,
+                    lsn,
+                    summary,
+                    owner.conf.verify_layer_checksums,
+                    ctx,
+                )
+                .await
+                .map(|res| res.map(LayerKind::Image))
             };
 
             match res {
@@ -18,7 +18,7 @@ use pageserver_api::{
     keyspace::{key_range_size, KeySpaceAccum},
     models::{
         DownloadRemoteLayersTaskInfo, DownloadRemoteLayersTaskSpawnRequest, EvictionPolicy,
-        LayerMapInfo, TimelineState,
+        GcRetainedSize, LayerMapInfo, LayerResidencyInfo, TimelineGcInfo, TimelineState,
     },
     reltag::BlockNumber,
     shard::{ShardIdentity, TenantShardId},
@@ -283,6 +283,10 @@ pub struct Timeline {
     // garbage collecting data that is still needed by the child timelines.
     pub gc_info: std::sync::RwLock<GcInfo>,
 
+    /// LSNs which GC must keep readable until the given time, e.g. for static
+    /// computes, see [`Timeline::make_lsn_lease`].
+    lsn_leases: std::sync::Mutex<LsnLeases>,
+
     // It may change across major versions so for simplicity
     // keep it after running initdb for a timeline.
     // It is needed in checks when we want to error on some operations
@@ -382,6 +386,36 @@ pub struct GcInfo {
     /// This is calculated by finding a number such that a record is needed for PITR
     /// if only if its LSN is larger than 'pitr_cutoff'.
     pub pitr_cutoff: Lsn,
+
+    /// How much layer data is kept because of each of the cutoffs above, as of
+    /// the last time they were updated.
+    pub retained: GcRetainedSize,
+}
+
+/// How long an LSN lease lasts if the request doesn't say.
+pub(crate) const DEFAULT_LSN_LEASE_LENGTH: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Default)]
+struct LsnLeases {
+    /// Leased LSNs and until when they are leased.
+    leases: BTreeMap<Lsn, SystemTime>,
+    /// The cutoff of the last GC, which may not be applied yet, and which new
+    /// leases can't be below.
+    planned_gc_cutoff: Lsn,
+}
+
+impl LsnLeases {
+    /// The lowest leased LSN, after dropping the expired leases.
+    fn min_leased_lsn(&mut self, now: SystemTime) -> Option<Lsn> {
+        self.leases.retain(|_, valid_until| *valid_until > now);
+        self.leases.keys().next().copied()
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum LsnLeaseError {
+    #[error("LSN {lsn} is below the GC cutoff {gc_cutoff}")]
+    GarbageCollected { lsn: Lsn, gc_cutoff: Lsn },
 }
 
 /// An error happened in a get() operation.
@@ -1278,6 +1312,15 @@ impl Timeline {
         }
     }
 
+    pub(crate) async fn layer_residency_info(&self) -> Vec<LayerResidencyInfo> {
+        let guard = self.layers.read().await;
+        guard
+            .layer_map()
+            .iter_historic_layers()
+            .map(|desc| guard.get_from_desc(&desc).residency_info())
+            .collect()
+    }
+
     #[instrument(skip_all, fields(tenant_id = %self.tenant_shard_id.tenant_id, shard_id = %self.tenant_shard_id.shard_slug(), timeline_id = %self.timeline_id))]
     pub(crate) async fn download_layer(
         &self,
@@ -1507,7 +1550,9 @@ impl Timeline {
                     retain_lsns: Vec::new(),
                     horizon_cutoff: Lsn(0),
                     pitr_cutoff: Lsn(0),
+                    retained: GcRetainedSize::default(),
                 }),
+                lsn_leases: std::sync::Mutex::new(LsnLeases::default()),
 
                 latest_gc_cutoff_lsn: Rcu::new(metadata.latest_gc_cutoff_lsn()),
                 initdb_lsn: metadata.initdb_lsn(),
@@ -1649,6 +1694,7 @@ impl Timeline {
                 auth_token: crate::config::SAFEKEEPER_AUTH_TOKEN.get().cloned(),
                 availability_zone: self.conf.availability_zone.clone(),
                 ingest_batch_size: self.conf.ingest_batch_size,
+                static_safekeepers: self.conf.static_safekeepers.clone(),
             },
             broker_client,
             ctx,
@@ -1708,6 +1754,10 @@ impl Timeline {
                         Discovered::Metadata | Discovered::IgnoredBackup => {
                             continue;
                         }
+                        Discovered::Quarantined(file_name) => {
+                            warn!("found quarantined layer file {file_name}, leaving it in place");
+                            continue;
+                        }
                         Discovered::Unknown(file_name) => {
                             // we will later error if there are any
                             unrecognized_files.push(file_name);
@@ -3126,6 +3176,9 @@ impl Timeline {
             }
         }
         let keyspace = self.collect_keyspace(lsn, ctx).await?;
+        // On sharded tenants, only partition the stripes that this shard stores, so
+        // that image layers are sized by local data and never cover foreign pages.
+        let keyspace = self.shard_identity.filter_keyspace(&keyspace);
         let partitioning = keyspace.partition(partition_size);
 
         let mut partitioning_guard = self.partitioning.lock().unwrap();
@@ -3258,19 +3311,19 @@ impl Timeline {
                 for range in &partition.ranges {
                     let mut key = range.start;
                     while key < range.end {
+                        // The partitioning is already filtered by shard, this is only a safety net.
                         if self.shard_identity.is_key_disposable(&key) {
                             debug!(
                                 "Dropping key {} during compaction (it belongs on shard {:?})",
                                 key,
                                 self.shard_identity.get_shard_number(&key)
                             );
-                            key = key.next();
-                            continue;
+                        } else {
+                            key_request_accum.add_key(key);
                         }
 
-                        key_request_accum.add_key(key);
                         if key_request_accum.size() >= Timeline::MAX_GET_VECTORED_KEYS
-                            || key.next() == range.end
+                            || (key.next() == range.end && key_request_accum.size() > 0)
                         {
                             let results = self
                                 .get_vectored(
@@ -3683,6 +3736,11 @@ impl Timeline {
         stats.read_lock_held_key_sort_micros = stats.read_lock_held_prerequisites_micros.till_now();
 
         for &DeltaEntry { key: next_key, .. } in all_keys.iter() {
+            // Keys that belong to other shards will be dropped below, so the gaps
+            // they leave are holes as far as this shard is concerned.
+            if self.shard_identity.is_key_disposable(&next_key) {
+                continue;
+            }
             if let Some(prev_key) = prev {
                 // just first fast filter
                 if next_key.to_i128() - prev_key.to_i128() >= min_hole_range {
@@ -4140,16 +4198,108 @@ impl Timeline {
             cutoff_horizon
         };
 
+        let leased_lsn = self
+            .lsn_leases
+            .lock()
+            .unwrap()
+            .min_leased_lsn(SystemTime::now());
+        let retained = self
+            .gc_retained_size(cutoff_horizon, pitr_cutoff, leased_lsn, &retain_lsns)
+            .await;
+
         // Grab the lock and update the values
         *self.gc_info.write().unwrap() = GcInfo {
             retain_lsns,
             horizon_cutoff: cutoff_horizon,
             pitr_cutoff,
+            retained,
         };
 
         Ok(())
     }
 
+    /// Sum up the size of the historic layers by the reason GC has to keep them,
+    /// following the same rules as [`Self::gc_timeline`], with the cutoffs
+    /// lowered to `leased_lsn` as [`Self::gc`] does.
+    async fn gc_retained_size(
+        &self,
+        horizon_cutoff: Lsn,
+        pitr_cutoff: Lsn,
+        leased_lsn: Option<Lsn>,
+        retain_lsns: &[Lsn],
+    ) -> GcRetainedSize {
+        let horizon_cutoff = min(horizon_cutoff, self.get_disk_consistent_lsn());
+        let leased_cutoff = leased_lsn.unwrap_or(Lsn::MAX);
+        let new_gc_cutoff = Lsn::min(Lsn::min(horizon_cutoff, pitr_cutoff), leased_cutoff);
+
+        let mut retained = GcRetainedSize::default();
+        let guard = self.layers.read().await;
+        let layers = guard.layer_map();
+        for l in layers.iter_historic_layers() {
+            let lsn_range = l.get_lsn_range();
+            let bytes = if lsn_range.end > horizon_cutoff {
+                &mut retained.horizon_bytes
+            } else if lsn_range.end > pitr_cutoff {
+                &mut retained.pitr_bytes
+            } else if lsn_range.end > leased_cutoff {
+                &mut retained.leased_bytes
+            } else if retain_lsns.iter().any(|lsn| &lsn_range.start <= lsn) {
+                &mut retained.branches_bytes
+            } else if !layers
+                .image_layer_exists(&l.get_key_range(), &(lsn_range.end..new_gc_cutoff))
+            {
+                &mut retained.latest_bytes
+            } else {
+                &mut retained.collectable_bytes
+            };
+            *bytes += l.file_size();
+        }
+        retained
+    }
+
+    pub(crate) fn gc_info_summary(&self) -> TimelineGcInfo {
+        let leased_lsns = self
+            .lsn_leases
+            .lock()
+            .unwrap()
+            .leases
+            .iter()
+            .filter(|(_, valid_until)| **valid_until > SystemTime::now())
+            .map(|(lsn, _)| *lsn)
+            .collect();
+        let gc_info = self.gc_info.read().unwrap();
+        TimelineGcInfo {
+            horizon_cutoff: gc_info.horizon_cutoff,
+            pitr_cutoff: gc_info.pitr_cutoff,
+            latest_gc_cutoff_lsn: *self.get_latest_gc_cutoff_lsn(),
+            retain_lsns: gc_info.retain_lsns.clone(),
+            leased_lsns,
+            retained: gc_info.retained,
+        }
+    }
+
+    /// Keep `lsn` readable for at least `length`, by holding the GC cutoff
+    /// below it, so that a static compute can be started there. Leasing an
+    /// LSN again extends the lease. Returns until when the lease is valid.
+    pub(crate) fn make_lsn_lease(
+        &self,
+        lsn: Lsn,
+        length: Duration,
+    ) -> Result<SystemTime, LsnLeaseError> {
+        // GC plans its cutoff while holding the lock, so a GC in progress
+        // either sees this lease or already planned a cutoff we check against.
+        let mut leases = self.lsn_leases.lock().unwrap();
+        let gc_cutoff = Lsn::max(*self.get_latest_gc_cutoff_lsn(), leases.planned_gc_cutoff);
+        if lsn < gc_cutoff {
+            return Err(LsnLeaseError::GarbageCollected { lsn, gc_cutoff });
+        }
+
+        let valid_until = SystemTime::now() + length;
+        let lease = leases.leases.entry(lsn).or_insert(valid_until);
+        *lease = (*lease).max(valid_until);
+        Ok(*lease)
+    }
+
     /// Garbage collect layer files on a timeline that are no longer needed.
     ///
     /// Currently, we don't make any attempt at removing unneeded page versions
@@ -4173,7 +4323,7 @@ impl Timeline {
             anyhow::bail!("timeline is Stopping");
         }
 
-        let (horizon_cutoff, pitr_cutoff, retain_lsns) = {
+        let (mut horizon_cutoff, mut pitr_cutoff, retain_lsns) = {
             let gc_info = self.gc_info.read().unwrap();
 
             let horizon_cutoff = min(gc_info.horizon_cutoff, self.get_disk_consistent_lsn());
@@ -4182,7 +4332,18 @@ impl Timeline {
             (horizon_cutoff, pitr_cutoff, retain_lsns)
         };
 
-        let new_gc_cutoff = Lsn::min(horizon_cutoff, pitr_cutoff);
+        // Unlike the branch points, a leased LSN must stay readable by
+        // computes, so everything newer than it is kept.
+        let new_gc_cutoff = {
+            let mut leases = self.lsn_leases.lock().unwrap();
+            if let Some(leased_lsn) = leases.min_leased_lsn(SystemTime::now()) {
+                horizon_cutoff = min(horizon_cutoff, leased_lsn);
+                pitr_cutoff = min(pitr_cutoff, leased_lsn);
+            }
+            let new_gc_cutoff = Lsn::min(horizon_cutoff, pitr_cutoff);
+            leases.planned_gc_cutoff = Lsn::max(leases.planned_gc_cutoff, new_gc_cutoff);
+            new_gc_cutoff
+        };
 
         let res = self
             .gc_timeline(horizon_cutoff, pitr_cutoff, retain_lsns, new_gc_cutoff)
@@ -4341,10 +4502,11 @@ impl Timeline {
             );
             layers_to_remove.push(l);
         }
-        self.wanted_image_layers
-            .lock()
-            .unwrap()
-            .replace((new_gc_cutoff, wanted_image_layers.to_keyspace()));
+        self.wanted_image_layers.lock().unwrap().replace((
+            new_gc_cutoff,
+            self.shard_identity
+                .filter_keyspace(&wanted_image_layers.to_keyspace()),
+        ));
 
         if !layers_to_remove.is_empty() {
             // Persist the new GC cutoff value in the metadata file, before
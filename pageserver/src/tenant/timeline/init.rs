@@ -31,6 +31,8 @@ pub(super) enum Discovered {
     Metadata,
     /// Backup file from previously future layers
     IgnoredBackup,
+    /// Downloaded layer file which failed verification, kept for investigation
+    Quarantined(String),
     /// Unrecognized, warn about these
     Unknown(String),
 }
@@ -54,6 +56,8 @@ pub(super) fn scan_timeline_dir(path: &Utf8Path) -> anyhow::Result<Vec<Discovere
                 } else if file_name.ends_with(".old") {
                     // ignore these
                     Discovered::IgnoredBackup
+                } else if remote_timeline_client::is_quarantined_layer_file(direntry.path()) {
+                    Discovered::Quarantined(file_name)
                 } else if remote_timeline_client::is_temp_download_file(direntry.path()) {
                     Discovered::TemporaryDownload(file_name)
                 } else if is_ephemeral_file(&file_name) {
@@ -59,6 +59,8 @@ pub struct WalReceiverConf {
     pub auth_token: Option<Arc<String>>,
     pub availability_zone: Option<String>,
     pub ingest_batch_size: u64,
+    /// HTTP endpoints of safekeepers to poll instead of subscribing to the broker.
+    pub static_safekeepers: Vec<String>,
 }
 
 pub struct WalReceiver {
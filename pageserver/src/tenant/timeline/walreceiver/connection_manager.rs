@@ -8,6 +8,8 @@
 //! After every connection or storage broker update fetched, the state gets updated correspondingly and rechecked for the new conneciton leader,
 //! then a (re)connection happens, if necessary.
 //! Only WAL streaming task expects to be finished, other loops (storage broker, connection management) never exit unless cancelled explicitly via the dedicated channel.
+//!
+//! If static safekeepers list is configured, the broker is not used: the same timeline state is polled from each safekeeper over HTTP.
 
 use std::{collections::HashMap, num::NonZeroU64, ops::ControlFlow, sync::Arc, time::Duration};
 
@@ -17,17 +19,20 @@ use crate::metrics::{
     WALRECEIVER_ACTIVE_MANAGERS, WALRECEIVER_BROKER_UPDATES, WALRECEIVER_CANDIDATES_ADDED,
     WALRECEIVER_CANDIDATES_REMOVED, WALRECEIVER_SWITCHES,
 };
-use crate::task_mgr::{shutdown_token, TaskKind};
+use crate::task_mgr::{shutdown_token, TaskKind, WALRECEIVER_RUNTIME};
 use crate::tenant::{debug_assert_current_span_has_tenant_and_timeline_id, Timeline};
 use anyhow::Context;
 use chrono::{NaiveDateTime, Utc};
 use pageserver_api::models::TimelineState;
+use safekeeper_api::models::BrokerTimelineInfo;
 use storage_broker::proto::subscribe_safekeeper_info_request::SubscriptionKey;
 use storage_broker::proto::SafekeeperTimelineInfo;
 use storage_broker::proto::SubscribeSafekeeperInfoRequest;
 use storage_broker::proto::TenantTimelineId as ProtoTenantTimelineId;
 use storage_broker::{BrokerClientChannel, Code, Streaming};
 use tokio::select;
+use tokio::sync::mpsc;
+use tokio_util::sync::{CancellationToken, DropGuard};
 use tracing::*;
 
 use postgres_connection::PgConnectionConfig;
@@ -86,8 +91,20 @@ pub(super) async fn connection_manager_loop_step(
     // Subscribe to the broker updates. Stream shares underlying TCP connection
     // with other streams on this client (other connection managers). When
     // object goes out of scope, stream finishes in drop() automatically.
-    let mut broker_subscription = subscribe_for_timeline_updates(broker_client, id).await;
-    debug!("Subscribed for broker timeline updates");
+    // With static safekeepers, poll them instead; polling stops on drop.
+    let (mut broker_subscription, mut static_safekeepers_poller) =
+        if connection_manager_state.conf.static_safekeepers.is_empty() {
+            let subscription = subscribe_for_timeline_updates(broker_client, id).await;
+            debug!("Subscribed for broker timeline updates");
+            (Some(subscription), None)
+        } else {
+            let poller = StaticSafekeepersPoller::spawn(
+                connection_manager_state.conf.static_safekeepers.clone(),
+                id,
+                connection_manager_state.conf.auth_token.clone(),
+            );
+            (None, Some(poller))
+        };
 
     loop {
         let time_until_next_retry = connection_manager_state.time_until_next_retry();
@@ -133,7 +150,12 @@ pub(super) async fn connection_manager_loop_step(
             },
 
             // Got a new update from the broker
-            broker_update = broker_subscription.message() => {
+            Some(broker_update) = async {
+                match broker_subscription.as_mut() {
+                    Some(subscription) => Some(subscription.message().await),
+                    None => None,
+                }
+            } => {
                 match broker_update {
                     Ok(Some(broker_update)) => connection_manager_state.register_timeline_update(broker_update),
                     Err(status) => {
@@ -156,6 +178,14 @@ pub(super) async fn connection_manager_loop_step(
                 }
             },
 
+            // Got a new update from static safekeepers
+            Some(update) = async {
+                match static_safekeepers_poller.as_mut() {
+                    Some(poller) => poller.updates.recv().await,
+                    None => None,
+                }
+            } => connection_manager_state.register_timeline_update(update),
+
             new_event = async {
                 loop {
                     if connection_manager_state.timeline.current_state() == TimelineState::Loading {
@@ -255,6 +285,84 @@ async fn subscribe_for_timeline_updates(
     }
 }
 
+const STATIC_SAFEKEEPERS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const STATIC_SAFEKEEPERS_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Background task polling static list of safekeepers for the timeline state,
+/// which they otherwise push to the broker. Stops when dropped.
+struct StaticSafekeepersPoller {
+    updates: mpsc::Receiver<SafekeeperTimelineInfo>,
+    _cancel: DropGuard,
+}
+
+impl StaticSafekeepersPoller {
+    fn spawn(
+        safekeepers: Vec<String>,
+        id: TenantTimelineId,
+        auth_token: Option<Arc<String>>,
+    ) -> Self {
+        let (tx, updates) = mpsc::channel(safekeepers.len());
+        let cancel = CancellationToken::new();
+        let cancel_clone = cancel.clone();
+        WALRECEIVER_RUNTIME.spawn(
+            async move {
+                let client = reqwest::Client::new();
+                let mut interval = tokio::time::interval(STATIC_SAFEKEEPERS_POLL_INTERVAL);
+                let poll_loop = async {
+                    loop {
+                        interval.tick().await;
+                        for safekeeper in &safekeepers {
+                            match fetch_timeline_info(&client, safekeeper, id, auth_token.as_deref())
+                                .await
+                            {
+                                Ok(info) => {
+                                    if tx.send(info.into()).await.is_err() {
+                                        return; // connection manager is gone
+                                    }
+                                }
+                                // Safekeeper can be down or not have the timeline yet.
+                                Err(e) => debug!(
+                                    "failed to fetch timeline {id} state from safekeeper {safekeeper}: {e:#}"
+                                ),
+                            }
+                        }
+                    }
+                };
+                select! {
+                    _ = cancel_clone.cancelled() => {},
+                    _ = poll_loop => {},
+                }
+            }
+            .in_current_span(),
+        );
+        debug!("Started polling static safekeepers for timeline updates");
+        Self {
+            updates,
+            _cancel: cancel.drop_guard(),
+        }
+    }
+}
+
+async fn fetch_timeline_info(
+    client: &reqwest::Client,
+    safekeeper: &str,
+    id: TenantTimelineId,
+    auth_token: Option<&String>,
+) -> anyhow::Result<BrokerTimelineInfo> {
+    let url = format!(
+        "{}/v1/tenant/{}/timeline/{}/broker_info",
+        safekeeper.trim_end_matches('/'),
+        id.tenant_id,
+        id.timeline_id
+    );
+    let mut req = client.get(url).timeout(STATIC_SAFEKEEPERS_REQUEST_TIMEOUT);
+    if let Some(token) = auth_token {
+        req = req.bearer_auth(token);
+    }
+    let info = req.send().await?.error_for_status()?.json().await?;
+    Ok(info)
+}
+
 const WALCONNECTION_RETRY_MIN_BACKOFF_SECONDS: f64 = 0.1;
 const WALCONNECTION_RETRY_MAX_BACKOFF_SECONDS: f64 = 15.0;
 const WALCONNECTION_RETRY_BACKOFF_MULTIPLIER: f64 = 1.5;
@@ -923,6 +1031,7 @@ mod tests {
                 safekeeper_connstr: safekeeper_connstr.to_owned(),
                 http_connstr: safekeeper_connstr.to_owned(),
                 availability_zone: None,
+                ps_last_received_lsn: 0,
             },
             latest_update,
         }
@@ -1348,6 +1457,7 @@ mod tests {
                 auth_token: None,
                 availability_zone: None,
                 ingest_batch_size: 1,
+                static_safekeepers: Vec::new(),
             },
             wal_connection: None,
             wal_stream_candidates: HashMap::new(),
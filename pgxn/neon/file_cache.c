@@ -22,7 +22,10 @@
 #include "neon_pgversioncompat.h"
 
 #include "access/parallel.h"
+#include "catalog/pg_class.h"
+#include "common/hashfn.h"
 #include "funcapi.h"
+#include "hll.h"
 #include "miscadmin.h"
 #include "pagestore_client.h"
 #include "pgstat.h"
@@ -84,6 +87,8 @@ typedef struct FileCacheControl
 	uint64		writes;
 	dlist_head	lru;			/* double linked list for LRU replacement
 								 * algorithm */
+	HyperLogLogState wss_estimation;	/* estimation of the working set size,
+										 * i.e. of the distinct pages read */
 } FileCacheControl;
 
 static HTAB *lfc_hash;
@@ -231,6 +236,7 @@ lfc_shmem_startup(void)
 		lfc_ctl->misses = 0;
 		lfc_ctl->writes = 0;
 		dlist_init(&lfc_ctl->lru);
+		initSHLL(&lfc_ctl->wss_estimation);
 
 		/* Recreate file cache on restart */
 		fd = BasicOpenFile(lfc_path, O_RDWR | O_CREAT | O_TRUNC);
@@ -506,8 +512,10 @@ lfc_read(NRelFileInfo rinfo, ForkNumber forkNum, BlockNumber blkno,
 	int			chunk_offs = blkno & (BLOCKS_PER_CHUNK - 1);
 	bool		result = true;
 	uint32		hash;
+	uint32		page_hash;
 	uint64		generation;
 	uint32		entry_offset;
+	TimestampTz now;
 
 	if (lfc_maybe_disabled())	/* fast exit if file cache is disabled */
 		return false;
@@ -517,9 +525,14 @@ lfc_read(NRelFileInfo rinfo, ForkNumber forkNum, BlockNumber blkno,
 
 	CopyNRelFileInfoToBufTag(tag, rinfo);
 	tag.forkNum = forkNum;
+	tag.blockNum = blkno;
+	page_hash = hash_bytes((unsigned char *) &tag, sizeof(tag));
 	tag.blockNum = blkno & ~(BLOCKS_PER_CHUNK - 1);
 	hash = get_hash_value(lfc_hash, &tag);
 
+	/* don't make the other backends wait for the clock */
+	now = GetCurrentTimestamp();
+
 	LWLockAcquire(lfc_lock, LW_EXCLUSIVE);
 
 	if (!LFC_ENABLED())
@@ -528,6 +541,9 @@ lfc_read(NRelFileInfo rinfo, ForkNumber forkNum, BlockNumber blkno,
 		return false;
 	}
 
+	/* every page read goes through here, whether it's cached or not */
+	addSHLL(&lfc_ctl->wss_estimation, page_hash, now);
+
 	entry = hash_search_with_hash_value(lfc_hash, &tag, hash, HASH_FIND, NULL);
 	if (entry == NULL || (entry->bitmap[chunk_offs >> 5] & (1 << (chunk_offs & 31))) == 0)
 	{
@@ -965,3 +981,94 @@ local_cache_pages(PG_FUNCTION_ARGS)
 	else
 		SRF_RETURN_DONE(funcctx);
 }
+
+/*
+ * Read a range of blocks of a relation into the local file cache, skipping
+ * the blocks which are already cached. Used by compute_ctl to prewarm the
+ * cache after restart with the pages that were cached before suspend, so the
+ * relation doesn't have to be in the current database, and blocks past the
+ * end of the relation (e.g. because it was truncated since) are ignored.
+ *
+ * Returns the number of blocks fetched from the page server.
+ */
+PG_FUNCTION_INFO_V1(prewarm_local_cache);
+
+Datum
+prewarm_local_cache(PG_FUNCTION_ARGS)
+{
+	NRelFileInfo rinfo;
+	ForkNumber	forknum = PG_GETARG_INT16(3);
+	int64		first = PG_GETARG_INT64(4);
+	int64		count = PG_GETARG_INT64(5);
+	SMgrRelation reln;
+	BlockNumber nblocks;
+	int64		n_fetched = 0;
+	PGAlignedBlock buffer;
+
+	if (!superuser())
+		ereport(ERROR,
+				(errcode(ERRCODE_INSUFFICIENT_PRIVILEGE),
+				 errmsg("must be superuser to prewarm the local file cache")));
+
+	if (forknum < 0 || forknum > MAX_FORKNUM)
+		neon_log(ERROR, "invalid fork number %d", forknum);
+
+	if (first < 0 || count < 0 || first + count > MaxBlockNumber)
+		neon_log(ERROR, "invalid block range " INT64_FORMAT "+" INT64_FORMAT, first, count);
+
+	if (lfc_maybe_disabled())
+		PG_RETURN_INT64(0);
+
+	NInfoGetSpcOid(rinfo) = PG_GETARG_OID(0);
+	NInfoGetDbOid(rinfo) = PG_GETARG_OID(1);
+	NInfoGetRelNumber(rinfo) = PG_GETARG_OID(2);
+
+	reln = smgropen(rinfo, InvalidBackendId);
+	/* only permanent relations are stored in the page server */
+	reln->smgr_relpersistence = RELPERSISTENCE_PERMANENT;
+
+	if (!smgrexists(reln, forknum))
+		PG_RETURN_INT64(0);
+
+	nblocks = smgrnblocks(reln, forknum);
+	for (BlockNumber blkno = first; blkno < first + count && blkno < nblocks; blkno++)
+	{
+		CHECK_FOR_INTERRUPTS();
+
+		if (lfc_cache_contains(rinfo, forknum, blkno))
+			continue;
+
+		/* the page is stored in the local file cache on the way */
+		smgrread(reln, forknum, blkno, buffer.data);
+		n_fetched++;
+	}
+
+	PG_RETURN_INT64(n_fetched);
+}
+
+/*
+ * Estimated number of distinct pages read in the last `duration_seconds`,
+ * or since start if it's NULL. NULL if the local file cache is disabled.
+ */
+PG_FUNCTION_INFO_V1(approximate_working_set_size_seconds);
+
+Datum
+approximate_working_set_size_seconds(PG_FUNCTION_ARGS)
+{
+	TimestampTz since;
+	int32		estimate;
+
+	if (lfc_size_limit == 0 || lfc_ctl == NULL)
+		PG_RETURN_NULL();
+
+	if (PG_ARGISNULL(0))
+		since = 1;				/* registers which were ever set */
+	else
+		since = GetCurrentTimestamp() - (TimestampTz) PG_GETARG_INT32(0) * USECS_PER_SEC;
+
+	LWLockAcquire(lfc_lock, LW_SHARED);
+	estimate = (int32) estimateSHLL(&lfc_ctl->wss_estimation, since);
+	LWLockRelease(lfc_lock);
+
+	PG_RETURN_INT32(estimate);
+}
@@ -0,0 +1,99 @@
+/*-------------------------------------------------------------------------
+ *
+ * hll.c
+ *	  Sliding window HyperLogLog cardinality estimator
+ *
+ * Based on the HyperLogLog algorithm of Flajolet et al. with the sliding
+ * window extension of Chabchoub and Hebrail, see hll.h.
+ *
+ * IDENTIFICATION
+ *	  pgxn/neon/hll.c
+ *
+ *-------------------------------------------------------------------------
+ */
+#include "postgres.h"
+
+#include <math.h>
+
+#include "port/pg_bitutils.h"
+
+#include "hll.h"
+
+/*
+ * Position of the leftmost 1 bit of the top `b` bits of `x`, counting from 1,
+ * or b + 1 if all of them are zero.
+ */
+static inline uint8
+rho(uint32 x, uint8 b)
+{
+	uint8		j;
+
+	if (x == 0)
+		return b + 1;
+
+	j = 32 - pg_leftmost_one_pos32(x);
+	if (j > b)
+		return b + 1;
+	return j;
+}
+
+void
+initSHLL(HyperLogLogState *cState)
+{
+	memset(cState->regs, 0, sizeof(cState->regs));
+}
+
+/*
+ * Add a value with the given hash at time `now`, the hash should be uniformly
+ * distributed. The time is passed in, so that callers can take it before
+ * acquiring the lock protecting the state.
+ */
+void
+addSHLL(HyperLogLogState *cState, uint32 hash, TimestampTz now)
+{
+	uint8		count;
+	uint32		index;
+
+	/* the top bits select the register, the rest gives the rank */
+	index = hash >> HLL_C_BITS;
+	count = rho(hash << HLL_BIT_WIDTH, HLL_C_BITS);
+
+	cState->regs[index][count] = now;
+}
+
+/*
+ * Estimate the number of distinct values added at or after `since`.
+ */
+double
+estimateSHLL(HyperLogLogState *cState, TimestampTz since)
+{
+	double		alpha = 0.7213 / (1.0 + 1.079 / HLL_N_REGISTERS);
+	double		sum = 0.0;
+	double		estimate;
+	int			zero_registers = 0;
+
+	for (uint32 i = 0; i < HLL_N_REGISTERS; i++)
+	{
+		uint8		max_rank = 0;
+
+		for (uint8 j = HLL_C_BITS + 1; j > 0; j--)
+		{
+			if (cState->regs[i][j] >= since)
+			{
+				max_rank = j;
+				break;
+			}
+		}
+		if (max_rank == 0)
+			zero_registers++;
+		sum += 1.0 / (double) ((uint64) 1 << max_rank);
+	}
+
+	estimate = alpha * HLL_N_REGISTERS * HLL_N_REGISTERS / sum;
+
+	/* small range correction */
+	if (estimate <= 2.5 * HLL_N_REGISTERS && zero_registers > 0)
+		estimate = HLL_N_REGISTERS * log((double) HLL_N_REGISTERS / zero_registers);
+
+	return estimate;
+}
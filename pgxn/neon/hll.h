@@ -0,0 +1,38 @@
+/*-------------------------------------------------------------------------
+ *
+ * hll.h
+ *	  Sliding window HyperLogLog cardinality estimator
+ *
+ * Every register keeps, for each possible rank, the last time a hash with
+ * that rank was added. This allows estimating the number of distinct values
+ * added within any recent time window, e.g. the working set of the pages
+ * accessed in the last minute.
+ *
+ * IDENTIFICATION
+ *	  pgxn/neon/hll.h
+ *
+ *-------------------------------------------------------------------------
+ */
+#ifndef NEON_HLL_H
+#define NEON_HLL_H
+
+#include "utils/timestamp.h"
+
+/*
+ * 2^10 registers give a standard error of about 3%. The ranks go from 1 to
+ * HLL_C_BITS + 1, so that's 2^10 * 24 timestamps, i.e. 192kB of shared memory.
+ */
+#define HLL_BIT_WIDTH	10
+#define HLL_C_BITS		(32 - HLL_BIT_WIDTH)
+#define HLL_N_REGISTERS	(1 << HLL_BIT_WIDTH)
+
+typedef struct HyperLogLogState
+{
+	TimestampTz regs[HLL_N_REGISTERS][HLL_C_BITS + 2];
+} HyperLogLogState;
+
+extern void initSHLL(HyperLogLogState *cState);
+extern void addSHLL(HyperLogLogState *cState, uint32 hash, TimestampTz now);
+extern double estimateSHLL(HyperLogLogState *cState, TimestampTz since);
+
+#endif							/* NEON_HLL_H */
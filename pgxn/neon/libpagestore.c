@@ -279,6 +279,16 @@ load_shard_map(shardno_t shard_no, char *connstr_p, shardno_t *num_shards_p)
 		*num_shards_p = num_shards;
 }
 
+/*
+ * Has the shard map in shared memory changed since this backend last loaded
+ * it?
+ */
+static bool
+shard_map_changed(void)
+{
+	return pagestore_local_counter != pg_atomic_read_u64(&pagestore_shared->end_update_counter);
+}
+
 #define MB (1024*1024)
 
 shardno_t
@@ -460,6 +470,19 @@ retry:
 
 		CHECK_FOR_INTERRUPTS();
 
+		/*
+		 * If the shard map changed while we were waiting, the pageserver may
+		 * not serve the tenant anymore and never respond. The postmaster
+		 * forwards the SIGHUP which updated the shard map to us, which sets
+		 * the latch, so we notice it here. Give up on the connection, the
+		 * request is retried with the new shard map.
+		 */
+		if (shard_map_changed())
+		{
+			neon_shard_log(shard_no, LOG, "shard map changed while waiting for response from pageserver");
+			return -1;
+		}
+
 		/* Data available in socket? */
 		if (event.events & WL_SOCKET_READABLE)
 		{
@@ -0,0 +1,8 @@
+\echo Use "ALTER EXTENSION neon UPDATE TO '1.2'" to load this file. \quit
+
+CREATE FUNCTION prewarm_local_cache(reltablespace oid, reldatabase oid, relfilenode oid, relforknumber smallint, first_block bigint, nblocks bigint)
+RETURNS bigint
+AS 'MODULE_PATHNAME', 'prewarm_local_cache'
+LANGUAGE C STRICT;
+
+REVOKE ALL ON FUNCTION prewarm_local_cache(oid, oid, oid, smallint, bigint, bigint) FROM PUBLIC;
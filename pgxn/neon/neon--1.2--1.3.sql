@@ -0,0 +1,10 @@
+\echo Use "ALTER EXTENSION neon UPDATE TO '1.3'" to load this file. \quit
+
+-- Estimated number of distinct pages accessed through the local file cache in
+-- the last duration_seconds, or since start if NULL.
+CREATE FUNCTION approximate_working_set_size_seconds(duration_seconds integer DEFAULT NULL)
+RETURNS integer
+AS 'MODULE_PATHNAME', 'approximate_working_set_size_seconds'
+LANGUAGE C PARALLEL SAFE;
+
+GRANT EXECUTE ON FUNCTION approximate_working_set_size_seconds(integer) TO pg_monitor;
@@ -38,6 +38,7 @@ PG_MODULE_MAGIC;
 void		_PG_init(void);
 
 static int	logical_replication_max_time_lag = 3600;
+static char *neon_traceparent = NULL;
 
 static void
 InitLogicalReplicationMonitor(void)
@@ -181,6 +182,16 @@ _PG_init(void)
 
 	pg_init_extension_server();
 
+	DefineCustomStringVariable(
+		"neon.traceparent",
+		"W3C trace context of the request served by this session",
+		"Set by the proxy for traced requests, to correlate the session with the trace.",
+		&neon_traceparent,
+		"",
+		PGC_USERSET,
+		GUC_NOT_IN_SAMPLE,
+		NULL, NULL, NULL);
+
 	/*
 	 * Important: This must happen after other parts of the extension are
 	 * loaded, otherwise any settings to GUCs that were set before the
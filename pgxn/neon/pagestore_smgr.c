@@ -958,20 +958,25 @@ page_server_request(void const *req)
 		default:
 			neon_log(ERROR, "Unexpected request tag: %d", ((NeonRequest *) req)->tag);
 	}
-	shard_no = get_shard_number(&tag);
-
-
-	/*
-	 * Current sharding model assumes that all metadata is present only at shard 0.
-	 * We still need to call get_shard_no() to check if shard map is up-to-date.
-	 */
-	if (((NeonRequest *) req)->tag != T_NeonGetPageRequest || ((NeonGetPageRequest *) req)->forknum != MAIN_FORKNUM)
-	{
-		shard_no = 0;
-	}
 
 	do
 	{
+		/*
+		 * Look up the shard on every attempt: the request may have failed
+		 * because the shard map changed, e.g. when the tenant was split or
+		 * moved to another pageserver.
+		 */
+		shard_no = get_shard_number(&tag);
+
+		/*
+		 * Current sharding model assumes that all metadata is present only at shard 0.
+		 * We still need to call get_shard_no() to check if shard map is up-to-date.
+		 */
+		if (((NeonRequest *) req)->tag != T_NeonGetPageRequest || ((NeonGetPageRequest *) req)->forknum != MAIN_FORKNUM)
+		{
+			shard_no = 0;
+		}
+
 		while (!page_server->send(shard_no, (NeonRequest *) req) || !page_server->flush(shard_no));
 		consume_prefetch_responses();
 		resp = page_server->receive(shard_no);
@@ -72,11 +72,20 @@ static XLogRecPtr CalculateMinFlushLsn(WalProposer *wp);
 static XLogRecPtr GetAcknowledgedByQuorumWALPosition(WalProposer *wp);
 static void HandleSafekeeperResponse(WalProposer *wp);
 static bool AsyncRead(Safekeeper *sk, char **buf, int *buf_size);
+static bool ReadMemberSet(StringInfo s, MemberSet *members);
+static bool ReadMembershipConfiguration(StringInfo s, MembershipConfiguration *mconf);
 static bool AsyncReadMessage(Safekeeper *sk, AcceptorProposerMessage *anymsg);
 static bool BlockingWrite(Safekeeper *sk, void *msg, size_t msg_size, SafekeeperState success_state);
 static bool AsyncWrite(Safekeeper *sk, void *msg, size_t msg_size, SafekeeperState flush_state);
 static bool AsyncFlush(Safekeeper *sk);
 static int	CompareLsn(const void *a, const void *b);
+static bool SkGreeted(Safekeeper *sk);
+static bool SkVoted(Safekeeper *sk);
+static bool SkIdle(Safekeeper *sk);
+static bool SkSynced(Safekeeper *sk);
+static Safekeeper *FindMember(WalProposer *wp, NNodeId nodeId);
+static bool MemberSetIsQuorum(WalProposer *wp, MemberSet *members, bool (*pred) (Safekeeper *sk));
+static bool IsQuorum(WalProposer *wp, bool (*pred) (Safekeeper *sk));
 static char *FormatSafekeeperState(Safekeeper *sk);
 static void AssertEventsOkForState(uint32 events, Safekeeper *sk);
 static char *FormatEvents(WalProposer *wp, uint32 events);
@@ -139,10 +148,19 @@ WalProposerCreate(WalProposerConfig *config, walproposer_api api)
 
 	/* Fill the greeting package */
 	wp->greetRequest.tag = 'g';
-	wp->greetRequest.protocolVersion = SK_PROTOCOL_VERSION;
+	/*
+	 * Protocol version 3 is needed only to pass the membership configuration
+	 * generation, so stick to version 2 without it to keep working with
+	 * safekeepers which don't know version 3 yet.
+	 */
+	if (wp->config->safekeepers_generation != INVALID_GENERATION)
+		wp->greetRequest.protocolVersion = SK_PROTOCOL_VERSION;
+	else
+		wp->greetRequest.protocolVersion = SK_PROTOCOL_VERSION_NO_MCONF;
 	wp->greetRequest.pgVersion = PG_VERSION_NUM;
 	wp->api.strong_random(wp, &wp->greetRequest.proposerId, sizeof(wp->greetRequest.proposerId));
 	wp->greetRequest.systemId = wp->config->systemId;
+	wp->greetRequest.mconfGeneration = wp->config->safekeepers_generation;
 	if (!wp->config->neon_timeline)
 		wp_log(FATAL, "neon.timeline_id is not provided");
 	if (*wp->config->neon_timeline != '\0' &&
@@ -667,11 +685,17 @@ RecvStartWALPushResult(Safekeeper *sk)
 static void
 SendProposerGreeting(Safekeeper *sk)
 {
+	size_t		len = sizeof(sk->wp->greetRequest);
+
+	/* version 2 greeting ends before the membership configuration generation */
+	if (sk->wp->greetRequest.protocolVersion < SK_PROTOCOL_VERSION)
+		len = offsetof(ProposerGreeting, mconfGeneration);
+
 	/*
 	 * On failure, logging & resetting the connection is handled. We just need
 	 * to handle the control flow.
 	 */
-	BlockingWrite(sk, &sk->wp->greetRequest, sizeof(sk->wp->greetRequest), SS_HANDSHAKE_RECV);
+	BlockingWrite(sk, &sk->wp->greetRequest, len, SS_HANDSHAKE_RECV);
 }
 
 static void
@@ -692,23 +716,36 @@ RecvAcceptorGreeting(Safekeeper *sk)
 
 	/* Protocol is all good, move to voting. */
 	sk->state = SS_VOTING;
+	sk->greeted = true;
+	++wp->n_connected;
 
 	/*
-	 * Note: it would be better to track the counter on per safekeeper basis,
-	 * but at worst walproposer would restart with 'term rejected', so leave
-	 * as is for now.
+	 * Safekeepers which already switched to our generation tell us its
+	 * members, which are the ones whose votes and acknowledgments count.
 	 */
-	++wp->n_connected;
-	if (wp->n_connected <= wp->quorum)
+	if (!wp->has_mconf &&
+		wp->config->safekeepers_generation != INVALID_GENERATION &&
+		sk->greetResponse.mconf.generation == wp->config->safekeepers_generation)
+	{
+		wp->mconf = sk->greetResponse.mconf;
+		wp->has_mconf = true;
+		wp_log(LOG, "learnt membership configuration of generation %u with %u members%s from safekeeper %s:%s",
+			   wp->mconf.generation, wp->mconf.members.len,
+			   wp->mconf.has_new_members ? " and joint new members" : "",
+			   sk->host, sk->port);
+	}
+
+	/* vote request is not prepared until quorum greeted us */
+	if (wp->voteRequest.term == 0)
 	{
-		/* We're still collecting terms from the majority. */
+		/* We're still collecting terms from the quorum. */
 		wp->propTerm = Max(sk->greetResponse.term, wp->propTerm);
 
 		/* Quorum is acquried, prepare the vote request. */
-		if (wp->n_connected == wp->quorum)
+		if (IsQuorum(wp, SkGreeted))
 		{
 			wp->propTerm++;
-			wp_log(LOG, "proposer connected to quorum (%d) safekeepers, propTerm=" INT64_FORMAT, wp->quorum, wp->propTerm);
+			wp_log(LOG, "proposer connected to quorum of safekeepers (%d connected), propTerm=" INT64_FORMAT, wp->n_connected, wp->propTerm);
 
 			wp->voteRequest = (VoteRequest)
 			{
@@ -732,7 +769,7 @@ RecvAcceptorGreeting(Safekeeper *sk)
 	 *
 	 * If we do have quorum, we can start an election.
 	 */
-	if (wp->n_connected < wp->quorum)
+	if (wp->voteRequest.term == 0)
 	{
 		/*
 		 * SS_VOTING is an idle state; read-ready indicates the connection
@@ -775,6 +812,7 @@ static void
 RecvVoteResponse(Safekeeper *sk)
 {
 	WalProposer *wp = sk->wp;
+	bool		was_elected;
 
 	sk->voteResponse.apm.tag = 'v';
 	if (!AsyncReadMessage(sk, (AcceptorProposerMessage *) &sk->voteResponse))
@@ -792,8 +830,9 @@ RecvVoteResponse(Safekeeper *sk)
 	 * already lives in strictly higher term (concurrent compute spotted) or
 	 * we are not elected yet and thus need the vote.
 	 */
+	was_elected = IsQuorum(wp, SkVoted);
 	if ((!sk->voteResponse.voteGiven) &&
-		(sk->voteResponse.term > wp->propTerm || wp->n_votes < wp->quorum))
+		(sk->voteResponse.term > wp->propTerm || !was_elected))
 	{
 		wp_log(FATAL, "WAL acceptor %s:%s with term " INT64_FORMAT " rejects our connection request with term " INT64_FORMAT "",
 			   sk->host, sk->port,
@@ -802,16 +841,17 @@ RecvVoteResponse(Safekeeper *sk)
 	Assert(sk->voteResponse.term == wp->propTerm);
 
 	/* Handshake completed, do we have quorum? */
+	sk->voted = true;
 	wp->n_votes++;
-	if (wp->n_votes < wp->quorum)
-	{
-		sk->state = SS_IDLE;	/* can't do much yet, no quorum */
-	}
-	else if (wp->n_votes > wp->quorum)
+	if (was_elected)
 	{
 		/* already elected, start streaming */
 		SendProposerElected(sk);
 	}
+	else if (!IsQuorum(wp, SkVoted))
+	{
+		sk->state = SS_IDLE;	/* can't do much yet, no quorum */
+	}
 	else
 	{
 		sk->state = SS_IDLE;
@@ -844,6 +884,13 @@ HandleElectedProposer(WalProposer *wp)
 		wp_log(FATAL, "failed to download WAL for logical replicaiton");
 	}
 
+	/*
+	 * This is the only part of the sync handshake which can be skipped. The
+	 * election itself can't: the vote in a new term is what fences off a
+	 * previous compute which may still be streaming, and term histories
+	 * remembered from an earlier sync may be stale by now, so the epoch
+	 * start LSN must be determined from the votes of this term.
+	 */
 	if (wp->truncateLsn == wp->propEpochStartLsn && wp->config->syncSafekeepers)
 	{
 		/* Sync is not needed: just exit */
@@ -961,7 +1008,7 @@ DetermineEpochStartLsn(WalProposer *wp)
 		}
 	}
 
-	if (n_ready < wp->quorum)
+	if (!IsQuorum(wp, SkIdle))
 	{
 		/*
 		 * This is a rare case that can be triggered if safekeeper has voted and disconnected.
@@ -1030,9 +1077,12 @@ DetermineEpochStartLsn(WalProposer *wp)
 		 * Basebackup LSN always points to the beginning of the record (not
 		 * the page), as StartupXLOG most probably wants it this way.
 		 * Safekeepers don't skip header as they need continious stream of
-		 * data, so correct LSN for comparison.
+		 * data, so correct LSN for comparison. On a promoted replica, the
+		 * start LSN is the end of the last replayed record instead, which
+		 * may point to the page header as well.
 		 */
-		if (SkipXLogPageHeader(wp, wp->propEpochStartLsn) != wp->api.get_redo_start_lsn(wp))
+		if (SkipXLogPageHeader(wp, wp->propEpochStartLsn) !=
+			SkipXLogPageHeader(wp, wp->api.get_redo_start_lsn(wp)))
 		{
 			/*
 			 * However, allow to proceed if previously elected leader was me;
@@ -1555,6 +1605,132 @@ CalculateMinFlushLsn(WalProposer *wp)
 	return lsn;
 }
 
+static bool
+SkGreeted(Safekeeper *sk)
+{
+	return sk->greeted;
+}
+
+static bool
+SkVoted(Safekeeper *sk)
+{
+	return sk->voted;
+}
+
+static bool
+SkIdle(Safekeeper *sk)
+{
+	return sk->state == SS_IDLE;
+}
+
+/* safekeeper knows that epochStartLsn is committed */
+static bool
+SkSynced(Safekeeper *sk)
+{
+	return sk->appendResponse.commitLsn >= sk->wp->propEpochStartLsn;
+}
+
+/*
+ * Find safekeeper with the given node id among the ones which greeted us, or
+ * NULL if there is none.
+ */
+static Safekeeper *
+FindMember(WalProposer *wp, NNodeId nodeId)
+{
+	for (int i = 0; i < wp->n_safekeepers; i++)
+	{
+		if (wp->safekeeper[i].greeted && wp->safekeeper[i].greetResponse.nodeId == nodeId)
+			return &wp->safekeeper[i];
+	}
+	return NULL;
+}
+
+/* Does predicate hold for majority of the member set? */
+static bool
+MemberSetIsQuorum(WalProposer *wp, MemberSet *members, bool (*pred) (Safekeeper *sk))
+{
+	uint32		n = 0;
+
+	for (int i = 0; i < members->len; i++)
+	{
+		Safekeeper *sk = FindMember(wp, members->ids[i]);
+
+		if (sk != NULL && pred(sk))
+			n++;
+	}
+	return n >= members->len / 2 + 1;
+}
+
+/*
+ * Does predicate hold for quorum of safekeepers? Without membership
+ * configuration it is majority of neon.safekeepers, otherwise majority of
+ * members and, if configuration is joint, also of new members.
+ */
+static bool
+IsQuorum(WalProposer *wp, bool (*pred) (Safekeeper *sk))
+{
+	if (wp->config->safekeepers_generation == INVALID_GENERATION)
+	{
+		int			n = 0;
+
+		for (int i = 0; i < wp->n_safekeepers; i++)
+		{
+			if (pred(&wp->safekeeper[i]))
+				n++;
+		}
+		return n >= wp->quorum;
+	}
+
+	/* members are not known until a safekeeper of our generation greets us */
+	if (!wp->has_mconf)
+		return false;
+	if (!MemberSetIsQuorum(wp, &wp->mconf.members, pred))
+		return false;
+	return !wp->mconf.has_new_members || MemberSetIsQuorum(wp, &wp->mconf.new_members, pred);
+}
+
+/*
+ * Flush position of the safekeeper which counts for commit.
+ */
+static XLogRecPtr
+AcknowledgedFlushLsn(Safekeeper *sk)
+{
+	/*
+	 * Like in Raft, we aren't allowed to commit entries from previous terms,
+	 * so ignore reported LSN until it gets to epochStartLsn.
+	 */
+	return sk->appendResponse.flushLsn >= sk->wp->propEpochStartLsn ? sk->appendResponse.flushLsn : 0;
+}
+
+/*
+ * Calculate WAL position acknowledged by quorum of the member set.
+ */
+static XLogRecPtr
+GetAcknowledgedByMembersWALPosition(WalProposer *wp, MemberSet *members)
+{
+	XLogRecPtr	responses[MAX_SAFEKEEPERS];
+
+	if (members->len == 0)
+		return InvalidXLogRecPtr;
+
+	/*
+	 * Sort acknowledged LSNs. Members we don't know about, e.g. not in
+	 * neon.safekeepers, haven't acknowledged anything.
+	 */
+	for (int i = 0; i < members->len; i++)
+	{
+		Safekeeper *sk = FindMember(wp, members->ids[i]);
+
+		responses[i] = sk != NULL ? AcknowledgedFlushLsn(sk) : InvalidXLogRecPtr;
+	}
+	qsort(responses, members->len, sizeof(XLogRecPtr), CompareLsn);
+
+	/*
+	 * Get the smallest LSN committed by quorum
+	 */
+	return responses[members->len - (members->len / 2 + 1)];
+}
+
 /*
  * Calculate WAL position acknowledged by quorum
  */
@@ -1562,18 +1738,28 @@ static XLogRecPtr
 GetAcknowledgedByQuorumWALPosition(WalProposer *wp)
 {
 	XLogRecPtr	responses[MAX_SAFEKEEPERS];
+	XLogRecPtr	lsn;
 
 	/*
-	 * Sort acknowledged LSNs
+	 * With membership configuration WAL is committed once quorum of each of
+	 * its member sets has it.
 	 */
-	for (int i = 0; i < wp->n_safekeepers; i++)
+	if (wp->config->safekeepers_generation != INVALID_GENERATION)
 	{
-		/*
-		 * Like in Raft, we aren't allowed to commit entries from previous
-		 * terms, so ignore reported LSN until it gets to epochStartLsn.
-		 */
-		responses[i] = wp->safekeeper[i].appendResponse.flushLsn >= wp->propEpochStartLsn ? wp->safekeeper[i].appendResponse.flushLsn : 0;
+		if (!wp->has_mconf)
+			return InvalidXLogRecPtr;
+
+		lsn = GetAcknowledgedByMembersWALPosition(wp, &wp->mconf.members);
+		if (wp->mconf.has_new_members)
+			lsn = Min(lsn, GetAcknowledgedByMembersWALPosition(wp, &wp->mconf.new_members));
+		return lsn;
 	}
+
+	/*
+	 * Sort acknowledged LSNs
+	 */
+	for (int i = 0; i < wp->n_safekeepers; i++)
+		responses[i] = AcknowledgedFlushLsn(&wp->safekeeper[i]);
 	qsort(responses, wp->n_safekeepers, sizeof(XLogRecPtr), CompareLsn);
 
 	/*
@@ -1594,7 +1780,7 @@ GetDonor(WalProposer *wp, XLogRecPtr *donor_lsn)
 	Safekeeper *donor = NULL;
 	int			i;
 
-	if (wp->n_votes < wp->quorum)
+	if (!IsQuorum(wp, SkVoted))
 	{
 		wp_log(WARNING, "GetDonor called before elections are won");
 		return NULL;
@@ -1669,22 +1855,16 @@ HandleSafekeeperResponse(WalProposer *wp)
 	 */
 	if (wp->config->syncSafekeepers)
 	{
-		int			n_synced;
-
-		n_synced = 0;
 		for (int i = 0; i < wp->n_safekeepers; i++)
 		{
 			Safekeeper *sk = &wp->safekeeper[i];
-			bool		synced = sk->appendResponse.commitLsn >= wp->propEpochStartLsn;
 
 			/* alive safekeeper which is not synced yet; wait for it */
-			if (sk->state != SS_OFFLINE && !synced)
+			if (sk->state != SS_OFFLINE && !SkSynced(sk))
 				return;
-			if (synced)
-				n_synced++;
 		}
 
-		if (n_synced >= wp->quorum)
+		if (IsQuorum(wp, SkSynced))
 		{
 			/* A quorum of safekeepers has been synced! */
 
@@ -1734,6 +1914,34 @@ AsyncRead(Safekeeper *sk, char **buf, int *buf_size)
 	return false;
 }
 
+/*
+ * Read member set from the message, returning false if it has more members
+ * than we can hold.
+ */
+static bool
+ReadMemberSet(StringInfo s, MemberSet *members)
+{
+	members->len = pq_getmsgint32_le(s);
+	if (members->len > MAX_SAFEKEEPERS)
+		return false;
+	for (int i = 0; i < members->len; i++)
+		members->ids[i] = pq_getmsgint64_le(s);
+	return true;
+}
+
+/*
+ * Read membership configuration sent in the greeting since protocol version 3.
+ */
+static bool
+ReadMembershipConfiguration(StringInfo s, MembershipConfiguration *mconf)
+{
+	mconf->generation = pq_getmsgint32_le(s);
+	if (!ReadMemberSet(s, &mconf->members))
+		return false;
+	mconf->has_new_members = pq_getmsgbyte(s) != 0;
+	return !mconf->has_new_members || ReadMemberSet(s, &mconf->new_members);
+}
+
 /*
  * Read next message with known type into provided struct, by reading a CopyData
  * block from the safekeeper's postgres connection, returning whether the read
@@ -1778,6 +1986,14 @@ AsyncReadMessage(Safekeeper *sk, AcceptorProposerMessage *anymsg)
 
 				msg->term = pq_getmsgint64_le(&s);
 				msg->nodeId = pq_getmsgint64_le(&s);
+				if (wp->greetRequest.protocolVersion >= SK_PROTOCOL_VERSION &&
+					!ReadMembershipConfiguration(&s, &msg->mconf))
+				{
+					wp_log(WARNING, "membership configuration from node %s:%s has more than %d members",
+						   sk->host, sk->port, MAX_SAFEKEEPERS);
+					ResetConnection(sk);
+					return false;
+				}
 				pq_getmsgend(&s);
 				return true;
 			}
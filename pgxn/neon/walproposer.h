@@ -12,7 +12,12 @@
 #include "neon_walreader.h"
 
 #define SK_MAGIC 0xCafeCeefu
-#define SK_PROTOCOL_VERSION 2
+#define SK_PROTOCOL_VERSION 3
+/* protocol version used when safekeepers membership is not configured */
+#define SK_PROTOCOL_VERSION_NO_MCONF 2
+
+/* safekeepers membership configuration generation which is not set */
+#define INVALID_GENERATION 0
 
 #define MAX_SAFEKEEPERS 32
 #define MAX_SEND_SIZE (XLOG_BLCKSZ * 16)	/* max size of a single* WAL
@@ -158,6 +163,8 @@ typedef struct ProposerGreeting
 	uint8		tenant_id[16];
 	TimeLineID	timeline;
 	uint32		walSegSize;
+	uint32		mconfGeneration;	/* safekeepers membership configuration
+									 * generation, since protocol version 3 */
 } ProposerGreeting;
 
 typedef struct AcceptorProposerMessage
@@ -165,6 +172,27 @@ typedef struct AcceptorProposerMessage
 	uint64		tag;
 } AcceptorProposerMessage;
 
+/*
+ * Set of safekeepers of a membership configuration, identified by node ids.
+ */
+typedef struct MemberSet
+{
+	uint32		len;
+	NNodeId		ids[MAX_SAFEKEEPERS];
+} MemberSet;
+
+/*
+ * Safekeepers membership configuration. During a change it is joint: both
+ * members and new_members must form a quorum then.
+ */
+typedef struct MembershipConfiguration
+{
+	uint32		generation;
+	MemberSet	members;
+	bool		has_new_members;
+	MemberSet	new_members;
+} MembershipConfiguration;
+
 /*
  * Acceptor -> Proposer initial response: the highest term acceptor voted for.
  */
@@ -173,6 +201,7 @@ typedef struct AcceptorGreeting
 	AcceptorProposerMessage apm;
 	term_t		term;
 	NNodeId		nodeId;
+	MembershipConfiguration mconf;	/* since protocol version 3 */
 } AcceptorGreeting;
 
 /*
@@ -352,6 +381,8 @@ typedef struct Safekeeper
 	TimestampTz latestMsgReceivedAt;	/* when latest msg is received */
 	AcceptorGreeting greetResponse; /* acceptor greeting */
 	VoteResponse voteResponse;	/* the vote */
+	bool		greeted;		/* greeting was received, ever */
+	bool		voted;			/* vote was given to us, ever */
 	AppendResponse appendResponse;	/* feedback for master */
 
 
@@ -624,6 +655,13 @@ typedef struct WalProposerConfig
 	/* Will be passed to safekeepers in greet request. */
 	TimeLineID	pgTimeline;
 
+	/*
+	 * Generation of safekeepers membership configuration. Safekeepers refuse
+	 * proposers with generation older than theirs. Will be passed to
+	 * safekeepers in greet request.
+	 */
+	uint32		safekeepers_generation;
+
 #ifdef WALPROPOSER_LIB
 	void	   *callback_data;
 #endif
@@ -690,6 +728,14 @@ typedef struct WalProposer
 	/* number of successful connections over the lifetime of walproposer */
 	int			n_connected;
 
+	/*
+	 * Membership configuration of generation config->safekeepers_generation,
+	 * learnt from the first safekeeper greeting us with it. Unused if the
+	 * generation is INVALID_GENERATION: then all safekeepers are members.
+	 */
+	bool		has_mconf;
+	MembershipConfiguration mconf;
+
 	/*
 	 * Timestamp of the last reconnection attempt. Related to
 	 * config->safekeeper_reconnect_timeout
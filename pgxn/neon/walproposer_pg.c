@@ -11,6 +11,7 @@
 #include <signal.h>
 #include <unistd.h>
 #include <sys/stat.h>
+#include "access/timeline.h"
 #include "access/xact.h"
 #include "access/xlog.h"
 #include "access/xlogdefs.h"
@@ -62,6 +63,7 @@
 char	   *wal_acceptors_list = "";
 int			wal_acceptor_reconnect_timeout = 1000;
 int			wal_acceptor_connection_timeout = 10000;
+int			safekeepers_generation = 0;
 
 static AppendResponse quorumFeedback;
 static WalproposerShmemState *walprop_shared;
@@ -118,6 +120,7 @@ init_walprop_config(bool syncSafekeepers)
 	else
 		walprop_config.systemId = 0;
 	walprop_config.pgTimeline = walprop_pg_get_timeline_id();
+	walprop_config.safekeepers_generation = safekeepers_generation;
 }
 
 /*
@@ -209,6 +212,17 @@ nwp_register_gucs(void)
 							PGC_SIGHUP,
 							GUC_UNIT_MS,
 							NULL, NULL, NULL);
+
+	DefineCustomIntVariable(
+							"neon.safekeepers_generation",
+							"Generation of safekeepers membership configuration neon.safekeepers belong to.",
+							"If set, votes and acknowledgments are counted against members of this configuration, "
+							"so neon.safekeepers must list all of them. 0 means all of neon.safekeepers are members.",
+							&safekeepers_generation,
+							0, 0, INT_MAX,
+							PGC_POSTMASTER,
+							0,
+							NULL, NULL, NULL);
 }
 
 /*  Check if we need to suspend inserts because of lagging replication. */
@@ -514,6 +528,13 @@ walprop_pg_init_bgworker(void)
 	GetXLogReplayRecPtr(&tli);
 #else
 	GetXLogReplayRecPtr(&ThisTimeLineID);
+
+	/*
+	 * A replica promoted to primary inserts WAL on a newer timeline than the
+	 * one it replayed: once recovery is over, RecoveryInProgress() switches
+	 * ThisTimeLineID to it.
+	 */
+	(void) RecoveryInProgress();
 #endif
 }
 
@@ -537,7 +558,13 @@ static TimeLineID
 walprop_pg_get_timeline_id(void)
 {
 #if PG_VERSION_NUM >= 150000
-	/* FIXME don't use hardcoded timeline id */
+	/*
+	 * Neon computes start from a basebackup on timeline 1, but a replica
+	 * promoted to primary switches to the next one. Standalone
+	 * sync-safekeepers has no WAL of its own.
+	 */
+	if (IsUnderPostmaster && !RecoveryInProgress())
+		return GetWALInsertionTimeLine();
 	return 1;
 #else
 	return ThisTimeLineID;
@@ -1800,26 +1827,42 @@ walprop_pg_finish_sync_safekeepers(WalProposer *wp, XLogRecPtr lsn)
 
 /*
  * Choose most advanced PageserverFeedback and set it to *rf.
+ *
+ * Safekeepers complement feedback of pageservers streaming from them with
+ * pageserver progress learnt from peers, so a safekeeper without pageserver
+ * connection may report the same last_received_lsn as the one having it, but
+ * without the timeline size. On ties prefer the fresher reply. Consistent
+ * LSNs are taken as maximum over all safekeepers, each of them is a lower
+ * bound of the pageserver progress.
  */
 static void
 GetLatestNeonFeedback(PageserverFeedback *rf, WalProposer *wp)
 {
 	int			latest_safekeeper = 0;
 	XLogRecPtr	last_received_lsn = InvalidXLogRecPtr;
+	TimestampTz replytime = 0;
+	XLogRecPtr	disk_consistent_lsn = InvalidXLogRecPtr;
+	XLogRecPtr	remote_consistent_lsn = InvalidXLogRecPtr;
 
 	for (int i = 0; i < wp->n_safekeepers; i++)
 	{
-		if (wp->safekeeper[i].appendResponse.rf.last_received_lsn > last_received_lsn)
+		PageserverFeedback *skrf = &wp->safekeeper[i].appendResponse.rf;
+
+		if (skrf->last_received_lsn > last_received_lsn ||
+			(skrf->last_received_lsn == last_received_lsn && skrf->replytime > replytime))
 		{
 			latest_safekeeper = i;
-			last_received_lsn = wp->safekeeper[i].appendResponse.rf.last_received_lsn;
+			last_received_lsn = skrf->last_received_lsn;
+			replytime = skrf->replytime;
 		}
+		disk_consistent_lsn = Max(disk_consistent_lsn, skrf->disk_consistent_lsn);
+		remote_consistent_lsn = Max(remote_consistent_lsn, skrf->remote_consistent_lsn);
 	}
 
 	rf->currentClusterSize = wp->safekeeper[latest_safekeeper].appendResponse.rf.currentClusterSize;
 	rf->last_received_lsn = wp->safekeeper[latest_safekeeper].appendResponse.rf.last_received_lsn;
-	rf->disk_consistent_lsn = wp->safekeeper[latest_safekeeper].appendResponse.rf.disk_consistent_lsn;
-	rf->remote_consistent_lsn = wp->safekeeper[latest_safekeeper].appendResponse.rf.remote_consistent_lsn;
+	rf->disk_consistent_lsn = disk_consistent_lsn;
+	rf->remote_consistent_lsn = remote_consistent_lsn;
 	rf->replytime = wp->safekeeper[latest_safekeeper].appendResponse.rf.replytime;
 
 	wpg_log(DEBUG2, "GetLatestNeonFeedback: currentClusterSize %lu,"
@@ -1934,6 +1977,15 @@ walprop_pg_process_safekeeper_feedback(WalProposer *wp, XLogRecPtr commitLsn)
 static XLogRecPtr
 walprop_pg_get_redo_start_lsn(WalProposer *wp)
 {
+	TimeLineID	tli = walprop_pg_get_timeline_id();
+
+	/*
+	 * A replica promoted to primary writes WAL since the end of the WAL it
+	 * replayed, where its timeline branched off, not since its basebackup.
+	 */
+	if (tli > 1)
+		return tliSwitchPoint(tli - 1, readTimeLineHistory(tli), NULL);
+
 	return GetRedoStartLsn();
 }
 
@@ -57,6 +57,12 @@ pub enum AuthErrorImpl {
     #[error("password authentication failed for user '{0}'")]
     AuthFailed(Box<str>),
 
+    #[error("certificate authentication failed for user '{0}'")]
+    CertificateAuthFailed(Box<str>),
+
+    #[error("JWT authentication failed for user '{0}'")]
+    JwtAuthFailed(Box<str>),
+
     /// Errors produced by e.g. [`crate::stream::PqStream`].
     #[error(transparent)]
     Io(#[from] io::Error),
@@ -70,6 +76,9 @@ pub enum AuthErrorImpl {
     #[error("Too many connections to this endpoint. Please try again later.")]
     TooManyConnections,
 
+    #[error("Too many open connections to this endpoint (the limit is {0}).")]
+    ConnectionLimitExceeded(u32),
+
     #[error("Authentication timed out")]
     UserTimeout(Elapsed),
 }
@@ -87,6 +96,14 @@ impl AuthError {
         AuthErrorImpl::AuthFailed(user.into()).into()
     }
 
+    pub fn certificate_auth_failed(user: impl Into<Box<str>>) -> Self {
+        AuthErrorImpl::CertificateAuthFailed(user.into()).into()
+    }
+
+    pub fn jwt_auth_failed(user: impl Into<Box<str>>) -> Self {
+        AuthErrorImpl::JwtAuthFailed(user.into()).into()
+    }
+
     pub fn ip_address_not_allowed() -> Self {
         AuthErrorImpl::IpAddressNotAllowed.into()
     }
@@ -95,6 +112,10 @@ impl AuthError {
         AuthErrorImpl::TooManyConnections.into()
     }
 
+    pub fn connection_limit_exceeded(limit: u32) -> Self {
+        AuthErrorImpl::ConnectionLimitExceeded(limit).into()
+    }
+
     pub fn is_auth_failed(&self) -> bool {
         matches!(self.0.as_ref(), AuthErrorImpl::AuthFailed(_))
     }
@@ -118,15 +139,38 @@ impl UserFacingError for AuthError {
             GetAuthInfo(e) => e.to_string_client(),
             Sasl(e) => e.to_string_client(),
             AuthFailed(_) => self.to_string(),
+            CertificateAuthFailed(_) => self.to_string(),
+            JwtAuthFailed(_) => self.to_string(),
             BadAuthMethod(_) => self.to_string(),
             MalformedPassword(_) => self.to_string(),
             MissingEndpointName => self.to_string(),
             Io(_) => "Internal error".to_string(),
             IpAddressNotAllowed => self.to_string(),
             TooManyConnections => self.to_string(),
+            ConnectionLimitExceeded(_) => self.to_string(),
             UserTimeout(_) => self.to_string(),
         }
     }
+
+    fn sqlstate(&self) -> Option<&'static [u8; 5]> {
+        match self.0.as_ref() {
+            // same as postgres uses for a missing pg_hba.conf entry
+            AuthErrorImpl::IpAddressNotAllowed => {
+                Some(pq_proto::SQLSTATE_INVALID_AUTHORIZATION_SPECIFICATION)
+            }
+            // same as postgres uses for failed `cert` authentication
+            AuthErrorImpl::CertificateAuthFailed(_) => {
+                Some(pq_proto::SQLSTATE_INVALID_AUTHORIZATION_SPECIFICATION)
+            }
+            AuthErrorImpl::JwtAuthFailed(_) => {
+                Some(pq_proto::SQLSTATE_INVALID_AUTHORIZATION_SPECIFICATION)
+            }
+            AuthErrorImpl::ConnectionLimitExceeded(_) => {
+                Some(pq_proto::SQLSTATE_TOO_MANY_CONNECTIONS)
+            }
+            _ => None,
+        }
+    }
 }
 
 impl ReportableError for AuthError {
@@ -137,12 +181,15 @@ impl ReportableError for AuthError {
             GetAuthInfo(e) => e.get_error_kind(),
             Sasl(e) => e.get_error_kind(),
             AuthFailed(_) => crate::error::ErrorKind::User,
+            CertificateAuthFailed(_) => crate::error::ErrorKind::User,
+            JwtAuthFailed(_) => crate::error::ErrorKind::User,
             BadAuthMethod(_) => crate::error::ErrorKind::User,
             MalformedPassword(_) => crate::error::ErrorKind::User,
             MissingEndpointName => crate::error::ErrorKind::User,
             Io(_) => crate::error::ErrorKind::ClientDisconnect,
             IpAddressNotAllowed => crate::error::ErrorKind::User,
             TooManyConnections => crate::error::ErrorKind::RateLimit,
+            ConnectionLimitExceeded(_) => crate::error::ErrorKind::RateLimit,
             UserTimeout(_) => crate::error::ErrorKind::User,
         }
     }
@@ -1,7 +1,10 @@
+mod cert;
 mod classic;
 mod hacks;
+mod jwt;
 mod link;
 
+pub use jwt::JwkCache;
 pub use link::LinkAuthError;
 use tokio_postgres::config::AuthKeys;
 
@@ -12,6 +15,7 @@ use crate::console::errors::GetAuthInfoError;
 use crate::console::provider::{CachedRoleSecret, ConsoleBackend};
 use crate::console::{AuthSecret, NodeInfo};
 use crate::context::RequestMonitoring;
+use crate::metrics::count_ip_not_allowed;
 use crate::proxy::connect_compute::ComputeConnectBackend;
 use crate::proxy::NeonOptions;
 use crate::stream::Stream;
@@ -209,8 +213,26 @@ async fn auth_quirks(
 
     // check allowed list
     if !check_peer_addr_is_in_list(&ctx.peer_addr, &allowed_ips) {
+        info!(peer_addr = %ctx.peer_addr, "peer address is not in the allowed list");
+        count_ip_not_allowed(info.endpoint.as_str(), ctx.protocol);
         return Err(auth::AuthError::ip_address_not_allowed());
     }
+
+    // Clients which don't present a certificate can still use a password.
+    let client_cert_verifier = config
+        .client_cert_auth
+        .as_ref()
+        .and_then(|c| c.verifier(&info.endpoint));
+    let info = match client_cert_verifier {
+        Some(verifier) if unauthenticated_password.is_none() => {
+            match cert::authenticate(ctx, api, info, client, verifier).await? {
+                Ok(credentials) => return Ok(credentials),
+                Err(info) => info,
+            }
+        }
+        _ => info,
+    };
+
     let cached_secret = match maybe_secret {
         Some(secret) => secret,
         None => api.get_role_secret(ctx, &info).await?,
@@ -0,0 +1,146 @@
+use super::{ComputeCredentialKeys, ComputeCredentials, ComputeUserInfo};
+use crate::{
+    auth, console,
+    context::RequestMonitoring,
+    stream::{self, Stream},
+};
+use anyhow::Context;
+use rustls::{
+    server::{AllowAnyAuthenticatedClient, ClientCertVerifier},
+    Certificate,
+};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::info;
+use x509_parser::extensions::GeneralName;
+
+/// Authenticate the client with the certificate it presented during the TLS handshake.
+/// The certificate must be signed by the endpoint's certificate authority and issued
+/// for the requested role, either as its common name or as a DNS subject alternative name.
+///
+/// Gives `info` back if the client didn't present a certificate,
+/// so that the caller can fall back to password authentication.
+///
+/// The client has no password to log into the compute with, so the console provides it.
+pub async fn authenticate(
+    ctx: &mut RequestMonitoring,
+    api: &impl console::Api,
+    info: ComputeUserInfo,
+    client: &mut stream::PqStream<Stream<impl AsyncRead + AsyncWrite + Unpin>>,
+    verifier: &AllowAnyAuthenticatedClient,
+) -> auth::Result<Result<ComputeCredentials, ComputeUserInfo>> {
+    let Some((end_entity, intermediates)) = client
+        .get_ref()
+        .client_certificates()
+        .and_then(|certs| certs.split_first())
+    else {
+        return Ok(Err(info));
+    };
+
+    if let Err(e) = verify_certificate(verifier, end_entity, intermediates, &info.user) {
+        info!("client certificate authentication failed: {e:#}");
+        return Err(auth::AuthError::certificate_auth_failed(&*info.user));
+    }
+
+    let Some(password) = api.get_role_password(ctx, &info).await? else {
+        info!("role has no password to log into the compute with");
+        return Err(auth::AuthError::certificate_auth_failed(&*info.user));
+    };
+
+    ctx.set_auth_method(crate::context::AuthMethod::ClientCertificate);
+    client.write_message_noflush(&pq_proto::BeMessage::AuthenticationOk)?;
+
+    Ok(Ok(ComputeCredentials {
+        info,
+        keys: ComputeCredentialKeys::Password(password),
+    }))
+}
+
+fn verify_certificate(
+    verifier: &AllowAnyAuthenticatedClient,
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    role: &str,
+) -> anyhow::Result<()> {
+    verifier
+        .verify_client_cert(end_entity, intermediates, SystemTime::now())
+        .context("invalid certificate chain")?;
+
+    let names = certificate_names(end_entity)?;
+    anyhow::ensure!(
+        names.iter().any(|name| name == role),
+        "certificate is issued for {names:?}"
+    );
+
+    Ok(())
+}
+
+/// Common names and DNS subject alternative names of the certificate.
+fn certificate_names(cert: &Certificate) -> anyhow::Result<Vec<String>> {
+    let (_, cert) =
+        x509_parser::parse_x509_certificate(&cert.0).context("failed to parse certificate")?;
+
+    let mut names = Vec::new();
+    for cn in cert.subject().iter_common_name() {
+        names.push(cn.as_str()?.to_owned());
+    }
+    if let Some(san) = cert.subject_alternative_name()? {
+        for name in &san.value.general_names {
+            if let GeneralName::DNSName(name) = name {
+                names.push((*name).to_owned());
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verify_certificate;
+    use rustls::{server::AllowAnyAuthenticatedClient, Certificate, RootCertStore};
+
+    fn generate_ca() -> rcgen::Certificate {
+        let mut params = rcgen::CertificateParams::default();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        rcgen::Certificate::from_params(params).unwrap()
+    }
+
+    fn generate_client_cert(ca: &rcgen::Certificate, cn: &str, san: &str) -> Certificate {
+        let mut params = rcgen::CertificateParams::new(vec![san.to_owned()]);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, cn);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        Certificate(cert.serialize_der_with_signer(ca).unwrap())
+    }
+
+    fn verifier(ca: &rcgen::Certificate) -> AllowAnyAuthenticatedClient {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&Certificate(ca.serialize_der().unwrap()))
+            .unwrap();
+        AllowAnyAuthenticatedClient::new(roots)
+    }
+
+    #[test]
+    fn client_certificate_roles() {
+        let ca = generate_ca();
+        let verifier = verifier(&ca);
+        let cert = generate_client_cert(&ca, "alice", "service-a");
+
+        verify_certificate(&verifier, &cert, &[], "alice").unwrap();
+        verify_certificate(&verifier, &cert, &[], "service-a").unwrap();
+        verify_certificate(&verifier, &cert, &[], "bob").unwrap_err();
+    }
+
+    #[test]
+    fn client_certificate_unknown_ca() {
+        let ca = generate_ca();
+        let other_ca = generate_ca();
+        let cert = generate_client_cert(&other_ca, "alice", "service-a");
+
+        verify_certificate(&verifier(&ca), &cert, &[], "alice").unwrap_err();
+    }
+}
@@ -458,6 +458,49 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_read_replica() -> anyhow::Result<()> {
+        let common_names = Some(["localhost".into()].into());
+
+        // the endpoint name doesn't tell anything about the compute
+        let options = StartupMessageParams::new([("user", "john_doe")]);
+        let sni = Some("project-ro.localhost");
+        let mut ctx = RequestMonitoring::test();
+        let user_info =
+            ComputeUserInfoMaybeEndpoint::parse(&mut ctx, &options, sni, common_names.as_ref())?;
+        assert_eq!(user_info.endpoint_id.as_deref(), Some("project-ro"));
+        assert_eq!(user_info.options.get_cache_key("project-ro"), "project-ro");
+
+        // the hint in options takes precedence over the endpoint type
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            (
+                "options",
+                "project=project neon_read_replica:true neon_endpoint_type:read_write",
+            ),
+        ]);
+        let sni = Some("project.localhost");
+        let mut ctx = RequestMonitoring::test();
+        let user_info =
+            ComputeUserInfoMaybeEndpoint::parse(&mut ctx, &options, sni, common_names.as_ref())?;
+        assert_eq!(user_info.endpoint_id.as_deref(), Some("project"));
+        assert_eq!(
+            user_info.options.get_cache_key("project"),
+            "project endpoint_type:read_only"
+        );
+
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("options", "neon_read_replica:false"),
+        ]);
+        let mut ctx = RequestMonitoring::test();
+        let user_info =
+            ComputeUserInfoMaybeEndpoint::parse(&mut ctx, &options, sni, common_names.as_ref())?;
+        assert_eq!(user_info.options.get_cache_key("project"), "project");
+
+        Ok(())
+    }
+
     #[test]
     fn test_check_peer_addr_is_in_list() {
         fn check(v: serde_json::Value) -> bool {
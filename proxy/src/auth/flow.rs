@@ -2,7 +2,7 @@
 
 use super::{backend::ComputeCredentialKeys, AuthErrorImpl, PasswordHackPayload};
 use crate::{
-    config::TlsServerEndPoint,
+    config::TlsChannelBinding,
     console::AuthSecret,
     context::RequestMonitoring,
     sasl, scram,
@@ -69,32 +69,32 @@ pub struct AuthFlow<'a, S, State> {
     stream: &'a mut PqStream<Stream<S>>,
     /// State might contain ancillary data (see [`Self::begin`]).
     state: State,
-    tls_server_end_point: TlsServerEndPoint,
+    tls_channel_binding: TlsChannelBinding,
 }
 
 /// Initial state of the stream wrapper.
 impl<'a, S: AsyncRead + AsyncWrite + Unpin> AuthFlow<'a, S, Begin> {
     /// Create a new wrapper for client authentication.
     pub fn new(stream: &'a mut PqStream<Stream<S>>) -> Self {
-        let tls_server_end_point = stream.get_ref().tls_server_end_point();
+        let tls_channel_binding = stream.get_ref().tls_channel_binding();
 
         Self {
             stream,
             state: Begin,
-            tls_server_end_point,
+            tls_channel_binding,
         }
     }
 
     /// Move to the next step by sending auth method's name & params to client.
     pub async fn begin<M: AuthMethod>(self, method: M) -> io::Result<AuthFlow<'a, S, M>> {
         self.stream
-            .write_message(&method.first_message(self.tls_server_end_point.supported()))
+            .write_message(&method.first_message(self.tls_channel_binding.supported()))
             .await?;
 
         Ok(AuthFlow {
             stream: self.stream,
             state: method,
-            tls_server_end_point: self.tls_server_end_point,
+            tls_channel_binding: self.tls_channel_binding,
         })
     }
 }
@@ -168,7 +168,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AuthFlow<'_, S, Scram<'_>> {
             .authenticate(scram::Exchange::new(
                 secret,
                 rand::random,
-                self.tls_server_end_point,
+                self.tls_channel_binding,
             ))
             .await?;
 
@@ -199,7 +199,7 @@ pub(crate) fn validate_password_and_exchange(
             let outcome = crate::scram::exchange(
                 &scram_secret,
                 sasl_client,
-                crate::config::TlsServerEndPoint::Undefined,
+                crate::config::TlsChannelBinding::NONE,
             )?;
 
             let client_key = match outcome {
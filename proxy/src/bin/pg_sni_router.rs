@@ -6,13 +6,12 @@
 use std::{net::SocketAddr, sync::Arc};
 
 use futures::future::Either;
-use itertools::Itertools;
-use proxy::config::TlsServerEndPoint;
+use proxy::config::CertResolver;
 use proxy::context::RequestMonitoring;
 use proxy::proxy::run_until_cancelled;
 use tokio::net::TcpListener;
 
-use anyhow::{anyhow, bail, ensure, Context};
+use anyhow::{anyhow, bail, Context};
 use clap::{self, Arg};
 use futures::TryFutureExt;
 use proxy::console::messages::MetricsAuxInfo;
@@ -50,6 +49,11 @@ fn cli() -> clap::Command {
                 .help("path to TLS cert for client postgres connections")
                 .required(true),
         )
+        .arg(
+            Arg::new("certs-dir")
+                .long("certs-dir")
+                .help("path to directory with extra TLS certificates, selected by SNI"),
+        )
         .arg(
             Arg::new("dest")
                 .short('d')
@@ -69,50 +73,26 @@ async fn main() -> anyhow::Result<()> {
     let destination: String = args.get_one::<String>("dest").unwrap().parse()?;
 
     // Configure TLS
-    let (tls_config, tls_server_end_point): (Arc<rustls::ServerConfig>, TlsServerEndPoint) = match (
+    let cert_resolver = match (
         args.get_one::<String>("tls-key"),
         args.get_one::<String>("tls-cert"),
     ) {
-        (Some(key_path), Some(cert_path)) => {
-            let key = {
-                let key_bytes = std::fs::read(key_path).context("TLS key file")?;
-                let mut keys = rustls_pemfile::pkcs8_private_keys(&mut &key_bytes[..])
-                    .context(format!("Failed to read TLS keys at '{key_path}'"))?;
-
-                ensure!(keys.len() == 1, "keys.len() = {} (should be 1)", keys.len());
-                keys.pop().map(rustls::PrivateKey).unwrap()
-            };
-
-            let cert_chain_bytes = std::fs::read(cert_path)
-                .context(format!("Failed to read TLS cert file at '{cert_path}.'"))?;
-
-            let cert_chain = {
-                rustls_pemfile::certs(&mut &cert_chain_bytes[..])
-                    .context(format!(
-                        "Failed to read TLS certificate chain from bytes from file at '{cert_path}'."
-                    ))?
-                    .into_iter()
-                    .map(rustls::Certificate)
-                    .collect_vec()
-            };
-
-            // needed for channel bindings
-            let first_cert = cert_chain.first().context("missing certificate")?;
-            let tls_server_end_point = TlsServerEndPoint::new(first_cert)?;
-
-            let tls_config = rustls::ServerConfig::builder()
-                .with_safe_default_cipher_suites()
-                .with_safe_default_kx_groups()
-                .with_protocol_versions(&[&rustls::version::TLS13, &rustls::version::TLS12])?
-                .with_no_client_auth()
-                .with_single_cert(cert_chain, key)?
-                .into();
-
-            (tls_config, tls_server_end_point)
-        }
+        (Some(key_path), Some(cert_path)) => Arc::new(CertResolver::load(
+            key_path,
+            cert_path,
+            args.get_one::<String>("certs-dir"),
+        )?),
         _ => bail!("tls-key and tls-cert must be specified"),
     };
 
+    let tls_config = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13, &rustls::version::TLS12])?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(RouterCertResolver(cert_resolver.clone())))
+        .into();
+
     // Start listening for incoming client connections
     let proxy_address: SocketAddr = args.get_one::<String>("listen").unwrap().parse()?;
     info!("Starting sni router on {proxy_address}");
@@ -123,11 +103,18 @@ async fn main() -> anyhow::Result<()> {
     let main = tokio::spawn(task_main(
         Arc::new(destination),
         tls_config,
-        tls_server_end_point,
+        cert_resolver.clone(),
         proxy_listener,
         cancellation_token.clone(),
     ));
-    let signals_task = tokio::spawn(proxy::handle_signals(cancellation_token));
+    let signals_task = tokio::spawn(proxy::handle_signals(cancellation_token, move || {
+        let cert_resolver = cert_resolver.clone();
+        async move {
+            if let Err(e) = cert_resolver.reload().await {
+                error!("failed to reload TLS certificates: {e:#}");
+            }
+        }
+    }));
 
     // the signal task cant ever succeed.
     // the main task can error, or can succeed on cancellation.
@@ -142,10 +129,24 @@ async fn main() -> anyhow::Result<()> {
     match signal {}
 }
 
+/// Unlike the proxy, the router serves the default certificate to unknown domains.
+struct RouterCertResolver(Arc<CertResolver>);
+
+impl rustls::server::ResolvesServerCert for RouterCertResolver {
+    fn resolve(
+        &self,
+        client_hello: rustls::server::ClientHello,
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        self.0
+            .resolve_or_default(client_hello.server_name())
+            .map(|x| x.0)
+    }
+}
+
 async fn task_main(
     dest_suffix: Arc<String>,
     tls_config: Arc<rustls::ServerConfig>,
-    tls_server_end_point: TlsServerEndPoint,
+    cert_resolver: Arc<CertResolver>,
     listener: tokio::net::TcpListener,
     cancellation_token: CancellationToken,
 ) -> anyhow::Result<()> {
@@ -162,6 +163,7 @@ async fn task_main(
 
         let session_id = uuid::Uuid::new_v4();
         let tls_config = Arc::clone(&tls_config);
+        let cert_resolver = Arc::clone(&cert_resolver);
         let dest_suffix = Arc::clone(&dest_suffix);
 
         connections.spawn(
@@ -173,14 +175,7 @@ async fn task_main(
                 info!(%peer_addr, "serving");
                 let mut ctx =
                     RequestMonitoring::new(session_id, peer_addr.ip(), "sni_router", "sni");
-                handle_client(
-                    &mut ctx,
-                    dest_suffix,
-                    tls_config,
-                    tls_server_end_point,
-                    socket,
-                )
-                .await
+                handle_client(&mut ctx, dest_suffix, tls_config, &cert_resolver, socket).await
             }
             .unwrap_or_else(|e| {
                 // Acknowledge that the task has finished with an error.
@@ -204,7 +199,7 @@ const ERR_INSECURE_CONNECTION: &str = "connection is insecure (try using `sslmod
 async fn ssl_handshake<S: AsyncRead + AsyncWrite + Unpin>(
     raw_stream: S,
     tls_config: Arc<rustls::ServerConfig>,
-    tls_server_end_point: TlsServerEndPoint,
+    cert_resolver: &CertResolver,
 ) -> anyhow::Result<Stream<S>> {
     let mut stream = PqStream::new(Stream::from_raw(raw_stream));
 
@@ -230,8 +225,15 @@ async fn ssl_handshake<S: AsyncRead + AsyncWrite + Unpin>(
                 bail!("data is sent before server replied with EncryptionResponse");
             }
 
+            let tls = raw.upgrade(tls_config).await?;
+
+            // needed for channel bindings
+            let (_, tls_server_end_point) = cert_resolver
+                .resolve_or_default(tls.get_ref().1.server_name())
+                .context("missing certificate")?;
+
             Ok(Stream::Tls {
-                tls: Box::new(raw.upgrade(tls_config).await?),
+                tls: Box::new(tls),
                 tls_server_end_point,
             })
         }
@@ -251,10 +253,10 @@ async fn handle_client(
     ctx: &mut RequestMonitoring,
     dest_suffix: Arc<String>,
     tls_config: Arc<rustls::ServerConfig>,
-    tls_server_end_point: TlsServerEndPoint,
+    cert_resolver: &CertResolver,
     stream: impl AsyncRead + AsyncWrite + Unpin,
 ) -> anyhow::Result<()> {
-    let tls_stream = ssl_handshake(stream, tls_config, tls_server_end_point).await?;
+    let tls_stream = ssl_handshake(stream, tls_config, cert_resolver).await?;
 
     // Cut off first part of the SNI domain
     // We receive required destination details in the format of
@@ -1,21 +1,31 @@
 use futures::future::Either;
 use proxy::auth;
 use proxy::auth::backend::MaybeOwned;
+use proxy::cancellation::CancelMap;
 use proxy::config::AuthenticationConfig;
 use proxy::config::CacheOptions;
+use proxy::config::ClientCertAuthConfig;
 use proxy::config::HttpConfig;
 use proxy::config::ProjectInfoCacheOptions;
 use proxy::console;
+use proxy::context::audit::AuditLogArgs;
 use proxy::context::parquet::ParquetUploadArgs;
 use proxy::http;
+use proxy::rate_limiter::CircuitBreakerConfig;
 use proxy::rate_limiter::EndpointRateLimiter;
+use proxy::rate_limiter::LeakyBucketConfig;
 use proxy::rate_limiter::RateBucketInfo;
 use proxy::rate_limiter::RateLimiterConfig;
+use proxy::rate_limiter::WakeComputeCircuitBreaker;
+use proxy::rate_limiter::WakeComputeRateLimiter;
+use proxy::redis::cancellation::{self, CancellationPublisher};
 use proxy::redis::notifications;
+use proxy::redis::rate_limiter;
 use proxy::serverless::GlobalConnPoolOptions;
 use proxy::usage_metrics;
 
 use anyhow::bail;
+use camino::Utf8PathBuf;
 use proxy::config::{self, ProxyConfig};
 use proxy::serverless;
 use std::net::SocketAddr;
@@ -24,8 +34,10 @@ use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
+use tracing::error;
 use tracing::info;
 use tracing::warn;
+use utils::auth::{JwtAuth, SwappableJwtAuth};
 use utils::{project_build_tag, project_git_version, sentry_init::init_sentry};
 
 project_git_version!(GIT_VERSION);
@@ -62,6 +74,10 @@ struct ProxyCliArgs {
     /// listen for incoming http connections (metrics, etc) on ip:port
     #[clap(long, default_value = "127.0.0.1:7001")]
     http: String,
+    /// path to the public key (or a directory of them) that tokens for the routes of the http
+    /// listener which change state are verified with; those routes are disabled without it
+    #[clap(long)]
+    http_auth_public_key_path: Option<Utf8PathBuf>,
     /// listen for incoming wss connections on ip:port
     #[clap(long)]
     wss: Option<String>,
@@ -88,6 +104,19 @@ struct ProxyCliArgs {
     /// path to directory with TLS certificates for client postgres connections
     #[clap(long)]
     certs_dir: Option<String>,
+    /// path to directory with certificate authorities for client certificate authentication,
+    /// as `<endpoint id>/ca.crt` files
+    #[clap(long)]
+    client_ca_dir: Option<String>,
+    /// how many TLS sessions with clients to keep for resumption by session ID, 0 to disable it
+    #[clap(long, default_value_t = 10_000)]
+    tls_session_cache_size: usize,
+    /// issue TLS session tickets, so that clients can resume their sessions
+    #[clap(long, default_value_t = true, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    tls_session_tickets: bool,
+    /// how long to wait for the existing connections to close on shutdown (waits indefinitely if not set)
+    #[clap(long, value_parser = humantime::parse_duration)]
+    drain_timeout: Option<tokio::time::Duration>,
     /// timeout for the TLS handshake
     #[clap(long, default_value = "15s", value_parser = humantime::parse_duration)]
     handshake_timeout: tokio::time::Duration,
@@ -114,6 +143,13 @@ struct ProxyCliArgs {
     /// Require that all incoming requests have a Proxy Protocol V2 packet **and** have an IP address associated.
     #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
     require_client_ip: bool,
+    /// Log how long each phase of a client connection took, for debugging latency issues.
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    log_latency_breakdown: bool,
+    /// Startup parameters which are forwarded from the client to the compute, e.g. `search_path,statement_timeout`.
+    /// `options` and `application_name` are always forwarded.
+    #[clap(long, value_delimiter = ',')]
+    startup_params_allow_list: Vec<String>,
     /// Disable dynamic rate limiter and store the metrics to ensure its production behaviour.
     #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
     disable_dynamic_rate_limiter: bool,
@@ -129,6 +165,24 @@ struct ProxyCliArgs {
     /// Can be given multiple times for different bucket sizes.
     #[clap(long, default_values_t = RateBucketInfo::DEFAULT_SET)]
     endpoint_rps_limit: Vec<RateBucketInfo>,
+    /// Rate at which cache misses of `wake_compute` for a single endpoint are
+    /// allowed to reach the control plane, in requests per second (use `0` to disable).
+    #[clap(long, default_value_t = 50.0)]
+    wake_compute_limit_rps: f64,
+    /// Number of `wake_compute` requests for a single endpoint allowed in a burst.
+    #[clap(long, default_value_t = 300.0)]
+    wake_compute_limit_max: f64,
+    /// Apply the `wake_compute` limits per endpoint and client IP address
+    /// rather than per endpoint.
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    wake_compute_limit_per_ip: bool,
+    /// Number of consecutive control plane failures of `wake_compute` for a single endpoint
+    /// after which further requests fail fast (use `0` to disable).
+    #[clap(long, default_value_t = 10)]
+    wake_compute_circuit_breaker_threshold: u32,
+    /// How long `wake_compute` requests for an endpoint fail fast before the control plane is tried again.
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    wake_compute_circuit_breaker_cooldown: tokio::time::Duration,
     /// Initial limit for dynamic rate limiter. Makes sense only if `rate_limit_algorithm` is *not* `None`.
     #[clap(long, default_value_t = 100)]
     initial_limit: usize,
@@ -146,12 +200,24 @@ struct ProxyCliArgs {
     /// redis url for notifications.
     #[clap(long)]
     redis_notifications: Option<String>,
+    /// redis url for forwarding query cancellation requests between proxy instances.
+    #[clap(long)]
+    redis_cancellation: Option<String>,
+    /// redis url for sharing endpoint rate limits between proxy instances.
+    #[clap(long)]
+    redis_rate_limiter: Option<String>,
+    /// how often the endpoint rate limits are synced with redis
+    #[clap(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    redis_rate_limiter_sync_interval: tokio::time::Duration,
     /// cache for `project_info` (use `size=0` to disable)
     #[clap(long, default_value = config::ProjectInfoCacheOptions::CACHE_DEFAULT_OPTIONS)]
     project_info_cache: String,
 
     #[clap(flatten)]
     parquet_upload: ParquetUploadArgs,
+
+    #[clap(flatten)]
+    audit_log: AuditLogArgs,
 }
 
 #[derive(clap::Args, Clone, Copy, Debug)]
@@ -172,6 +238,10 @@ struct SqlOverHttpArgs {
     #[clap(long, default_value_t = 20000)]
     sql_over_http_pool_max_total_conns: usize,
 
+    /// Upper bound of the per endpoint connection limits set through the http admin API
+    #[clap(long, default_value_t = 1000)]
+    sql_over_http_pool_max_conns_per_endpoint_cap: usize,
+
     /// How long pooled connections should remain idle for before closing
     #[clap(long, default_value = "5m", value_parser = humantime::parse_duration)]
     sql_over_http_idle_timeout: tokio::time::Duration,
@@ -186,6 +256,20 @@ struct SqlOverHttpArgs {
     /// increase memory used by the pool
     #[clap(long, default_value_t = 128)]
     sql_over_http_pool_shards: usize,
+
+    /// How long a transaction opened with Neon-Transaction-Begin may stay idle
+    /// between requests before it's rolled back
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    sql_over_http_txn_timeout: tokio::time::Duration,
+
+    /// How often to ping websocket clients, so that idle tunnels aren't dropped
+    /// by intermediaries (use `0s` to disable)
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    websocket_ping_interval: tokio::time::Duration,
+
+    /// Disconnect websocket clients which didn't respond to pings for this long
+    #[clap(long, default_value = "90s", value_parser = humantime::parse_duration)]
+    websocket_ping_timeout: tokio::time::Duration,
 }
 
 #[tokio::main]
@@ -214,6 +298,14 @@ async fn main() -> anyhow::Result<()> {
     let http_address: SocketAddr = args.http.parse()?;
     info!("Starting http on {http_address}");
     let http_listener = TcpListener::bind(http_address).await?.into_std()?;
+    let http_auth = match &args.http_auth_public_key_path {
+        Some(path) => {
+            info!("Loading http auth public key from {path}");
+            let auth = JwtAuth::from_key_path(path)?;
+            Some(Arc::new(SwappableJwtAuth::new(auth)))
+        }
+        None => None,
+    };
 
     let mgmt_address: SocketAddr = args.mgmt.parse()?;
     info!("Starting mgmt on {mgmt_address}");
@@ -225,6 +317,15 @@ async fn main() -> anyhow::Result<()> {
     let cancellation_token = CancellationToken::new();
 
     let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new(&config.endpoint_rps_limit));
+    // sql over http connection pool, limits are adjustable through the http api
+    let conn_pool = serverless::GlobalConnPool::new(&config.http_config);
+
+    let cancellation_publisher = args
+        .redis_cancellation
+        .as_deref()
+        .map(|url| CancellationPublisher::new(url, args.region.clone()))
+        .transpose()?;
+    let cancel_map = Arc::new(CancelMap::new(cancellation_publisher));
 
     // client facing tasks. these will exit on error or on cancellation
     // cancellation returns Ok(())
@@ -234,6 +335,7 @@ async fn main() -> anyhow::Result<()> {
         proxy_listener,
         cancellation_token.clone(),
         endpoint_rate_limiter.clone(),
+        cancel_map.clone(),
     ));
 
     // TODO: rename the argument to something like serverless.
@@ -248,6 +350,8 @@ async fn main() -> anyhow::Result<()> {
             serverless_listener,
             cancellation_token.clone(),
             endpoint_rate_limiter.clone(),
+            conn_pool.clone(),
+            cancel_map.clone(),
         ));
     }
 
@@ -255,17 +359,58 @@ async fn main() -> anyhow::Result<()> {
         cancellation_token.clone(),
         args.parquet_upload,
     ));
+    client_tasks.spawn(proxy::context::audit::worker(
+        cancellation_token.clone(),
+        args.audit_log,
+    ));
 
     // maintenance tasks. these never return unless there's an error
     let mut maintenance_tasks = JoinSet::new();
-    maintenance_tasks.spawn(proxy::handle_signals(cancellation_token));
-    maintenance_tasks.spawn(http::health_server::task_main(http_listener));
+    maintenance_tasks.spawn(proxy::drain_deadline(
+        cancellation_token.clone(),
+        args.drain_timeout,
+    ));
+    maintenance_tasks.spawn(proxy::handle_signals(
+        cancellation_token.clone(),
+        move || async move {
+            if let Some(tls_config) = &config.tls_config {
+                if let Err(e) = tls_config.cert_resolver.reload().await {
+                    error!("failed to reload TLS certificates: {e:#}");
+                }
+            }
+        },
+    ));
+    maintenance_tasks.spawn(http::health_server::task_main(
+        http_listener,
+        conn_pool,
+        cancellation_token,
+        http_auth,
+    ));
     maintenance_tasks.spawn(console::mgmt::task_main(mgmt_listener));
 
     if let Some(metrics_config) = &config.metric_collection {
         maintenance_tasks.spawn(usage_metrics::task_main(metrics_config));
     }
 
+    if let Some(url) = &args.redis_cancellation {
+        info!("Starting redis cancellation listener ({url})");
+        maintenance_tasks.spawn(cancellation::task_main(
+            url.clone(),
+            args.region.clone(),
+            cancel_map,
+        ));
+    }
+
+    if let Some(url) = &args.redis_rate_limiter {
+        info!("Starting redis endpoint rate limiter sync ({url})");
+        maintenance_tasks.spawn(rate_limiter::task_main(
+            url.clone(),
+            args.region.clone(),
+            endpoint_rate_limiter,
+            args.redis_rate_limiter_sync_interval,
+        ));
+    }
+
     if let auth::BackendType::Console(api, _) = &config.auth_backend {
         if let proxy::console::provider::ConsoleBackend::Console(api) = &**api {
             let cache = api.caches.project_info.clone();
@@ -308,6 +453,9 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             key_path,
             cert_path,
             args.certs_dir.as_ref(),
+            args.client_ca_dir.is_some(),
+            args.tls_session_cache_size,
+            args.tls_session_tickets,
         )?),
         (None, None) => None,
         _ => bail!("either both or neither tls-key and tls-cert must be specified"),
@@ -370,7 +518,44 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             let url = args.auth_endpoint.parse()?;
             let endpoint = http::Endpoint::new(url, http::new_client(rate_limiter_config));
 
-            let api = console::provider::neon::Api::new(endpoint, caches, locks);
+            let wake_compute_limiter = if args.wake_compute_limit_rps > 0.0 {
+                if args.wake_compute_limit_max < 1.0 {
+                    bail!("wake-compute-limit-max must allow at least one request");
+                }
+                let config = LeakyBucketConfig::new(
+                    args.wake_compute_limit_rps,
+                    args.wake_compute_limit_max,
+                );
+                info!(
+                    ?config,
+                    per_ip = args.wake_compute_limit_per_ip,
+                    "Using wake_compute rate limiter"
+                );
+                let limiter = WakeComputeRateLimiter::new(config, args.wake_compute_limit_per_ip);
+                Some(&*Box::leak(Box::new(limiter)))
+            } else {
+                None
+            };
+
+            let wake_compute_circuit_breaker = if args.wake_compute_circuit_breaker_threshold > 0 {
+                let config = CircuitBreakerConfig {
+                    threshold: args.wake_compute_circuit_breaker_threshold,
+                    cooldown: args.wake_compute_circuit_breaker_cooldown,
+                };
+                info!(?config, "Using wake_compute circuit breaker");
+                let breaker = WakeComputeCircuitBreaker::new(config);
+                Some(&*Box::leak(Box::new(breaker)))
+            } else {
+                None
+            };
+
+            let api = console::provider::neon::Api::new(
+                endpoint,
+                caches,
+                locks,
+                wake_compute_limiter,
+                wake_compute_circuit_breaker,
+            );
             let api = console::provider::ConsoleBackend::Console(api);
             auth::BackendType::Console(MaybeOwned::Owned(api), ())
         }
@@ -395,10 +580,26 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             idle_timeout: args.sql_over_http.sql_over_http_idle_timeout,
             opt_in: args.sql_over_http.sql_over_http_pool_opt_in,
             max_total_conns: args.sql_over_http.sql_over_http_pool_max_total_conns,
+            max_conns_per_endpoint_cap: args
+                .sql_over_http
+                .sql_over_http_pool_max_conns_per_endpoint_cap,
         },
+        txn_timeout: args.sql_over_http.sql_over_http_txn_timeout,
+        websocket_ping_interval: Some(args.sql_over_http.websocket_ping_interval)
+            .filter(|interval| !interval.is_zero()),
+        websocket_ping_timeout: args.sql_over_http.websocket_ping_timeout,
+    };
+    let client_cert_auth = match &args.client_ca_dir {
+        Some(_) if tls_config.is_none() => {
+            bail!("client-ca-dir requires tls-key and tls-cert to be specified")
+        }
+        Some(dir) => Some(ClientCertAuthConfig::load(dir)?),
+        None => None,
     };
+
     let authentication_config = AuthenticationConfig {
         scram_protocol_timeout: args.scram_protocol_timeout,
+        client_cert_auth,
     };
 
     let mut endpoint_rps_limit = args.endpoint_rps_limit.clone();
@@ -415,6 +616,8 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         disable_ip_check_for_http: args.disable_ip_check_for_http,
         endpoint_rps_limit,
         handshake_timeout: args.handshake_timeout,
+        log_latency_breakdown: args.log_latency_breakdown,
+        startup_params_allow_list: args.startup_params_allow_list.clone(),
         // TODO: add this argument
         region: args.region.clone(),
     }));
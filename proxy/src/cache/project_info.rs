@@ -52,6 +52,8 @@ impl<T> From<T> for Entry<T> {
 struct EndpointInfo {
     secret: std::collections::HashMap<RoleNameInt, Entry<Option<AuthSecret>>>,
     allowed_ips: Option<Entry<Arc<Vec<IpPattern>>>>,
+    /// Comes together with the other auth info, so it isn't invalidated separately.
+    max_client_connections: Option<u32>,
 }
 
 impl EndpointInfo {
@@ -104,6 +106,7 @@ impl EndpointInfo {
 /// Cache for project info.
 /// This is used to cache auth data for endpoints.
 /// Invalidation is done by console notifications or by TTL (if console notifications are disabled).
+/// Expired (but not invalidated) entries are kept for a while longer to be used if the console is unavailable.
 ///
 /// We also store endpoint-to-project mapping in the cache, to be able to access per-endpoint data.
 /// One may ask, why the data is stored per project, when on the user request there is only data about the endpoint available?
@@ -212,6 +215,31 @@ impl ProjectInfoCacheImpl {
         }
         Some(Cached::new_uncached(value))
     }
+    /// Same as [`Self::get_role_secret`], but also returns entries which expired less than
+    /// `stale_ttl` ago. Should only be used if the console is unavailable.
+    pub fn get_stale_role_secret(
+        &self,
+        endpoint_id: &EndpointId,
+        role_name: &RoleName,
+    ) -> Option<Cached<&Self, Option<AuthSecret>>> {
+        let endpoint_id = EndpointIdInt::get(endpoint_id)?;
+        let role_name = RoleNameInt::get(role_name)?;
+        let endpoint_info = self.cache.get(&endpoint_id)?;
+        let (value, _) =
+            endpoint_info.get_role_secret(role_name, self.get_stale_valid_since(), None)?;
+        Some(Cached::new_uncached(value))
+    }
+    /// Same as [`Self::get_allowed_ips`], but also returns entries which expired less than
+    /// `stale_ttl` ago. Should only be used if the console is unavailable.
+    pub fn get_stale_allowed_ips(
+        &self,
+        endpoint_id: &EndpointId,
+    ) -> Option<Cached<&Self, Arc<Vec<IpPattern>>>> {
+        let endpoint_id = EndpointIdInt::get(endpoint_id)?;
+        let endpoint_info = self.cache.get(&endpoint_id)?;
+        let (value, _) = endpoint_info.get_allowed_ips(self.get_stale_valid_since(), None)?;
+        Some(Cached::new_uncached(value))
+    }
     pub fn insert_role_secret(
         &self,
         project_id: &ProjectId,
@@ -247,6 +275,17 @@ impl ProjectInfoCacheImpl {
         self.insert_project2endpoint(project_id, endpoint_id);
         self.cache.entry(endpoint_id).or_default().allowed_ips = Some(allowed_ips.into());
     }
+    pub fn insert_max_client_connections(&self, endpoint_id: &EndpointId, limit: Option<u32>) {
+        let endpoint_id = EndpointIdInt::from(endpoint_id);
+        // Only endpoints with the other auth info cached are tracked.
+        if let Some(mut entry) = self.cache.get_mut(&endpoint_id) {
+            entry.max_client_connections = limit;
+        }
+    }
+    pub fn get_max_client_connections(&self, endpoint_id: &EndpointId) -> Option<u32> {
+        let endpoint_id = EndpointIdInt::get(endpoint_id)?;
+        self.cache.get(&endpoint_id)?.max_client_connections
+    }
     fn insert_project2endpoint(&self, project_id: ProjectIdInt, endpoint_id: EndpointIdInt) {
         if let Some(mut endpoints) = self.project2ep.get_mut(&project_id) {
             endpoints.insert(endpoint_id);
@@ -256,7 +295,9 @@ impl ProjectInfoCacheImpl {
         }
     }
     fn get_cache_times(&self) -> (Instant, Option<Instant>) {
-        let mut valid_since = Instant::now() - self.config.ttl;
+        let mut valid_since = Instant::now()
+            .checked_sub(self.config.ttl)
+            .unwrap_or(self.start_time);
         // Only ignore cache if ttl is disabled.
         let ttl_disabled_since_us = self
             .ttl_disabled_since_us
@@ -272,6 +313,15 @@ impl ProjectInfoCacheImpl {
         (valid_since, ignore_cache_since)
     }
 
+    fn get_stale_valid_since(&self) -> Instant {
+        let (valid_since, _) = self.get_cache_times();
+        // Shortly after boot, the monotonic clock may not go back that far. All
+        // the entries were inserted since we started anyway.
+        valid_since
+            .checked_sub(self.config.stale_ttl)
+            .unwrap_or(self.start_time)
+    }
+
     pub async fn gc_worker(&self) -> anyhow::Result<Infallible> {
         let mut interval =
             tokio::time::interval(self.config.gc_interval / (self.cache.shards().len()) as u32);
@@ -369,6 +419,7 @@ mod tests {
             max_roles: 2,
             ttl: Duration::from_secs(1),
             gc_interval: Duration::from_secs(600),
+            stale_ttl: Duration::ZERO,
         });
         let project_id = "project".into();
         let endpoint_id = "endpoint".into();
@@ -424,6 +475,7 @@ mod tests {
             max_roles: 2,
             ttl: Duration::from_secs(1),
             gc_interval: Duration::from_secs(600),
+            stale_ttl: Duration::ZERO,
         }));
         cache.clone().disable_ttl();
         tokio::time::advance(Duration::from_secs(2)).await;
@@ -481,6 +533,7 @@ mod tests {
             max_roles: 2,
             ttl: Duration::from_secs(1),
             gc_interval: Duration::from_secs(600),
+            stale_ttl: Duration::ZERO,
         }));
 
         let project_id = "project".into();
@@ -530,4 +583,80 @@ mod tests {
         assert!(!cached.cached());
         assert_eq!(cached.value, allowed_ips);
     }
+
+    #[tokio::test]
+    async fn test_stale_entries() {
+        tokio::time::pause();
+        let cache = Arc::new(ProjectInfoCacheImpl::new(ProjectInfoCacheOptions {
+            size: 2,
+            max_roles: 2,
+            ttl: Duration::from_secs(1),
+            gc_interval: Duration::from_secs(600),
+            stale_ttl: Duration::from_secs(10),
+        }));
+
+        let project_id: ProjectId = "project".into();
+        let endpoint_id = "endpoint".into();
+        let user1: RoleName = "user1".into();
+        let user2: RoleName = "user2".into();
+        let secret1 = Some(AuthSecret::Scram(ServerSecret::mock(
+            user1.as_str(),
+            [1; 32],
+        )));
+        let secret2 = Some(AuthSecret::Scram(ServerSecret::mock(
+            user2.as_str(),
+            [2; 32],
+        )));
+        let allowed_ips = Arc::new(vec!["127.0.0.1".parse().unwrap()]);
+        cache.insert_role_secret(&project_id, &endpoint_id, &user1, secret1.clone());
+        cache.insert_role_secret(&project_id, &endpoint_id, &user2, secret2.clone());
+        cache.insert_allowed_ips(&project_id, &endpoint_id, allowed_ips.clone());
+
+        // Expired entries can still be used if the console is unavailable.
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(cache.get_role_secret(&endpoint_id, &user1).is_none());
+        let cached = cache.get_stale_role_secret(&endpoint_id, &user1).unwrap();
+        assert!(!cached.cached());
+        assert_eq!(cached.value, secret1);
+        let cached = cache.get_stale_allowed_ips(&endpoint_id).unwrap();
+        assert_eq!(cached.value, allowed_ips);
+
+        // Invalidated entries can't.
+        cache.invalidate_role_secret_for_project((&project_id).into(), (&user1).into());
+        assert!(cache.get_stale_role_secret(&endpoint_id, &user1).is_none());
+        assert!(cache.get_stale_role_secret(&endpoint_id, &user2).is_some());
+
+        // Neither can the ones which are too old.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(cache.get_stale_role_secret(&endpoint_id, &user2).is_none());
+        assert!(cache.get_stale_allowed_ips(&endpoint_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stale_ttl_longer_than_uptime() {
+        tokio::time::pause();
+        // Longer than the monotonic clock can go back.
+        let cache = ProjectInfoCacheImpl::new(ProjectInfoCacheOptions {
+            size: 2,
+            max_roles: 2,
+            ttl: Duration::from_secs(1),
+            gc_interval: Duration::from_secs(600),
+            stale_ttl: Duration::from_secs(u64::MAX / 4),
+        });
+
+        let project_id: ProjectId = "project".into();
+        let endpoint_id = "endpoint".into();
+        let user: RoleName = "user".into();
+        let secret = Some(AuthSecret::Scram(ServerSecret::mock(
+            user.as_str(),
+            [1; 32],
+        )));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        cache.insert_role_secret(&project_id, &endpoint_id, &user, secret.clone());
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(cache.get_role_secret(&endpoint_id, &user).is_none());
+        let cached = cache.get_stale_role_secret(&endpoint_id, &user).unwrap();
+        assert_eq!(cached.value, secret);
+    }
 }
@@ -6,11 +6,15 @@ use tokio::net::TcpStream;
 use tokio_postgres::{CancelToken, NoTls};
 use tracing::info;
 
-use crate::error::ReportableError;
+use crate::{error::ReportableError, redis::cancellation::CancellationPublisher};
 
 /// Enables serving `CancelRequest`s.
 #[derive(Default)]
-pub struct CancelMap(DashMap<CancelKeyData, Option<CancelClosure>>);
+pub struct CancelMap {
+    map: DashMap<CancelKeyData, Option<CancelClosure>>,
+    /// Forwards requests for sessions of other proxy instances, if set.
+    publisher: Option<CancellationPublisher>,
+}
 
 #[derive(Debug, Error)]
 pub enum CancelError {
@@ -33,10 +37,32 @@ impl ReportableError for CancelError {
 }
 
 impl CancelMap {
+    pub fn new(publisher: Option<CancellationPublisher>) -> Self {
+        Self {
+            map: DashMap::new(),
+            publisher,
+        }
+    }
+
     /// Cancel a running query for the corresponding connection.
     pub async fn cancel_session(&self, key: CancelKeyData) -> Result<(), CancelError> {
+        if let Some(publisher) = &self.publisher {
+            if !self.map.contains_key(&key) {
+                info!("query cancellation key {key} not found, forwarding it to other proxies");
+                if let Err(e) = publisher.publish(key).await {
+                    tracing::warn!("failed to forward query cancellation request: {e:#}");
+                }
+                return Ok(());
+            }
+        }
+
+        self.cancel_session_local(key).await
+    }
+
+    /// Cancel a running query if the connection is served by this proxy instance.
+    pub async fn cancel_session_local(&self, key: CancelKeyData) -> Result<(), CancelError> {
         // NB: we should immediately release the lock after cloning the token.
-        let Some(cancel_closure) = self.0.get(&key).and_then(|x| x.clone()) else {
+        let Some(cancel_closure) = self.map.get(&key).and_then(|x| x.clone()) else {
             tracing::warn!("query cancellation key not found: {key}");
             return Ok(());
         };
@@ -57,7 +83,7 @@ impl CancelMap {
 
             // Random key collisions are unlikely to happen here, but they're still possible,
             // which is why we have to take care not to rewrite an existing key.
-            match self.0.entry(key) {
+            match self.map.entry(key) {
                 dashmap::mapref::entry::Entry::Occupied(_) => continue,
                 dashmap::mapref::entry::Entry::Vacant(e) => {
                     e.insert(None);
@@ -75,12 +101,12 @@ impl CancelMap {
 
     #[cfg(test)]
     fn contains(&self, session: &Session) -> bool {
-        self.0.contains_key(&session.key)
+        self.map.contains_key(&session.key)
     }
 
     #[cfg(test)]
     fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.map.is_empty()
     }
 }
 
@@ -123,7 +149,7 @@ impl Session {
     /// This enables query cancellation in `crate::proxy::prepare_client_connection`.
     pub fn enable_query_cancellation(&self, cancel_closure: CancelClosure) -> CancelKeyData {
         info!("enabling query cancellation for this session");
-        self.cancel_map.0.insert(self.key, Some(cancel_closure));
+        self.cancel_map.map.insert(self.key, Some(cancel_closure));
 
         self.key
     }
@@ -131,7 +157,7 @@ impl Session {
 
 impl Drop for Session {
     fn drop(&mut self) {
-        self.cancel_map.0.remove(&self.key);
+        self.cancel_map.map.remove(&self.key);
         info!("dropped query cancellation key {}", &self.key);
     }
 }
@@ -4,17 +4,33 @@ use crate::{
     console::{errors::WakeComputeError, messages::MetricsAuxInfo},
     context::RequestMonitoring,
     error::{ReportableError, UserFacingError},
-    metrics::NUM_DB_CONNECTIONS_GAUGE,
+    metrics::{count_tls_resumption, NUM_DB_CONNECTIONS_GAUGE},
     proxy::neon_option,
 };
 use futures::{FutureExt, TryFutureExt};
 use itertools::Itertools;
 use metrics::IntCounterPairGuard;
+use once_cell::sync::Lazy;
 use pq_proto::StartupMessageParams;
-use std::{io, net::SocketAddr, time::Duration};
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, Resumption, Tls12ClientSessionValue,
+    Tls13ClientSessionValue,
+};
+use rustls::{ClientConfig, NamedGroup, RootCertStore, ServerName};
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream;
+use tokio_postgres::maybe_tls_stream::MaybeTlsStream;
 use tokio_postgres::tls::MakeTlsConnect;
+use tokio_postgres_rustls::{MakeRustlsConnect, RustlsStream};
 use tracing::{error, info, warn};
 
 const COULD_NOT_CONNECT: &str = "Couldn't connect to compute node";
@@ -104,7 +120,8 @@ impl ConnCfg {
     }
 
     /// Apply startup message params to the connection config.
-    pub fn set_startup_params(&mut self, params: &StartupMessageParams) {
+    /// Parameters from `allow_list` are forwarded to the compute as well.
+    pub fn set_startup_params(&mut self, params: &StartupMessageParams, allow_list: &[String]) {
         // Only set `user` if it's not present in the config.
         // Link auth flow takes username from the console's response.
         if let (None, Some(user)) = (self.get_user(), params.get("user")) {
@@ -119,7 +136,15 @@ impl ConnCfg {
 
         // Don't add `options` if they were only used for specifying a project.
         // Connection pools don't support `options`, because they affect backend startup.
-        if let Some(options) = filtered_options(params) {
+        // tokio-postgres doesn't allow us to pass arbitrary parameters,
+        // so the allowed ones are passed as `-c name=value` options.
+        #[allow(unstable_name_collisions)]
+        let options: String = filtered_options(params)
+            .into_iter()
+            .chain(allowed_params_options(params, allow_list))
+            .intersperse(" ".to_owned())
+            .collect();
+        if !options.is_empty() {
             self.options(&options);
         }
 
@@ -141,11 +166,7 @@ impl ConnCfg {
             }
         }
 
-        // TODO: extend the list of the forwarded startup parameters.
-        // Currently, tokio-postgres doesn't allow us to pass
-        // arbitrary parameters, but the ones above are a good start.
-        //
-        // This and the reverse params problem can be better addressed
+        // TODO: The reverse params problem can be better addressed
         // in a bespoke connection machinery (a new library for that sake).
     }
 }
@@ -243,12 +264,139 @@ impl ConnCfg {
     }
 }
 
+/// How many TLS sessions to computes to keep for resumption.
+const COMPUTE_TLS_SESSION_CACHE_SIZE: usize = 1024;
+
+/// TLS config for connecting to computes. It is shared by all connections,
+/// so that reconnects to the same compute resume the TLS session.
+static COMPUTE_TLS: Lazy<ClientConfig> = Lazy::new(compute_tls_config);
+
+fn compute_tls_config() -> ClientConfig {
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(compute_root_certs())
+        .with_no_client_auth();
+    config.resumption = Resumption::store(Arc::new(CountingSessionStore(
+        ClientSessionMemoryCache::new(COMPUTE_TLS_SESSION_CACHE_SIZE),
+    )));
+    config
+}
+
+/// The public CAs, and those trusted by the system, e.g. private CAs which
+/// issued the certificates of the computes.
+fn compute_root_certs() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    match rustls_native_certs::load_native_certs() {
+        Ok(certs) => {
+            let certs: Vec<_> = certs.into_iter().map(|cert| cert.0).collect();
+            let (_, ignored) = roots.add_parsable_certificates(&certs);
+            if ignored > 0 {
+                warn!("ignored {ignored} system root certificates which cannot be parsed");
+            }
+        }
+        Err(e) => warn!("failed to load the system root certificates: {e}"),
+    }
+    roots
+}
+
+/// Counts whether there are cached sessions to offer to computes for resumption.
+struct CountingSessionStore(ClientSessionMemoryCache);
+
+impl ClientSessionStore for CountingSessionStore {
+    fn set_kx_hint(&self, server_name: &ServerName, group: NamedGroup) {
+        self.0.set_kx_hint(server_name, group)
+    }
+
+    fn kx_hint(&self, server_name: &ServerName) -> Option<NamedGroup> {
+        self.0.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: &ServerName, value: Tls12ClientSessionValue) {
+        self.0.set_tls12_session(server_name, value)
+    }
+
+    fn tls12_session(&self, server_name: &ServerName) -> Option<Tls12ClientSessionValue> {
+        let session = self.0.tls12_session(server_name);
+        count_tls_resumption("compute", session.is_some());
+        session
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName) {
+        self.0.remove_tls12_session(server_name)
+    }
+
+    fn insert_tls13_ticket(&self, server_name: &ServerName, value: Tls13ClientSessionValue) {
+        self.0.insert_tls13_ticket(server_name, value)
+    }
+
+    fn take_tls13_ticket(&self, server_name: &ServerName) -> Option<Tls13ClientSessionValue> {
+        let ticket = self.0.take_tls13_ticket(server_name);
+        // if there's no ticket, rustls falls back to looking for a TLS 1.2 session
+        if ticket.is_some() {
+            count_tls_resumption("compute", true);
+        }
+        ticket
+    }
+}
+
+/// Socket connected to a compute node. Computes with self-signed certificates,
+/// which are only allowed for testing, are connected to with native-tls, which
+/// can skip the verification of the certificate.
+pub enum ComputeStream {
+    Rustls(MaybeTlsStream<TcpStream, RustlsStream<TcpStream>>),
+    NativeTls(MaybeTlsStream<TcpStream, postgres_native_tls::TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for ComputeStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ComputeStream::Rustls(stream) => Pin::new(stream).poll_read(cx, buf),
+            ComputeStream::NativeTls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ComputeStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ComputeStream::Rustls(stream) => Pin::new(stream).poll_write(cx, buf),
+            ComputeStream::NativeTls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ComputeStream::Rustls(stream) => Pin::new(stream).poll_flush(cx),
+            ComputeStream::NativeTls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ComputeStream::Rustls(stream) => Pin::new(stream).poll_shutdown(cx),
+            ComputeStream::NativeTls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
 pub struct PostgresConnection {
     /// Socket connected to a compute node.
-    pub stream: tokio_postgres::maybe_tls_stream::MaybeTlsStream<
-        tokio::net::TcpStream,
-        postgres_native_tls::TlsStream<tokio::net::TcpStream>,
-    >,
+    pub stream: ComputeStream,
     /// PostgreSQL connection parameters.
     pub params: std::collections::HashMap<String, String>,
     /// Query cancellation token.
@@ -270,28 +418,34 @@ impl ConnCfg {
     ) -> Result<PostgresConnection, ConnectionError> {
         let (socket_addr, stream, host) = self.connect_raw(timeout).await?;
 
-        let tls_connector = native_tls::TlsConnector::builder()
-            .danger_accept_invalid_certs(allow_self_signed_compute)
-            .build()
-            .unwrap();
-        let mut mk_tls = postgres_native_tls::MakeTlsConnector::new(tls_connector);
-        let tls = MakeTlsConnect::<tokio::net::TcpStream>::make_tls_connect(&mut mk_tls, host)?;
-
-        // connect_raw() will not use TLS if sslmode is "disable"
-        let (client, connection) = self.0.connect_raw(stream, tls).await?;
+        // connect_raw() will not use TLS if sslmode is "disable".
+        // This is very ugly but as of now there's no better way to
+        // extract the connection parameters from tokio-postgres' connection.
+        // TODO: solve this problem in a more elegant manner (e.g. the new library).
+        let (client, stream, params) = if allow_self_signed_compute {
+            let tls_connector = native_tls::TlsConnector::builder()
+                .danger_accept_invalid_certs(true)
+                .build()?;
+            let mut mk_tls = postgres_native_tls::MakeTlsConnector::new(tls_connector);
+            let tls = MakeTlsConnect::<TcpStream>::make_tls_connect(&mut mk_tls, host)?;
+            let (client, connection) = self.0.connect_raw(stream, tls).await?;
+            let stream = ComputeStream::NativeTls(connection.stream.into_inner());
+            (client, stream, connection.parameters)
+        } else {
+            // the copy shares the session cache with the original config
+            let mut mk_tls = MakeRustlsConnect::new(ClientConfig::clone(&COMPUTE_TLS));
+            let tls = MakeTlsConnect::<TcpStream>::make_tls_connect(&mut mk_tls, host)?;
+            let (client, connection) = self.0.connect_raw(stream, tls).await?;
+            let stream = ComputeStream::Rustls(connection.stream.into_inner());
+            (client, stream, connection.parameters)
+        };
         tracing::Span::current().record("pid", &tracing::field::display(client.get_process_id()));
-        let stream = connection.stream.into_inner();
 
         info!(
             "connected to compute node at {host} ({socket_addr}) sslmode={:?}",
             self.0.get_ssl_mode()
         );
 
-        // This is very ugly but as of now there's no better way to
-        // extract the connection parameters from tokio-postgres' connection.
-        // TODO: solve this problem in a more elegant manner (e.g. the new library).
-        let params = connection.parameters;
-
         // NB: CancelToken is supposed to hold socket_addr, but we use connect_raw.
         // Yet another reason to rework the connection establishing code.
         let cancel_closure = CancelClosure::new(socket_addr, client.cancel_token());
@@ -327,6 +481,48 @@ fn filtered_options(params: &StartupMessageParams) -> Option<String> {
     Some(options)
 }
 
+/// Startup parameters which are handled by [`ConnCfg::set_startup_params`] itself.
+const RESERVED_STARTUP_PARAMS: &[&str] = &[
+    "user",
+    "database",
+    "options",
+    "application_name",
+    "replication",
+];
+
+/// Turn the startup parameters from `allow_list` into `-c name=value` options.
+fn allowed_params_options<'a>(
+    params: &'a StartupMessageParams,
+    allow_list: &'a [String],
+) -> impl Iterator<Item = String> + 'a {
+    allow_list
+        .iter()
+        .filter(|allowed| {
+            !RESERVED_STARTUP_PARAMS
+                .iter()
+                .any(|reserved| reserved.eq_ignore_ascii_case(allowed))
+        })
+        .flat_map(|allowed| {
+            // Parameter names are case-insensitive, e.g. clients might send both `datestyle` and `DateStyle`.
+            params
+                .iter()
+                .filter(move |(name, _)| name.eq_ignore_ascii_case(allowed))
+        })
+        .map(|(name, value)| format!("-c {name}={}", escape_option(value)))
+}
+
+/// Escape whitespaces and backslashes, see `postgres: pg_split_opts`.
+fn escape_option(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || c.is_ascii_whitespace() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -357,4 +553,62 @@ mod tests {
         )]);
         assert_eq!(filtered_options(&params).as_deref(), Some("project = foo"));
     }
+
+    #[test]
+    fn test_allowed_params_options() {
+        let allow_list = ["search_path".to_owned(), "DateStyle".to_owned()];
+
+        let params = StartupMessageParams::new([
+            ("user", "john"),
+            ("search_path", r"my schema,pub\lic"),
+            ("datestyle", "ISO"),
+            ("work_mem", "1GB"),
+        ]);
+        let options: Vec<_> = allowed_params_options(&params, &allow_list).collect();
+        assert_eq!(
+            options,
+            [r"-c search_path=my\ schema,pub\\lic", "-c datestyle=ISO"]
+        );
+
+        // Reserved parameters are never turned into options.
+        let allow_list = ["user".to_owned()];
+        assert_eq!(allowed_params_options(&params, &allow_list).count(), 0);
+    }
+
+    #[test]
+    fn test_compute_root_certs() {
+        // the system roots come on top of the public ones
+        let roots = compute_root_certs();
+        assert!(roots.len() >= webpki_roots::TLS_SERVER_ROOTS.len());
+    }
+
+    #[tokio::test]
+    async fn test_compute_tls_rejects_self_signed() {
+        let cert = rcgen::generate_simple_self_signed(vec!["compute.local".to_owned()]).unwrap();
+        let server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(cert.serialize_der().unwrap())],
+                rustls::PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        tokio::spawn(async move { acceptor.accept(server).await });
+
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(compute_tls_config()));
+        let server_name = ServerName::try_from("compute.local").unwrap();
+        let err = connector.connect(server_name, client).await.unwrap_err();
+        let err = err
+            .into_inner()
+            .unwrap()
+            .downcast::<rustls::Error>()
+            .unwrap();
+        assert_eq!(
+            *err,
+            rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer)
+        );
+    }
 }
@@ -1,7 +1,17 @@
-use crate::{auth, rate_limiter::RateBucketInfo, serverless::GlobalConnPoolOptions};
+use crate::{
+    auth, metrics::count_tls_resumption, rate_limiter::RateBucketInfo,
+    serverless::GlobalConnPoolOptions, EndpointId,
+};
 use anyhow::{bail, ensure, Context, Ok};
-use rustls::{sign, Certificate, PrivateKey};
-use sha2::{Digest, Sha256};
+use parking_lot::RwLock;
+use rustls::{
+    server::{
+        AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier,
+        NoServerSessionStorage, ProducesTickets, ServerSessionMemoryCache, StoresServerSessions,
+    },
+    sign, Certificate, DistinguishedName, PrivateKey, RootCertStore,
+};
+use sha2::{Digest, Sha256, Sha384, Sha512};
 use std::{
     collections::{HashMap, HashSet},
     str::FromStr,
@@ -23,6 +33,8 @@ pub struct ProxyConfig {
     pub endpoint_rps_limit: Vec<RateBucketInfo>,
     pub region: String,
     pub handshake_timeout: Duration,
+    pub log_latency_breakdown: bool,
+    pub startup_params_allow_list: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -33,72 +45,220 @@ pub struct MetricCollectionConfig {
 
 pub struct TlsConfig {
     pub config: Arc<rustls::ServerConfig>,
-    pub common_names: HashSet<String>,
+    /// Same as `config`, but also requests client certificates.
+    /// Only used for postgres protocol connections.
+    pub client_cert_config: Option<Arc<rustls::ServerConfig>>,
     pub cert_resolver: Arc<CertResolver>,
 }
 
 pub struct HttpConfig {
     pub request_timeout: tokio::time::Duration,
     pub pool_options: GlobalConnPoolOptions,
+    /// How long a transaction spanning several requests may stay idle.
+    pub txn_timeout: tokio::time::Duration,
+    /// How often to ping websocket clients, `None` to never ping them.
+    pub websocket_ping_interval: Option<tokio::time::Duration>,
+    /// How long a pinged websocket client may stay silent before it's disconnected.
+    pub websocket_ping_timeout: tokio::time::Duration,
 }
 
 pub struct AuthenticationConfig {
     pub scram_protocol_timeout: tokio::time::Duration,
+    /// `None` if client certificate authentication is disabled.
+    pub client_cert_auth: Option<ClientCertAuthConfig>,
 }
 
 impl TlsConfig {
     pub fn to_server_config(&self) -> Arc<rustls::ServerConfig> {
         self.config.clone()
     }
+
+    pub fn to_pg_server_config(&self) -> Arc<rustls::ServerConfig> {
+        self.client_cert_config
+            .clone()
+            .unwrap_or_else(|| self.config.clone())
+    }
+}
+
+/// Certificate authorities which sign client certificates, per endpoint.
+pub struct ClientCertAuthConfig {
+    verifiers: HashMap<EndpointId, AllowAnyAuthenticatedClient>,
+}
+
+impl ClientCertAuthConfig {
+    /// Load certificate authorities from `<dir>/<endpoint id>/ca.crt` files.
+    pub fn load(dir: &str) -> anyhow::Result<Self> {
+        let mut verifiers = HashMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let ca_path = path.join("ca.crt");
+            if !path.is_dir() || !ca_path.exists() {
+                continue;
+            }
+            let Some(endpoint) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            let ca_bytes = std::fs::read(&ca_path)
+                .with_context(|| format!("Failed to read CA file at '{}'", ca_path.display()))?;
+            let certs = rustls_pemfile::certs(&mut &ca_bytes[..]).with_context(|| {
+                format!("Failed to parse CA certificates at '{}'", ca_path.display())
+            })?;
+
+            let mut roots = RootCertStore::empty();
+            let (added, _) = roots.add_parsable_certificates(&certs);
+            ensure!(
+                added > 0,
+                "no valid CA certificates at '{}'",
+                ca_path.display()
+            );
+
+            verifiers.insert(endpoint.into(), AllowAnyAuthenticatedClient::new(roots));
+        }
+
+        info!(
+            "loaded client certificate authorities for {} endpoints",
+            verifiers.len()
+        );
+        Ok(Self { verifiers })
+    }
+
+    pub fn verifier(&self, endpoint: &EndpointId) -> Option<&AllowAnyAuthenticatedClient> {
+        self.verifiers.get(endpoint)
+    }
+}
+
+/// Requests client certificates, but leaves the chain verification to the authentication step,
+/// as the certificate authority to trust depends on the endpoint. rustls still checks that the
+/// client owns the key of the certificate.
+struct DeferredClientCertVerifier;
+
+impl ClientCertVerifier for DeferredClientCertVerifier {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _now: std::time::SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Result::Ok(ClientCertVerified::assertion())
+    }
+}
+
+/// Counts session lookups for resumption, for both session IDs and stateful TLS 1.3 resumption.
+struct CountingSessionStorage(Arc<dyn StoresServerSessions>);
+
+impl StoresServerSessions for CountingSessionStorage {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.0.put(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let session = self.0.get(key);
+        count_tls_resumption("client", session.is_some());
+        session
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let session = self.0.take(key);
+        count_tls_resumption("client", session.is_some());
+        session
+    }
+
+    fn can_cache(&self) -> bool {
+        self.0.can_cache()
+    }
+}
+
+/// Counts the tickets clients try to resume sessions with.
+struct CountingTicketer(Arc<dyn ProducesTickets>);
+
+impl ProducesTickets for CountingTicketer {
+    fn enabled(&self) -> bool {
+        self.0.enabled()
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.0.lifetime()
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.0.encrypt(plain)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        let session = self.0.decrypt(cipher);
+        count_tls_resumption("client", session.is_some());
+        session
+    }
+}
+
+/// Let clients resume their TLS sessions, to save a round trip and the key exchange on reconnects.
+/// Sessions are only resumed on the proxy instance which issued them.
+fn enable_session_resumption(
+    config: &mut rustls::ServerConfig,
+    session_cache_size: usize,
+    session_tickets: bool,
+) -> anyhow::Result<()> {
+    config.session_storage = if session_cache_size > 0 {
+        Arc::new(CountingSessionStorage(ServerSessionMemoryCache::new(
+            session_cache_size,
+        )))
+    } else {
+        Arc::new(NoServerSessionStorage {})
+    };
+    if session_tickets {
+        config.ticketer = Arc::new(CountingTicketer(rustls::Ticketer::new()?));
+    }
+    Ok(())
 }
 
 /// Configure TLS for the main endpoint.
+///
+/// `session_cache_size` is the number of sessions kept for resumption by session ID, 0 to disable it.
 pub fn configure_tls(
     key_path: &str,
     cert_path: &str,
     certs_dir: Option<&String>,
+    client_cert_auth: bool,
+    session_cache_size: usize,
+    session_tickets: bool,
 ) -> anyhow::Result<TlsConfig> {
-    let mut cert_resolver = CertResolver::new();
-
-    // add default certificate
-    cert_resolver.add_cert_path(key_path, cert_path, true)?;
-
-    // add extra certificates
-    if let Some(certs_dir) = certs_dir {
-        for entry in std::fs::read_dir(certs_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                // file names aligned with default cert-manager names
-                let key_path = path.join("tls.key");
-                let cert_path = path.join("tls.crt");
-                if key_path.exists() && cert_path.exists() {
-                    cert_resolver.add_cert_path(
-                        &key_path.to_string_lossy(),
-                        &cert_path.to_string_lossy(),
-                        false,
-                    )?;
-                }
-            }
-        }
-    }
-
-    let common_names = cert_resolver.get_common_names();
-
-    let cert_resolver = Arc::new(cert_resolver);
+    let cert_resolver = Arc::new(CertResolver::load(key_path, cert_path, certs_dir)?);
 
-    let config = rustls::ServerConfig::builder()
+    let builder = rustls::ServerConfig::builder()
         .with_safe_default_cipher_suites()
         .with_safe_default_kx_groups()
         // allow TLS 1.2 to be compatible with older client libraries
-        .with_protocol_versions(&[&rustls::version::TLS13, &rustls::version::TLS12])?
+        .with_protocol_versions(&[&rustls::version::TLS13, &rustls::version::TLS12])?;
+
+    let mut config = builder
+        .clone()
         .with_no_client_auth()
-        .with_cert_resolver(cert_resolver.clone())
-        .into();
+        .with_cert_resolver(cert_resolver.clone());
+    enable_session_resumption(&mut config, session_cache_size, session_tickets)?;
+
+    // resumed sessions keep the client certificate chain, so it's still verified on authentication
+    let client_cert_config = if client_cert_auth {
+        let mut config = builder
+            .with_client_cert_verifier(Arc::new(DeferredClientCertVerifier))
+            .with_cert_resolver(cert_resolver.clone());
+        enable_session_resumption(&mut config, session_cache_size, session_tickets)?;
+        Some(Arc::new(config))
+    } else {
+        None
+    };
 
     Ok(TlsConfig {
-        config,
-        common_names,
+        config: Arc::new(config),
+        client_cert_config,
         cert_resolver,
     })
 }
@@ -128,6 +288,8 @@ pub fn configure_tls(
 #[derive(Debug, Clone, Copy)]
 pub enum TlsServerEndPoint {
     Sha256([u8; 32]),
+    Sha384([u8; 48]),
+    Sha512([u8; 64]),
     Undefined,
 }
 
@@ -138,6 +300,14 @@ impl TlsServerEndPoint {
             oid_registry::OID_SIG_ECDSA_WITH_SHA256,
             oid_registry::OID_PKCS1_SHA256WITHRSA,
         ];
+        let sha384_oids = [
+            oid_registry::OID_SIG_ECDSA_WITH_SHA384,
+            oid_registry::OID_PKCS1_SHA384WITHRSA,
+        ];
+        let sha512_oids = [
+            oid_registry::OID_SIG_ECDSA_WITH_SHA512,
+            oid_registry::OID_PKCS1_SHA512WITHRSA,
+        ];
 
         let pem = x509_parser::parse_x509_certificate(&cert.0)
             .context("Failed to parse PEM object from cerficiate")?
@@ -148,26 +318,83 @@ impl TlsServerEndPoint {
         let reg = oid_registry::OidRegistry::default().with_all_crypto();
         let oid = pem.signature_algorithm.oid();
         let alg = reg.get(oid);
-        if sha256_oids.contains(oid) {
-            let tls_server_end_point: [u8; 32] =
-                Sha256::new().chain_update(&cert.0).finalize().into();
-            info!(subject = %pem.subject, signature_algorithm = alg.map(|a| a.description()), tls_server_end_point = %base64::encode(tls_server_end_point), "determined channel binding");
-            Ok(Self::Sha256(tls_server_end_point))
+        let end_point = if sha256_oids.contains(oid) {
+            Self::Sha256(Sha256::new().chain_update(&cert.0).finalize().into())
+        } else if sha384_oids.contains(oid) {
+            Self::Sha384(Sha384::new().chain_update(&cert.0).finalize().into())
+        } else if sha512_oids.contains(oid) {
+            Self::Sha512(Sha512::new().chain_update(&cert.0).finalize().into())
         } else {
             error!(subject = %pem.subject, signature_algorithm = alg.map(|a| a.description()), "unknown channel binding");
-            Ok(Self::Undefined)
-        }
+            return Ok(Self::Undefined);
+        };
+        let tls_server_end_point = base64::encode(end_point.as_bytes().unwrap_or_default());
+        info!(subject = %pem.subject, signature_algorithm = alg.map(|a| a.description()), tls_server_end_point = %tls_server_end_point, "determined channel binding");
+        Ok(end_point)
     }
 
     pub fn supported(&self) -> bool {
         !matches!(self, TlsServerEndPoint::Undefined)
     }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            TlsServerEndPoint::Sha256(x) => Some(x),
+            TlsServerEndPoint::Sha384(x) => Some(x),
+            TlsServerEndPoint::Sha512(x) => Some(x),
+            TlsServerEndPoint::Undefined => None,
+        }
+    }
+}
+
+/// Channel binding data of a client TLS connection.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsChannelBinding {
+    pub tls_server_end_point: TlsServerEndPoint,
+    /// `tls-exporter` channel binding, see <https://www.rfc-editor.org/rfc/rfc9266>.
+    /// Only defined for TLS 1.3, as with TLS 1.2 it is secure only if extended
+    /// master secret was negotiated.
+    pub tls_exporter: Option<[u8; 32]>,
+}
+
+impl TlsChannelBinding {
+    /// Connection without TLS.
+    pub const NONE: Self = Self {
+        tls_server_end_point: TlsServerEndPoint::Undefined,
+        tls_exporter: None,
+    };
+
+    /// Whether to advertise SCRAM-SHA-256-PLUS. Clients which don't ask for a
+    /// method pick `tls-server-end-point` (libpq only implements that one), so
+    /// PLUS is only advertised if it is available. `tls-exporter` is still
+    /// accepted from clients which ask for it.
+    pub fn supported(&self) -> bool {
+        self.tls_server_end_point.supported()
+    }
 }
 
+/// Picks a certificate based on SNI. Certificates loaded from disk can be
+/// reloaded, which only affects new connections.
 #[derive(Default)]
 pub struct CertResolver {
+    state: RwLock<CertResolverState>,
+    paths: Option<CertPaths>,
+}
+
+#[derive(Default)]
+struct CertResolverState {
     certs: HashMap<String, (Arc<rustls::sign::CertifiedKey>, TlsServerEndPoint)>,
     default: Option<(Arc<rustls::sign::CertifiedKey>, TlsServerEndPoint)>,
+    /// Keys of `certs`, shared with the connections instead of being collected
+    /// for each of them.
+    common_names: Arc<HashSet<String>>,
+}
+
+#[derive(Clone)]
+struct CertPaths {
+    key_path: String,
+    cert_path: String,
+    certs_dir: Option<String>,
 }
 
 impl CertResolver {
@@ -175,6 +402,85 @@ impl CertResolver {
         Self::default()
     }
 
+    /// Load the default certificate, and the certificates from `certs_dir` if any.
+    pub fn load(
+        key_path: &str,
+        cert_path: &str,
+        certs_dir: Option<&String>,
+    ) -> anyhow::Result<Self> {
+        let paths = CertPaths {
+            key_path: key_path.to_owned(),
+            cert_path: cert_path.to_owned(),
+            certs_dir: certs_dir.cloned(),
+        };
+        Ok(Self {
+            state: RwLock::new(CertResolverState::load(&paths)?),
+            paths: Some(paths),
+        })
+    }
+
+    /// Load the certificates from disk again, e.g. after they were rotated or new
+    /// domains were added. Keeps the old certificates if any of them fails to load.
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let Some(paths) = self.paths.clone() else {
+            return Ok(());
+        };
+        // the files are read on a blocking thread, not to stall the connections
+        let state = tokio::task::spawn_blocking(move || CertResolverState::load(&paths)).await??;
+        info!(
+            "reloaded TLS certificates for {:?}",
+            state.certs.keys().collect::<Vec<_>>()
+        );
+        *self.state.write() = state;
+        Ok(())
+    }
+
+    pub fn add_cert(
+        &mut self,
+        priv_key: PrivateKey,
+        cert_chain: Vec<Certificate>,
+        is_default: bool,
+    ) -> anyhow::Result<()> {
+        self.state
+            .get_mut()
+            .add_cert(priv_key, cert_chain, is_default)
+    }
+
+    pub fn get_common_names(&self) -> Arc<HashSet<String>> {
+        self.state.read().common_names.clone()
+    }
+}
+
+impl CertResolverState {
+    fn load(paths: &CertPaths) -> anyhow::Result<Self> {
+        let mut state = Self::default();
+
+        // add default certificate
+        state.add_cert_path(&paths.key_path, &paths.cert_path, true)?;
+
+        // add extra certificates
+        if let Some(certs_dir) = &paths.certs_dir {
+            for entry in std::fs::read_dir(certs_dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if path.is_dir() {
+                    // file names aligned with default cert-manager names
+                    let key_path = path.join("tls.key");
+                    let cert_path = path.join("tls.crt");
+                    if key_path.exists() && cert_path.exists() {
+                        state.add_cert_path(
+                            &key_path.to_string_lossy(),
+                            &cert_path.to_string_lossy(),
+                            false,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        Ok(state)
+    }
+
     fn add_cert_path(
         &mut self,
         key_path: &str,
@@ -209,7 +515,7 @@ impl CertResolver {
         self.add_cert(priv_key, cert_chain, is_default)
     }
 
-    pub fn add_cert(
+    fn add_cert(
         &mut self,
         priv_key: PrivateKey,
         cert_chain: Vec<Certificate>,
@@ -217,7 +523,7 @@ impl CertResolver {
     ) -> anyhow::Result<()> {
         let key = sign::any_supported_type(&priv_key).context("invalid private key")?;
 
-        let first_cert = &cert_chain[0];
+        let first_cert = cert_chain.first().context("missing certificate")?;
         let tls_server_end_point = TlsServerEndPoint::new(first_cert)?;
         let pem = x509_parser::parse_x509_certificate(&first_cert.0)
             .context("Failed to parse PEM object from cerficiate")?
@@ -244,14 +550,11 @@ impl CertResolver {
             self.default = Some((cert.clone(), tls_server_end_point));
         }
 
+        Arc::make_mut(&mut self.common_names).insert(common_name.clone());
         self.certs.insert(common_name, (cert, tls_server_end_point));
 
         Ok(())
     }
-
-    pub fn get_common_names(&self) -> HashSet<String> {
-        self.certs.keys().map(|s| s.to_string()).collect()
-    }
 }
 
 impl rustls::server::ResolvesServerCert for CertResolver {
@@ -274,9 +577,10 @@ impl CertResolver {
         //
         // With the current coding foo.com will match *.foo.com and that
         // repeats behavior of the old code.
+        let state = self.state.read();
         if let Some(mut sni_name) = server_name {
             loop {
-                if let Some(cert) = self.certs.get(sni_name) {
+                if let Some(cert) = state.certs.get(sni_name) {
                     return Some(cert.clone());
                 }
                 if let Some((_, rest)) = sni_name.split_once('.') {
@@ -296,9 +600,18 @@ impl CertResolver {
             // a) Instead of multi-cert approach use single cert with extra
             //    domains listed in Subject Alternative Name (SAN).
             // b) Deploy separate proxy instances for extra domains.
-            self.default.as_ref().cloned()
+            state.default.as_ref().cloned()
         }
     }
+
+    /// Same as [`Self::resolve`], but falls back to the default certificate
+    /// when none of the certificates match the SNI hostname.
+    pub fn resolve_or_default(
+        &self,
+        server_name: Option<&str>,
+    ) -> Option<(Arc<rustls::sign::CertifiedKey>, TlsServerEndPoint)> {
+        self.resolve(server_name).or_else(|| self.resolve(None))
+    }
 }
 
 /// Helper for cmdline cache options parsing.
@@ -364,12 +677,14 @@ pub struct ProjectInfoCacheOptions {
     pub max_roles: usize,
     /// Gc interval.
     pub gc_interval: Duration,
+    /// How long an expired entry can still be used while the console is unavailable.
+    pub stale_ttl: Duration,
 }
 
 impl ProjectInfoCacheOptions {
     /// Default options for [`crate::console::provider::NodeInfoCache`].
     pub const CACHE_DEFAULT_OPTIONS: &'static str =
-        "size=10000,ttl=4m,max_roles=10,gc_interval=60m";
+        "size=10000,ttl=4m,max_roles=10,gc_interval=60m,stale_ttl=30m";
 
     /// Parse cache options passed via cmdline.
     /// Example: [`Self::CACHE_DEFAULT_OPTIONS`].
@@ -378,6 +693,7 @@ impl ProjectInfoCacheOptions {
         let mut ttl = None;
         let mut max_roles = None;
         let mut gc_interval = None;
+        let mut stale_ttl = None;
 
         for option in options.split(',') {
             let (key, value) = option
@@ -389,6 +705,7 @@ impl ProjectInfoCacheOptions {
                 "ttl" => ttl = Some(humantime::parse_duration(value)?),
                 "max_roles" => max_roles = Some(value.parse()?),
                 "gc_interval" => gc_interval = Some(humantime::parse_duration(value)?),
+                "stale_ttl" => stale_ttl = Some(humantime::parse_duration(value)?),
                 unknown => bail!("unknown key: {unknown}"),
             }
         }
@@ -403,6 +720,9 @@ impl ProjectInfoCacheOptions {
             ttl: ttl.context("missing `ttl`")?,
             max_roles: max_roles.context("missing `max_roles`")?,
             gc_interval: gc_interval.context("missing `gc_interval`")?,
+            // Expired entries are never used unless `stale_ttl` is given, as it
+            // is in the defaults.
+            stale_ttl: stale_ttl.unwrap_or_default(),
         })
     }
 }
@@ -551,4 +871,49 @@ mod tests {
 
         Ok(())
     }
+
+    fn write_cert(dir: &camino::Utf8Path, common_name: &str) -> anyhow::Result<()> {
+        let mut params = rcgen::CertificateParams::new(vec![common_name.to_owned()]);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        let cert = rcgen::Certificate::from_params(params)?;
+
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("tls.crt"), cert.serialize_pem()?)?;
+        std::fs::write(dir.join("tls.key"), cert.serialize_private_key_pem())?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reload_certs() -> anyhow::Result<()> {
+        let tmp = camino_tempfile::tempdir()?;
+        let certs_dir = tmp.path().join("certs");
+        write_cert(tmp.path(), "*.a.com")?;
+        write_cert(&certs_dir.join("b"), "*.b.com")?;
+
+        let resolver = CertResolver::load(
+            tmp.path().join("tls.key").as_str(),
+            tmp.path().join("tls.crt").as_str(),
+            Some(&certs_dir.to_string()),
+        )?;
+        assert_eq!(
+            *resolver.get_common_names(),
+            HashSet::from(["a.com".to_owned(), "b.com".to_owned()])
+        );
+
+        // a new domain is picked up
+        write_cert(&certs_dir.join("c"), "*.c.com")?;
+        resolver.reload().await?;
+        assert!(resolver.resolve(Some("ep-foo.c.com")).is_some());
+        assert_eq!(resolver.get_common_names().len(), 3);
+
+        // a broken certificate doesn't affect the loaded ones
+        std::fs::write(certs_dir.join("c").join("tls.crt"), "garbage")?;
+        resolver.reload().await.unwrap_err();
+        assert!(resolver.resolve(Some("ep-foo.c.com")).is_some());
+
+        Ok(())
+    }
 }
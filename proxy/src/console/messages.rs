@@ -3,7 +3,7 @@ use std::fmt;
 
 use crate::auth::IpPattern;
 
-use crate::{BranchId, EndpointId, ProjectId};
+use crate::{BranchId, EndpointId, ProjectId, RoleName};
 
 /// Generic error response with human-readable description.
 /// Note that we can't always present it to user as is.
@@ -19,6 +19,8 @@ pub struct GetRoleSecret {
     pub role_secret: Box<str>,
     pub allowed_ips: Option<Vec<IpPattern>>,
     pub project_id: Option<ProjectId>,
+    /// Max number of simultaneously open client connections to the endpoint.
+    pub max_client_connections: Option<u32>,
 }
 
 // Manually implement debug to omit sensitive info.
@@ -28,6 +30,48 @@ impl fmt::Debug for GetRoleSecret {
     }
 }
 
+/// Response which holds the password the proxy logs into the compute with on behalf of
+/// clients it authenticated itself, e.g. with a certificate.
+/// Returned by the `/proxy_get_role_password` API method.
+#[derive(Deserialize)]
+pub struct GetRolePassword {
+    pub password: Box<str>,
+}
+
+// Manually implement debug to omit sensitive info.
+impl fmt::Debug for GetRolePassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GetRolePassword").finish_non_exhaustive()
+    }
+}
+
+/// Response which holds the JWKS the endpoint accepts JWTs from.
+/// Returned by the `/proxy_get_endpoint_jwks` API method.
+#[derive(Debug, Deserialize)]
+pub struct GetEndpointJwks {
+    pub jwks: Vec<JwksSettings>,
+}
+
+/// JWKS of an identity provider, and the roles its tokens can authenticate as.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwksSettings {
+    pub id: String,
+    pub jwks_url: String,
+    pub provider_name: String,
+    /// Expected `aud` claim of the tokens, if any.
+    pub jwt_audience: Option<String>,
+    /// Claim holding the name of the role to authenticate as.
+    #[serde(default = "JwksSettings::default_role_claim")]
+    pub role_claim: String,
+    pub role_names: Vec<RoleName>,
+}
+
+impl JwksSettings {
+    pub(crate) fn default_role_claim() -> String {
+        "role".to_owned()
+    }
+}
+
 /// Response which holds compute node's `host:port` pair.
 /// Returned by the `/proxy_wake_compute` API method.
 #[derive(Debug, Deserialize)]
@@ -2,7 +2,7 @@
 pub mod mock;
 pub mod neon;
 
-use super::messages::MetricsAuxInfo;
+use super::messages::{JwksSettings, MetricsAuxInfo};
 use crate::{
     auth::{
         backend::{ComputeCredentialKeys, ComputeUserInfo},
@@ -12,7 +12,7 @@ use crate::{
     compute,
     config::{CacheOptions, ProjectInfoCacheOptions},
     context::RequestMonitoring,
-    scram, EndpointCacheKey, ProjectId,
+    scram, EndpointCacheKey, EndpointId, ProjectId,
 };
 use async_trait::async_trait;
 use dashmap::DashMap;
@@ -25,7 +25,7 @@ pub mod errors {
     use crate::{
         error::{io_error, ReportableError, UserFacingError},
         http,
-        proxy::retry::ShouldRetry,
+        proxy::retry::{ShouldRetry, NUM_RETRIES_CONNECT, NUM_RETRIES_CONTROL_PLANE_FAILURE},
     };
     use thiserror::Error;
 
@@ -56,6 +56,15 @@ pub mod errors {
                 _ => None,
             }
         }
+
+        /// Whether the control plane failed to serve the request,
+        /// as opposed to rejecting it because of the endpoint state.
+        pub fn is_control_plane_failure(&self) -> bool {
+            match self {
+                ApiError::Transport(_) => true,
+                ApiError::Console { status, .. } => status.is_server_error(),
+            }
+        }
     }
 
     impl UserFacingError for ApiError {
@@ -117,9 +126,26 @@ pub mod errors {
                     !text.contains("quota exceeded")
                         && !text.contains("the limit for current plan reached")
                 }
+                // the control plane is overloaded or being restarted
+                Self::Console {
+                    status:
+                        http::StatusCode::BAD_GATEWAY
+                        | http::StatusCode::SERVICE_UNAVAILABLE
+                        | http::StatusCode::GATEWAY_TIMEOUT,
+                    ..
+                } => true,
                 _ => false,
             }
         }
+
+        fn should_retry(&self, num_retries: u32) -> bool {
+            let budget = if self.is_control_plane_failure() {
+                NUM_RETRIES_CONTROL_PLANE_FAILURE
+            } else {
+                NUM_RETRIES_CONNECT
+            };
+            num_retries < budget && self.could_retry()
+        }
     }
 
     impl From<reqwest::Error> for ApiError {
@@ -151,6 +177,16 @@ pub mod errors {
         }
     }
 
+    impl GetAuthInfoError {
+        /// Whether the control plane failed to serve the request, see [`ApiError::is_control_plane_failure`].
+        pub fn is_control_plane_failure(&self) -> bool {
+            match self {
+                GetAuthInfoError::BadSecret => false,
+                GetAuthInfoError::ApiError(e) => e.is_control_plane_failure(),
+            }
+        }
+    }
+
     impl UserFacingError for GetAuthInfoError {
         fn to_string_client(&self) -> String {
             use GetAuthInfoError::*;
@@ -182,6 +218,12 @@ pub mod errors {
 
         #[error("Timeout waiting to acquire wake compute lock")]
         TimeoutError,
+
+        #[error("Too many connection attempts, retry after {retry_after:?}")]
+        TooManyConnections { retry_after: std::time::Duration },
+
+        #[error("Control plane keeps failing for this endpoint, retry after {retry_after:?}")]
+        CircuitBreakerOpen { retry_after: std::time::Duration },
     }
 
     // This allows more useful interactions than `#[from]`.
@@ -213,6 +255,16 @@ pub mod errors {
                 ApiError(e) => e.to_string_client(),
 
                 TimeoutError => "timeout while acquiring the compute resource lock".to_owned(),
+
+                TooManyConnections { retry_after } => format!(
+                    "Too many connection attempts to this endpoint. Please try again in {} seconds.",
+                    retry_after.as_secs_f64().ceil()
+                ),
+
+                CircuitBreakerOpen { retry_after } => format!(
+                    "Control plane is temporarily unable to start this endpoint. Please try again in {} seconds.",
+                    retry_after.as_secs_f64().ceil()
+                ),
             }
         }
     }
@@ -223,6 +275,10 @@ pub mod errors {
                 WakeComputeError::BadComputeAddress(_) => crate::error::ErrorKind::ControlPlane,
                 WakeComputeError::ApiError(e) => e.get_error_kind(),
                 WakeComputeError::TimeoutError => crate::error::ErrorKind::RateLimit,
+                WakeComputeError::TooManyConnections { .. } => crate::error::ErrorKind::RateLimit,
+                WakeComputeError::CircuitBreakerOpen { .. } => {
+                    crate::error::ErrorKind::ControlPlane
+                }
             }
         }
     }
@@ -246,6 +302,8 @@ pub struct AuthInfo {
     pub allowed_ips: Vec<IpPattern>,
     /// Project ID. This is used for cache invalidation.
     pub project_id: Option<ProjectId>,
+    /// Max number of simultaneously open client connections to the endpoint.
+    pub max_client_connections: Option<u32>,
 }
 
 /// Info for establishing a connection to a compute node.
@@ -286,9 +344,13 @@ impl NodeInfo {
 
     pub fn set_keys(&mut self, keys: &ComputeCredentialKeys) {
         match keys {
-            ComputeCredentialKeys::Password(password) => self.config.password(password),
-            ComputeCredentialKeys::AuthKeys(auth_keys) => self.config.auth_keys(*auth_keys),
-        };
+            ComputeCredentialKeys::Password(password) => {
+                self.config.password(password);
+            }
+            ComputeCredentialKeys::AuthKeys(auth_keys) => {
+                self.config.auth_keys(*auth_keys);
+            }
+        }
     }
 }
 
@@ -316,6 +378,21 @@ pub trait Api {
         user_info: &ComputeUserInfo,
     ) -> Result<(CachedAllowedIps, Option<CachedRoleSecret>), errors::GetAuthInfoError>;
 
+    /// Get the password to log into the compute with as the role, for clients which
+    /// authenticated without one, e.g. with a certificate. `None` if the role has none.
+    async fn get_role_password(
+        &self,
+        ctx: &mut RequestMonitoring,
+        user_info: &ComputeUserInfo,
+    ) -> Result<Option<Vec<u8>>, errors::GetAuthInfoError>;
+
+    /// Get the JWKS the endpoint accepts JWTs from, for authenticating with a JWT.
+    async fn get_endpoint_jwks(
+        &self,
+        ctx: &mut RequestMonitoring,
+        endpoint: &EndpointId,
+    ) -> Result<Vec<JwksSettings>, errors::GetAuthInfoError>;
+
     /// Wake up the compute node and return the corresponding connection info.
     async fn wake_compute(
         &self,
@@ -368,6 +445,36 @@ impl Api for ConsoleBackend {
         }
     }
 
+    async fn get_role_password(
+        &self,
+        ctx: &mut RequestMonitoring,
+        user_info: &ComputeUserInfo,
+    ) -> Result<Option<Vec<u8>>, errors::GetAuthInfoError> {
+        use ConsoleBackend::*;
+        match self {
+            Console(api) => api.get_role_password(ctx, user_info).await,
+            #[cfg(any(test, feature = "testing"))]
+            Postgres(api) => api.get_role_password(ctx, user_info).await,
+            #[cfg(test)]
+            Test(_) => unreachable!("this function should never be called in the test backend"),
+        }
+    }
+
+    async fn get_endpoint_jwks(
+        &self,
+        ctx: &mut RequestMonitoring,
+        endpoint: &EndpointId,
+    ) -> Result<Vec<JwksSettings>, errors::GetAuthInfoError> {
+        use ConsoleBackend::*;
+        match self {
+            Console(api) => api.get_endpoint_jwks(ctx, endpoint).await,
+            #[cfg(any(test, feature = "testing"))]
+            Postgres(api) => api.get_endpoint_jwks(ctx, endpoint).await,
+            #[cfg(test)]
+            Test(_) => unreachable!("this function should never be called in the test backend"),
+        }
+    }
+
     async fn wake_compute(
         &self,
         ctx: &mut RequestMonitoring,
@@ -4,10 +4,11 @@ use super::{
     errors::{ApiError, GetAuthInfoError, WakeComputeError},
     AuthInfo, AuthSecret, CachedNodeInfo, NodeInfo,
 };
+use crate::console::messages::JwksSettings;
 use crate::console::provider::{CachedAllowedIps, CachedRoleSecret};
 use crate::context::RequestMonitoring;
 use crate::{auth::backend::ComputeUserInfo, compute, error::io_error, scram, url::ApiUrl};
-use crate::{auth::IpPattern, cache::Cached};
+use crate::{auth::IpPattern, cache::Cached, EndpointId, RoleName};
 use async_trait::async_trait;
 use futures::TryFutureExt;
 use std::{str::FromStr, sync::Arc};
@@ -103,9 +104,69 @@ impl Api {
             secret,
             allowed_ips,
             project_id: None,
+            max_client_connections: None,
         })
     }
 
+    async fn do_get_role_password(
+        &self,
+        user_info: &ComputeUserInfo,
+    ) -> Result<Option<Vec<u8>>, GetAuthInfoError> {
+        let password = async {
+            let (client, connection) =
+                tokio_postgres::connect(self.endpoint.as_str(), tokio_postgres::NoTls).await?;
+
+            tokio::spawn(connection);
+            get_execute_postgres_query(
+                &client,
+                "select password from neon_control_plane.role_passwords where role_name = $1",
+                &[&&*user_info.user],
+                "password",
+            )
+            .await
+        }
+        .map_err(crate::error::log_error::<GetAuthInfoError>)
+        .instrument(info_span!("postgres", url = self.endpoint.as_str()))
+        .await?;
+        Ok(password.map(String::into_bytes))
+    }
+
+    async fn do_get_endpoint_jwks(
+        &self,
+        endpoint: &EndpointId,
+    ) -> Result<Vec<JwksSettings>, GetAuthInfoError> {
+        async {
+            let (client, connection) =
+                tokio_postgres::connect(self.endpoint.as_str(), tokio_postgres::NoTls).await?;
+
+            tokio::spawn(connection);
+            let rows = client
+                .query(
+                    "select id, jwks_url, jwt_audience, role_names from neon_control_plane.endpoint_jwks where endpoint_id = $1",
+                    &[&endpoint.as_str()],
+                )
+                .await?;
+
+            let mut jwks = Vec::with_capacity(rows.len());
+            for row in rows {
+                let id: String = row.try_get("id")?;
+                let role_names: String = row.try_get("role_names")?;
+                jwks.push(JwksSettings {
+                    provider_name: id.clone(),
+                    id,
+                    jwks_url: row.try_get("jwks_url")?,
+                    jwt_audience: row.try_get("jwt_audience")?,
+                    role_claim: JwksSettings::default_role_claim(),
+                    role_names: role_names.split(',').map(RoleName::from).collect(),
+                });
+            }
+            Ok(jwks)
+        }
+        .map_err(crate::error::log_error::<GetAuthInfoError>)
+        .instrument(info_span!("postgres", url = self.endpoint.as_str()))
+        .await
+    }
+
     async fn do_wake_compute(&self) -> Result<NodeInfo, WakeComputeError> {
         let mut config = compute::ConnCfg::new();
         config
@@ -170,6 +231,22 @@ impl super::Api for Api {
         ))
     }
 
+    async fn get_role_password(
+        &self,
+        _ctx: &mut RequestMonitoring,
+        user_info: &ComputeUserInfo,
+    ) -> Result<Option<Vec<u8>>, GetAuthInfoError> {
+        self.do_get_role_password(user_info).await
+    }
+
+    async fn get_endpoint_jwks(
+        &self,
+        _ctx: &mut RequestMonitoring,
+        endpoint: &EndpointId,
+    ) -> Result<Vec<JwksSettings>, GetAuthInfoError> {
+        self.do_get_endpoint_jwks(endpoint).await
+    }
+
     #[tracing::instrument(skip_all)]
     async fn wake_compute(
         &self,
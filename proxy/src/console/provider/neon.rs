@@ -1,16 +1,23 @@
 //! Production console backend.
 
 use super::{
-    super::messages::{ConsoleError, GetRoleSecret, WakeCompute},
+    super::messages::{
+        ConsoleError, GetEndpointJwks, GetRolePassword, GetRoleSecret, JwksSettings, WakeCompute,
+    },
     errors::{ApiError, GetAuthInfoError, WakeComputeError},
     ApiCaches, ApiLocks, AuthInfo, AuthSecret, CachedAllowedIps, CachedNodeInfo, CachedRoleSecret,
     NodeInfo,
 };
-use crate::{auth::backend::ComputeUserInfo, compute, http, scram};
+use crate::{
+    auth::backend::ComputeUserInfo,
+    compute, http,
+    rate_limiter::{WakeComputeCircuitBreaker, WakeComputeRateLimiter},
+    scram, EndpointId,
+};
 use crate::{
     cache::Cached,
     context::RequestMonitoring,
-    metrics::{ALLOWED_IPS_BY_CACHE_OUTCOME, ALLOWED_IPS_NUMBER},
+    metrics::{ALLOWED_IPS_BY_CACHE_OUTCOME, ALLOWED_IPS_NUMBER, PROJECT_INFO_CACHE_STALE_HITS},
 };
 use async_trait::async_trait;
 use futures::TryFutureExt;
@@ -23,6 +30,8 @@ pub struct Api {
     endpoint: http::Endpoint,
     pub caches: &'static ApiCaches,
     locks: &'static ApiLocks,
+    wake_compute_limiter: Option<&'static WakeComputeRateLimiter>,
+    wake_compute_circuit_breaker: Option<&'static WakeComputeCircuitBreaker>,
     jwt: String,
 }
 
@@ -32,6 +41,8 @@ impl Api {
         endpoint: http::Endpoint,
         caches: &'static ApiCaches,
         locks: &'static ApiLocks,
+        wake_compute_limiter: Option<&'static WakeComputeRateLimiter>,
+        wake_compute_circuit_breaker: Option<&'static WakeComputeCircuitBreaker>,
     ) -> Self {
         let jwt: String = match std::env::var("NEON_PROXY_TO_CONTROLPLANE_TOKEN") {
             Ok(v) => v,
@@ -41,6 +52,8 @@ impl Api {
             endpoint,
             caches,
             locks,
+            wake_compute_limiter,
+            wake_compute_circuit_breaker,
             jwt,
         }
     }
@@ -97,6 +110,7 @@ impl Api {
                 secret,
                 allowed_ips,
                 project_id: body.project_id,
+                max_client_connections: body.max_client_connections,
             })
         }
         .map_err(crate::error::log_error)
@@ -104,6 +118,72 @@ impl Api {
         .await
     }
 
+    async fn do_get_role_password(
+        &self,
+        ctx: &mut RequestMonitoring,
+        user_info: &ComputeUserInfo,
+    ) -> Result<Option<Vec<u8>>, GetAuthInfoError> {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        async {
+            let request = self
+                .endpoint
+                .get("proxy_get_role_password")
+                .header("X-Request-ID", &request_id)
+                .header("Authorization", format!("Bearer {}", &self.jwt))
+                .query(&[("session_id", ctx.session_id)])
+                .query(&[
+                    ("project", user_info.endpoint.as_str()),
+                    ("role", user_info.user.as_str()),
+                ])
+                .build()?;
+
+            info!(url = request.url().as_str(), "sending http request");
+            let start = Instant::now();
+            let response = self.endpoint.execute(request).await?;
+            info!(duration = ?start.elapsed(), "received http response");
+            let body = match parse_body::<GetRolePassword>(response).await {
+                Ok(body) => body,
+                // Error 404 is special: the role has no password.
+                Err(e) => match e.http_status_code() {
+                    Some(http::StatusCode::NOT_FOUND) => return Ok(None),
+                    _otherwise => return Err(e.into()),
+                },
+            };
+            Ok(Some(body.password.as_bytes().to_vec()))
+        }
+        .map_err(crate::error::log_error)
+        .instrument(info_span!("http", id = request_id))
+        .await
+    }
+
+    async fn do_get_endpoint_jwks(
+        &self,
+        ctx: &mut RequestMonitoring,
+        endpoint: &EndpointId,
+    ) -> Result<Vec<JwksSettings>, GetAuthInfoError> {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        async {
+            let request = self
+                .endpoint
+                .get("proxy_get_endpoint_jwks")
+                .header("X-Request-ID", &request_id)
+                .header("Authorization", format!("Bearer {}", &self.jwt))
+                .query(&[("session_id", ctx.session_id)])
+                .query(&[("project", endpoint.as_str())])
+                .build()?;
+
+            info!(url = request.url().as_str(), "sending http request");
+            let start = Instant::now();
+            let response = self.endpoint.execute(request).await?;
+            info!(duration = ?start.elapsed(), "received http response");
+            let body = parse_body::<GetEndpointJwks>(response).await?;
+            Ok(body.jwks)
+        }
+        .map_err(crate::error::log_error)
+        .instrument(info_span!("http", id = request_id))
+        .await
+    }
+
     async fn do_wake_compute(
         &self,
         ctx: &mut RequestMonitoring,
@@ -175,7 +255,22 @@ impl super::Api for Api {
         if let Some(role_secret) = self.caches.project_info.get_role_secret(ep, user) {
             return Ok(role_secret);
         }
-        let auth_info = self.do_get_auth_info(ctx, user_info).await?;
+        let auth_info = match self.do_get_auth_info(ctx, user_info).await {
+            Ok(auth_info) => auth_info,
+            Err(e) if e.is_control_plane_failure() => {
+                // Don't fail the login just because the console is having a bad time.
+                let Some(role_secret) = self.caches.project_info.get_stale_role_secret(ep, user)
+                else {
+                    return Err(e);
+                };
+                warn!("using a stale role secret, console is unavailable: {e}");
+                PROJECT_INFO_CACHE_STALE_HITS
+                    .with_label_values(&["role_secret"])
+                    .inc();
+                return Ok(role_secret);
+            }
+            Err(e) => return Err(e),
+        };
         if let Some(project_id) = auth_info.project_id {
             self.caches.project_info.insert_role_secret(
                 &project_id,
@@ -188,8 +283,12 @@ impl super::Api for Api {
                 ep,
                 Arc::new(auth_info.allowed_ips),
             );
+            self.caches
+                .project_info
+                .insert_max_client_connections(ep, auth_info.max_client_connections);
             ctx.set_project_id(project_id);
         }
+        ctx.set_max_client_connections(auth_info.max_client_connections);
         // When we just got a secret, we don't need to invalidate it.
         Ok(Cached::new_uncached(auth_info.secret))
     }
@@ -204,12 +303,30 @@ impl super::Api for Api {
             ALLOWED_IPS_BY_CACHE_OUTCOME
                 .with_label_values(&["hit"])
                 .inc();
+            ctx.set_max_client_connections(self.caches.project_info.get_max_client_connections(ep));
             return Ok((allowed_ips, None));
         }
         ALLOWED_IPS_BY_CACHE_OUTCOME
             .with_label_values(&["miss"])
             .inc();
-        let auth_info = self.do_get_auth_info(ctx, user_info).await?;
+        let auth_info = match self.do_get_auth_info(ctx, user_info).await {
+            Ok(auth_info) => auth_info,
+            Err(e) if e.is_control_plane_failure() => {
+                let Some(allowed_ips) = self.caches.project_info.get_stale_allowed_ips(ep) else {
+                    return Err(e);
+                };
+                warn!("using stale allowed ips, console is unavailable: {e}");
+                PROJECT_INFO_CACHE_STALE_HITS
+                    .with_label_values(&["allowed_ips"])
+                    .inc();
+                ctx.set_max_client_connections(
+                    self.caches.project_info.get_max_client_connections(ep),
+                );
+                // The role secret is looked up separately, falling back to the stale entry as well.
+                return Ok((allowed_ips, None));
+            }
+            Err(e) => return Err(e),
+        };
         let allowed_ips = Arc::new(auth_info.allowed_ips);
         let user = &user_info.user;
         if let Some(project_id) = auth_info.project_id {
@@ -222,14 +339,36 @@ impl super::Api for Api {
             self.caches
                 .project_info
                 .insert_allowed_ips(&project_id, ep, allowed_ips.clone());
+            self.caches
+                .project_info
+                .insert_max_client_connections(ep, auth_info.max_client_connections);
             ctx.set_project_id(project_id);
         }
+        ctx.set_max_client_connections(auth_info.max_client_connections);
         Ok((
             Cached::new_uncached(allowed_ips),
             Some(Cached::new_uncached(auth_info.secret)),
         ))
     }
 
+    #[tracing::instrument(skip_all)]
+    async fn get_role_password(
+        &self,
+        ctx: &mut RequestMonitoring,
+        user_info: &ComputeUserInfo,
+    ) -> Result<Option<Vec<u8>>, GetAuthInfoError> {
+        self.do_get_role_password(ctx, user_info).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_endpoint_jwks(
+        &self,
+        ctx: &mut RequestMonitoring,
+        endpoint: &EndpointId,
+    ) -> Result<Vec<JwksSettings>, GetAuthInfoError> {
+        self.do_get_endpoint_jwks(ctx, endpoint).await
+    }
+
     #[tracing::instrument(skip_all)]
     async fn wake_compute(
         &self,
@@ -247,6 +386,23 @@ impl super::Api for Api {
             return Ok(cached);
         }
 
+        // Don't let a storm of connection attempts to a single endpoint
+        // translate into a storm of requests to the control plane.
+        if let Some(limiter) = self.wake_compute_limiter {
+            if let Err(retry_after) = limiter.check(&user_info.endpoint, ctx.peer_addr) {
+                warn!(?retry_after, "wake_compute rate limit exceeded");
+                return Err(WakeComputeError::TooManyConnections { retry_after });
+            }
+        }
+
+        // Fail fast if the control plane keeps failing for this endpoint.
+        if let Some(breaker) = self.wake_compute_circuit_breaker {
+            if let Err(retry_after) = breaker.check(&user_info.endpoint) {
+                warn!(?retry_after, "wake_compute circuit breaker is open");
+                return Err(WakeComputeError::CircuitBreakerOpen { retry_after });
+            }
+        }
+
         let permit = self.locks.get_wake_compute_permit(&key).await?;
 
         // after getting back a permit - it's possible the cache was filled
@@ -258,7 +414,17 @@ impl super::Api for Api {
             }
         }
 
-        let node = self.do_wake_compute(ctx, user_info).await?;
+        let node = self.do_wake_compute(ctx, user_info).await;
+        if let Some(breaker) = self.wake_compute_circuit_breaker {
+            match &node {
+                Ok(_) => breaker.record_success(&user_info.endpoint),
+                Err(WakeComputeError::ApiError(e)) if e.is_control_plane_failure() => {
+                    breaker.record_failure(&user_info.endpoint)
+                }
+                Err(_) => {}
+            }
+        }
+        let node = node?;
         let (_, cached) = self.caches.node_info.insert(key.clone(), node);
         info!(key = &*key, "created a cache entry for compute node info");
 
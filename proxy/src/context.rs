@@ -10,13 +10,17 @@ use uuid::Uuid;
 use crate::{
     console::messages::MetricsAuxInfo,
     error::ErrorKind,
-    metrics::{LatencyTimer, ENDPOINT_ERRORS_BY_KIND, ERROR_BY_KIND},
+    metrics::{
+        LatencyBreakdown, LatencyPhase, LatencyTimer, ENDPOINT_ERRORS_BY_KIND, ERROR_BY_KIND,
+    },
     BranchId, DbName, EndpointId, ProjectId, RoleName,
 };
 
+pub mod audit;
 pub mod parquet;
 
 static LOG_CHAN: OnceCell<mpsc::WeakUnboundedSender<RequestMonitoring>> = OnceCell::new();
+static AUDIT_CHAN: OnceCell<mpsc::WeakUnboundedSender<audit::AuditRecord>> = OnceCell::new();
 
 #[derive(Clone)]
 /// Context data for a single request to connect to a database.
@@ -39,12 +43,15 @@ pub struct RequestMonitoring {
     application: Option<SmolStr>,
     error_kind: Option<ErrorKind>,
     pub(crate) auth_method: Option<AuthMethod>,
+    max_client_connections: Option<u32>,
     success: bool,
 
     // extra
     // This sender is here to keep the request monitoring channel open while requests are taking place.
     sender: Option<mpsc::UnboundedSender<RequestMonitoring>>,
+    audit_sender: Option<mpsc::UnboundedSender<audit::AuditRecord>>,
     pub latency_timer: LatencyTimer,
+    pub latency_breakdown: LatencyBreakdown,
 }
 
 #[derive(Clone, Debug)]
@@ -54,6 +61,21 @@ pub enum AuthMethod {
     ScramSha256,
     ScramSha256Plus,
     Cleartext,
+    ClientCertificate,
+    Jwt,
+}
+
+impl AuthMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthMethod::Web => "web",
+            AuthMethod::ScramSha256 => "scram_sha_256",
+            AuthMethod::ScramSha256Plus => "scram_sha_256_plus",
+            AuthMethod::Cleartext => "cleartext",
+            AuthMethod::ClientCertificate => "client_certificate",
+            AuthMethod::Jwt => "jwt",
+        }
+    }
 }
 
 impl RequestMonitoring {
@@ -78,10 +100,13 @@ impl RequestMonitoring {
             application: None,
             error_kind: None,
             auth_method: None,
+            max_client_connections: None,
             success: false,
 
             sender: LOG_CHAN.get().and_then(|tx| tx.upgrade()),
+            audit_sender: AUDIT_CHAN.get().and_then(|tx| tx.upgrade()),
             latency_timer: LatencyTimer::new(protocol),
+            latency_breakdown: LatencyBreakdown::default(),
         }
     }
 
@@ -108,6 +133,15 @@ impl RequestMonitoring {
         self.project = Some(project_id);
     }
 
+    pub fn set_max_client_connections(&mut self, limit: Option<u32>) {
+        self.max_client_connections = limit;
+    }
+
+    /// The endpoint and the max number of open client connections to it, if it's limited.
+    pub fn connection_limit(&self) -> Option<(&EndpointId, u32)> {
+        Some((self.endpoint_id.as_ref()?, self.max_client_connections?))
+    }
+
     pub fn set_endpoint_id(&mut self, endpoint_id: EndpointId) {
         crate::metrics::CONNECTING_ENDPOINTS
             .with_label_values(&[self.protocol])
@@ -143,11 +177,18 @@ impl RequestMonitoring {
         self.error_kind = Some(kind);
     }
 
+    pub fn record_latency(&mut self, phase: LatencyPhase, elapsed: std::time::Duration) {
+        self.latency_breakdown.record(self.protocol, phase, elapsed);
+    }
+
     pub fn set_success(&mut self) {
         self.success = true;
     }
 
     pub fn log(&mut self) {
+        if let Some(tx) = self.audit_sender.take() {
+            let _: Result<(), _> = tx.send(audit::AuditRecord::from(&*self));
+        }
         if let Some(tx) = self.sender.take() {
             let _: Result<(), _> = tx.send(self.clone());
         }
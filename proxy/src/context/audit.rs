@@ -0,0 +1,295 @@
+//! Connection audit log.
+//!
+//! Every connection attempt results in one JSON line, written either to stdout or to a file
+//! which is rotated once it grows too large. Failed attempts are rate limited per source IP,
+//! so that brute force attempts don't flood the log.
+
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use tokio::{
+    fs,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    metrics::{AUDIT_LOG_RECORDS, AUDIT_LOG_WRITE_ERRORS},
+    rate_limiter::{LeakyBucketConfig, LeakyBucketRateLimiter},
+};
+
+use super::{RequestMonitoring, AUDIT_CHAN};
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct AuditLogArgs {
+    /// Where to write the connection audit log to, `-` for stdout. Disabled if not set.
+    #[clap(long)]
+    audit_log: Option<String>,
+
+    /// Rotate the audit log file once it's larger than this many bytes
+    #[clap(long, default_value_t = 100_000_000)]
+    audit_log_max_size: u64,
+
+    /// How many rotated audit log files to keep
+    #[clap(long, default_value_t = 5)]
+    audit_log_max_files: usize,
+
+    /// How many failed connection attempts per second to log for a single source IP
+    #[clap(long, default_value_t = 1.0)]
+    audit_log_failures_rps: f64,
+
+    /// How many failed connection attempts from a single source IP to log in a burst
+    #[clap(long, default_value_t = 20.0)]
+    audit_log_failures_burst: f64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct AuditRecord {
+    timestamp: chrono::DateTime<chrono::Utc>,
+    session_id: Uuid,
+    region: &'static str,
+    protocol: &'static str,
+    peer_addr: IpAddr,
+    endpoint_id: Option<String>,
+    project: Option<String>,
+    role: Option<String>,
+    database: Option<String>,
+    application_name: Option<String>,
+    auth_method: Option<&'static str>,
+    result: &'static str,
+    error: Option<&'static str>,
+}
+
+impl From<&RequestMonitoring> for AuditRecord {
+    fn from(value: &RequestMonitoring) -> Self {
+        Self {
+            timestamp: value.first_packet,
+            session_id: value.session_id,
+            region: value.region,
+            protocol: value.protocol,
+            peer_addr: value.peer_addr,
+            endpoint_id: value.endpoint_id.as_deref().map(String::from),
+            project: value.project.as_deref().map(String::from),
+            role: value.user.as_deref().map(String::from),
+            database: value.dbname.as_deref().map(String::from),
+            application_name: value.application.as_deref().map(String::from),
+            auth_method: value.auth_method.as_ref().map(|x| x.as_str()),
+            result: if value.success { "success" } else { "failure" },
+            error: value.error_kind.as_ref().map(|e| e.to_metric_label()),
+        }
+    }
+}
+
+/// Audit log worker
+///
+/// It listens on a channel for all completed requests and writes an audit record for each of them.
+pub async fn worker(
+    cancellation_token: CancellationToken,
+    config: AuditLogArgs,
+) -> anyhow::Result<()> {
+    let Some(target) = &config.audit_log else {
+        return Ok(());
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    AUDIT_CHAN.set(tx.downgrade()).unwrap();
+
+    tokio::spawn(async move {
+        cancellation_token.cancelled().await;
+        // dropping this sender will cause the channel to close only once
+        // all the remaining inflight requests have been completed.
+        drop(tx);
+    });
+
+    let failures = LeakyBucketRateLimiter::<IpAddr>::new(LeakyBucketConfig::new(
+        config.audit_log_failures_rps,
+        config.audit_log_failures_burst,
+    ));
+
+    let mut writer = if target.as_str() == "-" {
+        info!("writing audit log to stdout");
+        AuditWriter::Stdout(BufWriter::new(tokio::io::stdout()))
+    } else {
+        info!("writing audit log to {target}");
+        AuditWriter::File(RotatingFile::open(PathBuf::from(target), &config).await?)
+    };
+
+    // The audit log is not worth refusing connections for, so write errors are
+    // only logged and counted. Log only the first of them in a row, a full disk
+    // would otherwise add a line to the regular log for every connection.
+    let mut failing = false;
+    let mut on_result = |res: anyhow::Result<()>| match res {
+        Ok(()) => {
+            if failing {
+                info!("audit log is written again");
+                failing = false;
+            }
+        }
+        Err(e) => {
+            AUDIT_LOG_WRITE_ERRORS.inc();
+            if !failing {
+                warn!("failed to write audit log: {e:#}");
+                failing = true;
+            }
+        }
+    };
+
+    let mut line = Vec::new();
+    while let Some(record) = rx.recv().await {
+        if record.result != "success" && failures.check(record.peer_addr, 1).is_err() {
+            AUDIT_LOG_RECORDS.with_label_values(&["dropped"]).inc();
+            continue;
+        }
+
+        line.clear();
+        serde_json::to_writer(&mut line, &record)?;
+        line.push(b'\n');
+        let res = writer.write(&line).await;
+        if res.is_ok() {
+            AUDIT_LOG_RECORDS.with_label_values(&["written"]).inc();
+        }
+        on_result(res);
+
+        // don't hold the records back if there are no more of them coming
+        if rx.is_empty() {
+            on_result(writer.flush().await);
+        }
+    }
+
+    on_result(writer.flush().await);
+    Ok(())
+}
+
+enum AuditWriter {
+    Stdout(BufWriter<tokio::io::Stdout>),
+    File(RotatingFile),
+}
+
+impl AuditWriter {
+    async fn write(&mut self, buf: &[u8]) -> anyhow::Result<()> {
+        match self {
+            AuditWriter::Stdout(w) => Ok(w.write_all(buf).await?),
+            AuditWriter::File(f) => f.write(buf).await,
+        }
+    }
+
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        match self {
+            AuditWriter::Stdout(w) => Ok(w.flush().await?),
+            AuditWriter::File(f) => Ok(f.file.flush().await?),
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: BufWriter<fs::File>,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    async fn open(path: PathBuf, config: &AuditLogArgs) -> anyhow::Result<Self> {
+        let (file, size) = open_append(&path).await?;
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size: config.audit_log_max_size,
+            max_files: config.audit_log_max_files,
+        })
+    }
+
+    async fn write(&mut self, buf: &[u8]) -> anyhow::Result<()> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate().await?;
+        }
+        self.file.write_all(buf).await?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Move `audit.log` to `audit.log.1`, `audit.log.1` to `audit.log.2` and so on,
+    /// dropping the oldest file, then start a new one.
+    async fn rotate(&mut self) -> anyhow::Result<()> {
+        self.file.flush().await?;
+
+        let rotated = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{n}"));
+            PathBuf::from(path)
+        };
+        for n in (1..self.max_files).rev() {
+            match fs::rename(rotated(n), rotated(n + 1)).await {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                res => res?,
+            }
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, rotated(1)).await?;
+        } else {
+            fs::remove_file(&self.path).await?;
+        }
+
+        (self.file, self.size) = open_append(&self.path).await?;
+        Ok(())
+    }
+}
+
+async fn open_append(path: &Path) -> anyhow::Result<(BufWriter<fs::File>, u64)> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("failed to open audit log {}", path.display()))?;
+    let size = file.metadata().await?.len();
+    if size > 0 {
+        warn!("appending to existing audit log {}", path.display());
+    }
+    Ok((BufWriter::new(file), size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use camino_tempfile::tempdir;
+    use tokio::io::AsyncWriteExt;
+
+    use super::{AuditLogArgs, RotatingFile};
+
+    #[tokio::test]
+    async fn rotate_files() {
+        let dir = tempdir().unwrap();
+        let path: PathBuf = dir.path().join("audit.log").into();
+        let config = AuditLogArgs {
+            audit_log: None,
+            audit_log_max_size: 10,
+            audit_log_max_files: 2,
+            audit_log_failures_rps: 1.0,
+            audit_log_failures_burst: 1.0,
+        };
+
+        let mut file = RotatingFile::open(path.clone(), &config).await.unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write(line.as_bytes()).await.unwrap();
+        }
+        file.file.flush().await.unwrap();
+
+        let read =
+            |suffix: &str| std::fs::read_to_string(format!("{}{suffix}", path.display())).unwrap();
+        assert_eq!(read(""), "fourth\n");
+        assert_eq!(read(".1"), "third\n");
+        assert_eq!(read(".2"), "second\n");
+        assert!(!dir.path().join("audit.log.3").exists());
+    }
+}
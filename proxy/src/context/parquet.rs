@@ -109,12 +109,7 @@ impl From<RequestMonitoring> for RequestData {
             database: value.dbname.as_deref().map(String::from),
             project: value.project.as_deref().map(String::from),
             branch: value.branch.as_deref().map(String::from),
-            auth_method: value.auth_method.as_ref().map(|x| match x {
-                super::AuthMethod::Web => "web",
-                super::AuthMethod::ScramSha256 => "scram_sha_256",
-                super::AuthMethod::ScramSha256Plus => "scram_sha_256_plus",
-                super::AuthMethod::Cleartext => "cleartext",
-            }),
+            auth_method: value.auth_method.as_ref().map(|x| x.as_str()),
             protocol: value.protocol,
             region: value.region,
             error: value.error_kind.as_ref().map(|e| e.to_metric_label()),
@@ -27,6 +27,13 @@ pub trait UserFacingError: ReportableError {
     fn to_string_client(&self) -> String {
         self.to_string()
     }
+
+    /// SQLSTATE code to report to the client, if there is a more specific
+    /// one than `XX000` (internal error).
+    #[inline(always)]
+    fn sqlstate(&self) -> Option<&'static [u8; 5]> {
+        None
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
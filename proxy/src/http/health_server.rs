@@ -1,23 +1,174 @@
 use anyhow::{anyhow, bail};
-use hyper::{Body, Request, Response, StatusCode};
-use std::{convert::Infallible, net::TcpListener};
-use tracing::info;
-use utils::http::{endpoint, error::ApiError, json::json_response, RouterBuilder, RouterService};
+use hyper::{Body, Request, Response, StatusCode, Uri};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::{collections::HashSet, convert::Infallible, net::TcpListener, sync::Arc};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utils::auth::{Claims, Scope, SwappableJwtAuth};
+use utils::http::{
+    endpoint::{self, auth_middleware, check_permission_with},
+    error::ApiError,
+    json::{json_request, json_response},
+    request::get_request_param,
+    RequestExt, RouterBuilder, RouterService,
+};
 
-async fn status_handler(_: Request<Body>) -> Result<Response<Body>, ApiError> {
+use crate::serverless::{EndpointPoolLimits, GlobalConnPool};
+use crate::EndpointId;
+
+type ConnPool = Arc<GlobalConnPool<tokio_postgres::Client>>;
+
+async fn status_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    // Let the load balancer know that no new connections should be routed here.
+    if get_shutdown_token(&request).is_cancelled() {
+        return json_response(StatusCode::SERVICE_UNAVAILABLE, "draining");
+    }
+    json_response(StatusCode::OK, "")
+}
+
+/// Stop accepting new connections and shut down once the existing ones are closed,
+/// same as on SIGTERM.
+async fn drain_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    check_permission(&request)?;
+    warn!("received a drain request, shutting down once all existing connections have closed");
+    get_shutdown_token(&request).cancel();
     json_response(StatusCode::OK, "")
 }
 
-fn make_router() -> RouterBuilder<hyper::Body, ApiError> {
-    endpoint::make_router().get("/v1/status", status_handler)
+fn get_shutdown_token(request: &Request<Body>) -> &CancellationToken {
+    request
+        .data::<CancellationToken>()
+        .expect("unknown state type")
+}
+
+fn get_conn_pool(request: &Request<Body>) -> &ConnPool {
+    request.data::<ConnPool>().expect("unknown state type")
 }
 
-pub async fn task_main(http_listener: TcpListener) -> anyhow::Result<Infallible> {
+fn get_endpoint_id(request: &Request<Body>) -> Result<EndpointId, ApiError> {
+    Ok(get_request_param(request, "endpoint_id")?.into())
+}
+
+fn check_permission(request: &Request<Body>) -> Result<(), ApiError> {
+    check_permission_with(request, |claims: &Claims| match claims.scope {
+        Scope::ProxyApi => Ok(()),
+        _ => Err(utils::auth::AuthError(
+            "only ProxyApi scope is allowed for the proxy".into(),
+        )),
+    })
+}
+
+async fn pool_status_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    check_permission(&request)?;
+    json_response(StatusCode::OK, get_conn_pool(&request).status())
+}
+
+/// Request to change limits of the sql over http connection pool; omitted
+/// fields are left as is.
+#[derive(Deserialize)]
+struct PoolLimitsRequest {
+    max_total_conns: Option<usize>,
+    default_limits: Option<EndpointPoolLimits>,
+}
+
+async fn pool_limits_handler(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    check_permission(&request)?;
+    let req: PoolLimitsRequest = json_request(&mut request).await?;
+    let pool = get_conn_pool(&request);
+    if let Some(limits) = req.default_limits {
+        pool.set_default_limits(limits)
+            .map_err(ApiError::BadRequest)?;
+    }
+    if let Some(max_total_conns) = req.max_total_conns {
+        pool.set_max_total_conns(max_total_conns);
+    }
+    json_response(StatusCode::OK, pool.status())
+}
+
+async fn endpoint_pool_limits_handler(
+    mut request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request)?;
+    let endpoint_id = get_endpoint_id(&request)?;
+    let limits: EndpointPoolLimits = json_request(&mut request).await?;
+    let pool = get_conn_pool(&request);
+    pool.set_endpoint_limits(endpoint_id, Some(limits))
+        .map_err(ApiError::BadRequest)?;
+    json_response(StatusCode::OK, pool.status())
+}
+
+async fn endpoint_pool_limits_reset_handler(
+    request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request)?;
+    let endpoint_id = get_endpoint_id(&request)?;
+    let pool = get_conn_pool(&request);
+    pool.set_endpoint_limits(endpoint_id, None)
+        .map_err(ApiError::BadRequest)?;
+    json_response(StatusCode::OK, pool.status())
+}
+
+/// Router of the http listener. The routes which change state are only mounted
+/// with `auth`, and require a token of the ProxyApi scope.
+fn make_router(
+    conn_pool: ConnPool,
+    shutdown: CancellationToken,
+    auth: Option<Arc<SwappableJwtAuth>>,
+) -> RouterBuilder<hyper::Body, ApiError> {
+    let router = endpoint::make_router()
+        .data(conn_pool)
+        .data(shutdown)
+        .get("/v1/status", status_handler);
+
+    let Some(auth) = auth else {
+        return router;
+    };
+
+    router
+        .middleware(auth_middleware(|request| {
+            #[allow(clippy::mutable_key_type)]
+            static ALLOWLIST_ROUTES: Lazy<HashSet<Uri>> = Lazy::new(|| {
+                ["/v1/status", "/metrics"]
+                    .iter()
+                    .map(|v| v.parse().unwrap())
+                    .collect()
+            });
+            if ALLOWLIST_ROUTES.contains(request.uri()) {
+                None
+            } else {
+                // Arc<SwappableJwtAuth> is always provided as data below, hence unwrap().
+                Some(request.data::<Arc<SwappableJwtAuth>>().unwrap().as_ref())
+            }
+        }))
+        .data(auth)
+        .post("/v1/drain", drain_handler)
+        .get("/v1/sql_over_http/pool", pool_status_handler)
+        .put("/v1/sql_over_http/pool", pool_limits_handler)
+        .put(
+            "/v1/sql_over_http/pool/endpoints/:endpoint_id",
+            endpoint_pool_limits_handler,
+        )
+        .delete(
+            "/v1/sql_over_http/pool/endpoints/:endpoint_id",
+            endpoint_pool_limits_reset_handler,
+        )
+}
+
+pub async fn task_main(
+    http_listener: TcpListener,
+    conn_pool: ConnPool,
+    shutdown: CancellationToken,
+    auth: Option<Arc<SwappableJwtAuth>>,
+) -> anyhow::Result<Infallible> {
     scopeguard::defer! {
         info!("http has shut down");
     }
 
-    let service = || RouterService::new(make_router().build()?);
+    if auth.is_none() {
+        info!("http auth is not configured, the routes which change state are disabled");
+    }
+    let service = || RouterService::new(make_router(conn_pool, shutdown, auth).build()?);
 
     hyper::Server::from_tcp(http_listener)?
         .serve(service().map_err(|e| anyhow!(e))?)
@@ -1,11 +1,13 @@
 #![deny(clippy::undocumented_unsafe_blocks)]
 
-use std::convert::Infallible;
+use std::{convert::Infallible, future::Future, time::Duration};
 
 use anyhow::{bail, Context};
 use tokio::task::JoinError;
-use tokio_util::sync::CancellationToken;
-use tracing::warn;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
+
+use crate::metrics::NUM_DRAINING_CONNECTIONS;
 
 pub mod auth;
 pub mod cache;
@@ -34,7 +36,14 @@ pub mod usage_metrics;
 pub mod waiters;
 
 /// Handle unix signals appropriately.
-pub async fn handle_signals(token: CancellationToken) -> anyhow::Result<Infallible> {
+pub async fn handle_signals<F, Fut>(
+    token: CancellationToken,
+    mut refresh_config: F,
+) -> anyhow::Result<Infallible>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
     use tokio::signal::unix::{signal, SignalKind};
 
     let mut hangup = signal(SignalKind::hangup())?;
@@ -45,7 +54,8 @@ pub async fn handle_signals(token: CancellationToken) -> anyhow::Result<Infallib
         tokio::select! {
             // Hangup is commonly used for config reload.
             _ = hangup.recv() => {
-                warn!("received SIGHUP; config reload is not supported");
+                warn!("received SIGHUP");
+                refresh_config().await;
             }
             // Shut down the whole application.
             _ = interrupt.recv() => {
@@ -60,6 +70,39 @@ pub async fn handle_signals(token: CancellationToken) -> anyhow::Result<Infallib
     }
 }
 
+/// Fail if the connections haven't been drained in `timeout` after the shutdown has started.
+/// Without a timeout, wait for them indefinitely.
+pub async fn drain_deadline(
+    token: CancellationToken,
+    timeout: Option<Duration>,
+) -> anyhow::Result<Infallible> {
+    token.cancelled().await;
+    let Some(timeout) = timeout else {
+        return Ok(std::future::pending().await);
+    };
+    tokio::time::sleep(timeout).await;
+    bail!("connections haven't been drained in {timeout:?}, exiting")
+}
+
+/// Wait for the connections to close, reporting how many of them are left.
+/// `connections` must be closed already.
+pub async fn drain_connections(connections: &TaskTracker, protocol: &'static str) {
+    info!(protocol, "draining {} connections", connections.len());
+
+    let gauge = NUM_DRAINING_CONNECTIONS.with_label_values(&[protocol]);
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        gauge.set(connections.len() as i64);
+        tokio::select! {
+            _ = connections.wait() => break,
+            _ = interval.tick() => {}
+        }
+    }
+    gauge.set(0);
+
+    info!(protocol, "all connections are closed");
+}
+
 /// Flattens `Result<Result<T>>` into `Result<T>`.
 pub fn flatten_err<T>(r: Result<anyhow::Result<T>, JoinError>) -> anyhow::Result<T> {
     r.context("join error").and_then(|x| x)
@@ -1,13 +1,14 @@
 use ::metrics::{
     exponential_buckets, register_histogram, register_histogram_vec, register_hll_vec,
-    register_int_counter_pair_vec, register_int_counter_vec, register_int_gauge,
-    register_int_gauge_vec, Histogram, HistogramVec, HyperLogLogVec, IntCounterPairVec,
-    IntCounterVec, IntGauge, IntGaugeVec,
+    register_int_counter, register_int_counter_pair_vec, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Histogram, HistogramVec, HyperLogLogVec,
+    IntCounter, IntCounterPairVec, IntCounterVec, IntGauge, IntGaugeVec,
 };
 use metrics::{register_int_counter_pair, IntCounterPair};
 
 use once_cell::sync::Lazy;
 use tokio::time;
+use tracing::info;
 
 pub static NUM_DB_CONNECTIONS_GAUGE: Lazy<IntCounterPairVec> = Lazy::new(|| {
     register_int_counter_pair_vec!(
@@ -55,6 +56,18 @@ pub static COMPUTE_CONNECTION_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static CONNECTION_PHASE_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "proxy_connection_phase_latency_seconds",
+        "Time spent in each phase of a client connection",
+        // http/ws/tcp, handshake/auth/wake_compute/connect_compute/first_byte
+        &["protocol", "phase"],
+        // largest bucket = 2^16 * 0.5ms = 32s
+        exponential_buckets(0.0005, 2.0, 16).unwrap(),
+    )
+    .unwrap()
+});
+
 pub static CONSOLE_REQUEST_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
     register_histogram_vec!(
         "proxy_console_request_latency",
@@ -77,6 +90,16 @@ pub static ALLOWED_IPS_BY_CACHE_OUTCOME: Lazy<IntCounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static PROJECT_INFO_CACHE_STALE_HITS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_project_info_cache_stale_hits_total",
+        "Number of expired cache entries used because the console was unavailable",
+        // role_secret/allowed_ips
+        &["lookup"],
+    )
+    .unwrap()
+});
+
 pub static RATE_LIMITER_ACQUIRE_LATENCY: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "proxy_control_plane_token_acquire_seconds",
@@ -87,6 +110,15 @@ pub static RATE_LIMITER_ACQUIRE_LATENCY: Lazy<Histogram> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static NUM_DRAINING_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "proxy_draining_connections",
+        "Number of client connections left to close during the shutdown",
+        &["protocol"],
+    )
+    .unwrap()
+});
+
 pub static RATE_LIMITER_LIMIT: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
         "semaphore_control_plane_limit",
@@ -114,6 +146,37 @@ pub static ALLOWED_IPS_NUMBER: Lazy<Histogram> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static IP_NOT_ALLOWED_REJECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_ip_not_allowed_rejections_total",
+        "Number of connections rejected because the client IP is not in the allowed list of the endpoint",
+        &["protocol"],
+    )
+    .unwrap()
+});
+
+pub static IP_NOT_ALLOWED_ENDPOINTS: Lazy<HyperLogLogVec<32>> = Lazy::new(|| {
+    register_hll_vec!(
+        32,
+        "proxy_ip_not_allowed_endpoints",
+        "HLL approximate cardinality of endpoints which rejected connections because the client IP is not in their allowed list",
+        &["protocol"],
+    )
+    .unwrap()
+});
+
+/// Count a connection rejected because the client IP is not allowed. The
+/// endpoints are only counted approximately, to bound the cardinality of the
+/// metrics; the rejections of an endpoint are in the logs.
+pub fn count_ip_not_allowed(endpoint: &str, protocol: &str) {
+    IP_NOT_ALLOWED_REJECTIONS
+        .with_label_values(&[protocol])
+        .inc();
+    IP_NOT_ALLOWED_ENDPOINTS
+        .with_label_values(&[protocol])
+        .measure(&endpoint);
+}
+
 pub static HTTP_CONTENT_LENGTH: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "proxy_http_conn_content_length_bytes",
@@ -124,6 +187,16 @@ pub static HTTP_CONTENT_LENGTH: Lazy<Histogram> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static HTTP_PREPARED_STATEMENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_http_prepared_statements_total",
+        "Number of named prepared statement lookups in the http connection pool",
+        // hit/miss
+        &["outcome"],
+    )
+    .unwrap()
+});
+
 pub static GC_LATENCY: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "proxy_http_pool_reclaimation_lag_seconds",
@@ -152,6 +225,40 @@ pub static NUM_OPEN_CLIENTS_IN_HTTP_POOL: Lazy<IntGauge> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static POOL_MAX_TOTAL_CONNS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "proxy_http_pool_max_total_conns",
+        "Max total number of connections in the http pool.",
+    )
+    .unwrap()
+});
+
+pub static POOL_ENDPOINT_LIMIT_OVERRIDES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "proxy_http_pool_endpoint_limit_overrides",
+        "Number of endpoints with http pool limits different from the default.",
+    )
+    .unwrap()
+});
+
+pub static POOL_DISCARDED_CONNECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_http_pool_discarded_connections_total",
+        "Number of connections not returned to the http pool because it was full.",
+        // endpoint/global
+        &["limit"],
+    )
+    .unwrap()
+});
+
+pub static NUM_OPEN_HTTP_TRANSACTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "proxy_http_open_transactions",
+        "Number of SQL-over-HTTP transactions kept open between requests.",
+    )
+    .unwrap()
+});
+
 #[derive(Clone)]
 pub struct LatencyTimer {
     // time since the stopwatch was started
@@ -231,6 +338,69 @@ impl Drop for LatencyTimer {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum LatencyPhase {
+    /// Startup packet and TLS handshake.
+    Handshake,
+    /// Authentication, including the round trips to the client and the console.
+    Auth,
+    WakeCompute,
+    /// A single attempt to connect to the compute.
+    ConnectCompute,
+    /// From the first request forwarded to the compute till the first byte of its response.
+    FirstByte,
+}
+
+impl LatencyPhase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LatencyPhase::Handshake => "handshake",
+            LatencyPhase::Auth => "auth",
+            LatencyPhase::WakeCompute => "wake_compute",
+            LatencyPhase::ConnectCompute => "connect_compute",
+            LatencyPhase::FirstByte => "first_byte",
+        }
+    }
+}
+
+/// Time spent in each phase of a single client connection, to tell the proxy
+/// overhead apart from the time spent by the control plane and the compute.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyBreakdown {
+    pub handshake: time::Duration,
+    pub auth: time::Duration,
+    pub wake_compute: time::Duration,
+    pub connect_compute: time::Duration,
+    pub first_byte: Option<time::Duration>,
+}
+
+impl LatencyBreakdown {
+    pub fn record(&mut self, protocol: &'static str, phase: LatencyPhase, elapsed: time::Duration) {
+        CONNECTION_PHASE_LATENCY
+            .with_label_values(&[protocol, phase.as_str()])
+            .observe(elapsed.as_secs_f64());
+
+        match phase {
+            LatencyPhase::Handshake => self.handshake += elapsed,
+            LatencyPhase::Auth => self.auth += elapsed,
+            LatencyPhase::WakeCompute => self.wake_compute += elapsed,
+            LatencyPhase::ConnectCompute => self.connect_compute += elapsed,
+            LatencyPhase::FirstByte => self.first_byte = Some(elapsed),
+        }
+    }
+
+    pub fn log(&self) {
+        info!(
+            handshake = ?self.handshake,
+            auth = ?self.auth,
+            wake_compute = ?self.wake_compute,
+            connect_compute = ?self.connect_compute,
+            first_byte = ?self.first_byte,
+            "connection latency breakdown"
+        );
+    }
+}
+
 pub static NUM_CONNECTION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "proxy_connection_failures_total",
@@ -249,6 +419,23 @@ pub static NUM_WAKEUP_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static WAKE_COMPUTE_CIRCUIT_BREAKER_OPEN: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "proxy_wake_compute_circuit_breaker_open",
+        "Number of endpoints with an open wake_compute circuit breaker.",
+    )
+    .unwrap()
+});
+
+pub static WAKE_COMPUTE_CIRCUIT_BREAKER_TRANSITIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_wake_compute_circuit_breaker_transitions_total",
+        "Number of wake_compute circuit breaker state transitions (per new state).",
+        &["state"],
+    )
+    .unwrap()
+});
+
 pub static NUM_BYTES_PROXIED_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "proxy_io_bytes",
@@ -294,3 +481,37 @@ pub static ENDPOINT_ERRORS_BY_KIND: Lazy<HyperLogLogVec<32>> = Lazy::new(|| {
     )
     .unwrap()
 });
+
+pub static AUDIT_LOG_RECORDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_audit_log_records_total",
+        "Number of connection audit records, by whether they were written or dropped by the rate limit",
+        &["outcome"],
+    )
+    .unwrap()
+});
+
+pub static AUDIT_LOG_WRITE_ERRORS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "proxy_audit_log_write_errors_total",
+        "Number of failed writes or flushes of the connection audit log",
+    )
+    .unwrap()
+});
+
+pub static TLS_SESSION_RESUMPTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_tls_session_resumptions_total",
+        "Number of attempts to resume TLS sessions with clients and computes, by whether the session was found",
+        &["side", "outcome"],
+    )
+    .unwrap()
+});
+
+/// Count a lookup of a TLS session to resume.
+pub fn count_tls_resumption(side: &str, found: bool) {
+    let outcome = if found { "hit" } else { "miss" };
+    TLS_SESSION_RESUMPTIONS
+        .with_label_values(&[side, outcome])
+        .inc();
+}
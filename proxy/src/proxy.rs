@@ -15,10 +15,10 @@ use crate::{
     config::{ProxyConfig, TlsConfig},
     context::RequestMonitoring,
     error::ReportableError,
-    metrics::{NUM_CLIENT_CONNECTION_GAUGE, NUM_CONNECTION_REQUESTS_GAUGE},
+    metrics::{LatencyPhase, NUM_CLIENT_CONNECTION_GAUGE, NUM_CONNECTION_REQUESTS_GAUGE},
     protocol2::WithClientIp,
     proxy::handshake::{handshake, HandshakeData},
-    rate_limiter::EndpointRateLimiter,
+    rate_limiter::{EndpointRateLimiter, ENDPOINT_CONNECTION_LIMITER},
     stream::{PqStream, Stream},
     EndpointCacheKey,
 };
@@ -32,6 +32,7 @@ use smol_str::{format_smolstr, SmolStr};
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, info_span, Instrument};
 
@@ -62,6 +63,7 @@ pub async fn task_main(
     listener: tokio::net::TcpListener,
     cancellation_token: CancellationToken,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    cancel_map: Arc<CancelMap>,
 ) -> anyhow::Result<()> {
     scopeguard::defer! {
         info!("proxy has shut down");
@@ -72,7 +74,6 @@ pub async fn task_main(
     socket2::SockRef::from(&listener).set_keepalive(true)?;
 
     let connections = tokio_util::task::task_tracker::TaskTracker::new();
-    let cancel_map = Arc::new(CancelMap::default());
 
     while let Some(accept_result) =
         run_until_cancelled(listener.accept(), &cancellation_token).await
@@ -151,7 +152,7 @@ pub async fn task_main(
     drop(listener);
 
     // Drain connections
-    connections.wait().await;
+    crate::drain_connections(&connections, "tcp").await;
 
     Ok(())
 }
@@ -248,6 +249,7 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
     let tls = config.tls_config.as_ref();
 
     let pause = ctx.latency_timer.pause();
+    let handshake_start = Instant::now();
     let do_handshake = handshake(stream, mode.handshake_tls(tls));
     let (mut stream, params) =
         match tokio::time::timeout(config.handshake_timeout, do_handshake).await?? {
@@ -260,16 +262,24 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
             }
         };
     drop(pause);
+    ctx.record_latency(LatencyPhase::Handshake, handshake_start.elapsed());
 
     let hostname = mode.hostname(stream.get_ref());
 
-    let common_names = tls.map(|tls| &tls.common_names);
+    let common_names = tls.map(|tls| tls.cert_resolver.get_common_names());
 
     // Extract credentials which we're going to use for auth.
     let result = config
         .auth_backend
         .as_ref()
-        .map(|_| auth::ComputeUserInfoMaybeEndpoint::parse(ctx, &params, hostname, common_names))
+        .map(|_| {
+            auth::ComputeUserInfoMaybeEndpoint::parse(
+                ctx,
+                &params,
+                hostname,
+                common_names.as_deref(),
+            )
+        })
         .transpose();
 
     let user_info = match result {
@@ -287,6 +297,7 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
     }
 
     let user = user_info.get_user().to_owned();
+    let auth_start = Instant::now();
     let user_info = match user_info
         .authenticate(
             ctx,
@@ -305,10 +316,27 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
             return stream.throw_error(e).instrument(params_span).await?;
         }
     };
+    ctx.record_latency(LatencyPhase::Auth, auth_start.elapsed());
+
+    // check the connection quota, which comes from the console together with the auth info
+    let connection_limit = match ctx.connection_limit() {
+        Some((ep, limit)) => match ENDPOINT_CONNECTION_LIMITER.acquire(ep, limit) {
+            Some(guard) => Some(guard),
+            None => {
+                return stream
+                    .throw_error(auth::AuthError::connection_limit_exceeded(limit))
+                    .await?
+            }
+        },
+        None => None,
+    };
 
     let mut node = connect_to_compute(
         ctx,
-        &TcpMechanism { params: &params },
+        &TcpMechanism {
+            params: &params,
+            startup_params_allow_list: &config.startup_params_allow_list,
+        },
         &user_info,
         mode.allow_self_signed_compute(config),
     )
@@ -329,8 +357,12 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
         client: stream,
         aux: node.aux.clone(),
         compute: node,
+        protocol: ctx.protocol,
+        latency: ctx.latency_breakdown.clone(),
+        log_latency: config.log_latency_breakdown,
         req: _request_gauge,
         conn: _client_gauge,
+        connection_limit,
     }))
 }
 
@@ -364,6 +396,11 @@ async fn prepare_client_connection(
     Ok(())
 }
 
+/// Option asking for a read-only compute of the endpoint: `neon_read_replica:true`.
+const READ_REPLICA_OPTION: &str = "read_replica";
+/// Type of the compute the control plane should wake up for the session.
+const ENDPOINT_TYPE_OPTION: &str = "endpoint_type";
+
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct NeonOptions(Vec<(SmolStr, SmolStr)>);
 
@@ -379,12 +416,38 @@ impl NeonOptions {
     }
 
     fn parse_from_iter<'a>(options: impl Iterator<Item = &'a str>) -> Self {
+        let mut read_replica = false;
         let mut options = options
             .filter_map(neon_option)
+            .filter(|&(k, v)| {
+                if k == READ_REPLICA_OPTION {
+                    read_replica = v == "true";
+                    return false;
+                }
+                true
+            })
             .map(|(k, v)| (k.into(), v.into()))
             .collect_vec();
         options.sort();
-        Self(options)
+
+        let options = Self(options);
+        if read_replica {
+            options.with_read_replica()
+        } else {
+            options
+        }
+    }
+
+    /// Ask the control plane for a read-only compute of the endpoint,
+    /// which it knows as the `read_only` endpoint type.
+    pub fn with_read_replica(mut self) -> Self {
+        self.0.retain(|(k, _)| k != ENDPOINT_TYPE_OPTION);
+        self.0.push((
+            ENDPOINT_TYPE_OPTION.into(),
+            SmolStr::new_inline("read_only"),
+        ));
+        self.0.sort();
+        self
     }
 
     pub fn get_cache_key(&self, prefix: &str) -> EndpointCacheKey {
@@ -4,7 +4,7 @@ use crate::{
     console::{self, errors::WakeComputeError, CachedNodeInfo, NodeInfo},
     context::RequestMonitoring,
     error::ReportableError,
-    metrics::NUM_CONNECTION_FAILURES,
+    metrics::{LatencyPhase, NUM_CONNECTION_FAILURES},
     proxy::{
         retry::{retry_after, ShouldRetry},
         wake_compute::wake_compute,
@@ -12,7 +12,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use pq_proto::StartupMessageParams;
-use tokio::time;
+use tokio::time::{self, Instant};
 use tracing::{error, info, warn};
 
 const CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(2);
@@ -63,6 +63,8 @@ pub trait ComputeConnectBackend {
 pub struct TcpMechanism<'a> {
     /// KV-dictionary with PostgreSQL connection params.
     pub params: &'a StartupMessageParams,
+    /// Startup parameters which are forwarded to the compute.
+    pub startup_params_allow_list: &'a [String],
 }
 
 #[async_trait]
@@ -82,7 +84,7 @@ impl ConnectMechanism for TcpMechanism<'_> {
     }
 
     fn update_connect_config(&self, config: &mut compute::ConnCfg) {
-        config.set_startup_params(self.params);
+        config.set_startup_params(self.params, self.startup_params_allow_list);
     }
 }
 
@@ -100,7 +102,10 @@ where
     M::Error: From<WakeComputeError>,
 {
     let mut num_retries = 0;
-    let mut node_info = wake_compute(&mut num_retries, ctx, user_info).await?;
+    let wake_start = Instant::now();
+    let res = wake_compute(&mut num_retries, ctx, user_info).await;
+    ctx.record_latency(LatencyPhase::WakeCompute, wake_start.elapsed());
+    let mut node_info = res?;
     if let Some(keys) = user_info.get_keys() {
         node_info.set_keys(keys);
     }
@@ -109,10 +114,12 @@ where
     mechanism.update_connect_config(&mut node_info.config);
 
     // try once
-    let err = match mechanism
+    let connect_start = Instant::now();
+    let res = mechanism
         .connect_once(ctx, &node_info, CONNECT_TIMEOUT)
-        .await
-    {
+        .await;
+    ctx.record_latency(LatencyPhase::ConnectCompute, connect_start.elapsed());
+    let err = match res {
         Ok(res) => {
             ctx.latency_timer.success();
             return Ok(res);
@@ -135,7 +142,10 @@ where
             info!("compute node's state has likely changed; requesting a wake-up");
             ctx.latency_timer.cache_miss();
             let old_node_info = invalidate_cache(node_info);
-            let mut node_info = wake_compute(&mut num_retries, ctx, user_info).await?;
+            let wake_start = Instant::now();
+            let res = wake_compute(&mut num_retries, ctx, user_info).await;
+            ctx.record_latency(LatencyPhase::WakeCompute, wake_start.elapsed());
+            let mut node_info = res?;
             node_info.reuse_settings(old_node_info);
 
             mechanism.update_connect_config(&mut node_info.config);
@@ -148,10 +158,12 @@ where
     info!("wake_compute success. attempting to connect");
     num_retries = 1;
     loop {
-        match mechanism
+        let connect_start = Instant::now();
+        let res = mechanism
             .connect_once(ctx, &node_info, CONNECT_TIMEOUT)
-            .await
-        {
+            .await;
+        ctx.record_latency(LatencyPhase::ConnectCompute, connect_start.elapsed());
+        match res {
             Ok(res) => {
                 ctx.latency_timer.success();
                 return Ok(res);
@@ -21,6 +21,9 @@ pub enum HandshakeError {
     #[error("missing certificate")]
     MissingCertificate,
 
+    #[error("client disconnected after GSSAPI encryption was refused, it might require it (gssencmode=require)")]
+    GssEncRefused,
+
     #[error("{0}")]
     StreamUpgradeError(#[from] StreamUpgradeError),
 
@@ -40,6 +43,7 @@ impl ReportableError for HandshakeError {
             // the client sends no SNI extension.
             // If they provide SNI then we can be sure there is a certificate that matches.
             HandshakeError::MissingCertificate => crate::error::ErrorKind::Service,
+            HandshakeError::GssEncRefused => crate::error::ErrorKind::User,
             HandshakeError::StreamUpgradeError(upgrade) => match upgrade {
                 StreamUpgradeError::AlreadyTls => crate::error::ErrorKind::Service,
                 StreamUpgradeError::Io(_) => crate::error::ErrorKind::ClientDisconnect,
@@ -68,9 +72,19 @@ pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
     let (mut tried_ssl, mut tried_gss) = (false, false);
 
     let mut stream = PqStream::new(Stream::from_raw(stream));
+    // Whether we've just refused GSSAPI encryption
+    let mut gss_refused = false;
     loop {
-        let msg = stream.read_startup_packet().await?;
+        let msg = match stream.read_startup_packet().await {
+            Ok(msg) => msg,
+            // With `gssencmode=require`, the client closes the connection right away
+            Err(e) if gss_refused && e.kind() == std::io::ErrorKind::ConnectionAborted => {
+                return Err(HandshakeError::GssEncRefused)
+            }
+            Err(e) => return Err(e.into()),
+        };
         info!("received {msg:?}");
+        gss_refused = false;
 
         use FeStartupPacket::*;
         match msg {
@@ -95,7 +109,7 @@ pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
                         if !read_buf.is_empty() {
                             return Err(HandshakeError::EarlyData);
                         }
-                        let tls_stream = raw.upgrade(tls.to_server_config()).await?;
+                        let tls_stream = raw.upgrade(tls.to_pg_server_config()).await?;
 
                         let (_, tls_server_end_point) = tls
                             .cert_resolver
@@ -114,8 +128,11 @@ pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
                 Stream::Raw { .. } if !tried_gss => {
                     tried_gss = true;
 
-                    // Currently, we don't support GSSAPI
+                    // Currently, we don't support GSSAPI. Refusing it makes
+                    // the clients with `gssencmode=prefer` fall back to TLS.
+                    info!("refusing GSSAPI encryption");
                     stream.write_message(&Be::EncryptionResponse(false)).await?;
+                    gss_refused = true;
                 }
                 _ => return Err(HandshakeError::ProtocolViolation),
             },
@@ -1,12 +1,16 @@
 use crate::{
     compute::PostgresConnection,
     console::messages::MetricsAuxInfo,
-    metrics::NUM_BYTES_PROXIED_COUNTER,
+    metrics::{LatencyBreakdown, LatencyPhase, NUM_BYTES_PROXIED_COUNTER},
+    rate_limiter::EndpointConnectionGuard,
     stream::Stream,
     usage_metrics::{Ids, USAGE_METRICS},
 };
 use metrics::IntCounterPairGuard;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Instant;
 use tracing::info;
 use utils::measured_stream::MeasuredStream;
 
@@ -21,6 +25,7 @@ pub async fn proxy_pass(
         endpoint_id: aux.endpoint_id.clone(),
         branch_id: aux.branch_id.clone(),
     });
+    let _connection = usage.track_connection();
 
     let m_sent = NUM_BYTES_PROXIED_COUNTER.with_label_values(&["tx"]);
     let mut client = MeasuredStream::new(
@@ -40,6 +45,7 @@ pub async fn proxy_pass(
         |cnt| {
             // Number of bytes the client sent to the compute node (inbound).
             m_recv.inc_by(cnt as u64);
+            usage.record_ingress(cnt as u64);
         },
     );
 
@@ -55,12 +61,132 @@ pub struct ProxyPassthrough<S> {
     pub compute: PostgresConnection,
     pub aux: MetricsAuxInfo,
 
+    pub protocol: &'static str,
+    pub latency: LatencyBreakdown,
+    pub log_latency: bool,
+
     pub req: IntCounterPairGuard,
     pub conn: IntCounterPairGuard,
+    pub connection_limit: Option<EndpointConnectionGuard>,
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin> ProxyPassthrough<S> {
     pub async fn proxy_pass(self) -> anyhow::Result<()> {
-        proxy_pass(self.client, self.compute.stream, self.aux).await
+        let timer = FirstByteTimer::new(self.latency);
+
+        // Writes are reported on flush, so this measures the time between
+        // the first request flushed to the compute and the first response flushed to the client.
+        let client = MeasuredStream::new(
+            self.client,
+            |_| {},
+            |_| timer.response_received(self.protocol, self.log_latency),
+        );
+        let compute = MeasuredStream::new(self.compute.stream, |_| {}, |_| timer.request_sent());
+
+        let res = proxy_pass(client, compute, self.aux).await;
+
+        // The client might disconnect before the compute has responded.
+        if let Some(latency) = timer.into_unrecorded() {
+            if self.log_latency {
+                latency.log();
+            }
+        }
+
+        res
+    }
+}
+
+/// Records [`LatencyPhase::FirstByte`] once. This is called on every flush for the
+/// whole connection, so after the first byte it only checks a flag.
+struct FirstByteTimer {
+    request_sent: OnceLock<Instant>,
+    recorded: AtomicBool,
+    /// Taken once the first byte is recorded.
+    latency: Mutex<Option<LatencyBreakdown>>,
+}
+
+impl FirstByteTimer {
+    fn new(latency: LatencyBreakdown) -> Self {
+        Self {
+            request_sent: OnceLock::new(),
+            recorded: AtomicBool::new(false),
+            latency: Mutex::new(Some(latency)),
+        }
+    }
+
+    fn request_sent(&self) {
+        self.request_sent.get_or_init(Instant::now);
+    }
+
+    fn response_received(&self, protocol: &'static str, log: bool) {
+        if self.recorded.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(request_sent) = self.request_sent.get() else {
+            return;
+        };
+        if self.recorded.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let Some(mut latency) = self.latency.lock().unwrap().take() else {
+            return;
+        };
+        latency.record(protocol, LatencyPhase::FirstByte, request_sent.elapsed());
+        if log {
+            latency.log();
+        }
+    }
+
+    /// The latency breakdown, if the first byte was never recorded.
+    fn into_unrecorded(self) -> Option<LatencyBreakdown> {
+        self.latency.into_inner().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::CONNECTION_PHASE_LATENCY;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn first_byte_recorded_once() {
+        tokio::time::pause();
+        let protocol = "first_byte_test";
+        let samples = || {
+            CONNECTION_PHASE_LATENCY
+                .with_label_values(&[protocol, LatencyPhase::FirstByte.as_str()])
+                .get_sample_count()
+        };
+        let timer = FirstByteTimer::new(LatencyBreakdown::default());
+
+        // Nothing to measure before the first request.
+        timer.response_received(protocol, false);
+        assert_eq!(samples(), 0);
+
+        timer.request_sent();
+        tokio::time::advance(Duration::from_millis(10)).await;
+        timer.request_sent();
+        tokio::time::advance(Duration::from_millis(10)).await;
+        timer.response_received(protocol, false);
+        assert_eq!(samples(), 1);
+        let sum = CONNECTION_PHASE_LATENCY
+            .with_label_values(&[protocol, LatencyPhase::FirstByte.as_str()])
+            .get_sample_sum();
+        assert_eq!(sum, 0.02);
+
+        // The later responses don't count.
+        timer.request_sent();
+        timer.response_received(protocol, false);
+        assert_eq!(samples(), 1);
+        assert!(timer.into_unrecorded().is_none());
+    }
+
+    #[test]
+    fn first_byte_never_received() {
+        let timer = FirstByteTimer::new(LatencyBreakdown::default());
+        timer.request_sent();
+        let latency = timer.into_unrecorded().unwrap();
+        assert_eq!(latency.first_byte, None);
     }
 }
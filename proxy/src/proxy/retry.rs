@@ -5,6 +5,10 @@ use tokio::time;
 /// Number of times we should retry the `/proxy_wake_compute` http request.
 /// Retry duration is BASE_RETRY_WAIT_DURATION * RETRY_WAIT_EXPONENT_BASE ^ n, where n starts at 0
 pub const NUM_RETRIES_CONNECT: u32 = 16;
+/// Number of times we should retry the `/proxy_wake_compute` http request if the control plane
+/// itself failed, rather than the endpoint being in transition. Retrying a struggling control
+/// plane a lot only makes it struggle more.
+pub const NUM_RETRIES_CONTROL_PLANE_FAILURE: u32 = 3;
 const BASE_RETRY_WAIT_DURATION: time::Duration = time::Duration::from_millis(25);
 const RETRY_WAIT_EXPONENT_BASE: f64 = std::f64::consts::SQRT_2;
 
@@ -5,6 +5,7 @@ mod mitm;
 use std::time::Duration;
 
 use super::connect_compute::ConnectMechanism;
+use super::handshake::HandshakeError;
 use super::retry::ShouldRetry;
 use super::*;
 use crate::auth::backend::{
@@ -17,8 +18,10 @@ use crate::console::{self, CachedNodeInfo, NodeInfo};
 use crate::error::ErrorKind;
 use crate::proxy::retry::{retry_after, NUM_RETRIES_CONNECT};
 use crate::{auth, http, sasl, scram};
+use anyhow::ensure;
 use async_trait::async_trait;
 use rstest::rstest;
+use tokio::io::AsyncReadExt;
 use tokio_postgres::config::SslMode;
 use tokio_postgres::tls::{MakeTlsConnect, NoTls};
 use tokio_postgres_rustls::{MakeRustlsConnect, RustlsStream};
@@ -89,11 +92,9 @@ fn generate_tls_config<'a>(
         let mut cert_resolver = CertResolver::new();
         cert_resolver.add_cert(key, vec![cert], true)?;
 
-        let common_names = cert_resolver.get_common_names();
-
         TlsConfig {
             config,
-            common_names,
+            client_cert_config: None,
             cert_resolver: Arc::new(cert_resolver),
         }
     };
@@ -248,6 +249,128 @@ async fn handshake_raw() -> anyhow::Result<()> {
     proxy.await?
 }
 
+/// Ask for GSSAPI encryption the way libpq does with `gssencmode=prefer` or `require`,
+/// and check that proxy refuses it.
+async fn gssenc_is_refused(stream: &mut tokio::io::DuplexStream) -> anyhow::Result<()> {
+    const NEGOTIATE_GSS_CODE: u32 = 80877104;
+    let mut request = Vec::new();
+    request.extend_from_slice(&8u32.to_be_bytes());
+    request.extend_from_slice(&NEGOTIATE_GSS_CODE.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut response = [0; 1];
+    stream.read_exact(&mut response).await?;
+    ensure!(response == *b"N", "GSSAPI encryption should be refused");
+    Ok(())
+}
+
+#[rstest]
+#[case::ssl_disable(SslMode::Disable, false)]
+#[case::ssl_prefer(SslMode::Prefer, false)]
+#[case::ssl_prefer_tls(SslMode::Prefer, true)]
+#[case::ssl_require_tls(SslMode::Require, true)]
+#[tokio::test]
+async fn handshake_gssenc_prefer(
+    #[case] ssl_mode: SslMode,
+    #[case] with_tls: bool,
+) -> anyhow::Result<()> {
+    let (client, mut server) = tokio::io::duplex(1024);
+
+    let (client_config, server_config) =
+        generate_tls_config("generic-project-name.localhost", "localhost")?;
+    let proxy = tokio::spawn(dummy_proxy(
+        client,
+        with_tls.then_some(server_config),
+        NoAuth,
+    ));
+
+    // the client falls back to the usual negotiation on the same connection
+    gssenc_is_refused(&mut server).await?;
+
+    let config = tokio_postgres::Config::new()
+        .user("john_doe")
+        .dbname("earth")
+        .options("project=generic-project-name")
+        .ssl_mode(ssl_mode)
+        .clone();
+    let _conn = if with_tls {
+        config
+            .connect_raw(server, client_config.make_tls_connect()?)
+            .await?
+            .0
+    } else {
+        config.connect_raw(server, NoTls).await?.0
+    };
+
+    proxy.await?
+}
+
+#[tokio::test]
+async fn handshake_gssenc_prefer_tls_is_enforced_by_proxy() -> anyhow::Result<()> {
+    let (client, mut server) = tokio::io::duplex(1024);
+
+    let (_, server_config) = generate_tls_config("generic-project-name.localhost", "localhost")?;
+    let proxy = tokio::spawn(dummy_proxy(client, Some(server_config), NoAuth));
+
+    gssenc_is_refused(&mut server).await?;
+
+    let client_err = tokio_postgres::Config::new()
+        .user("john_doe")
+        .dbname("earth")
+        .ssl_mode(SslMode::Disable)
+        .connect_raw(server, NoTls)
+        .await
+        .err()
+        .context("client shouldn't be able to connect")?;
+    assert!(client_err.to_string().contains(ERR_INSECURE_CONNECTION));
+
+    assert!(proxy.await?.is_err());
+    Ok(())
+}
+
+#[rstest]
+#[case::without_tls(false)]
+#[case::with_tls(true)]
+#[tokio::test]
+async fn handshake_gssenc_require(#[case] with_tls: bool) -> anyhow::Result<()> {
+    let (client, mut server) = tokio::io::duplex(1024);
+
+    let (_, server_config) = generate_tls_config("generic-project-name.localhost", "localhost")?;
+    let proxy = tokio::spawn(async move {
+        let client = WithClientIp::new(client);
+        handshake(client, with_tls.then_some(&server_config))
+            .await
+            .map(|_| ())
+    });
+
+    // the client gives up once GSSAPI encryption is refused
+    gssenc_is_refused(&mut server).await?;
+    drop(server);
+
+    let err = proxy.await?.err().context("handshake should fail")?;
+    assert!(matches!(err, HandshakeError::GssEncRefused));
+    assert_eq!(err.get_error_kind(), ErrorKind::User);
+    Ok(())
+}
+
+#[tokio::test]
+async fn handshake_gssenc_twice() -> anyhow::Result<()> {
+    let (client, mut server) = tokio::io::duplex(1024);
+
+    let proxy = tokio::spawn(async move {
+        let client = WithClientIp::new(client);
+        handshake(client, None).await.map(|_| ())
+    });
+
+    gssenc_is_refused(&mut server).await?;
+    // the client may try upgrading to GSSAPI only once
+    assert!(gssenc_is_refused(&mut server).await.is_err());
+
+    let err = proxy.await?.err().context("handshake should fail")?;
+    assert!(matches!(err, HandshakeError::ProtocolViolation));
+    Ok(())
+}
+
 #[tokio::test]
 async fn keepalive_is_inherited() -> anyhow::Result<()> {
     use tokio::net::{TcpListener, TcpStream};
@@ -671,3 +794,29 @@ async fn wake_non_retry() {
         .unwrap_err();
     mechanism.verify();
 }
+
+/// Control plane failures get a smaller retry budget than endpoint transitions.
+#[test]
+fn wake_retry_budget() {
+    use crate::proxy::retry::NUM_RETRIES_CONTROL_PLANE_FAILURE;
+
+    let unavailable = console::errors::ApiError::Console {
+        status: http::StatusCode::SERVICE_UNAVAILABLE,
+        text: "TEST".into(),
+    };
+    assert!(unavailable.should_retry(NUM_RETRIES_CONTROL_PLANE_FAILURE - 1));
+    assert!(!unavailable.should_retry(NUM_RETRIES_CONTROL_PLANE_FAILURE));
+
+    let internal = console::errors::ApiError::Console {
+        status: http::StatusCode::INTERNAL_SERVER_ERROR,
+        text: "TEST".into(),
+    };
+    assert!(!internal.should_retry(0));
+
+    let transition = console::errors::ApiError::Console {
+        status: http::StatusCode::BAD_REQUEST,
+        text: "TEST".into(),
+    };
+    assert!(transition.should_retry(NUM_RETRIES_CONTROL_PLANE_FAILURE));
+    assert!(!transition.should_retry(NUM_RETRIES_CONNECT));
+}
@@ -9,6 +9,7 @@ use tracing::{error, warn};
 use super::connect_compute::ComputeConnectBackend;
 use super::retry::ShouldRetry;
 
+#[tracing::instrument(skip_all)]
 pub async fn wake_compute<B: ComputeConnectBackend>(
     num_retries: &mut u32,
     ctx: &mut RequestMonitoring,
@@ -84,6 +85,8 @@ fn report_error(e: &WakeComputeError, retry: bool) {
         }
         WakeComputeError::ApiError(ApiError::Console { .. }) => "api_console_other_error",
         WakeComputeError::TimeoutError => "timeout_error",
+        WakeComputeError::TooManyConnections { .. } => "rate_limited",
+        WakeComputeError::CircuitBreakerOpen { .. } => "circuit_breaker_open",
     };
     NUM_WAKEUP_FAILURES.with_label_values(&[retry, kind]).inc();
 }
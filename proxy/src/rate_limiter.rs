@@ -1,7 +1,15 @@
 mod aimd;
+mod circuit_breaker;
+mod connection_limiter;
+mod leaky_bucket;
 mod limit_algorithm;
 mod limiter;
 pub use aimd::Aimd;
+pub use circuit_breaker::{CircuitBreakerConfig, WakeComputeCircuitBreaker};
+pub use connection_limiter::{
+    EndpointConnectionGuard, EndpointConnectionLimiter, ENDPOINT_CONNECTION_LIMITER,
+};
+pub use leaky_bucket::{LeakyBucketConfig, LeakyBucketRateLimiter, WakeComputeRateLimiter};
 pub use limit_algorithm::{AimdConfig, Fixed, RateLimitAlgorithm, RateLimiterConfig};
 pub use limiter::Limiter;
 pub use limiter::{EndpointRateLimiter, RateBucketInfo};
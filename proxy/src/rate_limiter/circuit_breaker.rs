@@ -0,0 +1,236 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use dashmap::DashMap;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::{
+    intern::EndpointIdInt,
+    metrics::{WAKE_COMPUTE_CIRCUIT_BREAKER_OPEN, WAKE_COMPUTE_CIRCUIT_BREAKER_TRANSITIONS},
+    EndpointId,
+};
+
+// Circuit breaker for `wake_compute` requests.
+//
+// After `threshold` consecutive control plane failures for an endpoint, the breaker
+// opens and requests for this endpoint fail fast for `cooldown`. Afterwards a single
+// probe request is let through: if it succeeds the breaker closes, otherwise it opens
+// again. This keeps proxies from piling retries onto a control plane which is already
+// failing for the endpoint.
+pub struct WakeComputeCircuitBreaker {
+    map: DashMap<EndpointIdInt, BreakerState>,
+    config: CircuitBreakerConfig,
+    access_count: AtomicUsize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failures which opens the breaker.
+    pub threshold: u32,
+    /// How long the breaker stays open before letting a probe request through.
+    pub cooldown: Duration,
+}
+
+enum BreakerState {
+    Closed {
+        failures: u32,
+        last_failure: Instant,
+    },
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        probe_started: Instant,
+    },
+}
+
+impl WakeComputeCircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        assert!(config.threshold > 0, "threshold must be positive");
+        Self {
+            map: DashMap::with_shard_amount(64),
+            config,
+            access_count: AtomicUsize::new(1), // start from 1 to avoid GC on the first request
+        }
+    }
+
+    /// Check whether a request for the endpoint may reach the control plane.
+    /// Otherwise return after how long it's worth trying again.
+    pub fn check(&self, endpoint: &EndpointId) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        // drop stale entries every 2k requests.
+        if self.access_count.fetch_add(1, Ordering::AcqRel) % 2048 == 0 {
+            self.do_gc(now);
+        }
+
+        let Some(mut state) = self.map.get_mut(&EndpointIdInt::from(endpoint)) else {
+            return Ok(());
+        };
+
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } if now < until => Err(until - now),
+            BreakerState::HalfOpen { probe_started }
+                if now < probe_started + self.config.cooldown =>
+            {
+                Err(probe_started + self.config.cooldown - now)
+            }
+            // Either the cooldown has passed, or the previous probe never reported back.
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                *state = BreakerState::HalfOpen { probe_started: now };
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record_success(&self, endpoint: &EndpointId) {
+        let Some((_, state)) = self.map.remove(&EndpointIdInt::from(endpoint)) else {
+            return;
+        };
+        if !matches!(state, BreakerState::Closed { .. }) {
+            info!("wake_compute circuit breaker closed");
+            WAKE_COMPUTE_CIRCUIT_BREAKER_OPEN.dec();
+            WAKE_COMPUTE_CIRCUIT_BREAKER_TRANSITIONS
+                .with_label_values(&["closed"])
+                .inc();
+        }
+    }
+
+    pub fn record_failure(&self, endpoint: &EndpointId) {
+        let now = Instant::now();
+        let until = now + self.config.cooldown;
+
+        let mut state =
+            self.map
+                .entry(EndpointIdInt::from(endpoint))
+                .or_insert(BreakerState::Closed {
+                    failures: 0,
+                    last_failure: now,
+                });
+
+        match &mut *state {
+            BreakerState::Closed {
+                failures,
+                last_failure,
+            } => {
+                // failures which are far apart are not consecutive
+                if now.duration_since(*last_failure) > self.config.cooldown {
+                    *failures = 0;
+                }
+                *failures += 1;
+                *last_failure = now;
+
+                if *failures >= self.config.threshold {
+                    warn!(
+                        failures = *failures,
+                        cooldown = ?self.config.cooldown,
+                        "wake_compute circuit breaker opened"
+                    );
+                    *state = BreakerState::Open { until };
+                    WAKE_COMPUTE_CIRCUIT_BREAKER_OPEN.inc();
+                    WAKE_COMPUTE_CIRCUIT_BREAKER_TRANSITIONS
+                        .with_label_values(&["open"])
+                        .inc();
+                }
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                warn!(cooldown = ?self.config.cooldown, "wake_compute circuit breaker reopened");
+                *state = BreakerState::Open { until };
+                WAKE_COMPUTE_CIRCUIT_BREAKER_TRANSITIONS
+                    .with_label_values(&["reopen"])
+                    .inc();
+            }
+        }
+    }
+
+    /// Remove breakers which haven't seen any requests for a while.
+    pub fn do_gc(&self, now: Instant) {
+        info!(
+            "cleaning up wake_compute circuit breakers, current size = {}",
+            self.map.len()
+        );
+        let cooldown = self.config.cooldown;
+        self.map.retain(|_, state| {
+            let (since, open) = match *state {
+                BreakerState::Closed { last_failure, .. } => (last_failure, false),
+                BreakerState::Open { until } => (until, true),
+                BreakerState::HalfOpen { probe_started } => (probe_started + cooldown, true),
+            };
+            let retain = now.duration_since(since) <= cooldown;
+            if !retain && open {
+                WAKE_COMPUTE_CIRCUIT_BREAKER_OPEN.dec();
+            }
+            retain
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Duration;
+
+    use super::{CircuitBreakerConfig, WakeComputeCircuitBreaker};
+    use crate::EndpointId;
+
+    #[tokio::test(start_paused = true)]
+    async fn circuit_breaker_opens_and_closes() {
+        let breaker = WakeComputeCircuitBreaker::new(CircuitBreakerConfig {
+            threshold: 3,
+            cooldown: Duration::from_secs(10),
+        });
+        let endpoint: EndpointId = "ep-foo".into();
+        let other: EndpointId = "ep-bar".into();
+
+        // a success resets the failure count
+        breaker.record_failure(&endpoint);
+        breaker.record_failure(&endpoint);
+        breaker.record_success(&endpoint);
+        breaker.record_failure(&endpoint);
+        breaker.record_failure(&endpoint);
+        breaker.check(&endpoint).unwrap();
+
+        breaker.record_failure(&endpoint);
+        let retry_after = breaker.check(&endpoint).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(10));
+
+        // other endpoints are not affected
+        breaker.check(&other).unwrap();
+
+        // after the cooldown a single probe is let through
+        tokio::time::advance(Duration::from_secs(10)).await;
+        breaker.check(&endpoint).unwrap();
+        breaker.check(&endpoint).unwrap_err();
+
+        // a failed probe opens the breaker again
+        breaker.record_failure(&endpoint);
+        breaker.check(&endpoint).unwrap_err();
+
+        // a successful probe closes it
+        tokio::time::advance(Duration::from_secs(10)).await;
+        breaker.check(&endpoint).unwrap();
+        breaker.record_success(&endpoint);
+        breaker.check(&endpoint).unwrap();
+        breaker.check(&endpoint).unwrap();
+        assert!(breaker.map.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn circuit_breaker_ignores_sparse_failures() {
+        let breaker = WakeComputeCircuitBreaker::new(CircuitBreakerConfig {
+            threshold: 2,
+            cooldown: Duration::from_secs(10),
+        });
+        let endpoint: EndpointId = "ep-foo".into();
+
+        breaker.record_failure(&endpoint);
+        tokio::time::advance(Duration::from_secs(11)).await;
+        breaker.record_failure(&endpoint);
+        breaker.check(&endpoint).unwrap();
+
+        // stale entries are collected
+        tokio::time::advance(Duration::from_secs(11)).await;
+        breaker.do_gc(tokio::time::Instant::now());
+        assert!(breaker.map.is_empty());
+    }
+}
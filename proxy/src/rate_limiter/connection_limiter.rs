@@ -0,0 +1,90 @@
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+
+use crate::{intern::EndpointIdInt, EndpointId};
+
+pub static ENDPOINT_CONNECTION_LIMITER: Lazy<EndpointConnectionLimiter> =
+    Lazy::new(EndpointConnectionLimiter::default);
+
+// Limits the number of simultaneously open client connections per endpoint.
+//
+// The limits come from the console together with the auth info, so they are
+// passed on each acquisition, and only the number of open connections is kept here.
+#[derive(Default)]
+pub struct EndpointConnectionLimiter {
+    map: DashMap<EndpointIdInt, u32>,
+}
+
+/// Open connection to an endpoint, released on drop.
+pub struct EndpointConnectionGuard {
+    limiter: &'static EndpointConnectionLimiter,
+    endpoint: EndpointIdInt,
+}
+
+impl EndpointConnectionLimiter {
+    /// Count a new connection to the endpoint, unless there are `limit` of them already.
+    pub fn acquire(
+        &'static self,
+        endpoint: &EndpointId,
+        limit: u32,
+    ) -> Option<EndpointConnectionGuard> {
+        let endpoint = EndpointIdInt::from(endpoint);
+        let mut open = self.map.entry(endpoint).or_insert(0);
+        if *open >= limit {
+            drop(open);
+            self.map.remove_if(&endpoint, |_, open| *open == 0);
+            return None;
+        }
+        *open += 1;
+
+        Some(EndpointConnectionGuard {
+            limiter: self,
+            endpoint,
+        })
+    }
+
+    fn release(&self, endpoint: EndpointIdInt) {
+        if let Some(mut open) = self.map.get_mut(&endpoint) {
+            *open = open.saturating_sub(1);
+        }
+        // don't keep the endpoints without connections around
+        self.map.remove_if(&endpoint, |_, open| *open == 0);
+    }
+}
+
+impl Drop for EndpointConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.endpoint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EndpointConnectionLimiter;
+    use crate::{intern::EndpointIdInt, EndpointId};
+
+    #[test]
+    fn connection_limits() {
+        let limiter: &'static EndpointConnectionLimiter = Box::leak(Box::default());
+        let endpoint: EndpointId = "ep-foo".into();
+        let other: EndpointId = "ep-bar".into();
+
+        let first = limiter.acquire(&endpoint, 2).unwrap();
+        let _second = limiter.acquire(&endpoint, 2).unwrap();
+        assert!(limiter.acquire(&endpoint, 2).is_none());
+
+        // other endpoints are not affected
+        let other_conn = limiter.acquire(&other, 1).unwrap();
+
+        // the limit might have changed in the meantime
+        assert!(limiter.acquire(&endpoint, 1).is_none());
+        let _third = limiter.acquire(&endpoint, 3).unwrap();
+
+        drop(first);
+        let _fourth = limiter.acquire(&endpoint, 3).unwrap();
+        assert!(limiter.acquire(&endpoint, 3).is_none());
+
+        drop(other_conn);
+        assert!(!limiter.map.contains_key(&EndpointIdInt::from(&other)));
+    }
+}
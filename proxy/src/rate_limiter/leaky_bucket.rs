@@ -0,0 +1,185 @@
+use std::{
+    hash::Hash,
+    net::IpAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use dashmap::DashMap;
+use tokio::time::{Duration, Instant};
+use tracing::info;
+
+use crate::{intern::EndpointIdInt, EndpointId};
+
+// Leaky bucket rate limiter.
+//
+// Every key has a bucket which drains at `rps` requests per second and holds
+// at most `max` requests. A request is allowed if it fits into the bucket,
+// otherwise we report how long the client should wait until it does.
+// Compared to [`super::EndpointRateLimiter`], this doesn't let a client spend
+// the whole budget of a window in one go right when the window starts.
+pub struct LeakyBucketRateLimiter<K> {
+    map: DashMap<K, LeakyBucketState>,
+    config: LeakyBucketConfig,
+    access_count: AtomicUsize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LeakyBucketConfig {
+    pub rps: f64,
+    pub max: f64,
+}
+
+impl LeakyBucketConfig {
+    pub fn new(rps: f64, max: f64) -> Self {
+        assert!(rps > 0.0, "rps must be positive");
+        assert!(max >= 1.0, "max must allow at least one request");
+        Self { rps, max }
+    }
+}
+
+struct LeakyBucketState {
+    filled: f64,
+    time: Instant,
+}
+
+impl LeakyBucketState {
+    fn new(now: Instant) -> Self {
+        Self {
+            filled: 0.0,
+            time: now,
+        }
+    }
+
+    fn update(&mut self, info: &LeakyBucketConfig, now: Instant) {
+        let drain = now.duration_since(self.time).as_secs_f64() * info.rps;
+        self.filled = (self.filled - drain).max(0.0);
+        self.time = now;
+    }
+
+    fn check(&mut self, info: &LeakyBucketConfig, now: Instant, n: f64) -> Result<(), Duration> {
+        self.update(info, now);
+
+        let overflow = self.filled + n - info.max;
+        if overflow > 0.0 {
+            return Err(Duration::from_secs_f64(overflow / info.rps));
+        }
+
+        self.filled += n;
+        Ok(())
+    }
+}
+
+impl<K: Hash + Eq> LeakyBucketRateLimiter<K> {
+    pub fn new(config: LeakyBucketConfig) -> Self {
+        Self {
+            map: DashMap::with_shard_amount(64),
+            config,
+            access_count: AtomicUsize::new(1), // start from 1 to avoid GC on the first request
+        }
+    }
+
+    /// Check that `n` more requests are allowed for the key.
+    /// Otherwise return after how long they would be.
+    pub fn check(&self, key: K, n: u32) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        // drop drained buckets every 2k requests.
+        if self.access_count.fetch_add(1, Ordering::AcqRel) % 2048 == 0 {
+            self.do_gc(now);
+        }
+
+        let mut entry = self
+            .map
+            .entry(key)
+            .or_insert_with(|| LeakyBucketState::new(now));
+
+        entry.check(&self.config, now, n as f64)
+    }
+
+    /// Remove buckets which drained completely: they're the same as new ones.
+    pub fn do_gc(&self, now: Instant) {
+        info!(
+            "cleaning up leaky bucket rate limiter, current size = {}",
+            self.map.len()
+        );
+        self.map.retain(|_, state| {
+            state.update(&self.config, now);
+            state.filled > 0.0
+        });
+    }
+}
+
+/// Limits `wake_compute` requests to the control plane per endpoint,
+/// and optionally per client IP address.
+pub struct WakeComputeRateLimiter {
+    limiter: LeakyBucketRateLimiter<(EndpointIdInt, Option<IpAddr>)>,
+    per_ip: bool,
+}
+
+impl WakeComputeRateLimiter {
+    pub fn new(config: LeakyBucketConfig, per_ip: bool) -> Self {
+        Self {
+            limiter: LeakyBucketRateLimiter::new(config),
+            per_ip,
+        }
+    }
+
+    pub fn check(&self, endpoint: &EndpointId, peer_addr: IpAddr) -> Result<(), Duration> {
+        let ip = self.per_ip.then_some(peer_addr);
+        self.limiter.check((EndpointIdInt::from(endpoint), ip), 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use tokio::time::Duration;
+
+    use super::{LeakyBucketConfig, LeakyBucketRateLimiter, WakeComputeRateLimiter};
+    use crate::EndpointId;
+
+    #[tokio::test(start_paused = true)]
+    async fn leaky_bucket_drains() {
+        let limiter = LeakyBucketRateLimiter::new(LeakyBucketConfig::new(10.0, 20.0));
+
+        // a burst up to the bucket size is allowed
+        for _ in 0..20 {
+            limiter.check("ep", 1).unwrap();
+        }
+        let retry_after = limiter.check("ep", 1).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(100));
+
+        // other keys are not affected
+        limiter.check("other", 1).unwrap();
+
+        // 10 rps drains 5 requests in half a second
+        tokio::time::advance(Duration::from_millis(500)).await;
+        for _ in 0..5 {
+            limiter.check("ep", 1).unwrap();
+        }
+        limiter.check("ep", 1).unwrap_err();
+
+        // fully drained buckets are collected
+        tokio::time::advance(Duration::from_secs(2)).await;
+        limiter.do_gc(tokio::time::Instant::now());
+        assert!(limiter.map.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wake_compute_per_ip() {
+        let config = LeakyBucketConfig::new(1.0, 1.0);
+        let endpoint: EndpointId = "ep-foo".into();
+        let ip1 = IpAddr::from([127, 0, 0, 1]);
+        let ip2 = IpAddr::from([127, 0, 0, 2]);
+
+        let limiter = WakeComputeRateLimiter::new(config, false);
+        limiter.check(&endpoint, ip1).unwrap();
+        limiter.check(&endpoint, ip2).unwrap_err();
+
+        let limiter = WakeComputeRateLimiter::new(config, true);
+        limiter.check(&endpoint, ip1).unwrap();
+        limiter.check(&endpoint, ip2).unwrap();
+        limiter.check(&endpoint, ip1).unwrap_err();
+    }
+}
@@ -33,13 +33,23 @@ use super::{
 // saw SNI, before doing TLS handshake. User-side error messages in that case
 // does not look very nice (`SSL SYSCALL error: Undefined error: 0`), so for now
 // I went with a more expensive way that yields user-friendlier error messages.
+//
+// The limits can be shared between proxy instances (and restarts) via redis,
+// see [`crate::redis::rate_limiter`]. In that case the requests are counted
+// locally and periodically synced with the totals across all instances.
 pub struct EndpointRateLimiter<Rand = StdRng, Hasher = RandomState> {
-    map: DashMap<EndpointId, Vec<RateBucket>, Hasher>,
+    map: DashMap<EndpointId, EndpointBuckets, Hasher>,
     info: &'static [RateBucketInfo],
     access_count: AtomicUsize,
     rand: Mutex<Rand>,
 }
 
+struct EndpointBuckets {
+    buckets: Vec<RateBucket>,
+    /// Requests accepted since the last sync.
+    unsynced: u32,
+}
+
 #[derive(Clone, Copy)]
 struct RateBucket {
     start: Instant,
@@ -157,29 +167,76 @@ impl<R: Rng, S: BuildHasher + Clone> EndpointRateLimiter<R, S> {
         }
 
         let now = Instant::now();
-        let mut entry = self.map.entry(endpoint).or_insert_with(|| {
-            vec![
+        let mut entry = self.map.entry(endpoint).or_insert_with(|| EndpointBuckets {
+            buckets: vec![
                 RateBucket {
                     start: now,
                     count: 0,
                 };
                 self.info.len()
-            ]
+            ],
+            unsynced: 0,
         });
 
         let should_allow_request = entry
+            .buckets
             .iter_mut()
             .zip(self.info)
             .all(|(bucket, info)| bucket.should_allow_request(info, now));
 
         if should_allow_request {
             // only increment the bucket counts if the request will actually be accepted
-            entry.iter_mut().for_each(RateBucket::inc);
+            entry.buckets.iter_mut().for_each(RateBucket::inc);
+            entry.unsynced = entry.unsynced.saturating_add(1);
         }
 
         should_allow_request
     }
 
+    pub fn info(&self) -> &'static [RateBucketInfo] {
+        self.info
+    }
+
+    /// Number of requests accepted for each endpoint since the last call.
+    pub fn take_unsynced(&self) -> Vec<(EndpointId, u32)> {
+        let mut unsynced = Vec::new();
+        for mut entry in self.map.iter_mut() {
+            let count = std::mem::take(&mut entry.unsynced);
+            if count > 0 {
+                unsynced.push((entry.key().clone(), count));
+            }
+        }
+        unsynced
+    }
+
+    /// Give back the counts taken by [`Self::take_unsynced`] which failed to
+    /// sync, to be synced the next time. If the sync failed after the counts
+    /// were added up, they are counted twice, which errs on the side of
+    /// limiting. The counts of endpoints cleaned up meanwhile are dropped.
+    pub fn return_unsynced(&self, unsynced: Vec<(EndpointId, u32)>) {
+        for (endpoint, count) in unsynced {
+            if let Some(mut entry) = self.map.get_mut(&endpoint) {
+                entry.unsynced = entry.unsynced.saturating_add(count);
+            }
+        }
+    }
+
+    /// Replace the local counts with the totals across all proxy instances.
+    /// `totals` has a `(count, elapsed)` pair per bucket: the number of requests
+    /// in the current window of the bucket, which started `elapsed` ago.
+    pub fn set_synced(&self, endpoint: &EndpointId, totals: &[(u32, Duration)]) {
+        let Some(mut entry) = self.map.get_mut(endpoint) else {
+            return;
+        };
+        let now = Instant::now();
+        let EndpointBuckets { buckets, unsynced } = &mut *entry;
+        for (bucket, &(count, elapsed)) in buckets.iter_mut().zip(totals) {
+            bucket.start = now.checked_sub(elapsed).unwrap_or(now);
+            // requests accepted since the totals were taken are not included yet
+            bucket.count = count.saturating_add(*unsynced);
+        }
+    }
+
     /// Clean the map. Simple strategy: remove all entries in a random shard.
     /// At worst, we'll double the effective max_rps during the cleanup.
     /// But that way deletion does not aquire mutex on each entry access.
@@ -694,6 +751,54 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_rate_limits_synced() {
+        let mut rates: Vec<RateBucketInfo> = ["10@1s", "5@10s"]
+            .into_iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        RateBucketInfo::validate(&mut rates).unwrap();
+        let limiter = EndpointRateLimiter::new(Vec::leak(rates));
+
+        let endpoint = EndpointId::from("ep-my-endpoint-1234");
+
+        time::pause();
+
+        for _ in 0..10 {
+            assert!(limiter.check(endpoint.clone()));
+        }
+        assert_eq!(limiter.take_unsynced(), [(endpoint.clone(), 10)]);
+        assert!(limiter.take_unsynced().is_empty());
+
+        // counts which failed to sync are taken again for the next sync
+        limiter.return_unsynced(vec![(endpoint.clone(), 10)]);
+        assert_eq!(limiter.take_unsynced(), [(endpoint.clone(), 10)]);
+
+        // other instances have accepted more requests in the current windows
+        time::advance(Duration::from_secs(1)).await;
+        assert!(limiter.check(endpoint.clone()));
+        limiter.set_synced(
+            &endpoint,
+            &[
+                (10, Duration::from_millis(500)),
+                (40, Duration::from_secs(5)),
+            ],
+        );
+        assert!(!limiter.check(endpoint.clone()));
+
+        // the 1s window is over, but not the 10s one
+        time::advance(Duration::from_millis(500)).await;
+        for _ in 0..9 {
+            assert!(limiter.check(endpoint.clone()));
+        }
+        assert!(!limiter.check(endpoint.clone()));
+
+        // both windows are over
+        time::advance(Duration::from_secs(5)).await;
+        assert!(limiter.check(endpoint.clone()));
+        assert_eq!(limiter.take_unsynced(), [(endpoint.clone(), 11)]);
+    }
+
     #[tokio::test]
     async fn test_rate_limits_gc() {
         // fixed seeded random/hasher to ensure that the test is not flaky
@@ -1 +1,3 @@
+pub mod cancellation;
 pub mod notifications;
+pub mod rate_limiter;
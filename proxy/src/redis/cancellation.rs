@@ -0,0 +1,165 @@
+//! Query cancellation across proxy instances.
+//!
+//! A `CancelRequest` opens a new connection, which a load balancer may route
+//! to any proxy instance, while only the instance serving the session knows
+//! how to reach its compute. When a cancellation key isn't found locally, it's
+//! published to a redis channel shared by all instances of the region, and the
+//! one owning the session cancels the query.
+
+use std::{convert::Infallible, sync::Arc};
+
+use futures::StreamExt;
+use pq_proto::CancelKeyData;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::cancellation::CancelMap;
+
+const CHANNEL_NAME: &str = "neondb-proxy-to-proxy-updates";
+const RECONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+struct CancelSession {
+    region_id: String,
+    backend_pid: i32,
+    cancel_key: i32,
+}
+
+/// Forwards cancellation requests to other proxy instances.
+pub struct CancellationPublisher {
+    client: redis::Client,
+    region_id: String,
+    conn: Mutex<Option<redis::aio::MultiplexedConnection>>,
+}
+
+impl CancellationPublisher {
+    pub fn new(url: &str, region_id: String) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url)?;
+        Ok(Self {
+            client,
+            region_id,
+            conn: Mutex::new(None),
+        })
+    }
+
+    pub async fn publish(&self, key: CancelKeyData) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(&CancelSession {
+            region_id: self.region_id.clone(),
+            backend_pid: key.backend_pid,
+            cancel_key: key.cancel_key,
+        })?;
+
+        let mut conn = {
+            let mut conn = self.conn.lock().await;
+            match &*conn {
+                Some(conn) => conn.clone(),
+                None => {
+                    let new_conn = self.client.get_multiplexed_tokio_connection().await?;
+                    *conn = Some(new_conn.clone());
+                    new_conn
+                }
+            }
+        };
+
+        let res = conn.publish::<_, _, ()>(CHANNEL_NAME, payload).await;
+        if res.is_err() {
+            // reconnect on the next request
+            *self.conn.lock().await = None;
+        }
+        Ok(res?)
+    }
+}
+
+async fn try_connect(client: &redis::Client) -> anyhow::Result<redis::aio::PubSub> {
+    let mut conn = client.get_async_connection().await?.into_pubsub();
+    info!("subscribing to a channel `{CHANNEL_NAME}`");
+    conn.subscribe(CHANNEL_NAME).await?;
+    Ok(conn)
+}
+
+#[tracing::instrument(skip(msg, cancel_map))]
+fn handle_message(
+    msg: redis::Msg,
+    region_id: &str,
+    cancel_map: &Arc<CancelMap>,
+) -> anyhow::Result<()> {
+    let payload: String = msg.get_payload()?;
+    let msg: CancelSession = match serde_json::from_str(&payload) {
+        Ok(msg) => msg,
+        Err(e) => {
+            error!("broken message: {e}");
+            return Ok(());
+        }
+    };
+    if msg.region_id != region_id {
+        return Ok(());
+    }
+
+    let key = CancelKeyData {
+        backend_pid: msg.backend_pid,
+        cancel_key: msg.cancel_key,
+    };
+    // Every instance gets the message, including the one which sent it.
+    // Only try the local sessions, so that it isn't forwarded again.
+    let cancel_map = Arc::clone(cancel_map);
+    tokio::spawn(async move {
+        if let Err(e) = cancel_map.cancel_session_local(key).await {
+            warn!("failed to cancel query of a forwarded request: {e}");
+        }
+    });
+
+    Ok(())
+}
+
+/// Serve cancellation requests forwarded by other proxy instances.
+#[tracing::instrument(name = "cancellation_listener", skip_all)]
+pub async fn task_main(
+    url: String,
+    region_id: String,
+    cancel_map: Arc<CancelMap>,
+) -> anyhow::Result<Infallible> {
+    let client = redis::Client::open(url)?;
+    loop {
+        let conn = match try_connect(&client).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!(
+                    "failed to connect to redis: {e}, will try to reconnect in {RECONNECT_TIMEOUT:#?}"
+                );
+                tokio::time::sleep(RECONNECT_TIMEOUT).await;
+                continue;
+            }
+        };
+        let mut stream = conn.into_on_message();
+        while let Some(msg) = stream.next().await {
+            if let Err(e) = handle_message(msg, &region_id, &cancel_map) {
+                error!("failed to handle message: {e}, will try to reconnect");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_session_roundtrip() -> anyhow::Result<()> {
+        let msg = CancelSession {
+            region_id: "us-east-1".to_owned(),
+            backend_pid: 42,
+            cancel_key: -7,
+        };
+        let text = serde_json::to_string(&msg)?;
+        assert_eq!(
+            text,
+            r#"{"region_id":"us-east-1","backend_pid":42,"cancel_key":-7}"#
+        );
+        assert_eq!(serde_json::from_str::<CancelSession>(&text)?, msg);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Endpoint rate limits shared between proxy instances.
+//!
+//! Each instance counts the accepted requests locally, see [`EndpointRateLimiter`],
+//! and periodically adds them up in redis. Counters are kept per window aligned to
+//! the wall clock, so that all instances, including the ones started after a rollout,
+//! agree on them. The totals are then used by the local limiter.
+
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{error, info};
+
+use crate::{rate_limiter::EndpointRateLimiter, EndpointId};
+
+const KEY_PREFIX: &str = "proxy_endpoint_rate_limit";
+
+/// Sync the endpoint rate limiter with redis every `interval`.
+#[tracing::instrument(name = "rate_limiter_sync", skip_all)]
+pub async fn task_main(
+    url: String,
+    region_id: String,
+    limiter: Arc<EndpointRateLimiter>,
+    interval: Duration,
+) -> anyhow::Result<Infallible> {
+    let client = redis::Client::open(url)?;
+    let mut conn = None;
+
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        ticker.tick().await;
+
+        let unsynced = limiter.take_unsynced();
+        if unsynced.is_empty() {
+            continue;
+        }
+
+        if conn.is_none() {
+            match client.get_multiplexed_tokio_connection().await {
+                Ok(new_conn) => {
+                    info!("connected to redis");
+                    conn = Some(new_conn);
+                }
+                Err(e) => {
+                    error!("failed to connect to redis: {e}");
+                    limiter.return_unsynced(unsynced);
+                    continue;
+                }
+            }
+        }
+        let mut redis_conn = conn.clone().expect("connection should be established");
+
+        if let Err(e) = sync(&mut redis_conn, &region_id, &limiter, &unsynced).await {
+            error!("failed to sync endpoint rate limits: {e}");
+            // The requests which failed to sync are synced on the next tick.
+            limiter.return_unsynced(unsynced);
+            // reconnect on the next tick
+            conn = None;
+        }
+    }
+}
+
+async fn sync(
+    conn: &mut redis::aio::MultiplexedConnection,
+    region_id: &str,
+    limiter: &EndpointRateLimiter,
+    unsynced: &[(EndpointId, u32)],
+) -> anyhow::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+
+    let mut pipe = redis::pipe();
+    for (endpoint, count) in unsynced {
+        for info in limiter.info() {
+            let interval = info.interval.as_millis();
+            let key = format!(
+                "{KEY_PREFIX}:{region_id}:{endpoint}:{interval}:{}",
+                now / interval
+            );
+            // keep the counter for a while longer in case the clocks are skewed
+            pipe.incr(&key, *count)
+                .pexpire(&key, 2 * interval as usize)
+                .ignore();
+        }
+    }
+    let totals: Vec<u32> = pipe.query_async(conn).await?;
+
+    let mut totals = totals.into_iter();
+    for (endpoint, _) in unsynced {
+        let buckets: Vec<_> = limiter
+            .info()
+            .iter()
+            .map(|info| {
+                let interval = info.interval.as_millis();
+                let elapsed = Duration::from_millis((now % interval) as u64);
+                (totals.next().unwrap_or_default(), elapsed)
+            })
+            .collect();
+        limiter.set_synced(endpoint, &buckets);
+    }
+
+    Ok(())
+}
@@ -59,9 +59,12 @@ fn sha256<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> [u8; 32] {
 
 #[cfg(test)]
 mod tests {
-    use crate::sasl::{Mechanism, Step};
+    use crate::config::{TlsChannelBinding, TlsServerEndPoint};
+    use crate::sasl::{self, Mechanism, Step};
 
-    use super::{password::SaltedPassword, Exchange, ServerSecret};
+    use super::{
+        password::SaltedPassword, signature::SignatureBuilder, Exchange, ScramKey, ServerSecret,
+    };
 
     #[test]
     fn happy_path() {
@@ -83,11 +86,7 @@ mod tests {
         const NONCE: [u8; 18] = [
             1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
         ];
-        let mut exchange = Exchange::new(
-            &secret,
-            || NONCE,
-            crate::config::TlsServerEndPoint::Undefined,
-        );
+        let mut exchange = Exchange::new(&secret, || NONCE, TlsChannelBinding::NONE);
 
         let client_first = "n,,n=user,r=rOprNGfwEbeRWgbNEkqO";
         let client_final = "c=biws,r=rOprNGfwEbeRWgbNEkqOAQIDBAUGBwgJCgsMDQ4PEBES,p=rw1r5Kph5ThxmaUBC2GAQ6MfXbPnNkFiTIvdb/Rear0=";
@@ -121,4 +120,108 @@ mod tests {
             ]
         );
     }
+
+    /// Run the exchange as a client with the given GS2 header, e.g.
+    /// `p=tls-exporter,,`, and channel binding data.
+    fn exchange_channel_binding(
+        binding: TlsChannelBinding,
+        gs2_header: &str,
+        client_cbind_data: &[u8],
+    ) -> sasl::Result<Option<ScramKey>> {
+        let iterations = 4096;
+        let salt_base64 = "QSXCR+Q6sek8bf92";
+        let pw = SaltedPassword::new(
+            b"pencil",
+            base64::decode(salt_base64).unwrap().as_slice(),
+            iterations,
+        );
+
+        let secret = ServerSecret {
+            iterations,
+            salt_base64: salt_base64.to_owned(),
+            stored_key: pw.client_key().sha256(),
+            server_key: pw.server_key(),
+            doomed: false,
+        };
+        const NONCE: [u8; 18] = [
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
+        ];
+        let exchange = Exchange::new(&secret, || NONCE, binding);
+
+        let client_first_bare = "n=user,r=rOprNGfwEbeRWgbNEkqO";
+        let client_first = format!("{gs2_header}{client_first_bare}");
+        let (exchange, server_first) = match exchange.exchange(&client_first)? {
+            Step::Continue(exchange, message) => (exchange, message),
+            Step::Success(_, _) => panic!("expected continue, got success"),
+            Step::Failure(f) => panic!("{f}"),
+        };
+
+        let mut cbind_input = gs2_header.as_bytes().to_vec();
+        cbind_input.extend_from_slice(client_cbind_data);
+        let client_final_without_proof = format!(
+            "c={},r=rOprNGfwEbeRWgbNEkqOAQIDBAUGBwgJCgsMDQ4PEBES",
+            base64::encode(cbind_input)
+        );
+        let signature = SignatureBuilder {
+            client_first_message_bare: client_first_bare,
+            server_first_message: &server_first,
+            client_final_message_without_proof: &client_final_without_proof,
+        }
+        .build(&secret.stored_key);
+        let proof: Vec<u8> = signature
+            .as_ref()
+            .iter()
+            .zip(pw.client_key().as_ref())
+            .map(|(x, y)| x ^ y)
+            .collect();
+        let client_final = format!("{client_final_without_proof},p={}", base64::encode(proof));
+
+        match exchange.exchange(&client_final)? {
+            Step::Success(key, _) => Ok(Some(key)),
+            Step::Continue(_, _) => panic!("expected success, got continue"),
+            Step::Failure(_) => Ok(None),
+        }
+    }
+
+    #[test]
+    fn channel_binding_tls_exporter() {
+        let tls_exporter = [42; 32];
+        let binding = TlsChannelBinding {
+            tls_server_end_point: TlsServerEndPoint::Undefined,
+            tls_exporter: Some(tls_exporter),
+        };
+
+        let key = exchange_channel_binding(binding, "p=tls-exporter,,", &tls_exporter).unwrap();
+        assert!(key.is_some());
+
+        // client and server see different TLS connections
+        let err = exchange_channel_binding(binding, "p=tls-exporter,,", &[43; 32]).unwrap_err();
+        assert!(matches!(err, sasl::Error::ChannelBindingFailed(_)));
+
+        // tls-exporter is not available e.g. with TLS 1.2
+        let err =
+            exchange_channel_binding(TlsChannelBinding::NONE, "p=tls-exporter,,", &tls_exporter)
+                .unwrap_err();
+        assert!(matches!(err, sasl::Error::ChannelBindingBadMethod(_)));
+    }
+
+    #[test]
+    fn channel_binding_undefined_end_point() {
+        // e.g. an Ed25519 certificate under TLS 1.3
+        let binding = TlsChannelBinding {
+            tls_server_end_point: TlsServerEndPoint::Undefined,
+            tls_exporter: Some([42; 32]),
+        };
+
+        // PLUS is not advertised, as libpq would pick tls-server-end-point
+        assert!(!binding.supported());
+
+        // so clients which support channel binding fall back to plain SCRAM
+        let key = exchange_channel_binding(binding, "y,,", &[]).unwrap();
+        assert!(key.is_some());
+
+        let err =
+            exchange_channel_binding(binding, "p=tls-server-end-point,,", &[42; 32]).unwrap_err();
+        assert!(matches!(err, sasl::Error::ChannelBindingBadMethod(_)));
+    }
 }
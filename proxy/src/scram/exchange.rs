@@ -12,29 +12,50 @@ use super::signature::SignatureBuilder;
 use crate::config;
 use crate::sasl::{self, ChannelBinding, Error as SaslError};
 
-/// The only channel binding mode we currently support.
+/// Channel binding modes we support.
 #[derive(Debug)]
-struct TlsServerEndPoint;
+enum ChannelBindingMode {
+    TlsServerEndPoint,
+    TlsExporter,
+}
 
-impl std::fmt::Display for TlsServerEndPoint {
+impl std::fmt::Display for ChannelBindingMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "tls-server-end-point")
+        match self {
+            ChannelBindingMode::TlsServerEndPoint => write!(f, "tls-server-end-point"),
+            ChannelBindingMode::TlsExporter => write!(f, "tls-exporter"),
+        }
     }
 }
 
-impl std::str::FromStr for TlsServerEndPoint {
+impl std::str::FromStr for ChannelBindingMode {
     type Err = sasl::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "tls-server-end-point" => Ok(TlsServerEndPoint),
+            "tls-server-end-point" => Ok(ChannelBindingMode::TlsServerEndPoint),
+            "tls-exporter" => Ok(ChannelBindingMode::TlsExporter),
             _ => Err(sasl::Error::ChannelBindingBadMethod(s.into())),
         }
     }
 }
 
+impl ChannelBindingMode {
+    fn data<'a>(&self, tls_channel_binding: &'a config::TlsChannelBinding) -> Option<&'a [u8]> {
+        match self {
+            ChannelBindingMode::TlsServerEndPoint => {
+                tls_channel_binding.tls_server_end_point.as_bytes()
+            }
+            ChannelBindingMode::TlsExporter => tls_channel_binding
+                .tls_exporter
+                .as_ref()
+                .map(|x| x.as_slice()),
+        }
+    }
+}
+
 struct SaslSentInner {
-    cbind_flag: ChannelBinding<TlsServerEndPoint>,
+    cbind_flag: ChannelBinding<ChannelBindingMode>,
     client_first_message_bare: String,
     server_first_message: OwnedServerFirstMessage,
 }
@@ -54,19 +75,19 @@ enum ExchangeState {
 pub struct Exchange<'a> {
     state: ExchangeState,
     secret: &'a ServerSecret,
-    tls_server_end_point: config::TlsServerEndPoint,
+    tls_channel_binding: config::TlsChannelBinding,
 }
 
 impl<'a> Exchange<'a> {
     pub fn new(
         secret: &'a ServerSecret,
         nonce: fn() -> [u8; SCRAM_RAW_NONCE_LEN],
-        tls_server_end_point: config::TlsServerEndPoint,
+        tls_channel_binding: config::TlsChannelBinding,
     ) -> Self {
         Self {
             state: ExchangeState::Initial(SaslInitial { nonce }),
             secret,
-            tls_server_end_point,
+            tls_channel_binding,
         }
     }
 }
@@ -74,7 +95,7 @@ impl<'a> Exchange<'a> {
 pub fn exchange(
     secret: &ServerSecret,
     mut client: ScramSha256,
-    tls_server_end_point: config::TlsServerEndPoint,
+    tls_channel_binding: config::TlsChannelBinding,
 ) -> sasl::Result<sasl::Outcome<super::ScramKey>> {
     use sasl::Step::*;
 
@@ -84,7 +105,7 @@ pub fn exchange(
 
     let client_first = std::str::from_utf8(client.message())
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    let sent = match init.transition(secret, &tls_server_end_point, client_first)? {
+    let sent = match init.transition(secret, &tls_channel_binding, client_first)? {
         Continue(sent, server_first) => {
             client.update(server_first.as_bytes())?;
             sent
@@ -95,7 +116,7 @@ pub fn exchange(
 
     let client_final = std::str::from_utf8(client.message())
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    let keys = match sent.transition(secret, &tls_server_end_point, client_final)? {
+    let keys = match sent.transition(secret, &tls_channel_binding, client_final)? {
         Success(keys, server_final) => {
             client.finish(server_final.as_bytes())?;
             keys
@@ -111,7 +132,7 @@ impl SaslInitial {
     fn transition(
         &self,
         secret: &ServerSecret,
-        tls_server_end_point: &config::TlsServerEndPoint,
+        tls_channel_binding: &config::TlsChannelBinding,
         input: &str,
     ) -> sasl::Result<sasl::Step<SaslSentInner, Infallible>> {
         let client_first_message = ClientFirstMessage::parse(input)
@@ -120,7 +141,7 @@ impl SaslInitial {
         // If the flag is set to "y" and the server supports channel
         // binding, the server MUST fail authentication
         if client_first_message.cbind_flag == ChannelBinding::NotSupportedServer
-            && tls_server_end_point.supported()
+            && tls_channel_binding.supported()
         {
             return Err(SaslError::ChannelBindingFailed("SCRAM-PLUS not used"));
         }
@@ -132,8 +153,16 @@ impl SaslInitial {
         );
         let msg = server_first_message.as_str().to_owned();
 
+        let cbind_flag = client_first_message.cbind_flag.and_then(str::parse)?;
+        // e.g. tls-exporter requested over TLS 1.2
+        if let ChannelBinding::Required(mode) = &cbind_flag {
+            if mode.data(tls_channel_binding).is_none() {
+                return Err(SaslError::ChannelBindingBadMethod(mode.to_string().into()));
+            }
+        }
+
         let next = SaslSentInner {
-            cbind_flag: client_first_message.cbind_flag.and_then(str::parse)?,
+            cbind_flag,
             client_first_message_bare: client_first_message.bare.to_owned(),
             server_first_message,
         };
@@ -146,7 +175,7 @@ impl SaslSentInner {
     fn transition(
         &self,
         secret: &ServerSecret,
-        tls_server_end_point: &config::TlsServerEndPoint,
+        tls_channel_binding: &config::TlsChannelBinding,
         input: &str,
     ) -> sasl::Result<sasl::Step<Infallible, super::ScramKey>> {
         let Self {
@@ -158,9 +187,9 @@ impl SaslSentInner {
         let client_final_message = ClientFinalMessage::parse(input)
             .ok_or(SaslError::BadClientMessage("invalid client-final-message"))?;
 
-        let channel_binding = cbind_flag.encode(|_| match tls_server_end_point {
-            config::TlsServerEndPoint::Sha256(x) => Ok(x),
-            config::TlsServerEndPoint::Undefined => Err(SaslError::MissingBinding),
+        let channel_binding = cbind_flag.encode(|mode| {
+            mode.data(tls_channel_binding)
+                .ok_or(SaslError::MissingBinding)
         })?;
 
         // This might've been caused by a MITM attack
@@ -203,7 +232,7 @@ impl sasl::Mechanism for Exchange<'_> {
         use {sasl::Step::*, ExchangeState::*};
         match &self.state {
             Initial(init) => {
-                match init.transition(self.secret, &self.tls_server_end_point, input)? {
+                match init.transition(self.secret, &self.tls_channel_binding, input)? {
                     Continue(sent, msg) => {
                         self.state = SaltSent(sent);
                         Ok(Continue(self, msg))
@@ -213,7 +242,7 @@ impl sasl::Mechanism for Exchange<'_> {
                 }
             }
             SaltSent(sent) => {
-                match sent.transition(self.secret, &self.tls_server_end_point, input)? {
+                match sent.transition(self.secret, &self.tls_channel_binding, input)? {
                     Success(keys, msg) => Ok(Success(keys, msg)),
                     Continue(x, _) => match x {},
                     Failure(msg) => Ok(Failure(msg)),
@@ -6,9 +6,10 @@ mod backend;
 mod conn_pool;
 mod json;
 mod sql_over_http;
+mod txn_pool;
 mod websocket;
 
-pub use conn_pool::GlobalConnPoolOptions;
+pub use conn_pool::{EndpointPoolLimits, GlobalConnPool, GlobalConnPoolOptions, PoolStatus};
 
 use anyhow::bail;
 use hyper::StatusCode;
@@ -19,7 +20,9 @@ pub use reqwest_middleware::{ClientWithMiddleware, Error};
 pub use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use tokio_util::task::TaskTracker;
 
+use crate::auth::backend::JwkCache;
 use crate::context::RequestMonitoring;
+use crate::http;
 use crate::metrics::NUM_CLIENT_CONNECTION_GAUGE;
 use crate::protocol2::{ProxyProtocolAccept, WithClientIp};
 use crate::rate_limiter::EndpointRateLimiter;
@@ -45,17 +48,21 @@ use utils::http::{error::ApiError, json::json_response};
 
 pub const SERVERLESS_DRIVER_SNI: &str = "api";
 
+/// Timeout for fetching the JWKS of identity providers.
+const JWKS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 pub async fn task_main(
     config: &'static ProxyConfig,
     ws_listener: TcpListener,
     cancellation_token: CancellationToken,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    conn_pool: Arc<GlobalConnPool<tokio_postgres::Client>>,
+    cancel_map: Arc<CancelMap>,
 ) -> anyhow::Result<()> {
     scopeguard::defer! {
         info!("websocket server has shut down");
     }
 
-    let conn_pool = conn_pool::GlobalConnPool::new(&config.http_config);
     {
         let conn_pool = Arc::clone(&conn_pool);
         tokio::spawn(async move {
@@ -63,21 +70,35 @@ pub async fn task_main(
         });
     }
 
+    let txn_pool = txn_pool::TxnPool::new(&config.http_config);
+    {
+        let txn_pool = Arc::clone(&txn_pool);
+        tokio::spawn(async move {
+            txn_pool.gc_worker().await;
+        });
+    }
+
     // shutdown the connection pool
     tokio::spawn({
         let cancellation_token = cancellation_token.clone();
         let conn_pool = conn_pool.clone();
+        let txn_pool = txn_pool.clone();
         async move {
             cancellation_token.cancelled().await;
-            tokio::task::spawn_blocking(move || conn_pool.shutdown())
-                .await
-                .unwrap();
+            tokio::task::spawn_blocking(move || {
+                txn_pool.shutdown();
+                conn_pool.shutdown();
+            })
+            .await
+            .unwrap();
         }
     });
 
     let backend = Arc::new(PoolingBackend {
         pool: Arc::clone(&conn_pool),
+        txn_pool,
         config,
+        jwks: Arc::new(JwkCache::new(http::new_client_with_timeout(JWKS_TIMEOUT))),
     });
 
     let tls_config = match config.tls_config.as_ref() {
@@ -115,6 +136,7 @@ pub async fn task_main(
             let backend = backend.clone();
             let ws_connections = ws_connections.clone();
             let endpoint_rate_limiter = endpoint_rate_limiter.clone();
+            let cancel_map = cancel_map.clone();
 
             async move {
                 let peer_addr = match client_addr {
@@ -127,9 +149,9 @@ pub async fn task_main(
                         let backend = backend.clone();
                         let ws_connections = ws_connections.clone();
                         let endpoint_rate_limiter = endpoint_rate_limiter.clone();
+                        let cancel_map = cancel_map.clone();
 
                         async move {
-                            let cancel_map = Arc::new(CancelMap::default());
                             let session_id = uuid::Uuid::new_v4();
 
                             request_handler(
@@ -161,7 +183,7 @@ pub async fn task_main(
         .await?;
 
     // await websocket connections
-    ws_connections.wait().await;
+    crate::drain_connections(&ws_connections, "ws").await;
 
     Ok(())
 }
@@ -221,6 +243,9 @@ async fn request_handler(
     if hyper_tungstenite::is_upgrade_request(&request) {
         info!(session_id = ?session_id, "performing websocket upgrade");
 
+        // No extension the client offers is accepted, permessage-deflate
+        // included: tungstenite doesn't implement it, and rejects compressed
+        // frames, so clients must keep sending them uncompressed.
         let (response, websocket) = hyper_tungstenite::upgrade(&mut request, None)
             .map_err(|e| ApiError::BadRequest(e.into()))?;
 
@@ -246,17 +271,21 @@ async fn request_handler(
 
         // Return the response so the spawned future can continue.
         Ok(response)
-    } else if request.uri().path() == "/sql" && request.method() == Method::POST {
+    } else if matches!(request.uri().path(), "/sql" | "/sql/batch")
+        && request.method() == Method::POST
+    {
         let ctx = RequestMonitoring::new(session_id, peer_addr, "http", &config.region);
 
         sql_over_http::handle(config, ctx, request, backend).await
-    } else if request.uri().path() == "/sql" && request.method() == Method::OPTIONS {
+    } else if matches!(request.uri().path(), "/sql" | "/sql/batch")
+        && request.method() == Method::OPTIONS
+    {
         Response::builder()
             .header("Allow", "OPTIONS, POST")
             .header("Access-Control-Allow-Origin", "*")
             .header(
                 "Access-Control-Allow-Headers",
-                "Neon-Connection-String, Neon-Raw-Text-Output, Neon-Array-Mode, Neon-Pool-Opt-In, Neon-Batch-Read-Only, Neon-Batch-Isolation-Level",
+                "Authorization, Neon-Connection-String, Neon-Raw-Text-Output, Neon-Array-Mode, Neon-Pool-Opt-In, Neon-Batch-Read-Only, Neon-Batch-Isolation-Level",
             )
             .header("Access-Control-Max-Age", "86400" /* 24 hours */)
             .status(StatusCode::OK) // 204 is also valid, but see: https://developer.mozilla.org/en-US/docs/Web/HTTP/Methods/OPTIONS#status_code
@@ -4,7 +4,10 @@ use async_trait::async_trait;
 use tracing::{field::display, info};
 
 use crate::{
-    auth::{backend::ComputeCredentials, check_peer_addr_is_in_list, AuthError},
+    auth::{
+        backend::{ComputeCredentialKeys, ComputeCredentials, JwkCache},
+        check_peer_addr_is_in_list, AuthError, BackendType,
+    },
     compute,
     config::ProxyConfig,
     console::{
@@ -12,17 +15,22 @@ use crate::{
         CachedNodeInfo,
     },
     context::RequestMonitoring,
+    metrics::count_ip_not_allowed,
     proxy::connect_compute::ConnectMechanism,
 };
 
 use super::conn_pool::{poll_client, Client, ConnInfo, GlobalConnPool};
+use super::txn_pool::TxnPool;
 
 pub struct PoolingBackend {
     pub pool: Arc<GlobalConnPool<tokio_postgres::Client>>,
+    pub txn_pool: Arc<TxnPool>,
     pub config: &'static ProxyConfig,
+    pub jwks: Arc<JwkCache>,
 }
 
 impl PoolingBackend {
+    #[tracing::instrument(skip_all)]
     pub async fn authenticate(
         &self,
         ctx: &mut RequestMonitoring,
@@ -32,6 +40,8 @@ impl PoolingBackend {
         let backend = self.config.auth_backend.as_ref().map(|_| user_info.clone());
         let (allowed_ips, maybe_secret) = backend.get_allowed_ips_and_secret(ctx).await?;
         if !check_peer_addr_is_in_list(&ctx.peer_addr, &allowed_ips) {
+            info!(peer_addr = %ctx.peer_addr, "peer address is not in the allowed list");
+            count_ip_not_allowed(user_info.endpoint.as_str(), ctx.protocol);
             return Err(AuthError::ip_address_not_allowed());
         }
         let cached_secret = match maybe_secret {
@@ -62,6 +72,48 @@ impl PoolingBackend {
         })
     }
 
+    /// Authenticate with a JWT signed by one of the identity providers configured for the endpoint.
+    /// The compute trusts the proxy, so no credentials are passed on to it.
+    #[tracing::instrument(skip_all)]
+    pub async fn authenticate_with_jwt(
+        &self,
+        ctx: &mut RequestMonitoring,
+        conn_info: &ConnInfo,
+        jwt: &str,
+    ) -> Result<ComputeCredentials, AuthError> {
+        let user_info = conn_info.user_info.clone();
+        let backend = self.config.auth_backend.as_ref().map(|_| user_info.clone());
+        let (allowed_ips, _) = backend.get_allowed_ips_and_secret(ctx).await?;
+        if !check_peer_addr_is_in_list(&ctx.peer_addr, &allowed_ips) {
+            info!(peer_addr = %ctx.peer_addr, "peer address is not in the allowed list");
+            count_ip_not_allowed(user_info.endpoint.as_str(), ctx.protocol);
+            return Err(AuthError::ip_address_not_allowed());
+        }
+
+        let BackendType::Console(api, _) = &backend else {
+            return Err(AuthError::bad_auth_method("jwt"));
+        };
+        if let Err(e) = self
+            .jwks
+            .check_jwt(ctx, api, &user_info.endpoint, &user_info.user, jwt)
+            .await
+        {
+            info!("JWT authentication failed: {e:#}");
+            return Err(AuthError::jwt_auth_failed(&*user_info.user));
+        }
+
+        // The client has no password to log into the compute with, so the console provides it.
+        let Some(password) = api.get_role_password(ctx, &user_info).await? else {
+            info!("role has no password to log into the compute with");
+            return Err(AuthError::jwt_auth_failed(&*user_info.user));
+        };
+
+        Ok(ComputeCredentials {
+            info: user_info,
+            keys: ComputeCredentialKeys::Password(password),
+        })
+    }
+
     // Wake up the destination if needed. Code here is a bit involved because
     // we reuse the code from the usual proxy and we need to prepare few structures
     // that this code expects.
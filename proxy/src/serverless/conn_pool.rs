@@ -3,6 +3,7 @@ use futures::{future::poll_fn, Future};
 use metrics::IntCounterPairGuard;
 use parking_lot::RwLock;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use std::{collections::HashMap, pin::pin, sync::Arc, sync::Weak, time::Duration};
 use std::{
@@ -15,14 +16,17 @@ use std::{
 };
 use tokio::time::Instant;
 use tokio_postgres::tls::NoTlsStream;
-use tokio_postgres::{AsyncMessage, ReadyForQueryStatus, Socket};
+use tokio_postgres::{AsyncMessage, GenericClient, ReadyForQueryStatus, Socket, Statement};
 
 use crate::console::messages::MetricsAuxInfo;
-use crate::metrics::{ENDPOINT_POOLS, GC_LATENCY, NUM_OPEN_CLIENTS_IN_HTTP_POOL};
+use crate::metrics::{
+    ENDPOINT_POOLS, GC_LATENCY, HTTP_PREPARED_STATEMENTS, NUM_OPEN_CLIENTS_IN_HTTP_POOL,
+    POOL_DISCARDED_CONNECTIONS, POOL_ENDPOINT_LIMIT_OVERRIDES, POOL_MAX_TOTAL_CONNS,
+};
 use crate::usage_metrics::{Ids, MetricCounter, USAGE_METRICS};
 use crate::{
     auth::backend::ComputeUserInfo, context::RequestMonitoring, metrics::NUM_DB_CONNECTIONS_GAUGE,
-    DbName, EndpointCacheKey, RoleName,
+    DbName, EndpointCacheKey, EndpointId, RoleName,
 };
 
 use tracing::{debug, error, warn, Span};
@@ -67,15 +71,37 @@ struct ConnPoolEntry<C: ClientInnerExt> {
     _last_access: std::time::Instant,
 }
 
+/// Limits of the connection pool of a single endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointPoolLimits {
+    /// Max number of idle connections kept in the pool.
+    pub max_conns: usize,
+    /// How long pooled connections remain idle before closing. Changes apply
+    /// to connections opened afterwards.
+    #[serde(with = "humantime_serde")]
+    pub idle_timeout: Duration,
+}
+
+/// Current limits and size of the pool, reported by the http admin API.
+#[derive(Debug, Serialize)]
+pub struct PoolStatus {
+    pub max_total_conns: usize,
+    pub default_limits: EndpointPoolLimits,
+    pub endpoint_limits: HashMap<EndpointId, EndpointPoolLimits>,
+    pub endpoint_pools: usize,
+    pub total_conns: usize,
+}
+
 // Per-endpoint connection pool, (dbname, username) -> DbUserConnPool
-// Number of open connections is limited by the `max_conns_per_endpoint`.
+// Number of open connections is limited by the `max_conns` of the endpoint limits.
 pub struct EndpointConnPool<C: ClientInnerExt> {
     pools: HashMap<(DbName, RoleName), DbUserConnPool<C>>,
     total_conns: usize,
-    max_conns: usize,
+    endpoint: EndpointId,
+    limits: EndpointPoolLimits,
     _guard: IntCounterPairGuard,
     global_connections_count: Arc<AtomicUsize>,
-    global_pool_size_max_conns: usize,
+    global_pool_size_max_conns: Arc<AtomicUsize>,
 }
 
 impl<C: ClientInnerExt> EndpointConnPool<C> {
@@ -125,7 +151,10 @@ impl<C: ClientInnerExt> EndpointConnPool<C> {
             info!(%conn_id, "pool: throwing away connection '{conn_info}' because connection is closed");
             return Ok(());
         }
-        let global_max_conn = pool.read().global_pool_size_max_conns;
+        let global_max_conn = pool
+            .read()
+            .global_pool_size_max_conns
+            .load(atomic::Ordering::Relaxed);
         if pool
             .read()
             .global_connections_count
@@ -133,6 +162,9 @@ impl<C: ClientInnerExt> EndpointConnPool<C> {
             >= global_max_conn
         {
             info!(%conn_id, "pool: throwing away connection '{conn_info}' because pool is full");
+            POOL_DISCARDED_CONNECTIONS
+                .with_label_values(&["global"])
+                .inc();
             return Ok(());
         }
 
@@ -142,7 +174,7 @@ impl<C: ClientInnerExt> EndpointConnPool<C> {
         let total_conns = {
             let mut pool = pool.write();
 
-            if pool.total_conns < pool.max_conns {
+            if pool.total_conns < pool.limits.max_conns {
                 let pool_entries = pool.pools.entry(conn_info.db_and_user()).or_default();
                 pool_entries.conns.push(ConnPoolEntry {
                     conn: client,
@@ -166,6 +198,9 @@ impl<C: ClientInnerExt> EndpointConnPool<C> {
             info!(%conn_id, "pool: returning connection '{conn_info}' back to the pool, total_conns={total_conns}, for this (db, user)={per_db_size}");
         } else {
             info!(%conn_id, "pool: throwing away connection '{conn_info}' because pool is full, total_conns={total_conns}");
+            POOL_DISCARDED_CONNECTIONS
+                .with_label_values(&["endpoint"])
+                .inc();
         }
 
         Ok(())
@@ -238,6 +273,14 @@ pub struct GlobalConnPool<C: ClientInnerExt> {
     /// Total number of connections in the pool
     global_connections_count: Arc<AtomicUsize>,
 
+    /// Max total number of connections in the pool, adjustable at runtime
+    max_total_conns: Arc<AtomicUsize>,
+
+    /// Limits of endpoints without an override, and the overrides; initialized
+    /// from the config and adjustable at runtime.
+    default_limits: RwLock<EndpointPoolLimits>,
+    endpoint_limits: RwLock<HashMap<EndpointId, EndpointPoolLimits>>,
+
     config: &'static crate::config::HttpConfig,
 }
 
@@ -259,16 +302,28 @@ pub struct GlobalConnPoolOptions {
 
     // Total number of connections in the pool.
     pub max_total_conns: usize,
+
+    // Upper bound of `max_conns` of the endpoint limits set at runtime, so that
+    // a single endpoint can't take over the pool.
+    pub max_conns_per_endpoint_cap: usize,
 }
 
 impl<C: ClientInnerExt> GlobalConnPool<C> {
     pub fn new(config: &'static crate::config::HttpConfig) -> Arc<Self> {
         let shards = config.pool_options.pool_shards;
+        let options = &config.pool_options;
+        POOL_MAX_TOTAL_CONNS.set(options.max_total_conns as i64);
         Arc::new(Self {
             global_pool: DashMap::with_shard_amount(shards),
             global_pool_size: AtomicUsize::new(0),
             config,
             global_connections_count: Arc::new(AtomicUsize::new(0)),
+            max_total_conns: Arc::new(AtomicUsize::new(options.max_total_conns)),
+            default_limits: RwLock::new(EndpointPoolLimits {
+                max_conns: options.max_conns_per_endpoint,
+                idle_timeout: options.idle_timeout,
+            }),
+            endpoint_limits: RwLock::new(HashMap::new()),
         })
     }
 
@@ -278,8 +333,79 @@ impl<C: ClientInnerExt> GlobalConnPool<C> {
             .load(atomic::Ordering::Relaxed)
     }
 
-    pub fn get_idle_timeout(&self) -> Duration {
-        self.config.pool_options.idle_timeout
+    pub fn endpoint_limits(&self, endpoint: &EndpointId) -> EndpointPoolLimits {
+        match self.endpoint_limits.read().get(endpoint) {
+            Some(limits) => *limits,
+            None => *self.default_limits.read(),
+        }
+    }
+
+    pub fn set_max_total_conns(&self, max_total_conns: usize) {
+        info!("pool: setting max total connections to {max_total_conns}");
+        self.max_total_conns
+            .store(max_total_conns, atomic::Ordering::Relaxed);
+        POOL_MAX_TOTAL_CONNS.set(max_total_conns as i64);
+    }
+
+    fn check_limits(&self, limits: &EndpointPoolLimits) -> anyhow::Result<()> {
+        let cap = self.config.pool_options.max_conns_per_endpoint_cap;
+        if limits.max_conns > cap {
+            anyhow::bail!(
+                "max_conns {} exceeds the per endpoint cap of {cap}",
+                limits.max_conns
+            );
+        }
+        Ok(())
+    }
+
+    pub fn set_default_limits(&self, limits: EndpointPoolLimits) -> anyhow::Result<()> {
+        self.check_limits(&limits)?;
+        info!("pool: setting default endpoint limits to {limits:?}");
+        *self.default_limits.write() = limits;
+        self.apply_limits();
+        Ok(())
+    }
+
+    /// Override limits of the endpoint, or reset them to default with None.
+    pub fn set_endpoint_limits(
+        &self,
+        endpoint: EndpointId,
+        limits: Option<EndpointPoolLimits>,
+    ) -> anyhow::Result<()> {
+        if let Some(limits) = &limits {
+            self.check_limits(limits)?;
+        }
+        info!("pool: setting limits of endpoint {endpoint} to {limits:?}");
+        {
+            let mut endpoint_limits = self.endpoint_limits.write();
+            match limits {
+                Some(limits) => endpoint_limits.insert(endpoint, limits),
+                None => endpoint_limits.remove(&endpoint),
+            };
+            POOL_ENDPOINT_LIMIT_OVERRIDES.set(endpoint_limits.len() as i64);
+        }
+        self.apply_limits();
+        Ok(())
+    }
+
+    /// Update limits of existing endpoint pools.
+    fn apply_limits(&self) {
+        for pool in self.global_pool.iter() {
+            let mut pool = pool.write();
+            pool.limits = self.endpoint_limits(&pool.endpoint);
+        }
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        PoolStatus {
+            max_total_conns: self.max_total_conns.load(atomic::Ordering::Relaxed),
+            default_limits: *self.default_limits.read(),
+            endpoint_limits: self.endpoint_limits.read().clone(),
+            endpoint_pools: self.global_pool_size.load(atomic::Ordering::Relaxed),
+            total_conns: self
+                .global_connections_count
+                .load(atomic::Ordering::Relaxed),
+        }
     }
 
     pub fn shutdown(&self) {
@@ -361,7 +487,7 @@ impl<C: ClientInnerExt> GlobalConnPool<C> {
     ) -> Result<Option<Client<C>>, HttpConnError> {
         let mut client: Option<ClientInner<C>> = None;
 
-        let endpoint_pool = self.get_or_create_endpoint_pool(&conn_info.endpoint_cache_key());
+        let endpoint_pool = self.get_or_create_endpoint_pool(conn_info);
         if let Some(entry) = endpoint_pool
             .write()
             .get_conn_entry(conn_info.db_and_user())
@@ -393,10 +519,11 @@ impl<C: ClientInnerExt> GlobalConnPool<C> {
 
     fn get_or_create_endpoint_pool(
         self: &Arc<Self>,
-        endpoint: &EndpointCacheKey,
+        conn_info: &ConnInfo,
     ) -> Arc<RwLock<EndpointConnPool<C>>> {
+        let endpoint = conn_info.endpoint_cache_key();
         // fast path
-        if let Some(pool) = self.global_pool.get(endpoint) {
+        if let Some(pool) = self.global_pool.get(&endpoint) {
             return pool.clone();
         }
 
@@ -404,10 +531,11 @@ impl<C: ClientInnerExt> GlobalConnPool<C> {
         let new_pool = Arc::new(RwLock::new(EndpointConnPool {
             pools: HashMap::new(),
             total_conns: 0,
-            max_conns: self.config.pool_options.max_conns_per_endpoint,
+            endpoint: conn_info.user_info.endpoint.clone(),
+            limits: self.endpoint_limits(&conn_info.user_info.endpoint),
             _guard: ENDPOINT_POOLS.guard(),
             global_connections_count: self.global_connections_count.clone(),
-            global_pool_size_max_conns: self.config.pool_options.max_total_conns,
+            global_pool_size_max_conns: self.max_total_conns.clone(),
         }));
 
         // find or create a pool for this endpoint
@@ -455,12 +583,13 @@ pub fn poll_client<C: ClientInnerExt>(
     span.in_scope(|| {
         info!(%conn_info, %session_id, "new connection");
     });
-    let pool =
-        Arc::downgrade(&global_pool.get_or_create_endpoint_pool(&conn_info.endpoint_cache_key()));
+    let endpoint_pool = global_pool.get_or_create_endpoint_pool(&conn_info);
+    let idle = endpoint_pool.read().limits.idle_timeout;
+    let pool = Arc::downgrade(&endpoint_pool);
+    drop(endpoint_pool);
     let pool_clone = pool.clone();
 
     let db_user = conn_info.db_and_user();
-    let idle = global_pool.get_idle_timeout();
     tokio::spawn(
     async move {
         let _conn_gauge = conn_gauge;
@@ -526,6 +655,8 @@ pub fn poll_client<C: ClientInnerExt>(
         session: tx,
         aux,
         conn_id,
+        statements: Arc::default(),
+        traceparent: None,
     };
     Client::new(inner, conn_info, pool_clone)
 }
@@ -535,6 +666,57 @@ struct ClientInner<C: ClientInnerExt> {
     session: tokio::sync::watch::Sender<uuid::Uuid>,
     aux: MetricsAuxInfo,
     conn_id: uuid::Uuid,
+    statements: Arc<PreparedStatements>,
+    /// Trace context the session is currently tagged with, see [`Client::set_traceparent`].
+    traceparent: Option<String>,
+}
+
+/// Max number of named prepared statements kept per connection.
+const MAX_PREPARED_STATEMENTS: usize = 128;
+
+/// Named prepared statements of a pooled connection.
+///
+/// The statements live as long as the connection does: once it's closed or thrown
+/// away by the pool, dropping them closes them on the compute side as well.
+#[derive(Default)]
+pub struct PreparedStatements {
+    /// statement name -> (query, statement)
+    statements: parking_lot::Mutex<HashMap<Box<str>, (Box<str>, Statement)>>,
+}
+
+impl PreparedStatements {
+    /// Get the statement prepared under `name`, or prepare it if there is none yet.
+    /// If the statement was prepared for a different query, it's replaced.
+    pub async fn get_or_prepare<T: GenericClient>(
+        &self,
+        client: &T,
+        name: &str,
+        query: &str,
+    ) -> Result<Statement, tokio_postgres::Error> {
+        if let Some((prepared_query, statement)) = self.statements.lock().get(name) {
+            if **prepared_query == *query {
+                HTTP_PREPARED_STATEMENTS.with_label_values(&["hit"]).inc();
+                return Ok(statement.clone());
+            }
+        }
+        HTTP_PREPARED_STATEMENTS.with_label_values(&["miss"]).inc();
+
+        let statement = client.prepare(query).await?;
+
+        let mut statements = self.statements.lock();
+        if statements.len() < MAX_PREPARED_STATEMENTS || statements.contains_key(name) {
+            statements.insert(name.into(), (query.into(), statement.clone()));
+        } else {
+            // The statement is closed once the query is done with it.
+            debug!("too many prepared statements, not caching `{name}`");
+        }
+        Ok(statement)
+    }
+
+    /// Forget the statement, e.g. if it's no longer valid because the schema changed.
+    pub fn remove(&self, name: &str) {
+        self.statements.lock().remove(name);
+    }
 }
 
 pub trait ClientInnerExt: Sync + Send + 'static {
@@ -558,6 +740,10 @@ impl<C: ClientInnerExt> ClientInner<C> {
 }
 
 impl<C: ClientInnerExt> Client<C> {
+    pub fn prepared_statements(&self) -> Arc<PreparedStatements> {
+        self.inner.as_ref().unwrap().statements.clone()
+    }
+
     pub fn metrics(&self) -> Arc<MetricCounter> {
         let aux = &self.inner.as_ref().unwrap().aux;
         USAGE_METRICS.register(Ids {
@@ -626,6 +812,33 @@ impl<C: ClientInnerExt> Discard<'_, C> {
     }
 }
 
+impl Client<tokio_postgres::Client> {
+    /// Tag the compute session with the W3C trace context of the current request
+    /// (`neon.traceparent`), so that it can be correlated with the proxy side of the trace.
+    ///
+    /// Pooled connections remember what they were tagged with, so the setting is only
+    /// sent (or reset) when it changes.
+    pub async fn set_traceparent(
+        &mut self,
+        traceparent: Option<String>,
+    ) -> Result<(), tokio_postgres::Error> {
+        let inner = self
+            .inner
+            .as_mut()
+            .expect("client inner should not be removed");
+        if inner.traceparent == traceparent {
+            return Ok(());
+        }
+        let query = match &traceparent {
+            Some(traceparent) => format!("SET neon.traceparent = '{traceparent}'"),
+            None => "RESET neon.traceparent".to_owned(),
+        };
+        inner.inner.batch_execute(&query).await?;
+        inner.traceparent = traceparent;
+        Ok(())
+    }
+}
+
 impl<C: ClientInnerExt> Deref for Client<C> {
     type Target = C;
 
@@ -697,6 +910,8 @@ mod tests {
             session: tokio::sync::watch::Sender::new(uuid::Uuid::new_v4()),
             aux: Default::default(),
             conn_id: uuid::Uuid::new_v4(),
+            statements: Arc::default(),
+            traceparent: None,
         }
     }
 
@@ -711,8 +926,12 @@ mod tests {
                 idle_timeout: Duration::from_secs(1),
                 opt_in: false,
                 max_total_conns: 3,
+                max_conns_per_endpoint_cap: 10,
             },
             request_timeout: Duration::from_secs(1),
+            txn_timeout: Duration::from_secs(1),
+            websocket_ping_interval: None,
+            websocket_ping_timeout: Duration::ZERO,
         }));
         let pool = GlobalConnPool::new(config);
         let conn_info = ConnInfo {
@@ -724,8 +943,7 @@ mod tests {
             dbname: "dbname".into(),
             password: "password".as_bytes().into(),
         };
-        let ep_pool =
-            Arc::downgrade(&pool.get_or_create_endpoint_pool(&conn_info.endpoint_cache_key()));
+        let ep_pool = Arc::downgrade(&pool.get_or_create_endpoint_pool(&conn_info));
         {
             let mut client = Client::new(create_inner(), conn_info.clone(), ep_pool.clone());
             assert_eq!(0, pool.get_global_connections_count());
@@ -781,8 +999,7 @@ mod tests {
             dbname: "dbname".into(),
             password: "password".as_bytes().into(),
         };
-        let ep_pool =
-            Arc::downgrade(&pool.get_or_create_endpoint_pool(&conn_info.endpoint_cache_key()));
+        let ep_pool = Arc::downgrade(&pool.get_or_create_endpoint_pool(&conn_info));
         {
             let mut client = Client::new(create_inner(), conn_info.clone(), ep_pool.clone());
             client.do_drop().unwrap()();
@@ -805,4 +1022,79 @@ mod tests {
         // Closed client should be removed from the pool.
         assert_eq!(2, pool.get_global_connections_count());
     }
+
+    #[tokio::test]
+    async fn test_pool_limits() {
+        let _ = env_logger::try_init();
+        let config = Box::leak(Box::new(crate::config::HttpConfig {
+            pool_options: GlobalConnPoolOptions {
+                max_conns_per_endpoint: 1,
+                gc_epoch: Duration::from_secs(1),
+                pool_shards: 2,
+                idle_timeout: Duration::from_secs(1),
+                opt_in: false,
+                max_total_conns: 2,
+                max_conns_per_endpoint_cap: 10,
+            },
+            request_timeout: Duration::from_secs(1),
+            txn_timeout: Duration::from_secs(1),
+            websocket_ping_interval: None,
+            websocket_ping_timeout: Duration::ZERO,
+        }));
+        let pool = GlobalConnPool::new(config);
+        let conn_info = ConnInfo {
+            user_info: ComputeUserInfo {
+                user: "user".into(),
+                endpoint: "endpoint".into(),
+                options: Default::default(),
+            },
+            dbname: "dbname".into(),
+            password: "password".as_bytes().into(),
+        };
+        let ep_pool = Arc::downgrade(&pool.get_or_create_endpoint_pool(&conn_info));
+        let put = || {
+            let mut client = Client::new(create_inner(), conn_info.clone(), ep_pool.clone());
+            client.do_drop().unwrap()();
+            mem::forget(client); // drop the client
+        };
+
+        put();
+        put();
+        // The second client shouldn't be added to the pool. Because the ep-pool is full.
+        assert_eq!(1, pool.get_global_connections_count());
+
+        // Override applies to the existing pool.
+        let limits = EndpointPoolLimits {
+            max_conns: 3,
+            idle_timeout: Duration::from_secs(1),
+        };
+        pool.set_endpoint_limits("endpoint".into(), Some(limits))
+            .unwrap();
+        assert_eq!(limits, pool.endpoint_limits(&"endpoint".into()));
+        put();
+        put();
+        // The global pool is full now.
+        assert_eq!(2, pool.get_global_connections_count());
+
+        pool.set_max_total_conns(3);
+        put();
+        assert_eq!(3, pool.get_global_connections_count());
+
+        // Reset to the default.
+        pool.set_endpoint_limits("endpoint".into(), None).unwrap();
+        assert_eq!(1, pool.endpoint_limits(&"endpoint".into()).max_conns);
+
+        // Limits above the cap are rejected.
+        let limits = EndpointPoolLimits {
+            max_conns: 11,
+            idle_timeout: Duration::from_secs(1),
+        };
+        pool.set_endpoint_limits("endpoint".into(), Some(limits))
+            .unwrap_err();
+        pool.set_default_limits(limits).unwrap_err();
+        let status = pool.status();
+        assert_eq!(3, status.max_total_conns);
+        assert_eq!(3, status.total_conns);
+        assert!(status.endpoint_limits.is_empty());
+    }
 }
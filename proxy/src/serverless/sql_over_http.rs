@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use anyhow::bail;
+use anyhow::Context;
 use futures::pin_mut;
 use futures::StreamExt;
 use hyper::body::HttpBody;
@@ -10,6 +11,7 @@ use hyper::http::HeaderValue;
 use hyper::Response;
 use hyper::StatusCode;
 use hyper::{Body, HeaderMap, Request};
+use opentelemetry::trace::TraceContextExt;
 use serde_json::json;
 use serde_json::Value;
 use tokio::join;
@@ -18,30 +20,35 @@ use tokio_postgres::error::ErrorPosition;
 use tokio_postgres::GenericClient;
 use tokio_postgres::IsolationLevel;
 use tokio_postgres::ReadyForQueryStatus;
-use tokio_postgres::Transaction;
 use tracing::error;
 use tracing::info;
 use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use url::Url;
 use utils::http::error::ApiError;
 use utils::http::json::json_response;
 
 use crate::auth::backend::ComputeUserInfo;
 use crate::auth::endpoint_sni;
+use crate::auth::AuthError;
 use crate::auth::ComputeUserInfoParseError;
 use crate::config::ProxyConfig;
 use crate::config::TlsConfig;
+use crate::console::errors::WakeComputeError;
 use crate::context::RequestMonitoring;
 use crate::error::ReportableError;
+use crate::error::UserFacingError;
 use crate::metrics::HTTP_CONTENT_LENGTH;
 use crate::metrics::NUM_CONNECTION_REQUESTS_GAUGE;
 use crate::proxy::NeonOptions;
-use crate::serverless::backend::HttpConnError;
 use crate::DbName;
 use crate::RoleName;
 
+use super::backend::HttpConnError;
 use super::backend::PoolingBackend;
 use super::conn_pool::ConnInfo;
+use super::conn_pool::PreparedStatements;
 use super::json::json_to_pg_text;
 use super::json::pg_text_row_to_json;
 
@@ -53,6 +60,10 @@ struct QueryData {
     params: Vec<Option<String>>,
     #[serde(default)]
     array_mode: Option<bool>,
+    /// Name to prepare the query under. The statement is kept with the pooled connection
+    /// and reused by later queries with the same name, skipping the parsing and planning.
+    #[serde(default)]
+    name: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -67,6 +78,33 @@ enum Payload {
     Batch(BatchQueryData),
 }
 
+/// Body of a `/sql/batch` request: the same as for `/sql`, or just the list of queries.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum BatchPayload {
+    Batch(BatchQueryData),
+    Queries(Vec<QueryData>),
+}
+
+impl From<BatchPayload> for BatchQueryData {
+    fn from(value: BatchPayload) -> Self {
+        match value {
+            BatchPayload::Batch(batch) => batch,
+            BatchPayload::Queries(queries) => BatchQueryData { queries },
+        }
+    }
+}
+
+/// Context of the error of a query in a batch.
+#[derive(Debug)]
+struct FailedStatement(usize);
+
+impl std::fmt::Display for FailedStatement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "query {} of the batch failed", self.0)
+    }
+}
+
 const MAX_RESPONSE_SIZE: usize = 10 * 1024 * 1024; // 10 MiB
 const MAX_REQUEST_SIZE: u64 = 10 * 1024 * 1024; // 10 MiB
 
@@ -76,6 +114,11 @@ static ALLOW_POOL: HeaderName = HeaderName::from_static("neon-pool-opt-in");
 static TXN_ISOLATION_LEVEL: HeaderName = HeaderName::from_static("neon-batch-isolation-level");
 static TXN_READ_ONLY: HeaderName = HeaderName::from_static("neon-batch-read-only");
 static TXN_DEFERRABLE: HeaderName = HeaderName::from_static("neon-batch-deferrable");
+static TXN_BEGIN: HeaderName = HeaderName::from_static("neon-transaction-begin");
+static TXN_TOKEN: HeaderName = HeaderName::from_static("neon-transaction-token");
+static TXN_END: HeaderName = HeaderName::from_static("neon-transaction-end");
+static TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+static READ_REPLICA: HeaderName = HeaderName::from_static("neon-read-replica");
 
 static HEADER_VALUE_TRUE: HeaderValue = HeaderValue::from_static("true");
 
@@ -114,13 +157,31 @@ pub enum ConnInfoError {
     MalformedEndpoint,
 }
 
+/// Bearer token from the `Authorization` header, if any.
+fn get_jwt(headers: &HeaderMap) -> Result<Option<&str>, ConnInfoError> {
+    let Some(value) = headers.get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| Some(token.trim()))
+        .ok_or(ConnInfoError::InvalidHeader("Authorization"))
+}
+
 fn get_conn_info(
     ctx: &mut RequestMonitoring,
     headers: &HeaderMap,
     tls: &TlsConfig,
+    jwt_auth: bool,
 ) -> Result<ConnInfo, ConnInfoError> {
-    // HTTP only uses cleartext (for now and likely always)
-    ctx.set_auth_method(crate::context::AuthMethod::Cleartext);
+    // HTTP uses either cleartext passwords or JWTs
+    ctx.set_auth_method(if jwt_auth {
+        crate::context::AuthMethod::Jwt
+    } else {
+        crate::context::AuthMethod::Cleartext
+    });
 
     let connection_string = headers
         .get("Neon-Connection-String")
@@ -148,17 +209,20 @@ fn get_conn_info(
     }
     ctx.set_user(username.clone());
 
-    let password = connection_url
-        .password()
-        .ok_or(ConnInfoError::MissingPassword)?;
+    // the token replaces the password
+    let password = match connection_url.password() {
+        Some(password) => password,
+        None if jwt_auth => "",
+        None => return Err(ConnInfoError::MissingPassword),
+    };
     let password = urlencoding::decode_binary(password.as_bytes());
 
     let hostname = connection_url
         .host_str()
         .ok_or(ConnInfoError::MissingHostname)?;
 
-    let endpoint =
-        endpoint_sni(hostname, &tls.common_names)?.ok_or(ConnInfoError::MalformedEndpoint)?;
+    let endpoint = endpoint_sni(hostname, &tls.cert_resolver.get_common_names())?
+        .ok_or(ConnInfoError::MalformedEndpoint)?;
     ctx.set_endpoint_id(endpoint.clone());
 
     let pairs = connection_url.query_pairs();
@@ -172,10 +236,15 @@ fn get_conn_info(
         }
     }
 
+    let mut options = options.unwrap_or_default();
+    if headers.get(&READ_REPLICA) == Some(&HEADER_VALUE_TRUE) {
+        options = options.with_read_replica();
+    }
+
     let user_info = ComputeUserInfo {
         endpoint,
         user: username,
-        options: options.unwrap_or_default(),
+        options,
     };
 
     Ok(ConnInfo {
@@ -210,6 +279,15 @@ pub async fn handle(
                 // TODO: ctx.set_error_kind(e.get_error_type());
 
                 let mut message = format!("{:?}", e);
+                let retry_after = match e.downcast_ref::<HttpConnError>() {
+                    Some(HttpConnError::WakeCompute(WakeComputeError::TooManyConnections {
+                        retry_after,
+                    })) => Some((StatusCode::TOO_MANY_REQUESTS, *retry_after)),
+                    Some(HttpConnError::WakeCompute(WakeComputeError::CircuitBreakerOpen {
+                        retry_after,
+                    })) => Some((StatusCode::SERVICE_UNAVAILABLE, *retry_after)),
+                    _ => None,
+                };
                 let db_error = e
                     .downcast_ref::<tokio_postgres::Error>()
                     .and_then(|e| e.as_db_error());
@@ -241,7 +319,15 @@ pub async fn handle(
                     None => (Value::Null, Value::Null, Value::Null),
                 };
 
-                let code = get(db_error, |db| db.code().code());
+                let code = match db_error {
+                    Some(db) => get(Some(db), |db| db.code().code()),
+                    // e.g. 28000 if the client IP is not allowed
+                    None => e
+                        .downcast_ref::<AuthError>()
+                        .and_then(|e| e.sqlstate())
+                        .and_then(|code| std::str::from_utf8(code).ok())
+                        .map_or(Value::Null, |code| Value::String(code.to_owned())),
+                };
                 let severity = get(db_error, |db| db.severity());
                 let detail = get(db_error, |db| db.detail());
                 let hint = get(db_error, |db| db.hint());
@@ -260,9 +346,11 @@ pub async fn handle(
                     "sql-over-http per-client task finished with an error: {e:#}"
                 );
                 // TODO: this shouldn't always be bad request.
-                json_response(
-                    StatusCode::BAD_REQUEST,
-                    json!({
+                let status = match retry_after {
+                    Some((status, _)) => status,
+                    None => StatusCode::BAD_REQUEST,
+                };
+                let mut body = json!({
                         "message": message,
                         "code": code,
                         "detail": detail,
@@ -280,8 +368,18 @@ pub async fn handle(
                         "file": file,
                         "line": line,
                         "routine": routine,
-                    }),
-                )?
+                });
+                if let Some(FailedStatement(index)) = e.downcast_ref::<FailedStatement>() {
+                    body["statementIndex"] = json!(index);
+                }
+                let mut response = json_response(status, body)?;
+                if let Some((_, retry_after)) = retry_after {
+                    response.headers_mut().insert(
+                        header::RETRY_AFTER,
+                        HeaderValue::from(retry_after.as_secs_f64().ceil() as u64),
+                    );
+                }
+                response
             }
         },
         Err(e) => {
@@ -332,8 +430,24 @@ async fn handle_inner(
     // Determine the destination and connection params
     //
     let headers = request.headers();
+    // `/sql/batch` takes only batches
+    let batch_route = request.uri().path() == "/sql/batch";
+
+    // continue the client's trace, if it sent one
+    let traced = headers.contains_key(&TRACEPARENT);
+    if traced {
+        Span::current().set_parent(tracing_utils::http::extract_remote_context(headers));
+    }
+
+    let jwt = get_jwt(headers)?.map(String::from);
+
     // TLS config should be there.
-    let conn_info = get_conn_info(ctx, headers, config.tls_config.as_ref().unwrap())?;
+    let conn_info = get_conn_info(
+        ctx,
+        headers,
+        config.tls_config.as_ref().unwrap(),
+        jwt.is_some(),
+    )?;
     info!(
         user = conn_info.user_info.user.as_str(),
         project = conn_info.user_info.endpoint.as_str(),
@@ -367,6 +481,33 @@ async fn handle_inner(
     let txn_read_only = headers.get(&TXN_READ_ONLY) == Some(&HEADER_VALUE_TRUE);
     let txn_deferrable = headers.get(&TXN_DEFERRABLE) == Some(&HEADER_VALUE_TRUE);
 
+    // transaction spanning several requests, see txn_pool
+
+    let txn_begin = headers.get(&TXN_BEGIN) == Some(&HEADER_VALUE_TRUE);
+    let txn_token = match headers.get(&TXN_TOKEN) {
+        Some(x) => Some(
+            x.to_str()
+                .ok()
+                .and_then(|x| x.parse::<uuid::Uuid>().ok())
+                .ok_or_else(|| anyhow::anyhow!("invalid transaction token"))?,
+        ),
+        None => None,
+    };
+    let txn_end = match headers.get(&TXN_END) {
+        Some(x) => Some(match x.as_bytes() {
+            b"commit" => "COMMIT",
+            b"rollback" => "ROLLBACK",
+            _ => bail!("invalid transaction end"),
+        }),
+        None => None,
+    };
+    if txn_begin && txn_token.is_some() {
+        bail!("transaction is already open");
+    }
+    if txn_end.is_some() && txn_token.is_none() {
+        bail!("no open transaction to end");
+    }
+
     let request_content_length = match request.body().size_hint().upper() {
         Some(v) => v,
         None => MAX_REQUEST_SIZE + 1,
@@ -387,19 +528,31 @@ async fn handle_inner(
             .await
             .map_err(anyhow::Error::from)?;
         info!(length = body.len(), "request payload read");
-        let payload: Payload = serde_json::from_slice(&body)?;
-        Ok::<Payload, anyhow::Error>(payload) // Adjust error type accordingly
+        let payload = if batch_route {
+            Payload::Batch(serde_json::from_slice::<BatchPayload>(&body)?.into())
+        } else {
+            serde_json::from_slice(&body)?
+        };
+        Ok::<_, anyhow::Error>((payload, body.len())) // Adjust error type accordingly
     };
 
     let authenticate_and_connect = async {
-        let keys = backend.authenticate(ctx, &conn_info).await?;
-        let client = backend
-            .connect_to_compute(ctx, conn_info, keys, !allow_pool)
-            .await?;
+        let keys = match jwt.as_deref() {
+            Some(jwt) => backend.authenticate_with_jwt(ctx, &conn_info, jwt).await?,
+            None => backend.authenticate(ctx, &conn_info).await?,
+        };
+        let client = match txn_token {
+            Some(token) => backend.txn_pool.take(token, &conn_info)?,
+            None => {
+                backend
+                    .connect_to_compute(ctx, conn_info.clone(), keys, !allow_pool)
+                    .await?
+            }
+        };
         // not strictly necessary to mark success here,
         // but it's just insurance for if we forget it somewhere else
         ctx.latency_timer.success();
-        Ok::<_, HttpConnError>(client)
+        Ok::<_, anyhow::Error>(client)
     };
 
     // Run both operations in parallel
@@ -407,9 +560,23 @@ async fn handle_inner(
         join!(fetch_and_process_request, authenticate_and_connect,);
 
     // Handle the results
-    let payload = payload_result?; // Handle errors appropriately
+    let (payload, payload_len) = payload_result?; // Handle errors appropriately
     let mut client = auth_and_connect_result?; // Handle errors appropriately
 
+    // tag the compute session with the trace, or clear the tag of a previous request
+    let traceparent = if traced { current_traceparent() } else { None };
+    if let Some(traceparent) = &traceparent {
+        info!(traceparent, "propagating trace context to compute");
+    }
+    client.set_traceparent(traceparent).await.map_err(|e| {
+        client.discard();
+        e
+    })?;
+    let metrics = client.metrics();
+    let statements = client.prepared_statements();
+    metrics.record_ingress(payload_len as u64);
+    let _connection = metrics.track_connection();
+
     let mut response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json");
@@ -418,93 +585,150 @@ async fn handle_inner(
     // Now execute the query and return the result
     //
     let mut size = 0;
-    let result = match payload {
-        Payload::Single(stmt) => {
-            let (status, results) =
-                query_to_json(&*client, stmt, &mut 0, raw_output, default_array_mode)
-                    .await
-                    .map_err(|e| {
-                        client.discard();
-                        e
-                    })?;
-            client.check_idle(status);
-            results
-        }
-        Payload::Batch(statements) => {
+    let result = if txn_begin || txn_token.is_some() {
+        // Connection with an open transaction must never get back to the
+        // pool, even if the request fails or times out midway. On error the
+        // transaction can't continue anyway; dropping the client closes the
+        // connection, rolling it back.
+        client.discard();
+        if txn_begin {
             info!("starting transaction");
-            let (inner, mut discard) = client.inner();
-            let mut builder = inner.build_transaction();
-            if let Some(isolation_level) = txn_isolation_level {
-                builder = builder.isolation_level(isolation_level);
+            let begin = begin_query(txn_isolation_level, txn_read_only, txn_deferrable);
+            client.batch_execute(&begin).await?;
+        }
+        let result = match payload {
+            Payload::Single(stmt) => {
+                let (_, results) = query_to_json(
+                    &*client,
+                    &statements,
+                    stmt,
+                    &mut size,
+                    raw_output,
+                    default_array_mode,
+                )
+                .await?;
+                results
             }
-            if txn_read_only {
-                builder = builder.read_only(true);
+            Payload::Batch(batch) => {
+                let results = query_batch(
+                    &*client,
+                    &statements,
+                    batch,
+                    &mut size,
+                    raw_output,
+                    default_array_mode,
+                )
+                .await?;
+                json!({ "results": results })
             }
-            if txn_deferrable {
-                builder = builder.deferrable(true);
+        };
+        match txn_end {
+            Some(end) => {
+                info!(end, "ending transaction");
+                client.batch_execute(end).await?;
             }
-
-            let transaction = builder.start().await.map_err(|e| {
-                // if we cannot start a transaction, we should return immediately
-                // and not return to the pool. connection is clearly broken
-                discard.discard();
-                e
-            })?;
-
-            let results = match query_batch(
-                &transaction,
-                statements,
-                &mut size,
-                raw_output,
-                default_array_mode,
-            )
-            .await
-            {
-                Ok(results) => {
-                    info!("commit");
-                    let status = transaction.commit().await.map_err(|e| {
-                        // if we cannot commit - for now don't return connection to pool
-                        // TODO: get a query status from the error
-                        discard.discard();
-                        e
-                    })?;
-                    discard.check_idle(status);
-                    results
+            None => {
+                let token = txn_token.unwrap_or_else(uuid::Uuid::new_v4);
+                backend.txn_pool.pin(token, &conn_info, client);
+                response =
+                    response.header(TXN_TOKEN.clone(), HeaderValue::try_from(token.to_string())?);
+            }
+        }
+        result
+    } else {
+        match payload {
+            Payload::Single(stmt) => {
+                let (status, results) = query_to_json(
+                    &*client,
+                    &statements,
+                    stmt,
+                    &mut 0,
+                    raw_output,
+                    default_array_mode,
+                )
+                .await
+                .map_err(|e| {
+                    client.discard();
+                    e
+                })?;
+                client.check_idle(status);
+                results
+            }
+            Payload::Batch(batch) => {
+                info!("starting transaction");
+                let (inner, mut discard) = client.inner();
+                let mut builder = inner.build_transaction();
+                if let Some(isolation_level) = txn_isolation_level {
+                    builder = builder.isolation_level(isolation_level);
                 }
-                Err(err) => {
-                    info!("rollback");
-                    let status = transaction.rollback().await.map_err(|e| {
-                        // if we cannot rollback - for now don't return connection to pool
-                        // TODO: get a query status from the error
-                        discard.discard();
-                        e
-                    })?;
-                    discard.check_idle(status);
-                    return Err(err);
+                if txn_read_only {
+                    builder = builder.read_only(true);
+                }
+                if txn_deferrable {
+                    builder = builder.deferrable(true);
                 }
-            };
 
-            if txn_read_only {
-                response = response.header(
-                    TXN_READ_ONLY.clone(),
-                    HeaderValue::try_from(txn_read_only.to_string())?,
-                );
-            }
-            if txn_deferrable {
-                response = response.header(
-                    TXN_DEFERRABLE.clone(),
-                    HeaderValue::try_from(txn_deferrable.to_string())?,
-                );
-            }
-            if let Some(txn_isolation_level) = txn_isolation_level_raw {
-                response = response.header(TXN_ISOLATION_LEVEL.clone(), txn_isolation_level);
+                let transaction = builder.start().await.map_err(|e| {
+                    // if we cannot start a transaction, we should return immediately
+                    // and not return to the pool. connection is clearly broken
+                    discard.discard();
+                    e
+                })?;
+
+                let results = match query_batch(
+                    &transaction,
+                    &statements,
+                    batch,
+                    &mut size,
+                    raw_output,
+                    default_array_mode,
+                )
+                .await
+                {
+                    Ok(results) => {
+                        info!("commit");
+                        let status = transaction.commit().await.map_err(|e| {
+                            // if we cannot commit - for now don't return connection to pool
+                            // TODO: get a query status from the error
+                            discard.discard();
+                            e
+                        })?;
+                        discard.check_idle(status);
+                        results
+                    }
+                    Err(err) => {
+                        info!("rollback");
+                        let status = transaction.rollback().await.map_err(|e| {
+                            // if we cannot rollback - for now don't return connection to pool
+                            // TODO: get a query status from the error
+                            discard.discard();
+                            e
+                        })?;
+                        discard.check_idle(status);
+                        return Err(err);
+                    }
+                };
+
+                if txn_read_only {
+                    response = response.header(
+                        TXN_READ_ONLY.clone(),
+                        HeaderValue::try_from(txn_read_only.to_string())?,
+                    );
+                }
+                if txn_deferrable {
+                    response = response.header(
+                        TXN_DEFERRABLE.clone(),
+                        HeaderValue::try_from(txn_deferrable.to_string())?,
+                    );
+                }
+                if let Some(txn_isolation_level) = txn_isolation_level_raw {
+                    response = response.header(TXN_ISOLATION_LEVEL.clone(), txn_isolation_level);
+                }
+                json!({ "results": results })
             }
-            json!({ "results": results })
         }
     };
 
-    let metrics = client.metrics();
-
     // how could this possibly fail
     let body = serde_json::to_string(&result).expect("json serialization should not fail");
     let len = body.len();
@@ -521,8 +745,54 @@ async fn handle_inner(
     Ok(response)
 }
 
-async fn query_batch(
-    transaction: &Transaction<'_>,
+/// W3C `traceparent` of the current span, if the trace is sampled.
+fn current_traceparent() -> Option<String> {
+    let context = Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() || !span_context.is_sampled() {
+        return None;
+    }
+    Some(format!(
+        "00-{:032x}-{:016x}-{:02x}",
+        span_context.trace_id(),
+        span_context.span_id(),
+        span_context.trace_flags().to_u8()
+    ))
+}
+
+/// Statement opening a transaction with the given characteristics.
+fn begin_query(
+    isolation_level: Option<IsolationLevel>,
+    read_only: bool,
+    deferrable: bool,
+) -> String {
+    let mut modes = Vec::new();
+    if let Some(isolation_level) = isolation_level {
+        modes.push(match isolation_level {
+            IsolationLevel::Serializable => "ISOLATION LEVEL SERIALIZABLE",
+            IsolationLevel::RepeatableRead => "ISOLATION LEVEL REPEATABLE READ",
+            IsolationLevel::ReadCommitted => "ISOLATION LEVEL READ COMMITTED",
+            IsolationLevel::ReadUncommitted => "ISOLATION LEVEL READ UNCOMMITTED",
+            _ => unreachable!("isolation level is parsed from the header"),
+        });
+    }
+    if read_only {
+        modes.push("READ ONLY");
+    }
+    if deferrable {
+        modes.push("DEFERRABLE");
+    }
+    if modes.is_empty() {
+        "BEGIN".to_string()
+    } else {
+        format!("BEGIN {}", modes.join(", "))
+    }
+}
+
+async fn query_batch<T: GenericClient>(
+    transaction: &T,
+    statements: &PreparedStatements,
     queries: BatchQueryData,
     total_size: &mut usize,
     raw_output: bool,
@@ -530,18 +800,28 @@ async fn query_batch(
 ) -> anyhow::Result<Vec<Value>> {
     let mut results = Vec::with_capacity(queries.queries.len());
     let mut current_size = 0;
-    for stmt in queries.queries {
+    for (index, stmt) in queries.queries.into_iter().enumerate() {
         // TODO: maybe we should check that the transaction bit is set here
-        let (_, values) =
-            query_to_json(transaction, stmt, &mut current_size, raw_output, array_mode).await?;
+        let (_, values) = query_to_json(
+            transaction,
+            statements,
+            stmt,
+            &mut current_size,
+            raw_output,
+            array_mode,
+        )
+        .await
+        .context(FailedStatement(index))?;
         results.push(values);
     }
     *total_size += current_size;
     Ok(results)
 }
 
+#[instrument(name = "query", skip_all)]
 async fn query_to_json<T: GenericClient>(
     client: &T,
+    statements: &PreparedStatements,
     data: QueryData,
     current_size: &mut usize,
     raw_output: bool,
@@ -549,7 +829,20 @@ async fn query_to_json<T: GenericClient>(
 ) -> anyhow::Result<(ReadyForQueryStatus, Value)> {
     info!("executing query");
     let query_params = data.params;
-    let row_stream = client.query_raw_txt(&data.query, query_params).await?;
+    let row_stream = match &data.name {
+        Some(name) => {
+            let statement = statements.get_or_prepare(client, name, &data.query).await?;
+            client
+                .query_raw_txt(&statement, query_params)
+                .await
+                .map_err(|e| {
+                    // the statement might have been invalidated, e.g. by a schema change
+                    statements.remove(name);
+                    e
+                })?
+        }
+        None => client.query_raw_txt(&data.query, query_params).await?,
+    };
     info!("finished executing query");
 
     // Manually drain the stream into a vector to leave row_stream hanging
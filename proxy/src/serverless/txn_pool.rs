@@ -0,0 +1,109 @@
+//! Transactions spanning several SQL-over-HTTP requests.
+//!
+//! A request with `Neon-Transaction-Begin: true` opens a transaction and
+//! leaves it open, pinning its connection here under a random token which is
+//! returned in `Neon-Transaction-Token`. Requests carrying the token are
+//! executed on the same connection, until one with `Neon-Transaction-End`
+//! commits or rolls it back. Transactions left idle for longer than
+//! `--sql-over-http-txn-timeout` are aborted by closing their connection.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::metrics::NUM_OPEN_HTTP_TRANSACTIONS;
+use crate::{DbName, EndpointCacheKey, RoleName};
+
+use super::conn_pool::{Client, ConnInfo};
+
+const GC_INTERVAL: Duration = Duration::from_secs(1);
+
+struct PinnedTxn {
+    client: Client<tokio_postgres::Client>,
+    endpoint: EndpointCacheKey,
+    db_and_user: (DbName, RoleName),
+    expires_at: Instant,
+}
+
+pub struct TxnPool {
+    txns: DashMap<Uuid, PinnedTxn>,
+    timeout: Duration,
+}
+
+impl TxnPool {
+    pub fn new(config: &'static crate::config::HttpConfig) -> Arc<Self> {
+        Arc::new(Self {
+            txns: DashMap::new(),
+            timeout: config.txn_timeout,
+        })
+    }
+
+    /// Keep the client with an open transaction until the next request with
+    /// the token arrives. The client must have been discarded from the
+    /// connection pool, so that dropping it closes the connection.
+    pub fn pin(&self, token: Uuid, conn_info: &ConnInfo, client: Client<tokio_postgres::Client>) {
+        let txn = PinnedTxn {
+            client,
+            endpoint: conn_info.endpoint_cache_key(),
+            db_and_user: conn_info.db_and_user(),
+            expires_at: Instant::now() + self.timeout,
+        };
+        if self.txns.insert(token, txn).is_none() {
+            NUM_OPEN_HTTP_TRANSACTIONS.inc();
+        }
+    }
+
+    /// Take the client of the transaction out of the pool; it's put back with
+    /// `pin` if the transaction continues.
+    pub fn take(
+        &self,
+        token: Uuid,
+        conn_info: &ConnInfo,
+    ) -> anyhow::Result<Client<tokio_postgres::Client>> {
+        let endpoint = conn_info.endpoint_cache_key();
+        let db_and_user = conn_info.db_and_user();
+        // Either unknown token or the one of another user: don't tell which.
+        let (_, txn) = self
+            .txns
+            .remove_if(&token, |_, txn| {
+                txn.endpoint == endpoint && txn.db_and_user == db_and_user
+            })
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "transaction {token} not found, it might have timed out or be in use"
+                )
+            })?;
+        NUM_OPEN_HTTP_TRANSACTIONS.dec();
+        Ok(txn.client)
+    }
+
+    pub async fn gc_worker(&self) {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.gc();
+        }
+    }
+
+    fn gc(&self) {
+        let now = Instant::now();
+        self.txns.retain(|token, txn| {
+            if txn.expires_at > now {
+                return true;
+            }
+            // dropping the client closes the connection, aborting the transaction
+            info!(%token, "aborting transaction which was idle for more than {:?}", self.timeout);
+            NUM_OPEN_HTTP_TRANSACTIONS.dec();
+            false
+        });
+    }
+
+    pub fn shutdown(&self) {
+        self.txns.clear();
+        NUM_OPEN_HTTP_TRANSACTIONS.set(0);
+    }
+}
@@ -18,6 +18,7 @@ use std::{
     task::{ready, Context, Poll},
 };
 use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Duration, Instant, Interval, MissedTickBehavior};
 use tracing::warn;
 
 // TODO: use `std::sync::Exclusive` once it's stabilized.
@@ -31,6 +32,7 @@ pin_project! {
         #[pin]
         stream: SyncWrapper<WebSocketStream<S>>,
         bytes: Bytes,
+        keepalive: Option<Keepalive>,
     }
 }
 
@@ -39,8 +41,70 @@ impl<S> WebSocketRw<S> {
         Self {
             stream: stream.into(),
             bytes: Bytes::new(),
+            keepalive: None,
         }
     }
+
+    /// Ping the client every `interval`, and fail the connection if nothing,
+    /// pongs included, was received from it for longer than `timeout`.
+    pub fn with_keepalive(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.keepalive = Some(Keepalive::new(interval, timeout));
+        self
+    }
+}
+
+/// Keeps long-idle tunnels alive through load balancers and other
+/// intermediaries which drop connections without traffic.
+struct Keepalive {
+    interval: Interval,
+    timeout: Duration,
+    last_seen: Instant,
+    ping_pending: bool,
+}
+
+impl Keepalive {
+    fn new(interval: Duration, timeout: Duration) -> Self {
+        let mut interval = tokio::time::interval_at(Instant::now() + interval, interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            interval,
+            timeout,
+            last_seen: Instant::now(),
+            ping_pending: false,
+        }
+    }
+
+    fn poll_ping<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        mut stream: Pin<&mut WebSocketStream<S>>,
+        cx: &mut Context<'_>,
+    ) -> io::Result<()> {
+        while self.interval.poll_tick(cx).is_ready() {
+            if self.last_seen.elapsed() > self.timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "websocket client stopped responding to pings",
+                ));
+            }
+            self.ping_pending = true;
+        }
+
+        // Don't wait for the sink to become ready: we'll be polled again.
+        if self.ping_pending {
+            if let Poll::Ready(res) = stream.as_mut().poll_ready(cx) {
+                res.map_err(io_error)?;
+                stream
+                    .as_mut()
+                    .start_send(Message::Ping(Vec::new()))
+                    .map_err(io_error)?;
+                self.ping_pending = false;
+                // errors will show up on the next read or write
+                let _ = stream.poll_flush(cx);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketRw<S> {
@@ -98,7 +162,14 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncBufRead for WebSocketRw<S> {
                 return Poll::Ready(Ok(chunk));
             }
 
+            if let Some(keepalive) = this.keepalive.as_mut() {
+                keepalive.poll_ping(this.stream.as_mut().get_pin_mut(), cx)?;
+            }
+
             let res = ready!(this.stream.as_mut().get_pin_mut().poll_next(cx));
+            if let Some(keepalive) = this.keepalive.as_mut() {
+                keepalive.last_seen = Instant::now();
+            }
             match res.transpose().map_err(io_error)? {
                 Some(message) => match message {
                     Message::Ping(_) => {}
@@ -138,11 +209,15 @@ pub async fn serve_websocket(
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
 ) -> anyhow::Result<()> {
     let websocket = websocket.await?;
+    let mut stream = WebSocketRw::new(websocket);
+    if let Some(interval) = config.http_config.websocket_ping_interval {
+        stream = stream.with_keepalive(interval, config.http_config.websocket_ping_timeout);
+    }
     let res = handle_client(
         config,
         &mut ctx,
         cancel_map,
-        WebSocketRw::new(websocket),
+        stream,
         ClientMode::Websockets { hostname },
         endpoint_rate_limiter,
     )
@@ -180,6 +255,7 @@ mod tests {
     use tokio::{
         io::{duplex, AsyncReadExt, AsyncWriteExt},
         task::JoinSet,
+        time::Duration,
     };
 
     use super::WebSocketRw;
@@ -223,4 +299,50 @@ mod tests {
         js.join_next().await.unwrap().unwrap();
         js.join_next().await.unwrap().unwrap();
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn websocket_keepalive() {
+        let (stream1, stream2) = duplex(1024);
+
+        let mut client = WebSocketStream::from_raw_socket(stream1, Role::Client, None).await;
+        let mut rw = pin!(WebSocketRw::new(
+            WebSocketStream::from_raw_socket(stream2, Role::Server, None).await
+        )
+        .with_keepalive(Duration::from_secs(1), Duration::from_secs(3)));
+        let mut buf = vec![0; 1024];
+
+        // an idle client gets pinged, and its pongs keep the tunnel open
+        let client_task = async {
+            for _ in 0..5 {
+                let message = client.next().await.unwrap().unwrap();
+                assert!(matches!(message, Message::Ping(_)));
+            }
+            client
+                .send(Message::Binary(b"still here".to_vec()))
+                .await
+                .unwrap();
+        };
+        let (n, ()) = tokio::join!(rw.read(&mut buf), client_task);
+        assert_eq!(&buf[..n.unwrap()], b"still here");
+
+        // a client which doesn't respond is disconnected
+        let err = rw.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn websocket_compression_not_negotiated() {
+        let mut request = hyper::Request::builder()
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .header("sec-websocket-extensions", "permessage-deflate")
+            .body(hyper::Body::empty())
+            .unwrap();
+        assert!(hyper_tungstenite::is_upgrade_request(&request));
+
+        let (response, _) = hyper_tungstenite::upgrade(&mut request, None).unwrap();
+        assert!(!response.headers().contains_key("sec-websocket-extensions"));
+    }
 }
@@ -1,4 +1,4 @@
-use crate::config::TlsServerEndPoint;
+use crate::config::{TlsChannelBinding, TlsServerEndPoint};
 use crate::error::{ErrorKind, ReportableError, UserFacingError};
 use bytes::BytesMut;
 
@@ -151,6 +151,7 @@ impl<S: AsyncWrite + Unpin> PqStream<S> {
     {
         let error_kind = error.get_error_kind();
         let msg = error.to_string_client();
+        let code = error.sqlstate();
         tracing::info!(
             kind=error_kind.to_metric_label(),
             error=%error,
@@ -160,7 +161,7 @@ impl<S: AsyncWrite + Unpin> PqStream<S> {
 
         // already error case, ignore client IO error
         let _: Result<_, std::io::Error> = self
-            .write_message(&BeMessage::ErrorResponse(&msg, None))
+            .write_message(&BeMessage::ErrorResponse(&msg, code))
             .await;
 
         Err(ReportedError {
@@ -199,17 +200,39 @@ impl<S> Stream<S> {
         }
     }
 
-    pub fn tls_server_end_point(&self) -> TlsServerEndPoint {
+    /// Certificate chain presented by the client, starting with its own certificate.
+    pub fn client_certificates(&self) -> Option<&[rustls::Certificate]> {
         match self {
-            Stream::Raw { .. } => TlsServerEndPoint::Undefined,
+            Stream::Raw { .. } => None,
+            Stream::Tls { tls, .. } => tls.get_ref().1.peer_certificates(),
+        }
+    }
+
+    pub fn tls_channel_binding(&self) -> TlsChannelBinding {
+        match self {
+            Stream::Raw { .. } => TlsChannelBinding::NONE,
             Stream::Tls {
+                tls,
                 tls_server_end_point,
-                ..
-            } => *tls_server_end_point,
+            } => TlsChannelBinding {
+                tls_server_end_point: *tls_server_end_point,
+                tls_exporter: tls_exporter(tls.get_ref().1),
+            },
         }
     }
 }
 
+/// Keying material for `tls-exporter` channel binding, see [`TlsChannelBinding`].
+fn tls_exporter(conn: &rustls::ServerConnection) -> Option<[u8; 32]> {
+    if conn.protocol_version() != Some(rustls::ProtocolVersion::TLSv1_3) {
+        return None;
+    }
+    let mut tls_exporter = [0u8; 32];
+    conn.export_keying_material(&mut tls_exporter, b"EXPORTER-Channel-Binding", None)
+        .ok()?;
+    Some(tls_exporter)
+}
+
 #[derive(Debug, Error)]
 #[error("Can't upgrade TLS stream")]
 pub enum StreamUpgradeError {
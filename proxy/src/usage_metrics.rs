@@ -5,6 +5,7 @@ use chrono::{DateTime, Utc};
 use consumption_metrics::{idempotency_key, Event, EventChunk, EventType, CHUNK_SIZE};
 use dashmap::{mapref::entry::Entry, DashMap};
 use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::{
     convert::Infallible,
@@ -14,12 +15,19 @@ use std::{
     },
     time::Duration,
 };
+use tokio::time::Instant;
 use tracing::{error, info, instrument, trace};
 
 const PROXY_IO_BYTES_PER_CLIENT: &str = "proxy_io_bytes_per_client";
+const PROXY_INGRESS_BYTES_PER_CLIENT: &str = "proxy_ingress_bytes_per_client";
+const PROXY_CONNECTION_SECONDS_PER_CLIENT: &str = "proxy_connection_seconds_per_client";
 
 const DEFAULT_HTTP_REPORTING_TIMEOUT: Duration = Duration::from_secs(60);
 
+/// How many events to keep while the collector is unavailable.
+/// The oldest ones are dropped beyond that.
+const MAX_BUFFERED_EVENTS: usize = 100 * CHUNK_SIZE;
+
 /// Key that uniquely identifies the object, this metric describes.
 /// Currently, endpoint_id is enough, but this may change later,
 /// so keep it in a named struct.
@@ -36,7 +44,44 @@ pub struct Ids {
 #[derive(Debug)]
 pub struct MetricCounter {
     transmitted: AtomicU64,
+    received: AtomicU64,
     opened_connections: AtomicUsize,
+    connection_time: Mutex<ConnectionTime>,
+}
+
+/// Time spent by connections of the endpoint, counted for every connection
+/// separately, so that two connections open for a second make two seconds.
+#[derive(Debug)]
+struct ConnectionTime {
+    open: u32,
+    since: Instant,
+    accumulated: Duration,
+}
+
+impl ConnectionTime {
+    fn advance(&mut self, now: Instant) {
+        self.accumulated += now.saturating_duration_since(self.since) * self.open;
+        self.since = now;
+    }
+}
+
+/// Counts the connection time for as long as it's alive.
+pub struct ConnectionGuard(Arc<MetricCounter>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut time = self.0.connection_time.lock();
+        time.advance(Instant::now());
+        time.open -= 1;
+    }
+}
+
+/// Values of a counter to report for an interval.
+#[derive(Debug, Default, PartialEq)]
+struct Usage {
+    egress: u64,
+    ingress: u64,
+    connection_seconds: u64,
 }
 
 impl MetricCounter {
@@ -45,8 +90,31 @@ impl MetricCounter {
         self.transmitted.fetch_add(bytes, Ordering::AcqRel);
     }
 
+    /// Record that some bytes were received by the proxy from the client
+    pub fn record_ingress(&self, bytes: u64) {
+        self.received.fetch_add(bytes, Ordering::AcqRel);
+    }
+
+    /// Start counting the time of a client connection, until the guard is dropped.
+    pub fn track_connection(self: &Arc<Self>) -> ConnectionGuard {
+        let mut time = self.connection_time.lock();
+        time.advance(Instant::now());
+        time.open += 1;
+        ConnectionGuard(self.clone())
+    }
+
+    /// Take the connection time accumulated so far, rounded down to whole
+    /// seconds: the remainder is kept for the next interval.
+    fn take_connection_seconds(&self, now: Instant) -> u64 {
+        let mut time = self.connection_time.lock();
+        time.advance(now);
+        let seconds = time.accumulated.as_secs();
+        time.accumulated -= Duration::from_secs(seconds);
+        seconds
+    }
+
     /// extract the value that should be reported
-    fn should_report(self: &Arc<Self>) -> Option<u64> {
+    fn should_report(self: &Arc<Self>, now: Instant) -> Option<Usage> {
         // heuristic to see if the branch is still open
         // if a clone happens while we are observing, the heuristic will be incorrect.
         //
@@ -60,14 +128,18 @@ impl MetricCounter {
         // (to avoid sending the same metrics twice)
         // see the relevant discussion on why to do so even if the status is not success:
         // https://github.com/neondatabase/neon/pull/4563#discussion_r1246710956
-        let value = self.transmitted.swap(0, Ordering::AcqRel);
+        let usage = Usage {
+            egress: self.transmitted.swap(0, Ordering::AcqRel),
+            ingress: self.received.swap(0, Ordering::AcqRel),
+            connection_seconds: self.take_connection_seconds(now),
+        };
 
         // Our only requirement is that we report in every interval if there was an open connection
         // if there were no opened connections since, then we don't need to report
-        if value == 0 && !is_open && opened == 0 {
+        if usage == Usage::default() && !is_open && opened == 0 {
             None
         } else {
-            Some(value)
+            Some(usage)
         }
     }
 
@@ -78,9 +150,12 @@ impl MetricCounter {
             return false;
         };
         let opened = *counter.opened_connections.get_mut();
-        let value = *counter.transmitted.get_mut();
-        // clear if there's no data to report
-        value == 0 && opened == 0
+        let egress = *counter.transmitted.get_mut();
+        let ingress = *counter.received.get_mut();
+        let time = counter.connection_time.get_mut();
+        // clear if there's no data to report. The remaining connection time
+        // is below a second, and we can afford to lose it.
+        egress == 0 && ingress == 0 && opened == 0 && time.open == 0
     }
 }
 
@@ -103,7 +178,13 @@ impl Metrics {
                 .or_insert_with(|| {
                     Arc::new(MetricCounter {
                         transmitted: AtomicU64::new(0),
+                        received: AtomicU64::new(0),
                         opened_connections: AtomicUsize::new(0),
+                        connection_time: Mutex::new(ConnectionTime {
+                            open: 0,
+                            since: Instant::now(),
+                            accumulated: Duration::ZERO,
+                        }),
                     })
                 })
                 .clone()
@@ -125,6 +206,7 @@ pub async fn task_main(config: &MetricCollectionConfig) -> anyhow::Result<Infall
     let http_client = http::new_client_with_timeout(DEFAULT_HTTP_REPORTING_TIMEOUT);
     let hostname = hostname::get()?.as_os_str().to_string_lossy().into_owned();
 
+    let mut unsent = Vec::new();
     let mut prev = Utc::now();
     let mut ticker = tokio::time::interval(config.interval);
     loop {
@@ -133,6 +215,7 @@ pub async fn task_main(config: &MetricCollectionConfig) -> anyhow::Result<Infall
         let now = Utc::now();
         collect_metrics_iteration(
             &USAGE_METRICS,
+            &mut unsent,
             &http_client,
             &config.endpoint,
             &hostname,
@@ -147,6 +230,7 @@ pub async fn task_main(config: &MetricCollectionConfig) -> anyhow::Result<Infall
 #[instrument(skip_all)]
 async fn collect_metrics_iteration(
     metrics: &Metrics,
+    unsent: &mut Vec<Event<Ids, &'static str>>,
     client: &http::ClientWithMiddleware,
     metric_collection_endpoint: &reqwest::Url,
     hostname: &str,
@@ -160,16 +244,17 @@ async fn collect_metrics_iteration(
 
     let mut metrics_to_clear = Vec::new();
 
-    let metrics_to_send: Vec<(Ids, u64)> = metrics
+    let instant = Instant::now();
+    let metrics_to_send: Vec<(Ids, Usage)> = metrics
         .endpoints
         .iter()
         .filter_map(|counter| {
             let key = counter.key().clone();
-            let Some(value) = counter.should_report() else {
+            let Some(usage) = counter.should_report(instant) else {
                 metrics_to_clear.push(key);
                 return None;
             };
-            Some((key, value))
+            Some((key, usage))
         })
         .collect();
 
@@ -177,47 +262,82 @@ async fn collect_metrics_iteration(
         trace!("no new metrics to send");
     }
 
-    // Send metrics.
+    let event = |ids: &Ids, metric: &'static str, value: u64| Event {
+        kind: EventType::Incremental {
+            start_time: prev,
+            stop_time: now,
+        },
+        metric,
+        idempotency_key: idempotency_key(hostname),
+        value,
+        extra: ids.clone(),
+    };
+    for (ids, usage) in &metrics_to_send {
+        // egress is reported even if it's zero, as a sign of activity
+        unsent.push(event(ids, PROXY_IO_BYTES_PER_CLIENT, usage.egress));
+        if usage.ingress > 0 {
+            unsent.push(event(ids, PROXY_INGRESS_BYTES_PER_CLIENT, usage.ingress));
+        }
+        if usage.connection_seconds > 0 {
+            unsent.push(event(
+                ids,
+                PROXY_CONNECTION_SECONDS_PER_CLIENT,
+                usage.connection_seconds,
+            ));
+        }
+    }
+
+    if unsent.len() > MAX_BUFFERED_EVENTS {
+        let dropped = unsent.len() - MAX_BUFFERED_EVENTS;
+        error!("too many unsent metrics, dropping {dropped} oldest events");
+        unsent.drain(..dropped);
+    }
+
+    // Send metrics, including the ones we failed to send before: they keep
+    // their idempotency keys, so the collector can drop duplicates.
     // Split into chunks of 1000 metrics to avoid exceeding the max request size
-    for chunk in metrics_to_send.chunks(CHUNK_SIZE) {
-        let events = chunk
-            .iter()
-            .map(|(ids, value)| Event {
-                kind: EventType::Incremental {
-                    start_time: prev,
-                    stop_time: now,
-                },
-                metric: PROXY_IO_BYTES_PER_CLIENT,
-                idempotency_key: idempotency_key(hostname),
-                value: *value,
-                extra: Ids {
-                    endpoint_id: ids.endpoint_id.clone(),
-                    branch_id: ids.branch_id.clone(),
-                },
-            })
-            .collect();
+    while !unsent.is_empty() {
+        let chunk = &unsent[..unsent.len().min(CHUNK_SIZE)];
 
         let res = client
             .post(metric_collection_endpoint.clone())
-            .json(&EventChunk { events })
+            .json(&EventChunk {
+                events: chunk.into(),
+            })
             .send()
             .await;
 
         let res = match res {
             Ok(x) => x,
             Err(err) => {
-                error!("failed to send metrics: {:?}", err);
-                continue;
+                error!(
+                    "failed to send metrics, keeping {} events to retry: {:?}",
+                    unsent.len(),
+                    err
+                );
+                break;
             }
         };
 
+        if res.status().is_server_error() {
+            error!(
+                "metrics endpoint failed, keeping {} events to retry: {:?}",
+                unsent.len(),
+                res
+            );
+            break;
+        }
+
         if !res.status().is_success() {
             error!("metrics endpoint refused the sent metrics: {:?}", res);
-            for metric in chunk.iter().filter(|(_, value)| *value > (1u64 << 40)) {
+            for metric in chunk.iter().filter(|metric| metric.value > (1u64 << 40)) {
                 // Report if the metric value is suspiciously large
                 error!("potentially abnormal metric value: {:?}", metric);
             }
         }
+
+        let sent = chunk.len();
+        unsent.drain(..sent);
     }
 
     for metric in metrics_to_clear {
@@ -236,7 +356,11 @@ async fn collect_metrics_iteration(
 mod tests {
     use std::{
         net::TcpListener,
-        sync::{Arc, Mutex},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
     };
 
     use anyhow::Error;
@@ -244,11 +368,12 @@ mod tests {
     use consumption_metrics::{Event, EventChunk};
     use hyper::{
         service::{make_service_fn, service_fn},
-        Body, Response,
+        Body, Response, StatusCode,
     };
+    use tokio::time::Instant;
     use url::Url;
 
-    use super::{collect_metrics_iteration, Ids, Metrics};
+    use super::{collect_metrics_iteration, Ids, Metrics, PROXY_INGRESS_BYTES_PER_CLIENT};
     use crate::{http, rate_limiter::RateLimiterConfig};
 
     #[tokio::test]
@@ -257,15 +382,24 @@ mod tests {
 
         let reports = Arc::new(Mutex::new(vec![]));
         let reports2 = reports.clone();
+        let fail = Arc::new(AtomicBool::new(false));
+        let fail2 = fail.clone();
 
         let server = hyper::server::Server::from_tcp(listener)
             .unwrap()
             .serve(make_service_fn(move |_| {
                 let reports = reports.clone();
+                let fail = fail.clone();
                 async move {
                     Ok::<_, Error>(service_fn(move |req| {
                         let reports = reports.clone();
+                        let fail = fail.clone();
                         async move {
+                            if fail.load(Ordering::Relaxed) {
+                                let mut response = Response::new(Body::from(vec![]));
+                                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                                return Ok(response);
+                            }
                             let bytes = hyper::body::to_bytes(req.into_body()).await?;
                             let events: EventChunk<'static, Event<Ids, String>> =
                                 serde_json::from_slice(&bytes)?;
@@ -282,9 +416,10 @@ mod tests {
         let client = http::new_client(RateLimiterConfig::default());
         let endpoint = Url::parse(&format!("http://{addr}")).unwrap();
         let now = Utc::now();
+        let mut unsent = Vec::new();
 
         // no counters have been registered
-        collect_metrics_iteration(&metrics, &client, &endpoint, "foo", now, now).await;
+        collect_metrics_iteration(&metrics, &mut unsent, &client, &endpoint, "foo", now, now).await;
         let r = std::mem::take(&mut *reports2.lock().unwrap());
         assert!(r.is_empty());
 
@@ -295,7 +430,7 @@ mod tests {
         });
 
         // the counter should be observed despite 0 egress
-        collect_metrics_iteration(&metrics, &client, &endpoint, "foo", now, now).await;
+        collect_metrics_iteration(&metrics, &mut unsent, &client, &endpoint, "foo", now, now).await;
         let r = std::mem::take(&mut *reports2.lock().unwrap());
         assert_eq!(r.len(), 1);
         assert_eq!(r[0].events.len(), 1);
@@ -305,21 +440,80 @@ mod tests {
         counter.record_egress(1);
 
         // egress should be observered
-        collect_metrics_iteration(&metrics, &client, &endpoint, "foo", now, now).await;
+        collect_metrics_iteration(&metrics, &mut unsent, &client, &endpoint, "foo", now, now).await;
         let r = std::mem::take(&mut *reports2.lock().unwrap());
         assert_eq!(r.len(), 1);
         assert_eq!(r[0].events.len(), 1);
         assert_eq!(r[0].events[0].value, 1);
 
+        // record ingress
+        counter.record_ingress(2);
+
+        // ingress is reported as a separate event
+        collect_metrics_iteration(&metrics, &mut unsent, &client, &endpoint, "foo", now, now).await;
+        let r = std::mem::take(&mut *reports2.lock().unwrap());
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].events.len(), 2);
+        assert_eq!(r[0].events[0].value, 0);
+        assert_eq!(r[0].events[1].metric, PROXY_INGRESS_BYTES_PER_CLIENT);
+        assert_eq!(r[0].events[1].value, 2);
+
+        // the collector is unavailable
+        fail2.store(true, Ordering::Relaxed);
+        counter.record_egress(5);
+
+        // events are kept
+        collect_metrics_iteration(&metrics, &mut unsent, &client, &endpoint, "foo", now, now).await;
+        assert!(reports2.lock().unwrap().is_empty());
+        assert_eq!(unsent.len(), 1);
+
+        // and sent once the collector is back
+        fail2.store(false, Ordering::Relaxed);
+        counter.record_egress(7);
+        collect_metrics_iteration(&metrics, &mut unsent, &client, &endpoint, "foo", now, now).await;
+        let r = std::mem::take(&mut *reports2.lock().unwrap());
+        assert_eq!(r.len(), 1);
+        let values: Vec<_> = r[0].events.iter().map(|e| e.value).collect();
+        assert_eq!(values, [5, 7]);
+        assert!(unsent.is_empty());
+
         // release counter
         drop(counter);
 
         // we do not observe the counter
-        collect_metrics_iteration(&metrics, &client, &endpoint, "foo", now, now).await;
+        collect_metrics_iteration(&metrics, &mut unsent, &client, &endpoint, "foo", now, now).await;
         let r = std::mem::take(&mut *reports2.lock().unwrap());
         assert!(r.is_empty());
 
         // counter is unregistered
         assert!(metrics.endpoints.is_empty());
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn connection_seconds() {
+        let metrics = Metrics::default();
+        let counter = metrics.register(Ids {
+            endpoint_id: "e1".into(),
+            branch_id: "b1".into(),
+        });
+
+        let conn1 = counter.track_connection();
+        let conn2 = counter.track_connection();
+
+        // every connection is counted
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(counter.take_connection_seconds(Instant::now()), 20);
+
+        drop(conn1);
+        tokio::time::advance(Duration::from_millis(5500)).await;
+        assert_eq!(counter.take_connection_seconds(Instant::now()), 5);
+
+        // the remainder is carried over
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(counter.take_connection_seconds(Instant::now()), 1);
+
+        drop(conn2);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(counter.take_connection_seconds(Instant::now()), 0);
+    }
 }
@@ -0,0 +1,74 @@
+//! Throughput of WAL appends with each of the safekeeper's WAL io engines.
+//!
+//! Every iteration writes a chunk at the end of a segment file and
+//! fdatasyncs it, which is what `PhysicalStorage` does for an AppendRequest
+//! followed by a flush. The segment is rewritten from the start once full.
+//!
+//! Run with `cargo bench --bench wal_append`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use safekeeper::io_engine::{IoEngineKind, SegmentFile};
+
+const SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+fn engines() -> Vec<IoEngineKind> {
+    vec![
+        IoEngineKind::StdFs,
+        #[cfg(target_os = "linux")]
+        IoEngineKind::TokioEpollUring,
+    ]
+}
+
+fn bench_wal_append(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let dir = camino_tempfile::tempdir_in(env!("CARGO_TARGET_TMPDIR")).unwrap();
+
+    let mut group = c.benchmark_group("wal_append");
+    for write_size in [8 * 1024, 128 * 1024] {
+        group.throughput(Throughput::Bytes(write_size as u64));
+        let buf = vec![0xab; write_size];
+
+        for engine in engines() {
+            let engine = rt.block_on(engine.probe());
+            let path = dir.path().join(format!("{engine}-{write_size}"));
+            let mut file = rt.block_on(async {
+                let file = tokio::fs::File::create(&path).await.unwrap();
+                file.set_len(SEGMENT_SIZE).await.unwrap();
+                SegmentFile::new(engine, file, 0).await.unwrap()
+            });
+            let mut pos = 0;
+
+            group.bench_with_input(
+                BenchmarkId::new(engine.to_string(), write_size),
+                &write_size,
+                |b, _| {
+                    b.iter(|| {
+                        rt.block_on(async {
+                            if pos + buf.len() as u64 > SEGMENT_SIZE {
+                                // std-fs writes at the current position, reopen
+                                // to rewind it.
+                                let f = tokio::fs::OpenOptions::new()
+                                    .write(true)
+                                    .open(&path)
+                                    .await
+                                    .unwrap();
+                                file = SegmentFile::new(engine, f, 0).await.unwrap();
+                                pos = 0;
+                            }
+                            file.write_all_at(&buf, pos).await.unwrap();
+                            file.sync_data().await.unwrap();
+                            pos += buf.len() as u64;
+                        })
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_wal_append);
+criterion_main!(benches);
@@ -1,13 +1,30 @@
 use utils::auth::{AuthError, Claims, Scope};
 use utils::id::TenantId;
 
+/// Check that token is acceptable on endpoint allowing `allowed_auth_scope`
+/// tokens: either SafekeeperData (any token) or Tenant (only tenant scoped
+/// tokens, endpoint for semi-trusted networks).
+pub fn check_scope(claims: &Claims, allowed_auth_scope: Scope) -> Result<(), AuthError> {
+    if matches!(allowed_auth_scope, Scope::Tenant) && !matches!(claims.scope, Scope::Tenant) {
+        return Err(AuthError(
+            "passed JWT token is for full access, but only tenant scope is allowed".into(),
+        ));
+    }
+    if matches!(claims.scope, Scope::Tenant) && claims.tenant_id.is_none() {
+        return Err(AuthError(
+            "jwt token scope is Tenant, but tenant id is missing".into(),
+        ));
+    }
+    Ok(())
+}
+
 pub fn check_permission(claims: &Claims, tenant_id: Option<TenantId>) -> Result<(), AuthError> {
     match (&claims.scope, tenant_id) {
         (Scope::Tenant, None) => Err(AuthError(
             "Attempt to access management api with tenant scope. Permission denied".into(),
         )),
         (Scope::Tenant, Some(tenant_id)) => {
-            if claims.tenant_id.unwrap() != tenant_id {
+            if claims.tenant_id != Some(tenant_id) {
                 return Err(AuthError("Tenant id mismatch. Permission denied".into()));
             }
             Ok(())
@@ -16,5 +33,31 @@ pub fn check_permission(claims: &Claims, tenant_id: Option<TenantId>) -> Result<
             "PageServerApi scope makes no sense for Safekeeper".into(),
         )),
         (Scope::SafekeeperData, _) => Ok(()),
+        (Scope::ProxyApi, _) => Err(AuthError(
+            "ProxyApi scope makes no sense for Safekeeper".into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_only_scope() {
+        let tenant_id = TenantId::generate();
+        let tenant_claims = Claims::new(Some(tenant_id), Scope::Tenant);
+        let full_claims = Claims::new(None, Scope::SafekeeperData);
+
+        assert!(check_scope(&tenant_claims, Scope::Tenant).is_ok());
+        assert!(check_scope(&full_claims, Scope::Tenant).is_err());
+        assert!(check_scope(&full_claims, Scope::SafekeeperData).is_ok());
+        assert!(check_scope(&Claims::new(None, Scope::Tenant), Scope::SafekeeperData).is_err());
+
+        assert!(check_permission(&tenant_claims, Some(tenant_id)).is_ok());
+        assert!(check_permission(&tenant_claims, Some(TenantId::generate())).is_err());
+        assert!(check_permission(&tenant_claims, None).is_err());
+        // tenant id missing in the token is not a panic
+        assert!(check_permission(&Claims::new(None, Scope::Tenant), Some(tenant_id)).is_err());
     }
 }
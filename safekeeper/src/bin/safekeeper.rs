@@ -14,6 +14,7 @@ use tokio::signal::unix::{signal, SignalKind};
 use tokio::task::JoinError;
 use toml_edit::Document;
 
+use std::env::{var, VarError};
 use std::fs::{self, File};
 use std::io::{ErrorKind, Write};
 use std::str::FromStr;
@@ -28,8 +29,12 @@ use utils::pid_file;
 use metrics::set_build_info_metric;
 use safekeeper::defaults::{
     DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_HTTP_LISTEN_ADDR, DEFAULT_MAX_OFFLOADER_LAG_BYTES,
-    DEFAULT_PG_LISTEN_ADDR,
+    DEFAULT_PARTIAL_BACKUP_TIMEOUT, DEFAULT_PG_LISTEN_ADDR, DEFAULT_REMOTE_WAL_CACHE_SIZE,
+    DEFAULT_TENANT_WAL_INGEST_BURST, DEFAULT_TOMBSTONE_TTL, DEFAULT_WAL_FLUSH_BATCH_DELAY,
+    DEFAULT_WAL_IO_ENGINE,
 };
+use safekeeper::io_engine::IoEngineKind;
+use safekeeper::remote_wal_cache;
 use safekeeper::wal_service;
 use safekeeper::GlobalTimelines;
 use safekeeper::SafeKeeperConf;
@@ -95,6 +100,11 @@ struct Args {
     /// Listen http endpoint for management and metrics in the form host:port.
     #[arg(long, default_value = DEFAULT_HTTP_LISTEN_ADDR)]
     listen_http: String,
+    /// Listen http endpoint in the form host:port allowing only tenant scoped
+    /// auth tokens, which gives access only to per-timeline API. Requires
+    /// --http-tenant-only-auth-public-key-path.
+    #[arg(long, default_value = None, verbatim_doc_comment)]
+    listen_http_tenant_only: Option<String>,
     /// Advertised endpoint for receiving/sending WAL in the form host:port. If not
     /// specified, listen_pg is used to advertise instead.
     #[arg(long, default_value = None)]
@@ -102,12 +112,23 @@ struct Args {
     /// Availability zone of the safekeeper.
     #[arg(long)]
     availability_zone: Option<String>,
-    /// Do not wait for changes to be written safely to disk. Unsafe.
-    #[arg(short, long)]
+    /// Do not wait for changes to be written safely to disk. Unsafe, meant
+    /// only for tests and benchmarks: acknowledged WAL can be lost on OS crash
+    /// or power loss.
+    #[arg(short, long, verbatim_doc_comment)]
     no_sync: bool,
-    /// Dump control file at path specified by this argument and exit.
+    /// Dump control file at path specified by this argument and exit. Format
+    /// version of the file is printed to stderr.
     #[arg(long)]
     dump_control_file: Option<Utf8PathBuf>,
+    /// Rewrite control file at path specified by this argument in format
+    /// --control-file-version and exit. Allows to roll back to older
+    /// safekeeper binary.
+    #[arg(long, requires = "control_file_version", verbatim_doc_comment)]
+    migrate_control_file: Option<Utf8PathBuf>,
+    /// Target format version for --migrate-control-file.
+    #[arg(long)]
+    control_file_version: Option<u32>,
     /// Broker endpoint for storage nodes coordination in the form
     /// http[s]://host:port. In case of https schema TLS is connection is
     /// established; plaintext otherwise.
@@ -116,12 +137,18 @@ struct Args {
     /// Broker keepalive interval.
     #[arg(long, value_parser= humantime::parse_duration, default_value = storage_broker::DEFAULT_KEEPALIVE_INTERVAL)]
     broker_keepalive_interval: Duration,
+    /// Comma separated HTTP endpoints (http://host:port) of peer safekeepers.
+    /// If set, timelines state is exchanged with them by polling their HTTP
+    /// API instead of the broker, so the broker is not needed at all.
+    #[arg(long, value_delimiter = ',', verbatim_doc_comment)]
+    static_peers: Vec<String>,
     /// Peer safekeeper is considered dead after not receiving heartbeats from
     /// it during this period passed as a human readable duration.
     #[arg(long, value_parser= humantime::parse_duration, default_value = DEFAULT_HEARTBEAT_TIMEOUT, verbatim_doc_comment)]
     heartbeat_timeout: Duration,
-    /// Enable/disable peer recovery.
-    #[arg(long, default_value = "false", action=ArgAction::Set)]
+    /// Enable/disable peer recovery: fetching WAL from a peer safekeeper which
+    /// is ahead when no compute streams to this one.
+    #[arg(long, default_value = "true", action=ArgAction::Set)]
     peer_recovery: bool,
     /// Remote storage configuration for WAL backup (offloading to s3) as TOML
     /// inline table, e.g.
@@ -141,6 +168,69 @@ struct Args {
     /// WAL backup horizon.
     #[arg(long)]
     disable_wal_backup: bool,
+    /// Compress full WAL segments with zstd before uploading them to remote
+    /// storage; they are stored with '.zst' suffix. WAL readers handle both
+    /// compressed and uncompressed segments, so it can be toggled at any time.
+    #[arg(long)]
+    wal_backup_compression: bool,
+    /// Periodically upload the partial (not yet complete) WAL segment to
+    /// remote storage.
+    #[arg(long)]
+    partial_backup_enabled: bool,
+    /// How often to upload the partial WAL segment, as a human readable
+    /// duration.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = DEFAULT_PARTIAL_BACKUP_TIMEOUT)]
+    partial_backup_timeout: Duration,
+    /// Max time to wait for more WAL from compute before fsyncing and
+    /// acknowledging already received WAL, as a human readable duration. WAL
+    /// which is readily available is always flushed by a single fsync; a small
+    /// delay on busy timelines batches more of it at the cost of commit latency.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = DEFAULT_WAL_FLUSH_BATCH_DELAY, verbatim_doc_comment)]
+    wal_flush_batch_delay: Duration,
+    /// Engine to write and fsync WAL with: 'std-fs' or, on Linux,
+    /// 'tokio-epoll-uring'. Falls back to 'std-fs' if io_uring is not
+    /// available.
+    #[arg(long, default_value = DEFAULT_WAL_IO_ENGINE)]
+    wal_io_engine: IoEngineKind,
+    /// Validate incoming WAL (page headers, record CRCs and xl_prev links)
+    /// before writing it to disk, rejecting appends of corrupted WAL. Without
+    /// it, WAL is decoded only after being written.
+    #[arg(long)]
+    validate_wal: bool,
+    /// Remove local WAL once it is backed up to remote storage and consumed by
+    /// the pageserver, without waiting for lagging peers. Removed WAL is
+    /// downloaded from remote storage if anyone asks for it. Has no effect if
+    /// WAL backup is disabled.
+    #[arg(long)]
+    wal_eviction_enabled: bool,
+    /// Percentage of disk usage above which WAL that is backed up to remote
+    /// storage and consumed by the pageserver is removed even if lagging peers
+    /// still need it, to avoid running out of disk space. Has no effect if WAL
+    /// backup is disabled.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    wal_disk_usage_threshold_pct: Option<u8>,
+    /// Max size in bytes of local cache of WAL segments downloaded from remote
+    /// storage to serve lagging consumers; next segments are prefetched into
+    /// it. The cache is disabled by default (0), WAL is then streamed from
+    /// remote storage directly.
+    #[arg(long, default_value_t = DEFAULT_REMOTE_WAL_CACHE_SIZE, verbatim_doc_comment)]
+    remote_wal_cache_size: u64,
+    /// Max rate in bytes per second at which WAL of a single tenant is
+    /// accepted from computes. When it is exceeded, safekeeper stops reading
+    /// AppendRequests of the tenant's timelines for a while, slowing down
+    /// the walproposer. Unlimited by default.
+    #[arg(long, verbatim_doc_comment)]
+    tenant_wal_ingest_rate: Option<u64>,
+    /// Amount of WAL in bytes a tenant can write at once above
+    /// --tenant-wal-ingest-rate. Raised to the maximum AppendRequest size if
+    /// lower.
+    #[arg(long, default_value_t = DEFAULT_TENANT_WAL_INGEST_BURST)]
+    tenant_wal_ingest_burst: u64,
+    /// How long a deleted timeline can't be created again, as a human readable
+    /// duration. Computes which didn't notice the deletion are expected to be
+    /// gone by then.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = DEFAULT_TOMBSTONE_TTL)]
+    tombstone_ttl: Duration,
     /// If given, enables auth on incoming connections to WAL service endpoint
     /// (--listen-pg). Value specifies path to a .pem public key used for
     /// validations of JWT tokens. Empty string is allowed and means disabling
@@ -159,6 +249,12 @@ struct Args {
     /// means disabling auth.
     #[arg(long, verbatim_doc_comment, value_parser = opt_pathbuf_parser)]
     http_auth_public_key_path: Option<Utf8PathBuf>,
+    /// If given, enables auth on incoming connections to tenant only http
+    /// service endpoint (--listen-http-tenant-only). Value specifies path to a
+    /// .pem public key used for validations of JWT tokens. Empty string is
+    /// allowed and means disabling auth.
+    #[arg(long, verbatim_doc_comment, value_parser = opt_pathbuf_parser)]
+    http_tenant_only_auth_public_key_path: Option<Utf8PathBuf>,
     /// Format for logging, either 'plain' or 'json'.
     #[arg(long, default_value = "plain")]
     log_format: String,
@@ -203,14 +299,37 @@ async fn main() -> anyhow::Result<()> {
             args.http_auth_public_key_path = None;
         }
     }
+    if let Some(pb) = &args.http_tenant_only_auth_public_key_path {
+        if pb.as_os_str().is_empty() {
+            args.http_tenant_only_auth_public_key_path = None;
+        }
+    }
+    // Without auth the tenant only listener would serve the whole API.
+    if args.listen_http_tenant_only.is_some()
+        && args.http_tenant_only_auth_public_key_path.is_none()
+    {
+        bail!("--listen-http-tenant-only requires --http-tenant-only-auth-public-key-path");
+    }
 
     if let Some(addr) = args.dump_control_file {
-        let state = control_file::FileStorage::load_control_file(addr)?;
+        let (version, state) = control_file::FileStorage::load_control_file_versioned(addr)?;
         let json = serde_json::to_string(&state)?;
+        eprintln!("control file format version {version}");
         print!("{json}");
         return Ok(());
     }
 
+    if let Some(path) = args.migrate_control_file {
+        let version = args
+            .control_file_version
+            .expect("required by --migrate-control-file");
+        let (old_version, state) = control_file::FileStorage::load_control_file_versioned(&path)?;
+        let buf = control_file::FileStorage::serialize_state(&state, version)?;
+        control_file::FileStorage::write_control_file(&path, &buf, !args.no_sync).await?;
+        eprintln!("migrated control file {path} from format version {old_version} to {version}");
+        return Ok(());
+    }
+
     // important to keep the order of:
     // 1. init logging
     // 2. tracing panic hook
@@ -273,6 +392,31 @@ async fn main() -> anyhow::Result<()> {
             Some(Arc::new(SwappableJwtAuth::new(jwt_auth)))
         }
     };
+    let http_tenant_only_auth = match args.http_tenant_only_auth_public_key_path.as_ref() {
+        None => {
+            info!("http tenant only auth is disabled");
+            None
+        }
+        Some(path) => {
+            info!("loading http tenant only auth JWT key(s) from {path}");
+            let jwt_auth = JwtAuth::from_key_path(path).context("failed to load the auth key")?;
+            Some(Arc::new(SwappableJwtAuth::new(jwt_auth)))
+        }
+    };
+
+    let sk_auth_token = match var("SAFEKEEPER_AUTH_TOKEN") {
+        Ok(v) => {
+            info!("loaded JWT token for authentication with safekeepers");
+            Some(Arc::new(v))
+        }
+        Err(VarError::NotPresent) => {
+            info!("no JWT token for authentication with safekeepers detected");
+            None
+        }
+        Err(e) => {
+            return Err(e).context("failed to read SAFEKEEPER_AUTH_TOKEN environment variable");
+        }
+    };
 
     let conf = SafeKeeperConf {
         workdir,
@@ -280,20 +424,36 @@ async fn main() -> anyhow::Result<()> {
         listen_pg_addr: args.listen_pg,
         listen_pg_addr_tenant_only: args.listen_pg_tenant_only,
         listen_http_addr: args.listen_http,
+        listen_http_addr_tenant_only: args.listen_http_tenant_only,
         advertise_pg_addr: args.advertise_pg,
         availability_zone: args.availability_zone,
         no_sync: args.no_sync,
         broker_endpoint: args.broker_endpoint,
         broker_keepalive_interval: args.broker_keepalive_interval,
+        static_peers: args.static_peers,
         heartbeat_timeout: args.heartbeat_timeout,
         peer_recovery_enabled: args.peer_recovery,
         remote_storage: args.remote_storage,
         max_offloader_lag_bytes: args.max_offloader_lag,
         wal_backup_enabled: !args.disable_wal_backup,
+        wal_backup_compression: args.wal_backup_compression,
+        partial_backup_enabled: args.partial_backup_enabled,
+        partial_backup_timeout: args.partial_backup_timeout,
+        wal_flush_batch_delay: args.wal_flush_batch_delay,
+        wal_io_engine: args.wal_io_engine.probe().await,
+        validate_wal: args.validate_wal,
+        wal_eviction_enabled: args.wal_eviction_enabled,
+        wal_disk_usage_threshold_pct: args.wal_disk_usage_threshold_pct,
+        remote_wal_cache_size: args.remote_wal_cache_size,
+        tenant_wal_ingest_rate: args.tenant_wal_ingest_rate,
+        tenant_wal_ingest_burst: args.tenant_wal_ingest_burst,
+        tombstone_ttl: args.tombstone_ttl,
         backup_parallel_jobs: args.wal_backup_parallel_jobs,
         pg_auth,
         pg_tenant_only_auth,
         http_auth,
+        http_tenant_only_auth,
+        sk_auth_token,
         current_thread_runtime: args.current_thread_runtime,
     };
 
@@ -316,6 +476,19 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
         pid_file::claim_for_current_process(&lock_file_path).context("claim pid file")?;
     info!("claimed pid file at {lock_file_path:?}");
 
+    if conf.no_sync {
+        warn!("################################################################");
+        warn!("fsync is disabled by --no-sync: acknowledged WAL can be lost on");
+        warn!("OS crash or power loss. Use it only for tests and benchmarks!");
+        warn!("################################################################");
+    }
+    if !conf.wal_flush_batch_delay.is_zero() {
+        info!(
+            "WAL fsyncs are batched, delaying acknowledgement up to {:?}",
+            conf.wal_flush_batch_delay
+        );
+    }
+
     // ensure that the lock file is held even if the main thread of the process is panics
     // we need to release the lock file only when the current process is gone
     std::mem::forget(lock_file);
@@ -353,6 +526,25 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
         e
     })?;
 
+    let http_listener_tenant_only = if let Some(listen_http_addr_tenant_only) =
+        &conf.listen_http_addr_tenant_only
+    {
+        info!(
+            "starting safekeeper tenant scoped HTTP service on {}",
+            listen_http_addr_tenant_only
+        );
+        let listener = tcp_listener::bind(listen_http_addr_tenant_only.clone()).map_err(|e| {
+            error!(
+                "failed to bind to address {}: {}",
+                listen_http_addr_tenant_only, e
+            );
+            e
+        })?;
+        Some(listener)
+    } else {
+        None
+    };
+
     // Register metrics collector for active timelines. It's important to do this
     // after daemonizing, otherwise process collector will be upset.
     let timeline_collector = safekeeper::metrics::TimelineCollector::new();
@@ -381,6 +573,8 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
         .map(|res| ("WAL backup launcher".to_owned(), res));
     tasks_handles.push(Box::pin(wal_backup_handle));
 
+    remote_wal_cache::init(&conf)?;
+
     // Load all timelines from disk to memory.
     GlobalTimelines::init(conf.clone(), wal_backup_launcher_tx).await?;
 
@@ -421,10 +615,24 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
     let http_handle = current_thread_rt
         .as_ref()
         .unwrap_or_else(|| HTTP_RUNTIME.handle())
-        .spawn(http::task_main(conf_, http_listener))
+        .spawn(http::task_main(conf_, http_listener, Scope::SafekeeperData))
         .map(|res| ("HTTP service main".to_owned(), res));
     tasks_handles.push(Box::pin(http_handle));
 
+    if let Some(http_listener_tenant_only) = http_listener_tenant_only {
+        let conf_ = conf.clone();
+        let http_handle = current_thread_rt
+            .as_ref()
+            .unwrap_or_else(|| HTTP_RUNTIME.handle())
+            .spawn(http::task_main(
+                conf_,
+                http_listener_tenant_only,
+                Scope::Tenant,
+            ))
+            .map(|res| ("HTTP service tenant only main".to_owned(), res));
+        tasks_handles.push(Box::pin(http_handle));
+    }
+
     let conf_ = conf.clone();
     let broker_task_handle = current_thread_rt
         .as_ref()
@@ -0,0 +1,521 @@
+//! Synthetic walproposer measuring WAL append throughput and commit latency
+//! of a running safekeeper.
+//!
+//! Each client creates a new timeline on the safekeeper, goes through the
+//! proposer handshake and streams valid WAL consisting of logical message
+//! records of the given size, keeping up to `--max-inflight` bytes not yet
+//! flushed by the safekeeper. Commit latency is the time from sending an
+//! AppendRequest until the safekeeper reports WAL flushed up to its end,
+//! which is what walproposer waits for with a single safekeeper.
+//!
+//! fsync behaviour is configured on the safekeeper itself (`--no-sync`,
+//! `--wal-flush-batch-delay`, `--wal-io-engine`); run the benchmark against
+//! differently configured instances to compare them. Timelines created by the
+//! benchmark are left on the safekeeper, so don't point it to a production
+//! one.
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use clap::Parser;
+use futures::{SinkExt, Stream, StreamExt};
+use postgres_ffi::pg_constants::{
+    RM_LOGICALMSG_ID, XLP_FIRST_IS_CONTRECORD, XLP_LONG_HEADER, XLR_BLOCK_ID_DATA_LONG,
+    XLR_BLOCK_ID_DATA_SHORT,
+};
+use postgres_ffi::v16::bindings::{
+    XLogLongPageHeaderData, XLogPageHeaderData, XLogRecord, XLOG_PAGE_MAGIC,
+};
+use postgres_ffi::v16::xlog_utils::{
+    XlLogicalMessage, XLOG_RECORD_CRC_OFFS, XLOG_SIZE_OF_XLOG_LONG_PHD, XLOG_SIZE_OF_XLOG_RECORD,
+};
+use postgres_ffi::{MAX_SEND_SIZE, PG_TLI, WAL_SEGMENT_SIZE, XLOG_BLCKSZ};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::*;
+use utils::id::{TenantId, TenantTimelineId, TimelineId};
+use utils::logging;
+use utils::lsn::Lsn;
+use utils::postgres_client::wal_stream_connection_config;
+
+const PROTOCOL_VERSION: u32 = 3;
+const PG_VERSION: u32 = 160000;
+const SYSTEM_ID: u64 = 0x5afe_be7c;
+const LOGICAL_MESSAGE_PREFIX: &[u8] = b"safekeeper_bench\0";
+
+/// Benchmark WAL appends to a running safekeeper.
+#[derive(Parser)]
+#[command(about)]
+struct Args {
+    /// Postgres protocol address of the safekeeper.
+    #[arg(long, default_value = "127.0.0.1:5454")]
+    safekeeper: String,
+    /// JWT token to authenticate with, if the safekeeper requires auth.
+    #[arg(long)]
+    auth_token: Option<String>,
+    /// Tenant to create timelines in, random by default.
+    #[arg(long)]
+    tenant_id: Option<TenantId>,
+    /// Number of concurrent walproposers, each streaming to its own timeline.
+    #[arg(long, default_value = "1")]
+    clients: NonZeroUsize,
+    /// Size of each WAL record in bytes.
+    #[arg(long, default_value = "128")]
+    record_size: usize,
+    /// Max amount of WAL in a single AppendRequest, in bytes.
+    #[arg(long, default_value_t = MAX_SEND_SIZE)]
+    append_size: usize,
+    /// Max amount of WAL sent but not yet flushed by the safekeeper, in
+    /// bytes. Set to --append-size to wait for each append before sending
+    /// the next one.
+    #[arg(long, default_value = "16777216")]
+    max_inflight: u64,
+    /// How long to stream WAL.
+    #[arg(long, default_value = "10s")]
+    runtime: humantime::Duration,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init(
+        logging::LogFormat::Plain,
+        logging::TracingErrorLayerEnablement::Disabled,
+        logging::Output::Stderr,
+    )?;
+
+    let args = Args::parse();
+    ensure!(
+        args.append_size <= MAX_SEND_SIZE,
+        "--append-size can't exceed {MAX_SEND_SIZE}"
+    );
+    ensure!(
+        max_record_len(args.record_size) <= args.append_size,
+        "--record-size doesn't fit into --append-size"
+    );
+    ensure!(
+        args.max_inflight >= args.append_size as u64,
+        "--max-inflight must be at least --append-size"
+    );
+
+    let tenant_id = args.tenant_id.unwrap_or_else(TenantId::generate);
+    let deadline = Instant::now() + *args.runtime;
+    let args = Arc::new(args);
+    let clients = (0..args.clients.get()).map(|_| {
+        let ttid = TenantTimelineId::new(tenant_id, TimelineId::generate());
+        let args = args.clone();
+        tokio::spawn(
+            async move { run_client(&args, ttid, deadline).await }
+                .instrument(info_span!("client", %ttid)),
+        )
+    });
+
+    let started_at = Instant::now();
+    let mut total = Stats::new();
+    for res in futures::future::join_all(clients).await {
+        total.add(&res??);
+    }
+    let elapsed = started_at.elapsed();
+
+    let output = total.output(elapsed);
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// Upper bound of the space record takes in WAL, including page headers.
+fn max_record_len(record_size: usize) -> usize {
+    let pages = record_size / XLOG_BLCKSZ + 2;
+    record_size + 8 + pages * XLOG_SIZE_OF_XLOG_LONG_PHD
+}
+
+/// Stream WAL to a new timeline until `deadline` and wait until all of it
+/// is flushed.
+async fn run_client(args: &Args, ttid: TenantTimelineId, deadline: Instant) -> Result<Stats> {
+    let mut cfg =
+        wal_stream_connection_config(ttid, &args.safekeeper, args.auth_token.as_deref(), None)?
+            .to_tokio_postgres_config();
+    cfg.application_name("safekeeper_bench");
+    let (client, connection) = cfg.connect(postgres::NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            warn!("connection error: {e}");
+        }
+    });
+    let copy_both: tokio_postgres::CopyBothDuplex<Bytes> =
+        client.copy_both_simple("START_WAL_PUSH").await?;
+    let (mut sink, mut stream) = copy_both.split();
+
+    // Handshake: the timeline is new, so simply start WAL in a new segment.
+    let start_lsn = Lsn(WAL_SEGMENT_SIZE as u64);
+    sink.send(greeting(ttid)).await?;
+    let Reply::Greeting { term } = recv(&mut stream).await? else {
+        bail!("expected greeting reply");
+    };
+    let term = term + 1;
+    sink.send(vote_request(term)).await?;
+    let Reply::Vote { vote_given } = recv(&mut stream).await? else {
+        bail!("expected vote reply");
+    };
+    ensure!(vote_given, "safekeeper refused to vote for term {term}");
+    sink.send(proposer_elected(term, start_lsn)).await?;
+    info!("elected in term {term}, streaming WAL since {start_lsn}");
+
+    // End LSNs and send times of appends not yet flushed.
+    let inflight = Mutex::new(VecDeque::<(Lsn, Instant)>::new());
+    let (flush_tx, mut flush_rx) = watch::channel(start_lsn);
+    // Set by writer once it is done, to the end of WAL it sent.
+    let (end_tx, mut end_rx) = watch::channel(None);
+
+    let writer = async {
+        let mut wal = WalGenerator::new(start_lsn, args.record_size);
+        let mut wal_bytes = 0;
+        loop {
+            let flush_lsn = tokio::select! {
+                res = flush_rx.wait_for(|flush_lsn| {
+                    wal.lsn.0 - flush_lsn.0 < args.max_inflight
+                }) => *res?,
+                _ = tokio::time::sleep_until(deadline) => break,
+            };
+            let begin_lsn = wal.lsn;
+            let mut data = BytesMut::with_capacity(args.append_size);
+            while data.len() + max_record_len(args.record_size) <= args.append_size {
+                wal.append_record(&mut data);
+            }
+            let msg = append_request(term, start_lsn, begin_lsn, wal.lsn, flush_lsn, &data);
+            inflight
+                .lock()
+                .unwrap()
+                .push_back((wal.lsn, Instant::now()));
+            sink.send(msg).await?;
+            wal_bytes += data.len() as u64;
+        }
+        end_tx.send_replace(Some(wal.lsn));
+        Ok::<_, anyhow::Error>(wal_bytes)
+    };
+
+    let reader = async {
+        let mut stats = Stats::new();
+        let mut flush_lsn = start_lsn;
+        loop {
+            if end_rx.borrow().is_some_and(|end_lsn| end_lsn <= flush_lsn) {
+                break;
+            }
+            tokio::select! {
+                reply = recv(&mut stream) => {
+                    let Reply::Append { term: sk_term, flush_lsn: sk_flush_lsn } = reply? else {
+                        bail!("expected append reply");
+                    };
+                    ensure!(sk_term == term, "safekeeper moved to term {sk_term}");
+                    flush_lsn = flush_lsn.max(sk_flush_lsn);
+                    flush_tx.send_replace(flush_lsn);
+                    let mut inflight = inflight.lock().unwrap();
+                    while let Some((end_lsn, sent_at)) = inflight.front() {
+                        if *end_lsn > flush_lsn {
+                            break;
+                        }
+                        stats.observe(sent_at.elapsed())?;
+                        inflight.pop_front();
+                    }
+                }
+                res = end_rx.changed() => res?,
+            }
+        }
+        info!("streamed WAL up to {flush_lsn}");
+        Ok(stats)
+    };
+
+    let (wal_bytes, mut stats) = tokio::try_join!(writer, reader)?;
+    stats.wal_bytes = wal_bytes;
+    Ok(stats)
+}
+
+async fn recv<S>(stream: &mut S) -> Result<Reply>
+where
+    S: Stream<Item = Result<Bytes, tokio_postgres::Error>> + Unpin,
+{
+    let msg = stream
+        .next()
+        .await
+        .context("safekeeper closed the connection")??;
+    Reply::parse(msg)
+}
+
+/// Messages from safekeeper we care about.
+enum Reply {
+    Greeting { term: u64 },
+    Vote { vote_given: bool },
+    Append { term: u64, flush_lsn: Lsn },
+}
+
+impl Reply {
+    fn parse(mut buf: Bytes) -> Result<Reply> {
+        if buf.remaining() < 16 {
+            bail!("reply is too short");
+        }
+        let tag = buf.get_u64_le() as u8 as char;
+        let term = buf.get_u64_le();
+        match tag {
+            'g' => Ok(Reply::Greeting { term }),
+            // term in vote reply is ours if vote is given
+            'v' if buf.remaining() >= 8 => Ok(Reply::Vote {
+                vote_given: buf.get_u64_le() != 0,
+            }),
+            'a' if buf.remaining() >= 8 => Ok(Reply::Append {
+                term,
+                flush_lsn: Lsn(buf.get_u64_le()),
+            }),
+            _ => bail!("unexpected reply with tag {tag}"),
+        }
+    }
+}
+
+fn greeting(ttid: TenantTimelineId) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u64_le('g' as u64);
+    buf.put_u32_le(PROTOCOL_VERSION);
+    buf.put_u32_le(PG_VERSION);
+    buf.put_slice(&[0u8; 16]); // proposer_id
+    buf.put_u64_le(SYSTEM_ID);
+    buf.put_slice(&ttid.timeline_id.as_arr());
+    buf.put_slice(&ttid.tenant_id.as_arr());
+    buf.put_u32_le(PG_TLI);
+    buf.put_u32_le(WAL_SEGMENT_SIZE as u32);
+    buf.put_u32_le(0); // mconf generation
+    buf.freeze()
+}
+
+fn vote_request(term: u64) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u64_le('v' as u64);
+    buf.put_u64_le(term);
+    buf.freeze()
+}
+
+fn proposer_elected(term: u64, start_lsn: Lsn) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u64_le('e' as u64);
+    buf.put_u64_le(term);
+    buf.put_u64_le(start_lsn.0); // start_streaming_at
+    buf.put_u32_le(1); // term history
+    buf.put_u64_le(term);
+    buf.put_u64_le(start_lsn.0);
+    buf.put_u64_le(start_lsn.0); // timeline_start_lsn
+    buf.freeze()
+}
+
+fn append_request(
+    term: u64,
+    epoch_start_lsn: Lsn,
+    begin_lsn: Lsn,
+    end_lsn: Lsn,
+    commit_lsn: Lsn,
+    wal: &[u8],
+) -> Bytes {
+    let mut buf = BytesMut::with_capacity(80 + wal.len());
+    buf.put_u64_le('a' as u64);
+    buf.put_u64_le(term);
+    buf.put_u64_le(epoch_start_lsn.0);
+    buf.put_u64_le(begin_lsn.0);
+    buf.put_u64_le(end_lsn.0);
+    buf.put_u64_le(commit_lsn.0);
+    buf.put_u64_le(commit_lsn.0); // truncate_lsn
+    buf.put_slice(&[0u8; 16]); // proposer_uuid
+    buf.put_slice(wal);
+    buf.freeze()
+}
+
+/// Generates valid WAL of logical message records, so that it passes
+/// decoding (and --validate-wal) on the safekeeper.
+struct WalGenerator {
+    /// Position of the next WAL byte.
+    lsn: Lsn,
+    /// Start of the previous record, for xl_prev.
+    prev_record_lsn: Lsn,
+    message: Vec<u8>,
+}
+
+impl WalGenerator {
+    fn new(start_lsn: Lsn, record_size: usize) -> Self {
+        let overhead = XLOG_SIZE_OF_XLOG_RECORD
+            + 5
+            + std::mem::size_of::<XlLogicalMessage>()
+            + LOGICAL_MESSAGE_PREFIX.len();
+        WalGenerator {
+            lsn: start_lsn,
+            prev_record_lsn: Lsn(0),
+            message: vec![0xab; record_size.saturating_sub(overhead)],
+        }
+    }
+
+    /// Append next record to `buf`, along with page headers it crosses.
+    fn append_record(&mut self, buf: &mut BytesMut) {
+        if self.lsn.block_offset() == 0 {
+            self.put_page_header(buf, 0);
+        }
+        let record = self.encode_record();
+        self.prev_record_lsn = self.lsn;
+
+        let mut rest = &record[..];
+        while !rest.is_empty() {
+            if self.lsn.block_offset() == 0 {
+                self.put_page_header(buf, rest.len() as u32);
+            }
+            let n = rest.len().min(self.lsn.remaining_in_block() as usize);
+            buf.put_slice(&rest[..n]);
+            rest = &rest[n..];
+            self.lsn += n as u64;
+        }
+        // records are 8 byte aligned
+        let aligned = self.lsn.align();
+        buf.put_bytes(0, (aligned.0 - self.lsn.0) as usize);
+        self.lsn = aligned;
+    }
+
+    /// Put header of the page starting at current position; `rem_len` is
+    /// remaining length of the record continued on it.
+    fn put_page_header(&mut self, buf: &mut BytesMut, rem_len: u32) {
+        let mut short_hdr = XLogPageHeaderData {
+            xlp_magic: XLOG_PAGE_MAGIC as u16,
+            xlp_info: if rem_len > 0 {
+                XLP_FIRST_IS_CONTRECORD
+            } else {
+                0
+            },
+            xlp_tli: PG_TLI,
+            xlp_pageaddr: self.lsn.0,
+            xlp_rem_len: rem_len,
+            ..Default::default()
+        };
+        let hdr = if self.lsn.segment_offset(WAL_SEGMENT_SIZE) == 0 {
+            short_hdr.xlp_info |= XLP_LONG_HEADER;
+            XLogLongPageHeaderData {
+                std: short_hdr,
+                xlp_sysid: SYSTEM_ID,
+                xlp_seg_size: WAL_SEGMENT_SIZE as u32,
+                xlp_xlog_blcksz: XLOG_BLCKSZ as u32,
+            }
+            .encode()
+        } else {
+            short_hdr.encode()
+        }
+        .expect("failed to encode page header");
+        buf.put_slice(&hdr);
+        self.lsn += hdr.len() as u64;
+    }
+
+    /// Encode record starting at current position.
+    fn encode_record(&self) -> Vec<u8> {
+        let mainrdata = XlLogicalMessage {
+            db_id: 0,
+            transactional: 0,
+            prefix_size: LOGICAL_MESSAGE_PREFIX.len() as u64,
+            message_size: self.message.len() as u64,
+        }
+        .encode();
+        let mainrdata_len = mainrdata.len() + LOGICAL_MESSAGE_PREFIX.len() + self.message.len();
+
+        let mut data = Vec::with_capacity(5 + mainrdata_len);
+        if mainrdata_len <= u8::MAX as usize {
+            data.push(XLR_BLOCK_ID_DATA_SHORT);
+            data.push(mainrdata_len as u8);
+        } else {
+            data.push(XLR_BLOCK_ID_DATA_LONG);
+            data.extend_from_slice(&(mainrdata_len as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&mainrdata);
+        data.extend_from_slice(LOGICAL_MESSAGE_PREFIX);
+        data.extend_from_slice(&self.message);
+
+        let mut header = XLogRecord {
+            xl_tot_len: (XLOG_SIZE_OF_XLOG_RECORD + data.len()) as u32,
+            xl_xid: 0,
+            xl_prev: self.prev_record_lsn.0,
+            xl_info: 0, // XLOG_LOGICAL_MESSAGE
+            xl_rmid: RM_LOGICALMSG_ID,
+            __bindgen_padding_0: [0u8; 2],
+            xl_crc: 0,
+        };
+        let header_bytes = header.encode().expect("failed to encode record header");
+        let crc = crc32c::crc32c_append(0, &data);
+        header.xl_crc = crc32c::crc32c_append(crc, &header_bytes[..XLOG_RECORD_CRC_OFFS]);
+
+        let mut record = header
+            .encode()
+            .expect("failed to encode record header")
+            .to_vec();
+        record.extend_from_slice(&data);
+        record
+    }
+}
+
+struct Stats {
+    wal_bytes: u64,
+    latency_histo: hdrhistogram::Histogram<u64>,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Stats {
+            wal_bytes: 0,
+            // Fixed bounds, so that recording fails instead of resizing the
+            // histogram and skewing the results.
+            latency_histo: hdrhistogram::Histogram::new_with_bounds(1, 100_000_000, 3).unwrap(),
+        }
+    }
+
+    fn observe(&mut self, latency: Duration) -> Result<()> {
+        let micros = latency.as_micros().try_into().context("latency too big")?;
+        self.latency_histo
+            .record(micros)
+            .context("add to histogram")
+    }
+
+    fn add(&mut self, other: &Stats) {
+        self.wal_bytes += other.wal_bytes;
+        self.latency_histo.add(&other.latency_histo).unwrap();
+    }
+
+    fn output(&self, elapsed: Duration) -> Output {
+        let secs = elapsed.as_secs_f64();
+        let percentile = |p| {
+            humantime::format_duration(Duration::from_micros(
+                self.latency_histo.value_at_percentile(p),
+            ))
+            .to_string()
+        };
+        Output {
+            elapsed: humantime::format_duration(elapsed).to_string(),
+            appends: self.latency_histo.len(),
+            appends_per_sec: self.latency_histo.len() as f64 / secs,
+            wal_bytes: self.wal_bytes,
+            wal_mib_per_sec: self.wal_bytes as f64 / secs / (1024.0 * 1024.0),
+            commit_latency_mean: humantime::format_duration(Duration::from_micros(
+                self.latency_histo.mean() as u64,
+            ))
+            .to_string(),
+            commit_latency_p50: percentile(50.0),
+            commit_latency_p99: percentile(99.0),
+            commit_latency_p99_9: percentile(99.9),
+            commit_latency_max: humantime::format_duration(Duration::from_micros(
+                self.latency_histo.max(),
+            ))
+            .to_string(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Output {
+    elapsed: String,
+    appends: u64,
+    appends_per_sec: f64,
+    wal_bytes: u64,
+    wal_mib_per_sec: f64,
+    commit_latency_mean: String,
+    commit_latency_p50: String,
+    commit_latency_p99: String,
+    commit_latency_p99_9: String,
+    commit_latency_max: String,
+}
@@ -1,4 +1,7 @@
 //! Communication with the broker, providing safekeeper peers and pageserver coordination.
+//!
+//! If static peers are configured, the broker is not used at all: the same
+//! data is exchanged by polling peers' HTTP API.
 
 use anyhow::anyhow;
 use anyhow::bail;
@@ -7,6 +10,7 @@ use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 
+use safekeeper_api::models::BrokerTimelineInfo;
 use storage_broker::parse_proto_ttid;
 
 use storage_broker::proto::subscribe_safekeeper_info_request::SubscriptionKey as ProtoSubscriptionKey;
@@ -18,22 +22,34 @@ use std::time::Instant;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tracing::*;
+use utils::id::TenantTimelineId;
 
 use crate::metrics::BROKER_ITERATION_TIMELINES;
 use crate::metrics::BROKER_PULLED_UPDATES;
 use crate::metrics::BROKER_PUSHED_UPDATES;
 use crate::metrics::BROKER_PUSH_ALL_UPDATES_SECONDS;
+use crate::wal_service::is_partitioned;
 use crate::GlobalTimelines;
 use crate::SafeKeeperConf;
 
 const RETRY_INTERVAL_MSEC: u64 = 1000;
 const PUSH_INTERVAL_MSEC: u64 = 1000;
+const STATIC_PEER_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often inactive timelines are pushed if peer recovery is enabled.
+const INACTIVE_PUSH_INTERVAL: Duration = Duration::from_secs(30);
 
 /// Push once in a while data about all active timelines to the broker.
+///
+/// With peer recovery enabled, inactive timelines are pushed as well, on start
+/// and then every INACTIVE_PUSH_INTERVAL. That's how a safekeeper which missed
+/// WAL and its peers learn about each other after compute is gone: they
+/// become active while one of them can recover from another, see
+/// `SharedState::is_active`.
 async fn push_loop(conf: SafeKeeperConf) -> anyhow::Result<()> {
     let mut client =
         storage_broker::connect(conf.broker_endpoint.clone(), conf.broker_keepalive_interval)?;
     let push_interval = Duration::from_millis(PUSH_INTERVAL_MSEC);
+    let mut inactive_pushed_at: Option<Instant> = None;
 
     let outbound = async_stream::stream! {
         loop {
@@ -41,14 +57,23 @@ async fn push_loop(conf: SafeKeeperConf) -> anyhow::Result<()> {
             // is under plain mutex. That's ok, all this code is not performance
             // sensitive and there is no risk of deadlock as we don't await while
             // lock is held.
+            if is_partitioned() {
+                sleep(push_interval).await;
+                continue;
+            }
             let now = Instant::now();
+            let push_inactive = conf.peer_recovery_enabled
+                && inactive_pushed_at.map_or(true, |at| at.elapsed() >= INACTIVE_PUSH_INTERVAL);
+            if push_inactive {
+                inactive_pushed_at = Some(now);
+            }
             let all_tlis = GlobalTimelines::get_all();
             let mut n_pushed_tlis = 0;
             for tli in &all_tlis {
                 // filtering alternative futures::stream::iter(all_tlis)
                 //   .filter(|tli| {let tli = tli.clone(); async move { tli.is_active().await}}).collect::<Vec<_>>().await;
                 // doesn't look better, and I'm not sure how to do that without collect.
-                if !tli.is_active().await {
+                if !push_inactive && !tli.is_active().await {
                     continue;
                 }
                 let sk_info = tli.get_safekeeper_info(&conf).await;
@@ -119,7 +144,92 @@ async fn pull_loop(conf: SafeKeeperConf) -> Result<()> {
     bail!("end of stream");
 }
 
+/// Poll static peers for their timelines state instead of using the broker.
+async fn static_peers_loop(conf: SafeKeeperConf) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let mut ticker = tokio::time::interval(Duration::from_millis(PUSH_INTERVAL_MSEC));
+    let mut inactive_pulled_at: Option<Instant> = None;
+
+    loop {
+        ticker.tick().await;
+        // Same as with the broker, see push_loop.
+        let include_inactive = conf.peer_recovery_enabled
+            && inactive_pulled_at.map_or(true, |at| at.elapsed() >= INACTIVE_PUSH_INTERVAL);
+        if include_inactive {
+            inactive_pulled_at = Some(Instant::now());
+        }
+
+        // With the broker we receive our own info as well, record it directly.
+        for tli in GlobalTimelines::get_all() {
+            if !include_inactive && !tli.is_active().await {
+                continue;
+            }
+            let sk_info = tli.get_safekeeper_info(&conf).await;
+            if let Err(e) = tli.record_safekeeper_info(sk_info).await {
+                warn!("failed to record own timeline {} info: {:?}", tli.ttid, e);
+            }
+        }
+
+        let pulls = conf
+            .static_peers
+            .iter()
+            .map(|peer| pull_static_peer(&client, &conf, peer, include_inactive));
+        for (peer, res) in conf
+            .static_peers
+            .iter()
+            .zip(futures::future::join_all(pulls).await)
+        {
+            if let Err(e) = res {
+                warn!("failed to pull timelines info from peer {}: {:?}", peer, e);
+            }
+        }
+    }
+}
+
+/// Fetch info about all active timelines of the peer, or all of them if
+/// `include_inactive`, and record it.
+async fn pull_static_peer(
+    client: &reqwest::Client,
+    conf: &SafeKeeperConf,
+    peer: &str,
+    include_inactive: bool,
+) -> Result<()> {
+    let url = format!("{}/v1/broker_info", peer.trim_end_matches('/'));
+    let mut req = client
+        .get(url)
+        .query(&[("include_inactive", include_inactive)])
+        .timeout(STATIC_PEER_TIMEOUT);
+    if let Some(token) = &conf.sk_auth_token {
+        req = req.bearer_auth(token.as_str());
+    }
+    let infos: Vec<BrokerTimelineInfo> = req.send().await?.error_for_status()?.json().await?;
+
+    let ok_counter = BROKER_PULLED_UPDATES.with_label_values(&["ok"]);
+    let not_found = BROKER_PULLED_UPDATES.with_label_values(&["not_found"]);
+    let err_counter = BROKER_PULLED_UPDATES.with_label_values(&["error"]);
+
+    for info in infos {
+        let ttid = TenantTimelineId::new(info.tenant_id, info.timeline_id);
+        if let Ok(tli) = GlobalTimelines::get(ttid) {
+            let res = tli.record_safekeeper_info(info.into()).await;
+            if res.is_ok() {
+                ok_counter.inc();
+            } else {
+                err_counter.inc();
+            }
+            res?;
+        } else {
+            not_found.inc();
+        }
+    }
+    Ok(())
+}
+
 pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
+    if !conf.static_peers.is_empty() {
+        info!("started, polling static peers {:?}", conf.static_peers);
+        return static_peers_loop(conf).await;
+    }
     info!("started, broker endpoint {:?}", conf.broker_endpoint);
 
     let mut ticker = tokio::time::interval(Duration::from_millis(RETRY_INTERVAL_MSEC));
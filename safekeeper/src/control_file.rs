@@ -2,7 +2,7 @@
 
 use anyhow::{bail, ensure, Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use utils::crashsafe::durable_rename;
@@ -12,7 +12,7 @@ use std::ops::Deref;
 use std::path::Path;
 use std::time::Instant;
 
-use crate::control_file_upgrade::upgrade_control_file;
+use crate::control_file_upgrade::{downgrade_control_file, upgrade_control_file};
 use crate::metrics::PERSIST_CONTROL_FILE_SECONDS;
 use crate::state::TimelinePersistentState;
 use utils::{bin_ser::LeSer, id::TenantTimelineId};
@@ -22,12 +22,12 @@ use crate::SafeKeeperConf;
 use std::convert::TryInto;
 
 pub const SK_MAGIC: u32 = 0xcafeceefu32;
-pub const SK_FORMAT_VERSION: u32 = 7;
+pub const SK_FORMAT_VERSION: u32 = 8;
 
 // contains persistent metadata for safekeeper
-const CONTROL_FILE_NAME: &str = "safekeeper.control";
+pub const CONTROL_FILE_NAME: &str = "safekeeper.control";
 // needed to atomically update the state using `rename`
-const CONTROL_FILE_NAME_PARTIAL: &str = "safekeeper.control.partial";
+const PARTIAL_SUFFIX: &str = "partial";
 pub const CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
 
 /// Storage should keep actual state inside of it. It should implement Deref
@@ -85,7 +85,8 @@ impl FileStorage {
     }
 
     /// Check the magic/version in the on-disk data and deserialize it, if possible.
-    fn deser_sk_state(buf: &mut &[u8]) -> Result<TimelinePersistentState> {
+    /// Returns the on-disk version along with the state in the current format.
+    fn deser_sk_state(buf: &mut &[u8]) -> Result<(u32, TimelinePersistentState)> {
         // Read the version independent part
         let magic = ReadBytesExt::read_u32::<LittleEndian>(buf)?;
         if magic != SK_MAGIC {
@@ -98,10 +99,28 @@ impl FileStorage {
         let version = ReadBytesExt::read_u32::<LittleEndian>(buf)?;
         if version == SK_FORMAT_VERSION {
             let res = TimelinePersistentState::des(buf)?;
-            return Ok(res);
+            return Ok((version, res));
         }
         // try to upgrade
-        upgrade_control_file(buf, version)
+        Ok((version, upgrade_control_file(buf, version)?))
+    }
+
+    /// Serialize the state into control file of given format `version`, with
+    /// magic, version and checksum.
+    pub fn serialize_state(s: &TimelinePersistentState, version: u32) -> Result<Vec<u8>> {
+        let mut buf: Vec<u8> = Vec::new();
+        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_MAGIC)?;
+        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, version)?;
+        if version == SK_FORMAT_VERSION {
+            s.ser_into(&mut buf)?;
+        } else {
+            buf.extend(downgrade_control_file(s, version)?);
+        }
+
+        // calculate checksum before resize
+        let checksum = crc32c::crc32c(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        Ok(buf)
     }
 
     /// Load control file for given ttid at path specified by conf.
@@ -117,6 +136,13 @@ impl FileStorage {
     pub fn load_control_file<P: AsRef<Path>>(
         control_file_path: P,
     ) -> Result<TimelinePersistentState> {
+        Ok(Self::load_control_file_versioned(control_file_path)?.1)
+    }
+
+    /// Read in the control file, returning its on-disk format version as well.
+    pub fn load_control_file_versioned<P: AsRef<Path>>(
+        control_file_path: P,
+    ) -> Result<(u32, TimelinePersistentState)> {
         let mut control_file = std::fs::OpenOptions::new()
             .read(true)
             .write(true)
@@ -147,51 +173,30 @@ impl FileStorage {
             )
         );
 
-        let state = FileStorage::deser_sk_state(&mut &buf[..buf.len() - CHECKSUM_SIZE])
-            .with_context(|| {
-                format!(
-                    "while reading control file {}",
-                    control_file_path.as_ref().display(),
-                )
-            })?;
-        Ok(state)
-    }
-}
-
-impl Deref for FileStorage {
-    type Target = TimelinePersistentState;
-
-    fn deref(&self) -> &Self::Target {
-        &self.state
+        FileStorage::deser_sk_state(&mut &buf[..buf.len() - CHECKSUM_SIZE]).with_context(|| {
+            format!(
+                "while reading control file {}",
+                control_file_path.as_ref().display(),
+            )
+        })
     }
-}
 
-#[async_trait::async_trait]
-impl Storage for FileStorage {
-    /// Persists state durably to the underlying storage.
+    /// Durably write serialized control file `buf` at `control_path`, going
+    /// through a temporary file and `rename`.
     ///
     /// For a description, see <https://lwn.net/Articles/457667/>.
-    async fn persist(&mut self, s: &TimelinePersistentState) -> Result<()> {
-        let _timer = PERSIST_CONTROL_FILE_SECONDS.start_timer();
-
+    pub async fn write_control_file(control_path: &Utf8Path, buf: &[u8], sync: bool) -> Result<()> {
         // write data to safekeeper.control.partial
-        let control_partial_path = self.timeline_dir.join(CONTROL_FILE_NAME_PARTIAL);
+        let control_partial_path =
+            Utf8PathBuf::from(format!("{}.{}", control_path, PARTIAL_SUFFIX));
         let mut control_partial = File::create(&control_partial_path).await.with_context(|| {
             format!(
                 "failed to create partial control file at: {}",
                 &control_partial_path
             )
         })?;
-        let mut buf: Vec<u8> = Vec::new();
-        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_MAGIC)?;
-        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_FORMAT_VERSION)?;
-        s.ser_into(&mut buf)?;
 
-        // calculate checksum before resize
-        let checksum = crc32c::crc32c(&buf);
-        buf.extend_from_slice(&checksum.to_le_bytes());
-
-        control_partial.write_all(&buf).await.with_context(|| {
+        control_partial.write_all(buf).await.with_context(|| {
             format!(
                 "failed to write safekeeper state into control file at: {}",
                 control_partial_path
@@ -204,8 +209,28 @@ impl Storage for FileStorage {
             )
         })?;
 
+        durable_rename(&control_partial_path, control_path, sync).await?;
+        Ok(())
+    }
+}
+
+impl Deref for FileStorage {
+    type Target = TimelinePersistentState;
+
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for FileStorage {
+    /// Persists state durably to the underlying storage.
+    async fn persist(&mut self, s: &TimelinePersistentState) -> Result<()> {
+        let _timer = PERSIST_CONTROL_FILE_SECONDS.start_timer();
+
+        let buf = Self::serialize_state(s, SK_FORMAT_VERSION)?;
         let control_path = self.timeline_dir.join(CONTROL_FILE_NAME);
-        durable_rename(&control_partial_path, &control_path, !self.conf.no_sync).await?;
+        Self::write_control_file(&control_path, &buf, !self.conf.no_sync).await?;
 
         // update internal state
         self.state = s.clone();
@@ -223,8 +248,12 @@ mod test {
     use super::*;
     use crate::SafeKeeperConf;
     use anyhow::Result;
+    use std::str::FromStr;
     use tokio::fs;
-    use utils::{id::TenantTimelineId, lsn::Lsn};
+    use utils::{
+        id::{TenantId, TenantTimelineId, TimelineId},
+        lsn::Lsn,
+    };
 
     fn stub_conf() -> SafeKeeperConf {
         let workdir = camino_tempfile::tempdir().unwrap().into_path();
@@ -310,4 +339,66 @@ mod test {
             Ok(_) => panic!("expected error"),
         }
     }
+
+    fn fixture_path(version: u32) -> Utf8PathBuf {
+        Utf8PathBuf::from(format!("test_data/control_file/v{version}.control"))
+    }
+
+    // Every format version ever written must stay loadable; add a fixture when
+    // bumping SK_FORMAT_VERSION.
+    #[test]
+    fn test_load_historical_versions() {
+        let tenant_id = TenantId::from_str("cf0480929707ee75372337efaa5ecf96").unwrap();
+        let timeline_id = TimelineId::from_str("112ded66422aa5e953e5440fa5427ac4").unwrap();
+
+        for version in 1..=SK_FORMAT_VERSION {
+            let (disk_version, state) =
+                FileStorage::load_control_file_versioned(fixture_path(version))
+                    .unwrap_or_else(|e| panic!("failed to load control file v{version}: {e:#}"));
+
+            assert_eq!(disk_version, version);
+            assert_eq!(state.tenant_id, tenant_id);
+            assert_eq!(state.timeline_id, timeline_id);
+            assert_eq!(state.acceptor_state.term, 42);
+            assert_eq!(state.acceptor_state.term_history.0.len(), 1);
+            assert_eq!(state.server.pg_version, 140005);
+            assert_eq!(state.server.system_id, 0x1234567887654321);
+            assert_eq!(state.server.wal_seg_size, 16 * 1024 * 1024);
+            assert_eq!(state.commit_lsn, Lsn(0x2000100));
+            assert_ne!(state.timeline_start_lsn, Lsn(0));
+            assert_eq!(state.mconf.generation != 0, version >= 8);
+        }
+
+        // v5 files written before timeline_start_lsn was known
+        let state = FileStorage::load_control_file(fixture_path(5)).unwrap();
+        assert_eq!(state.timeline_start_lsn, Lsn(1));
+        assert_eq!(state.local_start_lsn, Lsn(1));
+
+        let state = FileStorage::load_control_file(fixture_path(8)).unwrap();
+        assert_eq!(state.mconf.generation, 3);
+        assert!(state.mconf.is_joint());
+    }
+
+    #[test]
+    fn test_downgrade_control_file() {
+        for version in [7, 8] {
+            let path = fixture_path(version);
+            let state = FileStorage::load_control_file(&path).unwrap();
+            let orig = std::fs::read(&path).unwrap();
+            assert_eq!(FileStorage::serialize_state(&state, version).unwrap(), orig);
+        }
+
+        // membership configuration can't be represented in older formats
+        let state = FileStorage::load_control_file(fixture_path(8)).unwrap();
+        assert!(FileStorage::serialize_state(&state, 7).is_err());
+        assert!(FileStorage::serialize_state(&state, 6).is_err());
+
+        let mut state = TimelinePersistentState::empty();
+        state.commit_lsn = Lsn(42);
+        let buf = FileStorage::serialize_state(&state, 7).unwrap();
+        let (version, restored) =
+            FileStorage::deser_sk_state(&mut &buf[..buf.len() - CHECKSUM_SIZE]).unwrap();
+        assert_eq!(version, 7);
+        assert_eq!(restored.commit_lsn, Lsn(42));
+    }
 }
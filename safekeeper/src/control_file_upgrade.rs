@@ -1,5 +1,7 @@
 //! Code to deal with safekeeper control file upgrades
 use crate::{
+    control_file::SK_FORMAT_VERSION,
+    membership::{Configuration, INVALID_GENERATION},
     safekeeper::{AcceptorState, PgUuid, ServerInfo, Term, TermHistory, TermLsn},
     state::{PersistedPeers, TimelinePersistentState},
 };
@@ -138,132 +140,255 @@ pub struct SafeKeeperStateV4 {
     pub peers: PersistedPeers,
 }
 
-pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersistentState> {
-    // migrate to storing full term history
-    if version == 1 {
-        info!("reading safekeeper control file version {}", version);
-        let oldstate = SafeKeeperStateV1::des(&buf[..buf.len()])?;
-        let ac = AcceptorState {
-            term: oldstate.acceptor_state.term,
-            term_history: TermHistory(vec![TermLsn {
-                term: oldstate.acceptor_state.epoch,
-                lsn: Lsn(0),
-            }]),
-        };
-        return Ok(TimelinePersistentState {
-            tenant_id: oldstate.server.tenant_id,
-            timeline_id: oldstate.server.timeline_id,
-            acceptor_state: ac,
-            server: ServerInfo {
-                pg_version: oldstate.server.pg_version,
-                system_id: oldstate.server.system_id,
-                wal_seg_size: oldstate.server.wal_seg_size,
-            },
-            proposer_uuid: oldstate.proposer_uuid,
-            timeline_start_lsn: Lsn(0),
-            local_start_lsn: Lsn(0),
-            commit_lsn: oldstate.commit_lsn,
-            backup_lsn: Lsn(0),
-            peer_horizon_lsn: oldstate.truncate_lsn,
-            remote_consistent_lsn: Lsn(0),
-            peers: PersistedPeers(vec![]),
-        });
-    // migrate to hexing some ids
-    } else if version == 2 {
-        info!("reading safekeeper control file version {}", version);
-        let oldstate = SafeKeeperStateV2::des(&buf[..buf.len()])?;
-        let server = ServerInfo {
-            pg_version: oldstate.server.pg_version,
-            system_id: oldstate.server.system_id,
-            wal_seg_size: oldstate.server.wal_seg_size,
-        };
-        return Ok(TimelinePersistentState {
-            tenant_id: oldstate.server.tenant_id,
-            timeline_id: oldstate.server.timeline_id,
-            acceptor_state: oldstate.acceptor_state,
-            server,
-            proposer_uuid: oldstate.proposer_uuid,
-            timeline_start_lsn: Lsn(0),
-            local_start_lsn: Lsn(0),
-            commit_lsn: oldstate.commit_lsn,
-            backup_lsn: Lsn(0),
-            peer_horizon_lsn: oldstate.truncate_lsn,
-            remote_consistent_lsn: Lsn(0),
-            peers: PersistedPeers(vec![]),
-        });
-    // migrate to moving tenant_id/timeline_id to the top and adding some lsns
-    } else if version == 3 {
-        info!("reading safekeeper control file version {version}");
-        let oldstate = SafeKeeperStateV3::des(&buf[..buf.len()])?;
-        let server = ServerInfo {
-            pg_version: oldstate.server.pg_version,
-            system_id: oldstate.server.system_id,
-            wal_seg_size: oldstate.server.wal_seg_size,
-        };
-        return Ok(TimelinePersistentState {
-            tenant_id: oldstate.server.tenant_id,
-            timeline_id: oldstate.server.timeline_id,
-            acceptor_state: oldstate.acceptor_state,
-            server,
-            proposer_uuid: oldstate.proposer_uuid,
-            timeline_start_lsn: Lsn(0),
-            local_start_lsn: Lsn(0),
-            commit_lsn: oldstate.commit_lsn,
-            backup_lsn: Lsn(0),
-            peer_horizon_lsn: oldstate.truncate_lsn,
-            remote_consistent_lsn: Lsn(0),
-            peers: PersistedPeers(vec![]),
-        });
-    // migrate to having timeline_start_lsn
-    } else if version == 4 {
-        info!("reading safekeeper control file version {}", version);
-        let oldstate = SafeKeeperStateV4::des(&buf[..buf.len()])?;
-        let server = ServerInfo {
-            pg_version: oldstate.server.pg_version,
-            system_id: oldstate.server.system_id,
-            wal_seg_size: oldstate.server.wal_seg_size,
-        };
-        return Ok(TimelinePersistentState {
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SafeKeeperStateV7 {
+    #[serde(with = "hex")]
+    pub tenant_id: TenantId,
+    #[serde(with = "hex")]
+    pub timeline_id: TimelineId,
+    /// persistent acceptor state
+    pub acceptor_state: AcceptorState,
+    /// information about server
+    pub server: ServerInfo,
+    /// Unique id of the last *elected* proposer we dealt with. Not needed
+    /// for correctness, exists for monitoring purposes.
+    #[serde(with = "hex")]
+    pub proposer_uuid: PgUuid,
+    /// Since which LSN this timeline generally starts. Safekeeper might have
+    /// joined later.
+    pub timeline_start_lsn: Lsn,
+    /// Since which LSN safekeeper has (had) WAL for this timeline.
+    /// All WAL segments next to one containing local_start_lsn are
+    /// filled with data from the beginning.
+    pub local_start_lsn: Lsn,
+    /// Part of WAL acknowledged by quorum *and available locally*. Always points
+    /// to record boundary.
+    pub commit_lsn: Lsn,
+    /// LSN that points to the end of the last backed up segment. Useful to
+    /// persist to avoid finding out offloading progress on boot.
+    pub backup_lsn: Lsn,
+    /// Minimal LSN which may be needed for recovery of some safekeeper (end_lsn
+    /// of last record streamed to everyone). Persisting it helps skipping
+    /// recovery in walproposer, generally we compute it from peers. In
+    /// walproposer proto called 'truncate_lsn'. Updates are currently drived
+    /// only by walproposer.
+    pub peer_horizon_lsn: Lsn,
+    /// LSN of the oldest known checkpoint made by pageserver and successfully
+    /// pushed to s3. We don't remove WAL beyond it. Persisted only for
+    /// informational purposes, we receive it from pageserver (or broker).
+    pub remote_consistent_lsn: Lsn,
+    // Peers and their state as we remember it. Knowing peers themselves is
+    // fundamental; but state is saved here only for informational purposes and
+    // obviously can be stale. (Currently not saved at all, but let's provision
+    // place to have less file version upgrades).
+    pub peers: PersistedPeers,
+}
+
+impl From<SafeKeeperStateV7> for TimelinePersistentState {
+    fn from(oldstate: SafeKeeperStateV7) -> Self {
+        TimelinePersistentState {
             tenant_id: oldstate.tenant_id,
             timeline_id: oldstate.timeline_id,
             acceptor_state: oldstate.acceptor_state,
-            server,
+            server: oldstate.server,
             proposer_uuid: oldstate.proposer_uuid,
-            timeline_start_lsn: Lsn(0),
-            local_start_lsn: Lsn(0),
+            timeline_start_lsn: oldstate.timeline_start_lsn,
+            local_start_lsn: oldstate.local_start_lsn,
             commit_lsn: oldstate.commit_lsn,
-            backup_lsn: Lsn::INVALID,
+            backup_lsn: oldstate.backup_lsn,
             peer_horizon_lsn: oldstate.peer_horizon_lsn,
-            remote_consistent_lsn: Lsn(0),
-            peers: PersistedPeers(vec![]),
-        });
-    } else if version == 5 {
-        info!("reading safekeeper control file version {}", version);
-        let mut oldstate = TimelinePersistentState::des(&buf[..buf.len()])?;
-        if oldstate.timeline_start_lsn != Lsn(0) {
-            return Ok(oldstate);
+            remote_consistent_lsn: oldstate.remote_consistent_lsn,
+            peers: oldstate.peers,
+            mconf: Configuration::empty(),
         }
+    }
+}
+
+// Each historical format is upgraded to SafeKeeperStateV7, the layout shared
+// by versions 5-7, and from there to the current format. To add a new format
+// version, freeze the current TimelinePersistentState as SafeKeeperStateV<N>,
+// add upgrade_v<N>_to_v<N+1> and downgrade_v<N+1>_to_v<N>, and fixture
+// test_data/control_file/v<N+1>.control.
 
+/// Deserialize control file body of given `version` and upgrade it to the
+/// current format.
+pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersistentState> {
+    info!("reading safekeeper control file version {}", version);
+    let state_v7 = match version {
+        1 => upgrade_v1_to_v7(SafeKeeperStateV1::des(buf)?),
+        2 => upgrade_v2_to_v7(SafeKeeperStateV2::des(buf)?),
+        3 => upgrade_v3_to_v7(SafeKeeperStateV3::des(buf)?),
+        4 => upgrade_v4_to_v7(SafeKeeperStateV4::des(buf)?),
+        5 => upgrade_v5_to_v7(SafeKeeperStateV7::des(buf)?),
+        6 => upgrade_v6_to_v7(SafeKeeperStateV7::des(buf)?),
+        7 => SafeKeeperStateV7::des(buf)?,
+        _ => bail!("unsupported safekeeper control file version {}", version),
+    };
+    Ok(upgrade_v7_to_v8(state_v7))
+}
+
+/// Serialize the state into control file body of given `version`, which must
+/// be either the current or the previous one. Used to roll back the
+/// safekeeper binary; fails if the state can't be represented in the older
+/// format without losing anything important.
+pub fn downgrade_control_file(state: &TimelinePersistentState, version: u32) -> Result<Vec<u8>> {
+    match version {
+        SK_FORMAT_VERSION => Ok(state.ser()?),
+        7 => Ok(downgrade_v8_to_v7(state)?.ser()?),
+        _ => bail!(
+            "downgrade of safekeeper control file to version {} is not supported",
+            version
+        ),
+    }
+}
+
+// migrate to storing full term history
+fn upgrade_v1_to_v7(oldstate: SafeKeeperStateV1) -> SafeKeeperStateV7 {
+    let ac = AcceptorState {
+        term: oldstate.acceptor_state.term,
+        term_history: TermHistory(vec![TermLsn {
+            term: oldstate.acceptor_state.epoch,
+            lsn: Lsn(0),
+        }]),
+    };
+    upgrade_v5_to_v7(SafeKeeperStateV7 {
+        tenant_id: oldstate.server.tenant_id,
+        timeline_id: oldstate.server.timeline_id,
+        acceptor_state: ac,
+        server: ServerInfo {
+            pg_version: oldstate.server.pg_version,
+            system_id: oldstate.server.system_id,
+            wal_seg_size: oldstate.server.wal_seg_size,
+        },
+        proposer_uuid: oldstate.proposer_uuid,
+        timeline_start_lsn: Lsn(0),
+        local_start_lsn: Lsn(0),
+        commit_lsn: oldstate.commit_lsn,
+        backup_lsn: Lsn(0),
+        peer_horizon_lsn: oldstate.truncate_lsn,
+        remote_consistent_lsn: Lsn(0),
+        peers: PersistedPeers(vec![]),
+    })
+}
+
+// migrate to hexing some ids
+fn upgrade_v2_to_v7(oldstate: SafeKeeperStateV2) -> SafeKeeperStateV7 {
+    let server = ServerInfo {
+        pg_version: oldstate.server.pg_version,
+        system_id: oldstate.server.system_id,
+        wal_seg_size: oldstate.server.wal_seg_size,
+    };
+    upgrade_v5_to_v7(SafeKeeperStateV7 {
+        tenant_id: oldstate.server.tenant_id,
+        timeline_id: oldstate.server.timeline_id,
+        acceptor_state: oldstate.acceptor_state,
+        server,
+        proposer_uuid: oldstate.proposer_uuid,
+        timeline_start_lsn: Lsn(0),
+        local_start_lsn: Lsn(0),
+        commit_lsn: oldstate.commit_lsn,
+        backup_lsn: Lsn(0),
+        peer_horizon_lsn: oldstate.truncate_lsn,
+        remote_consistent_lsn: Lsn(0),
+        peers: PersistedPeers(vec![]),
+    })
+}
+
+// migrate to moving tenant_id/timeline_id to the top and adding some lsns
+fn upgrade_v3_to_v7(oldstate: SafeKeeperStateV3) -> SafeKeeperStateV7 {
+    let server = ServerInfo {
+        pg_version: oldstate.server.pg_version,
+        system_id: oldstate.server.system_id,
+        wal_seg_size: oldstate.server.wal_seg_size,
+    };
+    upgrade_v5_to_v7(SafeKeeperStateV7 {
+        tenant_id: oldstate.server.tenant_id,
+        timeline_id: oldstate.server.timeline_id,
+        acceptor_state: oldstate.acceptor_state,
+        server,
+        proposer_uuid: oldstate.proposer_uuid,
+        timeline_start_lsn: Lsn(0),
+        local_start_lsn: Lsn(0),
+        commit_lsn: oldstate.commit_lsn,
+        backup_lsn: Lsn(0),
+        peer_horizon_lsn: oldstate.truncate_lsn,
+        remote_consistent_lsn: Lsn(0),
+        peers: PersistedPeers(vec![]),
+    })
+}
+
+// migrate to having timeline_start_lsn
+fn upgrade_v4_to_v7(oldstate: SafeKeeperStateV4) -> SafeKeeperStateV7 {
+    let server = ServerInfo {
+        pg_version: oldstate.server.pg_version,
+        system_id: oldstate.server.system_id,
+        wal_seg_size: oldstate.server.wal_seg_size,
+    };
+    upgrade_v5_to_v7(SafeKeeperStateV7 {
+        tenant_id: oldstate.tenant_id,
+        timeline_id: oldstate.timeline_id,
+        acceptor_state: oldstate.acceptor_state,
+        server,
+        proposer_uuid: oldstate.proposer_uuid,
+        timeline_start_lsn: Lsn(0),
+        local_start_lsn: Lsn(0),
+        commit_lsn: oldstate.commit_lsn,
+        backup_lsn: Lsn::INVALID,
+        peer_horizon_lsn: oldstate.peer_horizon_lsn,
+        remote_consistent_lsn: Lsn(0),
+        peers: PersistedPeers(vec![]),
+    })
+}
+
+// fill in timeline_start_lsn
+fn upgrade_v5_to_v7(mut oldstate: SafeKeeperStateV7) -> SafeKeeperStateV7 {
+    if oldstate.timeline_start_lsn == Lsn(0) {
         // set special timeline_start_lsn because we don't know the real one
         info!("setting timeline_start_lsn and local_start_lsn to Lsn(1)");
         oldstate.timeline_start_lsn = Lsn(1);
         oldstate.local_start_lsn = Lsn(1);
+    }
+    upgrade_v6_to_v7(oldstate)
+}
 
-        return Ok(oldstate);
-    } else if version == 6 {
-        info!("reading safekeeper control file version {}", version);
-        let mut oldstate = TimelinePersistentState::des(&buf[..buf.len()])?;
-        if oldstate.server.pg_version != 0 {
-            return Ok(oldstate);
-        }
-
+// fill in pg_version
+fn upgrade_v6_to_v7(mut oldstate: SafeKeeperStateV7) -> SafeKeeperStateV7 {
+    if oldstate.server.pg_version == 0 {
         // set pg_version to the default v14
         info!("setting pg_version to 140005");
         oldstate.server.pg_version = 140005;
+    }
+    oldstate
+}
+
+// migrate to having membership configuration
+fn upgrade_v7_to_v8(oldstate: SafeKeeperStateV7) -> TimelinePersistentState {
+    oldstate.into()
+}
 
-        return Ok(oldstate);
+fn downgrade_v8_to_v7(state: &TimelinePersistentState) -> Result<SafeKeeperStateV7> {
+    // Older safekeepers don't know about generations and would accept
+    // proposers of any configuration, so dropping it is unsafe.
+    if state.mconf.generation != INVALID_GENERATION {
+        bail!(
+            "timeline has membership configuration {}, can't downgrade control file",
+            state.mconf
+        );
     }
-    bail!("unsupported safekeeper control file version {}", version)
+    Ok(SafeKeeperStateV7 {
+        tenant_id: state.tenant_id,
+        timeline_id: state.timeline_id,
+        acceptor_state: state.acceptor_state.clone(),
+        server: state.server.clone(),
+        proposer_uuid: state.proposer_uuid,
+        timeline_start_lsn: state.timeline_start_lsn,
+        local_start_lsn: state.local_start_lsn,
+        commit_lsn: state.commit_lsn,
+        backup_lsn: state.backup_lsn,
+        peer_horizon_lsn: state.peer_horizon_lsn,
+        remote_consistent_lsn: state.remote_consistent_lsn,
+        peers: state.peers.clone(),
+    })
 }
 
 #[cfg(test)]
@@ -24,6 +24,18 @@ use crate::{
 // we don't want to have more than 10 segments on disk after copy, because they take space
 const MAX_BACKUP_LAG: u64 = 10 * WAL_SEGMENT_SIZE as u64;
 
+/// Errors caused by the request itself rather than by the safekeeper, so
+/// that they can be reported to the caller as such.
+#[derive(Debug, thiserror::Error)]
+pub enum CopyTimelineError {
+    #[error("invalid until_lsn {0}: {1}")]
+    InvalidLsn(Lsn, &'static str),
+    #[error("source timeline is not initialized")]
+    SourceNotInitialized,
+    #[error("too many segments are not backed up, backup_lsn={0}")]
+    BackupLag(Lsn),
+}
+
 pub struct Request {
     pub source: Arc<Timeline>,
     pub until_lsn: Lsn,
@@ -54,7 +66,7 @@ pub async fn handle_request(request: Request) -> Result<()> {
     let (mem_state, state) = request.source.get_state().await;
     let start_lsn = state.timeline_start_lsn;
     if start_lsn == Lsn::INVALID {
-        bail!("timeline is not initialized");
+        return Err(CopyTimelineError::SourceNotInitialized.into());
     }
     let backup_lsn = mem_state.backup_lsn;
 
@@ -72,10 +84,18 @@ pub async fn handle_request(request: Request) -> Result<()> {
         assert!(flush_lsn >= start_lsn);
 
         if request.until_lsn > flush_lsn {
-            bail!("requested LSN is beyond the end of the timeline");
+            return Err(CopyTimelineError::InvalidLsn(
+                request.until_lsn,
+                "beyond the end of the timeline",
+            )
+            .into());
         }
         if request.until_lsn < start_lsn {
-            bail!("requested LSN is before the start of the timeline");
+            return Err(CopyTimelineError::InvalidLsn(
+                request.until_lsn,
+                "before the start of the timeline",
+            )
+            .into());
         }
 
         if request.until_lsn > commit_lsn {
@@ -85,7 +105,7 @@ pub async fn handle_request(request: Request) -> Result<()> {
         if backup_lsn < request.until_lsn && request.until_lsn.0 - backup_lsn.0 > MAX_BACKUP_LAG {
             // we have a lot of segments that are not backed up. we can try to wait here until
             // segments will be backed up to remote storage, but it's not clear how long to wait
-            bail!("too many segments are not backed up");
+            return Err(CopyTimelineError::BackupLag(backup_lsn).into());
         }
     }
 
@@ -22,10 +22,12 @@ use utils::id::TenantTimelineId;
 use utils::id::{TenantId, TimelineId};
 use utils::lsn::Lsn;
 
+use crate::receive_wal::WalReceiverState;
 use crate::safekeeper::TermHistory;
 use crate::send_wal::WalSenderState;
 use crate::state::TimelineMemState;
 use crate::state::TimelinePersistentState;
+use crate::timeline::PeerInfo;
 use crate::wal_storage::WalReader;
 use crate::GlobalTimelines;
 use crate::SafeKeeperConf;
@@ -136,6 +138,11 @@ pub struct Config {
     pub no_sync: bool,
     pub max_offloader_lag_bytes: u64,
     pub wal_backup_enabled: bool,
+    pub wal_backup_compression: bool,
+    pub remote_wal_cache_size: u64,
+    pub wal_disk_usage_threshold_pct: Option<u8>,
+    pub validate_wal: bool,
+    pub static_peers: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -151,7 +158,9 @@ pub struct Timeline {
 pub struct Memory {
     pub is_cancelled: bool,
     pub peers_info_len: usize,
+    pub peers: Vec<PeerInfo>,
     pub walsenders: Vec<WalSenderState>,
+    pub walreceivers: Vec<WalReceiverState>,
     pub wal_backup_active: bool,
     pub active: bool,
     pub num_computes: u32,
@@ -301,6 +310,11 @@ fn build_config(config: SafeKeeperConf) -> Config {
         no_sync: config.no_sync,
         max_offloader_lag_bytes: config.max_offloader_lag_bytes,
         wal_backup_enabled: config.wal_backup_enabled,
+        wal_backup_compression: config.wal_backup_compression,
+        remote_wal_cache_size: config.remote_wal_cache_size,
+        wal_disk_usage_threshold_pct: config.wal_disk_usage_threshold_pct,
+        validate_wal: config.validate_wal,
+        static_peers: config.static_peers,
     }
 }
 
@@ -8,7 +8,7 @@ use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::{debug, info, info_span, Instrument};
 
-use crate::auth::check_permission;
+use crate::auth::{check_permission, check_scope};
 use crate::json_ctrl::{handle_json_ctrl, AppendLogicalMessage};
 
 use crate::metrics::{TrafficMetrics, PG_QUERIES_GAUGE};
@@ -170,19 +170,8 @@ impl<IO: AsyncRead + AsyncWrite + Unpin + Send> postgres_backend::Handler<IO>
             .map_err(|e| QueryError::Unauthorized(e.0))?;
 
         // The handler might be configured to allow only tenant scope tokens.
-        if matches!(allowed_auth_scope, Scope::Tenant)
-            && !matches!(data.claims.scope, Scope::Tenant)
-        {
-            return Err(QueryError::Unauthorized(
-                "passed JWT token is for full access, but only tenant scope is allowed".into(),
-            ));
-        }
-
-        if matches!(data.claims.scope, Scope::Tenant) && data.claims.tenant_id.is_none() {
-            return Err(QueryError::Unauthorized(
-                "jwt token scope is Tenant, but tenant id is missing".into(),
-            ));
-        }
+        check_scope(&data.claims, *allowed_auth_scope)
+            .map_err(|e| QueryError::Unauthorized(e.0))?;
 
         debug!(
             "jwt scope check succeeded for scope: {:#?} by tenant id: {:?}",
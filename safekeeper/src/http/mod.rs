@@ -2,14 +2,19 @@ pub mod routes;
 pub use routes::make_router;
 
 pub use safekeeper_api::models;
+use utils::auth::Scope;
 
 use crate::SafeKeeperConf;
 
+/// Serve http API. allowed_auth_scope is either SafekeeperData (wide JWT
+/// tokens giving access to all data are allowed) or Tenant (only tokens
+/// restricted to specific tenant are allowed).
 pub async fn task_main(
     conf: SafeKeeperConf,
     http_listener: std::net::TcpListener,
+    allowed_auth_scope: Scope,
 ) -> anyhow::Result<()> {
-    let router = make_router(conf)
+    let router = make_router(conf, allowed_auth_scope)
         .build()
         .map_err(|err| anyhow::anyhow!(err))?;
     let service = utils::http::RouterService::new(router).unwrap();
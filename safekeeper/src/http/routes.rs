@@ -2,7 +2,7 @@ use hyper::{Body, Request, Response, StatusCode, Uri};
 
 use once_cell::sync::Lazy;
 use postgres_ffi::WAL_SEGMENT_SIZE;
-use safekeeper_api::models::{SkTimelineInfo, TimelineCopyRequest};
+use safekeeper_api::models::{BrokerTimelineInfo, SkTimelineInfo, TimelineCopyRequest};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
@@ -22,19 +22,22 @@ use tokio_stream::wrappers::ReceiverStream;
 use tracing::{info_span, Instrument};
 use utils::http::endpoint::{request_span, ChannelWriter};
 
+use crate::copy_timeline::CopyTimelineError;
 use crate::debug_dump::TimelineDigestRequest;
+use crate::membership::Configuration;
+use crate::metrics::{collect_timeline_metrics, TimelineMetricsSummary};
 use crate::receive_wal::WalReceiverState;
 use crate::safekeeper::Term;
 use crate::safekeeper::{ServerInfo, TermLsn};
 use crate::send_wal::WalSenderState;
-use crate::timeline::PeerInfo;
-use crate::{copy_timeline, debug_dump, patch_control_file, pull_timeline};
+use crate::timeline::{PeerInfo, TimelineError};
+use crate::{copy_timeline, debug_dump, patch_control_file, pull_timeline, replicate_timeline};
 
 use crate::timelines_global_map::TimelineDeleteForceResult;
 use crate::GlobalTimelines;
 use crate::SafeKeeperConf;
 use utils::{
-    auth::SwappableJwtAuth,
+    auth::{Scope, SwappableJwtAuth},
     http::{
         endpoint::{self, auth_middleware, check_permission_with},
         error::ApiError,
@@ -61,6 +64,18 @@ async fn status_handler(request: Request<Body>) -> Result<Response<Body>, ApiErr
     json_response(StatusCode::OK, status)
 }
 
+/// Lag summaries of all active timelines, a cheaper alternative to scraping
+/// per-timeline prometheus metrics.
+async fn timelines_metrics_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let summaries: Vec<TimelineMetricsSummary> = collect_timeline_metrics()
+        .await
+        .iter()
+        .map(TimelineMetricsSummary::from)
+        .collect();
+    json_response(StatusCode::OK, summaries)
+}
+
 fn get_conf(request: &Request<Body>) -> &SafeKeeperConf {
     request
         .data::<Arc<SafeKeeperConf>>()
@@ -93,6 +108,19 @@ pub struct AcceptorStateStatus {
     pub term_history: Vec<TermSwitchApiEntry>,
 }
 
+/// State of a peer as learnt from the broker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerStatus {
+    #[serde(flatten)]
+    pub info: PeerInfo,
+    /// Time since the last message from the peer. Peers not heard from within
+    /// heartbeat_timeout are not regarded alive.
+    #[serde(default)]
+    pub last_heard_ms_ago: u64,
+    #[serde(default)]
+    pub alive: bool,
+}
+
 /// Info about timeline on safekeeper ready for reporting.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TimelineStatus {
@@ -107,13 +135,22 @@ pub struct TimelineStatus {
     pub backup_lsn: Lsn,
     pub peer_horizon_lsn: Lsn,
     pub remote_consistent_lsn: Lsn,
-    pub peers: Vec<PeerInfo>,
+    // Older safekeepers don't report it.
+    #[serde(default = "Configuration::empty")]
+    pub mconf: Configuration,
+    pub peers: Vec<PeerStatus>,
+    /// Size of WAL segments on disk. Older safekeepers don't report it.
+    #[serde(default)]
+    pub wal_disk_usage_bytes: u64,
     pub walsenders: Vec<WalSenderState>,
     pub walreceivers: Vec<WalReceiverState>,
 }
 
 fn check_permission(request: &Request<Body>, tenant_id: Option<TenantId>) -> Result<(), ApiError> {
+    // Scope is always provided as data, see make_router.
+    let allowed_auth_scope = *request.data::<Scope>().expect("unknown state type");
     check_permission_with(request, |claims| {
+        crate::auth::check_scope(claims, allowed_auth_scope)?;
         crate::auth::check_permission(claims, tenant_id)
     })
 }
@@ -148,6 +185,24 @@ async fn timeline_status_handler(request: Request<Body>) -> Result<Response<Body
     };
 
     let conf = get_conf(&request);
+    let peers = tli
+        .get_all_peers()
+        .await
+        .into_iter()
+        .map(|info| {
+            let last_heard = info.last_heard();
+            PeerStatus {
+                info,
+                last_heard_ms_ago: last_heard.as_millis() as u64,
+                alive: last_heard <= conf.heartbeat_timeout,
+            }
+        })
+        .collect();
+    let wal_disk_usage_bytes = tli
+        .wal_disk_usage()
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
     // Note: we report in memory values which can be lost.
     let status = TimelineStatus {
         tenant_id: ttid.tenant_id,
@@ -161,7 +216,9 @@ async fn timeline_status_handler(request: Request<Body>) -> Result<Response<Body
         backup_lsn: inmem.backup_lsn,
         peer_horizon_lsn: inmem.peer_horizon_lsn,
         remote_consistent_lsn: inmem.remote_consistent_lsn,
-        peers: tli.get_peers(conf).await,
+        mconf: state.mconf,
+        peers,
+        wal_disk_usage_bytes,
         walsenders: tli.get_walsenders().get_all(),
         walreceivers: tli.get_walreceivers().get_all(),
     };
@@ -189,7 +246,10 @@ async fn timeline_create_handler(mut request: Request<Body>) -> Result<Response<
     });
     GlobalTimelines::create(ttid, server_info, request_data.commit_lsn, local_start_lsn)
         .await
-        .map_err(ApiError::InternalServerError)?;
+        .map_err(|e| match e.downcast::<TimelineError>() {
+            Ok(te) => ApiError::from(te),
+            Err(e) => ApiError::InternalServerError(e),
+        })?;
 
     json_response(StatusCode::OK, ())
 }
@@ -206,6 +266,54 @@ async fn timeline_pull_handler(mut request: Request<Body>) -> Result<Response<Bo
     json_response(StatusCode::OK, resp)
 }
 
+/// Start replicating timeline from another safekeeper in background.
+async fn timeline_replicate_start_handler(
+    mut request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    let data: replicate_timeline::Request = json_request(&mut request).await?;
+    let conf = get_conf(&request).clone();
+
+    let status = replicate_timeline::start(ttid, data, conf)
+        .map_err(|e| ApiError::Conflict(format!("{e:#}")))?;
+    json_response(StatusCode::OK, status)
+}
+
+async fn timeline_replicate_status_handler(
+    request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    let status = replicate_timeline::get_status(&ttid).ok_or_else(|| {
+        ApiError::NotFound(anyhow::anyhow!("timeline {} is not replicated", ttid).into())
+    })?;
+    json_response(StatusCode::OK, status)
+}
+
+async fn timeline_replicate_stop_handler(
+    request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    let status = replicate_timeline::stop(&ttid).ok_or_else(|| {
+        ApiError::NotFound(anyhow::anyhow!("timeline {} is not replicated", ttid).into())
+    })?;
+    json_response(StatusCode::OK, status)
+}
+
 async fn timeline_copy_handler(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
     check_permission(&request, None)?;
 
@@ -224,7 +332,15 @@ async fn timeline_copy_handler(mut request: Request<Body>) -> Result<Response<Bo
     })
         .instrument(info_span!("copy_timeline", from=%ttid, to=%request_data.target_timeline_id, until_lsn=%request_data.until_lsn))
         .await
-        .map_err(ApiError::InternalServerError)?;
+        .map_err(|e| match e.downcast::<CopyTimelineError>() {
+            Ok(e @ CopyTimelineError::InvalidLsn(..)) => ApiError::BadRequest(e.into()),
+            Ok(e @ CopyTimelineError::SourceNotInitialized) => ApiError::Conflict(e.to_string()),
+            // retryable once backup catches up
+            Ok(e @ CopyTimelineError::BackupLag(_)) => {
+                ApiError::ResourceUnavailable(e.to_string().into())
+            }
+            Err(e) => ApiError::InternalServerError(e),
+        })?;
 
     json_response(StatusCode::OK, ())
 }
@@ -325,6 +441,47 @@ async fn tenant_delete_handler(mut request: Request<Body>) -> Result<Response<Bo
     )
 }
 
+/// Info about all active timelines, which is otherwise pushed to the broker.
+/// Polled by peers configured with static peers list.
+async fn broker_info_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let include_inactive = parse_query_param(&request, "include_inactive")?.unwrap_or(false);
+    let conf = get_conf(&request);
+
+    let mut infos = Vec::new();
+    for tli in GlobalTimelines::get_all() {
+        if !include_inactive && !tli.is_active().await {
+            continue;
+        }
+        infos.push(to_broker_timeline_info(
+            tli.get_safekeeper_info(conf).await,
+        )?);
+    }
+    json_response(StatusCode::OK, infos)
+}
+
+/// Timeline info which is otherwise pushed to the broker, polled by
+/// pageservers configured with static safekeepers list.
+async fn timeline_broker_info_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    check_permission(&request, Some(ttid.tenant_id))?;
+
+    let tli = GlobalTimelines::get(ttid).map_err(ApiError::from)?;
+    let info = to_broker_timeline_info(tli.get_safekeeper_info(get_conf(&request)).await)?;
+    json_response(StatusCode::OK, info)
+}
+
+fn to_broker_timeline_info(
+    sk_info: SafekeeperTimelineInfo,
+) -> Result<BrokerTimelineInfo, ApiError> {
+    sk_info
+        .try_into()
+        .map_err(|e| ApiError::InternalServerError(anyhow::anyhow!("{e}")))
+}
+
 /// Used only in tests to hand craft required data.
 async fn record_safekeeper_info(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
     let ttid = TenantTimelineId::new(
@@ -350,6 +507,7 @@ async fn record_safekeeper_info(mut request: Request<Body>) -> Result<Response<B
         backup_lsn: sk_info.backup_lsn.0,
         local_start_lsn: sk_info.local_start_lsn.0,
         availability_zone: None,
+        ps_last_received_lsn: sk_info.ps_last_received_lsn.0,
     };
 
     let tli = GlobalTimelines::get(ttid).map_err(ApiError::from)?;
@@ -465,6 +623,29 @@ async fn dump_debug_handler(mut request: Request<Body>) -> Result<Response<Body>
     Ok(response)
 }
 
+/// Switch timeline to the given membership configuration.
+async fn timeline_membership_switch_handler(
+    mut request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+
+    let tli = GlobalTimelines::get(ttid).map_err(ApiError::from)?;
+
+    let to: Configuration = json_request(&mut request).await?;
+    to.validate().map_err(ApiError::BadRequest)?;
+    let response = tli
+        .membership_switch(to)
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::OK, response)
+}
+
 async fn patch_control_file_handler(
     mut request: Request<Body>,
 ) -> Result<Response<Body>, ApiError> {
@@ -485,10 +666,18 @@ async fn patch_control_file_handler(
     json_response(StatusCode::OK, response)
 }
 
-/// Safekeeper http router.
-pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError> {
+/// Safekeeper http router. With Tenant allowed_auth_scope only tenant scoped
+/// tokens are accepted, validated by http_tenant_only_auth key.
+pub fn make_router(
+    conf: SafeKeeperConf,
+    allowed_auth_scope: Scope,
+) -> RouterBuilder<hyper::Body, ApiError> {
+    let auth = match allowed_auth_scope {
+        Scope::Tenant => conf.http_tenant_only_auth.clone(),
+        _ => conf.http_auth.clone(),
+    };
     let mut router = endpoint::make_router();
-    if conf.http_auth.is_some() {
+    if auth.is_some() {
         router = router.middleware(auth_middleware(|request| {
             #[allow(clippy::mutable_key_type)]
             static ALLOWLIST_ROUTES: Lazy<HashSet<Uri>> = Lazy::new(|| {
@@ -511,11 +700,14 @@ pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError>
 
     // NB: on any changes do not forget to update the OpenAPI spec
     // located nearby (/safekeeper/src/http/openapi_spec.yaml).
-    let auth = conf.http_auth.clone();
     router
         .data(Arc::new(conf))
         .data(auth)
+        .data(allowed_auth_scope)
         .get("/v1/status", |r| request_span(r, status_handler))
+        .get("/v1/metrics/timelines", |r| {
+            request_span(r, timelines_metrics_handler)
+        })
         .put("/v1/failpoints", |r| {
             request_span(r, move |r| async {
                 let cancel = CancellationToken::new();
@@ -529,6 +721,11 @@ pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError>
         .get("/v1/tenant/:tenant_id/timeline/:timeline_id", |r| {
             request_span(r, timeline_status_handler)
         })
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/broker_info",
+            |r| request_span(r, timeline_broker_info_handler),
+        )
+        .get("/v1/broker_info", |r| request_span(r, broker_info_handler))
         .delete("/v1/tenant/:tenant_id/timeline/:timeline_id", |r| {
             request_span(r, timeline_delete_handler)
         })
@@ -538,6 +735,18 @@ pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError>
         .post("/v1/pull_timeline", |r| {
             request_span(r, timeline_pull_handler)
         })
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/replicate",
+            |r| request_span(r, timeline_replicate_start_handler),
+        )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/replicate",
+            |r| request_span(r, timeline_replicate_status_handler),
+        )
+        .delete(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/replicate",
+            |r| request_span(r, timeline_replicate_stop_handler),
+        )
         .get(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/file/:filename",
             |r| request_span(r, timeline_files_handler),
@@ -550,6 +759,10 @@ pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError>
             "/v1/tenant/:tenant_id/timeline/:timeline_id/control_file",
             |r| request_span(r, patch_control_file_handler),
         )
+        .put(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/membership",
+            |r| request_span(r, timeline_membership_switch_handler),
+        )
         // for tests
         .post("/v1/record_safekeeper_info/:tenant_id/:timeline_id", |r| {
             request_span(r, record_safekeeper_info)
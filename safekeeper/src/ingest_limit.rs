@@ -0,0 +1,196 @@
+//! Per-tenant limit of the rate at which WAL is accepted from computes.
+//!
+//! Each tenant gets a token bucket refilled at `tenant_wal_ingest_rate` bytes
+//! per second and holding up to `tenant_wal_ingest_burst` bytes, shared by
+//! all its timelines on this safekeeper. Once it is empty, WalAcceptor
+//! flushes and acknowledges WAL written so far and then waits before taking
+//! the next AppendRequest. The walproposer sees flush_lsn on this safekeeper
+//! stall while the TCP connection pushes back, so one tenant's bulk load
+//! can't saturate the disk and delay commits of everyone else.
+//!
+//! Empty AppendRequests, which the walproposer sends as keepalives, are never
+//! throttled, and a single wait is capped well below the walproposer
+//! connection timeout, so that the compute doesn't reconnect in a loop at low
+//! rates.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use postgres_ffi::MAX_SEND_SIZE;
+use tokio::time::{Duration, Instant};
+use utils::id::TenantId;
+
+use crate::metrics::{WAL_INGEST_THROTTLED, WAL_INGEST_THROTTLED_WAIT_USECS};
+use crate::SafeKeeperConf;
+
+/// Longest wait for the bucket to refill, after which the request is admitted
+/// anyway. The walproposer resets the connection if it hears nothing for
+/// neon.safekeeper_connection_timeout, 10s by default.
+pub const MAX_THROTTLE_WAIT: Duration = Duration::from_secs(1);
+
+/// Limiters of tenants having active compute connections.
+static LIMITERS: Lazy<Mutex<HashMap<TenantId, Weak<IngestLimiter>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub struct IngestLimiter {
+    /// Bytes per second.
+    rate: f64,
+    /// At least MAX_SEND_SIZE, so that any AppendRequest can pass.
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Can be negative: a request is admitted whenever the bucket is not
+    /// empty, so that requests larger than the remaining tokens still pass.
+    /// Never below -burst.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Get limiter of the tenant, None if ingest rate is not limited.
+pub fn get(conf: &SafeKeeperConf, tenant_id: TenantId) -> Option<Arc<IngestLimiter>> {
+    let rate = conf.tenant_wal_ingest_rate?;
+    let mut limiters = LIMITERS.lock();
+    if let Some(limiter) = limiters.get(&tenant_id).and_then(Weak::upgrade) {
+        return Some(limiter);
+    }
+    // forget tenants without connections
+    limiters.retain(|_, limiter| limiter.strong_count() > 0);
+    let limiter = Arc::new(IngestLimiter::new(rate, conf.tenant_wal_ingest_burst));
+    limiters.insert(tenant_id, Arc::downgrade(&limiter));
+    Some(limiter)
+}
+
+impl IngestLimiter {
+    fn new(rate: u64, burst: u64) -> Self {
+        let burst = burst.max(MAX_SEND_SIZE as u64) as f64;
+        IngestLimiter {
+            rate: rate.max(1) as f64,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `bytes` if the bucket is not empty, return false otherwise.
+    /// Empty requests always pass.
+    pub fn try_acquire(&self, bytes: usize) -> bool {
+        if bytes == 0 {
+            return true;
+        }
+        let mut bucket = self.bucket.lock();
+        self.refill(&mut bucket);
+        if bucket.tokens <= 0.0 {
+            return false;
+        }
+        self.take(&mut bucket, bytes);
+        true
+    }
+
+    /// Take `bytes`, waiting until the bucket is not empty, but no longer than
+    /// MAX_THROTTLE_WAIT. Empty requests always pass.
+    pub async fn acquire(&self, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        let deadline = Instant::now() + MAX_THROTTLE_WAIT;
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                self.refill(&mut bucket);
+                let now = Instant::now();
+                if bucket.tokens > 0.0 || now >= deadline {
+                    self.take(&mut bucket, bytes);
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate).min(deadline - now)
+            };
+            WAL_INGEST_THROTTLED.inc();
+            WAL_INGEST_THROTTLED_WAIT_USECS.inc_by(wait.as_micros() as u64);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn take(&self, bucket: &mut Bucket, bytes: usize) {
+        bucket.tokens = (bucket.tokens - bytes as f64).max(-self.burst);
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1 << 20;
+
+    fn tokens(limiter: &IngestLimiter) -> f64 {
+        let mut bucket = limiter.bucket.lock();
+        limiter.refill(&mut bucket);
+        bucket.tokens
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket() {
+        let limiter = IngestLimiter::new(MIB, 2 * MIB);
+        assert!(limiter.try_acquire(MIB as usize));
+        assert!(limiter.try_acquire(MIB as usize));
+        assert!(!limiter.try_acquire(1));
+
+        // refilled at the rate, up to the burst
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(tokens(&limiter), (MIB / 2) as f64);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(tokens(&limiter), (2 * MIB) as f64);
+
+        // a request larger than what's left passes, but the debt is bounded
+        assert!(limiter.try_acquire(100 * MIB as usize));
+        assert_eq!(tokens(&limiter), -2.0 * MIB as f64);
+        assert!(!limiter.try_acquire(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_burst() {
+        // any AppendRequest fits into the burst
+        let limiter = IngestLimiter::new(1, 0);
+        let started = Instant::now();
+        limiter.acquire(MAX_SEND_SIZE).await;
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_is_capped() {
+        let limiter = IngestLimiter::new(1, 0);
+        limiter.acquire(2 * MAX_SEND_SIZE).await;
+
+        // refilling at 1 byte per second would take days
+        let started = Instant::now();
+        limiter.acquire(MAX_SEND_SIZE).await;
+        assert_eq!(started.elapsed(), MAX_THROTTLE_WAIT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keepalives_under_throttling() {
+        let limiter = IngestLimiter::new(1, 0);
+        limiter.acquire(MAX_SEND_SIZE).await;
+        assert!(!limiter.try_acquire(1));
+
+        // empty AppendRequests pass without waiting and without taking tokens
+        let before = tokens(&limiter);
+        let started = Instant::now();
+        assert!(limiter.try_acquire(0));
+        limiter.acquire(0).await;
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert_eq!(tokens(&limiter), before);
+    }
+}
@@ -0,0 +1,189 @@
+//! Engines used by [`crate::wal_storage::PhysicalStorage`] to write and fsync
+//! WAL segments.
+//!
+//! `std-fs` goes through tokio::fs, i.e. every write and fsync is a blocking
+//! syscall executed on the blocking thread pool. `tokio-epoll-uring` submits
+//! them to io_uring instead, like pageserver's VirtualFile does. It is Linux
+//! only and opt-in; safekeeper falls back to `std-fs` if io_uring can't be set
+//! up on startup.
+//!
+//! Creation of segments (zero filling and renames) always uses tokio::fs, it
+//! is off the hot path.
+
+use std::io::{self, SeekFrom};
+#[cfg(target_os = "linux")]
+use std::os::fd::OwnedFd;
+
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::*;
+
+#[derive(
+    Copy, Clone, PartialEq, Eq, Hash, strum_macros::EnumString, strum_macros::Display, Debug,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum IoEngineKind {
+    StdFs,
+    #[cfg(target_os = "linux")]
+    TokioEpollUring,
+}
+
+impl IoEngineKind {
+    /// Check that the engine can be used on this host, returning `std-fs`
+    /// otherwise.
+    pub async fn probe(self) -> IoEngineKind {
+        match self {
+            IoEngineKind::StdFs => self,
+            #[cfg(target_os = "linux")]
+            IoEngineKind::TokioEpollUring => match tokio_epoll_uring::System::launch().await {
+                Ok(_system) => self,
+                Err(e) => {
+                    warn!("failed to set up io_uring, falling back to std-fs WAL io engine: {e:#}");
+                    IoEngineKind::StdFs
+                }
+            },
+        }
+    }
+}
+
+/// WAL segment file opened for writing.
+pub enum SegmentFile {
+    /// Position of the file is kept at the end of written data.
+    StdFs(File),
+    /// Descriptor is moved into io_uring operations while they run; it is
+    /// missing only if such operation was cancelled.
+    #[cfg(target_os = "linux")]
+    TokioEpollUring(Option<OwnedFd>),
+}
+
+impl SegmentFile {
+    /// Prepare `file` for writing at `pos` with given engine.
+    pub async fn new(engine: IoEngineKind, mut file: File, pos: u64) -> io::Result<SegmentFile> {
+        match engine {
+            IoEngineKind::StdFs => {
+                file.seek(SeekFrom::Start(pos)).await?;
+                Ok(SegmentFile::StdFs(file))
+            }
+            #[cfg(target_os = "linux")]
+            IoEngineKind::TokioEpollUring => {
+                let file = file.into_std().await;
+                Ok(SegmentFile::TokioEpollUring(Some(file.into())))
+            }
+        }
+    }
+
+    /// Write the whole `buf` at `pos`. For `std-fs`, `pos` must be the end of
+    /// previously written data.
+    pub async fn write_all_at(&mut self, buf: &[u8], pos: u64) -> io::Result<()> {
+        match self {
+            SegmentFile::StdFs(file) => {
+                file.write_all(buf).await?;
+                // Note: flush just ensures write above reaches the OS (this is
+                // not needed in case of sync IO as Write::write there calls
+                // directly write syscall, but needed in case of async). It does
+                // *not* fsyncs the file.
+                file.flush().await
+            }
+            #[cfg(target_os = "linux")]
+            SegmentFile::TokioEpollUring(fd_slot) => {
+                use tokio_epoll_uring::BoundedBuf;
+
+                let system = tokio_epoll_uring::thread_local_system().await;
+                let mut fd = fd_slot.take().ok_or_else(cancelled_op_error)?;
+                let mut buf = buf.to_vec().slice_full();
+                let mut offset = pos;
+                let mut res = Ok(());
+                while buf.bytes_total() != 0 {
+                    let ((returned_fd, returned_buf), write_res) =
+                        system.write(fd, offset, buf).await;
+                    fd = returned_fd;
+                    buf = returned_buf;
+                    match write_res.map_err(epoll_uring_error_to_std) {
+                        Ok(0) => {
+                            res = Err(io::Error::new(
+                                io::ErrorKind::WriteZero,
+                                "failed to write whole buffer",
+                            ));
+                            break;
+                        }
+                        Ok(n) => {
+                            buf = buf.slice(n..);
+                            offset += n as u64;
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => {
+                            res = Err(e);
+                            break;
+                        }
+                    }
+                }
+                *fd_slot = Some(fd);
+                res
+            }
+        }
+    }
+
+    /// fdatasync the file.
+    pub async fn sync_data(&mut self) -> io::Result<()> {
+        match self {
+            SegmentFile::StdFs(file) => file.sync_data().await,
+            #[cfg(target_os = "linux")]
+            SegmentFile::TokioEpollUring(fd_slot) => {
+                let system = tokio_epoll_uring::thread_local_system().await;
+                let fd = fd_slot.take().ok_or_else(cancelled_op_error)?;
+                let (fd, res) = system.fdatasync(fd).await;
+                *fd_slot = Some(fd);
+                res.map_err(epoll_uring_error_to_std)
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn cancelled_op_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "segment file lost in cancelled io_uring operation",
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn epoll_uring_error_to_std(e: tokio_epoll_uring::Error<io::Error>) -> io::Error {
+    match e {
+        tokio_epoll_uring::Error::Op(e) => e,
+        tokio_epoll_uring::Error::System(system) => io::Error::new(io::ErrorKind::Other, system),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_and_sync() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        for engine in [
+            IoEngineKind::StdFs,
+            #[cfg(target_os = "linux")]
+            IoEngineKind::TokioEpollUring,
+        ] {
+            let engine = engine.probe().await;
+            let path = dir.path().join(engine.to_string());
+            tokio::fs::write(&path, b"0123456789").await.unwrap();
+            let file = tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .await
+                .unwrap();
+
+            let mut file = SegmentFile::new(engine, file, 4).await.unwrap();
+            file.write_all_at(b"abc", 4).await.unwrap();
+            file.write_all_at(b"def", 7).await.unwrap();
+            file.sync_data().await.unwrap();
+            drop(file);
+
+            let content = tokio::fs::read(&path).await.unwrap();
+            assert_eq!(content, b"0123abcdef", "engine {engine}");
+        }
+    }
+}
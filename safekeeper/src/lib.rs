@@ -1,5 +1,6 @@
 #![deny(clippy::undocumented_unsafe_blocks)]
 use camino::Utf8PathBuf;
+use io_engine::IoEngineKind;
 use once_cell::sync::Lazy;
 use remote_storage::RemoteStorageConfig;
 use tokio::runtime::Runtime;
@@ -20,18 +21,24 @@ pub mod copy_timeline;
 pub mod debug_dump;
 pub mod handler;
 pub mod http;
+pub mod ingest_limit;
+pub mod io_engine;
 pub mod json_ctrl;
+pub mod membership;
 pub mod metrics;
 pub mod patch_control_file;
 pub mod pull_timeline;
 pub mod receive_wal;
 pub mod recovery;
+pub mod remote_wal_cache;
 pub mod remove_wal;
+pub mod replicate_timeline;
 pub mod safekeeper;
 pub mod send_wal;
 pub mod state;
 pub mod timeline;
 pub mod wal_backup;
+pub mod wal_backup_partial;
 pub mod wal_service;
 pub mod wal_storage;
 
@@ -48,6 +55,12 @@ pub mod defaults {
 
     pub const DEFAULT_HEARTBEAT_TIMEOUT: &str = "5000ms";
     pub const DEFAULT_MAX_OFFLOADER_LAG_BYTES: u64 = 128 * (1 << 20);
+    pub const DEFAULT_PARTIAL_BACKUP_TIMEOUT: &str = "15m";
+    pub const DEFAULT_WAL_FLUSH_BATCH_DELAY: &str = "0ms";
+    pub const DEFAULT_WAL_IO_ENGINE: &str = "std-fs";
+    pub const DEFAULT_REMOTE_WAL_CACHE_SIZE: u64 = 0;
+    pub const DEFAULT_TENANT_WAL_INGEST_BURST: u64 = 64 * (1 << 20);
+    pub const DEFAULT_TOMBSTONE_TTL: &str = "7d";
 }
 
 #[derive(Debug, Clone)]
@@ -63,20 +76,55 @@ pub struct SafeKeeperConf {
     pub listen_pg_addr: String,
     pub listen_pg_addr_tenant_only: Option<String>,
     pub listen_http_addr: String,
+    pub listen_http_addr_tenant_only: Option<String>,
     pub advertise_pg_addr: Option<String>,
     pub availability_zone: Option<String>,
+    /// Don't fsync WAL and control files. Only for tests and benchmarks,
+    /// acknowledged WAL can be lost on OS crash.
     pub no_sync: bool,
     pub broker_endpoint: Uri,
     pub broker_keepalive_interval: Duration,
+    /// HTTP endpoints of peer safekeepers to poll for timelines state instead
+    /// of using the broker. If not empty, the broker is not used.
+    pub static_peers: Vec<String>,
     pub heartbeat_timeout: Duration,
     pub peer_recovery_enabled: bool,
     pub remote_storage: Option<RemoteStorageConfig>,
     pub max_offloader_lag_bytes: u64,
     pub backup_parallel_jobs: usize,
     pub wal_backup_enabled: bool,
+    /// Compress full WAL segments with zstd before uploading them.
+    pub wal_backup_compression: bool,
+    pub partial_backup_enabled: bool,
+    pub partial_backup_timeout: Duration,
+    /// Max time to wait for more AppendRequests before fsyncing WAL, to
+    /// acknowledge several of them with one fsync.
+    pub wal_flush_batch_delay: Duration,
+    /// Engine to write and fsync WAL segments with.
+    pub wal_io_engine: IoEngineKind,
+    /// Validate incoming WAL before writing it to disk.
+    pub validate_wal: bool,
+    pub wal_eviction_enabled: bool,
+    /// Disk usage percentage above which WAL which is backed up and consumed
+    /// by the pageserver is removed regardless of peers.
+    pub wal_disk_usage_threshold_pct: Option<u8>,
+    /// Max size of local cache of WAL segments read from remote storage, 0
+    /// (the default) disables it.
+    pub remote_wal_cache_size: u64,
+    /// Max rate in bytes per second at which WAL of a single tenant is
+    /// accepted from computes, None means unlimited.
+    pub tenant_wal_ingest_rate: Option<u64>,
+    /// Amount of WAL in bytes a tenant can write at once above
+    /// tenant_wal_ingest_rate.
+    pub tenant_wal_ingest_burst: u64,
+    /// How long a deleted timeline can't be created again.
+    pub tombstone_ttl: Duration,
     pub pg_auth: Option<Arc<JwtAuth>>,
     pub pg_tenant_only_auth: Option<Arc<JwtAuth>>,
     pub http_auth: Option<Arc<SwappableJwtAuth>>,
+    pub http_tenant_only_auth: Option<Arc<SwappableJwtAuth>>,
+    /// JWT token to connect to other safekeepers with.
+    pub sk_auth_token: Option<Arc<String>>,
     pub current_thread_runtime: bool,
 }
 
@@ -90,9 +138,25 @@ impl SafeKeeperConf {
             .join(ttid.timeline_id.to_string())
     }
 
+    /// Directory with markers of deleted timelines, laid out as
+    /// `<tenant_id>/<timeline_id>`.
+    pub fn tombstones_dir(&self) -> Utf8PathBuf {
+        self.workdir.join("tombstones")
+    }
+
+    /// Scratch directory for WAL segments downloaded from remote storage.
+    pub fn remote_wal_cache_dir(&self) -> Utf8PathBuf {
+        self.workdir.join("remote_wal_cache")
+    }
+
     pub fn is_wal_backup_enabled(&self) -> bool {
         self.remote_storage.is_some() && self.wal_backup_enabled
     }
+
+    /// WAL eviction only makes sense if evicted WAL can be downloaded back.
+    pub fn is_wal_eviction_enabled(&self) -> bool {
+        self.is_wal_backup_enabled() && self.wal_eviction_enabled
+    }
 }
 
 impl SafeKeeperConf {
@@ -104,6 +168,7 @@ impl SafeKeeperConf {
             listen_pg_addr: defaults::DEFAULT_PG_LISTEN_ADDR.to_string(),
             listen_pg_addr_tenant_only: None,
             listen_http_addr: defaults::DEFAULT_HTTP_LISTEN_ADDR.to_string(),
+            listen_http_addr_tenant_only: None,
             advertise_pg_addr: None,
             availability_zone: None,
             remote_storage: None,
@@ -112,12 +177,27 @@ impl SafeKeeperConf {
                 .parse()
                 .expect("failed to parse default broker endpoint"),
             broker_keepalive_interval: Duration::from_secs(5),
+            static_peers: Vec::new(),
             peer_recovery_enabled: true,
             wal_backup_enabled: true,
+            wal_backup_compression: false,
+            partial_backup_enabled: false,
+            partial_backup_timeout: Duration::from_secs(15 * 60),
+            wal_flush_batch_delay: Duration::ZERO,
+            wal_io_engine: IoEngineKind::StdFs,
+            validate_wal: false,
+            wal_eviction_enabled: false,
+            wal_disk_usage_threshold_pct: None,
+            remote_wal_cache_size: 0,
+            tenant_wal_ingest_rate: None,
+            tenant_wal_ingest_burst: defaults::DEFAULT_TENANT_WAL_INGEST_BURST,
+            tombstone_ttl: Duration::from_secs(7 * 24 * 60 * 60),
             backup_parallel_jobs: 1,
             pg_auth: None,
             pg_tenant_only_auth: None,
             http_auth: None,
+            http_tenant_only_auth: None,
+            sk_auth_token: None,
             heartbeat_timeout: Duration::new(5, 0),
             max_offloader_lag_bytes: defaults::DEFAULT_MAX_OFFLOADER_LAG_BYTES,
             current_thread_runtime: false,
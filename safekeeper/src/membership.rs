@@ -0,0 +1,259 @@
+//! Safekeeper membership configuration: set of safekeepers a timeline is
+//! replicated to, and the protocol to change it online.
+//!
+//! Configuration is identified by generation number which only grows.
+//! Generation 0 means that timeline has never been configured; such
+//! timelines accept any proposer, as before membership was introduced.
+//!
+//! Change of members from set A to set B goes through the joint
+//! configuration, similar to joint consensus in Raft:
+//! 1. New members B \ A are seeded with the timeline, e.g. by pull_timeline.
+//! 2. Safekeepers of A and B are switched to the joint configuration
+//!    `{generation: N+1, members: A, new_members: B}`. Safekeepers refuse
+//!    greetings from proposers with generation lower than their own, so once
+//!    a majority of A has switched, proposers of the old configuration can't
+//!    be elected anymore. Proposer of the joint configuration must collect
+//!    votes and acknowledgments from majorities of both A and B.
+//! 3. Once a majority of B has caught up with commit_lsn, safekeepers are
+//!    switched to `{generation: N+2, members: B}`, B first.
+//! 4. Safekeepers which are not in their configuration refuse to vote, so
+//!    A \ B can't take part in elections anymore and the timeline can be
+//!    deleted from them.
+//!
+//! Switching is driven from outside (control plane) through the HTTP API.
+//! Safekeeper applies a configuration only if its generation is higher than
+//! the current one, so retries are safe.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use utils::id::NodeId;
+
+/// Number uniquely identifying safekeeper configuration.
+pub type Generation = u32;
+
+/// Generation of the timeline which has never been configured.
+pub const INVALID_GENERATION: Generation = 0;
+
+/// Max number of safekeepers in a member set, as many as walproposer can
+/// connect to.
+pub const MAX_MEMBERS: usize = 32;
+
+/// Membership of a safekeeper.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SafekeeperId {
+    pub id: NodeId,
+    pub host: String,
+    pub pg_port: u16,
+}
+
+impl Display for SafekeeperId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[id={}, ep={}:{}]", self.id, self.host, self.pg_port)
+    }
+}
+
+/// Set of safekeepers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MemberSet {
+    pub members: Vec<SafekeeperId>,
+}
+
+impl MemberSet {
+    pub fn empty() -> Self {
+        MemberSet {
+            members: Vec::new(),
+        }
+    }
+
+    pub fn new(members: Vec<SafekeeperId>) -> anyhow::Result<Self> {
+        if members.len() > MAX_MEMBERS {
+            bail!(
+                "member set has {} safekeepers, at most {} are supported",
+                members.len(),
+                MAX_MEMBERS
+            );
+        }
+        let mut ids = HashSet::new();
+        for m in &members {
+            if !ids.insert(m.id) {
+                bail!("duplicate safekeeper id {} in member set", m.id);
+            }
+        }
+        Ok(MemberSet { members })
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.members.iter().any(|m| m.id == id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Whether `ids` contain a majority of the set.
+    pub fn is_quorum(&self, ids: &HashSet<NodeId>) -> bool {
+        let present = self.members.iter().filter(|m| ids.contains(&m.id)).count();
+        present > self.members.len() / 2
+    }
+}
+
+impl Display for MemberSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let members: Vec<String> = self.members.iter().map(|m| m.to_string()).collect();
+        write!(f, "[{}]", members.join(", "))
+    }
+}
+
+/// Membership configuration of a timeline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Configuration {
+    /// Unique id of the configuration.
+    pub generation: Generation,
+    /// Current members.
+    pub members: MemberSet,
+    /// Members the timeline migrates to, if the configuration is joint.
+    pub new_members: Option<MemberSet>,
+}
+
+impl Configuration {
+    /// Configuration of the timeline which has never been configured.
+    pub fn empty() -> Self {
+        Configuration {
+            generation: INVALID_GENERATION,
+            members: MemberSet::empty(),
+            new_members: None,
+        }
+    }
+
+    pub fn is_joint(&self) -> bool {
+        self.new_members.is_some()
+    }
+
+    /// Whether safekeeper `id` may take part in elections of this
+    /// configuration. Anyone may if the timeline has never been configured.
+    pub fn is_voter(&self, id: NodeId) -> bool {
+        self.generation == INVALID_GENERATION
+            || self.members.contains(id)
+            || self.new_members.as_ref().is_some_and(|m| m.contains(id))
+    }
+
+    /// Whether `ids` form a quorum: majority of members and, in joint
+    /// configuration, majority of new members as well.
+    pub fn is_quorum(&self, ids: &HashSet<NodeId>) -> bool {
+        self.members.is_quorum(ids)
+            && self
+                .new_members
+                .as_ref()
+                .map_or(true, |new_members| new_members.is_quorum(ids))
+    }
+
+    /// Check that configuration is acceptable to switch to.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.generation == INVALID_GENERATION {
+            bail!("generation {} is reserved", INVALID_GENERATION);
+        }
+        MemberSet::new(self.members.members.clone())?;
+        if self.members.is_empty() {
+            bail!("configuration must have members");
+        }
+        if let Some(new_members) = &self.new_members {
+            MemberSet::new(new_members.members.clone())?;
+            if new_members.is_empty() {
+                bail!("joint configuration must have new members");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Configuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.new_members {
+            Some(new_members) => write!(
+                f,
+                "gen={}, members={}, new_members={}",
+                self.generation, self.members, new_members
+            ),
+            None => write!(f, "gen={}, members={}", self.generation, self.members),
+        }
+    }
+}
+
+/// Result of an attempt to switch configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchResult {
+    pub previous_conf: Configuration,
+    pub current_conf: Configuration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ids: &[u64]) -> MemberSet {
+        MemberSet::new(
+            ids.iter()
+                .map(|id| SafekeeperId {
+                    id: NodeId(*id),
+                    host: format!("sk-{id}"),
+                    pg_port: 5454,
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    fn ids(ids: &[u64]) -> HashSet<NodeId> {
+        ids.iter().map(|id| NodeId(*id)).collect()
+    }
+
+    #[test]
+    fn test_duplicate_members() {
+        let mut members = set(&[1, 2]).members;
+        members.push(members[0].clone());
+        assert!(MemberSet::new(members).is_err());
+    }
+
+    #[test]
+    fn test_quorum() {
+        let conf = Configuration {
+            generation: 1,
+            members: set(&[1, 2, 3]),
+            new_members: None,
+        };
+        assert!(conf.is_quorum(&ids(&[1, 3])));
+        assert!(!conf.is_quorum(&ids(&[1, 4, 5])));
+        assert!(conf.is_voter(NodeId(2)));
+        assert!(!conf.is_voter(NodeId(4)));
+    }
+
+    #[test]
+    fn test_joint_quorum() {
+        let conf = Configuration {
+            generation: 2,
+            members: set(&[1, 2, 3]),
+            new_members: Some(set(&[2, 3, 4])),
+        };
+        // majority of both sets
+        assert!(conf.is_quorum(&ids(&[2, 3])));
+        assert!(conf.is_quorum(&ids(&[1, 3, 4])));
+        // majority of old set only
+        assert!(!conf.is_quorum(&ids(&[1, 2])));
+        // majority of new set only
+        assert!(!conf.is_quorum(&ids(&[3, 4])));
+        assert!(conf.is_voter(NodeId(1)));
+        assert!(conf.is_voter(NodeId(4)));
+        assert!(!conf.is_voter(NodeId(5)));
+    }
+
+    #[test]
+    fn test_unconfigured() {
+        let conf = Configuration::empty();
+        assert!(conf.is_voter(NodeId(42)));
+        assert!(conf.validate().is_err());
+    }
+}
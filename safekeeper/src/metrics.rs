@@ -11,14 +11,16 @@ use futures::Future;
 use metrics::{
     core::{AtomicU64, Collector, Desc, GenericCounter, GenericGaugeVec, Opts},
     proto::MetricFamily,
-    register_int_counter, register_int_counter_pair_vec, register_int_counter_vec, Gauge,
-    IntCounter, IntCounterPairVec, IntCounterVec, IntGaugeVec,
+    register_int_counter, register_int_counter_pair_vec, register_int_counter_vec,
+    register_int_gauge, Gauge, IntCounter, IntCounterPairVec, IntCounterVec, IntGaugeVec,
 };
 use once_cell::sync::Lazy;
 
 use postgres_ffi::XLogSegNo;
+use serde::{Deserialize, Serialize};
+use utils::id::{TenantId, TenantTimelineId, TimelineId};
+use utils::lsn::Lsn;
 use utils::pageserver_feedback::PageserverFeedback;
-use utils::{id::TenantTimelineId, lsn::Lsn};
 
 use crate::{
     state::{TimelineMemState, TimelinePersistentState},
@@ -59,6 +61,14 @@ pub static FLUSH_WAL_SECONDS: Lazy<Histogram> = Lazy::new(|| {
     )
     .expect("Failed to register safekeeper_flush_wal_seconds histogram")
 });
+pub static WAL_FLUSH_BATCH_SIZE: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "safekeeper_wal_flush_batch_size",
+        "Number of AppendRequests acknowledged by a single WAL flush",
+        vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0]
+    )
+    .expect("Failed to register safekeeper_wal_flush_batch_size histogram")
+});
 pub static PERSIST_CONTROL_FILE_SECONDS: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "safekeeper_persist_control_file_seconds",
@@ -107,6 +117,34 @@ pub static REMOVED_WAL_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
     )
     .expect("Failed to register safekeeper_removed_wal_segments_total counter")
 });
+pub static WAL_DISK_USAGE_THRESHOLD_EXCEEDED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "safekeeper_wal_disk_usage_threshold_exceeded",
+        "1 if disk usage is above the threshold and WAL is removed ignoring peers, 0 otherwise"
+    )
+    .expect("Failed to register safekeeper_wal_disk_usage_threshold_exceeded gauge")
+});
+pub static EMERGENCY_WAL_REMOVALS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_emergency_wal_removals_total",
+        "Number of WAL removal rounds ignoring peers because of high disk usage"
+    )
+    .expect("Failed to register safekeeper_emergency_wal_removals_total counter")
+});
+pub static WAL_INGEST_THROTTLED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_wal_ingest_throttled_total",
+        "Number of AppendRequests delayed because tenant exceeded WAL ingest rate"
+    )
+    .expect("Failed to register safekeeper_wal_ingest_throttled_total counter")
+});
+pub static WAL_INGEST_THROTTLED_WAIT_USECS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_wal_ingest_throttled_wait_usecs_total",
+        "Time spent delaying AppendRequests because tenant exceeded WAL ingest rate"
+    )
+    .expect("Failed to register safekeeper_wal_ingest_throttled_wait_usecs_total counter")
+});
 pub static BACKED_UP_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "safekeeper_backed_up_segments_total",
@@ -114,6 +152,27 @@ pub static BACKED_UP_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
     )
     .expect("Failed to register safekeeper_backed_up_segments_total counter")
 });
+pub static BACKED_UP_PARTIAL_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_backed_up_partial_segments_total",
+        "Number of partial WAL segment uploads to the S3"
+    )
+    .expect("Failed to register safekeeper_backed_up_partial_segments_total counter")
+});
+pub static REMOTE_WAL_SEGMENT_READS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_remote_wal_segment_reads_total",
+        "Number of WAL segments read from the S3 because they were not on the disk"
+    )
+    .expect("Failed to register safekeeper_remote_wal_segment_reads_total counter")
+});
+pub static REMOTE_WAL_CACHE_HITS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_remote_wal_cache_hits_total",
+        "Number of times WAL segment was served from local cache of remote WAL"
+    )
+    .expect("Failed to register safekeeper_remote_wal_cache_hits_total counter")
+});
 pub static BACKUP_ERRORS: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "safekeeper_backup_errors_total",
@@ -316,6 +375,63 @@ pub struct FullTimelineInfo {
     pub wal_storage: WalStorageMetrics,
 }
 
+impl FullTimelineInfo {
+    /// WAL flushed locally but not yet known to be committed.
+    pub fn commit_lag_bytes(&self) -> u64 {
+        self.flush_lsn.0.saturating_sub(self.mem_state.commit_lsn.0)
+    }
+
+    /// Committed WAL not yet backed up to remote storage.
+    pub fn backup_lag_bytes(&self) -> u64 {
+        self.mem_state
+            .commit_lsn
+            .0
+            .saturating_sub(self.mem_state.backup_lsn.0)
+    }
+
+    /// Committed WAL not yet persisted to remote storage by the pageserver.
+    pub fn remote_consistent_lag_bytes(&self) -> u64 {
+        self.mem_state
+            .commit_lsn
+            .0
+            .saturating_sub(self.mem_state.remote_consistent_lsn.0)
+    }
+}
+
+/// Cheap summary of a timeline state, served as JSON to the control plane.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineMetricsSummary {
+    pub tenant_id: TenantId,
+    pub timeline_id: TimelineId,
+    pub flush_lsn: Lsn,
+    pub commit_lsn: Lsn,
+    pub backup_lsn: Lsn,
+    pub remote_consistent_lsn: Lsn,
+    pub commit_lag_bytes: u64,
+    pub backup_lag_bytes: u64,
+    pub remote_consistent_lag_bytes: u64,
+    pub num_computes: u32,
+    pub wal_backup_active: bool,
+}
+
+impl From<&FullTimelineInfo> for TimelineMetricsSummary {
+    fn from(tli: &FullTimelineInfo) -> Self {
+        TimelineMetricsSummary {
+            tenant_id: tli.ttid.tenant_id,
+            timeline_id: tli.ttid.timeline_id,
+            flush_lsn: tli.flush_lsn,
+            commit_lsn: tli.mem_state.commit_lsn,
+            backup_lsn: tli.mem_state.backup_lsn,
+            remote_consistent_lsn: tli.mem_state.remote_consistent_lsn,
+            commit_lag_bytes: tli.commit_lag_bytes(),
+            backup_lag_bytes: tli.backup_lag_bytes(),
+            remote_consistent_lag_bytes: tli.remote_consistent_lag_bytes(),
+            num_computes: tli.num_computes,
+            wal_backup_active: tli.wal_backup_active,
+        }
+    }
+}
+
 /// Collects metrics for all active timelines.
 pub struct TimelineCollector {
     descs: Vec<Desc>,
@@ -325,6 +441,9 @@ pub struct TimelineCollector {
     epoch_start_lsn: GenericGaugeVec<AtomicU64>,
     peer_horizon_lsn: GenericGaugeVec<AtomicU64>,
     remote_consistent_lsn: GenericGaugeVec<AtomicU64>,
+    commit_lag_bytes: GenericGaugeVec<AtomicU64>,
+    backup_lag_bytes: GenericGaugeVec<AtomicU64>,
+    remote_consistent_lag_bytes: GenericGaugeVec<AtomicU64>,
     ps_last_received_lsn: GenericGaugeVec<AtomicU64>,
     feedback_last_time_seconds: GenericGaugeVec<AtomicU64>,
     timeline_active: GenericGaugeVec<AtomicU64>,
@@ -410,6 +529,36 @@ impl TimelineCollector {
         .unwrap();
         descs.extend(remote_consistent_lsn.desc().into_iter().cloned());
 
+        let commit_lag_bytes = GenericGaugeVec::new(
+            Opts::new(
+                "safekeeper_commit_lag_bytes",
+                "Amount of WAL flushed locally but not yet known to be committed (flush_lsn - commit_lsn)",
+            ),
+            &["tenant_id", "timeline_id"],
+        )
+        .unwrap();
+        descs.extend(commit_lag_bytes.desc().into_iter().cloned());
+
+        let backup_lag_bytes = GenericGaugeVec::new(
+            Opts::new(
+                "safekeeper_backup_lag_bytes",
+                "Amount of committed WAL not yet backed up to remote storage (commit_lsn - backup_lsn)",
+            ),
+            &["tenant_id", "timeline_id"],
+        )
+        .unwrap();
+        descs.extend(backup_lag_bytes.desc().into_iter().cloned());
+
+        let remote_consistent_lag_bytes = GenericGaugeVec::new(
+            Opts::new(
+                "safekeeper_remote_consistent_lag_bytes",
+                "Amount of committed WAL not yet persisted to remote storage by pageserver (commit_lsn - remote_consistent_lsn)",
+            ),
+            &["tenant_id", "timeline_id"],
+        )
+        .unwrap();
+        descs.extend(remote_consistent_lag_bytes.desc().into_iter().cloned());
+
         let ps_last_received_lsn = GenericGaugeVec::new(
             Opts::new(
                 "safekeeper_ps_last_received_lsn",
@@ -536,6 +685,9 @@ impl TimelineCollector {
             epoch_start_lsn,
             peer_horizon_lsn,
             remote_consistent_lsn,
+            commit_lag_bytes,
+            backup_lag_bytes,
+            remote_consistent_lag_bytes,
             ps_last_received_lsn,
             feedback_last_time_seconds,
             timeline_active,
@@ -568,6 +720,9 @@ impl Collector for TimelineCollector {
         self.epoch_start_lsn.reset();
         self.peer_horizon_lsn.reset();
         self.remote_consistent_lsn.reset();
+        self.commit_lag_bytes.reset();
+        self.backup_lag_bytes.reset();
+        self.remote_consistent_lag_bytes.reset();
         self.ps_last_received_lsn.reset();
         self.feedback_last_time_seconds.reset();
         self.timeline_active.reset();
@@ -622,6 +777,15 @@ impl Collector for TimelineCollector {
             self.remote_consistent_lsn
                 .with_label_values(labels)
                 .set(tli.mem_state.remote_consistent_lsn.into());
+            self.commit_lag_bytes
+                .with_label_values(labels)
+                .set(tli.commit_lag_bytes());
+            self.backup_lag_bytes
+                .with_label_values(labels)
+                .set(tli.backup_lag_bytes());
+            self.remote_consistent_lag_bytes
+                .with_label_values(labels)
+                .set(tli.remote_consistent_lag_bytes());
             self.timeline_active
                 .with_label_values(labels)
                 .set(tli.timeline_is_active as u64);
@@ -677,6 +841,9 @@ impl Collector for TimelineCollector {
         mfs.extend(self.epoch_start_lsn.collect());
         mfs.extend(self.peer_horizon_lsn.collect());
         mfs.extend(self.remote_consistent_lsn.collect());
+        mfs.extend(self.commit_lag_bytes.collect());
+        mfs.extend(self.backup_lag_bytes.collect());
+        mfs.extend(self.remote_consistent_lag_bytes.collect());
         mfs.extend(self.ps_last_received_lsn.collect());
         mfs.extend(self.feedback_last_time_seconds.collect());
         mfs.extend(self.timeline_active.collect());
@@ -703,7 +870,8 @@ impl Collector for TimelineCollector {
     }
 }
 
-async fn collect_timeline_metrics() -> Vec<FullTimelineInfo> {
+/// Collect metrics of all active timelines.
+pub async fn collect_timeline_metrics() -> Vec<FullTimelineInfo> {
     let mut res = vec![];
     let timelines = GlobalTimelines::get_all();
 
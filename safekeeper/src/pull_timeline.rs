@@ -1,3 +1,4 @@
+use std::cmp::max;
 use std::sync::Arc;
 
 use camino::Utf8PathBuf;
@@ -6,15 +7,18 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use anyhow::{bail, Context, Result};
+use postgres_ffi::v14::xlog_utils::{IsXLogFileName, XLogFromFileName, XLogSegNoOffsetToRecPtr};
 use tokio::io::AsyncWriteExt;
 use tracing::info;
 use utils::{
+    crashsafe::fsync_async_opt,
     id::{TenantId, TenantTimelineId, TimelineId},
     lsn::Lsn,
 };
 
 use crate::{
-    control_file, debug_dump,
+    control_file::{self, Storage as _},
+    debug_dump,
     http::routes::TimelineStatus,
     timeline::{Timeline, TimelineError},
     wal_storage::{self, Storage},
@@ -56,6 +60,7 @@ pub async fn handle_request(request: Request) -> Result<Response> {
         bail!("Timeline {} already exists", request.timeline_id);
     }
 
+    let conf = GlobalTimelines::get_global_config();
     let client = reqwest::Client::new();
     let http_hosts = request.http_hosts.clone();
 
@@ -65,13 +70,15 @@ pub async fn handle_request(request: Request) -> Result<Response> {
             "{}/v1/tenant/{}/timeline/{}",
             url, request.tenant_id, request.timeline_id
         );
-        client.get(url).send()
+        get(&client, &conf, url).send()
     }))
     .await;
 
     let mut statuses = Vec::new();
     for (i, response) in responses.into_iter().enumerate() {
-        let response = response.context(format!("Failed to get status from {}", http_hosts[i]))?;
+        let response = response
+            .and_then(|r| r.error_for_status())
+            .context(format!("Failed to get status from {}", http_hosts[i]))?;
         let status: crate::http::routes::TimelineStatus = response.json().await?;
         statuses.push((status, i));
     }
@@ -96,6 +103,15 @@ pub async fn handle_request(request: Request) -> Result<Response> {
     pull_timeline(status, safekeeper_host).await
 }
 
+/// Prepare GET request to a peer safekeeper, authenticating if we have a token.
+fn get(client: &reqwest::Client, conf: &SafeKeeperConf, url: String) -> reqwest::RequestBuilder {
+    let req = client.get(url);
+    match &conf.sk_auth_token {
+        Some(token) => req.bearer_auth(token.as_str()),
+        None => req,
+    }
+}
+
 async fn pull_timeline(status: TimelineStatus, host: String) -> Result<Response> {
     let ttid = TenantTimelineId::new(status.tenant_id, status.timeline_id);
     info!(
@@ -117,15 +133,19 @@ async fn pull_timeline(status: TimelineStatus, host: String) -> Result<Response>
 
     // Implementing our own scp over HTTP.
     // At first, we need to fetch list of files from safekeeper.
-    let dump: DebugDumpResponse = client
-        .get(format!(
+    let dump: DebugDumpResponse = get(
+        &client,
+        conf,
+        format!(
             "{}/v1/debug_dump?dump_all=true&tenant_id={}&timeline_id={}",
             host, status.tenant_id, status.timeline_id
-        ))
-        .send()
-        .await?
-        .json()
-        .await?;
+        ),
+    )
+    .send()
+    .await?
+    .error_for_status()?
+    .json()
+    .await?;
 
     if dump.timelines.len() != 1 {
         bail!(
@@ -162,6 +182,27 @@ async fn pull_timeline(status: TimelineStatus, host: String) -> Result<Response>
     filenames.remove(control_file_index);
     filenames.insert(0, "safekeeper.control".to_string());
 
+    // If we can read WAL from remote storage, don't copy segments which are
+    // already offloaded and consumed by pageserver, only the WAL suffix.
+    let mut wal_start_lsn = Lsn::INVALID;
+    let wal_seg_size = status.pg_info.wal_seg_size as usize;
+    if conf.is_wal_backup_enabled() && wal_seg_size != 0 {
+        let horizon_lsn = Lsn::min(status.backup_lsn, status.remote_consistent_lsn);
+        let horizon_segno = horizon_lsn.segment_number(wal_seg_size);
+        let before = filenames.len();
+        filenames.retain(|name| {
+            !IsXLogFileName(name) || XLogFromFileName(name, wal_seg_size).0 >= horizon_segno
+        });
+        if filenames.len() != before {
+            wal_start_lsn = Lsn(XLogSegNoOffsetToRecPtr(horizon_segno, 0, wal_seg_size));
+            info!(
+                "skipping {} segments before {}, they are available in remote storage",
+                before - filenames.len(),
+                wal_start_lsn
+            );
+        }
+    }
+
     info!(
         "downloading {} files from safekeeper {}",
         filenames.len(),
@@ -182,14 +223,30 @@ async fn pull_timeline(status: TimelineStatus, host: String) -> Result<Response>
         );
 
         let mut file = tokio::fs::File::create(&file_path).await?;
-        let mut response = client.get(&http_url).send().await?;
+        let mut response = get(&client, conf, http_url)
+            .send()
+            .await?
+            .error_for_status()?;
         while let Some(chunk) = response.chunk().await? {
             file.write_all(&chunk).await?;
-            file.flush().await?;
+        }
+        file.flush().await?;
+        if !conf.no_sync {
+            file.sync_all().await?;
         }
     }
 
-    // TODO: fsync?
+    // Segments before wal_start_lsn are not on disk anymore, let readers know
+    // they need to go to remote storage for them.
+    if wal_start_lsn != Lsn::INVALID {
+        let mut state =
+            control_file::FileStorage::load_control_file(tli_dir_path.join("safekeeper.control"))?;
+        state.local_start_lsn = max(state.local_start_lsn, wal_start_lsn);
+        let mut control_store =
+            control_file::FileStorage::create_new(tli_dir_path.clone(), conf, state.clone())?;
+        control_store.persist(&state).await?;
+    }
+    fsync_async_opt(&tli_dir_path, !conf.no_sync).await?;
 
     // Let's create timeline from temp directory and verify that it's correct
     let (commit_lsn, flush_lsn) = validate_temp_timeline(conf, ttid, &tli_dir_path).await?;
@@ -199,6 +256,10 @@ async fn pull_timeline(status: TimelineStatus, host: String) -> Result<Response>
     );
     assert!(status.commit_lsn <= status.flush_lsn);
 
+    // Pulling is an explicit request to host the timeline here, e.g. to move
+    // it back, so it overrides previous deletion.
+    GlobalTimelines::remove_tombstone(&ttid).await?;
+
     // Finally, load the timeline.
     let _tli = load_temp_timeline(conf, ttid, &tli_dir_path).await?;
 
@@ -271,6 +332,9 @@ pub async fn load_temp_timeline(
     if !matches!(GlobalTimelines::get(ttid), Err(TimelineError::NotFound(_))) {
         bail!("timeline already exists, cannot overwrite it")
     }
+    if GlobalTimelines::is_deleted(&ttid) {
+        bail!(TimelineError::Deleted(ttid));
+    }
 
     // Move timeline dir to the correct location
     let timeline_path = conf.timeline_dir(&ttid);
@@ -3,6 +3,9 @@
 //! sends replies back.
 
 use crate::handler::SafekeeperPostgresHandler;
+use crate::ingest_limit;
+use crate::ingest_limit::IngestLimiter;
+use crate::metrics::WAL_FLUSH_BATCH_SIZE;
 use crate::safekeeper::AcceptorProposerMessage;
 use crate::safekeeper::ProposerAcceptorMessage;
 use crate::safekeeper::ServerInfo;
@@ -203,6 +206,8 @@ impl SafekeeperPostgresHandler {
             pgb_reader: &mut pgb_reader,
             peer_addr,
             acceptor_handle: &mut acceptor_handle,
+            flush_batch_delay: self.conf.wal_flush_batch_delay,
+            ingest_limiter: ingest_limit::get(&self.conf, self.ttid.tenant_id),
         };
         let res = tokio::select! {
             // todo: add read|write .context to these errors
@@ -248,6 +253,8 @@ struct NetworkReader<'a, IO> {
     // WalAcceptor is spawned when we learn server info from walproposer and
     // create timeline; handle is put here.
     acceptor_handle: &'a mut Option<JoinHandle<anyhow::Result<()>>>,
+    flush_batch_delay: Duration,
+    ingest_limiter: Option<Arc<IngestLimiter>>,
 }
 
 impl<'a, IO: AsyncRead + AsyncWrite + Unpin> NetworkReader<'a, IO> {
@@ -284,6 +291,8 @@ impl<'a, IO: AsyncRead + AsyncWrite + Unpin> NetworkReader<'a, IO> {
             msg_rx,
             reply_tx,
             Some(self.conn_id),
+            self.flush_batch_delay,
+            self.ingest_limiter,
         ));
 
         // Forward all messages to WalAcceptor
@@ -347,6 +356,11 @@ pub struct WalAcceptor {
     msg_rx: Receiver<ProposerAcceptorMessage>,
     reply_tx: Sender<AcceptorProposerMessage>,
     conn_id: Option<ConnectionId>,
+    /// How long to wait for more AppendRequests before flushing the written
+    /// ones, see `SafeKeeperConf::wal_flush_batch_delay`.
+    flush_batch_delay: Duration,
+    /// Per-tenant WAL ingestion rate limit, None if not limited.
+    ingest_limiter: Option<Arc<IngestLimiter>>,
 }
 
 impl WalAcceptor {
@@ -360,6 +374,8 @@ impl WalAcceptor {
         msg_rx: Receiver<ProposerAcceptorMessage>,
         reply_tx: Sender<AcceptorProposerMessage>,
         conn_id: Option<ConnectionId>,
+        flush_batch_delay: Duration,
+        ingest_limiter: Option<Arc<IngestLimiter>>,
     ) -> JoinHandle<anyhow::Result<()>> {
         task::spawn(async move {
             let mut wa = WalAcceptor {
@@ -367,6 +383,8 @@ impl WalAcceptor {
                 msg_rx,
                 reply_tx,
                 conn_id,
+                flush_batch_delay,
+                ingest_limiter,
             };
 
             let span_ttid = wa.tli.ttid; // satisfy borrow checker
@@ -394,13 +412,17 @@ impl WalAcceptor {
         // to the walproposer. walproposer sends at least one AppendRequest per second,
         // we will send keepalives by replying to these requests once per second.
         let mut next_keepalive = Instant::now();
+        // Message received while batching AppendRequests, processed after the flush.
+        let mut pending_msg: Option<ProposerAcceptorMessage> = None;
 
         loop {
-            let opt_msg = self.msg_rx.recv().await;
-            if opt_msg.is_none() {
-                return Ok(()); // chan closed, streaming terminated
-            }
-            let mut next_msg = opt_msg.unwrap();
+            let mut next_msg = match pending_msg.take() {
+                Some(msg) => msg,
+                None => match self.msg_rx.recv().await {
+                    Some(msg) => msg,
+                    None => return Ok(()), // chan closed, streaming terminated
+                },
+            };
 
             // Update walreceiver state in shmem for reporting.
             if let ProposerAcceptorMessage::Elected(_) = &next_msg {
@@ -408,13 +430,35 @@ impl WalAcceptor {
             }
 
             let reply_msg = if matches!(next_msg, ProposerAcceptorMessage::AppendRequest(_)) {
-                // loop through AppendRequest's while it's readily available to
-                // write as many WAL as possible without fsyncing
-                //
-                // Note: this will need to be rewritten if we want to read non-AppendRequest messages here.
-                // Otherwise, we might end up in a situation where we read a message, but don't
-                // process it.
-                while let ProposerAcceptorMessage::AppendRequest(append_request) = next_msg {
+                // loop through AppendRequest's while they're readily available
+                // or arrive within flush_batch_delay to write as many WAL as
+                // possible without fsyncing, so that one fsync acknowledges
+                // all of them
+                let batch_deadline = Instant::now() + self.flush_batch_delay;
+                let mut batch_size = 0;
+                loop {
+                    let ProposerAcceptorMessage::AppendRequest(append_request) = next_msg else {
+                        pending_msg = Some(next_msg);
+                        break;
+                    };
+                    if batch_size > 0 {
+                        fail::fail_point!("sk-append-batch-error", |_| {
+                            Err(anyhow!("failpoint: sk-append-batch-error"))
+                        });
+                    }
+                    // keepalives are empty and never throttled
+                    if let Some(limiter) = &self.ingest_limiter {
+                        let wal_size = append_request.wal_data.len();
+                        if batch_size == 0 {
+                            limiter.acquire(wal_size).await;
+                        } else if !limiter.try_acquire(wal_size) {
+                            // Out of budget: flush and ack what is written
+                            // before waiting for more.
+                            pending_msg =
+                                Some(ProposerAcceptorMessage::AppendRequest(append_request));
+                            break;
+                        }
+                    }
                     let noflush_msg = ProposerAcceptorMessage::NoFlushAppendRequest(append_request);
 
                     if let Some(reply) = self.tli.process_msg(&noflush_msg).await? {
@@ -422,18 +466,20 @@ impl WalAcceptor {
                             return Ok(()); // chan closed, streaming terminated
                         }
                     }
+                    batch_size += 1;
 
                     // get out of this loop if keepalive time is reached
                     if Instant::now() >= next_keepalive {
                         break;
                     }
 
-                    match self.msg_rx.try_recv() {
-                        Ok(msg) => next_msg = msg,
-                        Err(TryRecvError::Empty) => break,
-                        Err(TryRecvError::Disconnected) => return Ok(()), // chan closed, streaming terminated
+                    match recv_batch_msg(&mut self.msg_rx, batch_deadline).await {
+                        BatchMsg::Msg(msg) => next_msg = msg,
+                        BatchMsg::Flush => break,
+                        BatchMsg::Closed => return Ok(()), // chan closed, streaming terminated
                     }
                 }
+                WAL_FLUSH_BATCH_SIZE.observe(batch_size as f64);
 
                 // flush all written WAL to the disk
                 self.tli
@@ -455,6 +501,39 @@ impl WalAcceptor {
     }
 }
 
+/// Next message received while writing a batch of AppendRequests.
+enum BatchMsg {
+    /// Message to add to the batch, or to process after flushing it if it is
+    /// not an AppendRequest.
+    Msg(ProposerAcceptorMessage),
+    /// Nothing arrived before the deadline, the batch should be flushed.
+    Flush,
+    /// Channel closed, streaming terminated.
+    Closed,
+}
+
+/// Receive the next message of a batch of AppendRequests: a readily available
+/// one, or one arriving before `deadline`.
+async fn recv_batch_msg(
+    msg_rx: &mut Receiver<ProposerAcceptorMessage>,
+    deadline: Instant,
+) -> BatchMsg {
+    match msg_rx.try_recv() {
+        Ok(msg) => BatchMsg::Msg(msg),
+        Err(TryRecvError::Empty) => {
+            if Instant::now() >= deadline {
+                return BatchMsg::Flush;
+            }
+            match tokio::time::timeout_at(deadline, msg_rx.recv()).await {
+                Ok(Some(msg)) => BatchMsg::Msg(msg),
+                Ok(None) => BatchMsg::Closed,
+                Err(_) => BatchMsg::Flush,
+            }
+        }
+        Err(TryRecvError::Disconnected) => BatchMsg::Closed,
+    }
+}
+
 /// Calls update_status_notify in drop to update timeline status.
 struct ComputeConnectionGuard {
     timeline: Arc<Timeline>,
@@ -470,3 +549,108 @@ impl Drop for ComputeConnectionGuard {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use utils::lsn::Lsn;
+
+    use super::*;
+    use crate::safekeeper::{AppendRequest, AppendRequestHeader};
+
+    fn append_request(begin_lsn: u64) -> ProposerAcceptorMessage {
+        ProposerAcceptorMessage::AppendRequest(AppendRequest {
+            h: AppendRequestHeader {
+                term: 1,
+                epoch_start_lsn: Lsn(0),
+                begin_lsn: Lsn(begin_lsn),
+                end_lsn: Lsn(begin_lsn + 1),
+                commit_lsn: Lsn(0),
+                truncate_lsn: Lsn(0),
+                proposer_uuid: [0; 16],
+            },
+            wal_data: Bytes::from_static(b"b"),
+        })
+    }
+
+    fn begin_lsn(msg: BatchMsg) -> u64 {
+        match msg {
+            BatchMsg::Msg(ProposerAcceptorMessage::AppendRequest(ar)) => ar.h.begin_lsn.0,
+            _ => panic!("expected AppendRequest"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_takes_available_messages() {
+        let (tx, mut rx) = channel(MSG_QUEUE_SIZE);
+        tx.send(append_request(1)).await.unwrap();
+        tx.send(append_request(2)).await.unwrap();
+
+        // readily available messages are batched even with no delay
+        let deadline = Instant::now();
+        assert_eq!(begin_lsn(recv_batch_msg(&mut rx, deadline).await), 1);
+        assert_eq!(begin_lsn(recv_batch_msg(&mut rx, deadline).await), 2);
+        assert!(matches!(
+            recv_batch_msg(&mut rx, deadline).await,
+            BatchMsg::Flush
+        ));
+        assert_eq!(Instant::now(), deadline);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_waits_until_deadline() {
+        let (tx, mut rx) = channel(MSG_QUEUE_SIZE);
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(10);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            tx.send(append_request(1)).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            tx.send(append_request(2)).await.unwrap();
+        });
+
+        // a message arriving before the deadline joins the batch
+        assert_eq!(begin_lsn(recv_batch_msg(&mut rx, deadline).await), 1);
+        assert_eq!(started.elapsed(), Duration::from_millis(5));
+
+        // the next one is too late, the batch is flushed at the deadline
+        assert!(matches!(
+            recv_batch_msg(&mut rx, deadline).await,
+            BatchMsg::Flush
+        ));
+        assert_eq!(started.elapsed(), Duration::from_millis(10));
+
+        // and it starts the next batch
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(begin_lsn(recv_batch_msg(&mut rx, deadline).await), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_ends_with_other_message() {
+        let (tx, mut rx) = channel(MSG_QUEUE_SIZE);
+        tx.send(ProposerAcceptorMessage::FlushWAL).await.unwrap();
+
+        // the message is returned to be processed after the flush
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(matches!(
+            recv_batch_msg(&mut rx, deadline).await,
+            BatchMsg::Msg(ProposerAcceptorMessage::FlushWAL)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batch_channel_closed() {
+        let (tx, mut rx) = channel(MSG_QUEUE_SIZE);
+        tx.send(append_request(1)).await.unwrap();
+        drop(tx);
+
+        // messages sent before closing are still received
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(begin_lsn(recv_batch_msg(&mut rx, deadline).await), 1);
+        assert!(matches!(
+            recv_batch_msg(&mut rx, deadline).await,
+            BatchMsg::Closed
+        ));
+        assert!(Instant::now() < deadline);
+    }
+}
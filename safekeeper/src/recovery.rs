@@ -151,22 +151,21 @@ async fn recovery_main_loop(tli: Arc<Timeline>, conf: SafeKeeperConf) {
 
 /// Recover from the specified donor. Returns message explaining normal finish
 /// reason or error.
-async fn recover(
+pub(crate) async fn recover(
     tli: Arc<Timeline>,
     donor: &Donor,
     conf: &SafeKeeperConf,
 ) -> anyhow::Result<String> {
     // Learn donor term switch history to figure out starting point.
     let client = reqwest::Client::new();
-    let timeline_info: TimelineStatus = client
-        .get(format!(
-            "http://{}/v1/tenant/{}/timeline/{}",
-            donor.http_connstr, tli.ttid.tenant_id, tli.ttid.timeline_id
-        ))
-        .send()
-        .await?
-        .json()
-        .await?;
+    let mut req = client.get(format!(
+        "http://{}/v1/tenant/{}/timeline/{}",
+        donor.http_connstr, tli.ttid.tenant_id, tli.ttid.timeline_id
+    ));
+    if let Some(token) = &conf.sk_auth_token {
+        req = req.bearer_auth(token.as_str());
+    }
+    let timeline_info: TimelineStatus = req.send().await?.json().await?;
     if timeline_info.acceptor_state.term != donor.term {
         bail!(
             "donor term changed from {} to {}",
@@ -243,8 +242,12 @@ async fn recovery_stream(
     start_streaming_at: Lsn,
     conf: &SafeKeeperConf,
 ) -> anyhow::Result<String> {
-    // TODO: pass auth token
-    let cfg = wal_stream_connection_config(tli.ttid, &donor.pg_connstr, None, None)?;
+    let cfg = wal_stream_connection_config(
+        tli.ttid,
+        &donor.pg_connstr,
+        conf.sk_auth_token.as_ref().map(|t| t.as_str()),
+        None,
+    )?;
     let mut cfg = cfg.to_tokio_postgres_config();
     // It will make safekeeper give out not committed WAL (up to flush_lsn).
     cfg.application_name(&format!("safekeeper_{}", conf.my_id));
@@ -288,7 +291,14 @@ async fn recovery_stream(
     // As in normal walreceiver, do networking and writing to disk in parallel.
     let (msg_tx, msg_rx) = channel(MSG_QUEUE_SIZE);
     let (reply_tx, reply_rx) = channel(REPLY_QUEUE_SIZE);
-    let wa = WalAcceptor::spawn(tli.clone(), msg_rx, reply_tx, None);
+    let wa = WalAcceptor::spawn(
+        tli.clone(),
+        msg_rx,
+        reply_tx,
+        None,
+        conf.wal_flush_batch_delay,
+        None,
+    );
 
     let res = tokio::select! {
         r = network_io(physical_stream, msg_tx, donor.clone(), tli.clone(), conf.clone()) => r,
@@ -0,0 +1,207 @@
+//! Local scratch cache of WAL segments downloaded from remote storage.
+//!
+//! Consumers lagging behind local_start_lsn (e.g. pageserver catching up after
+//! WAL was evicted from disk) are served WAL from remote storage. Instead of
+//! streaming every read from S3, full segments are downloaded into
+//! `<workdir>/remote_wal_cache` and the next segment is prefetched in
+//! background, so sequential readers rarely wait for S3 and several readers of
+//! the same segment download it once. The cache is bounded by size, evicted in
+//! LRU order and wiped on startup; reads fall back to streaming directly from
+//! S3 if anything goes wrong with it.
+
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use remote_storage::RemotePath;
+use tokio::fs::File;
+use tokio::io::AsyncSeekExt;
+use tracing::*;
+
+use crate::metrics::{REMOTE_WAL_CACHE_HITS, REMOTE_WAL_SEGMENT_READS};
+use crate::wal_backup::read_object;
+use crate::SafeKeeperConf;
+
+static REMOTE_WAL_CACHE: OnceCell<RemoteWalCache> = OnceCell::new();
+
+struct RemoteWalCache {
+    dir: Utf8PathBuf,
+    max_size: u64,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<RemotePath, CacheEntry>,
+    total_size: u64,
+    /// Segments being downloaded, so that concurrent readers wait for single
+    /// download instead of starting their own.
+    downloads: HashMap<RemotePath, Arc<tokio::sync::Mutex<()>>>,
+}
+
+struct CacheEntry {
+    size: u64,
+    last_used: Instant,
+}
+
+/// Set up the cache, if it is enabled in conf.
+pub fn init(conf: &SafeKeeperConf) -> Result<()> {
+    if conf.remote_wal_cache_size == 0 || !conf.is_wal_backup_enabled() {
+        info!("remote WAL cache is disabled");
+        return Ok(());
+    }
+    let dir = conf.remote_wal_cache_dir();
+    match std::fs::remove_dir_all(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("failed to wipe {}", dir)),
+    }
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir))?;
+    info!(
+        "remote WAL cache of {} bytes is enabled in {}",
+        conf.remote_wal_cache_size, dir
+    );
+    REMOTE_WAL_CACHE
+        .set(RemoteWalCache {
+            dir,
+            max_size: conf.remote_wal_cache_size,
+            state: Mutex::new(CacheState::default()),
+        })
+        .map_err(|_| anyhow::anyhow!("remote WAL cache is already initialized"))
+}
+
+/// Open full WAL segment stored at `remote_path` positioned at `offset`,
+/// downloading it to the cache if needed. Returns None if the cache is
+/// disabled.
+pub async fn open_segment(remote_path: &RemotePath, offset: u64) -> Result<Option<File>> {
+    let Some(cache) = REMOTE_WAL_CACHE.get() else {
+        return Ok(None);
+    };
+    let path = cache.get(remote_path).await?;
+    let mut file = File::open(&path)
+        .await
+        .with_context(|| format!("failed to open cached segment {}", path))?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    Ok(Some(file))
+}
+
+/// Download the segment to the cache in background, if the cache is enabled
+/// and doesn't have it yet.
+pub fn prefetch(remote_path: RemotePath) {
+    let Some(cache) = REMOTE_WAL_CACHE.get() else {
+        return;
+    };
+    if cache.state.lock().entries.contains_key(&remote_path) {
+        return;
+    }
+    tokio::spawn(
+        async move {
+            // The segment might be not uploaded yet, that's fine.
+            if let Err(e) = cache.get(&remote_path).await {
+                debug!("prefetch failed: {e:#}");
+            }
+        }
+        .instrument(info_span!("remote WAL prefetch")),
+    );
+}
+
+impl RemoteWalCache {
+    /// Get path to the cached segment, downloading it if needed.
+    async fn get(&self, remote_path: &RemotePath) -> Result<Utf8PathBuf> {
+        if let Some(path) = self.lookup(remote_path) {
+            return Ok(path);
+        }
+
+        let download_lock = self
+            .state
+            .lock()
+            .downloads
+            .entry(remote_path.clone())
+            .or_default()
+            .clone();
+        let _guard = download_lock.lock().await;
+        // Could have been downloaded while we waited.
+        if let Some(path) = self.lookup(remote_path) {
+            return Ok(path);
+        }
+
+        let res = self.download(remote_path).await;
+        let evicted = {
+            let mut state = self.state.lock();
+            state.downloads.remove(remote_path);
+            let size = res?;
+            let entry = CacheEntry {
+                size,
+                last_used: Instant::now(),
+            };
+            if let Some(replaced) = state.entries.insert(remote_path.clone(), entry) {
+                state.total_size -= replaced.size;
+            }
+            state.total_size += size;
+            self.evict(&mut state, remote_path)
+        };
+        for path in evicted {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                warn!("failed to remove cached segment {}: {}", path, e);
+            }
+        }
+        Ok(remote_path.with_base(&self.dir))
+    }
+
+    fn lookup(&self, remote_path: &RemotePath) -> Option<Utf8PathBuf> {
+        let mut state = self.state.lock();
+        let entry = state.entries.get_mut(remote_path)?;
+        entry.last_used = Instant::now();
+        REMOTE_WAL_CACHE_HITS.inc();
+        Some(remote_path.with_base(&self.dir))
+    }
+
+    /// Download the segment, returning its size.
+    async fn download(&self, remote_path: &RemotePath) -> Result<u64> {
+        let path = remote_path.with_base(&self.dir);
+        let tmp_path = path.with_extension("tmp");
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut reader = read_object(remote_path, 0).await?;
+        let mut file = File::create(&tmp_path)
+            .await
+            .with_context(|| format!("failed to create {}", tmp_path))?;
+        let size = tokio::io::copy(&mut reader, &mut file)
+            .await
+            .with_context(|| format!("failed to download {:?}", remote_path))?;
+        // No fsync: the cache doesn't survive restarts anyway.
+        tokio::fs::rename(&tmp_path, &path).await?;
+        REMOTE_WAL_SEGMENT_READS.inc();
+        debug!("downloaded {:?} to the cache", remote_path);
+        Ok(size)
+    }
+
+    /// Forget least recently used segments until the cache fits max_size,
+    /// keeping the just added one, and return the files to remove. Readers
+    /// which already opened removed files can still read them.
+    fn evict(&self, state: &mut CacheState, keep: &RemotePath) -> Vec<Utf8PathBuf> {
+        let mut evicted = Vec::new();
+        while state.total_size > self.max_size {
+            let Some(victim) = state
+                .entries
+                .iter()
+                .filter(|(p, _)| *p != keep)
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(p, _)| p.clone())
+            else {
+                break;
+            };
+            let entry = state.entries.remove(&victim).expect("entry exists");
+            state.total_size -= entry.size;
+            evicted.push(victim.with_base(&self.dir));
+        }
+        evicted
+    }
+}
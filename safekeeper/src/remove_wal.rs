@@ -1,10 +1,16 @@
 //! Thread removing old WAL.
+//!
+//! If disk usage exceeds the configured threshold, WAL which is backed up and
+//! consumed by the pageserver is removed even if lagging peers still need it
+//! (like with WAL eviction), until usage drops below the threshold.
 
 use std::time::Duration;
 
+use anyhow::Context;
 use tokio::time::sleep;
 use tracing::*;
 
+use crate::metrics::{EMERGENCY_WAL_REMOVALS, WAL_DISK_USAGE_THRESHOLD_EXCEEDED};
 use crate::{GlobalTimelines, SafeKeeperConf};
 
 const ALLOW_INACTIVE_TIMELINES: bool = true;
@@ -14,6 +20,16 @@ pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
     loop {
         let now = tokio::time::Instant::now();
         let mut active_timelines = 0;
+        let mut emergency = disk_usage_exceeded(&conf);
+        if emergency {
+            if conf.is_wal_backup_enabled() {
+                warn!("disk usage is above the threshold, removing WAL regardless of peers");
+                EMERGENCY_WAL_REMOVALS.inc();
+            } else {
+                warn!("disk usage is above the threshold, but WAL backup is disabled, can't remove WAL");
+                emergency = false;
+            }
+        }
 
         let tlis = GlobalTimelines::get_all();
         for tli in &tlis {
@@ -29,12 +45,23 @@ pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
                 if let Err(e) = tli.maybe_persist_control_file().await {
                     warn!("failed to persist control file: {e}");
                 }
-                if let Err(e) = tli.remove_old_wal(conf.wal_backup_enabled).await {
+                if let Err(e) = tli
+                    .remove_old_wal(
+                        conf.wal_backup_enabled,
+                        conf.is_wal_eviction_enabled() || emergency,
+                    )
+                    .await
+                {
                     error!("failed to remove WAL: {}", e);
                 }
             }
             .instrument(info_span!("WAL removal", ttid = %ttid))
             .await;
+
+            if emergency {
+                // stop ignoring peers as soon as we are below the threshold
+                emergency = disk_usage_exceeded(&conf);
+            }
         }
 
         let elapsed = now.elapsed();
@@ -50,3 +77,31 @@ pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
         sleep(wal_removal_interval).await;
     }
 }
+
+/// Check whether disk usage is above wal_disk_usage_threshold_pct, updating
+/// the metric.
+fn disk_usage_exceeded(conf: &SafeKeeperConf) -> bool {
+    let Some(threshold_pct) = conf.wal_disk_usage_threshold_pct else {
+        return false;
+    };
+    let exceeded = match disk_usage_pct(conf) {
+        Ok(usage_pct) => usage_pct >= threshold_pct as f64,
+        Err(e) => {
+            warn!("failed to get disk usage: {e:#}");
+            false
+        }
+    };
+    WAL_DISK_USAGE_THRESHOLD_EXCEEDED.set(exceeded as i64);
+    exceeded
+}
+
+fn disk_usage_pct(conf: &SafeKeeperConf) -> anyhow::Result<f64> {
+    let total = fs2::total_space(&conf.workdir)
+        .with_context(|| format!("failed to get total space of {}", conf.workdir))?;
+    let available = fs2::available_space(&conf.workdir)
+        .with_context(|| format!("failed to get available space of {}", conf.workdir))?;
+    if total == 0 {
+        anyhow::bail!("total space of {} is zero", conf.workdir);
+    }
+    Ok(total.saturating_sub(available) as f64 * 100.0 / total as f64)
+}
@@ -0,0 +1,296 @@
+//! Background replication of a timeline from a safekeeper serving it to this
+//! one, used by an orchestrator to rebalance timelines between safekeepers.
+//!
+//! Unlike pull_timeline, which takes a one-off snapshot, replication keeps
+//! streaming WAL from the source while the original set of safekeepers keeps
+//! serving compute, until it is stopped, normally after the membership switch.
+//! It starts with pulling the timeline if it doesn't exist here yet, and then
+//! repeatedly streams WAL from the source the same way peer recovery does.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::*;
+use utils::id::{NodeId, TenantTimelineId};
+use utils::lsn::Lsn;
+
+use crate::http::routes::TimelineStatus;
+use crate::recovery::{recover, Donor};
+use crate::timeline::Timeline;
+use crate::{pull_timeline, GlobalTimelines, SafeKeeperConf};
+
+/// How often to check the source and stream WAL from it if we lag.
+const CHECK_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// How long the status of a finished replication is kept for the
+/// orchestrator to see it.
+const FINISHED_RETENTION: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub source_id: NodeId,
+    /// HTTP endpoint of the source safekeeper, host:port.
+    pub source_http_connstr: String,
+    /// Postgres protocol endpoint of the source safekeeper, host:port.
+    pub source_pg_connstr: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicationState {
+    /// Pulling snapshot of the timeline from the source.
+    Pulling,
+    /// Streaming WAL from the source.
+    Streaming,
+    /// Stopped by request.
+    Stopped,
+    /// Failed to pull the timeline or the timeline was deleted, see
+    /// `last_error`.
+    Failed,
+}
+
+/// Replication progress reported via HTTP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub source_id: NodeId,
+    pub state: ReplicationState,
+    pub started_at: DateTime<Utc>,
+    /// flush_lsn of the source at the last check.
+    pub source_flush_lsn: Lsn,
+    /// Our flush_lsn at the last check.
+    pub flush_lsn: Lsn,
+    pub lag_bytes: u64,
+    /// Last error. Errors while streaming are retried.
+    pub last_error: Option<String>,
+}
+
+struct Replication {
+    id: u64,
+    status: Status,
+    cancel: CancellationToken,
+    /// When the replication was stopped or failed.
+    finished_at: Option<Instant>,
+}
+
+static NEXT_REPLICATION_ID: AtomicU64 = AtomicU64::new(0);
+
+static REPLICATIONS: Lazy<Mutex<HashMap<TenantTimelineId, Replication>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+impl Status {
+    fn is_running(&self) -> bool {
+        matches!(
+            self.state,
+            ReplicationState::Pulling | ReplicationState::Streaming
+        )
+    }
+}
+
+impl Replication {
+    fn finish(&mut self, state: ReplicationState, error: Option<String>) {
+        self.cancel.cancel();
+        self.status.state = state;
+        if error.is_some() {
+            self.status.last_error = error;
+        }
+        self.finished_at = Some(Instant::now());
+    }
+}
+
+/// Forget replications finished long enough ago.
+fn prune_finished(replications: &mut HashMap<TenantTimelineId, Replication>) {
+    replications.retain(|_, r| {
+        r.finished_at.map_or(true, |finished_at| {
+            finished_at.elapsed() < FINISHED_RETENTION
+        })
+    });
+}
+
+/// Start replicating the timeline from the source in background.
+pub fn start(ttid: TenantTimelineId, request: Request, conf: SafeKeeperConf) -> Result<Status> {
+    let mut replications = REPLICATIONS.lock();
+    prune_finished(&mut replications);
+    if let Some(replication) = replications.get(&ttid) {
+        if replication.status.is_running() {
+            bail!(
+                "timeline {} is already being replicated from safekeeper {}",
+                ttid,
+                replication.status.source_id
+            );
+        }
+    }
+
+    let id = NEXT_REPLICATION_ID.fetch_add(1, Ordering::Relaxed);
+    let status = Status {
+        source_id: request.source_id,
+        state: ReplicationState::Pulling,
+        started_at: Utc::now(),
+        source_flush_lsn: Lsn::INVALID,
+        flush_lsn: Lsn::INVALID,
+        lag_bytes: 0,
+        last_error: None,
+    };
+    let cancel = CancellationToken::new();
+    replications.insert(
+        ttid,
+        Replication {
+            id,
+            status: status.clone(),
+            cancel: cancel.clone(),
+            finished_at: None,
+        },
+    );
+
+    let span = info_span!("replicate timeline", %ttid, source = %request.source_id);
+    tokio::spawn(
+        async move {
+            info!("started");
+            match replicate(ttid, id, &request, &conf, &cancel).await {
+                Ok(()) => info!("stopped"),
+                Err(e) => {
+                    error!("failed: {e:#}");
+                    let mut replications = REPLICATIONS.lock();
+                    if let Some(replication) = replications.get_mut(&ttid) {
+                        if replication.id == id {
+                            replication.finish(ReplicationState::Failed, Some(format!("{e:#}")));
+                        }
+                    }
+                }
+            }
+        }
+        .instrument(span),
+    );
+    Ok(status)
+}
+
+/// Get status of the last replication of the timeline.
+pub fn get_status(ttid: &TenantTimelineId) -> Option<Status> {
+    let mut replications = REPLICATIONS.lock();
+    prune_finished(&mut replications);
+    replications.get(ttid).map(|r| r.status.clone())
+}
+
+/// Stop replication of the timeline, if it runs.
+pub fn stop(ttid: &TenantTimelineId) -> Option<Status> {
+    let mut replications = REPLICATIONS.lock();
+    prune_finished(&mut replications);
+    let replication = replications.get_mut(ttid)?;
+    if replication.status.is_running() {
+        replication.finish(ReplicationState::Stopped, None);
+    }
+    Some(replication.status.clone())
+}
+
+/// Apply `f` to the status, unless replication has been restarted since.
+fn update_status(ttid: TenantTimelineId, id: u64, f: impl FnOnce(&mut Status)) {
+    if let Some(replication) = REPLICATIONS.lock().get_mut(&ttid) {
+        if replication.id == id {
+            f(&mut replication.status);
+        }
+    }
+}
+
+/// Pull the timeline if needed and keep streaming WAL from the source until
+/// `cancel` fires. Fails if the pull fails or the timeline is deleted.
+async fn replicate(
+    ttid: TenantTimelineId,
+    id: u64,
+    request: &Request,
+    conf: &SafeKeeperConf,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    if GlobalTimelines::get(ttid).is_err() {
+        info!("pulling timeline");
+        let pull = pull_timeline::handle_request(pull_timeline::Request {
+            tenant_id: ttid.tenant_id,
+            timeline_id: ttid.timeline_id,
+            http_hosts: vec![format!("http://{}", request.source_http_connstr)],
+        });
+        tokio::select! {
+            res = pull => { res.context("pulling timeline")?; }
+            _ = cancel.cancelled() => return Ok(()),
+        }
+    }
+    let tli = GlobalTimelines::get(ttid)?;
+    update_status(ttid, id, |s| s.state = ReplicationState::Streaming);
+
+    let client = reqwest::Client::new();
+    while !cancel.is_cancelled() {
+        if tli.is_cancelled() {
+            bail!("timeline was deleted");
+        }
+        tokio::select! {
+            res = stream_from_source(&client, &tli, id, request, conf) => {
+                if let Err(e) = res {
+                    warn!("streaming failed: {e:#}");
+                    update_status(ttid, id, |s| s.last_error = Some(format!("{e:#}")));
+                }
+            }
+            _ = cancel.cancelled() => break,
+        }
+        tokio::select! {
+            _ = sleep(CHECK_INTERVAL) => {}
+            _ = cancel.cancelled() => break,
+        }
+    }
+    Ok(())
+}
+
+/// Check source state, update progress and stream WAL from the source if we
+/// lag behind it. Streaming finishes once we catch up.
+async fn stream_from_source(
+    client: &reqwest::Client,
+    tli: &Arc<Timeline>,
+    id: u64,
+    request: &Request,
+    conf: &SafeKeeperConf,
+) -> Result<()> {
+    let mut req = client.get(format!(
+        "http://{}/v1/tenant/{}/timeline/{}",
+        request.source_http_connstr, tli.ttid.tenant_id, tli.ttid.timeline_id
+    ));
+    if let Some(token) = &conf.sk_auth_token {
+        req = req.bearer_auth(token.as_str());
+    }
+    let source_status: TimelineStatus = req.send().await?.error_for_status()?.json().await?;
+
+    let flush_lsn = tli.get_flush_lsn().await;
+    update_status(tli.ttid, id, |s| {
+        s.source_flush_lsn = source_status.flush_lsn;
+        s.flush_lsn = flush_lsn;
+        s.lag_bytes = source_status.flush_lsn.0.saturating_sub(flush_lsn.0);
+    });
+    if flush_lsn >= source_status.flush_lsn {
+        return Ok(());
+    }
+
+    // As in peer recovery, we can act on behalf of the source only if it has
+    // been elected in its term.
+    let acceptor_state = &source_status.acceptor_state;
+    if acceptor_state.term != acceptor_state.epoch {
+        bail!(
+            "source term {} differs from its last log term {}, election is likely in progress",
+            acceptor_state.term,
+            acceptor_state.epoch
+        );
+    }
+    let donor = Donor {
+        sk_id: request.source_id,
+        term: acceptor_state.term,
+        flush_lsn: source_status.flush_lsn,
+        pg_connstr: request.source_pg_connstr.clone(),
+        http_connstr: request.source_http_connstr.clone(),
+    };
+    let msg = recover(tli.clone(), &donor, conf).await?;
+    info!("streaming finished: {}", msg);
+    Ok(())
+}
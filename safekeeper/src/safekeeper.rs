@@ -16,6 +16,7 @@ use storage_broker::proto::SafekeeperTimelineInfo;
 use tracing::*;
 
 use crate::control_file;
+use crate::membership::{Configuration, Generation, MemberSet, INVALID_GENERATION};
 use crate::send_wal::HotStandbyFeedback;
 
 use crate::state::TimelineState;
@@ -28,7 +29,10 @@ use utils::{
     lsn::Lsn,
 };
 
-const SK_PROTOCOL_VERSION: u32 = 2;
+/// Version 3 adds membership configuration generation to the greeting.
+const SK_PROTOCOL_VERSION: u32 = 3;
+/// Oldest proposer-acceptor protocol version still accepted.
+const MIN_SK_PROTOCOL_VERSION: u32 = 2;
 pub const UNKNOWN_SERVER_VERSION: u32 = 0;
 
 /// Consensus logical timestamp.
@@ -253,6 +257,10 @@ pub struct ProposerGreeting {
     pub tenant_id: TenantId,
     pub tli: TimeLineID,
     pub wal_seg_size: u32,
+    /// Generation of membership configuration proposer is running with,
+    /// sent since protocol version 3.
+    #[serde(skip)]
+    pub mconf_generation: Generation,
 }
 
 /// Acceptor -> Proposer initial response: the highest term known to me
@@ -261,6 +269,9 @@ pub struct ProposerGreeting {
 pub struct AcceptorGreeting {
     term: u64,
     node_id: NodeId,
+    /// Membership configuration, sent since protocol version 3 so that
+    /// proposer can count votes and acknowledgments of its members.
+    mconf: Option<Configuration>,
 }
 
 /// Vote request sent from proposer to safekeepers
@@ -369,7 +380,10 @@ impl ProposerAcceptorMessage {
         let tag = stream.read_u64::<LittleEndian>()? as u8 as char;
         match tag {
             'g' => {
-                let msg = ProposerGreeting::des_from(&mut stream)?;
+                let mut msg = ProposerGreeting::des_from(&mut stream)?;
+                if msg.protocol_version >= 3 {
+                    msg.mconf_generation = stream.read_u32::<LittleEndian>()?;
+                }
                 Ok(ProposerAcceptorMessage::Greeting(msg))
             }
             'v' => {
@@ -431,6 +445,14 @@ pub enum AcceptorProposerMessage {
     AppendResponse(AppendResponse),
 }
 
+/// Serialize ids of the members, which is all proposer needs to know of them.
+fn serialize_member_ids(members: &MemberSet, buf: &mut BytesMut) {
+    buf.put_u32_le(members.members.len() as u32);
+    for member in &members.members {
+        buf.put_u64_le(member.id.0);
+    }
+}
+
 impl AcceptorProposerMessage {
     /// Serialize acceptor -> proposer message.
     pub fn serialize(&self, buf: &mut BytesMut) -> Result<()> {
@@ -439,6 +461,17 @@ impl AcceptorProposerMessage {
                 buf.put_u64_le('g' as u64);
                 buf.put_u64_le(msg.term);
                 buf.put_u64_le(msg.node_id.0);
+                if let Some(mconf) = &msg.mconf {
+                    buf.put_u32_le(mconf.generation);
+                    serialize_member_ids(&mconf.members, buf);
+                    match &mconf.new_members {
+                        Some(new_members) => {
+                            buf.put_u8(1);
+                            serialize_member_ids(new_members, buf);
+                        }
+                        None => buf.put_u8(0),
+                    }
+                }
             }
             AcceptorProposerMessage::VoteResponse(msg) => {
                 buf.put_u64_le('v' as u64);
@@ -563,10 +596,11 @@ where
         msg: &ProposerGreeting,
     ) -> Result<Option<AcceptorProposerMessage>> {
         // Check protocol compatibility
-        if msg.protocol_version != SK_PROTOCOL_VERSION {
+        if !(MIN_SK_PROTOCOL_VERSION..=SK_PROTOCOL_VERSION).contains(&msg.protocol_version) {
             bail!(
-                "incompatible protocol version {}, expected {}",
+                "incompatible protocol version {}, expected {}..={}",
                 msg.protocol_version,
+                MIN_SK_PROTOCOL_VERSION,
                 SK_PROTOCOL_VERSION
             );
         }
@@ -605,6 +639,16 @@ where
                 self.state.server.wal_seg_size
             );
         }
+        // Proposers of older configurations are fenced off. Newer generation
+        // is fine, we'll be switched to it eventually.
+        let generation = self.state.mconf.generation;
+        if generation != INVALID_GENERATION && msg.mconf_generation < generation {
+            bail!(
+                "proposer membership generation {} is older than ours {}",
+                msg.mconf_generation,
+                generation
+            );
+        }
 
         // system_id will be updated on mismatch
         // sync-safekeepers doesn't know sysid and sends 0, ignore it
@@ -632,6 +676,7 @@ where
         Ok(Some(AcceptorProposerMessage::Greeting(AcceptorGreeting {
             term: self.state.acceptor_state.term,
             node_id: self.node_id,
+            mconf: (msg.protocol_version >= 3).then(|| self.state.mconf.clone()),
         })))
     }
 
@@ -651,6 +696,13 @@ where
         // only source of WAL; with peer2peer recovery it would be more
         // important.
         self.wal_store.flush_wal().await?;
+        if !self.state.mconf.is_voter(self.node_id) {
+            bail!(
+                "refusing to vote, safekeeper {} is not a member of configuration {}",
+                self.node_id,
+                self.state.mconf
+            );
+        }
         // initialize with refusal
         let mut resp = VoteResponse {
             term: self.state.acceptor_state.term,
@@ -949,14 +1001,19 @@ where
 
     /// Get oldest segno we still need to keep. We hold WAL till it is consumed
     /// by all of 1) pageserver (remote_consistent_lsn) 2) peers 3) s3
-    /// offloading.
+    /// offloading. With WAL eviction, peers are not waited for: WAL they
+    /// still need is downloaded from s3 on demand.
     /// While it is safe to use inmem values for determining horizon,
     /// we use persistent to make possible normal states less surprising.
-    pub fn get_horizon_segno(&self, wal_backup_enabled: bool) -> XLogSegNo {
-        let mut horizon_lsn = min(
-            self.state.remote_consistent_lsn,
-            self.state.peer_horizon_lsn,
-        );
+    pub fn get_horizon_segno(
+        &self,
+        wal_backup_enabled: bool,
+        wal_eviction_enabled: bool,
+    ) -> XLogSegNo {
+        let mut horizon_lsn = self.state.remote_consistent_lsn;
+        if !(wal_backup_enabled && wal_eviction_enabled) {
+            horizon_lsn = min(horizon_lsn, self.state.peer_horizon_lsn);
+        }
         if wal_backup_enabled {
             horizon_lsn = min(horizon_lsn, self.state.backup_lsn);
         }
@@ -971,6 +1028,7 @@ mod tests {
 
     use super::*;
     use crate::{
+        membership::SafekeeperId,
         state::{PersistedPeers, TimelinePersistentState},
         wal_storage::Storage,
     };
@@ -1074,6 +1132,85 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_voting_not_member() {
+        let mut state = test_sk_state();
+        state.mconf = Configuration {
+            generation: 1,
+            members: MemberSet::new(vec![SafekeeperId {
+                id: NodeId(1),
+                host: "sk-1".to_string(),
+                pg_port: 5454,
+            }])
+            .unwrap(),
+            new_members: None,
+        };
+        let storage = InMemoryState {
+            persisted_state: state,
+        };
+        let wal_store = DummyWalStore { lsn: Lsn(0) };
+        let mut sk = SafeKeeper::new(storage, wal_store, NodeId(0)).unwrap();
+
+        // safekeeper 0 is excluded from the configuration and must not vote
+        let vote_request = ProposerAcceptorMessage::VoteRequest(VoteRequest { term: 1 });
+        assert!(sk.process_msg(&vote_request).await.is_err());
+        assert_eq!(sk.state.acceptor_state.term, 0);
+    }
+
+    #[tokio::test]
+    async fn test_greeting_mconf() {
+        let mut state = test_sk_state();
+        state.mconf = Configuration {
+            generation: 2,
+            members: MemberSet::new(vec![SafekeeperId {
+                id: NodeId(1),
+                host: "sk-1".to_string(),
+                pg_port: 5454,
+            }])
+            .unwrap(),
+            new_members: None,
+        };
+        let storage = InMemoryState {
+            persisted_state: state,
+        };
+        let wal_store = DummyWalStore { lsn: Lsn(0) };
+        let mut sk = SafeKeeper::new(storage, wal_store, NodeId(1)).unwrap();
+
+        let greeting = |protocol_version, mconf_generation| {
+            ProposerAcceptorMessage::Greeting(ProposerGreeting {
+                protocol_version,
+                pg_version: 160000,
+                proposer_id: [0; 16],
+                system_id: 0,
+                timeline_id: TimelineId::from([1u8; 16]),
+                tenant_id: TenantId::from([1u8; 16]),
+                tli: 1,
+                wal_seg_size: WAL_SEGMENT_SIZE as u32,
+                mconf_generation,
+            })
+        };
+        let serialized = |resp: Option<AcceptorProposerMessage>| {
+            let mut buf = BytesMut::new();
+            resp.unwrap().serialize(&mut buf).unwrap();
+            buf
+        };
+
+        // proposer of an older configuration is fenced off
+        assert!(sk.process_msg(&greeting(3, 1)).await.is_err());
+
+        // configuration is sent to proposers which know protocol version 3
+        let resp = serialized(sk.process_msg(&greeting(3, 2)).await.unwrap());
+        let mut expected = BytesMut::new();
+        expected.put_u64_le('g' as u64);
+        expected.put_u64_le(0); // term
+        expected.put_u64_le(1); // node_id
+        expected.put_u32_le(2); // generation
+        expected.put_u32_le(1); // number of members
+        expected.put_u64_le(1);
+        expected.put_u8(0); // no new members
+        assert_eq!(resp, expected);
+    }
+
     #[tokio::test]
     async fn test_epoch_switch() {
         let storage = InMemoryState {
@@ -1132,6 +1269,38 @@ mod tests {
         assert_eq!(sk.get_epoch(), 1);
     }
 
+    #[test]
+    fn test_get_horizon_segno() {
+        let seg_lsn = |segno: u64| Lsn(segno * WAL_SEGMENT_SIZE as u64 + 42);
+        let new_sk = |remote_consistent_segno, peer_horizon_segno, backup_segno| {
+            let mut state = test_sk_state();
+            state.remote_consistent_lsn = seg_lsn(remote_consistent_segno);
+            state.peer_horizon_lsn = seg_lsn(peer_horizon_segno);
+            state.backup_lsn = seg_lsn(backup_segno);
+            let storage = InMemoryState {
+                persisted_state: state,
+            };
+            let wal_store = DummyWalStore { lsn: seg_lsn(6) };
+            SafeKeeper::new(storage, wal_store, NodeId(0)).unwrap()
+        };
+
+        let sk = new_sk(5, 2, 4);
+        // without eviction WAL is kept for peers
+        assert_eq!(sk.get_horizon_segno(false, false), 2);
+        assert_eq!(sk.get_horizon_segno(true, false), 2);
+        // evicted WAL must be in remote storage, so eviction needs backup
+        assert_eq!(sk.get_horizon_segno(false, true), 2);
+        // with eviction peers are not waited for, but backup is
+        assert_eq!(sk.get_horizon_segno(true, true), 4);
+
+        // pageserver is waited for in any case
+        let sk = new_sk(3, 2, 4);
+        assert_eq!(sk.get_horizon_segno(true, true), 3);
+        let sk = new_sk(1, 2, 4);
+        assert_eq!(sk.get_horizon_segno(true, false), 1);
+        assert_eq!(sk.get_horizon_segno(true, true), 1);
+    }
+
     #[test]
     fn test_find_highest_common_point_none() {
         let prop_th = TermHistory(vec![(0, Lsn(1)).into()]);
@@ -1233,6 +1402,7 @@ mod tests {
                     commit_lsn: Lsn(1234567600),
                 },
             )]),
+            mconf: Configuration::empty(),
         };
 
         let ser = state.ser().unwrap();
@@ -1278,6 +1448,12 @@ mod tests {
             0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x70, 0x02, 0x96, 0x49, 0x00, 0x00, 0x00, 0x00,
             0xb0, 0x01, 0x96, 0x49, 0x00, 0x00, 0x00, 0x00,
+            // mconf generation
+            0x00, 0x00, 0x00, 0x00,
+            // length prefix for members
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // new_members is None
+            0x00,
         ];
 
         assert_eq!(Hex(&ser), Hex(&expected));
@@ -12,6 +12,7 @@ use utils::{
 
 use crate::{
     control_file,
+    membership::Configuration,
     safekeeper::{AcceptorState, PersistedPeerInfo, PgUuid, ServerInfo, TermHistory},
 };
 
@@ -59,6 +60,8 @@ pub struct TimelinePersistentState {
     // obviously can be stale. (Currently not saved at all, but let's provision
     // place to have less file version upgrades).
     pub peers: PersistedPeers,
+    /// Membership configuration of the timeline.
+    pub mconf: Configuration,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -93,6 +96,7 @@ impl TimelinePersistentState {
                     .map(|p| (*p, PersistedPeerInfo::new()))
                     .collect(),
             ),
+            mconf: Configuration::empty(),
         }
     }
 
@@ -3,6 +3,7 @@
 
 use anyhow::{anyhow, bail, Result};
 use camino::Utf8PathBuf;
+use postgres_ffi::v14::xlog_utils::{IsPartialXLogFileName, IsXLogFileName};
 use postgres_ffi::XLogSegNo;
 use serde::{Deserialize, Serialize};
 use tokio::fs;
@@ -20,11 +21,13 @@ use utils::http::error::ApiError;
 use utils::{
     id::{NodeId, TenantTimelineId},
     lsn::Lsn,
+    pageserver_feedback::PageserverFeedback,
 };
 
 use storage_broker::proto::SafekeeperTimelineInfo;
 use storage_broker::proto::TenantTimelineId as ProtoTenantTimelineId;
 
+use crate::membership::{Configuration, SwitchResult};
 use crate::receive_wal::WalReceivers;
 use crate::recovery::{recovery_main, Donor, RecoveryNeededInfo};
 use crate::safekeeper::{
@@ -54,6 +57,9 @@ pub struct PeerInfo {
     /// Since which LSN safekeeper has WAL. TODO: remove this once we fill new
     /// sk since backup_lsn.
     pub local_start_lsn: Lsn,
+    /// Last LSN received by the pageserver streaming from the peer.
+    #[serde(default)]
+    pub ps_last_received_lsn: Lsn,
     /// When info was received. Serde annotations are not very useful but make
     /// the code compile -- we don't rely on this field externally.
     #[serde(skip)]
@@ -63,6 +69,43 @@ pub struct PeerInfo {
     pub http_connstr: String,
 }
 
+/// Position of a safekeeper in the log, deciding whether it can recover from
+/// another one.
+struct RecoveryPosition {
+    term: Term,
+    last_log_term: Term,
+    flush_lsn: Lsn,
+}
+
+impl RecoveryPosition {
+    /// Donor must be ahead of us, and we must be able to act on behalf of
+    /// the leader of its last log term without (re)running elections. It is
+    /// possible if 1) donor term is equal to its last_log_term, so we are sure
+    /// the leader was ever elected and 2) our term is not higher, or we'll
+    /// refuse data.
+    fn can_recover_from(&self, donor: &RecoveryPosition) -> bool {
+        let my_tl = TermLsn {
+            term: self.last_log_term,
+            lsn: self.flush_lsn,
+        };
+        let donor_tl = TermLsn {
+            term: donor.last_log_term,
+            lsn: donor.flush_lsn,
+        };
+        my_tl < donor_tl && donor.term == donor.last_log_term && donor.term >= self.term
+    }
+}
+
+impl From<&PeerInfo> for RecoveryPosition {
+    fn from(p: &PeerInfo) -> Self {
+        RecoveryPosition {
+            term: p.term,
+            last_log_term: p.last_log_term,
+            flush_lsn: p.flush_lsn,
+        }
+    }
+}
+
 impl PeerInfo {
     fn from_sk_info(sk_info: &SafekeeperTimelineInfo, ts: Instant) -> PeerInfo {
         PeerInfo {
@@ -72,11 +115,17 @@ impl PeerInfo {
             flush_lsn: Lsn(sk_info.flush_lsn),
             commit_lsn: Lsn(sk_info.commit_lsn),
             local_start_lsn: Lsn(sk_info.local_start_lsn),
+            ps_last_received_lsn: Lsn(sk_info.ps_last_received_lsn),
             pg_connstr: sk_info.safekeeper_connstr.clone(),
             http_connstr: sk_info.http_connstr.clone(),
             ts,
         }
     }
+
+    /// Time since the info was received.
+    pub fn last_heard(&self) -> Duration {
+        self.ts.elapsed()
+    }
 }
 
 // vector-based node id -> peer state map with very limited functionality we
@@ -116,6 +165,14 @@ pub struct SharedState {
     /// when tli is inactive instead of having this flag.
     active: bool,
     last_removed_segno: XLogSegNo,
+    /// End of the WAL uploaded as partial segment by this safekeeper, invalid if
+    /// partial backup hasn't run since the timeline was loaded.
+    partial_backup_lsn: Lsn,
+    /// heartbeat_timeout if peer recovery is enabled. Then the timeline stays
+    /// active while some alive peer can recover from us or we from it, so that
+    /// both keep pushing to the broker until the lagging one catches up, even
+    /// without a compute.
+    peer_recovery_heartbeat_timeout: Option<Duration>,
 }
 
 impl SharedState {
@@ -155,6 +212,10 @@ impl SharedState {
             wal_backup_active: false,
             active: false,
             last_removed_segno: 0,
+            partial_backup_lsn: Lsn::INVALID,
+            peer_recovery_heartbeat_timeout: conf
+                .peer_recovery_enabled
+                .then_some(conf.heartbeat_timeout),
         })
     }
 
@@ -174,6 +235,10 @@ impl SharedState {
             wal_backup_active: false,
             active: false,
             last_removed_segno: 0,
+            partial_backup_lsn: Lsn::INVALID,
+            peer_recovery_heartbeat_timeout: conf
+                .peer_recovery_enabled
+                .then_some(conf.heartbeat_timeout),
         })
     }
 
@@ -182,6 +247,23 @@ impl SharedState {
             // FIXME: add tracking of relevant pageservers and check them here individually,
             // otherwise migration won't work (we suspend too early).
             || self.sk.state.inmem.remote_consistent_lsn < self.sk.state.inmem.commit_lsn
+            || self.is_peer_recovery_pending()
+    }
+
+    /// Can some alive peer recover from us, or we from it?
+    fn is_peer_recovery_pending(&self) -> bool {
+        let Some(heartbeat_timeout) = self.peer_recovery_heartbeat_timeout else {
+            return false;
+        };
+        let me = RecoveryPosition {
+            term: self.sk.state.acceptor_state.term,
+            last_log_term: self.sk.get_epoch(),
+            flush_lsn: self.sk.flush_lsn(),
+        };
+        self.get_peers(heartbeat_timeout).iter().any(|p| {
+            let peer = RecoveryPosition::from(p);
+            me.can_recover_from(&peer) || peer.can_recover_from(&me)
+        })
     }
 
     /// Mark timeline active/inactive and return whether s3 offloading requires
@@ -213,7 +295,11 @@ impl SharedState {
         num_computes > 0 ||
         // Currently only the whole segment is offloaded, so compare segment numbers.
             (self.sk.state.inmem.commit_lsn.segment_number(seg_size) >
-             self.sk.state.inmem.backup_lsn.segment_number(seg_size))
+             self.sk.state.inmem.backup_lsn.segment_number(seg_size)) ||
+        // Once partial backup is running, keep it until the tail of WAL is uploaded.
+            (self.partial_backup_lsn.is_valid() &&
+             self.sk.state.inmem.commit_lsn >
+             max(self.partial_backup_lsn, self.sk.state.inmem.backup_lsn))
     }
 
     /// Is current state of s3 offloading is not what it ought to be?
@@ -248,6 +334,7 @@ impl SharedState {
         &self,
         ttid: &TenantTimelineId,
         conf: &SafeKeeperConf,
+        ps_feedback: &PageserverFeedback,
     ) -> SafekeeperTimelineInfo {
         SafekeeperTimelineInfo {
             safekeeper_id: conf.my_id.0,
@@ -270,9 +357,23 @@ impl SharedState {
             backup_lsn: self.sk.state.inmem.backup_lsn.0,
             local_start_lsn: self.sk.state.local_start_lsn.0,
             availability_zone: conf.availability_zone.clone(),
+            ps_last_received_lsn: ps_feedback.last_received_lsn.0,
         }
     }
 
+    /// Pageserver feedback to pass to compute in AppendResponse. Feedback of
+    /// pageservers streaming from us is complemented with pageserver progress
+    /// learnt from peers through the broker, so that compute is throttled
+    /// according to pageserver lag even if the pageserver streams from
+    /// another safekeeper.
+    fn aggregate_ps_feedback(&self, local: PageserverFeedback) -> PageserverFeedback {
+        aggregate_ps_feedback(
+            local,
+            self.sk.state.inmem.remote_consistent_lsn,
+            &self.peers_info,
+        )
+    }
+
     /// Get our latest view of alive peers status on the timeline.
     /// We pass our own info through the broker as well, so when we don't have connection
     /// to the broker returned vec is empty.
@@ -298,6 +399,8 @@ pub enum TimelineError {
     Invalid(TenantTimelineId),
     #[error("Timeline {0} is already exists")]
     AlreadyExists(TenantTimelineId),
+    #[error("Timeline {0} was deleted and cannot be created again")]
+    Deleted(TenantTimelineId),
     #[error("Timeline {0} is not initialized, wal_seg_size is zero")]
     UninitializedWalSegSize(TenantTimelineId),
     #[error("Timeline {0} is not initialized, pg_version is unknown")]
@@ -311,6 +414,7 @@ impl From<TimelineError> for ApiError {
             TimelineError::NotFound(ttid) => {
                 ApiError::NotFound(anyhow!("timeline {} not found", ttid).into())
             }
+            TimelineError::Deleted(_) => ApiError::Conflict(te.to_string()),
             _ => ApiError::InternalServerError(anyhow!("{}", te)),
         }
     }
@@ -630,7 +734,7 @@ impl Timeline {
             if let Some(AcceptorProposerMessage::AppendResponse(ref mut resp)) = rmsg {
                 let (ps_feedback, hs_feedback) = self.walsenders.get_feedbacks();
                 resp.hs_feedback = hs_feedback;
-                resp.pageserver_feedback = ps_feedback;
+                resp.pageserver_feedback = shared_state.aggregate_ps_feedback(ps_feedback);
             }
 
             commit_lsn = shared_state.sk.state.inmem.commit_lsn;
@@ -680,10 +784,17 @@ impl Timeline {
         Ok(())
     }
 
+    /// Records the end of the WAL uploaded as partial segment.
+    pub async fn set_partial_backup_lsn(&self, partial_backup_lsn: Lsn) {
+        let mut state = self.write_shared_state().await;
+        state.partial_backup_lsn = max(state.partial_backup_lsn, partial_backup_lsn);
+    }
+
     /// Get safekeeper info for broadcasting to broker and other peers.
     pub async fn get_safekeeper_info(&self, conf: &SafeKeeperConf) -> SafekeeperTimelineInfo {
+        let ps_feedback = self.walsenders.get_ps_feedback();
         let shared_state = self.write_shared_state().await;
-        shared_state.get_safekeeper_info(&self.ttid, conf)
+        shared_state.get_safekeeper_info(&self.ttid, conf, &ps_feedback)
     }
 
     /// Update timeline state with peer safekeeper data.
@@ -718,6 +829,35 @@ impl Timeline {
         shared_state.get_peers(conf.heartbeat_timeout)
     }
 
+    /// Returns all peers we have heard of, including the ones not regarded
+    /// alive anymore.
+    pub async fn get_all_peers(&self) -> Vec<PeerInfo> {
+        let shared_state = self.write_shared_state().await;
+        shared_state.peers_info.0.clone()
+    }
+
+    /// Returns total size of WAL segments in the timeline directory.
+    pub async fn wal_disk_usage(&self) -> Result<u64> {
+        let mut usage = 0;
+        let mut entries = fs::read_dir(&self.timeline_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let fname = entry.file_name();
+            let Some(fname) = fname.to_str() else {
+                continue;
+            };
+            if !IsXLogFileName(fname) && !IsPartialXLogFileName(fname) {
+                continue;
+            }
+            // segment might be removed concurrently
+            match entry.metadata().await {
+                Ok(metadata) => usage += metadata.len(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(usage)
+    }
+
     /// Should we start fetching WAL from a peer safekeeper, and if yes, from
     /// which? Answer is yes, i.e. .donors is not empty if 1) there is something
     /// to fetch, and we can do that without running elections; 2) there is no
@@ -758,33 +898,15 @@ impl Timeline {
         let donors = if num_streaming_computes > 0 {
             vec![] // If there is a streaming compute, don't try to recover to not intervene.
         } else {
+            let me = RecoveryPosition {
+                term,
+                last_log_term,
+                flush_lsn,
+            };
             peers
                 .iter()
-                .filter_map(|candidate| {
-                    // Are we interested in this candidate?
-                    let candidate_tl = TermLsn {
-                        term: candidate.last_log_term,
-                        lsn: candidate.flush_lsn,
-                    };
-                    let my_tl = TermLsn {
-                        term: last_log_term,
-                        lsn: flush_lsn,
-                    };
-                    if my_tl < candidate_tl {
-                        // Yes, we are interested. Can we pull from it without
-                        // (re)running elections? It is possible if 1) his term
-                        // is equal to his last_log_term so we could act on
-                        // behalf of leader of this term (we must be sure he was
-                        // ever elected) and 2) our term is not higher, or we'll refuse data.
-                        if candidate.term == candidate.last_log_term && candidate.term >= term {
-                            Some(Donor::from(candidate))
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })
+                .filter(|candidate| me.can_recover_from(&RecoveryPosition::from(*candidate)))
+                .map(Donor::from)
                 .collect()
         };
         RecoveryNeededInfo {
@@ -812,7 +934,12 @@ impl Timeline {
 
     /// Delete WAL segments from disk that are no longer needed. This is determined
     /// based on pageserver's remote_consistent_lsn and local backup_lsn/peer_lsn.
-    pub async fn remove_old_wal(&self, wal_backup_enabled: bool) -> Result<()> {
+    /// If `wal_eviction_enabled`, peer_lsn is ignored.
+    pub async fn remove_old_wal(
+        &self,
+        wal_backup_enabled: bool,
+        wal_eviction_enabled: bool,
+    ) -> Result<()> {
         if self.is_cancelled() {
             bail!(TimelineError::Cancelled(self.ttid));
         }
@@ -820,7 +947,9 @@ impl Timeline {
         let horizon_segno: XLogSegNo;
         let remover = {
             let shared_state = self.write_shared_state().await;
-            horizon_segno = shared_state.sk.get_horizon_segno(wal_backup_enabled);
+            horizon_segno = shared_state
+                .sk
+                .get_horizon_segno(wal_backup_enabled, wal_eviction_enabled);
             if horizon_segno <= 1 || horizon_segno <= shared_state.last_removed_segno {
                 return Ok(()); // nothing to do
             }
@@ -888,7 +1017,9 @@ impl Timeline {
         debug_dump::Memory {
             is_cancelled: self.is_cancelled(),
             peers_info_len: state.peers_info.0.len(),
+            peers: state.peers_info.0.clone(),
             walsenders: self.walsenders.get_all(),
+            walreceivers: self.walreceivers.get_all(),
             wal_backup_active: state.wal_backup_active,
             active: state.active,
             num_computes: self.walreceivers.get_num() as u32,
@@ -902,6 +1033,31 @@ impl Timeline {
         }
     }
 
+    /// Switch to membership configuration `to` if its generation is higher
+    /// than the current one, otherwise leave the configuration as is.
+    pub async fn membership_switch(&self, to: Configuration) -> Result<SwitchResult> {
+        self.map_control_file(|state| {
+            let previous_conf = state.mconf.clone();
+            if to.generation > previous_conf.generation {
+                info!(
+                    "switching membership configuration from {} to {}",
+                    previous_conf, to
+                );
+                state.mconf = to;
+            } else {
+                info!(
+                    "not switching membership configuration to {}, current is {}",
+                    to, previous_conf
+                );
+            }
+            Ok(SwitchResult {
+                previous_conf,
+                current_conf: state.mconf.clone(),
+            })
+        })
+        .await
+    }
+
     /// Apply a function to the control file state and persist it.
     pub async fn map_control_file<T>(
         &self,
@@ -917,6 +1073,21 @@ impl Timeline {
     }
 }
 
+/// Complement the feedback of the pageservers streaming from us with the
+/// remote_consistent_lsn we know of and the pageserver progress on peers.
+fn aggregate_ps_feedback(
+    local: PageserverFeedback,
+    remote_consistent_lsn: Lsn,
+    peers: &PeersInfo,
+) -> PageserverFeedback {
+    let mut feedback = local;
+    feedback.remote_consistent_lsn = max(feedback.remote_consistent_lsn, remote_consistent_lsn);
+    for peer in &peers.0 {
+        feedback.last_received_lsn = max(feedback.last_received_lsn, peer.ps_last_received_lsn);
+    }
+    feedback
+}
+
 /// Deletes directory and it's contents. Returns false if directory does not exist.
 async fn delete_dir(path: &Utf8PathBuf) -> Result<bool> {
     match fs::remove_dir_all(path).await {
@@ -925,3 +1096,52 @@ async fn delete_dir(path: &Utf8PathBuf) -> Result<bool> {
         Err(e) => Err(e.into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn peer(sk_id: u64, ps_last_received_lsn: Lsn) -> PeerInfo {
+        PeerInfo {
+            sk_id: NodeId(sk_id),
+            term: 1,
+            last_log_term: 1,
+            flush_lsn: Lsn(0x300),
+            commit_lsn: Lsn(0x300),
+            local_start_lsn: Lsn(0),
+            ps_last_received_lsn,
+            ts: Instant::now(),
+            pg_connstr: String::new(),
+            http_connstr: String::new(),
+        }
+    }
+
+    #[test]
+    fn ps_feedback_from_peers() {
+        // No pageserver streams from us, but one streams from a peer.
+        let peers = PeersInfo(vec![peer(2, Lsn(0x200)), peer(3, Lsn::INVALID)]);
+        let feedback = aggregate_ps_feedback(PageserverFeedback::empty(), Lsn(0x100), &peers);
+        assert_eq!(feedback.last_received_lsn, Lsn(0x200));
+        assert_eq!(feedback.remote_consistent_lsn, Lsn(0x100));
+        assert_eq!(feedback.disk_consistent_lsn, Lsn::INVALID);
+        assert_eq!(feedback.current_timeline_size, 0);
+    }
+
+    #[test]
+    fn ps_feedback_local_ahead() {
+        // The feedback of the pageserver streaming from us is more recent than
+        // what we learnt from peers, and is kept as is.
+        let local = PageserverFeedback {
+            current_timeline_size: 42,
+            last_received_lsn: Lsn(0x300),
+            disk_consistent_lsn: Lsn(0x250),
+            remote_consistent_lsn: Lsn(0x200),
+            replytime: SystemTime::now(),
+        };
+        let peers = PeersInfo(vec![peer(2, Lsn(0x280))]);
+        let feedback = aggregate_ps_feedback(local, Lsn(0x100), &peers);
+        assert_eq!(feedback, local);
+    }
+}
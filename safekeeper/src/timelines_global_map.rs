@@ -4,7 +4,7 @@
 
 use crate::safekeeper::ServerInfo;
 use crate::timeline::{Timeline, TimelineError};
-use crate::SafeKeeperConf;
+use crate::{wal_backup, SafeKeeperConf};
 use anyhow::{bail, Context, Result};
 use camino::Utf8PathBuf;
 use once_cell::sync::Lazy;
@@ -12,13 +12,20 @@ use serde::Serialize;
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use tokio::sync::mpsc::Sender;
 use tracing::*;
+use utils::crashsafe::fsync_async_opt;
 use utils::id::{TenantId, TenantTimelineId, TimelineId};
 use utils::lsn::Lsn;
 
 struct GlobalTimelinesState {
     timelines: HashMap<TenantTimelineId, Arc<Timeline>>,
+    /// Deleted timelines with the time of deletion, persisted in
+    /// `SafeKeeperConf::tombstones_dir`. They can't be created again until the
+    /// tombstone expires, otherwise a lagging compute could resurrect a
+    /// timeline after its deletion.
+    tombstones: HashMap<TenantTimelineId, SystemTime>,
     wal_backup_launcher_tx: Option<Sender<TenantTimelineId>>,
     conf: Option<SafeKeeperConf>,
     load_lock: Arc<tokio::sync::Mutex<TimelineLoadLock>>,
@@ -60,11 +67,19 @@ impl GlobalTimelinesState {
             .cloned()
             .ok_or(TimelineError::NotFound(*ttid))
     }
+
+    /// Whether timeline has a tombstone which hasn't expired yet.
+    fn is_deleted(&self, ttid: &TenantTimelineId) -> bool {
+        self.tombstones
+            .get(ttid)
+            .is_some_and(|deleted_at| !tombstone_expired(self.get_conf(), *deleted_at))
+    }
 }
 
 static TIMELINES_STATE: Lazy<Mutex<GlobalTimelinesState>> = Lazy::new(|| {
     Mutex::new(GlobalTimelinesState {
         timelines: HashMap::new(),
+        tombstones: HashMap::new(),
         wal_backup_launcher_tx: None,
         conf: None,
         load_lock: Arc::new(tokio::sync::Mutex::new(TimelineLoadLock)),
@@ -86,6 +101,7 @@ impl GlobalTimelines {
             let mut state = TIMELINES_STATE.lock().unwrap();
             assert!(state.wal_backup_launcher_tx.is_none());
             state.wal_backup_launcher_tx = Some(wal_backup_launcher_tx);
+            state.tombstones = load_tombstones(&conf)?;
             state.conf = Some(conf);
 
             // Iterate through all directories and load tenants for all directories
@@ -129,11 +145,12 @@ impl GlobalTimelines {
     /// this function is called during init when nothing else is running, so
     /// this is fine.
     async fn load_tenant_timelines(tenant_id: TenantId) -> Result<()> {
-        let (conf, wal_backup_launcher_tx) = {
+        let (conf, wal_backup_launcher_tx, tombstones) = {
             let state = TIMELINES_STATE.lock().unwrap();
             (
                 state.get_conf().clone(),
                 state.wal_backup_launcher_tx.as_ref().unwrap().clone(),
+                state.tombstones.clone(),
             )
         };
 
@@ -147,6 +164,12 @@ impl GlobalTimelines {
                         TimelineId::from_str(timeline_dir_entry.file_name().to_str().unwrap_or(""))
                     {
                         let ttid = TenantTimelineId::new(tenant_id, timeline_id);
+                        if tombstones.contains_key(&ttid) {
+                            // Deletion was interrupted, finish it.
+                            info!("removing directory of deleted timeline {}", ttid);
+                            delete_dir(conf.timeline_dir(&ttid))?;
+                            continue;
+                        }
                         match Timeline::load_timeline(&conf, ttid, wal_backup_launcher_tx.clone()) {
                             Ok(timeline) => {
                                 let tli = Arc::new(timeline);
@@ -235,8 +258,13 @@ impl GlobalTimelines {
                 // Timeline already exists, return it.
                 return Ok(timeline);
             }
+            if state.is_deleted(&ttid) {
+                bail!(TimelineError::Deleted(ttid));
+            }
             state.get_dependencies()
         };
+        // Tombstone may be left, but it has expired.
+        Self::remove_tombstone(&ttid).await?;
 
         info!("creating new timeline {}", ttid);
 
@@ -303,6 +331,44 @@ impl GlobalTimelines {
         }
     }
 
+    /// Whether timeline was deleted and can't be created again.
+    pub fn is_deleted(ttid: &TenantTimelineId) -> bool {
+        TIMELINES_STATE.lock().unwrap().is_deleted(ttid)
+    }
+
+    /// Allow deleted timeline to be created again. Used when timeline is
+    /// explicitly pulled back to the safekeeper.
+    pub async fn remove_tombstone(ttid: &TenantTimelineId) -> Result<()> {
+        let conf = {
+            let mut state = TIMELINES_STATE.lock().unwrap();
+            if state.tombstones.remove(ttid).is_none() {
+                return Ok(());
+            }
+            state.get_conf().clone()
+        };
+        info!("removing tombstone of timeline {}", ttid);
+        let path = tombstone_path(&conf, ttid);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => fsync_async_opt(path.parent().unwrap(), !conf.no_sync).await?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    /// Remove tombstones older than `SafeKeeperConf::tombstone_ttl`, by when
+    /// computes of the deleted timelines are expected to be gone.
+    async fn remove_expired_tombstones() -> Result<()> {
+        let expired = {
+            let state = TIMELINES_STATE.lock().unwrap();
+            expired_tombstones(state.get_conf(), &state.tombstones)
+        };
+        for ttid in expired {
+            Self::remove_tombstone(&ttid).await?;
+        }
+        Ok(())
+    }
+
     /// Returns all timelines. This is used for background timeline processes.
     pub fn get_all() -> Vec<Arc<Timeline>> {
         let global_lock = TIMELINES_STATE.lock().unwrap();
@@ -328,11 +394,38 @@ impl GlobalTimelines {
 
     /// Cancels timeline, then deletes the corresponding data directory.
     /// If only_local, doesn't remove WAL segments in remote storage.
+    ///
+    /// Before anything is removed a tombstone is persisted which prevents
+    /// creation of the timeline for `SafeKeeperConf::tombstone_ttl`, e.g. by a
+    /// compute which didn't notice the deletion; only pulling the timeline
+    /// overrides it. If deletion is interrupted, it is finished on the next
+    /// start. Timelines unknown to this safekeeper get no tombstone.
     pub async fn delete(
         ttid: &TenantTimelineId,
         only_local: bool,
     ) -> Result<TimelineDeleteForceResult> {
-        let tli_res = TIMELINES_STATE.lock().unwrap().get(ttid);
+        Self::remove_expired_tombstones().await?;
+
+        let (tli_res, conf, has_tombstone) = {
+            let state = TIMELINES_STATE.lock().unwrap();
+            (
+                state.get(ttid),
+                state.get_conf().clone(),
+                state.tombstones.contains_key(ttid),
+            )
+        };
+        let is_known = tli_res.is_ok() || conf.timeline_dir(ttid).exists();
+        if is_known && !has_tombstone {
+            // The tombstone takes effect only once it is durable, so that it
+            // is there after a restart whenever deletion has started.
+            persist_tombstone(&conf, ttid).await?;
+            TIMELINES_STATE
+                .lock()
+                .unwrap()
+                .tombstones
+                .insert(*ttid, SystemTime::now());
+        }
+
         match tli_res {
             Ok(timeline) => {
                 // Take a lock and finish the deletion holding this mutex.
@@ -342,10 +435,8 @@ impl GlobalTimelines {
                 let (dir_existed, was_active) =
                     timeline.delete(&mut shared_state, only_local).await?;
 
-                // Remove timeline from the map.
-                // FIXME: re-enable it once we fix the issue with recreation of deleted timelines
-                // https://github.com/neondatabase/neon/issues/3146
-                // TIMELINES_STATE.lock().unwrap().timelines.remove(ttid);
+                // Remove timeline from the map, tombstone prevents its recreation.
+                TIMELINES_STATE.lock().unwrap().timelines.remove(ttid);
 
                 Ok(TimelineDeleteForceResult {
                     dir_existed,
@@ -353,13 +444,12 @@ impl GlobalTimelines {
                 })
             }
             Err(_) => {
-                // Timeline is not memory, but it may still exist on disk in broken state.
-                let dir_path = TIMELINES_STATE
-                    .lock()
-                    .unwrap()
-                    .get_conf()
-                    .timeline_dir(ttid);
-                let dir_existed = delete_dir(dir_path)?;
+                // Timeline is not memory, but it may still exist on disk in broken
+                // state, or remote WAL may be left from previous deletion attempt.
+                if !only_local && conf.is_wal_backup_enabled() {
+                    wal_backup::delete_timeline(ttid).await?;
+                }
+                let dir_existed = delete_dir(conf.timeline_dir(ttid))?;
 
                 Ok(TimelineDeleteForceResult {
                     dir_existed,
@@ -415,16 +505,15 @@ impl GlobalTimelines {
                 .tenant_dir(tenant_id),
         )?;
 
-        // FIXME: we temporarily disabled removing timelines from the map, see `delete_force`
-        // let tlis_after_delete = Self::get_all_for_tenant(*tenant_id);
-        // if !tlis_after_delete.is_empty() {
-        //     // Some timelines were created while we were deleting them, returning error
-        //     // to the caller, so it can retry later.
-        //     bail!(
-        //         "failed to delete all timelines for tenant {}: some timelines were created while we were deleting them",
-        //         tenant_id
-        //     );
-        // }
+        let tlis_after_delete = Self::get_all_for_tenant(*tenant_id);
+        if !tlis_after_delete.is_empty() {
+            // Some timelines were created while we were deleting them, returning error
+            // to the caller, so it can retry later.
+            bail!(
+                "failed to delete all timelines for tenant {}: some timelines were created while we were deleting them",
+                tenant_id
+            );
+        }
 
         Ok(deleted)
     }
@@ -444,3 +533,129 @@ fn delete_dir(path: Utf8PathBuf) -> Result<bool> {
         Err(e) => Err(e.into()),
     }
 }
+
+fn tombstone_path(conf: &SafeKeeperConf, ttid: &TenantTimelineId) -> Utf8PathBuf {
+    conf.tombstones_dir()
+        .join(ttid.tenant_id.to_string())
+        .join(ttid.timeline_id.to_string())
+}
+
+/// Durably mark timeline as deleted.
+async fn persist_tombstone(conf: &SafeKeeperConf, ttid: &TenantTimelineId) -> Result<()> {
+    let path = tombstone_path(conf, ttid);
+    let tenant_dir = path.parent().unwrap();
+    tokio::fs::create_dir_all(tenant_dir)
+        .await
+        .with_context(|| format!("failed to create tombstones dir {}", tenant_dir))?;
+    tokio::fs::File::create(&path)
+        .await
+        .with_context(|| format!("failed to create tombstone {}", path))?;
+    fsync_async_opt(&path, !conf.no_sync).await?;
+    fsync_async_opt(tenant_dir, !conf.no_sync).await?;
+    fsync_async_opt(conf.tombstones_dir(), !conf.no_sync).await?;
+    Ok(())
+}
+
+fn tombstone_expired(conf: &SafeKeeperConf, deleted_at: SystemTime) -> bool {
+    deleted_at
+        .elapsed()
+        .is_ok_and(|age| age >= conf.tombstone_ttl)
+}
+
+fn expired_tombstones(
+    conf: &SafeKeeperConf,
+    tombstones: &HashMap<TenantTimelineId, SystemTime>,
+) -> Vec<TenantTimelineId> {
+    tombstones
+        .iter()
+        .filter(|(_, deleted_at)| tombstone_expired(conf, **deleted_at))
+        .map(|(ttid, _)| *ttid)
+        .collect()
+}
+
+/// Read tombstones of deleted timelines, with the modification time of the
+/// file as the time of deletion. Expired ones are removed.
+fn load_tombstones(conf: &SafeKeeperConf) -> Result<HashMap<TenantTimelineId, SystemTime>> {
+    let mut tombstones = HashMap::new();
+    let tombstones_dir = conf.tombstones_dir();
+    let tenant_entries = match std::fs::read_dir(&tombstones_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(tombstones),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to list {}", tombstones_dir));
+        }
+    };
+    for tenant_entry in tenant_entries {
+        let tenant_entry = tenant_entry?;
+        let Ok(tenant_id) = TenantId::from_str(tenant_entry.file_name().to_str().unwrap_or(""))
+        else {
+            continue;
+        };
+        for timeline_entry in std::fs::read_dir(tenant_entry.path())? {
+            let timeline_entry = timeline_entry?;
+            let Ok(timeline_id) =
+                TimelineId::from_str(timeline_entry.file_name().to_str().unwrap_or(""))
+            else {
+                continue;
+            };
+            let deleted_at = timeline_entry.metadata()?.modified()?;
+            if tombstone_expired(conf, deleted_at) {
+                std::fs::remove_file(timeline_entry.path())?;
+                continue;
+            }
+            tombstones.insert(TenantTimelineId::new(tenant_id, timeline_id), deleted_at);
+        }
+    }
+    info!(
+        "loaded {} tombstones of deleted timelines",
+        tombstones.len()
+    );
+    Ok(tombstones)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn stub_conf() -> SafeKeeperConf {
+        let workdir = camino_tempfile::tempdir().unwrap().into_path();
+        SafeKeeperConf {
+            workdir,
+            ..SafeKeeperConf::dummy()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tombstones_survive_restart() {
+        let conf = stub_conf();
+        let ttid = TenantTimelineId::generate();
+        persist_tombstone(&conf, &ttid).await.unwrap();
+
+        let tombstones = load_tombstones(&conf).unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert!(!tombstone_expired(&conf, tombstones[&ttid]));
+        assert!(expired_tombstones(&conf, &tombstones).is_empty());
+        assert!(tombstone_path(&conf, &ttid).exists());
+    }
+
+    #[tokio::test]
+    async fn test_tombstones_expire() {
+        let mut conf = stub_conf();
+        let expiring = TenantTimelineId::generate();
+        let staying = TenantTimelineId::generate();
+        let now = SystemTime::now();
+        let tombstones = HashMap::from([
+            (expiring, now - Duration::from_secs(61)),
+            (staying, now - Duration::from_secs(59)),
+        ]);
+        conf.tombstone_ttl = Duration::from_secs(60);
+        assert_eq!(expired_tombstones(&conf, &tombstones), vec![expiring]);
+
+        // expired tombstones are removed on load
+        persist_tombstone(&conf, &expiring).await.unwrap();
+        conf.tombstone_ttl = Duration::ZERO;
+        assert!(load_tombstones(&conf).unwrap().is_empty());
+        assert!(!tombstone_path(&conf, &expiring).exists());
+    }
+}
@@ -15,11 +15,14 @@ use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
+use async_compression::tokio::bufread::{ZstdDecoder, ZstdEncoder};
+use bytes::Bytes;
 use postgres_ffi::v14::xlog_utils::XLogSegNoOffsetToRecPtr;
 use postgres_ffi::XLogFileName;
 use postgres_ffi::{XLogSegNo, PG_TLI};
-use remote_storage::{GenericRemoteStorage, RemotePath};
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
 use tokio::fs::File;
+use tokio::io::AsyncReadExt;
 
 use tokio::select;
 use tokio::sync::mpsc::{self, Receiver, Sender};
@@ -31,10 +34,14 @@ use utils::{id::TenantTimelineId, lsn::Lsn};
 
 use crate::metrics::{BACKED_UP_SEGMENTS, BACKUP_ERRORS};
 use crate::timeline::{PeerInfo, Timeline};
+use crate::wal_backup_partial::PartialBackup;
 use crate::{GlobalTimelines, SafeKeeperConf};
 
 use once_cell::sync::OnceCell;
 
+/// Suffix of segments compressed with zstd in remote storage.
+pub const COMPRESSED_SEGMENT_SUFFIX: &str = "zst";
+
 const UPLOAD_FAILURE_RETRY_MIN_MS: u64 = 10;
 const UPLOAD_FAILURE_RETRY_MAX_MS: u64 = 5000;
 
@@ -147,12 +154,18 @@ async fn update_task(
             let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
             let timeline_dir = conf.timeline_dir(&ttid);
 
+            let partial_backup_timeout = conf
+                .partial_backup_enabled
+                .then_some(conf.partial_backup_timeout);
             let handle = tokio::spawn(
                 backup_task_main(
                     ttid,
                     timeline_dir,
                     conf.workdir.clone(),
                     conf.backup_parallel_jobs,
+                    conf.wal_backup_compression,
+                    conf.my_id,
+                    partial_backup_timeout,
                     shutdown_rx,
                 )
                 .in_current_span(),
@@ -172,7 +185,7 @@ async fn update_task(
 static REMOTE_STORAGE: OnceCell<Option<GenericRemoteStorage>> = OnceCell::new();
 
 // Storage must be configured and initialized when this is called.
-fn get_configured_remote_storage() -> &'static GenericRemoteStorage {
+pub(crate) fn get_configured_remote_storage() -> &'static GenericRemoteStorage {
     REMOTE_STORAGE
         .get()
         .expect("failed to get remote storage")
@@ -255,6 +268,7 @@ struct WalBackupTask {
     workspace_dir: Utf8PathBuf,
     wal_seg_size: usize,
     parallel_jobs: usize,
+    compression: bool,
     commit_lsn_watch_rx: watch::Receiver<Lsn>,
 }
 
@@ -264,6 +278,9 @@ async fn backup_task_main(
     timeline_dir: Utf8PathBuf,
     workspace_dir: Utf8PathBuf,
     parallel_jobs: usize,
+    compression: bool,
+    my_id: NodeId,
+    partial_backup_timeout: Option<Duration>,
     mut shutdown_rx: Receiver<()>,
 ) {
     info!("started");
@@ -274,6 +291,23 @@ async fn backup_task_main(
     }
     let tli = res.unwrap();
 
+    let mut partial_backup = match partial_backup_timeout {
+        Some(timeout) => match remote_timeline_path(&timeline_dir, &workspace_dir) {
+            Ok(remote_timeline_path) => Some(PartialBackup::new(
+                tli.clone(),
+                remote_timeline_path,
+                tli.get_wal_seg_size().await,
+                my_id,
+                timeout,
+            )),
+            Err(e) => {
+                error!("partial backup disabled: {e:#}");
+                None
+            }
+        },
+        None => None,
+    };
+
     let mut wb = WalBackupTask {
         wal_seg_size: tli.get_wal_seg_size().await,
         commit_lsn_watch_rx: tli.get_commit_lsn_watch_rx(),
@@ -281,14 +315,23 @@ async fn backup_task_main(
         timeline_dir,
         workspace_dir,
         parallel_jobs,
+        compression,
     };
 
     // task is spinned up only when wal_seg_size already initialized
     assert!(wb.wal_seg_size > 0);
 
+    let partial_backup_run = async {
+        match partial_backup.as_mut() {
+            Some(partial_backup) => partial_backup.run().await,
+            None => futures::future::pending::<()>().await,
+        }
+    };
+
     let mut canceled = false;
     select! {
         _ = wb.run() => {}
+        _ = partial_backup_run => {}
         _ = shutdown_rx.recv() => {
             canceled = true;
         }
@@ -348,6 +391,7 @@ impl WalBackupTask {
                 &self.timeline_dir,
                 &self.workspace_dir,
                 self.parallel_jobs,
+                self.compression,
             )
             .await
             {
@@ -375,6 +419,7 @@ async fn backup_lsn_range(
     timeline_dir: &Utf8Path,
     workspace_dir: &Utf8Path,
     parallel_jobs: usize,
+    compression: bool,
 ) -> Result<()> {
     if parallel_jobs < 1 {
         anyhow::bail!("parallel_jobs must be >= 1");
@@ -392,7 +437,12 @@ async fn backup_lsn_range(
     loop {
         let added_task = match iter.next() {
             Some(s) => {
-                uploads.push_back(backup_single_segment(s, timeline_dir, workspace_dir));
+                uploads.push_back(backup_single_segment(
+                    s,
+                    timeline_dir,
+                    workspace_dir,
+                    compression,
+                ));
                 true
             }
             None => false,
@@ -427,10 +477,24 @@ async fn backup_lsn_range(
     Ok(())
 }
 
+/// Remote path of the timeline, mirroring its local path relative to the workdir.
+fn remote_timeline_path(timeline_dir: &Utf8Path, workspace_dir: &Utf8Path) -> Result<RemotePath> {
+    timeline_dir
+        .strip_prefix(workspace_dir)
+        .context("Failed to strip workspace dir prefix")
+        .and_then(RemotePath::new)
+        .with_context(|| {
+            format!(
+                "Failed to resolve remote part of path {timeline_dir:?} for base {workspace_dir:?}",
+            )
+        })
+}
+
 async fn backup_single_segment(
     seg: &Segment,
     timeline_dir: &Utf8Path,
     workspace_dir: &Utf8Path,
+    compression: bool,
 ) -> Result<Segment> {
     let segment_file_path = seg.file_path(timeline_dir)?;
     let remote_segment_path = segment_file_path
@@ -443,7 +507,15 @@ async fn backup_single_segment(
             )
         })?;
 
-    let res = backup_object(&segment_file_path, &remote_segment_path, seg.size()).await;
+    let res = if compression {
+        backup_object_compressed(
+            &segment_file_path,
+            &compressed_segment_path(&remote_segment_path),
+        )
+        .await
+    } else {
+        backup_object(&segment_file_path, &remote_segment_path, seg.size()).await
+    };
     if res.is_ok() {
         BACKED_UP_SEGMENTS.inc();
     } else {
@@ -514,6 +586,40 @@ async fn backup_object(
     storage.upload_storage_object(file, size, target_file).await
 }
 
+/// Compress the segment with zstd and upload it. Compressed size is not known
+/// in advance, so the segment is compressed in memory.
+async fn backup_object_compressed(source_file: &Utf8Path, target_file: &RemotePath) -> Result<()> {
+    let storage = get_configured_remote_storage();
+
+    let file = File::open(&source_file)
+        .await
+        .with_context(|| format!("Failed to open file {source_file:?} for wal backup"))?;
+    let mut encoder = ZstdEncoder::new(tokio::io::BufReader::with_capacity(BUFFER_SIZE, file));
+    let mut compressed = Vec::new();
+    encoder
+        .read_to_end(&mut compressed)
+        .await
+        .with_context(|| format!("Failed to compress file {source_file:?}"))?;
+
+    let size = compressed.len();
+    let stream = futures::stream::once(futures::future::ready(Ok(Bytes::from(compressed))));
+    storage
+        .upload_storage_object(stream, size, target_file)
+        .await
+}
+
+/// Remote path of zstd compressed segment stored instead of `segment_path`.
+pub fn compressed_segment_path(segment_path: &RemotePath) -> RemotePath {
+    RemotePath::from_string(&format!(
+        "{}.{}",
+        segment_path.get_path(),
+        COMPRESSED_SEGMENT_SUFFIX
+    ))
+    .expect("segment path is relative")
+}
+
+/// Read WAL segment stored at `file_path` starting at `offset`. If it doesn't
+/// exist, try the compressed version uploaded with --wal-backup-compression.
 pub async fn read_object(
     file_path: &RemotePath,
     offset: u64,
@@ -526,12 +632,18 @@ pub async fn read_object(
 
     info!("segment download about to start from remote path {file_path:?} at offset {offset}");
 
-    let download = storage
+    let download = match storage
         .download_storage_object(Some((offset, None)), file_path)
         .await
-        .with_context(|| {
-            format!("Failed to open WAL segment download stream for remote path {file_path:?}")
-        })?;
+    {
+        Ok(download) => download,
+        Err(DownloadError::NotFound) => return read_compressed_object(file_path, offset).await,
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!("Failed to open WAL segment download stream for remote path {file_path:?}")
+            })
+        }
+    };
 
     let reader = tokio_util::io::StreamReader::new(download.download_stream);
 
@@ -540,6 +652,34 @@ pub async fn read_object(
     Ok(Box::pin(reader))
 }
 
+async fn read_compressed_object(
+    file_path: &RemotePath,
+    offset: u64,
+) -> anyhow::Result<Pin<Box<dyn tokio::io::AsyncRead + Send + Sync>>> {
+    let storage = get_configured_remote_storage();
+    let compressed_path = compressed_segment_path(file_path);
+
+    let download = storage.download(&compressed_path).await.with_context(|| {
+        format!("Failed to open WAL segment download stream for remote path {compressed_path:?}")
+    })?;
+
+    let reader = tokio_util::io::StreamReader::new(download.download_stream);
+    let reader = tokio::io::BufReader::with_capacity(BUFFER_SIZE, reader);
+    let mut reader = tokio::io::BufReader::with_capacity(BUFFER_SIZE, ZstdDecoder::new(reader));
+
+    // Compressed stream can't be downloaded from the offset, skip to it.
+    let skipped = tokio::io::copy(&mut (&mut reader).take(offset), &mut tokio::io::sink())
+        .await
+        .with_context(|| format!("Failed to decompress {compressed_path:?}"))?;
+    if skipped != offset {
+        anyhow::bail!(
+            "compressed segment {compressed_path:?} is shorter than offset {offset}: {skipped}"
+        );
+    }
+
+    Ok(Box::pin(reader))
+}
+
 /// Delete WAL files for the given timeline. Remote storage must be configured
 /// when called.
 pub async fn delete_timeline(ttid: &TenantTimelineId) -> Result<()> {
@@ -631,15 +771,30 @@ pub async fn copy_s3_segments(
     let relative_src_path =
         Utf8Path::new(&src_ttid.tenant_id.to_string()).join(src_ttid.timeline_id.to_string());
 
+    let src_files = storage
+        .list_files(Some(&RemotePath::new(&relative_src_path)?), None)
+        .await?;
+    let src_segments = &src_files
+        .iter()
+        .filter_map(|file| file.object_name().map(ToOwned::to_owned))
+        .collect::<HashSet<_>>();
+
     for segno in from_segment..to_segment {
         if segno % SEGMENTS_PROGRESS_REPORT_INTERVAL == 0 {
             info!("copied all segments from {} until {}", from_segment, segno);
         }
 
-        let segment_name = XLogFileName(PG_TLI, segno, wal_seg_size);
-        if uploaded_segments.contains(&segment_name) {
+        let plain_name = XLogFileName(PG_TLI, segno, wal_seg_size);
+        let compressed_name = format!("{plain_name}.{COMPRESSED_SEGMENT_SUFFIX}");
+        if uploaded_segments.contains(&plain_name) || uploaded_segments.contains(&compressed_name) {
             continue;
         }
+        // Segment could have been uploaded compressed, copy it as is.
+        let segment_name = if src_segments.contains(&compressed_name) {
+            compressed_name
+        } else {
+            plain_name
+        };
         debug!("copying segment {}", segment_name);
 
         let from = RemotePath::new(&relative_src_path.join(&segment_name))?;
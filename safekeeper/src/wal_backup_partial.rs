@@ -0,0 +1,282 @@
+//! Periodic upload of the partial (not yet complete) WAL segment to remote storage.
+//!
+//! Regular WAL backup only offloads complete segments, so up to a segment worth of
+//! acknowledged WAL would otherwise exist only on safekeeper disks. The elected
+//! offloader additionally uploads the committed part of the current segment every
+//! `partial_backup_timeout`, as
+//!
+//!   <tenant_id>/<timeline_id>/partial/<segment_file>_<term>_sk<node_id>
+//!
+//! where `term` is the last log term of the uploader's WAL. Within one term the
+//! uploaded prefix only grows, so a new upload overwrites the previous object of the
+//! same name. Objects of older terms are removed once an object for a newer term has
+//! been uploaded, and the node id keeps safekeepers from overwriting each other's
+//! objects while the offloader changes. Once the segment is fully backed up, all of
+//! its partial objects are removed.
+//!
+//! Readers prefer the full segment and otherwise use the partial object of the
+//! highest term that covers the requested position.
+
+use std::collections::BTreeSet;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use postgres_ffi::v14::xlog_utils::{IsXLogFileName, XLogFromFileName};
+use postgres_ffi::{XLogFileName, XLogSegNo, PG_TLI};
+use remote_storage::RemotePath;
+use tokio::io::AsyncReadExt;
+use tracing::*;
+use utils::{id::NodeId, lsn::Lsn};
+
+use crate::metrics::{BACKED_UP_PARTIAL_SEGMENTS, BACKUP_ERRORS};
+use crate::safekeeper::Term;
+use crate::timeline::Timeline;
+use crate::wal_backup::{self, get_configured_remote_storage};
+
+/// Name of the folder holding partial segments, relative to the timeline's path.
+const PARTIAL_FOLDER: &str = "partial";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PartialRemoteSegment {
+    pub segno: XLogSegNo,
+    pub term: Term,
+    pub sk_id: NodeId,
+}
+
+impl PartialRemoteSegment {
+    pub fn object_name(&self, wal_seg_size: usize) -> String {
+        format!(
+            "{}_{}_sk{}",
+            XLogFileName(PG_TLI, self.segno, wal_seg_size),
+            self.term,
+            self.sk_id
+        )
+    }
+
+    pub fn parse(name: &str, wal_seg_size: usize) -> Option<Self> {
+        let mut parts = name.split('_');
+        let segment = parts.next()?;
+        let term = parts.next()?.parse().ok()?;
+        let sk_id = parts.next()?.strip_prefix("sk")?.parse().ok()?;
+        if parts.next().is_some() || !IsXLogFileName(segment) {
+            return None;
+        }
+        let (segno, _) = XLogFromFileName(segment, wal_seg_size);
+
+        Some(Self { segno, term, sk_id })
+    }
+
+    fn remote_path(&self, remote_timeline_path: &RemotePath, wal_seg_size: usize) -> RemotePath {
+        remote_timeline_path
+            .join(Utf8Path::new(PARTIAL_FOLDER))
+            .join(Utf8Path::new(&self.object_name(wal_seg_size)))
+    }
+}
+
+/// List partial segments of the timeline in remote storage.
+async fn list_partial_segments(
+    remote_timeline_path: &RemotePath,
+    wal_seg_size: usize,
+) -> Result<Vec<PartialRemoteSegment>> {
+    let storage = get_configured_remote_storage();
+    let folder = remote_timeline_path.join(Utf8Path::new(PARTIAL_FOLDER));
+    let files = storage.list_files(Some(&folder), None).await?;
+
+    Ok(files
+        .iter()
+        .filter_map(|f| f.object_name())
+        .filter_map(|name| PartialRemoteSegment::parse(name, wal_seg_size))
+        .collect())
+}
+
+/// Open the partial segment `segno` of the highest term which has data at `offset`.
+pub async fn read_partial_segment(
+    remote_timeline_path: &RemotePath,
+    segno: XLogSegNo,
+    wal_seg_size: usize,
+    offset: u64,
+) -> Result<Pin<Box<dyn tokio::io::AsyncRead + Send + Sync>>> {
+    let mut candidates = list_partial_segments(remote_timeline_path, wal_seg_size)
+        .await?
+        .into_iter()
+        .filter(|s| s.segno == segno)
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|s| std::cmp::Reverse(s.term));
+
+    for candidate in candidates {
+        let path = candidate.remote_path(remote_timeline_path, wal_seg_size);
+        match wal_backup::read_object(&path, offset).await {
+            Ok(reader) => return Ok(reader),
+            Err(e) => debug!("partial segment {path:?} doesn't cover offset {offset}: {e:#}"),
+        }
+    }
+
+    anyhow::bail!("no partial segment for segno {segno} covers offset {offset}")
+}
+
+pub struct PartialBackup {
+    timeline: Arc<Timeline>,
+    remote_timeline_path: RemotePath,
+    wal_seg_size: usize,
+    my_id: NodeId,
+    timeout: Duration,
+    /// Partial segments known to exist in remote storage, from any safekeeper
+    uploaded: BTreeSet<PartialRemoteSegment>,
+    /// Term and end LSN of the last upload by this safekeeper
+    last_upload: Option<(Term, Lsn)>,
+}
+
+impl PartialBackup {
+    pub fn new(
+        timeline: Arc<Timeline>,
+        remote_timeline_path: RemotePath,
+        wal_seg_size: usize,
+        my_id: NodeId,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            timeline,
+            remote_timeline_path,
+            wal_seg_size,
+            my_id,
+            timeout,
+            uploaded: BTreeSet::new(),
+            last_upload: None,
+        }
+    }
+
+    /// Upload the partial segment every `timeout` until cancelled.
+    pub async fn run(&mut self) {
+        match list_partial_segments(&self.remote_timeline_path, self.wal_seg_size).await {
+            Ok(segments) => self.uploaded.extend(segments),
+            Err(e) => warn!("failed to list partial segments: {e:#}"),
+        }
+
+        let mut ticker = tokio::time::interval(self.timeout);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.upload_and_cleanup().await {
+                BACKUP_ERRORS.inc();
+                error!("partial segment backup failed: {e:#}");
+            }
+        }
+    }
+
+    async fn upload_and_cleanup(&mut self) -> Result<()> {
+        let (inmem, persistent) = self.timeline.get_state().await;
+        let flush_lsn = self.timeline.get_flush_lsn().await;
+        let term = persistent.acceptor_state.get_epoch(flush_lsn);
+        // Only committed WAL is uploaded, it is the same on all safekeepers.
+        let end_lsn = Lsn::min(inmem.commit_lsn, flush_lsn);
+        let segno = end_lsn.segment_number(self.wal_seg_size);
+
+        let fully_backed_up = inmem.backup_lsn.segment_number(self.wal_seg_size) > segno;
+        let empty = end_lsn.segment_offset(self.wal_seg_size) == 0;
+        if !fully_backed_up && !empty && self.last_upload != Some((term, end_lsn)) {
+            let segment = PartialRemoteSegment {
+                segno,
+                term,
+                sk_id: self.my_id,
+            };
+            self.upload(&segment, end_lsn).await?;
+            self.uploaded.insert(segment);
+            self.last_upload = Some((term, end_lsn));
+            self.timeline.set_partial_backup_lsn(end_lsn).await;
+        }
+
+        // Objects of fully backed up segments, and our own objects of older terms
+        // for the segment we just uploaded, are not needed anymore.
+        let backup_segno = inmem.backup_lsn.segment_number(self.wal_seg_size);
+        let to_delete = self
+            .uploaded
+            .iter()
+            .filter(|s| {
+                s.segno < backup_segno
+                    || (s.segno == segno && s.sk_id == self.my_id && s.term < term)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        if !to_delete.is_empty() {
+            let paths = to_delete
+                .iter()
+                .map(|s| s.remote_path(&self.remote_timeline_path, self.wal_seg_size))
+                .collect::<Vec<_>>();
+            get_configured_remote_storage()
+                .delete_objects(&paths)
+                .await?;
+            for s in to_delete {
+                self.uploaded.remove(&s);
+            }
+        }
+
+        // Let the launcher stop the backup task if the partial upload was the
+        // only thing left to do.
+        self.timeline.update_status_notify().await?;
+
+        Ok(())
+    }
+
+    async fn upload(&self, segment: &PartialRemoteSegment, end_lsn: Lsn) -> Result<()> {
+        let size = end_lsn.segment_offset(self.wal_seg_size);
+        let local_path =
+            self.timeline
+                .timeline_dir
+                .join(XLogFileName(PG_TLI, segment.segno, self.wal_seg_size));
+        let remote_path = segment.remote_path(&self.remote_timeline_path, self.wal_seg_size);
+
+        // The segment is normally still open locally, but it might have been
+        // completed and renamed since we looked at flush_lsn.
+        let file = match tokio::fs::File::open(local_path.with_extension("partial")).await {
+            Ok(file) => file,
+            Err(_) => tokio::fs::File::open(&local_path)
+                .await
+                .with_context(|| format!("Failed to open {local_path} for partial backup"))?,
+        };
+        let stream = tokio_util::io::ReaderStream::new(file.take(size as u64));
+
+        get_configured_remote_storage()
+            .upload_storage_object(stream, size, &remote_path)
+            .await?;
+
+        BACKED_UP_PARTIAL_SEGMENTS.inc();
+        info!(
+            "uploaded partial segment {} up to {end_lsn}",
+            segment.object_name(self.wal_seg_size)
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_segment_name_roundtrip() {
+        let wal_seg_size = 16 * 1024 * 1024;
+        let segment = PartialRemoteSegment {
+            segno: 0x142,
+            term: 7,
+            sk_id: NodeId(3),
+        };
+        let name = segment.object_name(wal_seg_size);
+        assert_eq!(name, "000000010000000100000042_7_sk3");
+        assert_eq!(
+            PartialRemoteSegment::parse(&name, wal_seg_size),
+            Some(segment)
+        );
+
+        assert_eq!(
+            PartialRemoteSegment::parse("000000010000000100000042", wal_seg_size),
+            None
+        );
+        assert_eq!(
+            PartialRemoteSegment::parse("000000010000000100000042_7_3", wal_seg_size),
+            None
+        );
+    }
+}
@@ -32,6 +32,10 @@ pub async fn task_main(
 
     loop {
         let (socket, peer_addr) = listener.accept().await.context("accept")?;
+        if is_partitioned() {
+            debug!("dropped connection from {}, partitioned", peer_addr);
+            continue;
+        }
         debug!("accepted connection from {}", peer_addr);
         let conf = conf.clone();
         let conn_id = issue_connection_id(&mut connection_count);
@@ -99,9 +103,31 @@ async fn handle_socket(
     let pgbackend = PostgresBackend::new_from_io(socket, peer_addr, auth_type, None)?;
     // libpq protocol between safekeeper and walproposer / pageserver
     // We don't use shutdown.
-    pgbackend
-        .run(&mut conn_handler, future::pending::<()>)
-        .await
+    tokio::select! {
+        res = pgbackend.run(&mut conn_handler, future::pending::<()>) => res,
+        _ = wait_partitioned() => {
+            info!("closed connection from {}, partitioned", peer_addr);
+            Ok(())
+        }
+    }
+}
+
+/// Whether the `sk-partition` failpoint is enabled, to emulate a network
+/// partition: the WAL service connections, existing and new, are closed, and
+/// nothing is published to the broker. Unlike in a real partition, the peers
+/// see their connections closed rather than timing out.
+pub fn is_partitioned() -> bool {
+    fail::fail_point!("sk-partition", |_| true);
+    false
+}
+
+async fn wait_partitioned() {
+    if !fail::has_failpoints() {
+        return future::pending::<()>().await;
+    }
+    while !is_partitioned() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
 }
 
 /// Unique WAL service connection ids are logged in spans for observability.
@@ -11,7 +11,9 @@ use anyhow::{bail, Context, Result};
 use bytes::Bytes;
 use camino::{Utf8Path, Utf8PathBuf};
 use futures::future::BoxFuture;
-use postgres_ffi::v14::xlog_utils::{IsPartialXLogFileName, IsXLogFileName, XLogFromFileName};
+use postgres_ffi::v14::xlog_utils::{
+    IsPartialXLogFileName, IsXLogFileName, XLogFromFileName, XLogSegNoOffsetToRecPtr,
+};
 use postgres_ffi::{dispatch_pgversion, XLogSegNo, PG_TLI};
 use remote_storage::RemotePath;
 use std::cmp::{max, min};
@@ -23,13 +25,20 @@ use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tracing::*;
 use utils::crashsafe::durable_rename;
 
-use crate::metrics::{time_io_closure, WalStorageMetrics, REMOVED_WAL_SEGMENTS};
+use crate::io_engine::SegmentFile;
+use crate::metrics::{
+    time_io_closure, WalStorageMetrics, REMOTE_WAL_SEGMENT_READS, REMOVED_WAL_SEGMENTS,
+};
+use crate::remote_wal_cache;
 use crate::state::TimelinePersistentState;
 use crate::wal_backup::read_object;
+use crate::wal_backup_partial::read_partial_segment;
 use crate::SafeKeeperConf;
+use postgres_ffi::v14::xlog_utils::XLOG_SIZE_OF_XLOG_LONG_PHD;
 use postgres_ffi::waldecoder::WalStreamDecoder;
 use postgres_ffi::XLogFileName;
 use postgres_ffi::XLOG_BLCKSZ;
+use postgres_ffi::{XLogRecord, XLOG_SIZE_OF_XLOG_RECORD, XLOG_SIZE_OF_XLOG_SHORT_PHD};
 use pq_proto::SystemId;
 use utils::{id::TenantTimelineId, lsn::Lsn};
 
@@ -92,13 +101,21 @@ pub struct PhysicalStorage {
     /// Decoder is required for detecting boundaries of WAL records.
     decoder: WalStreamDecoder,
 
+    /// End of the last record returned by the decoder, i.e. position where
+    /// the next record starts, not counting page headers.
+    decoded_lsn: Lsn,
+
+    /// Start of the last record returned by the decoder, to validate xl_prev
+    /// of the next one. None if the decoder has been restarted since.
+    prev_record_lsn: Option<Lsn>,
+
     /// Cached open file for the last segment.
     ///
     /// If Some(file) is open, then it always:
     /// - has ".partial" suffix
     /// - points to write_lsn, so no seek is needed for writing
     /// - doesn't point to the end of the segment
-    file: Option<File>,
+    file: Option<SegmentFile>,
 
     /// When false, we have just initialized storage using the LSN from find_end_of_wal().
     /// In this case, [`write_lsn`] can be less than actually written WAL on disk. In particular,
@@ -173,6 +190,8 @@ impl PhysicalStorage {
             write_record_lsn: write_lsn,
             flush_record_lsn: flush_lsn,
             decoder: WalStreamDecoder::new(write_lsn, state.server.pg_version / 10000),
+            decoded_lsn: write_lsn,
+            prev_record_lsn: None,
             file: None,
             is_truncated_after_restart: false,
         })
@@ -188,8 +207,62 @@ impl PhysicalStorage {
         )
     }
 
+    /// Feed WAL starting at `startpos` to the decoder, returning end of the
+    /// last complete record in it, if any. Decoder checks page headers and
+    /// record CRCs; if WAL validation is enabled, records are additionally
+    /// checked to be linked by xl_prev.
+    fn decode_wal(&mut self, startpos: Lsn, buf: &[u8]) -> Result<Option<Lsn>> {
+        if self.decoder.available() != startpos {
+            info!(
+                "restart decoder from {} to {}",
+                self.decoder.available(),
+                startpos,
+            );
+            let pg_version = self.decoder.pg_version;
+            self.decoder = WalStreamDecoder::new(startpos, pg_version);
+            self.decoded_lsn = startpos;
+            self.prev_record_lsn = None;
+        }
+        self.decoder.feed_bytes(buf);
+
+        let mut record_lsn = None;
+        // None means no full record yet
+        while let Some((end_lsn, rec)) = self.decoder.poll_decode()? {
+            let start_lsn = self.record_start(self.decoded_lsn);
+            if self.conf.validate_wal {
+                let xlogrec = XLogRecord::from_slice(&rec[0..XLOG_SIZE_OF_XLOG_RECORD])?;
+                if let Some(prev_record_lsn) = self.prev_record_lsn {
+                    if Lsn(xlogrec.xl_prev) != prev_record_lsn {
+                        bail!(
+                            "WAL validation failed: record at {} has xl_prev {}, expected {}",
+                            start_lsn,
+                            Lsn(xlogrec.xl_prev),
+                            prev_record_lsn
+                        );
+                    }
+                }
+            }
+            self.prev_record_lsn = Some(start_lsn);
+            self.decoded_lsn = end_lsn;
+            record_lsn = Some(end_lsn);
+        }
+        Ok(record_lsn)
+    }
+
+    /// Start of the record following one ending at `lsn`: records never
+    /// start at the page boundary, page header goes first.
+    fn record_start(&self, lsn: Lsn) -> Lsn {
+        if lsn.segment_offset(self.wal_seg_size) == 0 {
+            lsn + XLOG_SIZE_OF_XLOG_LONG_PHD as u64
+        } else if lsn.block_offset() == 0 {
+            lsn + XLOG_SIZE_OF_XLOG_SHORT_PHD as u64
+        } else {
+            lsn
+        }
+    }
+
     /// Call fdatasync if config requires so.
-    async fn fdatasync_file(&mut self, file: &File) -> Result<()> {
+    async fn fdatasync_file(&mut self, file: &mut SegmentFile) -> Result<()> {
         if !self.conf.no_sync {
             self.metrics
                 .observe_flush_seconds(time_io_closure(file.sync_data()).await?);
@@ -251,21 +324,16 @@ impl PhysicalStorage {
         let mut file = if let Some(file) = self.file.take() {
             file
         } else {
-            let (mut file, is_partial) = self.open_or_create(segno).await?;
+            let (file, is_partial) = self.open_or_create(segno).await?;
             assert!(is_partial, "unexpected write into non-partial segment file");
-            file.seek(SeekFrom::Start(xlogoff as u64)).await?;
-            file
+            SegmentFile::new(self.conf.wal_io_engine, file, xlogoff as u64).await?
         };
 
-        file.write_all(buf).await?;
-        // Note: flush just ensures write above reaches the OS (this is not
-        // needed in case of sync IO as Write::write there calls directly write
-        // syscall, but needed in case of async). It does *not* fsyncs the file.
-        file.flush().await?;
+        file.write_all_at(buf, xlogoff as u64).await?;
 
         if xlogoff + buf.len() == self.wal_seg_size {
             // If we reached the end of a WAL segment, flush and close it.
-            self.fdatasync_file(&file).await?;
+            self.fdatasync_file(&mut file).await?;
 
             // Rename partial file to completed file
             let (wal_file_path, wal_file_partial_path) =
@@ -287,8 +355,8 @@ impl PhysicalStorage {
     async fn write_exact(&mut self, pos: Lsn, mut buf: &[u8]) -> Result<()> {
         if self.write_lsn != pos {
             // need to flush the file before discarding it
-            if let Some(file) = self.file.take() {
-                self.fdatasync_file(&file).await?;
+            if let Some(mut file) = self.file.take() {
+                self.fdatasync_file(&mut file).await?;
             }
 
             self.write_lsn = pos;
@@ -342,6 +410,14 @@ impl Storage for PhysicalStorage {
             );
         }
 
+        // With validation, decode WAL before writing it, so that corrupted
+        // WAL is rejected instead of being persisted.
+        let validated_record_lsn = if self.conf.validate_wal {
+            Some(self.decode_wal(startpos, buf)?)
+        } else {
+            None
+        };
+
         let write_seconds = time_io_closure(self.write_exact(startpos, buf)).await?;
         // WAL is written, updating write metrics
         self.metrics.observe_write_seconds(write_seconds);
@@ -349,23 +425,12 @@ impl Storage for PhysicalStorage {
 
         // figure out last record's end lsn for reporting (if we got the
         // whole record)
-        if self.decoder.available() != startpos {
-            info!(
-                "restart decoder from {} to {}",
-                self.decoder.available(),
-                startpos,
-            );
-            let pg_version = self.decoder.pg_version;
-            self.decoder = WalStreamDecoder::new(startpos, pg_version);
-        }
-        self.decoder.feed_bytes(buf);
-        loop {
-            match self.decoder.poll_decode()? {
-                None => break, // no full record yet
-                Some((lsn, _rec)) => {
-                    self.write_record_lsn = lsn;
-                }
-            }
+        let record_lsn = match validated_record_lsn {
+            Some(record_lsn) => record_lsn,
+            None => self.decode_wal(startpos, buf)?,
+        };
+        if let Some(lsn) = record_lsn {
+            self.write_record_lsn = lsn;
         }
 
         Ok(())
@@ -377,8 +442,8 @@ impl Storage for PhysicalStorage {
             return Ok(());
         }
 
-        if let Some(unflushed_file) = self.file.take() {
-            self.fdatasync_file(&unflushed_file).await?;
+        if let Some(mut unflushed_file) = self.file.take() {
+            self.fdatasync_file(&mut unflushed_file).await?;
             self.file = Some(unflushed_file);
         } else {
             // We have unflushed data (write_lsn != flush_lsn), but no file.
@@ -420,8 +485,8 @@ impl Storage for PhysicalStorage {
         }
 
         // Close previously opened file, if any
-        if let Some(unflushed_file) = self.file.take() {
-            self.fdatasync_file(&unflushed_file).await?;
+        if let Some(mut unflushed_file) = self.file.take() {
+            self.fdatasync_file(&mut unflushed_file).await?;
         }
 
         let xlogoff = end_pos.segment_offset(self.wal_seg_size);
@@ -435,7 +500,7 @@ impl Storage for PhysicalStorage {
         // Fill end with zeroes
         file.seek(SeekFrom::Start(xlogoff as u64)).await?;
         write_zeroes(&mut file, self.wal_seg_size - xlogoff).await?;
-        self.fdatasync_file(&file).await?;
+        self.fdatasync_file(&mut SegmentFile::StdFs(file)).await?;
 
         if !is_partial {
             // Make segment partial once again
@@ -697,12 +762,69 @@ impl WalReader {
                         wal_file_path, self.workdir,
                     )
                 })?;
-            return read_object(&remote_wal_file_path, xlogoff as u64).await;
+            match remote_wal_cache::open_segment(&remote_wal_file_path, xlogoff as u64).await {
+                Ok(Some(file)) => {
+                    self.prefetch_next_segment(segno).await;
+                    return Ok(Box::pin(file));
+                }
+                Ok(None) => {} // cache is disabled
+                // Likely the segment is not fully uploaded yet.
+                Err(e) => debug!("failed to read segment through cache: {e:#}"),
+            }
+
+            let res = read_object(&remote_wal_file_path, xlogoff as u64).await;
+            if res.is_ok() {
+                REMOTE_WAL_SEGMENT_READS.inc();
+                return res;
+            }
+
+            // The segment might not be complete yet, but its committed part could
+            // have been uploaded as partial segment.
+            if let Some(remote_timeline_path) = remote_wal_file_path.get_path().parent() {
+                let remote_timeline_path = RemotePath::new(remote_timeline_path)?;
+                match read_partial_segment(
+                    &remote_timeline_path,
+                    segno,
+                    self.wal_seg_size,
+                    xlogoff as u64,
+                )
+                .await
+                {
+                    Ok(reader) => {
+                        REMOTE_WAL_SEGMENT_READS.inc();
+                        return Ok(reader);
+                    }
+                    Err(e) => debug!("falling back to partial segment failed: {e:#}"),
+                }
+            }
+            return res;
         }
 
         bail!("WAL segment is not found")
     }
 
+    /// Read ahead next segment into the remote WAL cache if it is not on disk
+    /// either.
+    async fn prefetch_next_segment(&self, segno: XLogSegNo) {
+        let next_segno = segno + 1;
+        let wal_file_name = XLogFileName(PG_TLI, next_segno, self.wal_seg_size);
+        let wal_file_path = self.timeline_dir.join(wal_file_name);
+        let next_segment_lsn = Lsn(XLogSegNoOffsetToRecPtr(next_segno, 0, self.wal_seg_size));
+        if next_segment_lsn >= self.local_start_lsn
+            && tokio::fs::try_exists(&wal_file_path).await.unwrap_or(false)
+        {
+            return;
+        }
+        match wal_file_path
+            .strip_prefix(&self.workdir)
+            .context("Failed to strip workdir prefix")
+            .and_then(RemotePath::new)
+        {
+            Ok(remote_wal_file_path) => remote_wal_cache::prefetch(remote_wal_file_path),
+            Err(e) => warn!("failed to resolve remote path of {}: {e:#}", wal_file_path),
+        }
+    }
+
     /// Helper function for opening a wal file.
     async fn open_wal_file(wal_file_path: &Utf8Path) -> Result<tokio::fs::File> {
         // First try to open the .partial file.
@@ -753,3 +875,96 @@ pub fn wal_file_paths(
     let wal_file_partial_path = timeline_dir.join(wal_file_name + ".partial");
     Ok((wal_file_path, wal_file_partial_path))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use postgres_ffi::encode_logical_message;
+    use postgres_ffi::v14::xlog_utils::XLOG_RECORD_CRC_OFFS;
+
+    const WAL_SEG_SIZE: usize = 16 * 1024 * 1024;
+
+    fn new_storage(validate_wal: bool) -> (Utf8PathBuf, PhysicalStorage) {
+        let timeline_dir = camino_tempfile::tempdir().unwrap().into_path();
+        let conf = SafeKeeperConf {
+            workdir: timeline_dir.clone(),
+            validate_wal,
+            ..SafeKeeperConf::dummy()
+        };
+        let mut state = TimelinePersistentState::empty();
+        state.server.wal_seg_size = WAL_SEG_SIZE as u32;
+        state.server.pg_version = 160000;
+        let storage = PhysicalStorage::new(
+            &TenantTimelineId::generate(),
+            timeline_dir.clone(),
+            &conf,
+            &state,
+        )
+        .unwrap();
+        (timeline_dir, storage)
+    }
+
+    /// Set xl_prev of the encoded record, fixing up its CRC.
+    fn set_xl_prev(rec: &mut [u8], prev: Lsn) {
+        rec[8..16].copy_from_slice(&prev.0.to_le_bytes());
+        let tot_len = u32::from_le_bytes(rec[0..4].try_into().unwrap()) as usize;
+        let crc = crc32c::crc32c_append(0, &rec[XLOG_SIZE_OF_XLOG_RECORD..tot_len]);
+        let crc = crc32c::crc32c_append(crc, &rec[0..XLOG_RECORD_CRC_OFFS]);
+        rec[XLOG_RECORD_CRC_OFFS..XLOG_RECORD_CRC_OFFS + 4].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Two logical messages, the second one pointing to `second_xl_prev`.
+    fn two_records(second_xl_prev: Lsn) -> Vec<u8> {
+        // xl_prev of the first record is not checked
+        let mut wal = encode_logical_message("prefix", "message");
+        let mut second = encode_logical_message("prefix", "message");
+        set_xl_prev(&mut second, second_xl_prev);
+        wal.extend_from_slice(&second);
+        wal
+    }
+
+    fn segment_files(timeline_dir: &Utf8Path) -> Vec<String> {
+        std::fs::read_dir(timeline_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("00000001"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_validate_wal() {
+        // in the middle of the page, so that records go without page headers
+        let startpos = Lsn(0x0100_0100);
+        let first_len = encode_logical_message("prefix", "message").len() as u64;
+
+        // properly linked records are accepted
+        let (timeline_dir, mut storage) = new_storage(true);
+        let wal = two_records(startpos);
+        storage.write_wal(startpos, &wal).await.unwrap();
+        let (write_lsn, write_record_lsn, _, _) = storage.internal_state();
+        assert_eq!(write_lsn, startpos + wal.len() as u64);
+        assert_eq!(write_record_lsn, startpos + wal.len() as u64);
+        assert_eq!(segment_files(&timeline_dir).len(), 1);
+
+        // second record doesn't point to the first one
+        let broken = two_records(startpos + first_len);
+
+        let (timeline_dir, mut storage) = new_storage(true);
+        let err = storage.write_wal(startpos, &broken).await.unwrap_err();
+        assert!(
+            format!("{err:#}").contains("WAL validation failed"),
+            "unexpected error: {err:#}"
+        );
+        // nothing was written
+        assert_eq!(storage.internal_state(), (Lsn(0), Lsn(0), Lsn(0), false));
+        assert!(segment_files(&timeline_dir).is_empty());
+
+        // without validation the same WAL is accepted
+        let (timeline_dir, mut storage) = new_storage(false);
+        storage.write_wal(startpos, &broken).await.unwrap();
+        let (write_lsn, write_record_lsn, _, _) = storage.internal_state();
+        assert_eq!(write_lsn, startpos + broken.len() as u64);
+        assert_eq!(write_record_lsn, startpos + broken.len() as u64);
+        assert_eq!(segment_files(&timeline_dir).len(), 1);
+    }
+}
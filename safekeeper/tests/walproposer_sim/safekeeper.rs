@@ -15,6 +15,7 @@ use desim::{
 };
 use hyper::Uri;
 use safekeeper::{
+    io_engine::IoEngineKind,
     safekeeper::{ProposerAcceptorMessage, SafeKeeper, ServerInfo, UNKNOWN_SERVER_VERSION},
     state::TimelinePersistentState,
     timeline::TimelineError,
@@ -162,11 +163,25 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
         no_sync: false,
         broker_endpoint: "/".parse::<Uri>().unwrap(),
         broker_keepalive_interval: Duration::from_secs(0),
+        static_peers: Vec::new(),
         heartbeat_timeout: Duration::from_secs(0),
         remote_storage: None,
         max_offloader_lag_bytes: 0,
         wal_backup_enabled: false,
+        wal_backup_compression: false,
+        partial_backup_enabled: false,
+        partial_backup_timeout: Duration::from_secs(0),
+        wal_flush_batch_delay: Duration::ZERO,
+        wal_io_engine: IoEngineKind::StdFs,
+        validate_wal: false,
+        wal_eviction_enabled: false,
+        wal_disk_usage_threshold_pct: None,
+        remote_wal_cache_size: 0,
+        tenant_wal_ingest_rate: None,
+        tenant_wal_ingest_burst: 0,
+        tombstone_ttl: Duration::from_secs(0),
         listen_pg_addr_tenant_only: None,
+        listen_http_addr_tenant_only: None,
         advertise_pg_addr: None,
         availability_zone: None,
         peer_recovery_enabled: false,
@@ -174,6 +189,8 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
         pg_auth: None,
         pg_tenant_only_auth: None,
         http_auth: None,
+        http_tenant_only_auth: None,
+        sk_auth_token: None,
         current_thread_runtime: false,
     };
 
@@ -89,6 +89,7 @@ impl WalProposer {
             safekeeper_reconnect_timeout: 1000,
             safekeeper_connection_timeout: 5000,
             sync_safekeepers,
+            safekeepers_generation: 0,
         };
         let args = walproposer_api::Args {
             os,
@@ -734,6 +734,7 @@ mod tests {
             http_connstr: "neon-1-sk-1.local:7677".to_owned(),
             local_start_lsn: 0,
             availability_zone: None,
+            ps_last_received_lsn: 0,
         })
     }
 
@@ -8,8 +8,12 @@ use tonic::{transport::Channel, Status};
 use utils::id::{TenantId, TenantTimelineId, TimelineId};
 
 use proto::{
-    broker_service_client::BrokerServiceClient, TenantTimelineId as ProtoTenantTimelineId,
+    broker_service_client::BrokerServiceClient, SafekeeperTimelineInfo,
+    TenantTimelineId as ProtoTenantTimelineId,
 };
+use safekeeper_api::models::BrokerTimelineInfo;
+use utils::id::NodeId;
+use utils::lsn::Lsn;
 
 // Code generated by protobuf.
 pub mod proto {
@@ -95,6 +99,59 @@ pub fn parse_proto_ttid(proto_ttid: &ProtoTenantTimelineId) -> Result<TenantTime
     })
 }
 
+impl TryFrom<SafekeeperTimelineInfo> for BrokerTimelineInfo {
+    type Error = Status;
+
+    fn try_from(info: SafekeeperTimelineInfo) -> Result<Self, Self::Error> {
+        let proto_ttid = info
+            .tenant_timeline_id
+            .as_ref()
+            .ok_or_else(|| Status::new(Code::InvalidArgument, "missing tenant_timeline_id"))?;
+        let ttid = parse_proto_ttid(proto_ttid)?;
+        Ok(BrokerTimelineInfo {
+            safekeeper_id: NodeId(info.safekeeper_id),
+            tenant_id: ttid.tenant_id,
+            timeline_id: ttid.timeline_id,
+            term: info.term,
+            last_log_term: info.last_log_term,
+            flush_lsn: Lsn(info.flush_lsn),
+            commit_lsn: Lsn(info.commit_lsn),
+            backup_lsn: Lsn(info.backup_lsn),
+            remote_consistent_lsn: Lsn(info.remote_consistent_lsn),
+            peer_horizon_lsn: Lsn(info.peer_horizon_lsn),
+            local_start_lsn: Lsn(info.local_start_lsn),
+            ps_last_received_lsn: Lsn(info.ps_last_received_lsn),
+            safekeeper_connstr: info.safekeeper_connstr,
+            http_connstr: info.http_connstr,
+            availability_zone: info.availability_zone,
+        })
+    }
+}
+
+impl From<BrokerTimelineInfo> for SafekeeperTimelineInfo {
+    fn from(info: BrokerTimelineInfo) -> Self {
+        SafekeeperTimelineInfo {
+            safekeeper_id: info.safekeeper_id.0,
+            tenant_timeline_id: Some(ProtoTenantTimelineId {
+                tenant_id: info.tenant_id.as_ref().to_owned(),
+                timeline_id: info.timeline_id.as_ref().to_owned(),
+            }),
+            term: info.term,
+            last_log_term: info.last_log_term,
+            flush_lsn: info.flush_lsn.0,
+            commit_lsn: info.commit_lsn.0,
+            backup_lsn: info.backup_lsn.0,
+            remote_consistent_lsn: info.remote_consistent_lsn.0,
+            peer_horizon_lsn: info.peer_horizon_lsn.0,
+            local_start_lsn: info.local_start_lsn.0,
+            safekeeper_connstr: info.safekeeper_connstr,
+            http_connstr: info.http_connstr,
+            availability_zone: info.availability_zone,
+            ps_last_received_lsn: info.ps_last_received_lsn.0,
+        }
+    }
+}
+
 // These several usages don't justify anyhow dependency, though it would work as
 // well.
 type AnyError = Box<dyn std::error::Error + Send + Sync + 'static>;
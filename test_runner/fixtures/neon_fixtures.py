@@ -404,6 +404,9 @@ class AuthKeys:
     def generate_safekeeper_token(self) -> str:
         return self.generate_token(scope="safekeeperdata")
 
+    def generate_proxy_token(self) -> str:
+        return self.generate_token(scope="proxyapi")
+
     # generate token giving access to only one tenant
     def generate_tenant_token(self, tenant_id: TenantId) -> str:
         return self.generate_token(scope="tenant", tenant_id=str(tenant_id))
@@ -466,6 +469,8 @@ class NeonEnvBuilder:
         self.num_pageservers = num_pageservers
         self.safekeepers_id_start = safekeepers_id_start
         self.safekeepers_enable_fsync = safekeepers_enable_fsync
+        # Max fsync batching delay on safekeepers, e.g. "5ms"; safekeeper default if None
+        self.safekeepers_wal_flush_batch_delay: Optional[str] = None
         self.auth_enabled = auth_enabled
         self.default_branch_name = default_branch_name
         self.env: Optional[NeonEnv] = None
@@ -481,6 +486,14 @@ class NeonEnvBuilder:
         self.test_overlay_dir = test_overlay_dir
         self.overlay_mounts_created_by_us: List[Tuple[str, Path]] = []
         self.config_init_force: Optional[str] = None
+        # neon_local config profile applied on init, e.g. "tiny"
+        self.config_profile: Optional[str] = None
+        # Have neon_local supervise the services, restarting them when they crash
+        self.restart_on_crash = False
+        # Availability zone of each pageserver, repeating the list if there are more pageservers
+        self.pageserver_availability_zones: Optional[List[str]] = None
+        # How often the attachment service heartbeats the pageservers, e.g. "1s"
+        self.pageserver_heartbeat_interval: Optional[str] = None
         self.top_output_dir = top_output_dir
         self.control_plane_compute_hook_api: Optional[str] = None
 
@@ -1033,6 +1046,12 @@ class NeonEnv:
         if self.control_plane_compute_hook_api is not None:
             cfg["control_plane_compute_hook_api"] = self.control_plane_compute_hook_api
 
+        if config.restart_on_crash:
+            cfg["restart_on_crash"] = True
+
+        if config.pageserver_heartbeat_interval is not None:
+            cfg["pageserver_heartbeat_interval"] = config.pageserver_heartbeat_interval
+
         # Create config for pageserver
         http_auth_type = "NeonJWT" if config.auth_enabled else "Trust"
         pg_auth_type = "NeonJWT" if config.auth_enabled else "Trust"
@@ -1053,6 +1072,9 @@ class NeonEnv:
             }
             if self.pageserver_virtual_file_io_engine is not None:
                 ps_cfg["virtual_file_io_engine"] = self.pageserver_virtual_file_io_engine
+            if config.pageserver_availability_zones:
+                azs = config.pageserver_availability_zones
+                ps_cfg["availability_zone"] = azs[(ps_id - self.BASE_PAGESERVER_ID) % len(azs)]
 
             # Create a corresponding NeonPageserver object
             self.pageservers.append(
@@ -1071,6 +1093,7 @@ class NeonEnv:
                 pg=self.port_distributor.get_port(),
                 pg_tenant_only=self.port_distributor.get_port(),
                 http=self.port_distributor.get_port(),
+                http_tenant_only=self.port_distributor.get_port(),
             )
             id = config.safekeepers_id_start + i  # assign ids sequentially
             sk_cfg: Dict[str, Any] = {
@@ -1078,8 +1101,11 @@ class NeonEnv:
                 "pg_port": port.pg,
                 "pg_tenant_only_port": port.pg_tenant_only,
                 "http_port": port.http,
+                "http_tenant_only_port": port.http_tenant_only,
                 "sync": config.safekeepers_enable_fsync,
             }
+            if config.safekeepers_wal_flush_batch_delay is not None:
+                sk_cfg["wal_flush_batch_delay"] = config.safekeepers_wal_flush_batch_delay
             if config.auth_enabled:
                 sk_cfg["auth_enabled"] = True
             if self.safekeepers_remote_storage is not None:
@@ -1088,7 +1114,7 @@ class NeonEnv:
             cfg["safekeepers"].append(sk_cfg)
 
         log.info(f"Config: {cfg}")
-        self.neon_cli.init(cfg, force=config.config_init_force)
+        self.neon_cli.init(cfg, force=config.config_init_force, profile=config.config_profile)
 
     def start(self):
         # Attachment service starts first, so that pageserver /re-attach calls don't
@@ -1492,9 +1518,11 @@ class NeonCli(AbstractNeonCli):
         shard_count: Optional[int] = None,
         shard_stripe_size: Optional[int] = None,
         set_default: bool = False,
+        pageserver_id: Optional[int] = None,
     ) -> Tuple[TenantId, TimelineId]:
         """
         Creates a new tenant, returns its id and its initial timeline's id.
+        With `pageserver_id`, all the shards are placed on that pageserver.
         """
         tenant_id = tenant_id or TenantId.generate()
         timeline_id = timeline_id or TimelineId.generate()
@@ -1524,10 +1552,56 @@ class NeonCli(AbstractNeonCli):
         if shard_stripe_size is not None:
             args.extend(["--shard-stripe-size", str(shard_stripe_size)])
 
+        if pageserver_id is not None:
+            args.extend(["--pageserver-id", str(pageserver_id)])
+
         res = self.raw_cli(args)
         res.check_returncode()
         return tenant_id, timeline_id
 
+    def tenant_attach(
+        self,
+        tenant_id: TenantId,
+        remote_storage: str,
+        generation: int,
+        pageserver_id: int,
+    ) -> "subprocess.CompletedProcess[str]":
+        """
+        Attach an existing tenant read-only from `remote_storage`, a TOML inline
+        table, to pageserver `pageserver_id`, which is switched to it.
+        """
+        args = [
+            "tenant",
+            "attach",
+            "--tenant-id",
+            str(tenant_id),
+            "--remote-storage",
+            remote_storage,
+            "--generation",
+            str(generation),
+            "--pageserver-id",
+            str(pageserver_id),
+        ]
+        return self.raw_cli(args, check_return_code=True)
+
+    def tenant_shard_split(self, tenant_id: TenantId, shard_count: int):
+        """
+        Split the tenant through the attachment service, and reconfigure its
+        running endpoints with the new shards.
+        """
+        res = self.raw_cli(
+            [
+                "tenant",
+                "shard-split",
+                "--tenant-id",
+                str(tenant_id),
+                "--shard-count",
+                str(shard_count),
+            ]
+        )
+        res.check_returncode()
+        return res
+
     def set_default(self, tenant_id: TenantId):
         """
         Update default tenant for future operations that require tenant_id.
@@ -1561,6 +1635,7 @@ class NeonCli(AbstractNeonCli):
         new_branch_name: str,
         tenant_id: Optional[TenantId] = None,
         timeline_id: Optional[TimelineId] = None,
+        pageserver_id: Optional[int] = None,
     ) -> TimelineId:
         cmd = [
             "timeline",
@@ -1575,6 +1650,8 @@ class NeonCli(AbstractNeonCli):
 
         if timeline_id is not None:
             cmd.extend(["--timeline-id", str(timeline_id)])
+        if pageserver_id is not None:
+            cmd.extend(["--pageserver-id", str(pageserver_id)])
 
         res = self.raw_cli(cmd)
         res.check_returncode()
@@ -1593,6 +1670,7 @@ class NeonCli(AbstractNeonCli):
         ancestor_branch_name: Optional[str] = None,
         tenant_id: Optional[TenantId] = None,
         ancestor_start_lsn: Optional[Lsn] = None,
+        pageserver_id: Optional[int] = None,
     ) -> TimelineId:
         cmd = [
             "timeline",
@@ -1606,6 +1684,8 @@ class NeonCli(AbstractNeonCli):
             cmd.extend(["--ancestor-branch-name", ancestor_branch_name])
         if ancestor_start_lsn is not None:
             cmd.extend(["--ancestor-start-lsn", str(ancestor_start_lsn)])
+        if pageserver_id is not None:
+            cmd.extend(["--pageserver-id", str(pageserver_id)])
 
         res = self.raw_cli(cmd)
         res.check_returncode()
@@ -1639,10 +1719,55 @@ class NeonCli(AbstractNeonCli):
         )
         return timelines_cli
 
+    def timeline_compact(
+        self, tenant_id: TenantId, timeline_id: TimelineId, wait: bool = False
+    ) -> "subprocess.CompletedProcess[str]":
+        args = [
+            "timeline",
+            "compact",
+            "--tenant-id",
+            str(tenant_id),
+            "--timeline-id",
+            str(timeline_id),
+        ]
+        if wait:
+            args.append("--wait")
+        return self.raw_cli(args, check_return_code=True)
+
+    def timeline_gc(
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        gc_horizon: Optional[int] = None,
+        wait: bool = False,
+    ) -> "subprocess.CompletedProcess[str]":
+        args = ["timeline", "gc", "--tenant-id", str(tenant_id), "--timeline-id", str(timeline_id)]
+        if gc_horizon is not None:
+            args.extend(["--gc-horizon", str(gc_horizon)])
+        if wait:
+            args.append("--wait")
+        return self.raw_cli(args, check_return_code=True)
+
+    def workload_run(
+        self,
+        endpoint_id: str,
+        patterns: List[str],
+        duration: str,
+        scale: int = 1,
+        clients: int = 4,
+        seed: int = 0,
+    ) -> "subprocess.CompletedProcess[str]":
+        args = ["workload", "run", "--endpoint", endpoint_id, "--duration", duration]
+        args.extend(["--scale", str(scale), "--clients", str(clients), "--seed", str(seed)])
+        for pattern in patterns:
+            args.extend(["--pattern", pattern])
+        return self.raw_cli(args, check_return_code=True)
+
     def init(
         self,
         config: Dict[str, Any],
         force: Optional[str] = None,
+        profile: Optional[str] = None,
     ) -> "subprocess.CompletedProcess[str]":
         with tempfile.NamedTemporaryFile(mode="w+") as tmp:
             tmp.write(toml.dumps(config))
@@ -1652,6 +1777,8 @@ class NeonCli(AbstractNeonCli):
 
             if force is not None:
                 cmd.extend(["--force", force])
+            if profile is not None:
+                cmd.extend(["--profile", profile])
 
             storage = self.env.pageserver_remote_storage
 
@@ -1678,6 +1805,26 @@ class NeonCli(AbstractNeonCli):
             cmd.extend(["-m", "immediate"])
         return self.raw_cli(cmd)
 
+    def proxy_start(
+        self, endpoint_id: str, pg_port: int, http_port: int
+    ) -> "subprocess.CompletedProcess[str]":
+        args = [
+            "proxy",
+            "start",
+            endpoint_id,
+            "--pg-port",
+            str(pg_port),
+            "--http-port",
+            str(http_port),
+        ]
+        return self.raw_cli(args, check_return_code=True)
+
+    def proxy_stop(self, immediate: bool = False) -> "subprocess.CompletedProcess[str]":
+        args = ["proxy", "stop"]
+        if immediate:
+            args.extend(["-m", "immediate"])
+        return self.raw_cli(args, check_return_code=True)
+
     def pageserver_start(
         self,
         id: int,
@@ -1733,6 +1880,16 @@ class NeonCli(AbstractNeonCli):
             args.extend(["-m", "immediate"])
         return self.raw_cli(args)
 
+    def safekeeper_fault(
+        self, fault: str, id: int, failpoint: Optional[Tuple[str, str]] = None
+    ) -> "subprocess.CompletedProcess[str]":
+        args = ["safekeeper", "fault", fault]
+        if failpoint is not None:
+            args.extend([*failpoint, "--id", str(id)])
+        else:
+            args.append(str(id))
+        return self.raw_cli(args, check_return_code=True)
+
     def endpoint_create(
         self,
         branch_name: str,
@@ -1743,6 +1900,7 @@ class NeonCli(AbstractNeonCli):
         hot_standby: bool = False,
         lsn: Optional[Lsn] = None,
         pageserver_id: Optional[int] = None,
+        read_only: bool = False,
     ) -> "subprocess.CompletedProcess[str]":
         args = [
             "endpoint",
@@ -1764,6 +1922,8 @@ class NeonCli(AbstractNeonCli):
             args.append(endpoint_id)
         if hot_standby:
             args.extend(["--hot-standby", "true"])
+        if read_only:
+            args.append("--read-only")
         if pageserver_id is not None:
             args.extend(["--pageserver-id", str(pageserver_id)])
 
@@ -1777,6 +1937,7 @@ class NeonCli(AbstractNeonCli):
         safekeepers: Optional[List[int]] = None,
         remote_ext_config: Optional[str] = None,
         pageserver_id: Optional[int] = None,
+        lfc_state: bool = False,
     ) -> "subprocess.CompletedProcess[str]":
         args = [
             "endpoint",
@@ -1784,6 +1945,8 @@ class NeonCli(AbstractNeonCli):
         ]
         if remote_ext_config is not None:
             args.extend(["--remote-ext-config", remote_ext_config])
+        if lfc_state:
+            args.append("--lfc-state")
 
         if safekeepers is not None:
             args.extend(["--safekeepers", (",".join(map(str, safekeepers)))])
@@ -1816,6 +1979,7 @@ class NeonCli(AbstractNeonCli):
         destroy=False,
         check_return_code=True,
         mode: Optional[str] = None,
+        check_durability: bool = False,
     ) -> "subprocess.CompletedProcess[str]":
         args = [
             "endpoint",
@@ -1825,6 +1989,8 @@ class NeonCli(AbstractNeonCli):
             args.append("--destroy")
         if mode is not None:
             args.append(f"--mode={mode}")
+        if check_durability:
+            args.append("--check-durability")
         if endpoint_id is not None:
             args.append(endpoint_id)
 
@@ -1854,14 +2020,17 @@ class NeonCli(AbstractNeonCli):
         return self.raw_cli(args, check_return_code=True)
 
     def tenant_migrate(
-        self, tenant_shard_id: TenantShardId, new_pageserver: int, timeout_secs: Optional[int]
+        self,
+        tenant_shard_id: Union[TenantId, TenantShardId],
+        new_pageserver: int,
+        timeout_secs: Optional[int],
     ):
         args = [
             "tenant",
             "migrate",
             "--tenant-id",
             str(tenant_shard_id),
-            "--id",
+            "--to",
             str(new_pageserver),
         ]
         return self.raw_cli(args, check_return_code=True, timeout=timeout_secs)
@@ -2033,6 +2202,62 @@ class NeonAttachmentService:
             headers=self.headers(),
         ).raise_for_status()
 
+    def failover_events(self) -> list[dict[str, Any]]:
+        """
+        :return: list of {"time": str, "node_id": int, "kind": str, ...}, oldest first
+        """
+        response = self.request(
+            "GET",
+            f"{self.env.attachment_service_api}/control/v1/failover_events",
+            headers=self.headers(),
+        )
+        response.raise_for_status()
+        events: list[dict[str, Any]] = response.json()
+        return events
+
+    def service_metrics(self) -> dict[str, Any]:
+        """
+        :return: {"reconciles_in_progress": int, "schedule_decisions": {reason: int}, "pending_migrations": {operation: int}, "nodes": [{"node_id": int, "availability": str, "missed_heartbeats": int}], ...}
+        """
+        response = self.request(
+            "GET",
+            f"{self.env.attachment_service_api}/control/v1/metrics",
+            headers=self.headers(),
+        )
+        response.raise_for_status()
+        body: dict[str, Any] = response.json()
+        return body
+
+    def get_metrics_str(self) -> str:
+        response = self.request("GET", f"{self.env.attachment_service_api}/metrics")
+        response.raise_for_status()
+        return response.text
+
+    def node_drain(self, node_id) -> dict[str, Any]:
+        return self._node_operation_request("PUT", f"{node_id}/drain")
+
+    def node_fill(self, node_id) -> dict[str, Any]:
+        return self._node_operation_request("PUT", f"{node_id}/fill")
+
+    def node_decommission(self, node_id, mode: str = "graceful") -> dict[str, Any]:
+        return self._node_operation_request("DELETE", f"{node_id}?mode={mode}")
+
+    def node_operation(self, node_id) -> dict[str, Any]:
+        """
+        :return: {"node_id": int, "kind": "Drain"|"Fill"|"Decommission", "state": "InProgress"|"Complete"|"Failed", "pending": [...], "migrated": [...], "errors": [str]}
+        """
+        return self._node_operation_request("GET", f"{node_id}/operation")
+
+    def _node_operation_request(self, method: str, path: str) -> dict[str, Any]:
+        response = self.request(
+            method,
+            f"{self.env.attachment_service_api}/control/v1/node/{path}",
+            headers=self.headers(),
+        )
+        response.raise_for_status()
+        body: dict[str, Any] = response.json()
+        return body
+
     def tenant_create(
         self,
         tenant_id: TenantId,
@@ -2094,6 +2319,43 @@ class NeonAttachmentService:
         log.info(f"Migrated tenant {tenant_shard_id} to pageserver {dest_ps_id}")
         assert self.env.get_tenant_pageserver(tenant_shard_id).id == dest_ps_id
 
+    def tenant_shard_migrate_warm(
+        self, tenant_shard_id: TenantShardId, dest_ps_id: int, warm_threshold: float
+    ) -> dict[str, Any]:
+        """
+        :return: {"tenant_shard_id": str, "node_id": int, "state": str, "progress": dict, "error": str}
+        """
+        response = self.request(
+            "PUT",
+            f"{self.env.attachment_service_api}/control/v1/tenant/{tenant_shard_id}/migrate",
+            json={
+                "tenant_shard_id": str(tenant_shard_id),
+                "node_id": dest_ps_id,
+                "warm_threshold": warm_threshold,
+            },
+        )
+        response.raise_for_status()
+        return response.json()
+
+    def tenant_shard_migration(self, tenant_shard_id: TenantShardId) -> dict[str, Any]:
+        response = self.request(
+            "GET", f"{self.env.attachment_service_api}/control/v1/tenant/{tenant_shard_id}/migrate"
+        )
+        response.raise_for_status()
+        return response.json()
+
+    def optimizer_plan(self) -> dict[str, Any]:
+        """
+        :return: {"nodes": [{"node_id": int, "shard_count": int, "resident_size": int, "getpage_rate": float, "load": float}], "migrations": [{"tenant_shard_id": str, "from_node_id": int, "to_node_id": int}]}
+        """
+        response = self.request(
+            "GET", f"{self.env.attachment_service_api}/control/v1/optimizer/plan"
+        )
+        response.raise_for_status()
+        body: dict[str, Any] = response.json()
+        log.info(f"optimizer_plan success: {body}")
+        return body
+
     def __enter__(self) -> "NeonAttachmentService":
         return self
 
@@ -2748,6 +3010,7 @@ class NeonProxy(PgProtocol):
         self.auth_backend = auth_backend
         self.metric_collection_endpoint = metric_collection_endpoint
         self.metric_collection_interval = metric_collection_interval
+        self.extra_args: list[str] = []
         self._popen: Optional[subprocess.Popen[bytes]] = None
 
     def start(self) -> NeonProxy:
@@ -2789,6 +3052,7 @@ class NeonProxy(PgProtocol):
             *["-c", str(crt_path)],
             *["-k", str(key_path)],
             *self.auth_backend.extra_args(),
+            *self.extra_args,
         ]
 
         if (
@@ -2800,11 +3064,18 @@ class NeonProxy(PgProtocol):
                 *["--metric-collection-interval", self.metric_collection_interval],
             ]
 
-        logfile = open(self.test_output_dir / "proxy.log", "w")
+        logfile = open(self.test_output_dir / "proxy.log", "a")
         self._popen = subprocess.Popen(args, stdout=logfile, stderr=logfile)
         self._wait_until_ready()
         return self
 
+    def restart(self, extra_args: Optional[list[str]] = None) -> NeonProxy:
+        """Restart the proxy, passing it the given arguments in addition to the usual ones"""
+        self.__exit__(None, None, None)
+        self._popen = None
+        self.extra_args = extra_args or []
+        return self.start()
+
     # Sends SIGTERM to the proxy if it has been started
     def terminate(self):
         if self._popen:
@@ -2972,6 +3243,12 @@ def static_proxy(
     vanilla_pg.safe_psql(
         "CREATE TABLE neon_control_plane.endpoints (endpoint_id VARCHAR(255) PRIMARY KEY, allowed_ips VARCHAR(255))"
     )
+    vanilla_pg.safe_psql(
+        "CREATE TABLE neon_control_plane.role_passwords (role_name VARCHAR(255) PRIMARY KEY, password VARCHAR(255))"
+    )
+    vanilla_pg.safe_psql(
+        "CREATE TABLE neon_control_plane.endpoint_jwks (id VARCHAR(255) PRIMARY KEY, endpoint_id VARCHAR(255), jwks_url VARCHAR(255), jwt_audience VARCHAR(255), role_names VARCHAR(255))"
+    )
 
     proxy_port = port_distributor.get_port()
     mgmt_port = port_distributor.get_port()
@@ -3023,6 +3300,7 @@ class Endpoint(PgProtocol):
         lsn: Optional[Lsn] = None,
         config_lines: Optional[List[str]] = None,
         pageserver_id: Optional[int] = None,
+        read_only: bool = False,
     ) -> "Endpoint":
         """
         Create a new Postgres endpoint.
@@ -3045,6 +3323,7 @@ class Endpoint(PgProtocol):
             pg_port=self.pg_port,
             http_port=self.http_port,
             pageserver_id=pageserver_id,
+            read_only=read_only,
         )
         path = Path("endpoints") / self.endpoint_id / "pgdata"
         self.pgdata_dir = os.path.join(self.env.repo_dir, path)
@@ -3059,7 +3338,10 @@ class Endpoint(PgProtocol):
         return self
 
     def start(
-        self, remote_ext_config: Optional[str] = None, pageserver_id: Optional[int] = None
+        self,
+        remote_ext_config: Optional[str] = None,
+        pageserver_id: Optional[int] = None,
+        lfc_state: bool = False,
     ) -> "Endpoint":
         """
         Start the Postgres instance.
@@ -3075,6 +3357,7 @@ class Endpoint(PgProtocol):
             safekeepers=self.active_safekeepers,
             remote_ext_config=remote_ext_config,
             pageserver_id=pageserver_id,
+            lfc_state=lfc_state,
         )
         self.running = True
 
@@ -3165,7 +3448,7 @@ class Endpoint(PgProtocol):
         with open(remote_extensions_spec_path, "w") as file:
             json.dump(spec, file, indent=4)
 
-    def stop(self, mode: str = "fast") -> "Endpoint":
+    def stop(self, mode: str = "fast", check_durability: bool = False) -> "Endpoint":
         """
         Stop the Postgres instance if it's running.
         Returns self.
@@ -3174,7 +3457,10 @@ class Endpoint(PgProtocol):
         if self.running:
             assert self.endpoint_id is not None
             self.env.neon_cli.endpoint_stop(
-                self.endpoint_id, check_return_code=self.check_stop_result, mode=mode
+                self.endpoint_id,
+                check_return_code=self.check_stop_result,
+                mode=mode,
+                check_durability=check_durability,
             )
             self.running = False
 
@@ -3291,6 +3577,7 @@ class EndpointFactory:
         hot_standby: bool = False,
         config_lines: Optional[List[str]] = None,
         pageserver_id: Optional[int] = None,
+        read_only: bool = False,
     ) -> Endpoint:
         ep = Endpoint(
             self.env,
@@ -3311,6 +3598,7 @@ class EndpointFactory:
             hot_standby=hot_standby,
             config_lines=config_lines,
             pageserver_id=pageserver_id,
+            read_only=read_only,
         )
 
     def stop_all(self) -> "EndpointFactory":
@@ -3355,6 +3643,7 @@ class SafekeeperPort:
     pg: int
     pg_tenant_only: int
     http: int
+    http_tenant_only: int
 
 
 @dataclass
@@ -3420,10 +3709,13 @@ class Safekeeper:
                 assert isinstance(res, dict)
                 return res
 
-    def http_client(self, auth_token: Optional[str] = None) -> SafekeeperHttpClient:
+    def http_client(
+        self, auth_token: Optional[str] = None, tenant_only: bool = False
+    ) -> SafekeeperHttpClient:
         is_testing_enabled = '"testing"' in self.env.get_binary_version("safekeeper")
+        port = self.port.http_tenant_only if tenant_only else self.port.http
         return SafekeeperHttpClient(
-            port=self.port.http, auth_token=auth_token, is_testing_enabled=is_testing_enabled
+            port=port, auth_token=auth_token, is_testing_enabled=is_testing_enabled
         )
 
     def data_dir(self) -> str:
@@ -3462,6 +3754,8 @@ class SafekeeperTimelineStatus:
     peer_horizon_lsn: Lsn
     remote_consistent_lsn: Lsn
     walreceivers: List[Walreceiver]
+    peers: List[Dict[str, Any]]
+    wal_disk_usage_bytes: int
 
 
 @dataclass
@@ -3544,6 +3838,52 @@ class SafekeeperHttpClient(requests.Session):
         assert isinstance(res_json, dict)
         return res_json
 
+    def timeline_replicate_start(
+        self, tenant_id: TenantId, timeline_id: TimelineId, body: Dict[str, Any]
+    ) -> Dict[str, Any]:
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/replicate",
+            json=body,
+        )
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_replicate_status(
+        self, tenant_id: TenantId, timeline_id: TimelineId
+    ) -> Dict[str, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/replicate"
+        )
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_replicate_stop(
+        self, tenant_id: TenantId, timeline_id: TimelineId
+    ) -> Dict[str, Any]:
+        res = self.delete(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/replicate"
+        )
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def membership_switch(
+        self, tenant_id: TenantId, timeline_id: TimelineId, conf: Dict[str, Any]
+    ) -> Dict[str, Any]:
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/membership",
+            json=conf,
+        )
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def copy_timeline(self, tenant_id: TenantId, timeline_id: TimelineId, body: Dict[str, Any]):
         res = self.post(
             f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/copy",
@@ -3599,6 +3939,8 @@ class SafekeeperHttpClient(requests.Session):
             peer_horizon_lsn=Lsn(resj["peer_horizon_lsn"]),
             remote_consistent_lsn=Lsn(resj["remote_consistent_lsn"]),
             walreceivers=walreceivers,
+            peers=resj["peers"],
+            wal_disk_usage_bytes=resj["wal_disk_usage_bytes"],
         )
 
     def record_safekeeper_info(self, tenant_id: TenantId, timeline_id: TimelineId, body):
@@ -3630,6 +3972,13 @@ class SafekeeperHttpClient(requests.Session):
         assert isinstance(res_json, dict)
         return res_json
 
+    def timelines_metrics(self) -> List[Dict[str, Any]]:
+        res = self.get(f"http://localhost:{self.port}/v1/metrics/timelines")
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return res_json
+
     def get_metrics_str(self) -> str:
         request_result = self.get(f"http://localhost:{self.port}/metrics")
         request_result.raise_for_status()
@@ -0,0 +1,65 @@
+import json
+from pathlib import Path
+
+import pytest
+from fixtures.benchmark_fixture import MetricReport, NeonBenchmarker
+from fixtures.log_helper import log
+from fixtures.neon_fixtures import NeonEnvBuilder, PgBin
+from fixtures.utils import humantime_to_ms
+
+
+@pytest.mark.parametrize("duration", [10])
+@pytest.mark.parametrize("record_size", [128, 8192])
+@pytest.mark.parametrize("clients", [1, 8])
+def test_safekeeper_append(
+    neon_env_builder: NeonEnvBuilder,
+    zenbenchmark: NeonBenchmarker,
+    pg_bin: PgBin,
+    duration: int,
+    record_size: int,
+    clients: int,
+):
+    """
+    Stream WAL to a safekeeper with `safekeeper_bench` and record append
+    throughput and commit latency.
+    """
+    neon_env_builder.num_safekeepers = 1
+    env = neon_env_builder.init_start()
+    sk = env.safekeepers[0]
+
+    cmd = [
+        str(env.neon_binpath / "safekeeper_bench"),
+        "--safekeeper",
+        f"127.0.0.1:{sk.port.pg}",
+        "--clients",
+        str(clients),
+        "--record-size",
+        str(record_size),
+        "--runtime",
+        f"{duration}s",
+    ]
+    log.info(f"command: {' '.join(cmd)}")
+    basepath = pg_bin.run_capture(cmd, with_command_header=False)
+    results_path = Path(basepath + ".stdout")
+    with open(results_path, "r") as f:
+        results = json.load(f)
+    log.info(f"Results:\n{json.dumps(results, sort_keys=True, indent=2)}")
+
+    assert results["appends"] > 0
+    for metric in ["appends_per_sec", "wal_mib_per_sec"]:
+        zenbenchmark.record(
+            metric, metric_value=results[metric], unit="", report=MetricReport.HIGHER_IS_BETTER
+        )
+    for metric in [
+        "commit_latency_mean",
+        "commit_latency_p50",
+        "commit_latency_p99",
+        "commit_latency_p99_9",
+        "commit_latency_max",
+    ]:
+        zenbenchmark.record(
+            metric,
+            metric_value=humantime_to_ms(results[metric]),
+            unit="ms",
+            report=MetricReport.LOWER_IS_BETTER,
+        )
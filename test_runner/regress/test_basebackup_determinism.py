@@ -0,0 +1,49 @@
+from pathlib import Path
+
+from fixtures.neon_fixtures import NeonEnvBuilder, PgBin, wait_for_last_flush_lsn
+from fixtures.types import Lsn
+
+
+#
+# A basebackup taken at an LSN is byte-identical every time, including after a
+# pageserver restart, which resuming an interrupted download relies on.
+#
+def test_basebackup_determinism(
+    neon_env_builder: NeonEnvBuilder,
+    pg_bin: PgBin,
+    pg_distrib_dir: Path,
+    test_output_dir: Path,
+):
+    env = neon_env_builder.init_start()
+    endpoint = env.endpoints.create_start("main")
+
+    # Many databases and unlogged relations, whose init forks are in the
+    # basebackup, so that listing them in hash order would shuffle them.
+    for i in range(5):
+        endpoint.safe_psql(f"CREATE DATABASE db{i}")
+        endpoint.safe_psql_many(
+            [f"CREATE UNLOGGED TABLE u{j} (i int)" for j in range(20)], dbname=f"db{i}"
+        )
+    endpoint.safe_psql("CHECKPOINT")
+    lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_insert_lsn()")[0][0])
+    wait_for_last_flush_lsn(env, endpoint, env.initial_tenant, env.initial_timeline)
+    endpoint.stop()
+
+    psql_env = {"LD_LIBRARY_PATH": str(pg_distrib_dir / "lib")}
+
+    def basebackup(name: str, flags: str) -> bytes:
+        query = f"basebackup {env.initial_tenant} {env.initial_timeline} {lsn} {flags}"
+        output = test_output_dir / f"{name}.tar"
+        cmd = ["psql", "--no-psqlrc", env.pageserver.connstr(), "-c", query, "-o", str(output)]
+        pg_bin.run_capture(cmd, env=psql_env)
+        return output.read_bytes()
+
+    first = basebackup("first", "--sha256")
+    env.pageserver.restart()
+    second = basebackup("second", "--sha256")
+    assert first == second
+
+    # The rest of the tarball after an offset, with the checksum of all of it.
+    offset = len(first) // 2
+    resumed = basebackup("resumed", f"--sha256 --offset={offset}")
+    assert first[offset:] == resumed
@@ -186,3 +186,54 @@ def test_branch_creation_before_gc(neon_simple_env: NeonEnv):
         pageserver_http_client.timeline_create(env.pg_version, tenant, new_timeline_id, b0, lsn)
 
     thread.join()
+
+
+# Check the breakdown of the layer sizes by the reason GC keeps them, as reported by the gc_info
+# endpoint, on a timeline with a child branch.
+def test_gc_info_retained_size(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    pageserver_http_client = env.pageserver.http_client()
+
+    tenant, _ = env.neon_cli.create_tenant(
+        conf={
+            # disable background GC and compaction, GC is done by the test
+            "gc_period": "0s",
+            "compaction_period": "0s",
+            # small checkpoint distance to create more delta layer files
+            "checkpoint_distance": f"{1024 ** 2}",
+            # only the gc_horizon passed to do_gc counts
+            "pitr_interval": "0s",
+        }
+    )
+
+    timeline_main = env.neon_cli.create_timeline("test_main", tenant_id=tenant)
+    endpoint_main = env.endpoints.create_start("test_main", tenant_id=tenant)
+
+    main_cur = endpoint_main.connect().cursor()
+    main_cur.execute("CREATE TABLE foo(key serial primary key, t text default 'foooooooooooooo')")
+    main_cur.execute("INSERT INTO foo SELECT FROM generate_series(1, 100000)")
+    branch_lsn = Lsn(query_scalar(main_cur, "SELECT pg_current_wal_insert_lsn()"))
+    env.neon_cli.create_branch(
+        "test_branch", "test_main", tenant_id=tenant, ancestor_start_lsn=branch_lsn
+    )
+
+    main_cur.execute("INSERT INTO foo SELECT FROM generate_series(1, 100000)")
+    pageserver_http_client.timeline_checkpoint(tenant, timeline_main)
+
+    layers = pageserver_http_client.layer_map_info(tenant, timeline_main).historic_layers
+    total_size = sum(layer.layer_file_size for layer in layers)
+
+    # GC updates the breakdown before removing anything, so it covers all the layers
+    pageserver_http_client.timeline_gc(tenant, timeline_main, 0)
+    gc_info = pageserver_http_client.timeline_gc_info(tenant, timeline_main)
+    log.info(f"gc_info: {gc_info}")
+
+    assert [Lsn(lsn) for lsn in gc_info["retain_lsns"]] == [branch_lsn]
+    assert gc_info["pitr_cutoff"] == gc_info["horizon_cutoff"]
+    assert Lsn(gc_info["latest_gc_cutoff_lsn"]) > branch_lsn
+
+    retained = gc_info["retained"]
+    assert sum(retained.values()) == total_size
+    assert retained["pitr_bytes"] == 0
+    # the layers written before the branch point are needed by the branch
+    assert retained["branches_bytes"] > 0
@@ -0,0 +1,59 @@
+import requests
+from fixtures.neon_fixtures import NeonEnv
+
+
+def test_compute_catalog(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+    catalog_api = f"http://localhost:{endpoint.http_port}/catalog"
+
+    endpoint.safe_psql("CREATE ROLE catalog_owner LOGIN CREATEDB")
+    endpoint.safe_psql("CREATE ROLE catalog_member")
+    endpoint.safe_psql("GRANT catalog_owner TO catalog_member")
+    endpoint.safe_psql("CREATE DATABASE catalog_db OWNER catalog_owner")
+    endpoint.safe_psql_many(
+        [
+            "CREATE SCHEMA catalog_schema AUTHORIZATION catalog_owner",
+            "CREATE TABLE catalog_schema.t AS SELECT g FROM generate_series(1, 1000) g",
+            "CREATE EXTENSION neon_test_utils",
+        ],
+        dbname="catalog_db",
+    )
+
+    res = requests.get(f"{catalog_api}/databases")
+    res.raise_for_status()
+    databases = {db["name"]: db for db in res.json()}
+    assert "postgres" in databases
+    assert "template0" not in databases and "template1" not in databases
+    assert databases["catalog_db"]["owner"] == "catalog_owner"
+    assert databases["catalog_db"]["size_bytes"] > 0
+
+    res = requests.get(f"{catalog_api}/roles")
+    res.raise_for_status()
+    roles = {role["name"]: role for role in res.json()}
+    assert not any(name.startswith("pg_") for name in roles)
+    assert roles["catalog_owner"]["can_login"]
+    assert roles["catalog_owner"]["create_db"]
+    assert not roles["catalog_member"]["can_login"]
+    assert roles["catalog_member"]["member_of"] == ["catalog_owner"]
+
+    res = requests.get(f"{catalog_api}/schemas", params={"database": "catalog_db"})
+    res.raise_for_status()
+    schemas = {schema["name"]: schema for schema in res.json()}
+    assert "public" in schemas
+    assert "information_schema" not in schemas and "pg_catalog" not in schemas
+    assert schemas["catalog_schema"]["owner"] == "catalog_owner"
+    assert schemas["catalog_schema"]["size_bytes"] > 0
+
+    res = requests.get(f"{catalog_api}/extensions", params={"database": "catalog_db"})
+    res.raise_for_status()
+    extensions = {ext["name"]: ext for ext in res.json()}
+    assert extensions["plpgsql"]["schema"] == "pg_catalog"
+    assert extensions["neon_test_utils"]["schema"] == "public"
+
+    # The per-database routes need an existing database
+    for route in ["schemas", "extensions"]:
+        res = requests.get(f"{catalog_api}/{route}")
+        assert res.status_code == 400
+        res = requests.get(f"{catalog_api}/{route}", params={"database": "no_such_db"})
+        assert res.status_code == 404
@@ -0,0 +1,39 @@
+import requests
+from fixtures.metrics import parse_metrics
+from fixtures.neon_fixtures import NeonEnv
+
+
+def test_compute_ctl_metrics(neon_simple_env: NeonEnv):
+    """
+    compute_ctl serves the status of the compute and its startup metrics at /metrics.
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+
+    def scrape():
+        res = requests.get(f"http://localhost:{endpoint.http_port}/metrics")
+        res.raise_for_status()
+        return parse_metrics(res.text, "compute_ctl")
+
+    metrics = scrape()
+    assert metrics.query_one("compute_ctl_status", {"status": "running"}).value == 1
+    for status in ["empty", "init", "configuration", "failed", "terminated"]:
+        assert metrics.query_one("compute_ctl_status", {"status": status}).value == 0
+
+    total = metrics.query_one("compute_ctl_startup_phase_duration_ms", {"phase": "total"}).value
+    assert total > 0
+    for phase in ["basebackup", "start_postgres", "config"]:
+        duration = metrics.query_one(
+            "compute_ctl_startup_phase_duration_ms", {"phase": phase}
+        ).value
+        assert 0 <= duration <= total
+    assert metrics.query_one("compute_ctl_spec_generation").value == 1
+
+    # reconfiguration with the same spec doesn't apply anything
+    endpoint.reconfigure()
+    assert scrape().query_one("compute_ctl_spec_generation").value == 1
+
+    endpoint.config(["work_mem = '8MB'"])
+    endpoint.reconfigure()
+    assert endpoint.safe_psql("SHOW work_mem")[0][0] == "8MB"
+    assert scrape().query_one("compute_ctl_spec_generation").value == 2
@@ -0,0 +1,101 @@
+import concurrent.futures
+
+import psycopg2
+import requests
+from fixtures.neon_fixtures import NeonEnv
+from fixtures.utils import wait_until
+
+
+def test_compute_restart(neon_simple_env: NeonEnv):
+    """
+    /restart applies the settings which need a restart, rejecting new connections while the
+    open ones are drained.
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 1000) g")
+
+    endpoint.config(["max_connections = 120"])
+    endpoint.reconfigure()
+    assert endpoint.safe_psql("SHOW max_connections")[0][0] == "100"
+
+    conn = endpoint.connect()
+    with concurrent.futures.ThreadPoolExecutor(max_workers=1) as executor:
+        restart = executor.submit(
+            requests.post,
+            f"http://localhost:{endpoint.http_port}/restart",
+            json={"drain_timeout_ms": 60000},
+        )
+
+        def logins_rejected():
+            try:
+                endpoint.connect().close()
+            except psycopg2.OperationalError as e:
+                assert "rejects connection" in str(e)
+                return
+            raise AssertionError("new connection was accepted while draining")
+
+        wait_until(20, 0.5, logins_rejected)
+        # Postgres is not restarted until the last connection closes
+        assert not restart.done()
+        conn.close()
+
+        res = restart.result()
+        res.raise_for_status()
+        assert res.json()["terminated_connections"] == 0
+
+    assert endpoint.safe_psql("SHOW max_connections")[0][0] == "120"
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 1000
+    status = requests.get(f"http://localhost:{endpoint.http_port}/status").json()
+    assert status["status"] == "running"
+
+
+def test_compute_restart_rejected(neon_simple_env: NeonEnv):
+    """
+    /restart leaves Postgres running if the configuration is rejected, also for the settings
+    of the preloaded libraries.
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+
+    endpoint.config(["neon.max_file_cache_size = 'bogus'"])
+    endpoint.reconfigure()
+
+    conn = endpoint.connect()
+    res = requests.post(
+        f"http://localhost:{endpoint.http_port}/restart", json={"drain_timeout_ms": 100}
+    )
+    assert res.status_code == 500
+    assert "neon.max_file_cache_size" in res.json()["error"]
+
+    # nothing was drained or stopped
+    with conn.cursor() as cur:
+        cur.execute("SELECT 1")
+    conn.close()
+    assert endpoint.safe_psql("SELECT 1")[0][0] == 1
+    status = requests.get(f"http://localhost:{endpoint.http_port}/status").json()
+    assert status["status"] == "running"
+
+
+def test_compute_restart_failed(neon_simple_env: NeonEnv):
+    """
+    If Postgres doesn't start with a configuration which passed the checks, /restart reports
+    the failure and the compute is failed.
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+
+    # passes the checks, but Postgres can't listen on an address which is not local
+    endpoint.config(["listen_addresses = '192.0.2.1'"])
+    endpoint.reconfigure()
+
+    res = requests.post(f"http://localhost:{endpoint.http_port}/restart")
+    assert res.status_code == 500
+    assert "did not restart" in res.json()["error"]
+
+    status = requests.get(f"http://localhost:{endpoint.http_port}/status").json()
+    assert status["status"] == "failed"
+
+    # Postgres is already stopped
+    endpoint.check_stop_result = False
+    endpoint.stop()
@@ -0,0 +1,40 @@
+import requests
+from fixtures.neon_fixtures import NeonEnv
+from fixtures.types import Lsn
+from fixtures.utils import wait_until
+
+
+def test_compute_terminate(neon_simple_env: NeonEnv):
+    """
+    /terminate stops the compute and returns the LSN the safekeepers were synced to, once
+    compute_ctl is done with them.
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 1000) g")
+    flush_lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+
+    res = requests.post(f"http://localhost:{endpoint.http_port}/terminate")
+    res.raise_for_status()
+    lsn = Lsn(res.json()["lsn"])
+    assert lsn >= flush_lsn
+
+    sk_flush_lsn = max(
+        sk.http_client().timeline_status(env.initial_tenant, env.initial_timeline).flush_lsn
+        for sk in env.safekeepers
+    )
+    assert sk_flush_lsn >= lsn
+
+    # compute_ctl exits right after answering
+    def compute_ctl_exited():
+        try:
+            requests.get(f"http://localhost:{endpoint.http_port}/status")
+        except requests.ConnectionError:
+            return
+        raise AssertionError("compute_ctl is still running")
+
+    wait_until(10, 0.5, compute_ctl_exited)
+
+    # Postgres is already stopped
+    endpoint.check_stop_result = False
+    endpoint.stop()
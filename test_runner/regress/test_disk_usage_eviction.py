@@ -904,3 +904,75 @@ def test_secondary_mode_eviction(eviction_env_ha: EvictionEnv):
     assert (
         total_size - post_eviction_total_size >= evict_bytes
     ), "we requested at least evict_bytes worth of free space"
+
+
+def test_secondary_trimmed_before_attached(eviction_env_ha: EvictionEnv):
+    """
+    On a pageserver with both an attached and a secondary location, the secondary location's
+    layers are evicted before any of the attached tenant's, whatever their access times.
+    """
+    env = eviction_env_ha
+
+    (attached_tenant, attached_timeline), (secondary_tenant, secondary_timeline) = env.timelines
+    pageserver = env.neon_env.get_tenant_pageserver(attached_tenant)
+    assert pageserver is not None
+    ps_http = pageserver.http_client()
+
+    # Move the other tenant to a secondary location on the same pageserver
+    secondary_origin = env.neon_env.get_tenant_pageserver(secondary_tenant)
+    assert secondary_origin is not None
+    secondary_origin.http_client().tenant_heatmap_upload(secondary_tenant)
+    secondary_origin.tenant_detach(secondary_tenant)
+    pageserver.tenant_location_configure(
+        secondary_tenant,
+        {
+            "mode": "Secondary",
+            "secondary_conf": {"warm": True},
+            "tenant_conf": {},
+        },
+    )
+    ps_http.tenant_secondary_download(secondary_tenant)
+
+    # The attached tenant's layers are all resident, and the most recently accessed ones
+    ps_http.download_all_layers(attached_tenant, attached_timeline)
+    env.warm_up_tenant(attached_tenant)
+
+    du_before = env.du_by_timeline(pageserver)
+    secondary_size = du_before[(secondary_tenant, secondary_timeline)]
+    assert secondary_size > 0
+
+    evict_bytes = secondary_size // 2
+    response = ps_http.disk_usage_eviction_run({"evict_bytes": evict_bytes})
+    log.info(f"{response}")
+
+    du_after = env.du_by_timeline(pageserver)
+    assert (
+        du_after[(attached_tenant, attached_timeline)]
+        == du_before[(attached_tenant, attached_timeline)]
+    ), "the attached tenant's layers are only evicted after the secondary location's"
+    assert (
+        secondary_size - du_after[(secondary_tenant, secondary_timeline)] >= evict_bytes
+    ), "we requested at least evict_bytes worth of free space from the secondary location"
+
+    # With all of the secondary location's layers reserved, they are evicted along with the
+    # attached tenant's layers outside of its reservation, so reserving all of the attached
+    # tenant's layers still protects them.
+    evict_bytes = du_after[(secondary_tenant, secondary_timeline)] // 2
+    response = ps_http.disk_usage_eviction_run(
+        {
+            "evict_bytes": evict_bytes,
+            "reserve": {"attached_pct": 100, "secondary_pct": 100},
+        }
+    )
+    log.info(f"{response}")
+
+    du_reserved = env.du_by_timeline(pageserver)
+    assert (
+        du_reserved[(attached_tenant, attached_timeline)]
+        == du_before[(attached_tenant, attached_timeline)]
+    ), "the attached tenant's reserved layers are evicted last"
+    assert (
+        du_after[(secondary_tenant, secondary_timeline)]
+        - du_reserved[(secondary_tenant, secondary_timeline)]
+        >= evict_bytes
+    )
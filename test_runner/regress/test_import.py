@@ -153,6 +153,65 @@ def test_import_from_vanilla(test_output_dir, pg_bin, vanilla_pg, neon_env_build
     vanilla_pg.stop()
 
 
+def test_import_from_vanilla_wal_dir(test_output_dir, pg_bin, vanilla_pg, neon_env_builder):
+    """
+    Import a basebackup with the WAL from a pg_wal directory, and let neon_local
+    validate the result by starting an endpoint on it.
+    """
+    vanilla_pg.start()
+    vanilla_pg.safe_psql("create user cloud_admin with password 'postgres' superuser")
+    vanilla_pg.safe_psql("create table t as select g from generate_series(1,100000) g")
+
+    basebackup_dir = os.path.join(test_output_dir, "basebackup")
+    os.mkdir(basebackup_dir)
+    pg_bin.run(["pg_basebackup", "-F", "tar", "-d", vanilla_pg.connstr(), "-D", basebackup_dir])
+    vanilla_pg.stop()
+
+    wal_dir = os.path.join(basebackup_dir, "pg_wal")
+    os.mkdir(wal_dir)
+    subprocess_capture(
+        test_output_dir, ["tar", "-xf", os.path.join(basebackup_dir, "pg_wal.tar"), "-C", wal_dir]
+    )
+    with open(os.path.join(basebackup_dir, "backup_manifest")) as f:
+        manifest = json.load(f)
+        start_lsn = manifest["WAL-Ranges"][0]["Start-LSN"]
+        end_lsn = manifest["WAL-Ranges"][0]["End-LSN"]
+
+    env = neon_env_builder.init_start()
+    tenant = TenantId.generate()
+    timeline = TimelineId.generate()
+    env.pageserver.tenant_create(tenant)
+
+    endpoint_id = "ep-import_wal_dir"
+    res = env.neon_cli.raw_cli(
+        [
+            "timeline",
+            "import",
+            "--tenant-id",
+            str(tenant),
+            "--timeline-id",
+            str(timeline),
+            "--node-name",
+            endpoint_id,
+            "--base-lsn",
+            start_lsn,
+            "--base",
+            os.path.join(basebackup_dir, "base.tar"),
+            "--end-lsn",
+            end_lsn,
+            "--wal",
+            wal_dir,
+            "--pg-version",
+            env.pg_version,
+            "--validate",
+        ]
+    )
+    assert "database postgres" in res.stdout
+
+    endpoint = env.endpoints.create_start(endpoint_id, tenant_id=tenant)
+    assert endpoint.safe_psql("select count(*) from t") == [(100000,)]
+
+
 def test_import_from_pageserver_small(
     pg_bin: PgBin, neon_env_builder: NeonEnvBuilder, test_output_dir: Path
 ):
@@ -6,8 +6,9 @@ from fixtures.neon_fixtures import (
     NeonEnvBuilder,
     wait_for_last_flush_lsn,
 )
+from fixtures.pageserver.http import PageserverApiException
 from fixtures.pageserver.utils import wait_for_last_record_lsn, wait_for_upload
-from fixtures.remote_storage import RemoteStorageKind
+from fixtures.remote_storage import LocalFsStorage, RemoteStorageKind
 from fixtures.types import Lsn
 from fixtures.utils import query_scalar
 
@@ -300,3 +301,142 @@ def test_gc_of_remote_layers(neon_env_builder: NeonEnvBuilder):
 
     log.info("after running GC, ensure that resident size is still zero")
     ensure_resident_and_remote_size_metrics()
+
+
+# Corrupt layers in the remote storage, and check that downloading them fails and leaves them
+# quarantined next to the timeline's layers, rather than in place of the layer.
+def test_download_corrupt_layer(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # disable gc and compaction background loops because they perform on-demand downloads
+            "gc_period": "0s",
+            "compaction_period": "0s",
+            "checkpoint_distance": f"{1024 ** 2}",
+        }
+    )
+    env.pageserver.allowed_errors.extend(
+        [
+            ".*downloaded layer file failed verification.*",
+            ".*layer file download failed.*",
+            ".*Error processing HTTP request: InternalServerError.*",
+        ]
+    )
+    client = env.pageserver.http_client()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+
+    with env.endpoints.create_start("main") as endpoint:
+        endpoint.safe_psql("CREATE TABLE foo (t text)")
+        endpoint.safe_psql(
+            "INSERT INTO foo SELECT 'long string' || g FROM generate_series(1, 100000) g"
+        )
+        last_flush_lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload(client, tenant_id, timeline_id, last_flush_lsn)
+
+    layers = [
+        layer.layer_file_name
+        for layer in client.layer_map_info(tenant_id, timeline_id).historic_layers
+        if layer.kind == "Delta"
+    ]
+    assert len(layers) >= 2, f"expected several delta layers, got {layers}"
+    remote_storage = env.pageserver_remote_storage
+    assert isinstance(remote_storage, LocalFsStorage)
+
+    # The first layer has a bad summary, and the second one a bad data block, which its checksum
+    # catches.
+    page_size = 8192
+    for layer_name, offset in [(layers[0], 0), (layers[1], page_size)]:
+        client.evict_layer(tenant_id, timeline_id, layer_name)
+        path = remote_storage.remote_layer_path(tenant_id, timeline_id, layer_name)
+        with open(path, "r+b") as f:
+            f.seek(offset)
+            byte = f.read(1)
+            f.seek(offset)
+            f.write(bytes([byte[0] ^ 0xFF]))
+
+    timeline_dir = env.pageserver.timeline_dir(tenant_id, timeline_id)
+    for layer_name in layers[:2]:
+        with pytest.raises(PageserverApiException):
+            client.download_layer(tenant_id, timeline_id, layer_name)
+        assert not (timeline_dir / layer_name).exists()
+        assert (timeline_dir / f"{layer_name}.corrupt").exists()
+
+    assert client.get_metric_value("pageserver_layer_download_verification_failures_total") == 2
+    remote_layers = {
+        layer.layer_file_name
+        for layer in client.layer_map_info(tenant_id, timeline_id).historic_layers
+        if layer.remote
+    }
+    assert set(layers[:2]) <= remote_layers
+
+    # The quarantined files are left alone when the timeline is loaded again
+    env.pageserver.restart()
+    for layer_name in layers[:2]:
+        assert (timeline_dir / f"{layer_name}.corrupt").exists()
+
+
+# The residency and access statistics of the layers follow evictions and reads.
+def test_layer_residency_listing(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # disable gc and compaction background loops because they perform on-demand downloads
+            "gc_period": "0s",
+            "compaction_period": "0s",
+        }
+    )
+    client = env.pageserver.http_client()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g FROM generate_series(1, 10000) g")
+    last_flush_lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    endpoint.stop()
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload(client, tenant_id, timeline_id, last_flush_lsn)
+
+    layer_map = client.layer_map_info(tenant_id, timeline_id)
+    layers = {
+        layer["layer_file_name"]: layer
+        for layer in client.timeline_layers(tenant_id, timeline_id).json()
+    }
+    assert set(layers) == {layer.layer_file_name for layer in layer_map.historic_layers}
+    for layer in layer_map.historic_layers:
+        info = layers[layer.layer_file_name]
+        assert info["layer_file_size"] == layer.layer_file_size
+        assert info["residence"] == "Resident"
+        if layer.kind == "Image":
+            assert info["creation_reason"] == "Image"
+        else:
+            assert info["creation_reason"] in ("Flush", "Compaction")
+
+    evicted = layer_map.historic_layers[0].layer_file_name
+    client.evict_layer(tenant_id, timeline_id, evicted)
+    layers = {
+        layer["layer_file_name"]: layer
+        for layer in client.timeline_layers(tenant_id, timeline_id).json()
+    }
+    assert layers[evicted]["residence"] == "Evicted"
+
+    # Reading the data through a new compute accesses the layers
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM foo")[0][0] == 10000
+    endpoint.stop()
+    layers = client.timeline_layers(tenant_id, timeline_id).json()
+    accessed = [layer for layer in layers if layer["access_count"] > 0]
+    assert len(accessed) > 0
+    assert all(layer["last_access_millis_since_epoch"] is not None for layer in accessed)
+
+    csv = client.timeline_layers(tenant_id, timeline_id, format="csv")
+    assert csv.headers["Content-Type"] == "text/csv"
+    lines = csv.text.splitlines()
+    assert lines[0].startswith("layer_file_name,layer_file_size,residence")
+    assert len(lines) == len(layers) + 1
+
+    with pytest.raises(PageserverApiException, match="unsupported format"):
+        client.timeline_layers(tenant_id, timeline_id, format="xml")
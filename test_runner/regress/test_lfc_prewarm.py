@@ -0,0 +1,68 @@
+import requests
+from fixtures.log_helper import log
+from fixtures.neon_fixtures import NeonEnv
+from fixtures.utils import wait_until
+
+
+def test_lfc_prewarm(neon_simple_env: NeonEnv):
+    """
+    The local file cache state saved with /lfc_state is used to prewarm the
+    cache of the restarted compute.
+    """
+    env = neon_simple_env
+    env.neon_cli.create_branch("test_lfc_prewarm", "empty")
+    endpoint = env.endpoints.create(
+        "test_lfc_prewarm",
+        config_lines=[
+            "neon.file_cache_path='file.cache'",
+            "neon.max_file_cache_size=1GB",
+            "neon.file_cache_size_limit=1GB",
+        ],
+    )
+    # don't skip pg_catalog updates - it runs CREATE EXTENSION neon
+    endpoint.respec(skip_pg_catalog_updates=False)
+    endpoint.start(lfc_state=True)
+
+    def status():
+        res = requests.get(f"http://localhost:{endpoint.http_port}/status")
+        res.raise_for_status()
+        return res.json()
+
+    # nothing was saved yet
+    assert "lfc_prewarm" not in status()
+
+    endpoint.safe_psql(
+        "CREATE TABLE t AS SELECT g, repeat('x', 100) FROM generate_series(1, 100000) g"
+    )
+    assert endpoint.safe_psql("SELECT sum(g) FROM t")[0][0] == 5000050000
+    cached_blocks = endpoint.safe_psql("SELECT count(*) FROM neon.local_cache")[0][0]
+    assert cached_blocks > 0
+
+    res = requests.post(f"http://localhost:{endpoint.http_port}/lfc_state")
+    res.raise_for_status()
+    saved_blocks = res.json()["total_blocks"]
+    log.info(f"saved {saved_blocks} of {cached_blocks} cached blocks")
+    assert saved_blocks > 0
+
+    # the data directory, with the cache file in it, is recreated on start
+    endpoint.stop()
+    endpoint.start(lfc_state=True)
+
+    def prewarm_completed():
+        progress = status()["lfc_prewarm"]
+        log.info(f"prewarm progress: {progress}")
+        assert progress["status"] == "completed"
+        return progress
+
+    progress = wait_until(30, 1, prewarm_completed)
+    assert progress["total_blocks"] == saved_blocks
+    assert progress["prewarmed_blocks"] == saved_blocks
+    assert 0 < progress["fetched_blocks"] <= saved_blocks
+    assert progress["error"] is None
+
+    # the prewarmed pages are in the cache before anything reads them
+    assert (
+        endpoint.safe_psql("SELECT count(*) FROM neon.local_cache")[0][0]
+        >= progress["fetched_blocks"]
+    )
+    assert endpoint.safe_psql("SELECT sum(g) FROM t")[0][0] == 5000050000
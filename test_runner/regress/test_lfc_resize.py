@@ -2,6 +2,7 @@ import threading
 import time
 
 import pytest
+import requests
 from fixtures.log_helper import log
 from fixtures.neon_fixtures import NeonEnv, PgBin
 
@@ -41,3 +42,52 @@ def test_lfc_resize(neon_simple_env: NeonEnv, pg_bin: PgBin):
         time.sleep(1)
 
     thread.join()
+
+
+def test_lfc_resize_api(neon_simple_env: NeonEnv):
+    """
+    /lfc_size of compute_ctl resizes the local file cache of the running compute.
+    """
+    env = neon_simple_env
+    env.neon_cli.create_branch("test_lfc_resize_api", "empty")
+    endpoint = env.endpoints.create_start(
+        "test_lfc_resize_api",
+        config_lines=[
+            "neon.file_cache_path='file.cache'",
+            "neon.max_file_cache_size=1GB",
+            "neon.file_cache_size_limit=1GB",
+        ],
+    )
+    url = f"http://localhost:{endpoint.http_port}/lfc_size"
+    MB = 1024 * 1024
+
+    def size_limit() -> str:
+        return endpoint.safe_psql("SHOW neon.file_cache_size_limit")[0][0]
+
+    endpoint.safe_psql(
+        "CREATE TABLE t AS SELECT g, repeat('x', 100) FROM generate_series(1, 100000) g"
+    )
+
+    res = requests.get(url)
+    res.raise_for_status()
+    assert res.json()["size_bytes"] == 1024 * MB
+
+    res = requests.post(url, json={"size_bytes": 100 * MB})
+    res.raise_for_status()
+    assert res.json()["size_bytes"] == 100 * MB
+    assert size_limit() == "100MB"
+    assert requests.get(url).json()["size_bytes"] == 100 * MB
+
+    # capped by neon.max_file_cache_size
+    res = requests.post(url, json={"size_bytes": 2048 * MB})
+    res.raise_for_status()
+    assert res.json()["size_bytes"] == 1024 * MB
+    assert size_limit() == "1GB"
+
+    # removing the cap leaves the size as it is
+    res = requests.post(url, json={"size_bytes": None})
+    res.raise_for_status()
+    assert res.json()["size_bytes"] == 1024 * MB
+    assert size_limit() == "1GB"
+
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 100000
@@ -1,3 +1,4 @@
+import math
 import os
 import random
 import threading
@@ -72,3 +73,54 @@ def test_local_file_cache_unlink(neon_simple_env: NeonEnv):
         thread.join()
 
     assert query_scalar(cur, "SELECT SUM(n) FROM lfctest") == n_total_updates + n_rows
+
+
+def test_lfc_working_set_approximation(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+
+    cache_dir = os.path.join(env.repo_dir, "file_cache")
+    os.mkdir(cache_dir)
+
+    env.neon_cli.create_branch("test_lfc_working_set_approximation", "empty")
+
+    endpoint = env.endpoints.create_start(
+        "test_lfc_working_set_approximation",
+        config_lines=[
+            "shared_buffers='1MB'",
+            f"neon.file_cache_path='{cache_dir}/file.cache'",
+            "neon.max_file_cache_size='128MB'",
+            "neon.file_cache_size_limit='64MB'",
+        ],
+    )
+
+    cur = endpoint.connect().cursor()
+    cur.execute(
+        "CREATE TABLE t(pk integer PRIMARY KEY, n integer DEFAULT 0, "
+        "payload text DEFAULT repeat('?', 128))"
+    )
+    cur.execute("INSERT INTO t (pk) SELECT generate_series(1, 1000000)")
+    time.sleep(2)
+
+    # about 50 rows fit in a page
+    before_10k = time.monotonic()
+    cur.execute("SELECT sum(n) FROM t WHERE pk BETWEEN 10000 AND 20000")
+    time.sleep(2)
+    before_1k = time.monotonic()
+    cur.execute("SELECT sum(n) FROM t WHERE pk BETWEEN 1000 AND 2000")
+    after = time.monotonic()
+
+    duration = math.ceil(after - before_1k)
+    estimation_1k = query_scalar(
+        cur, f"SELECT neon.approximate_working_set_size_seconds({duration})"
+    )
+    assert 20 <= estimation_1k <= 40
+
+    duration = math.ceil(after - before_10k)
+    estimation_10k = query_scalar(
+        cur, f"SELECT neon.approximate_working_set_size_seconds({duration})"
+    )
+    assert 200 <= estimation_10k <= 400
+
+    # without a duration, everything read since start is counted
+    estimation_all = query_scalar(cur, "SELECT neon.approximate_working_set_size_seconds()")
+    assert estimation_all >= estimation_10k
@@ -462,6 +462,11 @@ def test_secondary_downloads(neon_env_builder: NeonEnvBuilder):
         ps_secondary, tenant_id, timeline_id
     )
 
+    progress = ps_secondary.http_client().tenant_secondary_status(tenant_id)
+    assert progress["layers_total"] == len(list_layers(ps_secondary, tenant_id, timeline_id))
+    assert progress["layers_downloaded"] == progress["layers_total"]
+    assert progress["bytes_downloaded"] == progress["bytes_total"]
+
     # Make changes on attached pageserver, check secondary downloads them
     # ===================================================================
     log.info("Synchronizing after subsequent write...")
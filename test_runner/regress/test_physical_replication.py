@@ -1,7 +1,9 @@
 import random
 import time
 
+import requests
 from fixtures.neon_fixtures import NeonEnv
+from fixtures.types import Lsn, TimelineId
 
 
 def test_physical_replication(neon_simple_env: NeonEnv):
@@ -27,3 +29,49 @@ def test_physical_replication(neon_simple_env: NeonEnv):
                                 s_cur.execute(
                                     "select * from t where pk=%s", (random.randrange(1, n_records),)
                                 )
+
+
+def test_replica_promote(neon_simple_env: NeonEnv):
+    """
+    Promote a replica once its primary is stopped, and write through it: the walproposer of the
+    promoted replica should accept the timeline switch, and the new WAL should reach the
+    safekeepers and the pageserver.
+    """
+    env = neon_simple_env
+    primary = env.endpoints.create_start(branch_name="main", endpoint_id="primary")
+    primary.safe_psql("CREATE TABLE t(pk bigint primary key, payload text)")
+    primary.safe_psql("INSERT INTO t SELECT g, 'primary' FROM generate_series(1, 1000) g")
+    tenant_id = env.initial_tenant
+    timeline_id = TimelineId(primary.safe_psql("show neon.timeline_id")[0][0])
+
+    secondary = env.endpoints.new_replica_start(origin=primary, endpoint_id="secondary")
+    primary.stop()
+
+    # Promote once the replica has replayed all the WAL of the old primary
+    flush_lsn = max(
+        sk.http_client().timeline_status(tenant_id, timeline_id).flush_lsn
+        for sk in env.safekeepers
+    )
+    res = requests.post(
+        f"http://localhost:{secondary.http_port}/promote", json={"wait_lsn": str(flush_lsn)}
+    )
+    res.raise_for_status()
+    promoted_lsn = Lsn(res.json()["lsn"])
+    assert promoted_lsn >= flush_lsn
+
+    secondary.safe_psql("INSERT INTO t SELECT g, 'promoted' FROM generate_series(1001, 2000) g")
+    assert secondary.safe_psql("SELECT count(*) FROM t")[0][0] == 2000
+    assert secondary.safe_psql("SELECT pg_is_in_recovery()")[0][0] is False
+
+    # The WAL of the promoted replica is on the safekeepers, and a new primary starting from
+    # the pageserver sees it.
+    end_lsn = Lsn(secondary.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+    secondary.stop()
+    sk_flush_lsn = max(
+        sk.http_client().timeline_status(tenant_id, timeline_id).flush_lsn
+        for sk in env.safekeepers
+    )
+    assert sk_flush_lsn >= end_lsn
+
+    primary = env.endpoints.create_start(branch_name="main", endpoint_id="new_primary")
+    assert primary.safe_psql("SELECT count(*) FROM t WHERE payload = 'promoted'")[0][0] == 1000
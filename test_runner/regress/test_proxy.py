@@ -2,12 +2,16 @@ import asyncio
 import json
 import subprocess
 import time
-from typing import Any, List, Optional, Tuple
+from pathlib import Path
+from typing import Any, Dict, List, Optional, Tuple
 
+import jwt
 import psycopg2
 import pytest
 import requests
-from fixtures.neon_fixtures import PSQL, NeonProxy, VanillaPostgres
+from cryptography.hazmat.primitives.asymmetric import ec
+from fixtures.neon_fixtures import PSQL, AuthKeys, NeonProxy, VanillaPostgres
+from pytest_httpserver import HTTPServer
 
 GET_CONNECTION_PID_QUERY = "SELECT pid FROM pg_stat_activity WHERE state = 'active'"
 
@@ -53,6 +57,22 @@ def test_proxy_select_1(static_proxy: NeonProxy):
     assert out[0][0] == 42
 
 
+def test_proxy_channel_binding(static_proxy: NeonProxy):
+    """
+    Check that clients requiring channel binding (SCRAM-SHA-256-PLUS) can connect.
+    """
+    static_proxy.safe_psql("create role binding with login password 'binding'")
+
+    out = static_proxy.safe_psql(
+        "select 1",
+        user="binding",
+        password="binding",
+        host="generic-project-name.localtest.me",
+        channel_binding="require",
+    )
+    assert out[0][0] == 1
+
+
 def test_password_hack(static_proxy: NeonProxy):
     """
     Check the PasswordHack auth flow: an alternative to SCRAM auth for
@@ -156,6 +176,170 @@ def test_auth_errors(static_proxy: NeonProxy):
         pass
 
 
+def enable_http_auth(proxy: NeonProxy, dir: Path) -> AuthKeys:
+    """
+    Restart the proxy with a public key for the routes of its http listener which change state,
+    and return the keys to sign tokens for them with.
+    """
+    priv_path = dir / "http_auth_private_key.pem"
+    pub_path = dir / "http_auth_public_key.pem"
+    subprocess.run(
+        ["openssl", "genpkey", "-algorithm", "ed25519", "-out", priv_path],
+        check=True,
+    )
+    subprocess.run(["openssl", "pkey", "-in", priv_path, "-pubout", "-out", pub_path], check=True)
+    proxy.restart(extra_args=["--http-auth-public-key-path", str(pub_path)])
+    return AuthKeys(priv=priv_path.read_text())
+
+
+def generate_client_certificate(dir: Path, role: str):
+    """
+    Generate a certificate authority in `dir/generic-project-name/ca.crt`, where the proxy
+    looks for the endpoint's CA, and a client certificate for `role` signed by it.
+    """
+    ca_dir = dir / "generic-project-name"
+    ca_dir.mkdir(parents=True)
+    openssl = ["openssl", "req", "-new", "-nodes"]
+    subprocess.run(
+        [*openssl, "-x509", "-days", "1", "-subj", "/CN=test-ca"]
+        + ["-keyout", str(dir / "ca.key"), "-out", str(ca_dir / "ca.crt")],
+        check=True,
+    )
+    subprocess.run(
+        [*openssl, "-subj", f"/CN={role}"]
+        + ["-keyout", str(dir / "client.key"), "-out", str(dir / "client.csr")],
+        check=True,
+    )
+    # Certificates must be X.509 v3, which the extensions make them.
+    (dir / "client.ext").write_text(f"subjectAltName=DNS:{role}\nextendedKeyUsage=clientAuth\n")
+    subprocess.run(
+        ["openssl", "x509", "-req", "-days", "1", "-in", str(dir / "client.csr")]
+        + ["-CA", str(ca_dir / "ca.crt"), "-CAkey", str(dir / "ca.key"), "-CAcreateserial"]
+        + ["-extfile", str(dir / "client.ext"), "-out", str(dir / "client.crt")],
+        check=True,
+    )
+    # libpq refuses to use keys others can read
+    (dir / "client.key").chmod(0o600)
+
+
+def test_proxy_client_certificate(
+    static_proxy: NeonProxy, vanilla_pg: VanillaPostgres, test_output_dir: Path
+):
+    """
+    Check that a client authenticated with a certificate gets through to the compute,
+    which requires the role's password: the proxy logs in with the one from the console.
+    """
+    role = "cert_user"
+    static_proxy.safe_psql(f"create role {role} with login password 'compute-password'")
+
+    # make the compute require the password of the role
+    vanilla_pg.stop()
+    host = vanilla_pg.default_options["host"]
+    vanilla_pg.edit_hba([f"host all {role} {host} scram-sha-256"])
+    vanilla_pg.start()
+
+    ca_dir = test_output_dir / "client-ca"
+    generate_client_certificate(ca_dir, role)
+    static_proxy.restart(extra_args=["--client-ca-dir", str(ca_dir)])
+
+    def connect(**kwargs):
+        return static_proxy.safe_psql(
+            "select current_user",
+            user=role,
+            password=None,
+            host="generic-project-name.localtest.me",
+            sslcert=str(ca_dir / "client.crt"),
+            sslkey=str(ca_dir / "client.key"),
+            **kwargs,
+        )
+
+    # the console has no password for the role, so there is no way to log into the compute
+    with pytest.raises(psycopg2.Error) as exprinfo:
+        connect()
+    assert exprinfo.value.pgcode == "28000"
+
+    vanilla_pg.safe_psql(
+        "insert into neon_control_plane.role_passwords (role_name, password) "
+        f"values ('{role}', 'compute-password')"
+    )
+    assert connect()[0][0] == role
+
+    # the certificate is issued for this role only
+    with pytest.raises(psycopg2.Error) as exprinfo:
+        static_proxy.safe_psql(
+            "select 1",
+            user="proxy",
+            host="generic-project-name.localtest.me",
+            sslcert=str(ca_dir / "client.crt"),
+            sslkey=str(ca_dir / "client.key"),
+        )
+    assert exprinfo.value.pgcode == "28000"
+
+
+def test_sql_over_http_jwt(
+    static_proxy: NeonProxy, vanilla_pg: VanillaPostgres, httpserver: HTTPServer
+):
+    """
+    Check that a client authenticated with a JWT gets through to the compute,
+    which requires the role's password: the proxy logs in with the one from the console.
+    """
+    role = "jwt_user"
+    static_proxy.safe_psql(f"create role {role} with login password 'compute-password'")
+
+    # make the compute require the password of the role
+    vanilla_pg.stop()
+    host = vanilla_pg.default_options["host"]
+    vanilla_pg.edit_hba([f"host all {role} {host} scram-sha-256"])
+    vanilla_pg.start()
+
+    key = ec.generate_private_key(ec.SECP256R1())
+    jwk = json.loads(jwt.algorithms.ECAlgorithm.to_jwk(key.public_key()))
+    jwk["kid"] = "test-key"
+    httpserver.expect_request("/jwks.json").respond_with_json({"keys": [jwk]})
+    jwks_url = httpserver.url_for("/jwks.json")
+    vanilla_pg.safe_psql(
+        "insert into neon_control_plane.endpoint_jwks "
+        "(id, endpoint_id, jwks_url, jwt_audience, role_names) "
+        f"values ('test-provider', 'generic-project-name', '{jwks_url}', null, '{role}')"
+    )
+
+    def query(role_claim: str) -> requests.Response:
+        token = jwt.encode(
+            {"role": role_claim, "exp": int(time.time()) + 60},
+            key,
+            algorithm="ES256",
+            headers={"kid": "test-key"},
+        )
+        endpoint_host = "generic-project-name.localtest.me"
+        connstr = f"postgresql://{role}@{endpoint_host}:{static_proxy.proxy_port}/postgres"
+        return requests.post(
+            f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql",
+            data=json.dumps({"query": "select current_user", "params": []}),
+            headers={
+                "Content-Type": "application/sql",
+                "Neon-Connection-String": connstr,
+                "Authorization": f"Bearer {token}",
+            },
+            verify=str(static_proxy.test_output_dir / "proxy.crt"),
+        )
+
+    # the console has no password for the role, so there is no way to log into the compute
+    response = query(role)
+    assert response.status_code == 400, f"response: {response.json()}"
+
+    vanilla_pg.safe_psql(
+        "insert into neon_control_plane.role_passwords (role_name, password) "
+        f"values ('{role}', 'compute-password')"
+    )
+    response = query(role)
+    assert response.status_code == 200, f"response: {response.json()}"
+    assert response.json()["rows"] == [{"current_user": role}]
+
+    # the token is issued for another role
+    response = query("proxy")
+    assert response.status_code == 400, f"response: {response.json()}"
+
+
 def test_forward_params_to_client(static_proxy: NeonProxy):
     """
     Check that we forward all necessary PostgreSQL server params to client.
@@ -423,6 +607,112 @@ def test_sql_over_http_batch_output_options(static_proxy: NeonProxy):
     assert results[1]["rows"] == [{"answer": "42"}]
 
 
+def test_sql_over_http_batch_route(static_proxy: NeonProxy):
+    static_proxy.safe_psql("create role http with login password 'http' superuser")
+    static_proxy.safe_psql("create table t_batch(id int primary key)")
+
+    def batch(queries: Any, status: int = 200) -> Any:
+        connstr = f"postgresql://http:http@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+        response = requests.post(
+            f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql/batch",
+            data=json.dumps(queries),
+            headers={"Content-Type": "application/sql", "Neon-Connection-String": connstr},
+            verify=str(static_proxy.test_output_dir / "proxy.crt"),
+        )
+        assert response.status_code == status
+        return response.json()
+
+    # a plain list of queries
+    results = batch(
+        [
+            {"query": "insert into t_batch values (1)", "params": []},
+            {"query": "select count(*) as n from t_batch", "params": []},
+        ]
+    )["results"]
+    assert results[0]["rowCount"] == 1
+    assert results[1]["rows"] == [{"n": 1}]
+
+    # the same body as for /sql
+    results = batch({"queries": [{"query": "select 42 as answer", "params": []}]})["results"]
+    assert results[0]["rows"] == [{"answer": 42}]
+
+    # the first error is returned, and the whole batch is rolled back
+    error = batch(
+        [
+            {"query": "insert into t_batch values (2)", "params": []},
+            {"query": "insert into t_batch values (1)", "params": []},
+            {"query": "select 1", "params": []},
+        ],
+        status=400,
+    )
+    assert error["code"] == "23505"
+    assert error["statementIndex"] == 1
+    assert static_proxy.safe_psql("select id from t_batch order by id") == [(1,)]
+
+    # single queries are not batches
+    batch({"query": "select 1", "params": []}, status=400)
+
+
+def test_sql_over_http_sticky_transaction(static_proxy: NeonProxy):
+    static_proxy.safe_psql("create role http with login password 'http' superuser")
+
+    def q(sql: str, headers: Dict[str, str], status: int = 200) -> requests.Response:
+        connstr = f"postgresql://http:http@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+        response = requests.post(
+            f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql",
+            data=json.dumps({"query": sql, "params": []}),
+            headers={
+                "Content-Type": "application/sql",
+                "Neon-Connection-String": connstr,
+                **headers,
+            },
+            verify=str(static_proxy.test_output_dir / "proxy.crt"),
+        )
+        assert response.status_code == status, f"response: {response.json()}"
+        return response
+
+    q("create table t(id int)", {})
+
+    # open a transaction and continue it with the returned token
+    res = q("insert into t values (1)", {"Neon-Transaction-Begin": "true"})
+    token = res.headers["Neon-Transaction-Token"]
+    res = q("select pg_backend_pid() as pid", {"Neon-Transaction-Token": token})
+    pid = res.json()["rows"][0]["pid"]
+    res = q("select txid_current_if_assigned() is not null as x", {"Neon-Transaction-Token": token})
+    assert res.json()["rows"] == [{"x": True}]
+    assert res.headers["Neon-Transaction-Token"] == token
+
+    # not visible outside of the transaction
+    assert q("select count(*) as c from t", {}).json()["rows"] == [{"c": 0}]
+
+    res = q(
+        "select pg_backend_pid() as pid",
+        {"Neon-Transaction-Token": token, "Neon-Transaction-End": "commit"},
+    )
+    assert res.json()["rows"] == [{"pid": pid}]
+    assert "Neon-Transaction-Token" not in res.headers
+    assert q("select count(*) as c from t", {}).json()["rows"] == [{"c": 1}]
+
+    # token is not valid after the end of transaction
+    res = q("select 1", {"Neon-Transaction-Token": token}, status=400)
+    assert "not found" in res.json()["message"]
+
+    # rollback
+    token = q("insert into t values (2)", {"Neon-Transaction-Begin": "true"}).headers[
+        "Neon-Transaction-Token"
+    ]
+    q("select 1", {"Neon-Transaction-Token": token, "Neon-Transaction-End": "rollback"})
+    assert q("select count(*) as c from t", {}).json()["rows"] == [{"c": 1}]
+
+    # error aborts the transaction
+    token = q("insert into t values (3)", {"Neon-Transaction-Begin": "true"}).headers[
+        "Neon-Transaction-Token"
+    ]
+    q("select garbage", {"Neon-Transaction-Token": token}, status=400)
+    q("select 1", {"Neon-Transaction-Token": token}, status=400)
+    assert q("select count(*) as c from t", {}).json()["rows"] == [{"c": 1}]
+
+
 def test_sql_over_http_pool(static_proxy: NeonProxy):
     static_proxy.safe_psql("create user http_auth with password 'http' superuser")
 
@@ -462,6 +752,107 @@ def test_sql_over_http_pool(static_proxy: NeonProxy):
     assert "password authentication failed for user" in res["message"]
 
 
+def test_sql_over_http_prepared_statements(static_proxy: NeonProxy):
+    static_proxy.safe_psql("create user http_auth with password 'http' superuser")
+    connstr = f"postgresql://http_auth:http@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+
+    def q(query: str, params: List[Any], name: Optional[str] = None) -> Any:
+        data: Dict[str, Any] = {"query": query, "params": params}
+        if name is not None:
+            data["name"] = name
+        response = requests.post(
+            f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql",
+            data=json.dumps(data),
+            headers={
+                "Content-Type": "application/sql",
+                "Neon-Connection-String": connstr,
+                "Neon-Pool-Opt-In": "true",
+            },
+            verify=str(static_proxy.test_output_dir / "proxy.crt"),
+        )
+        assert response.status_code == 200, f"response: {response.json()}"
+        return response.json()
+
+    add_one = "select $1::int + 1 as n"
+    assert q(add_one, [1], name="add_one")["rows"] == [{"n": 2}]
+    time.sleep(0.02)
+    assert q(add_one, [2], name="add_one")["rows"] == [{"n": 3}]
+    time.sleep(0.02)
+
+    # the statement is prepared once and kept with the pooled connection
+    rows = q("select statement from pg_prepared_statements", [])["rows"]
+    assert rows == [{"statement": add_one}]
+    time.sleep(0.02)
+
+    # reusing the name for another query replaces the statement
+    add_two = "select $1::int + 2 as n"
+    assert q(add_two, [1], name="add_one")["rows"] == [{"n": 3}]
+    time.sleep(0.02)
+    rows = q("select statement from pg_prepared_statements", [])["rows"]
+    assert rows == [{"statement": add_two}]
+
+    metrics = static_proxy.get_metrics()
+    assert 'proxy_http_prepared_statements_total{outcome="hit"} 1' in metrics
+
+
+def test_sql_over_http_pool_limits(static_proxy: NeonProxy, test_output_dir: Path):
+    api = f"http://{static_proxy.host}:{static_proxy.http_port}/v1/sql_over_http/pool"
+
+    # the routes are disabled without auth
+    assert requests.get(api).status_code == 404
+
+    auth_keys = enable_http_auth(static_proxy, test_output_dir)
+    assert requests.get(api).status_code == 401
+    headers = {"Authorization": f"Bearer {auth_keys.generate_pageserver_token()}"}
+    assert requests.get(api, headers=headers).status_code == 403
+    headers = {"Authorization": f"Bearer {auth_keys.generate_proxy_token()}"}
+
+    status = requests.get(api, headers=headers).json()
+    assert status["endpoint_limits"] == {}
+    default_limits = status["default_limits"]
+
+    res = requests.put(
+        api,
+        headers=headers,
+        json={
+            "max_total_conns": 100,
+            "default_limits": {"max_conns": 5, "idle_timeout": "1m"},
+        },
+    )
+    res.raise_for_status()
+    status = res.json()
+    assert status["max_total_conns"] == 100
+    assert status["default_limits"] == {"max_conns": 5, "idle_timeout": "1m"}
+
+    limits = {"max_conns": 50, "idle_timeout": "10m"}
+    res = requests.put(f"{api}/endpoints/ep-big", headers=headers, json=limits)
+    res.raise_for_status()
+    assert res.json()["endpoint_limits"] == {"ep-big": limits}
+    assert "proxy_http_pool_endpoint_limit_overrides 1" in static_proxy.get_metrics()
+
+    res = requests.delete(f"{api}/endpoints/ep-big", headers=headers)
+    res.raise_for_status()
+    assert res.json()["endpoint_limits"] == {}
+
+    # invalid limits are rejected
+    limits = {"max_conns": -1, "idle_timeout": "1m"}
+    res = requests.put(f"{api}/endpoints/ep-big", headers=headers, json=limits)
+    assert res.status_code == 400
+
+    # limits above the per endpoint cap are rejected
+    limits = {"max_conns": 1001, "idle_timeout": "1m"}
+    res = requests.put(f"{api}/endpoints/ep-big", headers=headers, json=limits)
+    assert res.status_code == 400
+    res = requests.put(api, headers=headers, json={"default_limits": limits})
+    assert res.status_code == 400
+
+    requests.put(api, headers=headers, json={"default_limits": default_limits}).raise_for_status()
+
+    # pool still works after the changes
+    static_proxy.safe_psql("create user http_auth with password 'http' superuser")
+    static_proxy.http_query("select 1", [], user="http_auth", password="http", expected_code=200)
+
+
 def test_sql_over_http_urlencoding(static_proxy: NeonProxy):
     static_proxy.safe_psql("create user \"http+auth$$\" with password '%+$^&*@!' superuser")
 
@@ -554,3 +945,33 @@ def test_sql_over_http_pool_custom_types(static_proxy: NeonProxy):
         "select array['foo'::foo, 'bar'::foo, 'baz'::foo] as data",
     )
     assert response["rows"][0]["data"] == ["foo", "bar", "baz"]
+
+
+def test_proxy_drain(static_proxy: NeonProxy, test_output_dir: Path):
+    status_api = f"http://{static_proxy.host}:{static_proxy.http_port}/v1/status"
+    drain_api = f"http://{static_proxy.host}:{static_proxy.http_port}/v1/drain"
+
+    # the route is disabled without auth
+    assert requests.post(drain_api).status_code == 404
+
+    auth_keys = enable_http_auth(static_proxy, test_output_dir)
+    assert requests.post(drain_api).status_code == 401
+    headers = {"Authorization": f"Bearer {auth_keys.generate_proxy_token()}"}
+
+    with static_proxy.connect() as conn:
+        with conn.cursor() as cur:
+            cur.execute("select 1")
+            assert cur.fetchone() == (1,)
+
+            requests.get(status_api).raise_for_status()
+            requests.post(drain_api, headers=headers).raise_for_status()
+
+            # the load balancer should stop routing new connections here
+            assert requests.get(status_api).status_code == 503
+
+            # new connections are refused, but the existing ones keep working
+            with pytest.raises(psycopg2.OperationalError):
+                static_proxy.safe_psql("select 1")
+
+            cur.execute("select 2")
+            assert cur.fetchone() == (2,)
@@ -1,5 +1,6 @@
 import psycopg2
 import pytest
+from fixtures.metrics import parse_metrics
 from fixtures.neon_fixtures import (
     NeonProxy,
     VanillaPostgres,
@@ -25,6 +26,7 @@ async def test_proxy_psql_allowed_ips(static_proxy: NeonProxy, vanilla_pg: Vanil
             static_proxy.safe_psql(**kwargs)
         text = str(exprinfo.value).strip()
         assert "This IP address is not allowed to connect" in text
+        assert exprinfo.value.pgcode == "28000"
 
     # no SNI, deprecated `options=project` syntax (before we had several endpoint in project)
     check_cannot_connect(query="select 1", sslsni=0, options="project=private-project")
@@ -47,6 +49,10 @@ async def test_proxy_psql_allowed_ips(static_proxy: NeonProxy, vanilla_pg: Vanil
     out = static_proxy.safe_psql(query="select 1", host="generic-project.localtest.me")
     assert out[0][0] == 1
 
+    metrics = parse_metrics(static_proxy.get_metrics())
+    rejections = metrics.query_one("proxy_ip_not_allowed_rejections_total", {"protocol": "tcp"})
+    assert rejections.value == 3
+
 
 @pytest.mark.asyncio
 async def test_proxy_http_allowed_ips(static_proxy: NeonProxy, vanilla_pg: VanillaPostgres):
@@ -58,7 +64,7 @@ async def test_proxy_http_allowed_ips(static_proxy: NeonProxy, vanilla_pg: Vanil
     )
 
     def query(status: int, query: str, *args):
-        static_proxy.http_query(
+        return static_proxy.http_query(
             query,
             args,
             user="http_auth",
@@ -66,7 +72,8 @@ async def test_proxy_http_allowed_ips(static_proxy: NeonProxy, vanilla_pg: Vanil
             expected_code=status,
         )
 
-    query(400, "select 1;")  # ip address is not allowed
+    res = query(400, "select 1;")  # ip address is not allowed
+    assert res["code"] == "28000"
     # Should be able to connect to this project
     vanilla_pg.safe_psql(
         f"UPDATE {TABLE_NAME} SET allowed_ips = '8.8.8.8,127.0.0.1' WHERE endpoint_id = 'proxy'"
@@ -0,0 +1,74 @@
+import json
+from pathlib import Path
+
+import requests
+from fixtures.neon_fixtures import NeonEnv, NeonProxy
+from fixtures.pg_version import PgVersion, skip_on_postgres
+from fixtures.port_distributor import PortDistributor
+
+
+# PG14 only warns about unknown settings with the `neon.` prefix, PG15+ refuses them,
+# so this checks that the setting is defined by the extension.
+@skip_on_postgres(PgVersion.V14, reason="PG14 does not reserve the neon prefix")
+def test_sql_over_http_traceparent(
+    neon_simple_env: NeonEnv,
+    port_distributor: PortDistributor,
+    neon_binpath: Path,
+    test_output_dir: Path,
+):
+    """
+    Check that the trace context of a sampled sql-over-http request reaches a
+    real compute as `neon.traceparent`, and that pooled connections reset it.
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("create role http with login password 'http' superuser")
+    endpoint.safe_psql("create schema if not exists neon_control_plane")
+    endpoint.safe_psql(
+        "create table neon_control_plane.endpoints (endpoint_id varchar(255) primary key, allowed_ips varchar(255))"
+    )
+
+    auth_endpoint = f"postgres://cloud_admin@localhost:{endpoint.pg_port}/postgres"
+    with NeonProxy(
+        neon_binpath=neon_binpath,
+        test_output_dir=test_output_dir,
+        proxy_port=port_distributor.get_port(),
+        http_port=port_distributor.get_port(),
+        mgmt_port=port_distributor.get_port(),
+        external_http_port=port_distributor.get_port(),
+        auth_backend=NeonProxy.Postgres(auth_endpoint),
+    ) as proxy:
+        proxy.start()
+
+        def q(traceparent=None):
+            connstr = f"postgresql://http:http@{proxy.domain}:{proxy.proxy_port}/postgres"
+            headers = {
+                "Content-Type": "application/sql",
+                "Neon-Connection-String": connstr,
+                "Neon-Pool-Opt-In": "true",
+            }
+            if traceparent is not None:
+                headers["traceparent"] = traceparent
+            response = requests.post(
+                f"https://{proxy.domain}:{proxy.external_http_port}/sql",
+                data=json.dumps(
+                    {"query": "select current_setting('neon.traceparent', true) as tp"}
+                ),
+                headers=headers,
+                verify=str(proxy.test_output_dir / "proxy.crt"),
+            )
+            assert response.status_code == 200, response.text
+            return response.json()["rows"][0]["tp"]
+
+        trace_id = "4bf92f3577b34da6a3ce929d0e0e4736"
+        tp = q(f"00-{trace_id}-00f067aa0ba902b7-01")
+        # the compute sees the proxy span as the parent, within the client's trace
+        version, got_trace_id, span_id, flags = tp.split("-")
+        assert (version, got_trace_id, flags) == ("00", trace_id, "01")
+        assert span_id != "00f067aa0ba902b7"
+
+        # the pooled connection does not keep the tag of the previous request
+        assert q() == ""
+
+        # unsampled traces are not propagated
+        assert q(f"00-{trace_id}-00f067aa0ba902b7-00") == ""
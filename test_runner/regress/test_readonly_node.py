@@ -1,9 +1,9 @@
 import pytest
 from fixtures.log_helper import log
-from fixtures.neon_fixtures import NeonEnv
+from fixtures.neon_fixtures import NeonEnv, NeonEnvBuilder
 from fixtures.pageserver.utils import wait_for_last_record_lsn
 from fixtures.types import Lsn
-from fixtures.utils import query_scalar
+from fixtures.utils import query_scalar, wait_until
 
 
 #
@@ -105,6 +105,66 @@ def test_readonly_node(neon_simple_env: NeonEnv):
         )
 
 
+#
+# Create a read-only node with --read-only, which leases its LSN on the
+# pageserver, and check that GC doesn't remove the data it reads.
+#
+def test_readonly_node_lsn_lease(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.pageserver_config_override = "tenant_config={pitr_interval = '0 sec'}"
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_readonly_node_lsn_lease")
+    endpoint_main = env.endpoints.create_start("test_readonly_node_lsn_lease")
+    client = env.pageserver.http_client()
+
+    with endpoint_main.cursor() as cur:
+        cur.execute("CREATE TABLE foo (t text)")
+        cur.execute("INSERT INTO foo SELECT 'row' || g FROM generate_series(1, 100) g")
+        lsn_a = Lsn(query_scalar(cur, "SELECT pg_current_wal_insert_lsn()"))
+        cur.execute("DELETE FROM foo")
+        cur.execute("VACUUM foo")
+        cur.execute("INSERT INTO foo SELECT 'row' || g FROM generate_series(1, 100000) g")
+        lsn_b = Lsn(query_scalar(cur, "SELECT pg_current_wal_insert_lsn()"))
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, lsn_b)
+
+    endpoint_leased = env.endpoints.create(
+        "test_readonly_node_lsn_lease",
+        endpoint_id="ep-readonly_node_leased",
+        lsn=lsn_a,
+        read_only=True,
+    )
+    gc_info = client.timeline_gc_info(tenant_id, timeline_id)
+    assert str(lsn_a) in gc_info["leased_lsns"]
+
+    # GC with no horizon doesn't move the cutoff past the leased LSN
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    client.timeline_gc(tenant_id, timeline_id, 0)
+    gc_info = client.timeline_gc_info(tenant_id, timeline_id)
+    assert Lsn(gc_info["latest_gc_cutoff_lsn"]) <= lsn_a
+
+    # the breakdown, updated by GC, doesn't report the layers the lease keeps as collectable
+    retained = gc_info["retained"]
+    log.info(f"retained: {retained}")
+    assert retained["leased_bytes"] > 0
+    assert retained["collectable_bytes"] == 0
+
+    endpoint_leased.start()
+    with endpoint_leased.cursor() as cur:
+        assert query_scalar(cur, "SELECT count(*) FROM foo") == 100
+
+    # the pageserver loses the lease on restart, and the running compute leases the LSN again
+    env.pageserver.restart()
+
+    def leased():
+        assert str(lsn_a) in client.timeline_gc_info(tenant_id, timeline_id)["leased_lsns"]
+
+    wait_until(20, 3, leased)
+
+    # an LSN below the GC cutoff can't be leased anymore
+    with pytest.raises(Exception, match="below the GC cutoff"):
+        client.timeline_lsn_lease(tenant_id, timeline_id, Lsn("0/42"))
+
+
 # Similar test, but with more data, and we force checkpoints
 def test_timetravel(neon_simple_env: NeonEnv):
     env = neon_simple_env
@@ -1,3 +1,9 @@
+import threading
+from contextlib import closing
+from typing import Any, List, Tuple
+
+import pytest
+import requests
 from fixtures.log_helper import log
 from fixtures.neon_fixtures import (
     NeonEnvBuilder,
@@ -5,6 +11,7 @@ from fixtures.neon_fixtures import (
 )
 from fixtures.remote_storage import s3_storage
 from fixtures.types import TenantShardId, TimelineId
+from fixtures.utils import wait_until
 from fixtures.workload import Workload
 
 
@@ -114,6 +121,104 @@ def test_sharding_split_unsharded(
     workload.validate()
 
 
+def test_sharding_neon_local(
+    neon_env_builder: NeonEnvBuilder,
+):
+    """
+    Test creating and splitting a sharded tenant with neon_local, and that its
+    running endpoint follows the split.
+    """
+    neon_env_builder.num_pageservers = 2
+    env = neon_env_builder.init_start()
+
+    tenant_id, _ = env.neon_cli.create_tenant(shard_count=2, shard_stripe_size=128)
+    assert env.attachment_service.inspect(TenantShardId(tenant_id, 1, 2)) is not None
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+
+    env.neon_cli.tenant_shard_split(tenant_id, shard_count=4)
+    assert len(tenant_get_shards(env, tenant_id)) == 4
+
+    # the endpoint was reconfigured with the four shards
+    connstr = endpoint.safe_psql("SHOW neon.pageserver_connstring")[0][0]
+    assert len(connstr.split(",")) == 4
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 10000
+
+
+def test_sharding_split_live_reads(
+    neon_env_builder: NeonEnvBuilder,
+):
+    """
+    Test that a compute which is serving reads switches to the new shard map when
+    its tenant is split, without failing the queries and with the same results
+    after the switch.
+    """
+    num_readers = 3
+
+    neon_env_builder.num_pageservers = 2
+    env = neon_env_builder.init_start()
+
+    tenant_id, _ = env.neon_cli.create_tenant(shard_count=2, shard_stripe_size=128)
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+
+    # Much larger than shared_buffers, so that the reads go to the pageservers
+    endpoint.safe_psql(
+        "CREATE TABLE t AS SELECT g, repeat('x', 100) AS s FROM generate_series(1, 100000) g"
+    )
+    expected = endpoint.safe_psql("SELECT count(*), sum(g) FROM t")[0]
+    assert expected == (100000, 5000050000)
+
+    stop = threading.Event()
+    # (number of shards in the map after the query, query result) of each reader
+    results: List[List[Tuple[int, Any]]] = [[] for _ in range(num_readers)]
+    errors: List[Exception] = []
+
+    def reader(i: int):
+        with closing(endpoint.connect()) as conn:
+            with conn.cursor() as cur:
+                while not stop.is_set():
+                    try:
+                        cur.execute("SELECT count(*), sum(g) FROM t")
+                        row = cur.fetchone()
+                        cur.execute("SHOW neon.pageserver_connstring")
+                        connstr = cur.fetchone()[0]
+                    except Exception as e:
+                        log.error(f"reader {i} failed: {e}")
+                        errors.append(e)
+                        return
+                    results[i].append((len(connstr.split(",")), row))
+
+    def reads_with_shards(shard_count: int):
+        assert not errors
+        for i in range(num_readers):
+            assert sum(1 for n, _ in results[i] if n == shard_count) >= 2
+
+    readers = [threading.Thread(target=reader, args=(i,)) for i in range(num_readers)]
+    for r in readers:
+        r.start()
+
+    try:
+        wait_until(30, 0.5, lambda: reads_with_shards(2))
+
+        # Reconfigures the endpoint while the readers are running
+        env.neon_cli.tenant_shard_split(tenant_id, shard_count=4)
+        assert len(tenant_get_shards(env, tenant_id)) == 4
+
+        wait_until(30, 0.5, lambda: reads_with_shards(4))
+    finally:
+        stop.set()
+        for r in readers:
+            r.join()
+
+    assert not errors
+    for i in range(num_readers):
+        log.info(f"reader {i} ran {len(results[i])} queries")
+        assert all(row == expected for _, row in results[i])
+
+    assert endpoint.safe_psql("SELECT count(*), sum(g) FROM t")[0] == expected
+
+
 def test_sharding_split_smoke(
     neon_env_builder: NeonEnvBuilder,
 ):
@@ -240,3 +345,42 @@ def test_sharding_split_smoke(
         env.neon_cli.tenant_migrate(migrate_shard, destination, timeout_secs=10)
 
     workload.validate()
+
+
+def test_sharding_split_rollback(neon_env_builder: NeonEnvBuilder):
+    """
+    A split which fails on the pageserver should be rolled back by the attachment service:
+    the children are dropped, the parent shard keeps serving, and the split may be retried.
+    """
+
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+    pageserver = env.get_tenant_pageserver(tenant_id)
+    pageserver.allowed_errors.extend(
+        [
+            ".*failpoint: shard-split-post-child.*",
+            # The rollback detaches the children, which can race with deletion queue operations
+            ".*Dropped remote consistent LSN updates.*",
+        ]
+    )
+
+    workload = Workload(env, tenant_id, timeline_id)
+    workload.init()
+    workload.write_rows(256)
+
+    pageserver.http_client().configure_failpoints(("shard-split-post-child", "return(1)"))
+    with pytest.raises(requests.exceptions.HTTPError):
+        env.attachment_service.tenant_shard_split(tenant_id, shard_count=2)
+
+    # Only the parent is left, both in the attachment service and on the pageserver
+    assert len(env.attachment_service.locate(tenant_id)) == 1
+    locations = pageserver.http_client().tenant_location_conf_list()
+    assert [tenant_shard_id for tenant_shard_id, _ in locations] == [str(tenant_id)]
+    workload.validate()
+
+    pageserver.http_client().configure_failpoints(("shard-split-post-child", "off"))
+    env.attachment_service.tenant_shard_split(tenant_id, shard_count=2)
+    assert len(env.attachment_service.locate(tenant_id)) == 2
+    workload.churn_rows(256)
+    workload.validate()
@@ -1,13 +1,18 @@
 import time
 from collections import defaultdict
 
+import pytest
+import requests
 from fixtures.log_helper import log
+from fixtures.metrics import parse_metrics
 from fixtures.neon_fixtures import NeonEnv, NeonEnvBuilder
-from fixtures.pageserver.http import PageserverHttpClient
+from fixtures.pageserver.http import PageserverApiException, PageserverHttpClient
 from fixtures.pageserver.utils import tenant_delete_wait_completed, timeline_delete_wait_completed
 from fixtures.pg_version import PgVersion
-from fixtures.types import TenantId, TimelineId
+from fixtures.remote_storage import RemoteStorageKind
+from fixtures.types import TenantId, TenantShardId, TimelineId
 from fixtures.utils import wait_until
+from fixtures.workload import Workload
 from pytest_httpserver import HTTPServer
 from werkzeug.wrappers.request import Request
 from werkzeug.wrappers.response import Response
@@ -217,6 +222,77 @@ def test_sharding_service_restart(neon_env_builder: NeonEnvBuilder):
     assert tenant_b in observed
 
 
+def test_sharding_service_restart_scheduling_policy(neon_env_builder: NeonEnvBuilder):
+    """
+    Changes to the scheduling policy of a node must survive a restart of the attachment service.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    env = neon_env_builder.init_start()
+    paused = env.pageservers[0]
+    other = env.pageservers[1]
+
+    env.attachment_service.node_configure(paused.id, {"scheduling": "Pause"})
+
+    env.attachment_service.stop()
+    env.attachment_service.start()
+
+    nodes = {node["node_id"]: node for node in env.attachment_service.node_list()}
+    assert nodes[paused.id]["scheduling_policy"] == "pause"
+    assert nodes[other.id]["scheduling_policy"] != "pause"
+
+    # The paused node still doesn't get new shards
+    tenant_id = TenantId.generate()
+    env.neon_cli.create_tenant(tenant_id, shard_count=2)
+    assert get_node_shard_counts(env, [tenant_id]) == {other.id: 2}
+
+
+def test_sharding_service_restart_placement_policy(neon_env_builder: NeonEnvBuilder):
+    """
+    Changes to the placement policy of a tenant must survive a restart of the attachment service.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+
+    def location_conf(mode: str):
+        virtual_ps_http = PageserverHttpClient(env.attachment_service_port, lambda: True)
+        virtual_ps_http.tenant_location_conf(
+            tenant_id,
+            {"mode": mode, "secondary_conf": None, "tenant_conf": {}, "generation": None},
+        )
+
+    # Secondary-only mode is refused without changing anything
+    with pytest.raises(PageserverApiException, match="Secondary mode is not supported") as e:
+        location_conf("Secondary")
+    assert e.value.status_code == 400
+
+    def attached_on() -> list[int]:
+        return [
+            ps.id
+            for ps in env.pageservers
+            if tenant_id in set(TenantId(t["id"]) for t in ps.http_client().tenant_list())
+        ]
+
+    assert len(attached_on()) == 1
+
+    location_conf("Detached")
+    assert attached_on() == []
+
+    env.attachment_service.stop()
+    env.attachment_service.start()
+
+    def settled():
+        assert env.attachment_service.ready()
+        assert env.attachment_service.service_metrics()["reconciles_in_progress"] == 0
+
+    wait_until(10, 1, settled)
+
+    # Had the policy been lost, the startup reconciliation would have attached the tenant again
+    assert attached_on() == []
+
+
 def test_sharding_service_onboarding(
     neon_env_builder: NeonEnvBuilder,
 ):
@@ -411,3 +487,495 @@ def test_sharding_service_debug_apis(neon_env_builder: NeonEnvBuilder):
     env.attachment_service.request(
         "POST", f"{env.attachment_service_api}/debug/v1/tenant/{tenant_id}/drop"
     )
+
+
+def test_sharding_service_optimizer_plan(neon_env_builder: NeonEnvBuilder):
+    """
+    Check that the dry run of the optimizer plans migrations from a pageserver holding
+    all the shards to an empty one, without migrating anything.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    env = neon_env_builder.init_start()
+    hot, cold = env.pageservers[0].id, env.pageservers[1].id
+
+    tenant_ids = [TenantId.generate() for _ in range(0, 4)]
+    for tenant_id in tenant_ids:
+        env.neon_cli.create_tenant(tenant_id, shard_count=2)
+    for tenant_id in tenant_ids:
+        for shard in env.attachment_service.locate(tenant_id):
+            if shard["node_id"] != hot:
+                env.attachment_service.tenant_shard_migrate(
+                    TenantShardId.parse(shard["shard_id"]), hot
+                )
+    assert get_node_shard_counts(env, tenant_ids) == {hot: 8}
+
+    plan = env.attachment_service.optimizer_plan()
+    loads = {node["node_id"]: node for node in plan["nodes"]}
+    assert loads[hot]["shard_count"] == 8
+    assert loads[cold]["shard_count"] == 0
+    assert loads[hot]["load"] > loads[cold]["load"]
+
+    # The default limit is one migration per interval
+    assert len(plan["migrations"]) == 1
+    migration = plan["migrations"][0]
+    assert migration["from_node_id"] == hot
+    assert migration["to_node_id"] == cold
+
+    # A dry run doesn't migrate anything
+    assert get_node_shard_counts(env, tenant_ids) == {hot: 8}
+
+
+def test_sharding_service_drain_and_fill(neon_env_builder: NeonEnvBuilder):
+    """
+    Drain a pageserver, restart it, and fill it back: its shards should be migrated off it
+    before the restart, and the shards should be balanced again afterwards.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    env = neon_env_builder.init_start()
+    for pageserver in env.pageservers:
+        # Migrations detach tenants, which can race with deletion queue operations
+        pageserver.allowed_errors.extend([".*Dropped remote consistent LSN updates.*"])
+    drained = env.pageservers[0]
+    other = env.pageservers[1]
+
+    tenant_ids = [TenantId.generate() for _ in range(0, 4)]
+    for tenant_id in tenant_ids:
+        env.neon_cli.create_tenant(tenant_id, shard_count=2)
+    assert get_node_shard_counts(env, tenant_ids) == {drained.id: 4, other.id: 4}
+
+    def operation_complete(node_id: int, kind: str):
+        status = env.attachment_service.node_operation(node_id)
+        assert status["kind"] == kind
+        assert status["state"] == "Complete"
+        return status
+
+    status = env.attachment_service.node_drain(drained.id)
+    assert len(status["pending"]) == 4
+    status = wait_until(30, 1, lambda: operation_complete(drained.id, "Drain"))
+    assert len(status["migrated"]) == 4
+    assert get_node_shard_counts(env, tenant_ids) == {other.id: 8}
+
+    # A draining node doesn't get new shards
+    new_tenant_id = TenantId.generate()
+    env.neon_cli.create_tenant(new_tenant_id, shard_count=2)
+    assert get_node_shard_counts(env, [new_tenant_id]) == {other.id: 2}
+    tenant_ids.append(new_tenant_id)
+
+    drained.stop()
+    drained.start()
+
+    status = env.attachment_service.node_fill(drained.id)
+    assert len(status["pending"]) == 5
+    wait_until(30, 1, lambda: operation_complete(drained.id, "Fill"))
+    assert get_node_shard_counts(env, tenant_ids) == {drained.id: 5, other.id: 5}
+
+    # The shards are still readable after the restart
+    for tenant_id in tenant_ids:
+        env.neon_cli.create_timeline("after_restart", tenant_id=tenant_id)
+
+
+def test_sharding_service_decommission(neon_env_builder: NeonEnvBuilder):
+    """
+    Decommission a live pageserver, and then a dead one: their shards should be moved to the
+    remaining pageservers, and the nodes should be removed.
+    """
+
+    neon_env_builder.num_pageservers = 3
+    env = neon_env_builder.init_start()
+    for pageserver in env.pageservers:
+        # Migrations detach tenants, which can race with deletion queue operations
+        pageserver.allowed_errors.extend([".*Dropped remote consistent LSN updates.*"])
+    live, dead, remaining = env.pageservers
+
+    tenant_ids = [TenantId.generate() for _ in range(0, 3)]
+    for tenant_id in tenant_ids:
+        env.neon_cli.create_tenant(tenant_id, shard_count=2)
+
+    def decommission_complete(node_id: int):
+        status = env.attachment_service.node_operation(node_id)
+        assert status["kind"] == "Decommission"
+        assert status["state"] == "Complete"
+        return status
+
+    env.attachment_service.node_decommission(live.id)
+    wait_until(30, 1, lambda: decommission_complete(live.id))
+    assert live.id not in [n["node_id"] for n in env.attachment_service.node_list()]
+    assert live.id not in get_node_shard_counts(env, tenant_ids)
+    assert live.http_client().tenant_location_conf_list() == []
+
+    # A dead node may only be decommissioned by force
+    dead.stop()
+    with pytest.raises(requests.exceptions.HTTPError, match="Precondition Failed"):
+        env.attachment_service.node_decommission(dead.id)
+    status = env.attachment_service.node_decommission(dead.id, mode="forced")
+    assert status["state"] == "Complete"
+    assert dead.id not in [n["node_id"] for n in env.attachment_service.node_list()]
+    assert get_node_shard_counts(env, tenant_ids) == {remaining.id: 6}
+
+    # The shards are still writable on the remaining node
+    for tenant_id in tenant_ids:
+        env.neon_cli.create_timeline("after_decommission", tenant_id=tenant_id)
+
+
+def test_sharding_service_metrics(neon_env_builder: NeonEnvBuilder):
+    """
+    The scheduling and reconciliation of shards should show in the metrics of the attachment
+    service, both on /metrics and in the debug JSON.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    env = neon_env_builder.init_start()
+    for pageserver in env.pageservers:
+        # Migrations detach tenants, which can race with deletion queue operations
+        pageserver.allowed_errors.extend([".*Dropped remote consistent LSN updates.*"])
+
+    tenant_id = TenantId.generate()
+    env.neon_cli.create_tenant(tenant_id, shard_count=2)
+    tenant_shard_id = TenantShardId(tenant_id, 0, 2)
+    dest = next(
+        ps.id
+        for ps in env.pageservers
+        if ps.id != env.attachment_service.locate(tenant_id)[0]["node_id"]
+    )
+    env.attachment_service.tenant_shard_migrate(tenant_shard_id, dest)
+
+    def metrics_settled():
+        metrics = env.attachment_service.service_metrics()
+        assert metrics["reconciles_in_progress"] == 0
+        assert [n["node_id"] for n in metrics["nodes"]] == [ps.id for ps in env.pageservers]
+        for node in metrics["nodes"]:
+            assert node["availability"] == "Active"
+            assert node["missed_heartbeats"] == 0
+        parsed = parse_metrics(env.attachment_service.get_metrics_str())
+        for ps in env.pageservers:
+            sample = parsed.query_one("storage_controller_node_available", {"node_id": str(ps.id)})
+            assert sample.value == 1
+        return metrics
+
+    metrics = wait_until(10, 1, metrics_settled)
+    # The initial tenant, and the two shards of ours
+    assert metrics["schedule_decisions"]["attach"] >= 3
+    assert metrics["schedule_decisions"]["api"] == 1
+    assert metrics["reconciles_succeeded"] >= 3
+    assert metrics["reconciles_failed"] == 0
+    assert all(count == 0 for count in metrics["pending_migrations"].values())
+
+    parsed = parse_metrics(env.attachment_service.get_metrics_str())
+    decisions = parsed.query_one("storage_controller_schedule_decisions_total", {"reason": "api"})
+    assert decisions.value == 1
+    reconciles = parsed.query_one("storage_controller_reconcile_seconds_count", {"result": "ok"})
+    assert reconciles.value == metrics["reconciles_succeeded"]
+
+
+def test_sharding_service_warm_migration(neon_env_builder: NeonEnvBuilder):
+    """
+    A warm migration should download the shard's layers to a secondary location on the
+    destination before attaching the shard there.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.MOCK_S3)
+    env = neon_env_builder.init_start()
+    for pageserver in env.pageservers:
+        # Migrations detach tenants, which can race with deletion queue operations
+        pageserver.allowed_errors.extend([".*Dropped remote consistent LSN updates.*"])
+
+    tenant_id = env.initial_tenant
+    tenant_shard_id = TenantShardId(tenant_id, 0, 0)
+    origin = env.get_tenant_pageserver(tenant_id)
+    dest = next(ps for ps in env.pageservers if ps.id != origin.id)
+
+    workload = Workload(env, tenant_id, env.initial_timeline)
+    workload.init(origin.id)
+    workload.write_rows(256, origin.id)
+
+    status = env.attachment_service.tenant_shard_migrate_warm(tenant_shard_id, dest.id, 1.0)
+    assert status["state"] == "WarmingUp"
+
+    def migration_complete():
+        status = env.attachment_service.tenant_shard_migration(tenant_shard_id)
+        assert status["state"] == "Complete"
+        return status
+
+    status = wait_until(60, 1, migration_complete)
+    assert status["progress"]["bytes_total"] > 0
+    assert status["progress"]["bytes_downloaded"] == status["progress"]["bytes_total"]
+    assert env.get_tenant_pageserver(tenant_id).id == dest.id
+
+    workload.validate(dest.id)
+
+
+def tenant_locations(env: NeonEnv, tenant_id: TenantId) -> dict[int, str]:
+    """The mode of the locations of an unsharded tenant, by pageserver."""
+    locations = {}
+    for pageserver in env.pageservers:
+        for tenant_shard_id, conf in pageserver.http_client().tenant_location_conf_list():
+            if conf is not None and tenant_shard_id.startswith(str(tenant_id)):
+                locations[pageserver.id] = conf["mode"]
+    return locations
+
+
+def test_sharding_service_warm_migration_existing_secondary(neon_env_builder: NeonEnvBuilder):
+    """
+    A warm migration to a node other than the one holding the shard's secondary location
+    should leave the shard with as many secondary locations as its policy asks for.
+    """
+
+    neon_env_builder.num_pageservers = 3
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.MOCK_S3)
+    env = neon_env_builder.init_start()
+    for pageserver in env.pageservers:
+        # Migrations detach tenants, which can race with deletion queue operations
+        pageserver.allowed_errors.extend([".*Dropped remote consistent LSN updates.*"])
+
+    tenant_id = env.initial_tenant
+    tenant_shard_id = TenantShardId(tenant_id, 0, 0)
+
+    # Configuring the tenant as attached through the attachment service gives it a
+    # secondary location
+    virtual_ps_http = PageserverHttpClient(env.attachment_service_port, lambda: True)
+    virtual_ps_http.tenant_location_conf(
+        tenant_id,
+        {
+            "mode": "AttachedSingle",
+            "secondary_conf": None,
+            "tenant_conf": {},
+            "generation": None,
+        },
+    )
+
+    def secondary_created():
+        locations = tenant_locations(env, tenant_id)
+        assert sorted(locations.values())[-1] == "Secondary"
+        assert len(locations) == 2
+        return locations
+
+    locations = wait_until(10, 1, secondary_created)
+    origin = next(id for id, mode in locations.items() if mode.startswith("Attached"))
+    secondary = next(id for id, mode in locations.items() if mode == "Secondary")
+    dest = next(ps.id for ps in env.pageservers if ps.id not in locations)
+
+    workload = Workload(env, tenant_id, env.initial_timeline)
+    workload.init(origin)
+    workload.write_rows(256, origin)
+
+    env.attachment_service.tenant_shard_migrate_warm(tenant_shard_id, dest, 1.0)
+
+    def migration_complete():
+        status = env.attachment_service.tenant_shard_migration(tenant_shard_id)
+        assert status["state"] == "Complete"
+
+    wait_until(60, 1, migration_complete)
+
+    # The origin is not kept next to the secondary location the tenant already had
+    locations = tenant_locations(env, tenant_id)
+    log.info(f"Locations after the migration: {locations}")
+    assert len(locations) == 2
+    assert locations[dest].startswith("Attached")
+    assert locations[secondary] == "Secondary"
+    assert origin not in locations
+
+    workload.validate(dest)
+
+
+def test_sharding_service_warm_migration_failure(neon_env_builder: NeonEnvBuilder):
+    """
+    A warm migration which fails to warm up the destination should remove the secondary
+    location it created there, and leave the shard attached where it was.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.MOCK_S3)
+    env = neon_env_builder.init_start()
+    for pageserver in env.pageservers:
+        # Migrations detach tenants, which can race with deletion queue operations
+        pageserver.allowed_errors.extend(
+            [".*Dropped remote consistent LSN updates.*", ".*failpoint secondary-status.*"]
+        )
+
+    tenant_id = env.initial_tenant
+    tenant_shard_id = TenantShardId(tenant_id, 0, 0)
+    origin = env.get_tenant_pageserver(tenant_id)
+    dest = next(ps for ps in env.pageservers if ps.id != origin.id)
+
+    workload = Workload(env, tenant_id, env.initial_timeline)
+    workload.init(origin.id)
+    workload.write_rows(256, origin.id)
+
+    dest.http_client().configure_failpoints(("secondary-status", "return"))
+    env.attachment_service.tenant_shard_migrate_warm(tenant_shard_id, dest.id, 1.0)
+
+    def migration_failed():
+        status = env.attachment_service.tenant_shard_migration(tenant_shard_id)
+        assert status["state"] == "Failed"
+        return status
+
+    status = wait_until(60, 1, migration_failed)
+    assert "failpoint secondary-status" in status["error"]
+
+    # The secondary location created for the migration is gone again
+    locations = tenant_locations(env, tenant_id)
+    assert list(locations.keys()) == [origin.id]
+    assert locations[origin.id].startswith("Attached")
+    assert env.get_tenant_pageserver(tenant_id).id == origin.id
+
+    # Once the destination recovers, the shard may be migrated there
+    dest.http_client().configure_failpoints(("secondary-status", "off"))
+    env.attachment_service.tenant_shard_migrate_warm(tenant_shard_id, dest.id, 1.0)
+
+    def migration_complete():
+        status = env.attachment_service.tenant_shard_migration(tenant_shard_id)
+        assert status["state"] == "Complete"
+
+    wait_until(60, 1, migration_complete)
+    assert env.get_tenant_pageserver(tenant_id).id == dest.id
+    workload.validate(dest.id)
+
+
+def test_sharding_service_az_anti_affinity(neon_env_builder: NeonEnvBuilder):
+    """
+    The shards of a tenant, and the attached and secondary locations of each shard, should
+    be placed on different pageservers and availability zones.
+    """
+
+    neon_env_builder.num_pageservers = 4
+    neon_env_builder.pageserver_availability_zones = ["az-a", "az-b"]
+    env = neon_env_builder.init_start()
+
+    nodes = env.attachment_service.node_list()
+    azs = {node["node_id"]: node["availability_zone_id"] for node in nodes}
+    assert sorted(azs.values()) == ["az-a", "az-a", "az-b", "az-b"]
+
+    tenant_id = TenantId.generate()
+    env.attachment_service.tenant_create(tenant_id, shard_count=2)
+    shards = env.attachment_service.locate(tenant_id)
+    assert len(set(azs[shard["node_id"]] for shard in shards)) == 2
+
+    # Configuring an existing tenant as attached through the attachment service gives
+    # its shards a secondary location
+    virtual_ps_http = PageserverHttpClient(env.attachment_service_port, lambda: True)
+    virtual_ps_http.tenant_location_conf(
+        tenant_id,
+        {
+            "mode": "AttachedSingle",
+            "secondary_conf": None,
+            "tenant_conf": {},
+            "generation": None,
+        },
+    )
+
+    def get_locations():
+        locations = defaultdict(list)
+        for pageserver in env.pageservers:
+            for tenant_shard_id, conf in pageserver.http_client().tenant_location_conf_list():
+                if conf is not None and tenant_shard_id.startswith(str(tenant_id)):
+                    locations[tenant_shard_id].append((pageserver.id, conf["mode"]))
+        assert len(locations) == 2
+        assert all(len(ls) == 2 for ls in locations.values())
+        return locations
+
+    locations = wait_until(10, 1, get_locations)
+    log.info(f"Locations: {locations}")
+
+    # Every location is on its own pageserver
+    assert len(set(node_id for ls in locations.values() for node_id, _ in ls)) == 4
+    for shard_locations in locations.values():
+        modes = sorted(mode for _, mode in shard_locations)
+        assert modes[0].startswith("Attached")
+        assert modes[1] == "Secondary"
+        assert azs[shard_locations[0][0]] != azs[shard_locations[1][0]]
+
+
+def test_sharding_service_heartbeat_failover(neon_env_builder: NeonEnvBuilder):
+    """
+    When a pageserver stops answering heartbeats, its shards should be attached to their
+    secondary locations with a new generation, and it should be marked active again once
+    it answers.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    neon_env_builder.pageserver_heartbeat_interval = "1s"
+    env = neon_env_builder.init_start()
+    for pageserver in env.pageservers:
+        pageserver.allowed_errors.extend([".*Dropped remote consistent LSN updates.*"])
+
+    tenant_id = TenantId.generate()
+    env.attachment_service.tenant_create(tenant_id)
+
+    # Configuring an existing tenant as attached through the attachment service gives
+    # it a secondary location
+    virtual_ps_http = PageserverHttpClient(env.attachment_service_port, lambda: True)
+    virtual_ps_http.tenant_location_conf(
+        tenant_id,
+        {
+            "mode": "AttachedSingle",
+            "secondary_conf": None,
+            "tenant_conf": {},
+            "generation": None,
+        },
+    )
+    attachment = env.attachment_service.inspect(tenant_id)
+    assert attachment is not None
+    generation, origin_id = attachment
+    origin = env.get_pageserver(origin_id)
+    other = next(ps for ps in env.pageservers if ps.id != origin_id)
+
+    origin.stop(immediate=True)
+
+    def failed_over():
+        events = env.attachment_service.failover_events()
+        kinds = [(event["node_id"], event["kind"]) for event in events]
+        assert (origin_id, "NodeOffline") in kinds
+        failover = next(event for event in events if event["kind"] == "ShardFailover")
+        assert failover["to_node_id"] == other.id
+        assert failover["to_secondary"]
+
+    wait_until(30, 1, failed_over)
+
+    def attached_to_other():
+        attachment = env.attachment_service.inspect(tenant_id)
+        assert attachment is not None
+        assert attachment[1] == other.id
+        assert attachment[0] > generation
+
+    wait_until(10, 1, attached_to_other)
+
+    origin.start()
+
+    def origin_active():
+        events = env.attachment_service.failover_events()
+        assert (origin_id, "NodeActive") in [(event["node_id"], event["kind"]) for event in events]
+
+    wait_until(10, 1, origin_active)
+
+
+def test_sharding_service_heartbeat_manual_offline(neon_env_builder: NeonEnvBuilder):
+    """
+    A pageserver which an operator marked offline should stay offline although it keeps
+    answering heartbeats.
+    """
+
+    neon_env_builder.num_pageservers = 2
+    neon_env_builder.pageserver_heartbeat_interval = "1s"
+    env = neon_env_builder.init_start()
+
+    node_id = env.pageservers[0].id
+    env.attachment_service.node_configure(node_id, {"availability": "Offline"})
+
+    # Give the heartbeat loop several ticks to (wrongly) bring the node back
+    time.sleep(5)
+
+    def node_availability() -> str:
+        nodes = env.attachment_service.service_metrics()["nodes"]
+        return next(n["availability"] for n in nodes if n["node_id"] == node_id)
+
+    assert node_availability() == "Offline"
+    events = env.attachment_service.failover_events()
+    assert (node_id, "NodeActive") not in [(event["node_id"], event["kind"]) for event in events]
+
+    # Once the operator marks it active again, it stays active
+    env.attachment_service.node_configure(node_id, {"availability": "Active"})
+    time.sleep(2)
+    assert node_availability() == "Active"
@@ -1,3 +1,4 @@
+import concurrent.futures
 import filecmp
 import os
 import random
@@ -31,10 +32,12 @@ from fixtures.neon_fixtures import (
     SafekeeperHttpClient,
     SafekeeperPort,
     last_flush_lsn_upload,
+    wait_for_last_flush_lsn,
 )
 from fixtures.pageserver.utils import (
     assert_prefix_empty,
     assert_prefix_not_empty,
+    list_prefix,
     timeline_delete_wait_completed,
     wait_for_last_record_lsn,
     wait_for_upload,
@@ -47,7 +50,7 @@ from fixtures.remote_storage import (
     s3_storage,
 )
 from fixtures.types import Lsn, TenantId, TimelineId
-from fixtures.utils import get_dir_size, query_scalar, start_in_background
+from fixtures.utils import get_dir_size, query_scalar, start_in_background, wait_until
 
 
 def wait_lsn_force_checkpoint(
@@ -94,6 +97,209 @@ class TimelineMetrics:
     commit_lsns: List[Lsn] = field(default_factory=list)
 
 
+# Check lag summaries served by /v1/metrics/timelines.
+def test_timelines_metrics(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_timelines_metrics")
+    endpoint = env.endpoints.create_start("test_timelines_metrics")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,1000), 'payload'")
+
+    http_cli = env.safekeepers[0].http_client()
+    summaries = http_cli.timelines_metrics()
+    log.info(f"timelines metrics: {summaries}")
+    summary = next(
+        s
+        for s in summaries
+        if s["tenant_id"] == str(tenant_id) and s["timeline_id"] == str(timeline_id)
+    )
+    assert summary["num_computes"] == 1
+    flush_lsn = Lsn(summary["flush_lsn"])
+    commit_lsn = Lsn(summary["commit_lsn"])
+    assert commit_lsn <= flush_lsn
+    assert summary["commit_lag_bytes"] == flush_lsn - commit_lsn
+    assert summary["backup_lag_bytes"] == max(0, commit_lsn - Lsn(summary["backup_lsn"]))
+
+    # lag metrics are exported to prometheus as well
+    metrics = http_cli.get_metrics_str()
+    assert (
+        f'safekeeper_backup_lag_bytes{{tenant_id="{tenant_id}",timeline_id="{timeline_id}"}}'
+        in metrics
+    )
+
+
+# Timeline status should report peers learnt from the broker and WAL disk usage.
+def test_timeline_status_peers(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_timeline_status_peers")
+    endpoint = env.endpoints.create_start("test_timeline_status_peers")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,1000), 'payload'")
+
+    def all_peers_alive():
+        for sk in env.safekeepers:
+            status = sk.http_client().timeline_status(tenant_id, timeline_id)
+            log.info(f"sk {sk.id} peers: {status.peers}")
+            assert {p["sk_id"] for p in status.peers} == {s.id for s in env.safekeepers}
+            assert all(p["alive"] for p in status.peers)
+            assert status.wal_disk_usage_bytes > 0
+
+    wait_until(30, 0.5, all_peers_alive)
+
+
+# Without the broker safekeepers and pageserver can exchange timeline state by
+# polling static list of safekeepers over HTTP.
+def test_static_peers(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    env = neon_env_builder.init_start()
+    env.broker.stop()
+
+    sk_urls = [f"http://127.0.0.1:{sk.port.http}" for sk in env.safekeepers]
+    for sk in env.safekeepers:
+        sk.stop()
+        sk.start(extra_opts=[f"--static-peers={','.join(sk_urls)}"])
+    static_safekeepers = ", ".join(f'"{url}"' for url in sk_urls)
+    env.pageserver.stop()
+    env.pageserver.start(
+        overrides=(f"--pageserver-config-override=static_safekeepers=[{static_safekeepers}]",)
+    )
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_static_peers")
+    endpoint = env.endpoints.create_start("test_static_peers")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,1000), 'payload'")
+
+    def all_peers_alive():
+        for sk in env.safekeepers:
+            status = sk.http_client().timeline_status(tenant_id, timeline_id)
+            log.info(f"sk {sk.id} peers: {status.peers}")
+            assert {p["sk_id"] for p in status.peers} == {s.id for s in env.safekeepers}
+            assert all(p["alive"] for p in status.peers)
+
+    wait_until(30, 0.5, all_peers_alive)
+
+    # pageserver discovers safekeepers to stream WAL from without the broker
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+
+# Safekeepers validating incoming WAL should accept WAL generated by compute,
+# including records crossing page and segment boundaries.
+def test_validate_wal(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    env = neon_env_builder.init_start()
+    for sk in env.safekeepers:
+        sk.stop()
+        sk.start(extra_opts=["--validate-wal"])
+
+    env.neon_cli.create_branch("test_validate_wal")
+    endpoint = env.endpoints.create_start("test_validate_wal")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    # roughly fills two segments, with large records
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,500000), 'payload'")
+    endpoint.safe_psql(
+        "INSERT INTO t SELECT 1000000 + g, repeat('x', 20000) FROM generate_series(1,10) g"
+    )
+
+    # compute restart starts new decoder on safekeepers
+    endpoint.stop_and_destroy().create_start("test_validate_wal")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(2000000,2100000), 'payload'")
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 600011
+
+
+# Durability settings from neon_local config are passed to safekeepers, and
+# disabled fsync is loudly reported.
+def test_durability_settings(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.safekeepers_wal_flush_batch_delay = "5ms"
+    env = neon_env_builder.init_start()
+    env.neon_cli.create_branch("test_durability_settings")
+    endpoint = env.endpoints.create_start("test_durability_settings")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,10000), 'payload'")
+
+    log_path = os.path.join(env.safekeepers[0].data_dir(), "safekeeper.log")
+    with open(log_path) as f:
+        sk_log = f.read()
+    assert "fsync is disabled by --no-sync" in sk_log
+    assert "WAL fsyncs are batched, delaying acknowledgement up to 5ms" in sk_log
+
+
+# Concurrent commits are acknowledged by batched WAL flushes, and an error in
+# the middle of a batch doesn't lose the WAL written before it.
+def test_wal_flush_batching(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    neon_env_builder.safekeepers_wal_flush_batch_delay = "20ms"
+    env = neon_env_builder.init_start()
+    sk1, sk2, sk3 = env.safekeepers
+    sk1_http_cli = sk1.http_client()
+    sk1_http_cli.is_testing_enabled_or_skip()
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_wal_flush_batching")
+    endpoint = env.endpoints.create_start("test_wal_flush_batching")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+
+    n_clients = 8
+    n_inserts = 200
+
+    def insert(first_key: int):
+        with closing(endpoint.connect()) as conn:
+            with conn.cursor() as cur:
+                for key in range(first_key, first_key + n_inserts):
+                    cur.execute(f"INSERT INTO t VALUES ({key}, 'payload')")
+
+    def run_clients(first_key: int):
+        with concurrent.futures.ThreadPoolExecutor(max_workers=n_clients) as executor:
+            futures = [
+                executor.submit(insert, first_key + i * n_inserts) for i in range(n_clients)
+            ]
+            for future in futures:
+                future.result()
+
+    def flushes_and_appends(sk: Safekeeper):
+        metrics = parse_metrics(sk.http_client().get_metrics_str())
+        flushes = metrics.query_one("safekeeper_wal_flush_batch_size_count").value
+        appends = metrics.query_one("safekeeper_wal_flush_batch_size_sum").value
+        log.info(f"safekeeper {sk.id}: {appends} AppendRequests in {flushes} flushes")
+        return flushes, appends
+
+    run_clients(0)
+    for sk in env.safekeepers:
+        flushes, appends = flushes_and_appends(sk)
+        assert flushes > 0
+        # some flushes acknowledged several AppendRequests
+        assert appends > flushes
+
+    # sk1 fails on the second AppendRequest of the next batch, the compute
+    # reconnects and keeps committing with the other two
+    sk1_http_cli.configure_failpoints(("sk-append-batch-error", "1*return"))
+    run_clients(n_clients * n_inserts)
+    sk1_log_path = os.path.join(sk1.data_dir(), "safekeeper.log")
+    with open(sk1_log_path) as f:
+        assert "failpoint: sk-append-batch-error" in f.read()
+
+    endpoint.safe_psql("INSERT INTO t VALUES (-1, 'payload')")
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 2 * n_clients * n_inserts + 1
+    endpoint.stop()
+
+    # sk1 caught up, and the WAL it wrote before the error is the same as on the others
+    http_clis = [sk.http_client() for sk in env.safekeepers]
+    wait(
+        partial(is_flush_lsn_aligned, http_clis, tenant_id, timeline_id),
+        "flush_lsn to get aligned",
+    )
+    status = sk2.http_client().timeline_status(tenant_id, timeline_id)
+    from_lsn, until_lsn = status.timeline_start_lsn, status.flush_lsn
+    digests = [
+        http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn)
+        for http_cli in http_clis
+    ]
+    assert all(digest == digests[0] for digest in digests)
+
+
 # Run page server and multiple acceptors, and multiple compute nodes running
 # against different timelines.
 def test_many_timelines(neon_env_builder: NeonEnvBuilder):
@@ -518,6 +724,317 @@ def test_wal_backup(neon_env_builder: NeonEnvBuilder):
     assert_prefix_empty(neon_env_builder.safekeepers_remote_storage, prefix)
 
 
+# With WAL eviction, backed up segments are removed from disk even if a peer still needs
+# them, and are read back from remote storage.
+def test_wal_eviction(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    neon_env_builder.enable_safekeeper_remote_storage(default_remote_storage())
+    # to advance remote_consistent_lsn
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    sk1, sk2, sk3 = env.safekeepers
+    for sk in [sk1, sk2]:
+        sk.stop()
+        sk.start(extra_opts=["--wal-eviction-enabled"])
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_wal_eviction")
+    endpoint = env.endpoints.create_start("test_wal_eviction")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+
+    # sk3 lags, so peer_horizon_lsn doesn't advance
+    sk3.stop()
+    # roughly fills three segments
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,750000), 'payload'")
+    wait_lsn_force_checkpoint(tenant_id, timeline_id, endpoint, env.pageserver)
+
+    first_segment = os.path.join(
+        sk1.data_dir(), str(tenant_id), str(timeline_id), "000000010000000000000001"
+    )
+
+    def first_segment_evicted():
+        assert not os.path.exists(first_segment)
+
+    wait_until(60, 1, first_segment_evicted)
+
+    # sk3 catches up and has all WAL on disk, evicted WAL read from remote storage is the same
+    sk3.start()
+    endpoint.safe_psql("INSERT INTO t VALUES (0, 'payload')")
+    endpoint.stop()
+    sk1_http_cli, sk3_http_cli = sk1.http_client(), sk3.http_client()
+    wait(
+        partial(is_flush_lsn_aligned, [sk1_http_cli, sk3_http_cli], tenant_id, timeline_id),
+        "flush_lsn to get aligned",
+    )
+    status = sk3_http_cli.timeline_status(tenant_id, timeline_id)
+    from_lsn, until_lsn = status.timeline_start_lsn, status.flush_lsn
+    assert sk1_http_cli.timeline_digest(
+        tenant_id, timeline_id, from_lsn, until_lsn
+    ) == sk3_http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn)
+
+    metrics = parse_metrics(sk1_http_cli.get_metrics_str())
+    assert metrics.query_one("safekeeper_remote_wal_segment_reads_total").value > 0
+
+
+# WAL evicted from safekeeper disk should be served from remote storage through
+# the local cache of remote WAL.
+def test_remote_wal_cache(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 1
+    neon_env_builder.enable_safekeeper_remote_storage(default_remote_storage())
+    # to advance remote_consistent_lsn
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    sk = env.safekeepers[0]
+    sk.stop()
+    sk.start(extra_opts=["--wal-eviction-enabled", f"--remote-wal-cache-size={1 << 30}"])
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_remote_wal_cache")
+    endpoint = env.endpoints.create_start("test_remote_wal_cache")
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t(key int primary key, value text)",
+            # roughly fills three segments
+            "INSERT INTO t SELECT generate_series(1,750000), 'payload'",
+        ]
+    )
+    wait_lsn_force_checkpoint(tenant_id, timeline_id, endpoint, env.pageserver)
+
+    http_cli = sk.http_client()
+    status = http_cli.timeline_status(tenant_id, timeline_id)
+    from_lsn, until_lsn = status.timeline_start_lsn, status.commit_lsn
+    digest = http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn)
+
+    first_segment = os.path.join(
+        sk.data_dir(), str(tenant_id), str(timeline_id), "000000010000000000000001"
+    )
+
+    def first_segment_evicted():
+        assert not os.path.exists(first_segment)
+
+    wait_until(60, 1, first_segment_evicted)
+
+    # first read downloads segments, the second one is served from the cache
+    for _ in range(2):
+        assert http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn) == digest
+
+    metrics = parse_metrics(http_cli.get_metrics_str())
+    assert metrics.query_one("safekeeper_remote_wal_segment_reads_total").value > 0
+    assert metrics.query_one("safekeeper_remote_wal_cache_hits_total").value > 0
+
+
+def wal_segment_name(lsn: Lsn, wal_seg_size: int = 16 * 1024 * 1024) -> str:
+    segno = lsn.as_int() // wal_seg_size
+    segments_per_id = 0x100000000 // wal_seg_size
+    return f"00000001{segno // segments_per_id:08X}{segno % segments_per_id:08X}"
+
+
+# Committed part of the current segment is uploaded as partial segment, WAL readers fall
+# back to it when the segment is not on disk, and it is removed once the full segment is
+# backed up.
+def test_partial_backup(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 1
+    neon_env_builder.enable_safekeeper_remote_storage(s3_storage())
+    env = neon_env_builder.init_start()
+    sk = env.safekeepers[0]
+    sk.stop()
+    sk.start(extra_opts=["--partial-backup-enabled", "--partial-backup-timeout=1s"])
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_partial_backup")
+    endpoint = env.endpoints.create_start("test_partial_backup")
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t(key int, value text)",
+            "INSERT INTO t SELECT generate_series(1,1000), 'payload'",
+        ]
+    )
+    endpoint.stop()
+
+    http_cli = sk.http_client()
+    status = http_cli.timeline_status(tenant_id, timeline_id)
+    from_lsn, until_lsn = status.timeline_start_lsn, status.commit_lsn
+    segment = wal_segment_name(until_lsn)
+    assert wal_segment_name(from_lsn) == segment
+    assert neon_env_builder.safekeepers_remote_storage is not None
+    partial_prefix = "/".join([str(tenant_id), str(timeline_id), "partial"]) + "/"
+
+    def partial_objects() -> Dict[str, int]:
+        objects = list_prefix(neon_env_builder.safekeepers_remote_storage, partial_prefix).get(
+            "Contents", []
+        )
+        return {obj["Key"].split("/")[-1]: obj["Size"] for obj in objects}
+
+    def committed_part_uploaded():
+        objects = partial_objects()
+        log.info(f"partial objects: {objects}")
+        sizes = [size for name, size in objects.items() if name.startswith(f"{segment}_")]
+        assert sizes == [until_lsn.as_int() % (16 * 1024 * 1024)]
+        assert all(name.endswith(f"_sk{sk.id}") for name in objects)
+
+    wait_until(30, 1, committed_part_uploaded)
+
+    # the full segment is not uploaded yet, so without the local file reader has to use
+    # the partial one
+    digest = http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn)
+    reads_before = parse_metrics(http_cli.get_metrics_str()).query_one(
+        "safekeeper_remote_wal_segment_reads_total"
+    )
+    tli_dir = Path(sk.data_dir()) / str(tenant_id) / str(timeline_id)
+    local_segment = tli_dir / f"{segment}.partial"
+    hidden_segment = Path(sk.data_dir()) / f"{segment}.partial"
+    local_segment.rename(hidden_segment)
+    try:
+        assert http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn) == digest
+    finally:
+        hidden_segment.rename(local_segment)
+    reads_after = parse_metrics(http_cli.get_metrics_str()).query_one(
+        "safekeeper_remote_wal_segment_reads_total"
+    )
+    assert reads_after.value > reads_before.value
+
+    # once the segment is fully backed up, its partial objects are removed
+    endpoint = env.endpoints.create_start("test_partial_backup")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,500000), 'payload'")
+    wait(
+        partial(is_segment_offloaded, sk, tenant_id, timeline_id, Lsn("0/2000000")),
+        "first segment to be offloaded",
+    )
+
+    def partial_objects_cleaned_up():
+        objects = partial_objects()
+        log.info(f"partial objects: {objects}")
+        assert not any(name.startswith(f"{segment}_") for name in objects)
+        assert len(objects) > 0
+
+    wait_until(30, 1, partial_objects_cleaned_up)
+
+
+# Segments uploaded with --wal-backup-compression are stored as .zst objects and
+# transparently decompressed when evicted WAL is read back.
+def test_wal_backup_compression(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 1
+    neon_env_builder.enable_safekeeper_remote_storage(default_remote_storage())
+    # to advance remote_consistent_lsn
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    sk = env.safekeepers[0]
+    sk.stop()
+    # the remote WAL cache is disabled by default, so reads go straight to remote storage
+    sk.start(extra_opts=["--wal-backup-compression", "--wal-eviction-enabled"])
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_wal_backup_compression")
+    endpoint = env.endpoints.create_start("test_wal_backup_compression")
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t(key int primary key, value text)",
+            "INSERT INTO t SELECT generate_series(1,750000), 'payload'",
+        ]
+    )
+    wait_lsn_force_checkpoint(tenant_id, timeline_id, endpoint, env.pageserver)
+
+    http_cli = sk.http_client()
+    status = http_cli.timeline_status(tenant_id, timeline_id)
+    from_lsn, until_lsn = status.timeline_start_lsn, status.commit_lsn
+    digest = http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn)
+
+    first_segment = os.path.join(
+        sk.data_dir(), str(tenant_id), str(timeline_id), "000000010000000000000001"
+    )
+
+    def first_segment_evicted():
+        assert not os.path.exists(first_segment)
+
+    wait_until(60, 1, first_segment_evicted)
+
+    prefix = "/".join([str(tenant_id), str(timeline_id)]) + "/"
+    assert neon_env_builder.safekeepers_remote_storage is not None
+    objects = list_prefix(neon_env_builder.safekeepers_remote_storage, prefix).get("Contents", [])
+    names = [obj["Key"].split("/")[-1] for obj in objects]
+    log.info(f"uploaded objects: {names}")
+    assert "000000010000000000000001.zst" in names
+    assert "000000010000000000000001" not in names
+
+    assert http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn) == digest
+
+
+# Above the disk usage threshold safekeeper removes WAL which is backed up and
+# consumed by the pageserver even though a lagging peer still needs it.
+def test_wal_disk_usage_threshold(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    neon_env_builder.enable_safekeeper_remote_storage(default_remote_storage())
+    # to advance remote_consistent_lsn
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    sk = env.safekeepers[0]
+    sk.stop()
+    # disk usage is surely above 1%
+    sk.start(extra_opts=["--wal-disk-usage-threshold-pct=1"])
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_wal_disk_usage_threshold")
+    endpoint = env.endpoints.create_start("test_wal_disk_usage_threshold")
+    # lagging peer holds peer horizon
+    env.safekeepers[2].stop()
+
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t(key int primary key, value text)",
+            "INSERT INTO t SELECT generate_series(1,500000), 'payload'",
+        ]
+    )
+    wait_lsn_force_checkpoint(tenant_id, timeline_id, endpoint, env.pageserver)
+
+    first_segments = [
+        os.path.join(s.data_dir(), str(tenant_id), str(timeline_id), "000000010000000000000001")
+        for s in env.safekeepers
+    ]
+
+    def first_segment_removed():
+        assert not os.path.exists(first_segments[0])
+
+    wait_until(60, 1, first_segment_removed)
+    # safekeeper without the threshold keeps WAL for the lagging peer
+    assert os.path.exists(first_segments[1])
+
+    metrics = parse_metrics(sk.http_client().get_metrics_str())
+    assert metrics.query_one("safekeeper_wal_disk_usage_threshold_exceeded").value == 1
+    assert metrics.query_one("safekeeper_emergency_wal_removals_total").value > 0
+
+    env.safekeepers[2].start()
+
+
+# With tenant WAL ingest rate limited, writes still complete, but safekeeper
+# throttles them.
+def test_tenant_wal_ingest_rate_limit(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 1
+    env = neon_env_builder.init_start()
+    sk = env.safekeepers[0]
+    sk.stop()
+    # 4MiB/s with 1MiB burst
+    sk.start(
+        extra_opts=[
+            f"--tenant-wal-ingest-rate={4 * 1024 * 1024}",
+            f"--tenant-wal-ingest-burst={1024 * 1024}",
+        ]
+    )
+
+    env.neon_cli.create_branch("test_tenant_wal_ingest_rate_limit")
+    endpoint = env.endpoints.create_start("test_tenant_wal_ingest_rate_limit")
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t(key int primary key, value text)",
+            "INSERT INTO t SELECT generate_series(1,100000), 'payload'",
+        ]
+    )
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 100000
+
+    metrics = parse_metrics(sk.http_client().get_metrics_str())
+    assert metrics.query_one("safekeeper_wal_ingest_throttled_total").value > 0
+    assert metrics.query_one("safekeeper_wal_ingest_throttled_wait_usecs_total").value > 0
+
+
 def test_s3_wal_replay(neon_env_builder: NeonEnvBuilder):
     neon_env_builder.num_safekeepers = 3
 
@@ -889,6 +1406,11 @@ def test_timeline_status(neon_env_builder: NeonEnvBuilder, auth_enabled: bool):
     # check .config in response
     assert debug_dump_1["config"]["id"] == env.safekeepers[0].id
 
+    # in-memory state includes connected walreceivers and on-disk WAL inventory
+    tli_dump = debug_dump_1["timelines"][0]
+    assert len(tli_dump["memory"]["walreceivers"]) > 0
+    assert any(f["name"].endswith(".partial") for f in tli_dump["disk_content"]["files"])
+
 
 class DummyConsumer(object):
     def __call__(self, msg):
@@ -930,7 +1452,8 @@ def test_start_replication_term(neon_env_builder: NeonEnvBuilder):
         assert "failed to acquire term 3" in str(excinfo.value)
 
 
-# Test auth on all ports: WAL service (postgres protocol), WAL service tenant only and http.
+# Test auth on all ports: WAL service (postgres protocol), WAL service tenant only,
+# http and http tenant only.
 def test_sk_auth(neon_env_builder: NeonEnvBuilder):
     neon_env_builder.auth_enabled = True
     env = neon_env_builder.init_start()
@@ -971,6 +1494,24 @@ def test_sk_auth(neon_env_builder: NeonEnvBuilder):
         sk_http_cli_noauth.timeline_status(tenant_id, timeline_id)
     sk_http_cli_auth.timeline_status(tenant_id, timeline_id)
 
+    # on tenant only http port tenant token gives access to the tenant's timelines
+    sk_http_cli_tenant_only = sk.http_client(auth_token=tenant_token, tenant_only=True)
+    sk_http_cli_tenant_only.timeline_status(tenant_id, timeline_id)
+    # but not to management API
+    with pytest.raises(sk_http_cli_tenant_only.HTTPError, match="Forbidden"):
+        sk_http_cli_tenant_only.timelines_metrics()
+    # and other tenants
+    other_tenant_token = env.auth_keys.generate_tenant_token(TenantId.generate())
+    with pytest.raises(sk_http_cli_tenant_only.HTTPError, match="Forbidden"):
+        sk.http_client(auth_token=other_tenant_token, tenant_only=True).timeline_status(
+            tenant_id, timeline_id
+        )
+    # full token is rejected there
+    with pytest.raises(sk_http_cli_tenant_only.HTTPError, match="Forbidden"):
+        sk.http_client(auth_token=full_token, tenant_only=True).timeline_status(
+            tenant_id, timeline_id
+        )
+
     # now, disable auth on http
     sk.stop()
     sk.start(extra_opts=["--http-auth-public-key-path="])
@@ -990,6 +1531,12 @@ def test_sk_auth(neon_env_builder: NeonEnvBuilder):
         connector.safe_psql("IDENTIFY_SYSTEM", port=sk.port.pg_tenant_only)
     connector.safe_psql("IDENTIFY_SYSTEM", port=sk.port.pg_tenant_only, password=tenant_token)
 
+    # tenant only http without auth would serve the whole API, so it is refused
+    sk.stop()
+    with pytest.raises(Exception):
+        sk.start(extra_opts=["--http-tenant-only-auth-public-key-path="])
+    sk.running = False
+
 
 # Try restarting endpoint with enabled auth.
 def test_restart_endpoint(neon_env_builder: NeonEnvBuilder):
@@ -1347,6 +1894,64 @@ def test_peer_recovery(neon_env_builder: NeonEnvBuilder):
     endpoint.safe_psql("insert into t select generate_series(1,100), 'payload'")
 
 
+def is_timeline_active(sk: Safekeeper, tenant_id: TenantId, timeline_id: TimelineId) -> bool:
+    dump = sk.http_client().debug_dump(
+        {"dump_memory": "true", "tenant_id": str(tenant_id), "timeline_id": str(timeline_id)}
+    )
+    active: bool = dump["timelines"][0]["memory"]["active"]
+    return active
+
+
+# Test that a safekeeper which missed WAL catches up from peers when there is no
+# compute and the timeline is already inactive on peers, i.e. they don't push it
+# to the broker anymore.
+def test_peer_recovery_inactive_timeline(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    neon_env_builder.enable_safekeeper_remote_storage(default_remote_storage())
+    env = neon_env_builder.init_start()
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_peer_recovery_inactive_timeline")
+    endpoint = env.endpoints.create_start("test_peer_recovery_inactive_timeline")
+    endpoint.safe_psql("create table t(key int, value text)")
+
+    sk1, sk2, sk3 = env.safekeepers
+    http_clis = [sk.http_client() for sk in env.safekeepers]
+    wait(
+        partial(is_flush_lsn_aligned, http_clis, tenant_id, timeline_id),
+        "flush_lsn to get aligned",
+    )
+
+    sk1.stop()
+    # roughly fills one segment
+    endpoint.safe_psql("insert into t select generate_series(1,250000), 'payload'")
+    # make pageserver catch up and upload, so that only lag of sk1 could keep the timeline active
+    wait_lsn_force_checkpoint(tenant_id, timeline_id, endpoint, env.pageserver)
+    endpoint.stop()
+
+    for sk in [sk2, sk3]:
+        wait(
+            lambda sk=sk: not is_timeline_active(sk, tenant_id, timeline_id),
+            f"timeline to become inactive on sk {sk.id}",
+            timeout=60,
+        )
+
+    sk1.start()
+    wait(
+        partial(is_flush_lsn_aligned, http_clis, tenant_id, timeline_id),
+        "flush_lsn to get aligned",
+    )
+    cmp_sk_wal([sk1, sk2, sk3], tenant_id, timeline_id)
+
+    # once sk1 caught up, nothing keeps the timeline active anymore
+    for sk in env.safekeepers:
+        wait(
+            lambda sk=sk: not is_timeline_active(sk, tenant_id, timeline_id),
+            f"timeline to become inactive on sk {sk.id}",
+            timeout=60,
+        )
+
+
 class SafekeeperEnv:
     def __init__(
         self,
@@ -1395,6 +2000,7 @@ class SafekeeperEnv:
             pg=self.port_distributor.get_port(),
             pg_tenant_only=self.port_distributor.get_port(),
             http=self.port_distributor.get_port(),
+            http_tenant_only=self.port_distributor.get_port(),
         )
 
         safekeeper_dir = self.repo_dir / f"sk{i}"
@@ -1648,6 +2254,15 @@ def test_delete_force(neon_env_builder: NeonEnvBuilder, auth_enabled: bool):
     assert (sk_data_dir / str(tenant_id) / str(timeline_id_4)).is_dir()
     assert (sk_data_dir / str(tenant_id_other) / str(timeline_id_other)).is_dir()
 
+    # Deleted timeline can't be created again, even after restart
+    pg_version = int(env.pg_version) * 10000
+    with pytest.raises(sk_http.HTTPError, match="Conflict"):
+        sk_http.timeline_create(tenant_id, timeline_id_1, pg_version, Lsn("0/1000000"))
+    sk.stop()
+    sk.start()
+    with pytest.raises(sk_http.HTTPError, match="Conflict"):
+        sk_http.timeline_create(tenant_id, timeline_id_1, pg_version, Lsn("0/1000000"))
+
     # Ensure repeated deletion succeeds
     assert not sk_http.timeline_delete(tenant_id, timeline_id_1)["dir_existed"]
     assert not (sk_data_dir / str(tenant_id) / str(timeline_id_1)).exists()
@@ -1673,8 +2288,9 @@ def test_delete_force(neon_env_builder: NeonEnvBuilder, auth_enabled: bool):
     assert (sk_data_dir / str(tenant_id) / str(timeline_id_4)).is_dir()
     assert (sk_data_dir / str(tenant_id_other) / str(timeline_id_other)).is_dir()
 
-    # Remove non-existing branch, should succeed
+    # Remove non-existing branch, should succeed without leaving a tombstone
     assert not sk_http.timeline_delete(tenant_id, TimelineId("00" * 16))["dir_existed"]
+    assert not (sk_data_dir / "tombstones" / str(tenant_id) / ("00" * 16)).exists()
     assert not (sk_data_dir / str(tenant_id) / str(timeline_id_1)).exists()
     assert not (sk_data_dir / str(tenant_id) / str(timeline_id_2)).exists()
     assert (sk_data_dir / str(tenant_id) / str(timeline_id_3)).exists()
@@ -1698,6 +2314,31 @@ def test_delete_force(neon_env_builder: NeonEnvBuilder, auth_enabled: bool):
     with closing(endpoint_other.connect()) as conn:
         with conn.cursor() as cur:
             cur.execute("INSERT INTO t (key) VALUES (123)")
+    endpoint_other.stop_and_destroy()
+
+    # Local deletion leaves a tombstone too
+    assert sk_http_other.timeline_delete(tenant_id_other, timeline_id_other, only_local=True)[
+        "dir_existed"
+    ]
+    with pytest.raises(sk_http.HTTPError, match="Conflict"):
+        sk_http_other.timeline_create(
+            tenant_id_other, timeline_id_other, pg_version, Lsn("0/1000000")
+        )
+
+    # Tombstones expire, after which timelines can be created again
+    sk.stop()
+    time.sleep(1)
+    sk.start(extra_opts=["--tombstone-ttl=1s"])
+    sk_http.timeline_create(tenant_id, timeline_id_1, pg_version, Lsn("0/1000000"))
+    sk_http_other.timeline_create(tenant_id_other, timeline_id_other, pg_version, Lsn("0/1000000"))
+
+    # Expired tombstones are also removed by the next deletion
+    tombstone = sk_data_dir / "tombstones" / str(tenant_id) / str(timeline_id_1)
+    sk_http.timeline_delete(tenant_id, timeline_id_1)
+    assert tombstone.exists()
+    time.sleep(1)
+    sk_http_other.timeline_delete(tenant_id_other, timeline_id_other)
+    assert not tombstone.exists()
 
 
 def test_pull_timeline(neon_env_builder: NeonEnvBuilder):
@@ -1789,6 +2430,219 @@ def test_pull_timeline(neon_env_builder: NeonEnvBuilder):
     show_statuses(env.safekeepers, tenant_id, timeline_id)
 
 
+# With remote storage, pull_timeline copies only the WAL suffix which is not offloaded or
+# not consumed by pageserver yet, and the rest is read from remote storage.
+def test_pull_timeline_wal_suffix(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 4
+    neon_env_builder.enable_safekeeper_remote_storage(default_remote_storage())
+    # to advance remote_consistent_lsn
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_pull_timeline_wal_suffix")
+    sk1, sk4 = env.safekeepers[0], env.safekeepers[3]
+
+    endpoint = env.endpoints.create("test_pull_timeline_wal_suffix")
+    endpoint.active_safekeepers = [1, 2, 3]
+    endpoint.start()
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t(key int, value text)",
+            # roughly fills three segments
+            "INSERT INTO t SELECT generate_series(1,750000), 'payload'",
+        ]
+    )
+    wait_lsn_force_checkpoint(tenant_id, timeline_id, endpoint, env.pageserver)
+    endpoint.stop()
+
+    sk1_http_cli = sk1.http_client()
+
+    def history_offloaded():
+        status = sk1_http_cli.timeline_status(tenant_id, timeline_id)
+        log.info(f"sk1 status: {status}")
+        assert status.backup_lsn >= Lsn("0/3000000")
+        assert status.remote_consistent_lsn >= Lsn("0/3000000")
+
+    wait_until(60, 1, history_offloaded)
+
+    sk4_http_cli = sk4.http_client()
+    sk4_http_cli.pull_timeline(
+        {
+            "tenant_id": str(tenant_id),
+            "timeline_id": str(timeline_id),
+            "http_hosts": [f"http://localhost:{sk1.port.http}"],
+        }
+    )
+
+    # only the suffix is on disk
+    sk1_status = sk1_http_cli.timeline_status(tenant_id, timeline_id)
+    sk4_status = sk4_http_cli.timeline_status(tenant_id, timeline_id)
+    assert sk4_status.flush_lsn == sk1_status.flush_lsn
+    assert sk4_status.commit_lsn == sk1_status.commit_lsn
+    segments = [
+        f
+        for f in os.listdir(os.path.join(sk4.data_dir(), str(tenant_id), str(timeline_id)))
+        if f.startswith("00000001")
+    ]
+    log.info(f"segments on sk4: {segments}")
+    assert "000000010000000000000001" not in segments
+    assert wal_segment_name(sk4_status.flush_lsn) + ".partial" in segments
+
+    # and whole WAL is served
+    from_lsn, until_lsn = sk4_status.timeline_start_lsn, sk4_status.flush_lsn
+    assert sk4_http_cli.timeline_digest(
+        tenant_id, timeline_id, from_lsn, until_lsn
+    ) == sk1_http_cli.timeline_digest(tenant_id, timeline_id, from_lsn, until_lsn)
+
+    # compute and pageserver work with the pulled timeline alone
+    endpoint = env.endpoints.create("test_pull_timeline_wal_suffix")
+    endpoint.active_safekeepers = [4]
+    endpoint.start()
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,1000), 'payload'")
+    wait_lsn_force_checkpoint(tenant_id, timeline_id, endpoint, env.pageserver)
+    assert endpoint.safe_psql("SELECT count(*) FROM t") == [(751000,)]
+
+
+# Test background replication of timeline to a safekeeper outside of the
+# compute's set, as done for rebalancing.
+def test_replicate_timeline(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 4
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_replicate_timeline")
+
+    endpoint = env.endpoints.create("test_replicate_timeline")
+    endpoint.active_safekeepers = [1, 2, 3]
+    endpoint.start()
+    endpoint.safe_psql("CREATE TABLE t(key int, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,10000), 'payload'")
+
+    source = env.safekeepers[0]
+    target_cli = env.safekeepers[3].http_client()
+    target_cli.timeline_replicate_start(
+        tenant_id,
+        timeline_id,
+        {
+            "source_id": source.id,
+            "source_http_connstr": f"localhost:{source.port.http}",
+            "source_pg_connstr": f"localhost:{source.port.pg}",
+        },
+    )
+
+    # compute keeps writing while replication runs
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,100000), 'payload'")
+    source_flush_lsn = source.http_client().timeline_status(tenant_id, timeline_id).flush_lsn
+
+    def caught_up():
+        status = target_cli.timeline_replicate_status(tenant_id, timeline_id)
+        log.info(f"replication status: {status}")
+        assert status["state"] == "streaming"
+        assert Lsn(status["flush_lsn"]) >= source_flush_lsn
+        assert target_cli.timeline_status(tenant_id, timeline_id).flush_lsn >= source_flush_lsn
+
+    wait_until(60, 1, caught_up)
+
+    # second start is refused while replication runs
+    with pytest.raises(target_cli.HTTPError, match="Conflict"):
+        target_cli.timeline_replicate_start(
+            tenant_id,
+            timeline_id,
+            {
+                "source_id": source.id,
+                "source_http_connstr": f"localhost:{source.port.http}",
+                "source_pg_connstr": f"localhost:{source.port.pg}",
+            },
+        )
+
+    status = target_cli.timeline_replicate_stop(tenant_id, timeline_id)
+    assert status["state"] == "stopped"
+
+    # the copy is usable: switch compute to it
+    endpoint.stop_and_destroy().create("test_replicate_timeline")
+    endpoint.active_safekeepers = [1, 2, 4]
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM t") == [(110000,)]
+
+
+def membership_conf(
+    generation: int, members: List[Safekeeper], new_members: Optional[List[Safekeeper]] = None
+) -> Dict[str, Any]:
+    def member_set(sks: List[Safekeeper]) -> List[Dict[str, Any]]:
+        return [{"id": sk.id, "host": "localhost", "pg_port": sk.port.pg} for sk in sks]
+
+    return {
+        "generation": generation,
+        "members": member_set(members),
+        "new_members": member_set(new_members) if new_members is not None else None,
+    }
+
+
+# Switch safekeepers membership with neon.safekeepers_generation: joint configuration
+# requires majority of both member sets, and proposers of older generations are fenced off.
+def test_membership_switch(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 4
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_membership_switch")
+    sk1, sk2, sk3, sk4 = env.safekeepers
+
+    # create the timeline on all safekeepers
+    endpoint = env.endpoints.create_start("test_membership_switch")
+    endpoint.safe_psql("CREATE TABLE t(key int, value text)")
+    endpoint.stop()
+
+    # migrate from {1, 2, 3} to {3, 4}
+    joint = membership_conf(1, [sk1, sk2, sk3], [sk3, sk4])
+    for sk in env.safekeepers:
+        res = sk.http_client().membership_switch(tenant_id, timeline_id, joint)
+        assert res["current_conf"]["generation"] == 1
+    # switching to the same generation again is a no-op
+    res = sk1.http_client().membership_switch(tenant_id, timeline_id, joint)
+    assert res["previous_conf"]["generation"] == 1
+
+    endpoint = env.endpoints.create_start(
+        "test_membership_switch", config_lines=["neon.safekeepers_generation=1"]
+    )
+    endpoint.safe_psql("INSERT INTO t VALUES (1, 'payload')")
+
+    # 3 of 4 safekeepers are up, but new members don't have a majority without sk4
+    sk4.stop()
+    with concurrent.futures.ThreadPoolExecutor(max_workers=1) as executor:
+        insert = executor.submit(endpoint.safe_psql, "INSERT INTO t VALUES (2, 'payload')")
+        time.sleep(5)
+        assert not insert.done(), "commit was acknowledged without joint quorum"
+        sk4.start()
+        insert.result(timeout=60)
+
+    # finish the migration; compute of generation 1 is fenced off
+    new = membership_conf(2, [sk3, sk4])
+    for sk in [sk4, sk3, sk2, sk1]:
+        sk.http_client().membership_switch(tenant_id, timeline_id, new)
+    # make walproposer reconnect and greet safekeepers again
+    for sk in env.safekeepers:
+        sk.stop().start()
+    with concurrent.futures.ThreadPoolExecutor(max_workers=1) as executor:
+        insert = executor.submit(endpoint.safe_psql, "INSERT INTO t VALUES (3, 'payload')")
+        time.sleep(5)
+        assert not insert.done(), "commit was acknowledged by fenced off safekeepers"
+        endpoint.stop(mode="immediate")
+        with pytest.raises(psycopg2.Error):
+            insert.result(timeout=60)
+
+    for sk in [sk3, sk4]:
+        log_path = os.path.join(sk.data_dir(), "safekeeper.log")
+        with open(log_path) as f:
+            assert "proposer membership generation 1 is older than ours 2" in f.read()
+
+    # compute of the new generation works with new members only
+    endpoint = env.endpoints.create("test_membership_switch")
+    endpoint.active_safekeepers = [3, 4]
+    endpoint.config(["neon.safekeepers_generation=2"])
+    endpoint.start()
+    endpoint.safe_psql("INSERT INTO t VALUES (4, 'payload')")
+    assert endpoint.safe_psql("SELECT key FROM t ORDER BY key") == [(1,), (2,), (4,)]
+
+
 # In this test we check for excessive START_REPLICATION and START_WAL_PUSH queries
 # when compute is active, but there are no writes to the timeline. In that case
 # pageserver should maintain a single connection to safekeeper and don't attempt
@@ -1935,6 +2789,19 @@ def test_timeline_copy(neon_env_builder: NeonEnvBuilder, insert_rows: int):
 
             assert orig_digest == new_digest
 
+    # copying beyond the end of the source timeline is a client error
+    sk_http = env.safekeepers[0].http_client()
+    beyond_end_lsn = sk_http.timeline_status(tenant_id, timeline_id).flush_lsn + 1024 * 1024
+    with pytest.raises(sk_http.HTTPError, match="Bad Request"):
+        sk_http.copy_timeline(
+            tenant_id,
+            timeline_id,
+            {
+                "target_timeline_id": str(TimelineId.generate()),
+                "until_lsn": str(beyond_end_lsn),
+            },
+        )
+
     # TODO: test timelines can start after copy
 
 
@@ -1984,3 +2851,44 @@ def test_patch_control_file(neon_env_builder: NeonEnvBuilder):
     )
     log.info(f"dump_control_file response: {res}")
     assert res["timelines"][0]["control_file"]["timeline_start_lsn"] == "0/1"
+
+
+# Safekeepers pass the pageserver progress they learn from peers to the compute, so that it
+# sees the progress of a pageserver streaming from any of them.
+def test_ps_feedback_from_peers(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+
+    endpoint = env.endpoints.create("main")
+    # don't skip pg_catalog updates - it runs CREATE EXTENSION neon
+    # which is needed for backpressure_lsns() to work
+    endpoint.respec(skip_pg_catalog_updates=False)
+    endpoint.start()
+    endpoint.safe_psql("create table t(key int, value text)")
+    endpoint.safe_psql("select * from t")
+
+    # With the pageserver stopped, the only pageserver progress the safekeepers know of is the
+    # one their (fake) peer tells them about.
+    env.pageserver.stop()
+    endpoint.safe_psql("insert into t values (1, 'payload')")
+    peer_lsn = Lsn(endpoint.safe_psql("select pg_current_wal_flush_lsn()")[0][0])
+    for sk in env.safekeepers:
+        sk.http_client().record_safekeeper_info(
+            tenant_id, timeline_id, {"ps_last_received_lsn": str(peer_lsn)}
+        )
+
+    def received_lsn_from_peer():
+        # each insert gets the safekeepers to send feedback to the compute
+        endpoint.safe_psql("insert into t values (2, 'payload')")
+        received_lsn = Lsn(
+            endpoint.safe_psql("select received_lsn from backpressure_lsns()")[0][0]
+        )
+        log.info(f"received_lsn: {received_lsn}, peer_lsn: {peer_lsn}")
+        assert received_lsn >= peer_lsn
+
+    wait_until(10, 1, received_lsn_from_peer)
+
+    endpoint.stop()
+    env.pageserver.start()